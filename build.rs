@@ -0,0 +1,45 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    grpc::generate();
+}
+
+/// The server half of `proto/freebucket.proto`'s service. Messages live in
+/// `src/grpc/proto.rs`, so no `protoc` is needed.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    /// (method, RPC name, request, response, client streaming, server streaming)
+    const METHODS: &[(&str, &str, &str, &str, bool, bool)] = &[
+        ("list_buckets", "ListBuckets", "ListBucketsRequest", "ListBucketsResponse", false, false),
+        ("create_bucket", "CreateBucket", "CreateBucketRequest", "Bucket", false, false),
+        ("delete_bucket", "DeleteBucket", "DeleteBucketRequest", "DeleteBucketResponse", false, false),
+        ("list_objects", "ListObjects", "ListObjectsRequest", "ListObjectsResponse", false, false),
+        ("put_object", "PutObject", "PutObjectRequest", "Object", true, false),
+        ("get_object", "GetObject", "GetObjectRequest", "GetObjectResponse", false, true),
+        ("delete_object", "DeleteObject", "DeleteObjectRequest", "DeleteObjectResponse", false, false),
+        ("get_stats", "GetStats", "GetStatsRequest", "Stats", false, false),
+    ];
+
+    pub fn generate() {
+        println!("cargo:rerun-if-changed=proto/freebucket.proto");
+        let mut service = Service::builder().name("FreeBucket").package("freebucket.v1");
+        for &(name, route, input, output, client_streaming, server_streaming) in METHODS {
+            let mut method = Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("crate::grpc::proto::{}", input))
+                .output_type(format!("crate::grpc::proto::{}", output))
+                .codec_path("tonic::codec::ProstCodec");
+            if client_streaming {
+                method = method.client_streaming();
+            }
+            if server_streaming {
+                method = method.server_streaming();
+            }
+            service = service.method(method.build());
+        }
+        Builder::new().build_client(false).compile(&[service.build()]);
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::body::HttpBody;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::error::ErrorCode;
+use crate::models::LoggingConfig;
+use crate::AppState;
+
+/// Canonical owner id used in log lines, matching ListBuckets
+const BUCKET_OWNER: &str = "freebucket-local";
+
+/// One formatted log line waiting to be delivered
+struct AccessRecord {
+    target: LoggingConfig,
+    line: String,
+}
+
+/// Access records buffered in memory until the next flush. Records still in
+/// the buffer when the server stops are lost, as with S3's best-effort delivery.
+#[derive(Default)]
+pub struct AccessLog {
+    records: Mutex<Vec<AccessRecord>>,
+}
+
+impl AccessLog {
+    /// Buffer a record and return how many are now waiting
+    fn push(&self, record: AccessRecord) -> usize {
+        let mut records = self.records.lock().unwrap();
+        records.push(record);
+        records.len()
+    }
+
+    fn take(&self) -> Vec<AccessRecord> {
+        std::mem::take(&mut *self.records.lock().unwrap())
+    }
+}
+
+/// Write every buffered record as log objects, one per target bucket and
+/// prefix. Writes go straight to storage, so they are never logged
+/// themselves. Returns how many records were delivered.
+pub fn flush_access_logs(state: &AppState) -> usize {
+    let mut batches: HashMap<(String, String), (LoggingConfig, Vec<String>)> = HashMap::new();
+    for record in state.access_log.take() {
+        let id = (
+            record.target.target_bucket.clone(),
+            record.target.target_prefix.clone(),
+        );
+        batches
+            .entry(id)
+            .or_insert_with(|| (record.target, Vec::new()))
+            .1
+            .push(record.line);
+    }
+
+    let mut delivered = 0;
+    for (target, lines) in batches.into_values() {
+        match state.storage.write_access_log(&target, &lines) {
+            Ok(_) => delivered += lines.len(),
+            // A deleted or broken target only costs the records meant for it
+            Err(e) => tracing::warn!(
+                "Dropping {} access log record(s) for {}: {:?}",
+                lines.len(),
+                target.target_bucket,
+                e
+            ),
+        }
+    }
+    delivered
+}
+
+/// The client's address: the first hop of `X-Forwarded-For` when a proxy
+/// sent one, else the peer of the connection
+pub fn remote_ip(req: &Request) -> Option<String> {
+    req.headers()
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string())
+        .or_else(|| {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0.ip().to_string())
+        })
+}
+
+/// Bucket and still-encoded key addressed by an S3 route path
+fn s3_target(path: &str) -> Option<(&str, Option<&str>)> {
+    let rest = path.strip_prefix("/s3/")?;
+    let (bucket, key) = match rest.strip_prefix("obj/") {
+        Some(object) => match object.split_once('/') {
+            Some((bucket, key)) => (bucket, Some(key).filter(|k| !k.is_empty())),
+            None => (object, None),
+        },
+        None => (rest.trim_end_matches('/'), None),
+    };
+    Some((bucket, key)).filter(|(bucket, _)| !bucket.is_empty())
+}
+
+/// S3's operation naming: `REST.<METHOD>.<RESOURCE>`
+fn operation_name(method: &Method, key: Option<&str>, query: &str) -> String {
+    let has = |name: &str| query.split('&').any(|p| p.split('=').next() == Some(name));
+    let resource = match key {
+        None if has("location") => "LOCATION",
+        None => "BUCKET",
+        Some(_) if has("uploads") => "UPLOADS",
+        Some(_) if has("partNumber") => "PART",
+        Some(_) if has("uploadId") => "UPLOAD",
+        Some(_) if has("attributes") => "OBJECT_ATTRIBUTES",
+        Some(_) => "OBJECT",
+    };
+    format!("REST.{}.{}", method, resource)
+}
+
+fn header_or_dash(headers: &HeaderMap, name: header::HeaderName) -> String {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map_or_else(|| "-".to_string(), |v| v.replace('"', "%22"))
+}
+
+/// Record S3 requests against buckets that have access logging enabled,
+/// using the S3 server access log line format
+pub async fn record_access(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let path = req.uri().path().to_string();
+    let Some((bucket, key)) = s3_target(&path) else {
+        return next.run(req).await;
+    };
+    let Some(target) = state.storage.get_bucket(bucket).ok().and_then(|b| b.logging) else {
+        return next.run(req).await;
+    };
+    let bucket = bucket.to_string();
+    let key = key.map(str::to_string);
+
+    let method = req.method().clone();
+    let query = req.uri().query().unwrap_or("").to_string();
+    let version = req.version();
+    let remote_ip = remote_ip(&req).unwrap_or_else(|| "-".to_string());
+    let referer = header_or_dash(req.headers(), header::REFERER);
+    let user_agent = header_or_dash(req.headers(), header::USER_AGENT);
+    let host = header_or_dash(req.headers(), header::HOST);
+    let received = state.storage.now();
+    let started = Instant::now();
+
+    let res = next.run(req).await;
+
+    let total_ms = started.elapsed().as_millis();
+    let status = res.status();
+    let error_code = res.extensions().get::<ErrorCode>().map_or("-", |c| c.0);
+    // Content-Length is only added on the way out; the body knows its size
+    let bytes_sent = res
+        .body()
+        .size_hint()
+        .exact()
+        .filter(|n| *n > 0)
+        .map_or_else(|| "-".to_string(), |n| n.to_string());
+    let object_size = match &key {
+        Some(key) if status.is_success() && method != Method::DELETE => {
+            let decoded = percent_encoding::percent_decode_str(key).decode_utf8_lossy();
+            state
+                .storage
+                .get_object_meta(&bucket, &decoded)
+                .map_or_else(|_| "-".to_string(), |m| m.size.to_string())
+        }
+        _ => "-".to_string(),
+    };
+    let request_uri = format!(
+        "{} {}{}{} {:?}",
+        method,
+        path.strip_prefix("/s3/obj").or_else(|| path.strip_prefix("/s3")).unwrap_or(&path),
+        if query.is_empty() { "" } else { "?" },
+        query,
+        version
+    );
+
+    // bucket_owner bucket [time] remote_ip requester request_id operation key
+    // "request_uri" status error_code bytes_sent object_size total_time
+    // turn_around_time "referer" "user_agent" version_id host_id sig_version
+    // cipher_suite auth_type host_header tls_version access_point_arn acl_required
+    let line = format!(
+        "{} {} [{}] {} - {} {} {} \"{}\" {} {} {} {} {} - \"{}\" \"{}\" - - - - - {} - - -",
+        BUCKET_OWNER,
+        bucket,
+        received.format("%d/%b/%Y:%H:%M:%S %z"),
+        remote_ip,
+        state.storage.new_id().simple().to_string()[..16].to_ascii_uppercase(),
+        operation_name(&method, key.as_deref(), &query),
+        key.as_deref().unwrap_or("-"),
+        request_uri,
+        status.as_u16(),
+        error_code,
+        bytes_sent,
+        object_size,
+        total_ms,
+        referer,
+        user_agent,
+        host,
+    );
+
+    let waiting = state.access_log.push(AccessRecord { target, line });
+    if waiting >= state.config().access_log_batch_size {
+        let flush_state = state.clone();
+        tokio::task::spawn_blocking(move || flush_access_logs(&flush_state));
+    }
+    res
+}
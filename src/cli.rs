@@ -1,9 +1,27 @@
 use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
 
-use crate::storage::{human_readable_size, StorageEngine};
+use crate::size::human_readable_size;
+use crate::storage::{Audit, PutOptions, StorageEngine};
 
 use clap::{Parser, Subcommand};
+use sha2::{Digest, Sha256};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::models::{AuditAction, BulkOperation, InventoryFormat, ListOrder, ListSort, ListingSummary, ModifiedWindow, RecomputeStatus, Snapshot};
+
+mod apply;
+mod backup;
+mod bulk;
+mod complete;
+mod diff;
+mod doctor;
+mod remote;
+mod replay;
+mod watch;
+
+pub use apply::{apply_at_startup, create_default_buckets};
 
 #[derive(Parser)]
 #[command(
@@ -31,6 +49,25 @@ pub enum Commands {
         /// Port to listen on
         #[arg(short, long, default_value = "3210")]
         port: u16,
+        /// Allow binding to a non-loopback address without authentication
+        #[arg(long = "i-know-this-is-open")]
+        i_know_this_is_open: bool,
+        /// Create and update buckets from a manifest before serving
+        #[arg(long, value_name = "FILE")]
+        apply: Option<String>,
+        /// Like --apply, but seed files never replace objects that exist
+        #[arg(long, value_name = "FILE", conflicts_with = "apply")]
+        init_manifest: Option<String>,
+        /// Also serve gRPC on this port (needs the `grpc` feature)
+        #[arg(long)]
+        grpc_port: Option<u16>,
+        /// Record each S3 request and response into this directory, for
+        /// debugging clients
+        #[arg(long, value_name = "DIR")]
+        record_requests: Option<String>,
+        /// Record requests even when authentication is configured
+        #[arg(long)]
+        record_force: bool,
     },
 
     /// Create a new bucket
@@ -38,9 +75,21 @@ pub enum Commands {
     MakeBucket {
         /// Name of the bucket to create
         name: String,
-        /// Region label
-        #[arg(short, long, default_value = "local")]
-        region: String,
+        /// Region label (defaults to FREEBUCKET_REGION, or "local")
+        #[arg(short, long)]
+        region: Option<String>,
+        /// Storage volume from FREEBUCKET_VOLUMES (defaults to the data dir)
+        #[arg(long)]
+        volume: Option<String>,
+    },
+
+    /// Move a bucket to another storage volume (stop the server first)
+    MoveBucket {
+        /// Name of the bucket to move
+        name: String,
+        /// Volume from FREEBUCKET_VOLUMES, or "default" for the data dir
+        #[arg(long)]
+        volume: String,
     },
 
     /// Remove a bucket (must be empty)
@@ -48,6 +97,9 @@ pub enum Commands {
     RemoveBucket {
         /// Name of the bucket to delete
         name: String,
+        /// Don't keep the bucket's settings for restoring it later
+        #[arg(long)]
+        purge: bool,
     },
 
     /// List buckets or objects in a bucket
@@ -58,40 +110,408 @@ pub enum Commands {
         /// Filter objects by prefix
         #[arg(short, long)]
         prefix: Option<String>,
+        /// Only objects not downloaded for this long, e.g. 30d or 12h
+        #[arg(long, value_name = "AGE")]
+        unused_since: Option<String>,
+        /// Order objects by key, newest first, or largest first
+        #[arg(long, default_value = "key", value_parser = ["key", "modified", "size"])]
+        sort: String,
+        /// Only objects modified at or after this time, e.g. 2024-06-01T00:00:00Z
+        #[arg(long, value_name = "TIME", requires = "bucket")]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        /// Print every object as a line of JSON, unsorted, without loading the whole listing
+        #[arg(long, requires = "bucket", conflicts_with_all = ["unused_since", "sort"])]
+        stream: bool,
     },
 
-    /// Upload a file to a bucket
+    /// Upload files to a bucket, or with --recursive, copy the objects
+    /// under a prefix
     #[command(visible_alias = "cp")]
     Put {
-        /// Local file path to upload
+        /// Local file paths or glob patterns to upload (e.g. 'photos/**/*.jpg'),
+        /// or with --recursive, one bucket/prefix
+        #[arg(required = true, num_args = 1..)]
+        sources: Vec<String>,
+        /// Destination as bucket/key, or bucket/prefix/ when uploading several files
+        destination: String,
+        /// Number of files to upload in parallel
+        #[arg(short, long, default_value = "4", value_parser = parse_jobs)]
+        jobs: usize,
+        /// Content type for every uploaded file, instead of detecting it
+        #[arg(long)]
+        content_type: Option<String>,
+        /// Content type for files with an extension, as EXT=TYPE (repeatable)
+        #[arg(long, value_name = "EXT=TYPE")]
+        type_map: Vec<String>,
+        /// Skip files whose content matches the object's ETag already
+        #[arg(long)]
+        if_changed: bool,
+        /// Give each object its file's modification time instead of the upload time
+        #[arg(long)]
+        preserve_mtime: bool,
+        /// Only print errors
+        #[arg(short, long)]
+        quiet: bool,
+        /// Exit with status 3 when every file was skipped as unchanged
+        #[arg(long)]
+        exit_code: bool,
+        /// Upload to the server running at this URL instead of the data dir
+        #[arg(long)]
+        endpoint: Option<String>,
+        /// Copy every object under the bucket/prefix SOURCES to the
+        /// DESTINATION bucket/prefix (with --endpoint, as one server-side job)
+        #[arg(
+            short,
+            long,
+            conflicts_with_all = ["content_type", "type_map", "if_changed", "preserve_mtime"]
+        )]
+        recursive: bool,
+    },
+
+    /// Move every object under a prefix to another bucket or prefix
+    #[command(visible_alias = "mv")]
+    Move {
+        /// Source as bucket/prefix
         source: String,
-        /// Destination as bucket/key (e.g. my-bucket/photos/cat.jpg)
+        /// Destination as bucket/prefix; keys keep what follows the source prefix
         destination: String,
+        /// Move the objects under the source prefix (required: only whole
+        /// prefixes are moved)
+        #[arg(short, long, required = true)]
+        recursive: bool,
+        /// Have the server running at this URL move them, as one bulk job
+        #[arg(long)]
+        endpoint: Option<String>,
     },
 
-    /// Download an object from a bucket
+    /// Download objects from a bucket
     Get {
-        /// Source as bucket/key
-        source: String,
-        /// Local file path to save to (defaults to the key filename)
+        /// Sources as bucket/key; keys may contain * and ** glob patterns.
+        /// When the last argument is not a bucket/key it is used as the output path.
+        #[arg(required = true, num_args = 1..)]
+        sources: Vec<String>,
+        /// Local file path (single object) or directory (several objects)
+        #[arg(short, long)]
         output: Option<String>,
+        /// Number of objects to download in parallel, or with --endpoint,
+        /// of ranged requests for a single large object
+        #[arg(short, long, default_value = "4", value_parser = parse_jobs)]
+        jobs: usize,
+        /// Skip objects whose local copy has the same size and is at least as new
+        #[arg(long)]
+        if_newer: bool,
+        /// Only print errors
+        #[arg(short, long)]
+        quiet: bool,
+        /// Exit with status 3 when every object was skipped as unchanged
+        #[arg(long)]
+        exit_code: bool,
+        /// Download from the server running at this URL instead of the data dir
+        #[arg(long)]
+        endpoint: Option<String>,
+        /// With --endpoint, objects larger than this come down in ranges of
+        /// this size, in parallel, and resume where they stopped
+        #[arg(long, default_value = "16MiB", value_parser = parse_chunk_size, requires = "endpoint")]
+        chunk_size: u64,
     },
 
-    /// Delete an object from a bucket
+    /// Delete objects from a bucket
     #[command(visible_alias = "rm")]
     Remove {
+        /// Object paths as bucket/key; keys may contain * and ** glob patterns
+        #[arg(required = true, num_args = 1..)]
+        paths: Vec<String>,
+        /// Number of objects to delete in parallel
+        #[arg(short, long, default_value = "4", value_parser = parse_jobs)]
+        jobs: usize,
+    },
+
+    /// Check whether objects exist; exits with 1 if any is missing
+    Exists {
         /// Object path as bucket/key
-        path: String,
+        #[arg(required_unless_present = "stdin", conflicts_with = "stdin")]
+        path: Option<String>,
+        /// Read bucket/key paths from standard input, one per line
+        #[arg(long)]
+        stdin: bool,
     },
 
     /// Show storage statistics
-    Stats,
+    Stats {
+        /// Break usage down by bucket, largest first
+        #[arg(long)]
+        per_bucket: bool,
+    },
+
+    /// List the biggest objects in a bucket
+    Largest {
+        /// Bucket name
+        bucket: String,
+        /// Number of objects to show
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
 
     /// Show information about a specific bucket
     Info {
         /// Bucket name
         bucket: String,
     },
+
+    /// Create, list, restore, or delete bucket snapshots
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+
+    /// Write an inventory of every object in a bucket to stdout
+    Inventory {
+        /// Bucket name
+        bucket: String,
+        /// Report format
+        #[arg(long, default_value = "csv", value_parser = ["csv", "json"])]
+        format: String,
+    },
+
+    /// Report groups of objects with identical content
+    Dupes {
+        /// Only look for duplicates inside this bucket
+        bucket: Option<String>,
+    },
+
+    /// Compare the objects of two buckets or prefixes, locally or on servers.
+    /// Exits 0 when they match, 1 when they differ, 2 on error.
+    Diff {
+        /// Left side as bucket or bucket/prefix
+        left: String,
+        /// Right side as bucket or bucket/prefix
+        right: String,
+        /// Read the left side from this server instead of the data dir
+        #[arg(long)]
+        left_endpoint: Option<String>,
+        /// Read the right side from this server instead of the data dir
+        #[arg(long)]
+        right_endpoint: Option<String>,
+        /// Print one JSON object per difference, then a summary line
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Mirror a local directory into a bucket, uploading files as they change
+    Watch {
+        /// Local directory to watch
+        dir: String,
+        /// Destination as bucket or bucket/prefix/
+        destination: String,
+        /// Delete objects whose local file was removed
+        #[arg(long)]
+        delete: bool,
+        /// Skip files and directories whose name matches this glob (repeatable)
+        #[arg(long, default_values = [".git", "*.swp", "*~"])]
+        ignore: Vec<String>,
+        /// Quiet period in milliseconds before a burst of changes is uploaded
+        #[arg(long, default_value = "300")]
+        debounce_ms: u64,
+        /// Content type for files with an extension, as EXT=TYPE (repeatable)
+        #[arg(long, value_name = "EXT=TYPE")]
+        type_map: Vec<String>,
+    },
+
+    /// Remove stale temp files, abandoned multipart uploads, orphaned metadata, and empty directories
+    Gc {
+        /// Report what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Write, read back, list, and delete a throwaway object; exits non-zero on failure
+    SelfTest,
+
+    /// Rebuild an object's metadata from its stored file
+    Repair {
+        /// Object path as bucket/key
+        path: String,
+    },
+
+    /// Check a bucket's recorded statistics against its stored files
+    Fsck {
+        /// Bucket to check
+        bucket: String,
+        /// Recount the object count and total size, correcting them if they are off
+        #[arg(long, required_unless_present = "confirm_metadata")]
+        stats_only: bool,
+        /// Accept metadata recovered from an unreadable file, clearing the bucket's notice
+        #[arg(long)]
+        confirm_metadata: bool,
+    },
+
+    /// Give objects stored as application/octet-stream a real content type
+    FixTypes {
+        /// Bucket to fix
+        bucket: String,
+        /// Report what would change without rewriting any metadata
+        #[arg(long)]
+        dry_run: bool,
+        /// Only look at objects under this prefix
+        #[arg(long, value_name = "PREFIX", default_value = "")]
+        only_prefix: String,
+        /// Leave objects with this extension alone (repeatable)
+        #[arg(long, value_name = "EXT")]
+        exclude_ext: Vec<String>,
+        /// Also detect types from the first bytes of objects whose extension says nothing
+        #[arg(long)]
+        sniff: bool,
+    },
+
+    /// Create and update buckets to match a manifest file
+    Apply {
+        /// YAML manifest describing buckets, their settings, and seed files
+        file: String,
+        /// Show what would change without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Also delete buckets (and their objects) not in the manifest
+        #[arg(long)]
+        prune: bool,
+        /// Don't ask before deleting buckets
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Apply a JSON list of puts, copies, and deletes across buckets, all or nothing
+    ApplyTxn {
+        /// Transaction file, as accepted by POST /api/transactions
+        file: String,
+    },
+
+    /// Back up every bucket into a new timestamped directory under DIR
+    Backup {
+        /// Directory to create the backup in
+        dir: String,
+        /// Re-check the checksums of the backup at DIR instead of taking one
+        #[arg(long)]
+        verify: bool,
+        /// Have the server running at this URL take the backup, so its
+        /// writes are paused for the cut (DIR is on the server's machine)
+        #[arg(long, conflicts_with = "verify")]
+        endpoint: Option<String>,
+    },
+
+    /// Recreate the data dir (--data-dir, which must not hold the backup's
+    /// buckets) from a backup, and check the result against it
+    Restore {
+        /// A backup directory, as made by `backup`
+        backup: String,
+    },
+
+    /// Print the current buckets and settings as a manifest for `apply`
+    ExportConfig {
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Check the environment for problems before (or while) running a server
+    Doctor {
+        /// Port the server will listen on (defaults to FREEBUCKET_PORT, or 3210)
+        #[arg(long)]
+        port: Option<u16>,
+        /// Probe a running server at this URL (e.g. http://localhost:3210) instead
+        #[arg(long)]
+        endpoint: Option<String>,
+    },
+
+    /// Wait until a server answers, e.g. in a container healthcheck.
+    /// Exits 0 once it does, or 1 when the timeout runs out.
+    Wait {
+        /// Server to wait for
+        #[arg(long, default_value = "http://127.0.0.1:3210")]
+        endpoint: String,
+        /// How long to keep trying (e.g. 30s, 2m)
+        #[arg(long, default_value = "30s")]
+        timeout: String,
+    },
+
+    /// Send a request recorded with `serve --record-requests` again, and
+    /// show the answer next to the one recorded
+    Replay {
+        /// A recorded `.request` file, or the id of a recording the server keeps
+        recording: String,
+        /// Server to send it to
+        #[arg(long, default_value = "http://127.0.0.1:3210")]
+        endpoint: String,
+        /// Send a request whose recorded body was cut short
+        #[arg(long)]
+        allow_truncated: bool,
+    },
+
+    /// Print a completion script for bash, zsh, or fish
+    Completions {
+        #[arg(value_parser = ["bash", "zsh", "fish"])]
+        shell: String,
+    },
+
+    /// Completion candidates for the shell scripts: `buckets`, or
+    /// `keys BUCKET PREFIX`
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(value_parser = ["buckets", "keys"])]
+        kind: String,
+        bucket: Option<String>,
+        #[arg(default_value = "")]
+        prefix: String,
+        /// Ask a running server instead of reading the data dir (defaults
+        /// to FREEBUCKET_ENDPOINT)
+        #[arg(long)]
+        endpoint: Option<String>,
+    },
+
+    /// Print a systemd socket and service unit pair that run this binary
+    SystemdUnit {
+        /// Address the socket listens on (defaults to FREEBUCKET_HOST, or 127.0.0.1)
+        #[arg(long)]
+        host: Option<String>,
+        /// Port the socket listens on (defaults to FREEBUCKET_PORT, or 3210)
+        #[arg(long)]
+        port: Option<u16>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Snapshot a bucket's current contents
+    Create {
+        /// Bucket name
+        bucket: String,
+    },
+    /// List a bucket's snapshots
+    List {
+        /// Bucket name
+        bucket: String,
+    },
+    /// Reset a bucket to a snapshot (deletes newer objects)
+    Restore {
+        /// Bucket name
+        bucket: String,
+        /// Snapshot ID
+        id: String,
+        /// Confirm that objects created after the snapshot will be deleted
+        #[arg(long)]
+        force: bool,
+    },
+    /// Delete a snapshot
+    Delete {
+        /// Bucket name
+        bucket: String,
+        /// Snapshot ID
+        id: String,
+    },
+}
+
+/// Wait for a client request from the CLI, which runs on the runtime's
+/// main thread without being async itself
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
 }
 
 pub fn run_cli(cli: Cli) {
@@ -99,8 +519,153 @@ pub fn run_cli(cli: Cli) {
         .data_dir
         .or_else(|| std::env::var("FREEBUCKET_DATA_DIR").ok())
         .unwrap_or_else(|| "./freebucket_data".to_string());
+    crate::size::set_units(crate::config::Config::default().size_units);
+
+    // Runs before the storage engine is opened, since that is what it diagnoses
+    if let Some(Commands::Doctor { port, endpoint }) = &cli.command {
+        match endpoint {
+            Some(endpoint) => doctor::run_remote_doctor(endpoint),
+            None => {
+                let mut config = crate::config::Config::default();
+                config.port = port.unwrap_or(config.port);
+                doctor::run_doctor(&data_dir, &config);
+            }
+        }
+        return;
+    }
+
+    if let Some(Commands::Wait { endpoint, timeout }) = &cli.command {
+        // Ages are whole non-negative numbers, so `to_std` can't fail
+        let timeout = match parse_age(timeout) {
+            Ok(timeout) => timeout.to_std().unwrap_or_default(),
+            Err(_) => {
+                eprintln!("✗ Invalid timeout '{}': expected a number with s, m, h, d, or w", timeout);
+                std::process::exit(1);
+            }
+        };
+        doctor::run_wait(endpoint, timeout);
+        return;
+    }
+
+    if let Some(Commands::Replay { recording, endpoint, allow_truncated }) = &cli.command {
+        replay::run_replay(recording, endpoint, *allow_truncated);
+        return;
+    }
+
+    // Must answer fast, so it never opens the storage engine
+    if let Some(Commands::Complete { kind, bucket, prefix, endpoint }) = &cli.command {
+        let endpoint = endpoint.clone().or_else(|| std::env::var("FREEBUCKET_ENDPOINT").ok());
+        complete::run_complete(&data_dir, endpoint.as_deref(), kind, bucket.as_deref(), prefix);
+        return;
+    }
+    if let Some(Commands::Completions { shell }) = &cli.command {
+        complete::print_completions(shell);
+        return;
+    }
 
-    let storage = match StorageEngine::new(&data_dir) {
+    // Neither opens the data dir: restoring fills a new one, and a check
+    // or remote backup doesn't use it
+    if let Some(Commands::Restore { backup }) = &cli.command {
+        backup::run_restore(backup, &data_dir);
+        return;
+    }
+    match &cli.command {
+        Some(Commands::Backup { dir, verify: true, .. }) => {
+            backup::run_verify(dir);
+            return;
+        }
+        Some(Commands::Backup { dir, endpoint: Some(endpoint), .. }) => {
+            backup::run_remote_backup(endpoint, dir);
+            return;
+        }
+        _ => {}
+    }
+
+    // Remote transfers talk to the server, never the data dir
+    match &cli.command {
+        Some(Commands::Put {
+            sources,
+            destination,
+            recursive: true,
+            endpoint: Some(endpoint),
+            ..
+        }) => {
+            bulk::run_remote(endpoint, BulkOperation::Copy, &single_source(sources), destination);
+            return;
+        }
+        Some(Commands::Move {
+            source,
+            destination,
+            endpoint: Some(endpoint),
+            ..
+        }) => {
+            bulk::run_remote(endpoint, BulkOperation::Move, source, destination);
+            return;
+        }
+        _ => {}
+    }
+    if let Some(Commands::Put {
+        sources,
+        destination,
+        jobs,
+        content_type,
+        type_map,
+        if_changed,
+        preserve_mtime,
+        quiet,
+        exit_code,
+        endpoint: Some(endpoint),
+        recursive: false,
+    }) = &cli.command
+    {
+        let options = remote::PutOptions {
+            jobs: *jobs,
+            content_type: content_type.clone(),
+            type_map: type_map.clone(),
+            if_changed: *if_changed,
+            preserve_mtime: *preserve_mtime,
+            quiet: *quiet,
+            exit_code: *exit_code,
+        };
+        remote::run_put(endpoint, sources, destination, &options);
+        return;
+    }
+    if let Some(Commands::Get {
+        sources,
+        output,
+        jobs,
+        if_newer,
+        quiet,
+        exit_code,
+        endpoint: Some(endpoint),
+        chunk_size,
+    }) = &cli.command
+    {
+        let options = remote::GetOptions {
+            jobs: *jobs,
+            chunk_size: *chunk_size,
+            if_newer: *if_newer,
+            quiet: *quiet,
+            exit_code: *exit_code,
+        };
+        remote::run_get(endpoint, sources.clone(), output.clone(), &options);
+        return;
+    }
+
+    // Opens the data dir itself, and only when a side is local
+    if let Some(Commands::Diff { left, right, left_endpoint, right_endpoint, json }) = &cli.command {
+        diff::run_diff(&data_dir, left, right, left_endpoint.as_deref(), right_endpoint.as_deref(), *json);
+        return;
+    }
+
+    // Only prints text, so it has no reason to create the data dir
+    if let Some(Commands::SystemdUnit { host, port }) = &cli.command {
+        print_systemd_units(&data_dir, host.clone(), *port);
+        return;
+    }
+
+    let options = crate::config::Config::default().storage_options();
+    let storage = match StorageEngine::new(&data_dir, options) {
         Ok(s) => s,
         Err(e) => {
             eprintln!(
@@ -113,23 +678,70 @@ pub fn run_cli(cli: Cli) {
 
     match cli.command.unwrap() {
         Commands::Serve { .. } => unreachable!("Serve is handled in main"),
+        Commands::Doctor { .. } => unreachable!("Doctor is handled above"),
+        Commands::SystemdUnit { .. } => unreachable!("SystemdUnit is handled above"),
+        Commands::Wait { .. } => unreachable!("Wait is handled above"),
+        Commands::Replay { .. } => unreachable!("Replay is handled above"),
+        Commands::Complete { .. } | Commands::Completions { .. } => {
+            unreachable!("Completion is handled above")
+        }
 
-        Commands::MakeBucket { name, region } => match storage.create_bucket(&name, &region) {
-            Ok(bucket) => {
-                println!("✓ Bucket '{}' created successfully", bucket.name);
-                println!("  Region:  {}", bucket.region);
-                println!(
-                    "  Created: {}",
-                    bucket.created_at.format("%Y-%m-%d %H:%M:%S")
-                );
+        Commands::MakeBucket { name, region, volume } => {
+            let region = region.unwrap_or_else(|| crate::config::Config::default().default_region);
+            let created = storage.create_bucket_on(&name, &region, volume.as_deref());
+            match audited(&storage, AuditAction::BucketCreate, &name, created) {
+                Ok(bucket) => {
+                    println!("✓ Bucket '{}' created successfully", bucket.name);
+                    println!("  Region:  {}", bucket.region);
+                    if let Some(volume) = &bucket.volume {
+                        println!("  Volume:  {}", volume);
+                    }
+                    println!(
+                        "  Created: {}",
+                        bucket.created_at.format("%Y-%m-%d %H:%M:%S")
+                    );
+                }
+                Err(e) => {
+                    eprintln!("✗ {}", format_error(&e));
+                    std::process::exit(1);
+                }
             }
+        }
+
+        Commands::MoveBucket { name, volume } => match audited(
+            &storage,
+            AuditAction::BucketMove,
+            &name,
+            storage.move_bucket(&name, &volume),
+        ) {
+            Ok(bucket) => println!(
+                "✓ Bucket '{}' is on volume {}",
+                bucket.name,
+                bucket.volume.as_deref().unwrap_or(crate::storage::DEFAULT_VOLUME)
+            ),
             Err(e) => {
                 eprintln!("✗ {}", format_error(&e));
                 std::process::exit(1);
             }
         },
 
-        Commands::RemoveBucket { name } => match storage.delete_bucket(&name) {
+        Commands::Apply {
+            file,
+            dry_run,
+            prune,
+            yes,
+        } => apply::run_apply(&storage, &file, dry_run, prune, yes),
+
+        Commands::ApplyTxn { file } => apply::run_apply_txn(&storage, &file),
+
+        Commands::ExportConfig { output } => apply::run_export(&storage, output.as_deref()),
+
+        Commands::RemoveBucket { name, purge } => match audited(
+            &storage,
+            if purge { AuditAction::BucketPurge } else { AuditAction::BucketDelete },
+            &name,
+            storage.delete_bucket(&name, purge),
+        ) {
             Ok(()) => println!("✓ Bucket '{}' deleted", name),
             Err(e) => {
                 eprintln!("✗ {}", format_error(&e));
@@ -137,7 +749,18 @@ pub fn run_cli(cli: Cli) {
             }
         },
 
-        Commands::List { bucket, prefix } => {
+        Commands::List {
+            bucket,
+            prefix,
+            unused_since,
+            sort,
+            since,
+            stream,
+        } => {
+            let window = ModifiedWindow {
+                after: since,
+                before: None,
+            };
             match bucket {
                 None => {
                     // List all buckets
@@ -162,36 +785,89 @@ pub fn run_cli(cli: Cli) {
                     println!("{}", "─".repeat(70));
                     println!("{} bucket(s)", buckets.len());
                 }
+                Some(bucket_name) if stream => {
+                    print_listing_stream(&storage, &bucket_name, prefix.as_deref().unwrap_or(""), window)
+                }
                 Some(bucket_name) => {
                     // List objects in bucket
                     let prefix_str = prefix.as_deref().unwrap_or("");
-                    match storage.list_objects(&bucket_name, prefix_str, None, 1000) {
-                        Ok(result) => {
+                    let cutoff = match unused_since.as_deref().map(parse_age).transpose() {
+                        Ok(age) => age.map(|age| storage.now() - age),
+                        Err(msg) => {
+                            eprintln!("✗ {}", msg);
+                            std::process::exit(1);
+                        }
+                    };
+                    // Filtering has to see every object, not just the first page
+                    let max_keys = if cutoff.is_some() { u32::MAX } else { 1000 };
+                    let sort = match sort.as_str() {
+                        "modified" => ListSort::LastModified,
+                        "size" => ListSort::Size,
+                        _ => ListSort::Key,
+                    };
+                    let listing = storage.list_objects_sorted(
+                        &bucket_name,
+                        prefix_str,
+                        None,
+                        max_keys,
+                        ListOrder { sort, order: sort.default_order(), window },
+                        None,
+                    );
+                    match listing {
+                        Ok(mut result) => {
+                            let access = storage.bucket_access(&bucket_name);
+                            if let Some(cutoff) = cutoff {
+                                let Some(access) = &access else {
+                                    eprintln!("✗ --unused-since needs access tracking, which FREEBUCKET_ACCESS_TRACKING turns off");
+                                    std::process::exit(1);
+                                };
+                                // Objects never downloaded count from their upload
+                                result.objects.retain(|o| {
+                                    let last_used = access.get(&o.key).and_then(|a| a.last_accessed);
+                                    last_used.unwrap_or(o.last_modified) < cutoff
+                                });
+                            }
                             if result.objects.is_empty() {
                                 println!(
-                                    "No objects in bucket '{}'{}",
+                                    "No objects in bucket '{}'{}{}{}",
                                     bucket_name,
                                     if !prefix_str.is_empty() {
                                         format!(" with prefix '{}'", prefix_str)
                                     } else {
                                         String::new()
-                                    }
+                                    },
+                                    unused_since
+                                        .as_deref()
+                                        .map_or(String::new(), |age| format!(" unused for {}", age)),
+                                    since.map_or(String::new(), |since| format!(" modified since {}", since.to_rfc3339()))
                                 );
                                 return;
                             }
-                            println!("{:<50} {:>12}  LAST MODIFIED", "KEY", "SIZE");
+                            if cutoff.is_some() {
+                                println!("{:<50} {:>12}  {:<16}  LAST ACCESSED", "KEY", "SIZE", "LAST MODIFIED");
+                            } else {
+                                println!("{:<50} {:>12}  LAST MODIFIED", "KEY", "SIZE");
+                            }
                             println!("{}", "─".repeat(85));
                             for obj in &result.objects {
-                                println!(
-                                    "{:<50} {:>12}  {}",
-                                    if obj.key.len() > 48 {
-                                        format!("…{}", &obj.key[obj.key.len() - 47..])
-                                    } else {
-                                        obj.key.clone()
-                                    },
-                                    human_readable_size(obj.size),
-                                    obj.last_modified.format("%Y-%m-%d %H:%M")
-                                );
+                                let key = pad_column(&truncate_start(&obj.key, 48), 50);
+                                let modified = obj.modified().format("%Y-%m-%d %H:%M");
+                                match (&access, cutoff) {
+                                    (Some(access), Some(_)) => {
+                                        let last_accessed = access
+                                            .get(&obj.key)
+                                            .and_then(|a| a.last_accessed)
+                                            .map_or("never".to_string(), |t| t.format("%Y-%m-%d %H:%M").to_string());
+                                        println!(
+                                            "{} {:>12}  {:<16}  {}",
+                                            key,
+                                            human_readable_size(obj.size),
+                                            modified,
+                                            last_accessed
+                                        );
+                                    }
+                                    _ => println!("{} {:>12}  {}", key, human_readable_size(obj.size), modified),
+                                }
                             }
                             println!("{}", "─".repeat(85));
                             println!("{} object(s)", result.objects.len());
@@ -206,124 +882,195 @@ pub fn run_cli(cli: Cli) {
         }
 
         Commands::Put {
-            source,
+            sources,
             destination,
+            recursive: true,
+            ..
+        } => bulk::run_local(&storage, BulkOperation::Copy, &single_source(&sources), &destination),
+
+        Commands::Move { source, destination, .. } => {
+            bulk::run_local(&storage, BulkOperation::Move, &source, &destination)
+        }
+
+        Commands::Put {
+            sources,
+            destination,
+            jobs,
+            content_type,
+            type_map,
+            if_changed,
+            preserve_mtime,
+            quiet,
+            exit_code,
+            ..
         } => {
-            // Parse destination as bucket/key
-            let (bucket, key) = match destination.find('/') {
-                Some(pos) => (&destination[..pos], &destination[pos + 1..]),
-                None => {
-                    // If no key given, use the filename
-                    let filename = Path::new(&source)
-                        .file_name()
-                        .map(|n| n.to_string_lossy().to_string())
-                        .unwrap_or_else(|| "upload".to_string());
-                    // Can't borrow destination and filename at the same time easily,
-                    // so handle it differently
-                    let data = match std::fs::read(&source) {
-                        Ok(d) => d,
-                        Err(e) => {
-                            eprintln!("✗ Cannot read file '{}': {}", source, e);
-                            std::process::exit(1);
-                        }
-                    };
-                    match storage.put_object(&destination, &filename, &data, None, HashMap::new()) {
-                        Ok(meta) => {
-                            println!("✓ Uploaded '{}' → {}/{}", source, destination, filename);
-                            println!(
-                                "  Size: {}  ETag: {}",
-                                human_readable_size(meta.size),
-                                meta.etag
-                            );
-                        }
-                        Err(e) => {
-                            eprintln!("✗ {}", format_error(&e));
-                            std::process::exit(1);
-                        }
+            let UploadPlan { bucket, tasks, type_map } =
+                match plan_upload(&sources, &destination, content_type.as_deref(), &type_map) {
+                    Ok(plan) => plan,
+                    Err(msg) => {
+                        eprintln!("✗ {}", msg);
+                        std::process::exit(1);
                     }
-                    return;
-                }
-            };
+                };
+            let bucket = bucket.as_str();
 
-            let data = match std::fs::read(&source) {
-                Ok(d) => d,
-                Err(e) => {
-                    eprintln!("✗ Cannot read file '{}': {}", source, e);
-                    std::process::exit(1);
+            let summary = run_parallel(&tasks, jobs, |(path, dest_key)| {
+                let data = std::fs::read(path)
+                    .map_err(|e| format!("Cannot read file '{}': {}", path, e))?;
+                if if_changed {
+                    let etag = file_etag(&data);
+                    if storage.get_object_meta(bucket, dest_key).is_ok_and(|meta| meta.etag == etag) {
+                        if !quiet {
+                            println!("• '{}' → {}/{}  skipped (unchanged)", path, bucket, dest_key);
+                        }
+                        return Ok(None);
+                    }
                 }
-            };
-
-            match storage.put_object(bucket, key, &data, None, HashMap::new()) {
-                Ok(meta) => {
-                    println!("✓ Uploaded '{}' → {}/{}", source, bucket, key);
+                let file_name = std::path::Path::new(path).file_name().and_then(|n| n.to_str());
+                let content_type = content_type
+                    .as_deref()
+                    .or_else(|| mapped_content_type(&type_map, path));
+                let source_mtime = if preserve_mtime { Some(file_mtime(path)?) } else { None };
+                let meta = storage
+                    .put_object(
+                        bucket,
+                        dest_key,
+                        &data,
+                        PutOptions { content_type, original_filename: file_name, source_mtime, ..Default::default() },
+                    )
+                    .map_err(|e| format_error(&e))?;
+                if !quiet {
                     println!(
-                        "  Size: {}  ETag: {}",
+                        "✓ Uploaded '{}' → {}/{}  ({}, ETag {})",
+                        path,
+                        bucket,
+                        dest_key,
                         human_readable_size(meta.size),
                         meta.etag
                     );
                 }
-                Err(e) => {
-                    eprintln!("✗ {}", format_error(&e));
-                    std::process::exit(1);
+                Ok(Some(meta.size))
+            });
+            summary.finish("uploaded", quiet, exit_code);
+        }
+
+        Commands::Get {
+            mut sources,
+            mut output,
+            jobs,
+            if_newer,
+            quiet,
+            exit_code,
+            ..
+        } => {
+            // Legacy form: `get bucket/key local-file`
+            if output.is_none() && sources.len() > 1 {
+                let last = sources.last().unwrap();
+                let names_bucket = last
+                    .split_once('/')
+                    .is_some_and(|(b, _)| storage.get_bucket(b).is_ok());
+                if !names_bucket {
+                    output = sources.pop();
                 }
             }
-        }
 
-        Commands::Get { source, output } => {
-            let (bucket, key) = match source.find('/') {
-                Some(pos) => (&source[..pos], &source[pos + 1..]),
-                None => {
-                    eprintln!("✗ Source must be in format: bucket/key");
+            let objects = match expand_remote_patterns(&storage, &sources) {
+                Ok(objects) => objects,
+                Err(msg) => {
+                    eprintln!("✗ {}", msg);
                     std::process::exit(1);
                 }
             };
 
-            match storage.get_object(bucket, key) {
-                Ok((meta, data)) => {
-                    let out_path =
-                        output.unwrap_or_else(|| key.rsplit('/').next().unwrap_or(key).to_string());
-
-                    match std::fs::write(&out_path, &data) {
-                        Ok(()) => {
-                            println!("✓ Downloaded {}/{} → '{}'", bucket, key, out_path);
-                            println!(
-                                "  Size: {}  Type: {}",
-                                human_readable_size(meta.size),
-                                meta.content_type
-                            );
-                        }
-                        Err(e) => {
-                            eprintln!("✗ Cannot write to '{}': {}", out_path, e);
-                            std::process::exit(1);
-                        }
+            let tasks = download_paths(objects, &sources, output.as_deref());
+
+            let summary = run_parallel(&tasks, jobs, |(bucket, key, out_path)| {
+                if if_newer && local_copy_current(&storage, bucket, key, out_path) {
+                    if !quiet {
+                        println!("• {}/{} → '{}'  skipped (unchanged)", bucket, key, out_path);
                     }
+                    return Ok(None);
                 }
-                Err(e) => {
-                    eprintln!("✗ {}", format_error(&e));
-                    std::process::exit(1);
+                let (meta, data) = storage.get_object(bucket, key).map_err(|e| format_error(&e))?;
+                if let Some(parent) = Path::new(out_path).parent() {
+                    if !parent.as_os_str().is_empty() {
+                        std::fs::create_dir_all(parent)
+                            .map_err(|e| format!("Cannot create '{}': {}", parent.display(), e))?;
+                    }
                 }
-            }
+                std::fs::write(out_path, &data)
+                    .map_err(|e| format!("Cannot write to '{}': {}", out_path, e))?;
+                if !quiet {
+                    println!(
+                        "✓ Downloaded {}/{} → '{}'  ({}, {})",
+                        bucket,
+                        key,
+                        out_path,
+                        human_readable_size(meta.size),
+                        meta.content_type
+                    );
+                }
+                Ok(Some(meta.size))
+            });
+            summary.finish("downloaded", quiet, exit_code);
         }
 
-        Commands::Remove { path } => {
-            let (bucket, key) = match path.find('/') {
-                Some(pos) => (&path[..pos], &path[pos + 1..]),
-                None => {
-                    eprintln!("✗ Path must be in format: bucket/key");
+        Commands::Remove { paths, jobs } => {
+            let objects = match expand_remote_patterns(&storage, &paths) {
+                Ok(objects) => objects,
+                Err(msg) => {
+                    eprintln!("✗ {}", msg);
                     std::process::exit(1);
                 }
             };
 
-            match storage.delete_object(bucket, key) {
-                Ok(()) => println!("✓ Deleted {}/{}", bucket, key),
-                Err(e) => {
-                    eprintln!("✗ {}", format_error(&e));
+            let summary = run_parallel(&objects, jobs, |(bucket, key)| {
+                let size = storage.get_object_meta(bucket, key).map(|m| m.size).unwrap_or(0);
+                storage.delete_object(bucket, key).map_err(|e| format_error(&e))?;
+                println!("✓ Deleted {}/{}", bucket, key);
+                Ok(Some(size))
+            });
+            summary.finish("deleted", false, false);
+        }
+
+        Commands::Exists { path, stdin } => {
+            let paths: Vec<String> = if stdin {
+                std::io::stdin()
+                    .lines()
+                    .map_while(Result::ok)
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect()
+            } else {
+                path.into_iter().collect()
+            };
+
+            let mut missing = 0;
+            for path in &paths {
+                let Some((bucket, key)) = path.split_once('/').filter(|(_, key)| !key.is_empty()) else {
+                    eprintln!("✗ '{}' is not a bucket/key path", path);
                     std::process::exit(1);
+                };
+                match storage.objects_exist(bucket, &[key.to_string()]) {
+                    Ok(found) => match found.first().and_then(|o| o.size) {
+                        Some(size) => println!("✓ {} ({})", path, human_readable_size(size)),
+                        None => {
+                            println!("✗ {}", path);
+                            missing += 1;
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("✗ {}", format_error(&e));
+                        std::process::exit(1);
+                    }
                 }
             }
+            if missing > 0 {
+                std::process::exit(1);
+            }
         }
 
-        Commands::Stats => {
+        Commands::Stats { per_bucket } => {
             let stats = storage.get_stats();
             println!("FreeBucket Storage Statistics");
             println!("{}", "─".repeat(35));
@@ -331,25 +1078,918 @@ pub fn run_cli(cli: Cli) {
             println!("  Objects:  {}", stats.total_objects);
             println!("  Size:     {}", stats.total_size_human);
             println!("  Data dir: {}", data_dir);
+
+            if per_bucket && stats.total_buckets > 0 {
+                println!();
+                println!("{:<30} {:>8} {:>12}  LAST ACTIVITY", "BUCKET", "OBJECTS", "SIZE");
+                println!("{}", "─".repeat(70));
+                for b in storage.bucket_stats() {
+                    println!(
+                        "{:<30} {:>8} {:>12}  {}",
+                        b.name,
+                        b.object_count,
+                        b.total_size_human,
+                        b.last_activity.format("%Y-%m-%d %H:%M")
+                    );
+                }
+            }
         }
 
-        Commands::Info { bucket } => match storage.get_bucket(&bucket) {
-            Ok(b) => {
+        Commands::Largest { bucket, limit } => match storage.largest_objects(&bucket, limit) {
+            Ok(objects) if objects.is_empty() => println!("Bucket '{}' is empty", bucket),
+            Ok(objects) => {
+                println!("{:>12}  KEY", "SIZE");
+                println!("{}", "─".repeat(70));
+                for obj in &objects {
+                    println!("{:>12}  {}", human_readable_size(obj.size), obj.key);
+                }
+            }
+            Err(e) => {
+                eprintln!("✗ {}", format_error(&e));
+                std::process::exit(1);
+            }
+        },
+
+        Commands::Info { bucket } => match storage.bucket_details(&bucket) {
+            Ok(details) => {
+                let b = &details.bucket;
+                let on_off = |enabled: bool| if enabled { "on" } else { "off" };
                 println!("Bucket: {}", b.name);
                 println!("{}", "─".repeat(35));
-                println!("  Region:   {}", b.region);
-                println!("  Objects:  {}", b.object_count);
-                println!("  Size:     {}", human_readable_size(b.total_size));
-                println!("  Created:  {}", b.created_at.format("%Y-%m-%d %H:%M:%S"));
+                println!("  Region:     {}", b.region);
+                match b.max_objects {
+                    Some(limit) => println!("  Objects:    {} of at most {}", b.object_count, limit),
+                    None => println!("  Objects:    {}", b.object_count),
+                }
+                println!("  Size:       {}", details.total_size_human);
+                println!("  Created:    {}", b.created_at.format("%Y-%m-%d %H:%M:%S"));
+                println!("  Activity:   {}", details.last_activity.format("%Y-%m-%d %H:%M:%S"));
+                println!("  Versioning: {}", on_off(b.versioning));
+                println!("  Overwrite:  {}", b.overwrite_policy.name());
+                println!("  Inbox:      {}", on_off(b.inbox));
+                if let Some(logging) = &b.logging {
+                    println!("  Logging:    {}/{}", logging.target_bucket, logging.target_prefix);
+                }
+                if !details.recent_objects.is_empty() {
+                    println!("\n  Recently modified:");
+                    for object in &details.recent_objects {
+                        println!(
+                            "    {}  {:>10}  {}",
+                            object.last_modified.format("%Y-%m-%d %H:%M"),
+                            human_readable_size(object.size),
+                            object.key
+                        );
+                    }
+                }
             }
             Err(e) => {
                 eprintln!("✗ {}", format_error(&e));
                 std::process::exit(1);
             }
         },
+
+        Commands::Snapshot { action } => run_snapshot(&storage, action),
+
+        Commands::Backup { dir, .. } => backup::run_backup(&storage, &dir),
+        Commands::Restore { .. } => unreachable!("Restore is handled above"),
+
+        Commands::Inventory { bucket, format } => {
+            let format = if format == "json" {
+                InventoryFormat::Json
+            } else {
+                InventoryFormat::Csv
+            };
+            let stdout = std::io::stdout();
+            let mut out = std::io::BufWriter::new(stdout.lock());
+            if let Err(e) = storage.write_inventory(&bucket, format, &mut out) {
+                eprintln!("✗ {}", format_error(&e));
+                std::process::exit(1);
+            }
+        }
+
+        Commands::Dupes { bucket } => print_dupes(&storage, bucket.as_deref()),
+        Commands::Diff { .. } => unreachable!("Diff is handled above"),
+
+        Commands::Watch {
+            dir,
+            destination,
+            delete,
+            ignore,
+            debounce_ms,
+            type_map,
+        } => {
+            let (bucket, prefix) = destination.split_once('/').unwrap_or((&destination, ""));
+            let prefix = if prefix.is_empty() || prefix.ends_with('/') {
+                prefix.to_string()
+            } else {
+                format!("{}/", prefix)
+            };
+            let type_map = match parse_type_map(&type_map) {
+                Ok(map) => map,
+                Err(msg) => {
+                    eprintln!("✗ {}", msg);
+                    std::process::exit(1);
+                }
+            };
+            let opts = watch::WatchOptions {
+                dir: dir.into(),
+                bucket: bucket.to_string(),
+                prefix,
+                delete,
+                ignore,
+                debounce: std::time::Duration::from_millis(debounce_ms),
+                type_map,
+            };
+            if let Err(msg) = watch::run_watch(&storage, opts) {
+                eprintln!("✗ {}", msg);
+                std::process::exit(1);
+            }
+        }
+
+        Commands::Repair { path } => {
+            let Some((bucket, key)) = path.split_once('/') else {
+                eprintln!("✗ '{}' must be in format: bucket/key", path);
+                std::process::exit(1);
+            };
+            match storage.rebuild_object_meta(bucket, key) {
+                Ok(meta) => {
+                    println!("✓ Rebuilt metadata for {}/{}", bucket, key);
+                    println!("  Size:         {}", human_readable_size(meta.size));
+                    println!("  Content-Type: {}", meta.content_type);
+                    println!("  ETag:         {}", meta.etag);
+                    if !meta.metadata.is_empty() {
+                        println!("  Metadata:     {} custom entries kept", meta.metadata.len());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("✗ {}", format_error(&e));
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Fsck { bucket, confirm_metadata: true, stats_only } => {
+            match storage.confirm_bucket_metadata(&bucket) {
+                Ok(Some(recovery)) => {
+                    println!("✓ Confirmed the metadata of {} recovered at {}", bucket, recovery.recovered_at.to_rfc3339());
+                    println!("  Salvaged:  {}", list_or_none(&recovery.salvaged));
+                    println!("  Discarded: {}", list_or_none(&recovery.discarded));
+                    if let Some(file) = &recovery.corrupt_file {
+                        println!("  The damaged file is still in the bucket's directory as {}", file);
+                    }
+                }
+                Ok(None) => println!("✓ The metadata of {} needs no confirming", bucket),
+                Err(e) => {
+                    eprintln!("✗ {}", format_error(&e));
+                    std::process::exit(1);
+                }
+            }
+            if stats_only {
+                run_stats_recount(&storage, &bucket);
+            }
+        }
+
+        Commands::Fsck { bucket, .. } => run_stats_recount(&storage, &bucket),
+
+        Commands::FixTypes { bucket, dry_run, only_prefix, exclude_ext, sniff } => {
+            let query = crate::models::FixContentTypesQuery {
+                dry_run,
+                prefix: only_prefix,
+                exclude: exclude_ext.join(","),
+                sniff,
+            };
+            match storage.fix_content_types(&bucket, &query) {
+                Ok(report) => {
+                    for (content_type, keys) in &report.by_type {
+                        println!("{} ({})", content_type, keys.len());
+                        for key in keys {
+                            println!("  {}", key);
+                        }
+                    }
+                    if !report.unresolved.is_empty() {
+                        println!("No better type found ({})", report.unresolved.len());
+                        for key in &report.unresolved {
+                            println!("  {}", key);
+                        }
+                    }
+                    let verb = if dry_run { "Would fix" } else { "Fixed" };
+                    println!(
+                        "\n{} {} of {} object(s) with a generic content type",
+                        verb, report.changed, report.scanned
+                    );
+                }
+                Err(e) => {
+                    eprintln!("✗ {}", format_error(&e));
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Gc { dry_run } => {
+            let opts = crate::config::Config::default().gc_options(dry_run);
+            match storage.collect_garbage(&opts) {
+                Ok(report) => {
+                    let verb = if dry_run { "Would remove" } else { "Removed" };
+                    if report.transactions_recovered > 0 {
+                        let finish = if dry_run { "Would finish" } else { "Finished" };
+                        println!("{} {} interrupted transaction(s)", finish, report.transactions_recovered);
+                    }
+                    println!("{}:", verb);
+                    println!("  Temp files:        {}", report.temp_files_removed);
+                    println!("  Stale uploads:     {}", report.stale_uploads_aborted);
+                    println!("  Orphan metadata:   {}", report.orphan_meta_removed);
+                    println!("  Empty directories: {}", report.empty_dirs_removed);
+                    println!("  Deleted buckets:   {}", report.deleted_buckets_purged);
+                    println!("  Reclaimed:         {}", human_readable_size(report.reclaimed_bytes));
+                }
+                Err(e) => {
+                    eprintln!("✗ {}", format_error(&e));
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::SelfTest => {
+            let report = storage.self_test(false);
+            for step in &report.steps {
+                let mark = if step.passed { "✓" } else { "✗" };
+                println!("{} {:<8} {:>9.2} ms", mark, step.step, step.duration_ms);
+                if let Some(error) = &step.error {
+                    println!("    {}", error);
+                }
+            }
+            if !report.passed {
+                eprintln!("✗ Self-test failed");
+                std::process::exit(1);
+            }
+            println!("Self-test passed in {:.2} ms", report.duration_ms);
+        }
+    }
+}
+
+/// Aggregate outcome of a batch of file transfers
+struct TransferSummary {
+    succeeded: usize,
+    /// Left alone because the other side already matched
+    skipped: usize,
+    bytes: u64,
+    failures: Vec<String>,
+}
+
+/// Exit status of a transfer that skipped everything, under `--exit-code`
+const EXIT_ALL_SKIPPED: i32 = 3;
+
+impl TransferSummary {
+    /// Print the aggregate line and exit non-zero if anything failed, or
+    /// with `EXIT_ALL_SKIPPED` if asked to flag a run that changed nothing
+    fn finish(self, verb: &str, quiet: bool, exit_code: bool) {
+        let total = self.succeeded + self.skipped + self.failures.len();
+        if !quiet && (total > 1 || !self.failures.is_empty()) {
+            println!("{}", "─".repeat(70));
+            let skipped = if self.skipped > 0 {
+                format!(", {} skipped (unchanged)", self.skipped)
+            } else {
+                String::new()
+            };
+            println!(
+                "{} of {} file(s) {}{} ({})",
+                self.succeeded,
+                total,
+                verb,
+                skipped,
+                human_readable_size(self.bytes)
+            );
+        }
+        if !self.failures.is_empty() {
+            if !quiet {
+                eprintln!("{} failure(s):", self.failures.len());
+                for failure in &self.failures {
+                    eprintln!("  ✗ {}", failure);
+                }
+            }
+            std::process::exit(1);
+        }
+        if exit_code && self.succeeded == 0 && self.skipped > 0 {
+            std::process::exit(EXIT_ALL_SKIPPED);
+        }
+    }
+}
+
+/// The one bucket/prefix `cp --recursive` copies from
+fn single_source(sources: &[String]) -> String {
+    match sources {
+        [source] => source.clone(),
+        _ => {
+            eprintln!("✗ --recursive copies from one bucket/prefix, not {}", sources.len());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// What `put` uploads: the bucket, `(file path, key)` pairs, and the
+/// `--type-map` entries
+struct UploadPlan {
+    bucket: String,
+    tasks: Vec<(String, String)>,
+    type_map: HashMap<String, String>,
+}
+
+/// Check `put`'s arguments and pick the key of every file. A single file
+/// with an explicit key keeps that key; everything else lands under the
+/// destination prefix.
+fn plan_upload(
+    sources: &[String],
+    destination: &str,
+    content_type: Option<&str>,
+    type_map: &[String],
+) -> Result<UploadPlan, String> {
+    let (bucket, key) = match destination.find('/') {
+        Some(pos) => (&destination[..pos], &destination[pos + 1..]),
+        None => (destination, ""),
+    };
+    let files = expand_local_sources(sources)?;
+    let type_map = parse_type_map(type_map)?;
+    if let Some(Err(e)) = content_type.map(crate::storage::check_content_type) {
+        return Err(e.parts().2);
+    }
+
+    let single_named = files.len() == 1 && !key.is_empty() && !key.ends_with('/');
+    let tasks = files
+        .into_iter()
+        .map(|(path, rel)| {
+            let dest_key = if single_named {
+                key.to_string()
+            } else if key.is_empty() || key.ends_with('/') {
+                format!("{}{}", key, rel)
+            } else {
+                format!("{}/{}", key, rel)
+            };
+            (path, dest_key)
+        })
+        .collect();
+    Ok(UploadPlan {
+        bucket: bucket.to_string(),
+        tasks,
+        type_map,
+    })
+}
+
+/// The ETag an object with this content has: the SHA-256 in hex
+fn file_etag(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// A file's modification time, for `--preserve-mtime`
+fn file_mtime(path: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(chrono::DateTime::<chrono::Utc>::from)
+        .map_err(|e| format!("Cannot read the modification time of '{}': {}", path, e))
+}
+
+/// Run `op` over every task using up to `jobs` worker threads. Each task's
+/// failure is reported individually instead of aborting the batch. `op`
+/// returns the bytes it moved, or None if it skipped the task.
+fn run_parallel<T, F>(tasks: &[T], jobs: usize, op: F) -> TransferSummary
+where
+    T: Sync,
+    F: Fn(&T) -> Result<Option<u64>, String> + Sync,
+{
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    let next = AtomicUsize::new(0);
+    let summary = Mutex::new(TransferSummary {
+        succeeded: 0,
+        skipped: 0,
+        bytes: 0,
+        failures: Vec::new(),
+    });
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, tasks.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(task) = tasks.get(i) else { break };
+                let result = op(task);
+                let mut summary = summary.lock().unwrap();
+                match result {
+                    Ok(Some(bytes)) => {
+                        summary.succeeded += 1;
+                        summary.bytes += bytes;
+                    }
+                    Ok(None) => summary.skipped += 1,
+                    Err(msg) => {
+                        eprintln!("✗ {}", msg);
+                        summary.failures.push(msg);
+                    }
+                }
+            });
+        }
+    });
+
+    summary.into_inner().unwrap()
+}
+
+fn local_copy_current(storage: &StorageEngine, bucket: &str, key: &str, path: &str) -> bool {
+    storage
+        .get_object_meta(bucket, key)
+        .is_ok_and(|meta| file_current(path, meta.size, meta.modified()))
+}
+
+/// Whether the file at `path` already holds an object of `size` bytes
+/// modified at `modified`: the same size, and written no earlier. File
+/// timestamps come from a coarser clock than object times (and FAT keeps
+/// only even seconds), so a copy written right after the object can look
+/// slightly older.
+fn file_current(path: &str, size: u64, modified: chrono::DateTime<chrono::Utc>) -> bool {
+    const MTIME_SLACK: chrono::Duration = chrono::Duration::seconds(2);
+    let Ok(local) = std::fs::metadata(path) else {
+        return false;
+    };
+    let local_modified = local.modified().map(chrono::DateTime::<chrono::Utc>::from);
+    local.is_file() && local.len() == size && local_modified.is_ok_and(|t| t + MTIME_SLACK >= modified)
+}
+
+/// Parse an age such as `30d`, `12h`, `45m`, `2w`, or plain seconds
+/// Keep the end of `text` that fits in `max_width` terminal columns, marking
+/// a cut with a leading `…`. Wide characters such as CJK take two columns.
+/// `ls --stream`: one JSON object per line, then a summary line, in the
+/// format of the REST NDJSON listing. Stops quietly once stdout is closed,
+/// e.g. by `| head`.
+fn print_listing_stream(storage: &StorageEngine, bucket: &str, prefix: &str, window: ModifiedWindow) {
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let mut summary = ListingSummary::default();
+    let mut open = true;
+    let mut visit = |meta: crate::models::ObjectMeta| {
+        summary.count += 1;
+        summary.bytes += meta.size;
+        open = serde_json::to_writer(&mut out, &meta).is_ok() && out.write_all(b"\n").is_ok();
+        if !open {
+            return Err(crate::error::AppError::StorageError("stdout closed".to_string()));
+        }
+        Ok(())
+    };
+    let result = if window.is_set() {
+        storage
+            .visit_modified(bucket, prefix, window, &mut visit)
+            .map(|before| summary.modified_before = Some(before))
+    } else {
+        storage.visit_objects(bucket, prefix, &mut visit)
+    };
+    match result {
+        Ok(()) => {
+            let _ = writeln!(out, "{}", serde_json::json!({ "summary": summary }));
+            let _ = out.flush();
+        }
+        Err(_) if !open => {}
+        Err(e) => {
+            eprintln!("✗ {}", format_error(&e));
+            std::process::exit(1);
+        }
+    }
+}
+
+fn truncate_start(text: &str, max_width: usize) -> String {
+    if text.width() <= max_width {
+        return text.to_string();
+    }
+    let mut width = 1;
+    let mut start = text.len();
+    for (i, c) in text.char_indices().rev() {
+        width += c.width().unwrap_or(0);
+        if width > max_width {
+            break;
+        }
+        start = i;
+    }
+    format!("…{}", &text[start..])
+}
+
+/// Pad `text` with spaces to `width` terminal columns; `{:<N}` counts chars
+fn pad_column(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+/// `--jobs`: at least one, or nothing would run
+fn parse_jobs(text: &str) -> Result<usize, String> {
+    match text.trim().parse::<usize>() {
+        Ok(0) => Err("Invalid job count '0': at least one job is needed".to_string()),
+        Ok(jobs) => Ok(jobs),
+        Err(_) => Err(format!("Invalid job count '{}': expected a whole number", text)),
+    }
+}
+
+/// `--chunk-size`: a size, but not 0, which would cut an object into
+/// endlessly many ranges
+fn parse_chunk_size(text: &str) -> Result<u64, String> {
+    match crate::size::parse_size(text)? {
+        0 => Err(format!("Invalid chunk size '{}': ranges must hold at least one byte", text)),
+        size => Ok(size),
     }
 }
 
+fn parse_age(text: &str) -> Result<chrono::Duration, String> {
+    let invalid = || format!("Invalid age '{}': expected a number with s, m, h, d, or w", text);
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: i64 = number.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    number
+        .checked_mul(seconds)
+        .and_then(chrono::Duration::try_seconds)
+        .ok_or_else(invalid)
+}
+
+/// Parse `--type-map` entries of the form `EXT=TYPE`, keyed by lowercase
+/// extension without the leading dot
+fn parse_type_map(entries: &[String]) -> Result<HashMap<String, String>, String> {
+    let mut map = HashMap::new();
+    for entry in entries {
+        let (ext, content_type) = entry
+            .split_once('=')
+            .filter(|(ext, ty)| !ext.trim_start_matches('.').is_empty() && !ty.is_empty())
+            .ok_or_else(|| format!("Invalid type mapping '{}': expected EXT=TYPE", entry))?;
+        crate::storage::check_content_type(content_type).map_err(|e| e.parts().2)?;
+        map.insert(ext.trim_start_matches('.').to_lowercase(), content_type.to_string());
+    }
+    Ok(map)
+}
+
+/// The `--type-map` entry for a local file's extension, if any. Files
+/// without one fall back to the server's detection.
+fn mapped_content_type(map: &HashMap<String, String>, path: impl AsRef<Path>) -> Option<&str> {
+    let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+    map.get(&ext).map(String::as_str)
+}
+
+fn has_glob_chars(s: &str) -> bool {
+    s.contains(['*', '?', '[', '{'])
+}
+
+/// Glob where `*` stays within one path segment and `**` crosses segments
+fn build_glob(pattern: &str) -> Result<globset::GlobMatcher, String> {
+    globset::GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|g| g.compile_matcher())
+        .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))
+}
+
+/// Resolve local sources (plain paths or glob patterns) into
+/// `(file path, key suffix)` pairs. Glob matches keep their path relative to
+/// the pattern's literal base directory; plain files use their file name.
+fn expand_local_sources(sources: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut files = Vec::new();
+    for source in sources {
+        if !has_glob_chars(source) {
+            let path = Path::new(source);
+            if path.is_dir() {
+                return Err(format!("'{}' is a directory; use a pattern like '{}/**'", source, source));
+            }
+            if !path.exists() {
+                return Err(format!("Cannot read file '{}': no such file", source));
+            }
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "upload".to_string());
+            files.push((source.clone(), name));
+            continue;
+        }
+
+        let normalized = source.replace('\\', "/");
+        let matcher = build_glob(&normalized)?;
+        // Walk from the longest leading run of literal path segments
+        let base: Vec<&str> = normalized
+            .split('/')
+            .take_while(|seg| !has_glob_chars(seg))
+            .collect();
+        let base = base.join("/");
+        let walk_root = if base.is_empty() { ".".to_string() } else { base.clone() };
+
+        let mut found = Vec::new();
+        walk_local_files(Path::new(&walk_root), &mut found);
+        let before = files.len();
+        for path in found {
+            let display = path.to_string_lossy().replace('\\', "/");
+            let display = display.strip_prefix("./").unwrap_or(&display).to_string();
+            if matcher.is_match(&display) {
+                let rel = display
+                    .strip_prefix(&base)
+                    .unwrap_or(&display)
+                    .trim_start_matches('/')
+                    .to_string();
+                files.push((display, rel));
+            }
+        }
+        if files.len() == before {
+            return Err(format!("No files match '{}'", source));
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn walk_local_files(dir: &Path, out: &mut Vec<std::path::PathBuf>) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                walk_local_files(&path, out);
+            } else {
+                out.push(path);
+            }
+        }
+    }
+}
+
+/// `(bucket, key, local path)` for each object `get` downloads: a single
+/// object to the output file or its own name, several into the output
+/// directory (or the current one) under their keys
+fn download_paths(
+    objects: Vec<(String, String)>,
+    sources: &[String],
+    output: Option<&str>,
+) -> Vec<(String, String, String)> {
+    let into_dir = objects.len() > 1
+        || sources.iter().any(|s| has_glob_chars(s))
+        || output.is_some_and(|o| o.ends_with('/') || Path::new(o).is_dir());
+    objects
+        .into_iter()
+        .map(|(bucket, key)| {
+            let name = key.rsplit('/').next().unwrap_or(&key).to_string();
+            let out_path = match (output, into_dir) {
+                (Some(dir), true) => Path::new(dir).join(local_path(&key)).to_string_lossy().to_string(),
+                (None, true) => local_path(&key),
+                (Some(file), false) => file.to_string(),
+                (None, false) => name,
+            };
+            (bucket, key, out_path)
+        })
+        .collect()
+}
+
+/// Where a downloaded key goes relative to the output directory. Empty,
+/// `.`, and `..` segments are legal in keys but would collapse or escape
+/// the directory, so they are dropped.
+fn local_path(key: &str) -> String {
+    key.split('/')
+        .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Resolve `bucket/key` arguments into concrete objects. Keys containing glob
+/// characters are listed server-side by their literal prefix, then matched
+/// client-side.
+fn expand_remote_patterns(
+    storage: &StorageEngine,
+    patterns: &[String],
+) -> Result<Vec<(String, String)>, String> {
+    expand_patterns(patterns, |bucket, prefix| {
+        let listing = storage
+            .list_objects(bucket, prefix, None, u32::MAX)
+            .map_err(|e| format_error(&e))?;
+        Ok(listing.objects.into_iter().map(|o| o.key).collect())
+    })
+}
+
+/// `expand_remote_patterns` with the keys under a prefix coming from `list`
+fn expand_patterns(
+    patterns: &[String],
+    list: impl Fn(&str, &str) -> Result<Vec<String>, String>,
+) -> Result<Vec<(String, String)>, String> {
+    let mut objects = Vec::new();
+    for pattern in patterns {
+        let (bucket, key) = pattern
+            .split_once('/')
+            .ok_or_else(|| format!("'{}' must be in format: bucket/key", pattern))?;
+
+        if !has_glob_chars(key) {
+            objects.push((bucket.to_string(), key.to_string()));
+            continue;
+        }
+
+        let matcher = build_glob(key)?;
+        let literal_end = key.find(['*', '?', '[', '{']).unwrap_or(key.len());
+        let keys = list(bucket, &key[..literal_end])?;
+        let before = objects.len();
+        objects.extend(
+            keys.into_iter()
+                .filter(|k| matcher.is_match(k))
+                .map(|k| (bucket.to_string(), k)),
+        );
+        if objects.len() == before {
+            return Err(format!("No objects match '{}'", pattern));
+        }
+    }
+    objects.sort();
+    objects.dedup();
+    Ok(objects)
+}
+
+fn run_snapshot(storage: &StorageEngine, action: SnapshotAction) {
+    let print_snapshot = |s: &Snapshot| {
+        println!(
+            "{:<28} {:>8} {:>12}  {}",
+            s.id,
+            s.object_count,
+            human_readable_size(s.total_size),
+            s.created_at.format("%Y-%m-%d %H:%M:%S")
+        );
+    };
+
+    let result = match action {
+        SnapshotAction::Create { bucket } => storage.create_snapshot(&bucket).map(|s| {
+            println!("✓ Snapshot '{}' of bucket '{}' created", s.id, bucket);
+            println!(
+                "  Objects: {}  Size: {}",
+                s.object_count,
+                human_readable_size(s.total_size)
+            );
+        }),
+        SnapshotAction::List { bucket } => storage.list_snapshots(&bucket).map(|snapshots| {
+            if snapshots.is_empty() {
+                println!("No snapshots for bucket '{}'", bucket);
+                return;
+            }
+            println!("{:<28} {:>8} {:>12}  CREATED", "SNAPSHOT", "OBJECTS", "SIZE");
+            println!("{}", "─".repeat(75));
+            snapshots.iter().for_each(print_snapshot);
+        }),
+        SnapshotAction::Restore { bucket, id, force } => storage
+            .restore_snapshot(&bucket, &id, force)
+            .map(|s| println!("✓ Bucket '{}' restored to snapshot '{}'", bucket, s.id)),
+        SnapshotAction::Delete { bucket, id } => storage
+            .delete_snapshot(&bucket, &id)
+            .map(|()| println!("✓ Snapshot '{}' deleted", id)),
+    };
+
+    if let Err(e) = result {
+        eprintln!("✗ {}", format_error(&e));
+        std::process::exit(1);
+    }
+}
+
+/// Recount a bucket's objects and bytes, printing the totals before and after
+fn run_stats_recount(storage: &StorageEngine, bucket: &str) {
+    // The progress line is redrawn in place, so only on a terminal
+    let progress = std::io::stderr().is_terminal();
+    let result = storage.recompute_bucket_stats(bucket, |job| {
+        if !progress {
+            return;
+        }
+        eprint!(
+            "\r  Counted {} object(s), {} in {} batch(es); {} director{} left   ",
+            job.object_count,
+            human_readable_size(job.total_size),
+            job.batches,
+            job.directories_left,
+            if job.directories_left == 1 { "y" } else { "ies" }
+        );
+    });
+    if progress {
+        eprint!("\r{}\r", " ".repeat(100));
+    }
+    match result {
+        Ok(job) => {
+            let before = format!(
+                "{} object(s), {}",
+                job.recorded_object_count,
+                human_readable_size(job.recorded_total_size)
+            );
+            let after = format!("{} object(s), {}", job.object_count, human_readable_size(job.total_size));
+            let unchanged = job.object_count == job.recorded_object_count
+                && job.total_size == job.recorded_total_size;
+            match job.status {
+                RecomputeStatus::Completed if unchanged => {
+                    println!("✓ Statistics of {} are correct: {}", bucket, after)
+                }
+                RecomputeStatus::Completed => {
+                    println!("✓ Corrected statistics of {}: {} → {}", bucket, before, after)
+                }
+                RecomputeStatus::Superseded => {
+                    println!("✓ {} was written to during the check, which recounted it already", bucket)
+                }
+                _ => {
+                    eprintln!("✗ {}", job.error.unwrap_or_else(|| format!("{:?}", job.status)));
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("✗ {}", format_error(&e));
+            std::process::exit(1);
+        }
+    }
+}
+
+fn list_or_none(items: &[String]) -> String {
+    match items.is_empty() {
+        true => "none".to_string(),
+        false => items.join(", "),
+    }
+}
+
+fn print_dupes(storage: &StorageEngine, bucket: Option<&str>) {
+    if let Some(name) = bucket {
+        if let Err(e) = storage.get_bucket(name) {
+            eprintln!("✗ {}", format_error(&e));
+            std::process::exit(1);
+        }
+    }
+
+    let groups = storage.duplicate_groups(bucket);
+    if groups.is_empty() {
+        println!("No duplicate objects found");
+        return;
+    }
+
+    let mut wasted = 0u64;
+    for group in &groups {
+        println!(
+            "{}…  {} copies × {}  (wasted {})",
+            &group.sha256[..group.sha256.len().min(16)],
+            group.objects.len(),
+            human_readable_size(group.size),
+            human_readable_size(group.wasted_bytes)
+        );
+        for obj in &group.objects {
+            println!("    {}/{}", obj.bucket, obj.key);
+        }
+        wasted += group.wasted_bytes;
+    }
+    println!("{}", "─".repeat(70));
+    println!(
+        "{} duplicate group(s), {} reclaimable",
+        groups.len(),
+        human_readable_size(wasted)
+    );
+}
+
+fn print_systemd_units(data_dir: &str, host: Option<String>, port: Option<u16>) {
+    let config = crate::config::Config::default();
+    let binary = match std::env::current_exe() {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("✗ Cannot find the path of this binary: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // The service runs from a different working directory
+    let data_dir = Path::new(data_dir);
+    let data_dir = data_dir.canonicalize().unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|cwd| cwd.join(data_dir))
+            .unwrap_or_else(|_| data_dir.to_path_buf())
+    });
+    if !cfg!(feature = "systemd") {
+        eprintln!("⚠ This binary was built without the `systemd` feature, so it cannot take the");
+        eprintln!("  socket or report readiness. Rebuild with `cargo build --release --features systemd`.");
+    }
+    print!(
+        "{}",
+        crate::systemd::unit_files(
+            &binary,
+            &data_dir,
+            &host.unwrap_or(config.host),
+            port.unwrap_or(config.port)
+        )
+    );
+}
+
+/// Who the audit log names for commands run from the command line
+const CLI_ACTOR: &str = "cli";
+
+/// Record an action on a bucket in the audit log before reporting it
+fn audited<T>(
+    storage: &StorageEngine,
+    action: AuditAction,
+    bucket: &str,
+    result: Result<T, crate::error::AppError>,
+) -> Result<T, crate::error::AppError> {
+    storage.audited(
+        Audit {
+            actor: CLI_ACTOR,
+            action,
+            bucket: Some(bucket),
+            target: bucket,
+            changes: Vec::new(),
+        },
+        result,
+    )
+}
+
 fn format_error(e: &crate::error::AppError) -> String {
     match e {
         crate::error::AppError::BucketNotFound(name) => format!("Bucket '{}' not found", name),
@@ -360,8 +2000,183 @@ fn format_error(e: &crate::error::AppError) -> String {
             format!("Object '{}/{}' not found", bucket, key)
         }
         crate::error::AppError::InvalidBucketName(msg) => format!("Invalid bucket name: {}", msg),
+        crate::error::AppError::ReservedBucketName { name, reserved } => {
+            format!("Bucket name '{}' is reserved (reserved: {})", name, reserved.join(", "))
+        }
         crate::error::AppError::InvalidObjectKey(msg) => format!("Invalid key: {}", msg),
+        crate::error::AppError::InvalidRange(msg) => format!("Invalid range: {}", msg),
+        crate::error::AppError::NoSuchUpload(id) => format!("Multipart upload '{}' not found", id),
+        crate::error::AppError::InvalidPart(msg) => format!("Invalid part: {}", msg),
+        crate::error::AppError::InvalidRequest(msg) => msg.clone(),
+        crate::error::AppError::SnapshotNotFound { bucket, id } => {
+            format!("Snapshot '{}' not found in bucket '{}'", id, bucket)
+        }
+        crate::error::AppError::VersionNotFound { key, version_id } => {
+            format!("Version '{}' of '{}' not found", version_id, key)
+        }
+        crate::error::AppError::JobNotFound(id) => format!("Job '{}' not found", id),
+        crate::error::AppError::RecordingNotFound(id) => format!("Recorded request '{}' not found", id),
+        crate::error::AppError::DeletedBucketNotFound(id) => format!("Deleted bucket '{}' not found", id),
+        crate::error::AppError::BadDigest(algorithm) => format!("{} checksum mismatch", algorithm),
+        crate::error::AppError::AccessDenied(reason) => format!("Access denied: {}", reason),
+        crate::error::AppError::InvalidCsrfToken(reason) => format!("Cross-site request refused: {}", reason),
+        crate::error::AppError::SlowDown(reason) => reason.clone(),
+        crate::error::AppError::ObjectBusy { bucket, key } => {
+            format!("Object '{}/{}' is being read", bucket, key)
+        }
+        crate::error::AppError::ObjectAlreadyExists { bucket, key } => {
+            format!("Object '{}/{}' already exists and its bucket denies overwrites", bucket, key)
+        }
+        crate::error::AppError::KeyCaseCollision { bucket, key, existing } => format!(
+            "Key '{}' differs only in case from '{}' in bucket '{}', and the data dir's filesystem ignores case",
+            key, existing, bucket
+        ),
+        crate::error::AppError::ObjectPending { bucket, key } => {
+            format!("Object '{}/{}' is waiting for its scan", bucket, key)
+        }
+        crate::error::AppError::ObjectLocked { bucket, key, until } => {
+            format!("Object '{}/{}' is leased until {}", bucket, key, until.to_rfc3339())
+        }
+        crate::error::AppError::LeaseNotFound { bucket, key } => {
+            format!("No lease is held on '{}/{}'", bucket, key)
+        }
+        crate::error::AppError::ShareNotFound(token) => format!("Share link '{}' not found", token),
+        crate::error::AppError::ShareExpired(token) => format!("Share link '{}' has expired", token),
+        crate::error::AppError::ShareExhausted(token) => format!("Share link '{}' has no downloads left", token),
+        crate::error::AppError::QuarantinedUploadNotFound { bucket, id } => {
+            format!("Quarantined upload '{}' not found in bucket '{}'", id, bucket)
+        }
+        crate::error::AppError::TooManyObjects { bucket, count, limit, adding } => format!(
+            "Bucket '{}' holds {} of at most {} objects; adding {} would exceed the limit",
+            bucket, count, limit, adding
+        ),
+        crate::error::AppError::MetadataTooLarge { what, size, limit } => {
+            format!("The {} is {} bytes; the limit is {}", what, size, limit)
+        }
+        crate::error::AppError::PreconditionFailed(reason) => format!("Precondition failed: {}", reason),
+        crate::error::AppError::IdempotencyKeyReused(key) => {
+            format!("Idempotency key '{}' was used for a different request", key)
+        }
+        crate::error::AppError::IdempotencyKeyInUse(key) => {
+            format!("A request with idempotency key '{}' is in progress", key)
+        }
+        crate::error::AppError::TransactionFailed { step, reason } => {
+            format!("Operation {} failed, nothing was applied: {}", step, reason)
+        }
+        crate::error::AppError::RecomputeInProgress(bucket) => {
+            format!("The statistics of bucket '{}' are already being recomputed", bucket)
+        }
+        crate::error::AppError::OriginUnavailable(reason) => format!("Origin unavailable: {}", reason),
+        crate::error::AppError::NotImplemented(reason) => format!("Not implemented: {}", reason),
+        crate::error::AppError::MethodNotAllowed(reason) => format!("Method not allowed: {}", reason),
+        crate::error::AppError::RouteNotFound(reason) => format!("Not found: {}", reason),
         crate::error::AppError::StorageError(msg) => format!("Storage error: {}", msg),
         crate::error::AppError::IoError(e) => format!("I/O error: {}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing::engine;
+
+    #[test]
+    fn columns_are_measured_in_terminal_cells() {
+        // Each of these takes two cells
+        assert_eq!(pad_column("日本語", 8), "日本語  ");
+        assert_eq!(pad_column("🎉", 4), "🎉  ");
+        assert_eq!(pad_column("fits-exactly", 4), "fits-exactly");
+        assert_eq!(truncate_start("фото/日本語/🎉.txt", 9), "…/🎉.txt");
+        assert_eq!(truncate_start("short", 9), "short");
+        assert!(truncate_start("日本語/日本語/日本語", 10).width() <= 10);
+    }
+
+    #[test]
+    fn jobs_and_chunk_sizes_must_be_more_than_zero() {
+        let get = |args: &[&str]| {
+            Cli::try_parse_from(["freebucket", "get", "media/big.bin", "--endpoint", "http://localhost:1"].iter().chain(args))
+        };
+        assert!(get(&["--chunk-size", "1MiB", "--jobs", "8"]).is_ok());
+        for args in [&["--chunk-size", "0"][..], &["--chunk-size", "0KiB"], &["--jobs", "0"]] {
+            let err = get(args).err().unwrap().to_string();
+            assert!(err.contains("Invalid"), "{:?}: {}", args, err);
+        }
+        assert!(Cli::try_parse_from(["freebucket", "rm", "media/a", "-j", "0"]).is_err());
+        assert!(Cli::try_parse_from(["freebucket", "put", "a.txt", "media", "-j", "0"]).is_err());
+    }
+
+    #[test]
+    fn if_newer_needs_the_same_size_and_a_file_no_older() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("media", "local").unwrap();
+        storage
+            .put_object("media", "a.txt", b"hello", PutOptions::default())
+            .unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("a.txt");
+        let path_str = path.to_str().unwrap();
+        assert!(!local_copy_current(&storage, "media", "a.txt", path_str));
+
+        std::fs::write(&path, b"hello").unwrap();
+        assert!(local_copy_current(&storage, "media", "a.txt", path_str));
+        assert!(!local_copy_current(&storage, "media", "missing.txt", path_str));
+
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(3600)).unwrap();
+        assert!(!local_copy_current(&storage, "media", "a.txt", path_str));
+        std::fs::write(&path, b"hello!").unwrap();
+        assert!(!local_copy_current(&storage, "media", "a.txt", path_str));
+    }
+
+    #[test]
+    fn preserved_mtimes_make_synced_files_look_unchanged() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("media", "local").unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("a.txt");
+        let path_str = path.to_str().unwrap();
+        std::fs::write(&path, b"hello").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_577_934_245))
+            .unwrap();
+        let mtime = chrono::DateTime::<chrono::Utc>::from(std::fs::metadata(&path).unwrap().modified().unwrap());
+
+        // Uploaded now, the object looks newer than the file
+        storage
+            .put_object("media", "a.txt", b"hello", PutOptions::default())
+            .unwrap();
+        assert!(!local_copy_current(&storage, "media", "a.txt", path_str));
+        storage
+            .put_object("media", "a.txt", b"hello", PutOptions { source_mtime: Some(mtime), ..Default::default() })
+            .unwrap();
+        assert!(local_copy_current(&storage, "media", "a.txt", path_str));
+        assert_eq!(storage.get_object_meta("media", "a.txt").unwrap().modified(), mtime);
+    }
+
+    #[test]
+    fn if_changed_compares_the_file_hash_with_the_etag() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("media", "local").unwrap();
+        let meta = storage
+            .put_object("media", "a.txt", b"hello", PutOptions::default())
+            .unwrap();
+        assert_eq!(file_etag(b"hello"), meta.etag);
+        assert_ne!(file_etag(b"hello!"), meta.etag);
+    }
+
+    #[test]
+    fn uploads_keep_a_named_key_or_go_under_the_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.txt").to_str().unwrap().to_string();
+        let b = dir.path().join("b.txt").to_str().unwrap().to_string();
+        std::fs::write(&a, b"a").unwrap();
+        std::fs::write(&b, b"b").unwrap();
+
+        let plan = plan_upload(std::slice::from_ref(&a), "media/report.txt", None, &[]).unwrap();
+        assert_eq!(plan.bucket, "media");
+        assert_eq!(plan.tasks, [(a.clone(), "report.txt".to_string())]);
+        let plan = plan_upload(&[a.clone(), b.clone()], "media/in", None, &[]).unwrap();
+        assert_eq!(plan.tasks, [(a.clone(), "in/a.txt".to_string()), (b, "in/b.txt".to_string())]);
+        assert!(plan_upload(&[a], "media/", Some("text/plain\n"), &[]).is_err());
+    }
+}
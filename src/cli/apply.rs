@@ -0,0 +1,207 @@
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use super::{audited, format_error, CLI_ACTOR};
+use crate::error::AppError;
+use crate::models::{AuditAction, Manifest, TransactionRequest};
+use crate::storage::{ManifestChange, StorageEngine};
+
+/// Parse a YAML manifest (JSON works too) and the directory seed paths are
+/// relative to
+fn load_manifest(file: &str) -> Result<(Manifest, &Path), String> {
+    let text = std::fs::read_to_string(file).map_err(|e| format!("Cannot read '{}': {}", file, e))?;
+    let manifest = serde_yaml::from_str(&text).map_err(|e| format!("Invalid manifest '{}': {}", file, e))?;
+    let base_dir = Path::new(file).parent().unwrap_or(Path::new(""));
+    Ok((manifest, base_dir))
+}
+
+fn confirm_deletes(plan: &[ManifestChange]) -> bool {
+    let deletes = plan
+        .iter()
+        .filter(|c| matches!(c, ManifestChange::DeleteBucket { .. }))
+        .count();
+    if deletes == 0 {
+        return true;
+    }
+    if !std::io::stdin().is_terminal() {
+        eprintln!("✗ Refusing to delete {} bucket(s) without confirmation; pass --yes", deletes);
+        return false;
+    }
+    eprint!("Delete {} bucket(s) and everything in them? Type 'yes' to continue: ", deletes);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut answer);
+    answer.trim() == "yes"
+}
+
+/// Make one change, recording it in the audit log
+fn apply_change(storage: &StorageEngine, change: &ManifestChange) -> Result<(), AppError> {
+    let applied = storage.apply_manifest_change(change);
+    match change.audit(CLI_ACTOR) {
+        Some(audit) => storage.audited(audit, applied),
+        None => applied,
+    }
+}
+
+/// `freebucket apply`: show the plan, then carry it out unless it's a dry run
+pub(super) fn run_apply(storage: &StorageEngine, file: &str, dry_run: bool, prune: bool, yes: bool) {
+    let default_region = crate::config::Config::default().default_region;
+    let plan = load_manifest(file).and_then(|(manifest, base_dir)| {
+        storage
+            .plan_manifest(&manifest, base_dir, &default_region, prune)
+            .map_err(|e| format_error(&e))
+    });
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(msg) => {
+            eprintln!("✗ {}", msg);
+            std::process::exit(1);
+        }
+    };
+
+    if plan.is_empty() {
+        println!("✓ Everything already matches {}", file);
+        return;
+    }
+    if dry_run {
+        for change in &plan {
+            println!("  {}", change);
+        }
+        println!("\n{} change(s) would be made (dry run)", plan.len());
+        return;
+    }
+    if !yes && !confirm_deletes(&plan) {
+        eprintln!("Aborted.");
+        std::process::exit(1);
+    }
+
+    for change in &plan {
+        match apply_change(storage, change) {
+            Ok(()) => println!("✓ {}", change),
+            Err(e) => {
+                eprintln!("✗ {}: {}", change, format_error(&e));
+                std::process::exit(1);
+            }
+        }
+    }
+    println!("\n{} change(s) applied", plan.len());
+}
+
+/// `serve --apply`: bring the instance in line with a manifest before
+/// serving. Buckets missing from it are left alone. With `keep_objects`
+/// (`serve --init-manifest`), seed files are only uploaded to keys that
+/// don't exist yet.
+pub fn apply_at_startup(
+    storage: &StorageEngine,
+    file: &str,
+    default_region: &str,
+    keep_objects: bool,
+) -> Result<(), String> {
+    let (manifest, base_dir) = load_manifest(file)?;
+    let mut plan = storage
+        .plan_manifest(&manifest, base_dir, default_region, false)
+        .map_err(|e| format_error(&e))?;
+    if keep_objects {
+        plan.retain(|change| match change {
+            ManifestChange::UploadSeed { bucket, key, .. } => storage.get_object_meta(bucket, key).is_err(),
+            _ => true,
+        });
+    }
+    for change in &plan {
+        apply_change(storage, change).map_err(|e| format!("{}: {}", change, format_error(&e)))?;
+        tracing::info!("Manifest: {}", change);
+    }
+    tracing::info!("Applied {} ({} change(s))", file, plan.len());
+    Ok(())
+}
+
+/// `FREEBUCKET_DEFAULT_BUCKETS`: create the listed buckets that don't exist
+/// yet before serving
+pub fn create_default_buckets(storage: &StorageEngine, names: &[String], default_region: &str) -> Result<(), String> {
+    for name in names {
+        match storage.create_bucket(name, default_region) {
+            Err(AppError::BucketAlreadyExists(_)) => {}
+            created => {
+                audited(storage, AuditAction::BucketCreate, name, created)
+                    .map_err(|e| format!("{}: {}", name, format_error(&e)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `freebucket apply-txn`: apply a transaction file all or nothing
+pub(super) fn run_apply_txn(storage: &StorageEngine, file: &str) {
+    let request = std::fs::read_to_string(file)
+        .map_err(|e| format!("Cannot read '{}': {}", file, e))
+        .and_then(|text| {
+            serde_json::from_str::<TransactionRequest>(&text).map_err(|e| format!("Invalid transaction '{}': {}", file, e))
+        });
+    let request = match request {
+        Ok(request) => request,
+        Err(msg) => {
+            eprintln!("✗ {}", msg);
+            std::process::exit(1);
+        }
+    };
+
+    match storage.apply_transaction(&request) {
+        Ok(report) => {
+            for step in &report.steps {
+                println!("✓ {} {}/{}", step.op, step.bucket, step.key);
+            }
+            println!("\nTransaction {} committed ({} step(s))", report.id, report.steps.len());
+        }
+        Err(e) => {
+            eprintln!("✗ {}", format_error(&e));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `freebucket export-config`
+pub(super) fn run_export(storage: &StorageEngine, output: Option<&str>) {
+    let yaml = match serde_yaml::to_string(&storage.export_manifest()) {
+        Ok(yaml) => yaml,
+        Err(e) => {
+            eprintln!("✗ Cannot serialize manifest: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, yaml) {
+                eprintln!("✗ Cannot write '{}': {}", path, e);
+                std::process::exit(1);
+            }
+            println!("✓ Wrote {}", path);
+        }
+        None => print!("{}", yaml),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::PutOptions;
+
+    #[test]
+    fn default_buckets_are_created_once() {
+        let (_dir, storage) = crate::storage::testing::engine();
+        storage.create_bucket("photos", "eu-west-1").unwrap();
+        storage
+            .put_object("photos", "a.txt", b"a", PutOptions::default())
+            .unwrap();
+
+        let names = ["photos".to_string(), "fixtures".to_string()];
+        create_default_buckets(&storage, &names, "local").unwrap();
+        create_default_buckets(&storage, &names, "local").unwrap();
+        let photos = storage.get_bucket("photos").unwrap();
+        assert_eq!((photos.region.as_str(), photos.object_count), ("eu-west-1", 1));
+        assert_eq!(storage.get_bucket("fixtures").unwrap().region, "local");
+
+        let err = create_default_buckets(&storage, &["Bad_Name".to_string()], "local").unwrap_err();
+        assert!(err.starts_with("Bad_Name: "), "{}", err);
+        assert!(storage.get_bucket("Bad_Name").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::path::Path;
+
+use freebucket::client::{ClientError, FreeBucketClient};
+
+use super::doctor::local_server_running;
+use super::{block_on, format_error};
+use crate::config::Config;
+use crate::models::BackupReport;
+use crate::size::human_readable_size;
+use crate::storage::{restore_backup, verify_backup, StorageEngine};
+
+fn print_report(report: &BackupReport) {
+    let objects: u64 = report.buckets.iter().map(|b| b.object_count).sum();
+    let size: u64 = report.buckets.iter().map(|b| b.total_size).sum();
+    println!("✓ Backup {} written to {}", report.id, report.path);
+    println!(
+        "  Buckets: {}  Objects: {}  Size: {}",
+        report.buckets.len(),
+        objects,
+        human_readable_size(size)
+    );
+    println!(
+        "  Files:   {} ({})  Writes paused: {:.2} ms",
+        report.file_count,
+        human_readable_size(report.total_size),
+        report.write_pause_ms
+    );
+}
+
+/// Back up the data dir directly. A server running on it would keep
+/// writing through the cut, so point at `--endpoint` when one answers.
+pub(super) fn run_backup(storage: &StorageEngine, dir: &str) {
+    let config = Config::default();
+    if local_server_running(&config) {
+        eprintln!(
+            "⚠ A server is running at {}:{}; if it uses this data dir, its writes are not paused for the backup. \
+             Use --endpoint http://{}:{} to have it take the backup.",
+            config.host, config.port, config.host, config.port
+        );
+    }
+    match storage.create_backup(Path::new(dir)) {
+        Ok(report) => print_report(&report),
+        Err(e) => {
+            eprintln!("✗ {}", format_error(&e));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Have a running server take the backup, into a directory on its machine
+pub(super) fn run_remote_backup(endpoint: &str, dir: &str) {
+    let fail = |msg: String| -> ! {
+        eprintln!("✗ {}", msg);
+        std::process::exit(1);
+    };
+    let client = FreeBucketClient::new(endpoint).unwrap_or_else(|e| fail(e.to_string()));
+    // The server resolves a relative path against its own working directory
+    let dir = match std::env::current_dir() {
+        Ok(cwd) => cwd.join(dir).display().to_string(),
+        Err(_) => dir.to_string(),
+    };
+    // A large backup takes a while to checksum, so wait as long as it takes
+    match block_on(client.with_timeout(None).backup(&dir)) {
+        Ok(report) => print_report(&report),
+        Err(ClientError::Api { status, error }) => fail(format!("Backup failed ({}): {}", status, error.message)),
+        Err(e @ ClientError::InvalidResponse(_)) => fail(format!("Unreadable answer from {}: {}", endpoint, e)),
+        Err(e) => fail(format!("Cannot reach {}: {}", endpoint, e)),
+    }
+}
+
+/// Re-check every file of a backup against its manifest
+pub(super) fn run_verify(dir: &str) {
+    let check = match verify_backup(Path::new(dir)) {
+        Ok(check) => check,
+        Err(e) => {
+            eprintln!("✗ {}", format_error(&e));
+            std::process::exit(1);
+        }
+    };
+    if check.is_intact() {
+        println!("✓ Backup intact: {} file(s) match their checksums", check.checked);
+        return;
+    }
+    for path in &check.missing {
+        println!("  missing  {}", path);
+    }
+    for path in &check.mismatched {
+        println!("  changed  {}", path);
+    }
+    eprintln!(
+        "✗ Backup damaged: {} of {} file(s) missing, {} changed",
+        check.missing.len(),
+        check.checked,
+        check.mismatched.len()
+    );
+    std::process::exit(1);
+}
+
+/// Recreate a data dir from a backup, then open it and recount every bucket
+/// against the backup's manifest
+pub(super) fn run_restore(backup: &str, data_dir: &str) {
+    let fail = |msg: String| -> ! {
+        eprintln!("✗ {}", msg);
+        std::process::exit(1);
+    };
+    let manifest = restore_backup(Path::new(backup), Path::new(data_dir)).unwrap_or_else(|e| fail(format_error(&e)));
+    let storage = StorageEngine::new(data_dir, Config::default().storage_options())
+        .unwrap_or_else(|e| fail(format!("The restored data dir does not open: {}", format_error(&e))));
+    let problems = storage.compare_with_backup(&manifest);
+    if !problems.is_empty() {
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+        fail(format!("Restored data dir {} does not match backup {}", data_dir, manifest.id));
+    }
+    println!(
+        "✓ Restored {} bucket(s) from backup {} into {}",
+        manifest.buckets.len(),
+        manifest.id,
+        data_dir
+    );
+}
@@ -0,0 +1,198 @@
+//! `cp --recursive` and `mv --recursive`: copy or move every object under
+//! a prefix. Against a server this is one bulk job, followed until it
+//! finishes, instead of a request per key.
+
+use std::io::IsTerminal;
+use std::time::Duration;
+
+use freebucket::client::{ClientError, FreeBucketClient};
+
+use super::{block_on, format_error};
+use crate::models::{BulkError, BulkJob, BulkJobStatus, BulkOperation, BulkRequest};
+use crate::storage::StorageEngine;
+
+/// How often a running job's progress is asked for
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+fn fail(msg: String) -> ! {
+    eprintln!("✗ {}", msg);
+    std::process::exit(1);
+}
+
+/// The request for `bucket/prefix` arguments; the prefix may be empty
+fn bulk_request(operation: BulkOperation, source: &str, destination: &str) -> Result<BulkRequest, String> {
+    let split = |path: &str| match path.split_once('/') {
+        Some((bucket, prefix)) if !bucket.is_empty() => Ok((bucket.to_string(), prefix.to_string())),
+        None if !path.is_empty() => Ok((path.to_string(), String::new())),
+        _ => Err(format!("'{}' must be in format: bucket/prefix", path)),
+    };
+    let (source_bucket, source_prefix) = split(source)?;
+    let (destination_bucket, destination_prefix) = split(destination)?;
+    Ok(BulkRequest {
+        operation,
+        source_bucket,
+        source_prefix,
+        destination_bucket,
+        destination_prefix,
+        keep_source_prefix: false,
+    })
+}
+
+/// Run the transfer in the data dir, one key after another
+pub(super) fn run_local(storage: &StorageEngine, operation: BulkOperation, source: &str, destination: &str) {
+    let req = bulk_request(operation, source, destination).unwrap_or_else(|msg| fail(msg));
+    match local_transfer(storage, &req) {
+        Ok((total, errors)) => finish(&req, total, &errors),
+        Err(e) => fail(format_error(&e)),
+    }
+}
+
+/// The keys transferred, and the ones that failed
+fn local_transfer(
+    storage: &StorageEngine,
+    req: &BulkRequest,
+) -> Result<(usize, Vec<BulkError>), crate::error::AppError> {
+    storage.get_bucket(&req.source_bucket)?;
+    let plan = storage.plan_bulk_transfer(req)?;
+    let mut errors = Vec::new();
+    for (src_key, dst_key) in &plan {
+        if let Err(e) = storage.bulk_transfer_object(req, src_key, dst_key) {
+            let (_, code, message) = e.parts();
+            errors.push(BulkError {
+                key: src_key.clone(),
+                code: code.to_string(),
+                message,
+            });
+        }
+    }
+    storage.finish_bulk_transfer(req)?;
+    Ok((plan.len(), errors))
+}
+
+/// Have the server at `endpoint` run the transfer as a bulk job
+pub(super) fn run_remote(endpoint: &str, operation: BulkOperation, source: &str, destination: &str) {
+    let req = bulk_request(operation, source, destination).unwrap_or_else(|msg| fail(msg));
+    let client = FreeBucketClient::new(endpoint).unwrap_or_else(|e| fail(e.to_string()));
+    // The progress line is redrawn in place, so only on a terminal
+    let progress = std::io::stderr().is_terminal();
+    let job = block_on(follow_job(&client, &req, |job| {
+        if progress {
+            eprint!("\r  {} of {} object(s)   ", job.done, job.total);
+        }
+    }));
+    if progress {
+        eprint!("\r{}\r", " ".repeat(60));
+    }
+    match job {
+        Ok(job) if job.status == BulkJobStatus::Failed => {
+            let reason = job.errors.first().map(|e| e.message.as_str()).unwrap_or("no reason given");
+            fail(format!("Bulk job {} failed: {}", job.id, reason))
+        }
+        Ok(job) => finish(&req, job.total, &job.errors),
+        Err(e) => fail(format!("{}: {}", endpoint, e)),
+    }
+}
+
+/// Start the job and poll it until it's no longer running, passing each
+/// state to `progress`
+async fn follow_job(
+    client: &FreeBucketClient,
+    req: &BulkRequest,
+    progress: impl Fn(&BulkJob),
+) -> Result<BulkJob, ClientError> {
+    let mut job = client.start_bulk(req).await?;
+    while job.status == BulkJobStatus::Running {
+        progress(&job);
+        tokio::time::sleep(POLL_INTERVAL).await;
+        job = client.bulk_job(&job.id).await?;
+    }
+    Ok(job)
+}
+
+/// Print the outcome; keys that failed make the process exit non-zero
+fn finish(req: &BulkRequest, total: usize, errors: &[BulkError]) {
+    let verb = match req.operation {
+        BulkOperation::Copy => "Copied",
+        BulkOperation::Move => "Moved",
+    };
+    println!(
+        "✓ {} {} of {} object(s) from {}/{} to {}/{}",
+        verb,
+        total - errors.len(),
+        total,
+        req.source_bucket,
+        req.source_prefix,
+        req.destination_bucket,
+        req.destination_prefix
+    );
+    if !errors.is_empty() {
+        eprintln!("{} failure(s):", errors.len());
+        for error in errors {
+            eprintln!("  ✗ {}: {} ({})", error.key, error.message, error.code);
+        }
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::PutOptions;
+    use crate::testing::TestServer;
+
+    fn keys(storage: &StorageEngine, bucket: &str) -> Vec<String> {
+        let listing = storage.list_objects(bucket, "", None, 1000).unwrap();
+        listing.objects.into_iter().map(|o| o.key).collect()
+    }
+
+    fn fill(storage: &StorageEngine) {
+        storage.create_bucket("raw-data", "local").unwrap();
+        storage.create_bucket("archive", "local").unwrap();
+        for key in ["raw/a.csv", "raw/sub/b.csv", "other.txt"] {
+            storage
+                .put_object("raw-data", key, key.as_bytes(), PutOptions::default())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn arguments_name_a_bucket_and_a_prefix() {
+        let req = bulk_request(BulkOperation::Move, "raw-data/raw/", "archive").unwrap();
+        assert_eq!((req.source_bucket.as_str(), req.source_prefix.as_str()), ("raw-data", "raw/"));
+        assert_eq!((req.destination_bucket.as_str(), req.destination_prefix.as_str()), ("archive", ""));
+        assert!(bulk_request(BulkOperation::Copy, "/raw", "archive").is_err());
+        assert!(bulk_request(BulkOperation::Copy, "raw-data", "").is_err());
+    }
+
+    #[test]
+    fn moves_a_prefix_in_the_data_dir() {
+        let (_dir, storage) = crate::storage::testing::engine();
+        fill(&storage);
+        let req = bulk_request(BulkOperation::Move, "raw-data/raw/", "archive/imported/").unwrap();
+        let (total, errors) = local_transfer(&storage, &req).unwrap();
+        assert_eq!((total, errors.len()), (2, 0));
+        assert_eq!(keys(&storage, "archive"), ["imported/a.csv", "imported/sub/b.csv"]);
+        assert_eq!(keys(&storage, "raw-data"), ["other.txt"]);
+        assert_eq!(storage.get_bucket("archive").unwrap().object_count, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copies_a_prefix_as_one_job_on_the_server() {
+        let server = TestServer::new();
+        fill(&server.state.storage);
+        let client = FreeBucketClient::new(&server.listen().await).unwrap();
+
+        let req = bulk_request(BulkOperation::Copy, "raw-data/raw/", "archive/imported/").unwrap();
+        let job = follow_job(&client, &req, |_| {}).await.unwrap();
+        assert_eq!(job.status, BulkJobStatus::Completed);
+        assert_eq!((job.done, job.total, job.errors.len()), (2, 2, 0));
+        let storage = &server.state.storage;
+        assert_eq!(keys(storage, "archive"), ["imported/a.csv", "imported/sub/b.csv"]);
+        assert_eq!(keys(storage, "raw-data"), ["other.txt", "raw/a.csv", "raw/sub/b.csv"]);
+
+        // A bucket that doesn't exist is refused before a job starts
+        let req = bulk_request(BulkOperation::Move, "raw-data/raw/", "missing/").unwrap();
+        let err = follow_job(&client, &req, |_| {}).await.unwrap_err();
+        assert_eq!(err.code(), Some("NoSuchBucket"));
+    }
+}
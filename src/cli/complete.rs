@@ -0,0 +1,213 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use clap::CommandFactory;
+
+use super::doctor::probe_client;
+use super::{block_on, Cli};
+
+/// Candidates printed at most, so a huge bucket still completes instantly
+const MAX_CANDIDATES: usize = 100;
+
+/// Print shell completion candidates, one per line. `keys` lists one level
+/// under the prefix, with folders ending in `/`. Failures print nothing, so
+/// a broken data dir or an unreachable server never garbles the prompt.
+pub(super) fn run_complete(data_dir: &str, endpoint: Option<&str>, kind: &str, bucket: Option<&str>, prefix: &str) {
+    let candidates = match (kind, endpoint, bucket) {
+        ("buckets", None, _) => local_buckets(data_dir),
+        ("buckets", Some(endpoint), _) => remote_buckets(endpoint),
+        ("keys", None, Some(bucket)) => local_keys(data_dir, bucket, prefix),
+        ("keys", Some(endpoint), Some(bucket)) => remote_keys(endpoint, bucket, prefix),
+        _ => None,
+    };
+    // A shell that stops reading early is not an error worth a panic
+    let mut out = std::io::stdout().lock();
+    for candidate in candidates.unwrap_or_default().into_iter().take(MAX_CANDIDATES) {
+        if writeln!(out, "{}", candidate).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads the data dir directly: opening the storage engine would reconcile
+/// and index every bucket first
+fn local_buckets(data_dir: &str) -> Option<Vec<String>> {
+    let mut names: Vec<String> = fs::read_dir(data_dir)
+        .ok()?
+        .flatten()
+        .filter(|e| e.path().join(".bucket_meta.json").exists())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    names.sort();
+    Some(names)
+}
+
+/// Only the directory the prefix ends in is read, never the whole bucket
+fn local_keys(data_dir: &str, bucket: &str, prefix: &str) -> Option<Vec<String>> {
+    if bucket.starts_with('.') || bucket.contains(['/', '\\']) || prefix.split('/').any(|s| s == "..") {
+        return None;
+    }
+    let (dir, partial) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+        None => ("", prefix),
+    };
+    let path = Path::new(data_dir).join(bucket).join("objects").join(dir);
+    let mut keys: Vec<String> = fs::read_dir(path)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(partial) {
+                return None;
+            }
+            let slash = if entry.file_type().ok()?.is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    keys.sort();
+    Some(keys)
+}
+
+fn remote_buckets(endpoint: &str) -> Option<Vec<String>> {
+    let client = probe_client(endpoint).ok()?;
+    let buckets = block_on(client.list_buckets()).ok()?;
+    Some(buckets.into_iter().map(|b| b.name).collect())
+}
+
+fn remote_keys(endpoint: &str, bucket: &str, prefix: &str) -> Option<Vec<String>> {
+    let client = probe_client(endpoint).ok()?;
+    let listing = block_on(client.list_objects_page(bucket, prefix, Some("/"), MAX_CANDIDATES as u32, None)).ok()?;
+    let objects = listing.objects.into_iter().map(|o| o.key);
+    let mut keys: Vec<String> = listing.common_prefixes.into_iter().chain(objects).collect();
+    keys.sort();
+    Some(keys)
+}
+
+/// Subcommands a completion script offers, aliases included
+fn command_names() -> String {
+    Cli::command()
+        .get_subcommands()
+        .filter(|c| !c.is_hide_set())
+        .flat_map(|c| std::iter::once(c.get_name()).chain(c.get_visible_aliases()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Print a completion script for `shell`
+pub(super) fn print_completions(shell: &str) {
+    let script = match shell {
+        "bash" => BASH,
+        "zsh" => ZSH,
+        _ => FISH,
+    };
+    print!("{}", script.replace("@COMMANDS@", &command_names()));
+}
+
+// The scripts pass `--data-dir` through and leave FREEBUCKET_DATA_DIR and
+// FREEBUCKET_ENDPOINT to the environment. Bucket-only arguments get bucket
+// names; bucket/key arguments get buckets, then keys one level at a time.
+
+const BASH: &str = r#"# freebucket completion for bash; source it from ~/.bashrc
+_freebucket_paths() {
+    local IFS=$'\n' words
+    if [[ $cur == */* ]]; then
+        local bucket=${cur%%/*}
+        words=$(freebucket "${args[@]}" __complete keys "$bucket" "${cur#*/}" 2>/dev/null | sed "s|^|$bucket/|")
+    else
+        words=$(freebucket "${args[@]}" __complete buckets 2>/dev/null | sed 's|$|/|')
+    fi
+    COMPREPLY+=($(compgen -W "$words" -- "$cur"))
+    compopt -o nospace
+}
+
+_freebucket() {
+    local cur=${COMP_WORDS[COMP_CWORD]} cmd="" i
+    local -a args=()
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case ${COMP_WORDS[i]} in
+            --data-dir) args=(--data-dir "${COMP_WORDS[i+1]}"); ((i++)) ;;
+            -*) ;;
+            *) [[ -z $cmd ]] && cmd=${COMP_WORDS[i]} ;;
+        esac
+    done
+    COMPREPLY=()
+    if [[ -z $cmd ]]; then
+        COMPREPLY=($(compgen -W "@COMMANDS@" -- "$cur"))
+        return
+    fi
+    [[ $cur == -* ]] && return
+    case $cmd in
+        info|list|ls|largest|inventory)
+            local IFS=$'\n'
+            COMPREPLY=($(compgen -W "$(freebucket "${args[@]}" __complete buckets 2>/dev/null)" -- "$cur")) ;;
+        get|remove|rm|exists) _freebucket_paths ;;
+        put|cp) COMPREPLY=($(compgen -f -- "$cur")); _freebucket_paths ;;
+    esac
+}
+complete -F _freebucket freebucket
+"#;
+
+const ZSH: &str = r#"#compdef freebucket
+# freebucket completion for zsh; save as _freebucket in a directory on $fpath
+_freebucket_paths() {
+    if [[ $cur == */* ]]; then
+        local bucket=${cur%%/*}
+        compadd -S '' -p "$bucket/" -- ${(f)"$(freebucket $args __complete keys $bucket ${cur#*/} 2>/dev/null)"}
+    else
+        compadd -S '/' -- ${(f)"$(freebucket $args __complete buckets 2>/dev/null)"}
+    fi
+}
+
+_freebucket() {
+    local cur=$words[CURRENT] cmd i
+    local -a args
+    for ((i = 2; i < CURRENT; i++)); do
+        case $words[i] in
+            --data-dir) args=(--data-dir $words[i+1]); ((i++)) ;;
+            -*) ;;
+            *) [[ -z $cmd ]] && cmd=$words[i] ;;
+        esac
+    done
+    if [[ -z $cmd ]]; then
+        compadd -- @COMMANDS@
+        return
+    fi
+    [[ $cur == -* ]] && return
+    case $cmd in
+        info|list|ls|largest|inventory) compadd -- ${(f)"$(freebucket $args __complete buckets 2>/dev/null)"} ;;
+        get|remove|rm|exists) _freebucket_paths ;;
+        put|cp) _files; _freebucket_paths ;;
+    esac
+}
+
+_freebucket "$@"
+"#;
+
+const FISH: &str = r#"# freebucket completion for fish; save as ~/.config/fish/completions/freebucket.fish
+function __freebucket_args
+    set -l tokens (commandline -opc)
+    if set -l i (contains -i -- --data-dir $tokens)
+        echo --data-dir
+        echo $tokens[(math $i + 1)]
+    end
+end
+
+function __freebucket_paths
+    set -l cur (commandline -ct)
+    set -l args (__freebucket_args)
+    if string match -q '*/*' -- $cur
+        set -l parts (string split -m1 / -- $cur)
+        freebucket $args __complete keys $parts[1] $parts[2] 2>/dev/null | string replace -r '^' "$parts[1]/"
+    else
+        freebucket $args __complete buckets 2>/dev/null | string replace -r '$' /
+    end
+end
+
+complete -c freebucket -f
+complete -c freebucket -n __fish_use_subcommand -a "@COMMANDS@"
+complete -c freebucket -n "__fish_seen_subcommand_from info list ls largest inventory" -a "(freebucket (__freebucket_args) __complete buckets 2>/dev/null)"
+complete -c freebucket -n "__fish_seen_subcommand_from get remove rm exists" -a "(__freebucket_paths)"
+complete -c freebucket -n "__fish_seen_subcommand_from put cp" -F -a "(__freebucket_paths)"
+"#;
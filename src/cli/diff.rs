@@ -0,0 +1,230 @@
+use std::io::Write;
+
+use freebucket::client::FreeBucketClient;
+use sha2::{Digest, Sha256};
+
+use super::{block_on, format_error};
+use crate::config::Config;
+use crate::diff::{diff_listings, local_listing, Listed, Side};
+use crate::models::{DiffChange, DiffEntry, DiffSide, DiffSummary, ObjectMeta};
+use crate::size::human_readable_size;
+use crate::storage::StorageEngine;
+
+/// Objects asked for per page of a remote listing
+const REMOTE_PAGE_KEYS: u32 = 1000;
+
+/// Exit status when the sides differ, as `diff` has it
+const EXIT_DIFFERENT: i32 = 1;
+/// Exit status when the comparison couldn't be made
+const EXIT_FAILED: i32 = 2;
+
+/// `bucket` or `bucket/prefix`
+fn parse_side(spec: &str) -> DiffSide {
+    match spec.split_once('/') {
+        Some((bucket, prefix)) => DiffSide {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+        },
+        None => DiffSide {
+            bucket: spec.to_string(),
+            prefix: String::new(),
+        },
+    }
+}
+
+/// A bucket's objects on a server, a page at a time, in key order
+struct RemoteListing<'a> {
+    client: &'a FreeBucketClient,
+    side: &'a DiffSide,
+    page: std::vec::IntoIter<ObjectMeta>,
+    /// Where the next page starts; `None` once the last one was fetched
+    next: Option<Option<String>>,
+}
+
+impl Iterator for RemoteListing<'_> {
+    type Item = Result<Listed, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(meta) = self.page.next() {
+                let key = meta.key[self.side.prefix.len()..].to_string();
+                return Some(Ok((key, meta)));
+            }
+            let token = self.next.take()?;
+            let fetched = block_on(self.client.list_objects_page(
+                &self.side.bucket,
+                &self.side.prefix,
+                None,
+                REMOTE_PAGE_KEYS,
+                token.as_deref(),
+            ));
+            match fetched {
+                Ok(page) => {
+                    self.next = page.next_continuation_token.filter(|_| page.is_truncated).map(Some);
+                    self.page = page.objects.into_iter();
+                }
+                Err(e) => return Some(Err(format!("Listing {} failed: {}", self.side.bucket, e))),
+            }
+        }
+    }
+}
+
+/// Where one side of the diff is read from
+struct Source {
+    side: DiffSide,
+    client: Option<FreeBucketClient>,
+}
+
+impl Source {
+    fn listing<'a>(
+        &'a self,
+        storage: Option<&'a StorageEngine>,
+    ) -> Result<Box<dyn Iterator<Item = Result<Listed, String>> + 'a>, String> {
+        match (&self.client, storage) {
+            (Some(client), _) => {
+                // A missing bucket would otherwise look like an empty one
+                block_on(client.list_objects_page(&self.side.bucket, "", None, 1, None))
+                    .map_err(|e| format!("Bucket {}: {}", self.side.bucket, e))?;
+                Ok(Box::new(RemoteListing {
+                    client,
+                    side: &self.side,
+                    page: Vec::new().into_iter(),
+                    next: Some(None),
+                }))
+            }
+            (None, Some(storage)) => {
+                let listing = local_listing(storage, &self.side).map_err(|e| format_error(&e))?;
+                Ok(Box::new(listing.map(|listed| listed.map_err(|e| format_error(&e)))))
+            }
+            (None, None) => unreachable!("local sides open the storage engine"),
+        }
+    }
+
+    /// SHA-256 of an object's content. Remote objects are downloaded whole.
+    fn content_hash(&self, storage: Option<&StorageEngine>, meta: &ObjectMeta) -> Result<String, String> {
+        match (&self.client, storage) {
+            (Some(client), _) => {
+                let data = block_on(client.get_object(&self.side.bucket, &meta.key))
+                    .map_err(|e| format!("Reading {}/{} failed: {}", self.side.bucket, meta.key, e))?;
+                Ok(hex::encode(Sha256::digest(&data)))
+            }
+            (None, Some(storage)) => storage
+                .content_sha256(&self.side.bucket, &meta.key)
+                .map_err(|e| format_error(&e)),
+            (None, None) => unreachable!("local sides open the storage engine"),
+        }
+    }
+}
+
+fn print_entry(out: &mut impl Write, entry: &DiffEntry) -> std::io::Result<()> {
+    let size = |size: Option<u64>| size.map(human_readable_size).unwrap_or_default();
+    match entry.change {
+        DiffChange::Added => writeln!(out, "+ {}  {}", entry.key, size(entry.right_size)),
+        DiffChange::Removed => writeln!(out, "- {}  {}", entry.key, size(entry.left_size)),
+        DiffChange::Changed if entry.left_size == entry.right_size => {
+            writeln!(out, "~ {}  {} (content differs)", entry.key, size(entry.left_size))
+        }
+        DiffChange::Changed => writeln!(
+            out,
+            "~ {}  {} → {}",
+            entry.key,
+            size(entry.left_size),
+            size(entry.right_size)
+        ),
+    }
+}
+
+fn print_summary(out: &mut impl Write, summary: &DiffSummary) -> std::io::Result<()> {
+    let hashed = match summary.hashed {
+        0 => String::new(),
+        n => format!(", {} compared by content", n),
+    };
+    if summary.differences() == 0 {
+        return writeln!(out, "✓ No differences ({} object(s){})", summary.left_objects, hashed);
+    }
+    writeln!(
+        out,
+        "{} added, {} removed, {} changed, {} unchanged (left {} object(s), right {}{})",
+        summary.added,
+        summary.removed,
+        summary.changed,
+        summary.unchanged,
+        summary.left_objects,
+        summary.right_objects,
+        hashed
+    )
+}
+
+/// Compare two buckets or prefixes, each in the data dir or on a server,
+/// and print the keys added, removed, and changed from left to right.
+/// Exits 0 when they match, 1 when they differ, and 2 when they couldn't
+/// be compared.
+pub(super) fn run_diff(
+    data_dir: &str,
+    left: &str,
+    right: &str,
+    left_endpoint: Option<&str>,
+    right_endpoint: Option<&str>,
+    json: bool,
+) {
+    let fail = |msg: String| -> ! {
+        eprintln!("✗ {}", msg);
+        std::process::exit(EXIT_FAILED);
+    };
+    let source = |spec: &str, endpoint: Option<&str>| Source {
+        side: parse_side(spec),
+        client: endpoint.map(|endpoint| {
+            FreeBucketClient::new(endpoint).unwrap_or_else(|e| fail(format!("{}: {}", endpoint, e)))
+        }),
+    };
+    let (left, right) = (source(left, left_endpoint), source(right, right_endpoint));
+    for side in [&left.side, &right.side] {
+        if side.bucket.is_empty() {
+            fail("Each side must name a bucket, as bucket or bucket/prefix".to_string());
+        }
+    }
+
+    // Only opened when a side is local, so comparing two servers leaves
+    // the data dir alone
+    let storage = (left.client.is_none() || right.client.is_none()).then(|| {
+        StorageEngine::new(data_dir, Config::default().storage_options())
+            .unwrap_or_else(|e| fail(format!("Failed to open storage at '{}': {}", data_dir, format_error(&e))))
+    });
+    let storage = storage.as_ref();
+    let left_listing = left.listing(storage).unwrap_or_else(|e| fail(e));
+    let right_listing = right.listing(storage).unwrap_or_else(|e| fail(e));
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let result = diff_listings(
+        left_listing,
+        right_listing,
+        |side, meta| match side {
+            Side::Left => left.content_hash(storage, meta),
+            Side::Right => right.content_hash(storage, meta),
+        },
+        |entry| {
+            let written = match json {
+                true => serde_json::to_writer(&mut out, &entry)
+                    .map_err(std::io::Error::from)
+                    .and_then(|_| out.write_all(b"\n")),
+                false => print_entry(&mut out, &entry),
+            };
+            written.map_err(|_| "stdout closed".to_string())
+        },
+    );
+    let summary = result.unwrap_or_else(|e| {
+        let _ = out.flush();
+        fail(e)
+    });
+    // Nothing is left to say to a reader that went away
+    let _ = match json {
+        true => serde_json::to_writer(&mut out, &serde_json::json!({ "summary": summary }))
+            .map_err(std::io::Error::from)
+            .and_then(|_| out.write_all(b"\n")),
+        false => print_summary(&mut out, &summary),
+    };
+    let _ = out.flush();
+    if summary.differences() > 0 {
+        std::process::exit(EXIT_DIFFERENT);
+    }
+}
@@ -0,0 +1,588 @@
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use freebucket::client::{ClientError, FreeBucketClient};
+
+use super::block_on;
+use crate::config::Config;
+use crate::instance_lock::{self, LockState};
+use crate::models::Bucket;
+use crate::size::human_readable_size;
+use crate::storage::{metadata_size, read_stats_journal, replay_stats_journal, MetadataLimits};
+
+/// Below this much free space in the data dir the check warns
+const LOW_DISK_SPACE: u64 = 1024 * 1024 * 1024;
+/// Clock difference S3 clients start tripping over (SigV4 allows 15 minutes)
+const MAX_CLOCK_SKEW: i64 = 5 * 60;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Objects named when metadata is over the limits; the rest are counted
+const SHOWN_OVERSIZED: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Collects check results and prints each one as it comes in
+#[derive(Default)]
+struct Report {
+    warnings: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn check(&mut self, status: Status, name: &str, detail: &str, hint: Option<&str>) {
+        let mark = match status {
+            Status::Pass => "✓",
+            Status::Warn => {
+                self.warnings += 1;
+                "⚠"
+            }
+            Status::Fail => {
+                self.failures += 1;
+                "✗"
+            }
+        };
+        println!("  {} {:<18} {}", mark, name, detail);
+        if let (Some(hint), true) = (hint, status != Status::Pass) {
+            println!("    {:<18} → {}", "", hint);
+        }
+    }
+
+    fn pass(&mut self, name: &str, detail: &str) {
+        self.check(Status::Pass, name, detail, None);
+    }
+
+    fn warn(&mut self, name: &str, detail: &str, hint: &str) {
+        self.check(Status::Warn, name, detail, Some(hint));
+    }
+
+    fn fail(&mut self, name: &str, detail: &str, hint: &str) {
+        self.check(Status::Fail, name, detail, Some(hint));
+    }
+
+    /// Print the summary; failures make the process exit non-zero
+    fn finish(self) {
+        println!();
+        println!("{} failure(s), {} warning(s)", self.failures, self.warnings);
+        if self.failures > 0 {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Check that this machine can run a server on `data_dir`, `host`, and `port`
+pub(super) fn run_doctor(data_dir: &str, config: &Config) {
+    println!("FreeBucket doctor — checking the local environment");
+    println!();
+    let mut report = Report::default();
+
+    let dir = Path::new(data_dir);
+    if check_data_dir(&mut report, dir) {
+        check_instance(&mut report, dir);
+        check_disk_space(&mut report, dir);
+        check_filesystem(&mut report, dir);
+        check_bucket_stats(&mut report, dir);
+        check_object_metadata(&mut report, dir, &config.metadata_limits);
+    }
+    check_port(&mut report, config);
+    check_clock(&mut report);
+    report.finish();
+}
+
+/// Returns whether the directory is usable for the checks that write into it
+fn check_data_dir(report: &mut Report, dir: &Path) -> bool {
+    const NAME: &str = "Data directory";
+    if let Err(e) = fs::create_dir_all(dir) {
+        report.fail(
+            NAME,
+            &format!("{} cannot be created: {}", dir.display(), e),
+            "Pick another location with --data-dir or FREEBUCKET_DATA_DIR",
+        );
+        return false;
+    }
+    let probe = dir.join(".doctor-probe");
+    let result = fs::write(&probe, b"freebucket").and_then(|_| fs::remove_file(&probe));
+    match result {
+        Ok(()) => {
+            report.pass(NAME, &format!("{} is writable", dir.display()));
+            true
+        }
+        Err(e) => {
+            report.fail(
+                NAME,
+                &format!("{} is not writable: {}", dir.display(), e),
+                "Fix the directory's permissions or run as its owner",
+            );
+            false
+        }
+    }
+}
+
+/// Whether a server holds the data dir's lock file. Starting another one
+/// on it would be refused.
+fn check_instance(report: &mut Report, dir: &Path) {
+    const NAME: &str = "Other instance";
+    let pid = |pid: Option<u32>| pid.map(|p| format!(" (pid {})", p)).unwrap_or_default();
+    match instance_lock::state(dir) {
+        Ok(LockState::Absent) => report.pass(NAME, "no server has run on this data dir"),
+        Ok(LockState::Free { .. }) => report.pass(NAME, "no server is running on this data dir"),
+        Ok(LockState::Held { pid: holder }) => report.warn(
+            NAME,
+            &format!("a FreeBucket server{} is running on this data dir", pid(holder)),
+            "Stop it before starting another server here, or check it with `freebucket doctor --endpoint`",
+        ),
+        Err(e) => report.warn(
+            NAME,
+            &format!("cannot read {}: {}", instance_lock::LOCK_FILE, e),
+            "Fix the lock file's permissions; a server starting here has to open it",
+        ),
+    }
+}
+
+/// Free bytes on the filesystem holding `dir`, from `df` since std has no API for it
+fn free_space(dir: &Path) -> Option<u64> {
+    let output = std::process::Command::new("df")
+        .arg("-Pk")
+        .arg(dir)
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let line = stdout.lines().nth(1)?;
+    let available_kb: u64 = line.split_whitespace().nth(3)?.parse().ok()?;
+    Some(available_kb * 1024)
+}
+
+fn check_disk_space(report: &mut Report, dir: &Path) {
+    const NAME: &str = "Free space";
+    match free_space(dir) {
+        Some(free) if free < LOW_DISK_SPACE => report.warn(
+            NAME,
+            &format!("only {} available", human_readable_size(free)),
+            "Free up space or move the data dir; uploads fail once the disk is full",
+        ),
+        Some(free) => report.pass(NAME, &format!("{} available", human_readable_size(free))),
+        None => report.warn(
+            NAME,
+            "could not be determined",
+            "Check free space manually; `df` was not available",
+        ),
+    }
+}
+
+/// Hard links back snapshots and version history; case sensitivity decides
+/// whether keys that differ only in case can coexist
+fn check_filesystem(report: &mut Report, dir: &Path) {
+    let original = dir.join(".doctor-Case");
+    let link = dir.join(".doctor-link");
+    if let Err(e) = fs::write(&original, b"freebucket") {
+        report.fail(
+            "Filesystem",
+            &format!("cannot create test files: {}", e),
+            "Fix the data dir's permissions",
+        );
+        return;
+    }
+
+    match fs::hard_link(&original, &link) {
+        Ok(()) => report.pass("Hard links", "supported"),
+        Err(e) => report.warn(
+            "Hard links",
+            &format!("not supported ({})", e),
+            "Snapshots and versions fall back to full copies and use more space",
+        ),
+    }
+
+    if dir.join(".doctor-case").exists() {
+        report.warn(
+            "Case sensitivity",
+            "filesystem is case-insensitive",
+            "Keys that differ only in case (Photo.jpg, photo.jpg) overwrite each other",
+        );
+    } else {
+        report.pass("Case sensitivity", "keys are case-sensitive");
+    }
+
+    let _ = fs::remove_file(&link);
+    let _ = fs::remove_file(&original);
+}
+
+/// Files and bytes under `dir`, counted the way bucket stats are
+fn count_files(dir: &Path) -> (u64, u64) {
+    let mut totals = (0, 0);
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else {
+                totals.0 += 1;
+                totals.1 += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            }
+        }
+    }
+    totals
+}
+
+/// Each bucket's metadata plus its stats journal should add up to what is
+/// actually stored. Read-only, so the journals are left for the server to
+/// replay.
+fn check_bucket_stats(report: &mut Report, dir: &Path) {
+    const NAME: &str = "Bucket stats";
+    let mut checked = 0;
+    let mut torn = Vec::new();
+    let mut mismatched = Vec::new();
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name.starts_with('.') || !path.is_dir() {
+            continue;
+        }
+        let Some(mut bucket) = fs::read_to_string(path.join(".bucket_meta.json"))
+            .ok()
+            .and_then(|data| serde_json::from_str::<Bucket>(&data).ok())
+        else {
+            continue;
+        };
+        checked += 1;
+        let (entries, cut_short) = read_stats_journal(&path.join(".stats_journal"));
+        if cut_short {
+            torn.push(name.clone());
+        }
+        replay_stats_journal(&mut bucket, &entries);
+        let (count, size) = count_files(&path.join("objects"));
+        if (bucket.object_count, bucket.total_size) != (count, size) {
+            mismatched.push(format!(
+                "{} ({} objects / {} recorded, {} / {} stored)",
+                name,
+                bucket.object_count,
+                human_readable_size(bucket.total_size),
+                count,
+                human_readable_size(size)
+            ));
+        }
+    }
+
+    if !torn.is_empty() {
+        report.warn(
+            "Stats journal",
+            &format!("ends in a torn write in {}", torn.join(", ")),
+            "Left by a crash; the server skips the partial entry when it replays the journal",
+        );
+    }
+    if mismatched.is_empty() {
+        report.pass(NAME, &format!("{} bucket(s) agree with their stored files", checked));
+    } else {
+        report.warn(
+            NAME,
+            &format!("disagree with stored files: {}", mismatched.join(", ")),
+            "The next write to a bucket recounts it",
+        );
+    }
+}
+
+/// Metadata stored before the limits were set or lowered still loads, but
+/// copies of it would be refused, so point out the objects carrying it
+fn check_object_metadata(report: &mut Report, dir: &Path, limits: &MetadataLimits) {
+    const NAME: &str = "Object metadata";
+    let mut checked = 0;
+    let mut oversized = Vec::new();
+    for bucket in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let bucket_name = bucket.file_name().to_string_lossy().to_string();
+        if bucket_name.starts_with('.') || !bucket.path().join(".bucket_meta.json").is_file() {
+            continue;
+        }
+        for entry in fs::read_dir(bucket.path().join(".meta")).into_iter().flatten().flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.ends_with(".json") {
+                continue;
+            }
+            let Some(meta) = fs::read(entry.path())
+                .ok()
+                .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+            else {
+                continue;
+            };
+            let Ok(metadata) = serde_json::from_value(meta["metadata"].clone()) else {
+                continue;
+            };
+            checked += 1;
+            if limits.check(&metadata).is_err() {
+                oversized.push(format!(
+                    "{}/{} ({} bytes)",
+                    bucket_name,
+                    meta["key"].as_str().unwrap_or_default(),
+                    metadata_size(&metadata)
+                ));
+            }
+        }
+    }
+
+    if oversized.is_empty() {
+        report.pass(NAME, &format!("{} object(s) within the metadata limits", checked));
+        return;
+    }
+    let mut shown = oversized[..oversized.len().min(SHOWN_OVERSIZED)].join(", ");
+    if oversized.len() > SHOWN_OVERSIZED {
+        shown.push_str(&format!(" and {} more", oversized.len() - SHOWN_OVERSIZED));
+    }
+    report.warn(
+        NAME,
+        &format!("over the limits in {}", shown),
+        "They still load; rewrite them with less metadata or raise FREEBUCKET_MAX_METADATA_SIZE",
+    );
+}
+
+/// A bind test, which also notices a server that is already running
+fn check_port(report: &mut Report, config: &Config) {
+    const NAME: &str = "Port";
+    let addr = format!("{}:{}", config.host, config.port);
+    match TcpListener::bind(&addr) {
+        Ok(_) => report.pass(NAME, &format!("{} is free", addr)),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            if local_server_running(config) {
+                report.warn(
+                    NAME,
+                    &format!("another FreeBucket instance is serving {}", addr),
+                    "Stop it first, or check it with `freebucket doctor --endpoint`",
+                );
+            } else {
+                report.fail(
+                    NAME,
+                    &format!("{} is in use by another program", addr),
+                    "Pick another port with --port or FREEBUCKET_PORT",
+                );
+            }
+        }
+        Err(e) => report.fail(
+            NAME,
+            &format!("cannot bind {}: {}", addr, e),
+            "Check the host address, and use a port above 1024 unless running as root",
+        ),
+    }
+}
+
+fn check_clock(report: &mut Report) {
+    const NAME: &str = "Clock";
+    let now = Utc::now();
+    // Earlier than any release of this crate: the clock was never set
+    if now < DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap() {
+        report.fail(
+            NAME,
+            &format!("system time is {}", now.format("%Y-%m-%d %H:%M:%S UTC")),
+            "Set the clock (enable NTP); S3 clients reject signatures from a skewed clock",
+        );
+    } else {
+        report.pass(NAME, &format!("{}", now.format("%Y-%m-%d %H:%M:%S UTC")));
+    }
+}
+
+// ─── Remote checks ───────────────────────────────────────────────
+
+/// A client for probing a server, which gives up after `PROBE_TIMEOUT`
+pub(super) fn probe_client(endpoint: &str) -> Result<FreeBucketClient, ClientError> {
+    Ok(FreeBucketClient::new(endpoint)?.with_timeout(Some(PROBE_TIMEOUT)))
+}
+
+/// Whether a FreeBucket answers on the configured address
+pub(super) fn local_server_running(config: &Config) -> bool {
+    let endpoint = match config.host.contains(':') {
+        true => format!("http://[{}]:{}", config.host, config.port),
+        false => format!("http://{}:{}", config.host, config.port),
+    };
+    probe_client(&endpoint).is_ok_and(|client| {
+        block_on(client.get("/api/server-info")).is_ok_and(|res| res.text().contains("\"FreeBucket\""))
+    })
+}
+
+/// Probe a running server and report what it advertises
+pub(super) fn run_remote_doctor(endpoint: &str) {
+    println!("FreeBucket doctor — checking {}", endpoint);
+    println!();
+    let mut report = Report::default();
+
+    let client = match probe_client(endpoint) {
+        Ok(client) => client,
+        Err(e) => {
+            report.fail("Endpoint", &e.to_string(), "Use the form http://host:port");
+            report.finish();
+            return;
+        }
+    };
+    let (host, port) = client.address();
+
+    let info = match block_on(client.get("/api/server-info")) {
+        Ok(res) if res.status == 200 => res,
+        Ok(res) => {
+            report.fail(
+                "Health",
+                &format!("/api/server-info answered {}", res.status),
+                "Make sure the endpoint is a FreeBucket server, not a proxy or another service",
+            );
+            report.finish();
+            return;
+        }
+        Err(e) => {
+            report.fail(
+                "Health",
+                &format!("cannot reach {}:{}: {}", host, port, e),
+                "Check that the server is running and the port is not firewalled",
+            );
+            report.finish();
+            return;
+        }
+    };
+    let Ok(info_json) = serde_json::from_slice::<serde_json::Value>(&info.body) else {
+        report.fail(
+            "Health",
+            "/api/server-info did not return JSON",
+            "Make sure the endpoint is a FreeBucket server",
+        );
+        report.finish();
+        return;
+    };
+    report.pass(
+        "Health",
+        &format!(
+            "{} {} on {}",
+            info_json["name"].as_str().unwrap_or("?"),
+            info_json["version"].as_str().unwrap_or("?"),
+            info_json["bind_address"].as_str().unwrap_or("?")
+        ),
+    );
+
+    // Servers from before configurable mounts only answer on /s3
+    let mount = info_json["s3_mounts"][0].as_str().unwrap_or("/s3");
+    match block_on(client.get(mount)) {
+        Ok(res) if res.status == 200 => {
+            let body = String::from_utf8_lossy(&res.body);
+            let count = body
+                .contains("<ListAllMyBucketsResult")
+                .then(|| body.matches("<Bucket>").count());
+            match count {
+                Some(n) => report.pass("S3 ListBuckets", &format!("{} bucket(s)", n)),
+                None => report.pass("S3 ListBuckets", "responded"),
+            }
+        }
+        Ok(res) => report.fail(
+            "S3 ListBuckets",
+            &format!("{} answered {}", mount, res.status),
+            "S3 clients will not work against this endpoint",
+        ),
+        Err(e) => report.fail("S3 ListBuckets", &e.to_string(), "Retry; the server stopped responding"),
+    }
+
+    let flag = |name: &str| info_json[name].as_bool().unwrap_or(false);
+    if flag("auth_configured") {
+        report.pass("Auth", "authentication is configured");
+    } else if flag("exposed") {
+        report.warn(
+            "Auth",
+            "no authentication, and reachable beyond loopback",
+            "Anyone on the network can read and delete data; bind to 127.0.0.1",
+        );
+    } else {
+        report.pass("Auth", "no authentication (loopback only)");
+    }
+
+    // SDKs sign requests with the local time; compare against the server's
+    let skew = info
+        .header("date")
+        .and_then(|d| DateTime::parse_from_rfc2822(d).ok())
+        .map(|server| (Utc::now() - server.with_timezone(&Utc)).num_seconds());
+    match skew {
+        Some(s) if s.abs() > MAX_CLOCK_SKEW => report.warn(
+            "Clock",
+            &format!("this machine is {}s off from the server", s),
+            "Sync both clocks (NTP); signed S3 requests are rejected past 15 minutes",
+        ),
+        Some(s) => report.pass("Clock", &format!("within {}s of the server", s.abs())),
+        None => report.warn("Clock", "server sent no Date header", "Compare the clocks manually"),
+    }
+
+    println!();
+    println!("  Capabilities:");
+    for name in ["loopback_only", "auth_configured", "exposed"] {
+        println!("    {:<18} {}", name, flag(name));
+    }
+    if let Some(region) = info_json["default_region"].as_str() {
+        println!("    {:<18} {}", "default_region", region);
+    }
+    report.finish();
+}
+
+/// `freebucket wait`: poll a server until it answers `/api/server-info`,
+/// which it only does once startup (manifest included) is done
+pub(super) fn run_wait(endpoint: &str, timeout: Duration) {
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    let client = match probe_client(endpoint) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("✗ {}", e);
+            std::process::exit(1);
+        }
+    };
+    let started = std::time::Instant::now();
+    loop {
+        let last = match block_on(client.get("/api/server-info")) {
+            Ok(res) if res.status == 200 => {
+                println!("✓ {} is ready ({:.1}s)", endpoint, started.elapsed().as_secs_f64());
+                return;
+            }
+            Ok(res) => format!("/api/server-info answered {}", res.status),
+            Err(e) => e.to_string(),
+        };
+        if started.elapsed() + POLL_INTERVAL > timeout {
+            eprintln!("✗ {} not ready after {}s: {}", endpoint, timeout.as_secs(), last);
+            std::process::exit(1);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::storage::{PutOptions, StorageEngine};
+
+    #[test]
+    fn objects_over_the_metadata_limits_are_listed_but_still_load() {
+        let (dir, storage) = crate::storage::testing::engine();
+        storage.create_bucket("photos", "local").unwrap();
+        let tags = HashMap::from([("camera".to_string(), "x".repeat(100))]);
+        storage
+            .put_object("photos", "big.jpg", b"jpeg", PutOptions { metadata: tags, ..Default::default() })
+            .unwrap();
+        storage
+            .put_object("photos", "small.jpg", b"jpeg", PutOptions::default())
+            .unwrap();
+        drop(storage);
+
+        let limits = MetadataLimits {
+            max_total: 64,
+            ..MetadataLimits::default()
+        };
+        let mut report = Report::default();
+        check_object_metadata(&mut report, dir.path(), &MetadataLimits::default());
+        assert_eq!(report.warnings, 0);
+        check_object_metadata(&mut report, dir.path(), &limits);
+        assert_eq!(report.warnings, 1);
+
+        // Lowering the limits doesn't lock the object out
+        let mut options = Config::builtin().storage_options();
+        options.metadata_limits = limits;
+        let storage = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+        let (meta, _) = storage.get_object("photos", "big.jpg").unwrap();
+        assert_eq!(meta.metadata["camera"].len(), 100);
+    }
+}
@@ -0,0 +1,677 @@
+//! `put` and `get` against a running server (`--endpoint`) instead of the
+//! data dir. Downloaded objects larger than a chunk come down in ranges over several
+//! connections, straight into a file of the object's size. A sidecar file
+//! next to it records the ranges written, so a download that is cut off
+//! picks up where it stopped.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use freebucket::client::{ClientError, FreeBucketClient, ObjectHead};
+use futures::TryStreamExt;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::runtime::Handle;
+
+use super::{
+    block_on, download_paths, expand_patterns, file_current, file_etag, file_mtime, mapped_content_type, plan_upload,
+    run_parallel, UploadPlan,
+};
+use crate::size::human_readable_size;
+
+/// Appended to the path of a download to name its sidecar file
+const STATE_SUFFIX: &str = ".freebucket-download";
+
+pub(super) struct PutOptions {
+    pub jobs: usize,
+    pub content_type: Option<String>,
+    pub type_map: Vec<String>,
+    pub if_changed: bool,
+    pub preserve_mtime: bool,
+    pub quiet: bool,
+    pub exit_code: bool,
+}
+
+pub(super) struct GetOptions {
+    pub jobs: usize,
+    pub chunk_size: u64,
+    pub if_newer: bool,
+    pub quiet: bool,
+    pub exit_code: bool,
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("✗ {}", msg);
+    std::process::exit(1);
+}
+
+pub(super) fn run_put(endpoint: &str, sources: &[String], destination: &str, options: &PutOptions) {
+    let client = FreeBucketClient::new(endpoint).unwrap_or_else(|e| fail(e.to_string()));
+    let UploadPlan { bucket, tasks, type_map } =
+        plan_upload(sources, destination, options.content_type.as_deref(), &options.type_map).unwrap_or_else(|msg| fail(msg));
+    let handle = Handle::current();
+    let upload = Upload {
+        client: &client,
+        handle: &handle,
+        content_type: options.content_type.as_deref(),
+        type_map: &type_map,
+        if_changed: options.if_changed,
+        preserve_mtime: options.preserve_mtime,
+        quiet: options.quiet,
+    };
+    let summary = run_parallel(&tasks, options.jobs, |(path, key)| upload.file(path, &bucket, key));
+    summary.finish("uploaded", options.quiet, options.exit_code);
+}
+
+/// How files are uploaded to one server. Runs on threads outside the
+/// runtime, so requests wait on `handle`.
+struct Upload<'a> {
+    client: &'a FreeBucketClient,
+    handle: &'a Handle,
+    content_type: Option<&'a str>,
+    type_map: &'a HashMap<String, String>,
+    if_changed: bool,
+    preserve_mtime: bool,
+    quiet: bool,
+}
+
+impl Upload<'_> {
+    /// Upload the file at `path` as `bucket/key`, returning its size, or
+    /// `None` when `--if-changed` finds the object holds it already
+    fn file(&self, path: &str, bucket: &str, key: &str) -> Result<Option<u64>, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Cannot read file '{}': {}", path, e))?;
+        if self.if_changed {
+            // A provisional ETag isn't a hash, so it never matches
+            let head = self
+                .handle
+                .block_on(self.client.head_object(bucket, key))
+                .map_err(|e| format!("{}/{}: {}", bucket, key, e))?;
+            if head.is_some_and(|head| head.etag_final && head.etag == file_etag(&data)) {
+                if !self.quiet {
+                    println!("• '{}' → {}/{}  skipped (unchanged)", path, bucket, key);
+                }
+                return Ok(None);
+            }
+        }
+
+        let mut headers = Vec::new();
+        if let Some(content_type) = self.content_type.or_else(|| mapped_content_type(self.type_map, path)) {
+            headers.push(("Content-Type".to_string(), content_type.to_string()));
+        }
+        if let Some(name) = Path::new(path).file_name().and_then(|n| n.to_str()) {
+            headers.push((
+                "X-Original-Filename".to_string(),
+                utf8_percent_encode(name, NON_ALPHANUMERIC).to_string(),
+            ));
+        }
+        if self.preserve_mtime {
+            headers.push(("X-Freebucket-Last-Modified".to_string(), file_mtime(path)?.to_rfc3339()));
+        }
+        let meta = self
+            .handle
+            .block_on(self.client.put_object_with_headers(bucket, key, &data, &headers))
+            .map_err(|e| format!("{}/{}: {}", bucket, key, e))?;
+        if !self.quiet {
+            println!(
+                "✓ Uploaded '{}' → {}/{}  ({}, ETag {})",
+                path,
+                bucket,
+                key,
+                human_readable_size(meta.size),
+                meta.etag
+            );
+        }
+        Ok(Some(meta.size))
+    }
+}
+
+pub(super) fn run_get(endpoint: &str, mut sources: Vec<String>, mut output: Option<String>, options: &GetOptions) {
+    // A server without ranges sends a large object in one answer, which
+    // takes as long as it takes
+    let client = FreeBucketClient::new(endpoint)
+        .unwrap_or_else(|e| fail(e.to_string()))
+        .with_timeout(None);
+
+    // Legacy form: `get bucket/key local-file`
+    if output.is_none() && sources.len() > 1 {
+        let buckets = block_on(client.list_buckets()).unwrap_or_else(|e| fail(format!("{}: {}", endpoint, e)));
+        let last = sources.last().unwrap();
+        let names_bucket = last
+            .split_once('/')
+            .is_some_and(|(b, _)| buckets.iter().any(|bucket| bucket.name == b));
+        if !names_bucket {
+            output = sources.pop();
+        }
+    }
+    let objects = expand_patterns(&sources, |bucket, prefix| {
+        block_on(client.list_objects(bucket, prefix).map_ok(|o| o.key).try_collect()).map_err(|e| e.to_string())
+    })
+    .unwrap_or_else(|msg| fail(msg));
+    let tasks = download_paths(objects, &sources, output.as_deref());
+
+    // The jobs go to whole objects, or to the ranges of a single one
+    let handle = Handle::current();
+    let download = Download {
+        client: &client,
+        handle: &handle,
+        jobs: (options.jobs / tasks.len().max(1)).max(1),
+        chunk_size: options.chunk_size.max(1),
+        if_newer: options.if_newer,
+        quiet: options.quiet,
+    };
+    let summary = run_parallel(&tasks, options.jobs, |(bucket, key, out_path)| {
+        download.object(bucket, key, out_path)
+    });
+    summary.finish("downloaded", options.quiet, options.exit_code);
+}
+
+/// What a download left off at, kept in its sidecar file. It only applies
+/// to the same content cut into the same ranges.
+#[derive(Serialize, Deserialize)]
+struct DownloadState {
+    etag: String,
+    size: u64,
+    chunk_size: u64,
+    /// Indexes of the ranges written to the file
+    done: Vec<u64>,
+}
+
+/// How objects are downloaded from one server. Runs on threads outside the
+/// runtime, so requests wait on `handle`.
+struct Download<'a> {
+    client: &'a FreeBucketClient,
+    handle: &'a Handle,
+    /// Connections for the ranges of one object
+    jobs: usize,
+    chunk_size: u64,
+    if_newer: bool,
+    quiet: bool,
+}
+
+impl Download<'_> {
+    /// Download `bucket/key` to `path`, returning its size, or `None` when
+    /// `--if-newer` finds the file holds it already
+    fn object(&self, bucket: &str, key: &str, path: &str) -> Result<Option<u64>, String> {
+        let head = self
+            .handle
+            .block_on(self.client.head_object(bucket, key))
+            .map_err(|e| format!("{}/{}: {}", bucket, key, e))?
+            .ok_or_else(|| format!("{}/{}: no such object", bucket, key))?;
+        // A file with a sidecar has its full size before it has its content
+        let unfinished = Path::new(&format!("{}{}", path, STATE_SUFFIX)).exists();
+        if self.if_newer
+            && !unfinished
+            && head.last_modified.is_some_and(|modified| file_current(path, head.size, modified))
+        {
+            if !self.quiet {
+                println!("• {}/{} → '{}'  skipped (unchanged)", bucket, key, path);
+            }
+            return Ok(None);
+        }
+        if let Some(parent) = Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent).map_err(|e| format!("Cannot create '{}': {}", parent.display(), e))?;
+            }
+        }
+
+        if head.accepts_ranges && head.size > self.chunk_size {
+            self.ranges(bucket, key, path, &head)?;
+        } else {
+            let data = self
+                .handle
+                .block_on(self.client.get_object(bucket, key))
+                .map_err(|e| format!("{}/{}: {}", bucket, key, e))?;
+            std::fs::write(path, &data).map_err(|e| format!("Cannot write to '{}': {}", path, e))?;
+            // Left by a ranged download of an earlier version
+            let _ = std::fs::remove_file(format!("{}{}", path, STATE_SUFFIX));
+        }
+        verify(&head, path).map_err(|msg| format!("{}/{}: {}", bucket, key, msg))?;
+
+        if !self.quiet {
+            println!(
+                "✓ Downloaded {}/{} → '{}'  ({}, {})",
+                bucket,
+                key,
+                path,
+                human_readable_size(head.size),
+                head.content_type
+            );
+        }
+        Ok(Some(head.size))
+    }
+
+    /// Fetch the object's ranges in parallel into a file of its full size,
+    /// skipping those the sidecar file records as written
+    fn ranges(&self, bucket: &str, key: &str, path: &str, head: &ObjectHead) -> Result<(), String> {
+        let state_path = format!("{}{}", path, STATE_SUFFIX);
+        let chunks = chunk_ranges(head.size, self.chunk_size);
+        let resumed = std::fs::read(&state_path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<DownloadState>(&data).ok())
+            .filter(|s| s.etag == head.etag && s.size == head.size && s.chunk_size == self.chunk_size)
+            .filter(|_| std::fs::metadata(path).is_ok_and(|m| m.len() == head.size));
+        let state = resumed.unwrap_or_else(|| DownloadState {
+            etag: head.etag.clone(),
+            size: head.size,
+            chunk_size: self.chunk_size,
+            done: Vec::new(),
+        });
+
+        let write_error = |e: std::io::Error| format!("Cannot write to '{}': {}", path, e);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(write_error)?;
+        if state.done.is_empty() {
+            // Sized up front, so ranges land at their offsets; most
+            // filesystems give the gaps no space until they're written
+            file.set_len(0).and_then(|_| file.set_len(head.size)).map_err(write_error)?;
+        }
+        save_state(&state_path, &state)?;
+
+        let pending: Vec<u64> = (0..chunks.len() as u64).filter(|i| !state.done.contains(i)).collect();
+        let written = state.done.len() as u64 * self.chunk_size;
+        let progress = Progress::new(path, head.size, written.min(head.size), !self.quiet);
+        let file = Mutex::new(file);
+        let state = Mutex::new(state);
+        let next = AtomicUsize::new(0);
+        let failure = Mutex::new(None::<String>);
+        // Only a final ETag can be matched; a changed object fails the read
+        let etag = head.etag_final.then_some(head.etag.as_str());
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.jobs.clamp(1, pending.len().max(1)) {
+                scope.spawn(|| loop {
+                    if failure.lock().unwrap().is_some() {
+                        break;
+                    }
+                    let Some(&chunk) = pending.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let (first, last) = chunks[chunk as usize];
+                    let fetched = self
+                        .handle
+                        .block_on(self.client.get_object_range(bucket, key, first, last, etag))
+                        .map_err(|e| match e {
+                            ClientError::Api { status: 412, .. } => {
+                                "the object changed during the download; run again to start over".to_string()
+                            }
+                            e => e.to_string(),
+                        })
+                        .and_then(|data| {
+                            let mut file = file.lock().unwrap();
+                            file.seek(SeekFrom::Start(first))
+                                .and_then(|_| file.write_all(&data))
+                                // On disk before the sidecar says so
+                                .and_then(|_| file.sync_data())
+                                .map_err(write_error)
+                        })
+                        .and_then(|_| {
+                            let mut state = state.lock().unwrap();
+                            state.done.push(chunk);
+                            save_state(&state_path, &state)
+                        });
+                    match fetched {
+                        Ok(()) => progress.add(last - first + 1),
+                        Err(msg) => {
+                            *failure.lock().unwrap() = Some(format!("{}/{}: {}", bucket, key, msg));
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        progress.clear();
+
+        if let Some(msg) = failure.into_inner().unwrap() {
+            return Err(msg);
+        }
+        // The file is whole; a sidecar left behind would only mislead
+        let _ = std::fs::remove_file(&state_path);
+        Ok(())
+    }
+}
+
+/// The first and last byte of each range an object of `size` bytes is
+/// fetched in; only the last may be short
+fn chunk_ranges(size: u64, chunk_size: u64) -> Vec<(u64, u64)> {
+    (0..size.div_ceil(chunk_size))
+        .map(|i| (i * chunk_size, (i * chunk_size).saturating_add(chunk_size).min(size) - 1))
+        .collect()
+}
+
+/// Replace the sidecar file whole, so being stopped midway never leaves
+/// one that can't be read
+fn save_state(path: &str, state: &DownloadState) -> Result<(), String> {
+    let temp = format!("{}.tmp", path);
+    std::fs::write(&temp, serde_json::to_vec(state).unwrap())
+        .and_then(|_| std::fs::rename(&temp, path))
+        .map_err(|e| format!("Cannot write to '{}': {}", path, e))
+}
+
+/// Check a downloaded file against the object's ETag, the SHA-256 of its
+/// content. A provisional ETag says nothing about the content, so that
+/// download goes unchecked.
+fn verify(head: &ObjectHead, path: &str) -> Result<(), String> {
+    if !head.etag_final || head.etag.len() != 64 {
+        return Ok(());
+    }
+    let read_error = |e: std::io::Error| format!("Cannot read '{}': {}", path, e);
+    let mut file = std::fs::File::open(path).map_err(read_error)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf).map_err(read_error)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    if hex::encode(hasher.finalize()) != head.etag {
+        // Whatever was written is suspect, so the next run starts over
+        let _ = std::fs::remove_file(format!("{}{}", path, STATE_SUFFIX));
+        return Err(format!(
+            "'{}' doesn't match the object's ETag {}; it may have changed during the download",
+            path, head.etag
+        ));
+    }
+    Ok(())
+}
+
+/// The bytes of one download so far, across all of its ranges, redrawn in
+/// place on stderr when that is a terminal
+struct Progress<'a> {
+    path: &'a str,
+    total: u64,
+    done: AtomicU64,
+    shown: bool,
+}
+
+impl<'a> Progress<'a> {
+    fn new(path: &'a str, total: u64, done: u64, show: bool) -> Self {
+        let progress = Self {
+            path,
+            total,
+            done: AtomicU64::new(done),
+            shown: show && std::io::stderr().is_terminal(),
+        };
+        progress.add(0);
+        progress
+    }
+
+    fn add(&self, bytes: u64) {
+        let done = self.done.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if self.shown {
+            eprint!(
+                "\r  '{}': {} of {} ({}%)   ",
+                self.path,
+                human_readable_size(done),
+                human_readable_size(self.total),
+                done * 100 / self.total.max(1)
+            );
+        }
+    }
+
+    fn clear(&self) {
+        if self.shown {
+            eprint!("\r{}\r", " ".repeat(100));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// 10 000 bytes that differ from one range to the next
+    fn content() -> Vec<u8> {
+        (0..10_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn server_with_object() -> (TestServer, FreeBucketClient) {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("media", "local").unwrap();
+        server
+            .state
+            .storage
+            .put_object("media", "big.bin", &content(), crate::storage::PutOptions::default())
+            .unwrap();
+        let client = FreeBucketClient::new(&server.listen().await).unwrap();
+        (server, client)
+    }
+
+    /// Download `media/big.bin` on a thread outside the runtime, as the CLI does
+    async fn get(client: &FreeBucketClient, path: &Path, chunk_size: u64) -> Result<Option<u64>, String> {
+        get_with(client, path, chunk_size, false).await
+    }
+
+    async fn get_with(
+        client: &FreeBucketClient,
+        path: &Path,
+        chunk_size: u64,
+        if_newer: bool,
+    ) -> Result<Option<u64>, String> {
+        let (client, path) = (client.clone(), path.to_str().unwrap().to_string());
+        tokio::task::spawn_blocking(move || {
+            let handle = Handle::current();
+            let download = Download {
+                client: &client,
+                handle: &handle,
+                jobs: 3,
+                chunk_size,
+                if_newer,
+                quiet: true,
+            };
+            download.object("media", "big.bin", &path)
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Upload the file at `path` as `media/big.bin`, the same way
+    async fn put(client: &FreeBucketClient, path: &Path, if_changed: bool) -> Result<Option<u64>, String> {
+        let (client, path) = (client.clone(), path.to_str().unwrap().to_string());
+        tokio::task::spawn_blocking(move || {
+            let handle = Handle::current();
+            let upload = Upload {
+                client: &client,
+                handle: &handle,
+                content_type: None,
+                type_map: &HashMap::new(),
+                if_changed,
+                preserve_mtime: true,
+                quiet: true,
+            };
+            upload.file(&path, "media", "big.bin")
+        })
+        .await
+        .unwrap()
+    }
+
+    fn sidecar(path: &Path) -> std::path::PathBuf {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(STATE_SUFFIX);
+        sidecar.into()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn downloads_in_ranges_or_whole() {
+        let (_server, client) = server_with_object().await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let ranged = dir.path().join("ranged/big.bin");
+        assert_eq!(get(&client, &ranged, 1024).await, Ok(Some(10_000)));
+        assert_eq!(std::fs::read(&ranged).unwrap(), content());
+        assert!(!sidecar(&ranged).exists());
+
+        // Smaller than a chunk: one request, no sidecar
+        let whole = dir.path().join("whole.bin");
+        assert_eq!(get(&client, &whole, 1 << 20).await, Ok(Some(10_000)));
+        assert_eq!(std::fs::read(&whole).unwrap(), content());
+        assert!(!sidecar(&whole).exists());
+    }
+
+    #[test]
+    fn objects_are_cut_into_ranges_of_the_chunk_size() {
+        assert_eq!(chunk_ranges(10_000, 4096), vec![(0, 4095), (4096, 8191), (8192, 9999)]);
+        assert_eq!(chunk_ranges(8192, 4096), vec![(0, 4095), (4096, 8191)]);
+        assert_eq!(chunk_ranges(3, 4096), vec![(0, 2)]);
+        assert_eq!(chunk_ranges(3, 1), vec![(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(chunk_ranges(0, 4096), vec![]);
+        assert_eq!(chunk_ranges(u64::MAX, 1 << 62).last(), Some(&(3 << 62, u64::MAX - 1)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn servers_without_ranges_send_the_object_in_one_answer() {
+        use axum::http::{HeaderMap, StatusCode};
+
+        // Answers HEAD and GET with the whole object and no Accept-Ranges,
+        // counting the requests that asked for a range anyway
+        let ranged = std::sync::Arc::new(AtomicUsize::new(0));
+        let counter = ranged.clone();
+        let app = axum::Router::new().route(
+            "/api/object/media/big.bin",
+            axum::routing::get(move |headers: HeaderMap| async move {
+                if headers.contains_key("range") {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+                (StatusCode::OK, content())
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = FreeBucketClient::new(&endpoint).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.bin");
+        assert_eq!(get(&client, &path, 1024).await, Ok(Some(10_000)));
+        assert_eq!(std::fs::read(&path).unwrap(), content());
+        assert!(!sidecar(&path).exists());
+        assert_eq!(ranged.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resumes_from_the_sidecar_file() {
+        let (_server, client) = server_with_object().await;
+        let etag = hex::encode(Sha256::digest(content()));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.bin");
+        let state = |done: Vec<u64>| {
+            let state = DownloadState {
+                etag: etag.clone(),
+                size: 10_000,
+                chunk_size: 4096,
+                done,
+            };
+            std::fs::write(sidecar(&path), serde_json::to_vec(&state).unwrap()).unwrap();
+        };
+
+        // Ranges recorded as written are not fetched again, so a wrong one
+        // shows up as a file that doesn't match the ETag
+        let mut partial = vec![0u8; 10_000];
+        partial[..4096].fill(b'x');
+        std::fs::write(&path, &partial).unwrap();
+        state(vec![0]);
+        let err = get(&client, &path, 4096).await.unwrap_err();
+        assert!(err.contains("doesn't match the object's ETag"), "{}", err);
+        assert!(std::fs::read(&path).unwrap().starts_with(&[b'x'; 4096]));
+        assert_eq!(&std::fs::read(&path).unwrap()[4096..], &content()[4096..]);
+        // ... and the next run starts over
+        assert!(!sidecar(&path).exists());
+        assert_eq!(get(&client, &path, 4096).await, Ok(Some(10_000)));
+        assert_eq!(std::fs::read(&path).unwrap(), content());
+
+        // A correct first range is kept and the rest is fetched
+        let mut partial = vec![0u8; 10_000];
+        partial[..4096].copy_from_slice(&content()[..4096]);
+        std::fs::write(&path, &partial).unwrap();
+        state(vec![0]);
+        assert_eq!(get(&client, &path, 4096).await, Ok(Some(10_000)));
+        assert_eq!(std::fs::read(&path).unwrap(), content());
+        assert!(!sidecar(&path).exists());
+
+        // A sidecar for other content or other ranges is ignored
+        std::fs::write(&path, &partial).unwrap();
+        state(vec![0, 1, 2]);
+        assert_eq!(get(&client, &path, 1000).await, Ok(Some(10_000)));
+        assert_eq!(std::fs::read(&path).unwrap(), content());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn get_if_newer_compares_with_a_head() {
+        let (_server, client) = server_with_object().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.bin");
+        assert_eq!(get_with(&client, &path, 1 << 20, true).await, Ok(Some(10_000)));
+        assert_eq!(get_with(&client, &path, 1 << 20, true).await, Ok(None));
+        assert_eq!(get_with(&client, &path, 1 << 20, false).await, Ok(Some(10_000)));
+
+        // Another size, an older file, or an unfinished download is fetched again
+        std::fs::write(&path, b"short").unwrap();
+        assert_eq!(get_with(&client, &path, 1 << 20, true).await, Ok(Some(10_000)));
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(3600)).unwrap();
+        assert_eq!(get_with(&client, &path, 1 << 20, true).await, Ok(Some(10_000)));
+        std::fs::write(sidecar(&path), b"{}").unwrap();
+        assert_eq!(get_with(&client, &path, 1 << 20, true).await, Ok(Some(10_000)));
+        assert!(!sidecar(&path).exists());
+        assert_eq!(get_with(&client, &path, 1 << 20, true).await, Ok(None));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn put_if_changed_compares_the_remote_etag() {
+        let (server, client) = server_with_object().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.bin");
+        std::fs::write(&path, content()).unwrap();
+        assert_eq!(put(&client, &path, true).await, Ok(None));
+        assert_eq!(put(&client, &path, false).await, Ok(Some(10_000)));
+
+        std::fs::write(&path, b"changed").unwrap();
+        let mtime = chrono::DateTime::<chrono::Utc>::from(std::fs::metadata(&path).unwrap().modified().unwrap());
+        assert_eq!(put(&client, &path, true).await, Ok(Some(7)));
+        let (meta, data) = server.state.storage.get_object("media", "big.bin").unwrap();
+        assert_eq!(data, b"changed");
+        assert_eq!(meta.original_filename.as_deref(), Some("big.bin"));
+        assert_eq!(meta.source_mtime.map(|t| t.timestamp()), Some(mtime.timestamp()));
+        assert_eq!(put(&client, &path, true).await, Ok(None));
+
+        // Nothing to compare with: uploaded
+        server.state.storage.delete_object("media", "big.bin").unwrap();
+        assert_eq!(put(&client, &path, true).await, Ok(Some(7)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_objects_fail() {
+        let (_server, client) = server_with_object().await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = client.clone();
+        let path = dir.path().join("none.bin").to_str().unwrap().to_string();
+        let result = tokio::task::spawn_blocking(move || {
+            let handle = Handle::current();
+            let download = Download {
+                client: &client,
+                handle: &handle,
+                jobs: 1,
+                chunk_size: 1024,
+                if_newer: false,
+                quiet: true,
+            };
+            download.object("media", "none.bin", &path)
+        })
+        .await
+        .unwrap();
+        assert_eq!(result, Err("media/none.bin: no such object".to_string()));
+    }
+}
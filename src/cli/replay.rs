@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::Path;
+
+use freebucket::client::{ClientError, FreeBucketClient};
+
+use super::block_on;
+use crate::recording::{REDACTED, TRUNCATED_HEADER};
+
+/// Headers a replay never sends as recorded: the client sets its own
+/// framing, and the recorded body is already decoded
+const FRAMING_HEADERS: &[&str] = &["host", "content-length", "connection", "transfer-encoding"];
+
+/// A recorded HTTP message, split into its parts
+struct Recorded {
+    start_line: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+fn parse_message(data: &[u8]) -> Option<Recorded> {
+    let split = data.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&data[..split]).ok()?;
+    let mut lines = head.split("\r\n");
+    let start_line = lines.next()?.to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Some(Recorded {
+        start_line,
+        headers,
+        body: data[split + 4..].to_vec(),
+    })
+}
+
+/// Send a recorded request to a server again and print what it answers
+/// now, next to what it answered when recorded. `recording` is the path of
+/// a `.request` file, or the id of a recording the server keeps.
+pub(super) fn run_replay(recording: &str, endpoint: &str, allow_truncated: bool) {
+    let fail = |msg: String| -> ! {
+        eprintln!("✗ {}", msg);
+        std::process::exit(1);
+    };
+    let client = FreeBucketClient::new(endpoint).unwrap_or_else(|e| fail(e.to_string()));
+
+    let path = Path::new(recording);
+    let (request, response) = if path.is_file() {
+        let request = fs::read(path).unwrap_or_else(|e| fail(format!("Cannot read {}: {}", recording, e)));
+        (request, fs::read(path.with_extension("response")).ok())
+    } else {
+        let fetch = |side: &str| -> Result<Vec<u8>, ClientError> {
+            let res = block_on(client.get(&format!("/api/admin/recordings/{}/{}", recording, side)))?;
+            res.expect(200)?;
+            Ok(res.body)
+        };
+        match fetch("request") {
+            Ok(request) => (request, fetch("response").ok()),
+            Err(e) => fail(format!("Cannot fetch recording '{}' from {}: {}", recording, endpoint, e)),
+        }
+    };
+
+    let request = parse_message(&request).unwrap_or_else(|| fail(format!("'{}' is not a recorded request", recording)));
+    let mut parts = request.start_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        fail(format!("'{}' is not a recorded request", recording));
+    };
+    let truncated = request
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(TRUNCATED_HEADER))
+        .map(|(_, size)| size.clone());
+    if let Some(size) = &truncated {
+        if !allow_truncated {
+            fail(format!(
+                "The recorded body is cut short ({} of {} bytes); pass --allow-truncated to send it anyway",
+                request.body.len(),
+                size
+            ));
+        }
+    }
+    let mut skipped = Vec::new();
+    let headers: Vec<(String, String)> = request
+        .headers
+        .into_iter()
+        .filter(|(name, value)| {
+            let name = name.to_ascii_lowercase();
+            if name == TRUNCATED_HEADER || FRAMING_HEADERS.contains(&name.as_str()) {
+                return false;
+            }
+            if value.contains(REDACTED) {
+                skipped.push(name);
+                return false;
+            }
+            true
+        })
+        .collect();
+    if !skipped.is_empty() {
+        eprintln!("  Not sending redacted headers: {}", skipped.join(", "));
+    }
+
+    eprintln!("→ {} {} ({} bytes)", method, target, request.body.len());
+    let answer = block_on(client.request_with_headers(method, target, &headers, &request.body))
+        .unwrap_or_else(|e| fail(format!("Cannot reach {}: {}", endpoint, e)));
+    let recorded_status = response
+        .as_deref()
+        .and_then(parse_message)
+        .and_then(|r| r.start_line.split_whitespace().nth(1).map(str::to_string));
+    match recorded_status {
+        Some(status) if status != answer.status.to_string() => {
+            eprintln!("← {} (recorded: {})", answer.status, status)
+        }
+        Some(_) => eprintln!("← {} (as recorded)", answer.status),
+        None => eprintln!("← {}", answer.status),
+    }
+    for (name, value) in &answer.headers {
+        println!("{}: {}", name, value);
+    }
+    println!();
+    match std::str::from_utf8(&answer.body) {
+        Ok(text) if text.is_empty() || text.ends_with('\n') => print!("{}", text),
+        Ok(text) => println!("{}", text),
+        Err(_) => println!("({} bytes of binary data)", answer.body.len()),
+    }
+}
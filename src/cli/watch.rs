@@ -0,0 +1,252 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use globset::{GlobSet, GlobSetBuilder};
+use notify::{RecursiveMode, Watcher};
+use sha2::{Digest, Sha256};
+
+use super::format_error;
+use crate::size::human_readable_size;
+use crate::storage::{PutOptions, StorageEngine};
+
+/// How often the event loop wakes up to check for Ctrl-C
+const POLL: Duration = Duration::from_millis(200);
+
+pub(super) struct WatchOptions {
+    pub dir: PathBuf,
+    pub bucket: String,
+    pub prefix: String,
+    pub delete: bool,
+    pub ignore: Vec<String>,
+    pub debounce: Duration,
+    /// Content types by lowercase extension, from `--type-map`
+    pub type_map: HashMap<String, String>,
+}
+
+struct Mirror<'a> {
+    storage: &'a StorageEngine,
+    opts: &'a WatchOptions,
+    ignore: GlobSet,
+}
+
+/// SHA-256 of a local file in the same hex form as stored ETags
+fn local_etag(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn build_ignore(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = globset::Glob::new(pattern)
+            .map_err(|e| format!("Invalid ignore pattern '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+impl Mirror<'_> {
+    /// Key for a local path, or None if it is outside the watched dir or
+    /// any of its components matches an ignore pattern
+    fn key_for(&self, path: &Path) -> Option<String> {
+        let rel = path.strip_prefix(&self.opts.dir).ok()?;
+        if rel.as_os_str().is_empty() || rel.components().any(|c| self.ignore.is_match(c.as_os_str())) {
+            return None;
+        }
+        let rel = rel.to_string_lossy().replace('\\', "/");
+        Some(format!("{}{}", self.opts.prefix, rel))
+    }
+
+    /// Regular files under `dir`, skipping symlinks and ignored entries
+    fn local_files(&self, dir: &Path, out: &mut Vec<(PathBuf, String)>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let Some(key) = self.key_for(&path) else {
+                continue;
+            };
+            if file_type.is_dir() {
+                self.local_files(&path, out);
+            } else if file_type.is_file() {
+                out.push((path, key));
+            }
+        }
+    }
+
+    /// Upload a file unless the stored object already has identical content
+    fn upload(&self, path: &Path, key: &str) {
+        let etag = match local_etag(path) {
+            Ok(etag) => etag,
+            // Deleted or replaced again since the event; a later event covers it
+            Err(_) => return,
+        };
+        if let Ok(meta) = self.storage.get_object_meta(&self.opts.bucket, key) {
+            if meta.etag == etag {
+                return;
+            }
+        }
+        let result = fs::read(path)
+            .map_err(|e| format!("Cannot read file '{}': {}", path.display(), e))
+            .and_then(|data| {
+                let content_type = super::mapped_content_type(&self.opts.type_map, path);
+                self.storage
+                    .put_object(&self.opts.bucket, key, &data, PutOptions { content_type, ..Default::default() })
+                    .map_err(|e| format_error(&e))
+            });
+        match result {
+            Ok(meta) => println!(
+                "✓ Uploaded {}/{}  ({})",
+                self.opts.bucket,
+                key,
+                human_readable_size(meta.size)
+            ),
+            Err(msg) => eprintln!("✗ {}: {}", key, msg),
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        match self.storage.delete_object(&self.opts.bucket, key) {
+            Ok(()) => println!("✓ Deleted {}/{}", self.opts.bucket, key),
+            Err(crate::error::AppError::ObjectNotFound { .. }) => {}
+            Err(e) => eprintln!("✗ {}: {}", key, format_error(&e)),
+        }
+    }
+
+    /// Stored keys under a prefix that are inside the mirrored area
+    fn remote_keys(&self, prefix: &str) -> Vec<String> {
+        let mut keys = Vec::new();
+        let _ = self.storage.visit_objects(&self.opts.bucket, prefix, |meta| {
+            keys.push(meta.key);
+            Ok(())
+        });
+        keys
+    }
+
+    fn initial_sync(&self) {
+        let mut files = Vec::new();
+        self.local_files(&self.opts.dir, &mut files);
+        for (path, key) in &files {
+            self.upload(path, key);
+        }
+        if self.opts.delete {
+            let local: BTreeSet<&str> = files.iter().map(|(_, k)| k.as_str()).collect();
+            for key in self.remote_keys(&self.opts.prefix) {
+                if !local.contains(key.as_str()) {
+                    self.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Reconcile every path touched during one debounced burst of events
+    fn apply(&self, paths: &BTreeSet<PathBuf>) {
+        for path in paths {
+            let Some(key) = self.key_for(path) else {
+                continue;
+            };
+            match fs::symlink_metadata(path) {
+                Ok(meta) if meta.is_file() => self.upload(path, &key),
+                // A directory created or moved in: mirror everything below it
+                Ok(meta) if meta.is_dir() => {
+                    let mut files = Vec::new();
+                    self.local_files(path, &mut files);
+                    for (file, file_key) in &files {
+                        self.upload(file, file_key);
+                    }
+                }
+                Ok(_) => {} // symlinks and special files are never mirrored
+                Err(_) if self.opts.delete => {
+                    // Gone: either a file or a whole directory
+                    self.remove(&key);
+                    for nested in self.remote_keys(&format!("{}/", key)) {
+                        self.remove(&nested);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// Mirror a local directory into a bucket prefix until Ctrl-C: one full
+/// sync, then incremental uploads (and deletes) as files change
+pub(super) fn run_watch(storage: &StorageEngine, mut opts: WatchOptions) -> Result<(), String> {
+    // Events arrive as absolute paths, so keys are computed against the canonical dir
+    opts.dir = fs::canonicalize(&opts.dir)
+        .ok()
+        .filter(|d| d.is_dir())
+        .ok_or_else(|| format!("'{}' is not a directory", opts.dir.display()))?;
+    storage
+        .get_bucket(&opts.bucket)
+        .map_err(|e| format_error(&e))?;
+
+    let mirror = Mirror {
+        storage,
+        opts: &opts,
+        ignore: build_ignore(&opts.ignore)?,
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))
+        .map_err(|e| format!("Cannot install Ctrl-C handler: {}", e))?;
+
+    // Start watching before the initial sync so nothing changed during it is missed
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| e.to_string())?;
+    watcher
+        .watch(&opts.dir, RecursiveMode::Recursive)
+        .map_err(|e| format!("Cannot watch '{}': {}", opts.dir.display(), e))?;
+
+    println!(
+        "Syncing '{}' → {}/{}",
+        opts.dir.display(),
+        opts.bucket,
+        opts.prefix
+    );
+    mirror.initial_sync();
+    println!("Watching for changes (Ctrl-C to stop)");
+
+    let mut pending: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut last_event = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        match rx.recv_timeout(POLL) {
+            Ok(Ok(event)) => {
+                pending.extend(event.paths);
+                last_event = Instant::now();
+            }
+            Ok(Err(e)) => eprintln!("✗ Watch error: {}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        // Wait for the burst to settle so a bundler's rewrite is one batch
+        if !pending.is_empty() && last_event.elapsed() >= opts.debounce {
+            mirror.apply(&std::mem::take(&mut pending));
+        }
+    }
+
+    if !pending.is_empty() {
+        mirror.apply(&pending);
+    }
+    println!("Stopped watching '{}'", opts.dir.display());
+    Ok(())
+}
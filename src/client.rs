@@ -0,0 +1,748 @@
+//! A typed client for the REST API of a running FreeBucket, for tests and
+//! tools that would otherwise hand-roll HTTP requests, and `send`, the HTTP
+//! client under it. Every request FreeBucket makes goes through `send`,
+//! including the server's webhooks and origins. It speaks HTTP/1.1, over
+//! TLS for `https://` URLs, one connection per request, and holds whole
+//! bodies in memory, which suits the local servers FreeBucket is meant for.
+//!
+//! ```
+//! use freebucket::client::FreeBucketClient;
+//! use futures::TryStreamExt;
+//!
+//! # // Doctests only see the library, so the server under test is the
+//! # // `freebucket` binary on a scratch data dir, as in tests/rclone.rs
+//! # struct Server(std::process::Child, tempfile::TempDir);
+//! # impl Drop for Server {
+//! #     fn drop(&mut self) {
+//! #         let _ = self.0.kill();
+//! #         let _ = self.0.wait();
+//! #     }
+//! # }
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), freebucket::client::ClientError> {
+//! # let target = std::env::var_os("CARGO_TARGET_DIR")
+//! #     .map_or_else(|| std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("target"), Into::into);
+//! # let binary = target
+//! #     .join(if cfg!(debug_assertions) { "debug" } else { "release" })
+//! #     .join(format!("freebucket{}", std::env::consts::EXE_SUFFIX));
+//! # let dir = tempfile::tempdir().unwrap();
+//! # let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+//! # let child = std::process::Command::new(&binary)
+//! #     .env("FREEBUCKET_HOST", "127.0.0.1")
+//! #     .env("FREEBUCKET_PORT", port.to_string())
+//! #     .env("FREEBUCKET_DATA_DIR", dir.path())
+//! #     .stdout(std::process::Stdio::null())
+//! #     .stderr(std::process::Stdio::null())
+//! #     .spawn()
+//! #     .unwrap_or_else(|e| panic!("cannot start {} (build it first): {}", binary.display(), e));
+//! # let _server = Server(child, dir);
+//! # let endpoint = format!("http://127.0.0.1:{}", port);
+//! let client = FreeBucketClient::new(&endpoint)?;
+//! # for _ in 0..100 {
+//! #     if client.list_buckets().await.is_ok() {
+//! #         break;
+//! #     }
+//! #     tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+//! # }
+//! client.create_bucket("fixtures").await?;
+//! client.put_object("fixtures", "hello.txt", b"hello", Some("text/plain")).await?;
+//! assert_eq!(client.get_object("fixtures", "hello.txt").await?, b"hello");
+//!
+//! let keys: Vec<String> = client
+//!     .list_objects("fixtures", "")
+//!     .map_ok(|object| object.key)
+//!     .try_collect()
+//!     .await?;
+//! assert_eq!(keys, ["hello.txt"]);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use std::sync::OnceLock;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, TryStreamExt};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::models::{
+    BackupReport, Bucket, BulkJob, BulkRequest, ErrorResponse, ListBucketsResponse, ListObjectsResponse, ObjectMeta, StorageStats,
+};
+
+/// Time allowed to connect to a server, TLS handshake aside
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time allowed for a whole request unless the client is given another
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Objects asked for per page by `list_objects`
+const LIST_PAGE_KEYS: u32 = 1000;
+
+/// Characters left alone in an object key in a path; the slashes stay so
+/// the key keeps its shape
+const KEY_PATH: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~').remove(b'/');
+
+/// Why a request failed
+#[derive(Debug)]
+pub enum ClientError {
+    /// The endpoint isn't an `http://` or `https://` URL
+    InvalidEndpoint(String),
+    /// The server couldn't be reached, took too long, or hung up midway
+    Io(std::io::Error),
+    /// The server refused the request, with the error it sent
+    Api { status: u16, error: ErrorResponse },
+    /// The server answered with something other than the API's JSON
+    InvalidResponse(String),
+    /// The answer's body is larger than the caller allowed
+    TooLarge { status: u16, limit: usize },
+}
+
+impl ClientError {
+    /// The API error code (`NoSuchBucket`, `NoSuchKey`, …) the server sent
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            ClientError::Api { error, .. } => Some(&error.code),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status the server sent with its error
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::Api { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidEndpoint(msg) => write!(f, "Invalid endpoint: {}", msg),
+            ClientError::Io(e) => write!(f, "{}", e),
+            ClientError::Api { status, error } => write!(f, "{} ({}): {}", error.code, status, error.message),
+            ClientError::InvalidResponse(msg) => write!(f, "Unexpected answer: {}", msg),
+            ClientError::TooLarge { status, limit } => {
+                write!(f, "The answer ({}) is larger than the {} bytes allowed", status, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+/// An answer as it came, for requests the typed methods don't cover
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    /// Header names lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// The body as JSON of type `T`, if the status is `expected`; otherwise
+    /// the error the server sent
+    pub fn json<T: DeserializeOwned>(&self, expected: u16) -> Result<T, ClientError> {
+        self.expect(expected)?;
+        serde_json::from_slice(&self.body).map_err(|e| ClientError::InvalidResponse(e.to_string()))
+    }
+
+    /// `Ok` if the status is `expected`; otherwise the error the server sent,
+    /// as JSON from the REST API or as S3 XML from the `/s3` routes
+    pub fn expect(&self, expected: u16) -> Result<(), ClientError> {
+        if self.status == expected {
+            return Ok(());
+        }
+        let error = serde_json::from_slice::<ErrorResponse>(&self.body).ok().or_else(|| self.xml_error());
+        match error {
+            Some(error) => Err(ClientError::Api {
+                status: self.status,
+                error,
+            }),
+            None => Err(ClientError::InvalidResponse(format!(
+                "status {} with a body that isn't a FreeBucket error",
+                self.status
+            ))),
+        }
+    }
+
+    /// An S3 `<Error>` document's code and message
+    fn xml_error(&self) -> Option<ErrorResponse> {
+        let body = std::str::from_utf8(&self.body).ok()?;
+        let element = |name: &str| {
+            let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+            let end = start + body[start..].find(&format!("</{}>", name))?;
+            Some(
+                body[start..end]
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            )
+        };
+        body.contains("<Error>").then_some(())?;
+        let code = element("Code")?;
+        Some(ErrorResponse {
+            error: code.clone(),
+            code,
+            message: element("Message").unwrap_or_default(),
+        })
+    }
+}
+
+/// What a `HEAD` tells about an object
+#[derive(Debug, Clone)]
+pub struct ObjectHead {
+    pub size: u64,
+    /// Bare hex, as listings have it
+    pub etag: String,
+    /// False while the ETag is provisional, before the content is hashed
+    pub etag_final: bool,
+    pub content_type: String,
+    pub last_modified: Option<DateTime<Utc>>,
+    /// Whether the server answers `Range` requests for the object
+    pub accepts_ranges: bool,
+}
+
+/// A FreeBucket server at an `http://host:port` endpoint, or behind a TLS
+/// proxy at an `https://` one
+#[derive(Debug, Clone)]
+pub struct FreeBucketClient {
+    https: bool,
+    host: String,
+    port: u16,
+    timeout: Option<Duration>,
+}
+
+impl FreeBucketClient {
+    /// A client for `http://host:port`; the scheme may be left out. Nothing
+    /// is sent until the first request.
+    pub fn new(endpoint: &str) -> Result<Self, ClientError> {
+        let (https, rest) = match endpoint.strip_prefix("https://") {
+            Some(rest) => (true, rest),
+            None => (false, endpoint.strip_prefix("http://").unwrap_or(endpoint)),
+        };
+        let authority = rest.split('/').next().unwrap_or(rest);
+        let (host, port) = match authority.rsplit_once(':') {
+            // A trailing `]` means the colons belong to an IPv6 address
+            Some((host, port)) if !authority.ends_with(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| ClientError::InvalidEndpoint(format!("invalid port in '{}'", endpoint)))?,
+            ),
+            _ => (authority, if https { 443 } else { 80 }),
+        };
+        Ok(Self {
+            https,
+            host: host.trim_start_matches('[').trim_end_matches(']').to_string(),
+            port,
+            timeout: Some(DEFAULT_TIMEOUT),
+        })
+    }
+
+    /// Give up on requests after `timeout`, or with `None` wait as long as
+    /// they take. A minute by default.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The endpoint's host and port
+    pub fn address(&self) -> (&str, u16) {
+        (&self.host, self.port)
+    }
+
+    pub async fn create_bucket(&self, name: &str) -> Result<Bucket, ClientError> {
+        let body = serde_json::json!({ "name": name }).to_string();
+        self.request("POST", "/api/buckets", Some("application/json"), body.as_bytes())
+            .await?
+            .json(201)
+    }
+
+    pub async fn list_buckets(&self) -> Result<Vec<Bucket>, ClientError> {
+        let listing: ListBucketsResponse = self.get("/api/buckets").await?.json(200)?;
+        Ok(listing.buckets)
+    }
+
+    /// Store `data` under `key`. Without a content type the server picks
+    /// one from the key and the content.
+    pub async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<ObjectMeta, ClientError> {
+        let headers: Vec<(String, String)> = content_type
+            .map(|t| ("Content-Type".to_string(), t.to_string()))
+            .into_iter()
+            .collect();
+        self.put_object_with_headers(bucket, key, data, &headers).await
+    }
+
+    /// `put_object` with headers of the caller's choosing, such as
+    /// `X-Original-Filename`, `X-Freebucket-Last-Modified`, or
+    /// `x-amz-meta-*` metadata
+    pub async fn put_object_with_headers(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: &[u8],
+        headers: &[(String, String)],
+    ) -> Result<ObjectMeta, ClientError> {
+        // The S3 PUT is the REST API's way to store a body under a key; it
+        // answers with just the ETag, so the metadata is read back
+        self.request_with_headers("PUT", &format!("/s3/{}", object_path(bucket, key)), headers, data)
+            .await?
+            .expect(200)?;
+        self.get(&format!("/api/object/{}?details", object_path(bucket, key)))
+            .await?
+            .json(200)
+    }
+
+    /// Store the file at `path` under `key`
+    pub async fn put_object_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<ObjectMeta, ClientError> {
+        let data = tokio::fs::read(path).await?;
+        self.put_object(bucket, key, &data, None).await
+    }
+
+    /// Store everything `reader` yields under `key`. It is read to the end
+    /// first, since the request has to state its length.
+    pub async fn put_object_reader(
+        &self,
+        bucket: &str,
+        key: &str,
+        mut reader: impl AsyncRead + Unpin,
+        content_type: Option<&str>,
+    ) -> Result<ObjectMeta, ClientError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        self.put_object(bucket, key, &data, content_type).await
+    }
+
+    pub async fn get_object(&self, bucket: &str, key: &str) -> Result<Vec<u8>, ClientError> {
+        let response = self.get(&format!("/api/object/{}", object_path(bucket, key))).await?;
+        response.expect(200)?;
+        Ok(response.body)
+    }
+
+    /// An object's size, ETag, and modification time, without its content;
+    /// `None` if there is no such object
+    pub async fn head_object(&self, bucket: &str, key: &str) -> Result<Option<ObjectHead>, ClientError> {
+        let response = self
+            .request("HEAD", &format!("/api/object/{}", object_path(bucket, key)), None, &[])
+            .await?;
+        if response.status == 404 {
+            return Ok(None);
+        }
+        response.expect(200)?;
+        let size = response
+            .header("content-length")
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| ClientError::InvalidResponse("HEAD without a Content-Length".to_string()))?;
+        Ok(Some(ObjectHead {
+            size,
+            etag: response.header("etag").unwrap_or_default().trim_matches('"').to_string(),
+            etag_final: response.header("x-freebucket-etag-final") != Some("false"),
+            content_type: response.header("content-type").unwrap_or_default().to_string(),
+            last_modified: response
+                .header("last-modified")
+                .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+                .map(|t| t.with_timezone(&Utc)),
+            accepts_ranges: response.header("accept-ranges") == Some("bytes"),
+        }))
+    }
+
+    /// Bytes `first` to `last` (inclusive) of an object. With an `etag`, the
+    /// read fails with `PreconditionFailed` once the object has changed.
+    pub async fn get_object_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        first: u64,
+        last: u64,
+        etag: Option<&str>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut headers = vec![("Range".to_string(), format!("bytes={}-{}", first, last))];
+        if let Some(etag) = etag {
+            headers.push(("If-Match".to_string(), format!("\"{}\"", etag)));
+        }
+        let response = self
+            .request_with_headers("GET", &format!("/api/object/{}", object_path(bucket, key)), &headers, &[])
+            .await?;
+        response.expect(206)?;
+        if response.body.len() as u64 != last - first + 1 {
+            return Err(ClientError::InvalidResponse(format!(
+                "{} bytes for the range {}-{}",
+                response.body.len(),
+                first,
+                last
+            )));
+        }
+        Ok(response.body)
+    }
+
+    /// Write an object to `writer`, returning its size
+    pub async fn get_object_to(
+        &self,
+        bucket: &str,
+        key: &str,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> Result<u64, ClientError> {
+        let data = self.get_object(bucket, key).await?;
+        writer.write_all(&data).await?;
+        writer.flush().await?;
+        Ok(data.len() as u64)
+    }
+
+    pub async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), ClientError> {
+        self.request("DELETE", &format!("/api/object/{}", object_path(bucket, key)), None, &[])
+            .await?
+            .expect(204)
+    }
+
+    /// One page of a listing: the objects and, with a `delimiter`, the
+    /// common prefixes under `prefix`
+    pub async fn list_objects_page(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        delimiter: Option<&str>,
+        max_keys: u32,
+        continuation_token: Option<&str>,
+    ) -> Result<ListObjectsResponse, ClientError> {
+        let mut path = format!(
+            "/api/buckets/{}/objects?prefix={}&max_keys={}",
+            query_value(bucket),
+            query_value(prefix),
+            max_keys
+        );
+        if let Some(delimiter) = delimiter {
+            path.push_str(&format!("&delimiter={}", query_value(delimiter)));
+        }
+        if let Some(token) = continuation_token {
+            path.push_str(&format!("&continuation_token={}", query_value(token)));
+        }
+        self.get(&path).await?.json(200)
+    }
+
+    /// Every object under `prefix`, fetching pages as the stream is read
+    pub fn list_objects<'a>(
+        &'a self,
+        bucket: &'a str,
+        prefix: &'a str,
+    ) -> impl Stream<Item = Result<ObjectMeta, ClientError>> + 'a {
+        // `None` once the last page was fetched
+        let start = Some(None::<String>);
+        stream::try_unfold(start, move |token| async move {
+            let Some(token) = token else {
+                return Ok::<_, ClientError>(None);
+            };
+            let page = self
+                .list_objects_page(bucket, prefix, None, LIST_PAGE_KEYS, token.as_deref())
+                .await?;
+            let next = page.next_continuation_token.filter(|_| page.is_truncated).map(Some);
+            Ok(Some((stream::iter(page.objects.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+    }
+
+    /// Object counts and sizes across all buckets
+    pub async fn stats(&self) -> Result<StorageStats, ClientError> {
+        self.get("/api/stats").await?.json(200)
+    }
+
+    /// Have the server back up its data dir into `dir`, a path on its own
+    /// machine. Large backups take a while, so consider `with_timeout`.
+    pub async fn backup(&self, dir: &str) -> Result<BackupReport, ClientError> {
+        let body = serde_json::json!({ "dir": dir }).to_string();
+        self.request("POST", "/api/admin/backup", Some("application/json"), body.as_bytes())
+            .await?
+            .json(201)
+    }
+
+    /// Have the server copy or move every object under a prefix. The job
+    /// runs on after this returns; follow it with `bulk_job`.
+    pub async fn start_bulk(&self, request: &BulkRequest) -> Result<BulkJob, ClientError> {
+        let body = serde_json::json!(request).to_string();
+        self.request("POST", "/api/admin/bulk", Some("application/json"), body.as_bytes())
+            .await?
+            .json(202)
+    }
+
+    /// The progress of a bulk job
+    pub async fn bulk_job(&self, id: &str) -> Result<BulkJob, ClientError> {
+        self.get(&format!("/api/admin/bulk/{}", query_value(id))).await?.json(200)
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Response, ClientError> {
+        self.request("GET", path, None, &[]).await
+    }
+
+    /// Send one request and return whatever the server answered. `path`
+    /// includes the query and must already be percent-encoded.
+    pub async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> Result<Response, ClientError> {
+        let headers: Vec<(String, String)> = content_type
+            .map(|t| ("Content-Type".to_string(), t.to_string()))
+            .into_iter()
+            .collect();
+        self.request_with_headers(method, path, &headers, body).await
+    }
+
+    /// `request` with headers of the caller's choosing. `Host`,
+    /// `Content-Length`, and `Connection` are always the client's own.
+    pub async fn request_with_headers(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> Result<Response, ClientError> {
+        let exchange = self.exchange(method, path, headers, body);
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange).await.map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("no answer within {}s", timeout.as_secs()),
+                )
+            })?,
+            None => exchange.await,
+        }
+    }
+
+    async fn exchange(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> Result<Response, ClientError> {
+        let scheme = if self.https { "https" } else { "http" };
+        let url = match self.host.contains(':') {
+            true => format!("{}://[{}]:{}{}", scheme, self.host, self.port, path),
+            false => format!("{}://{}:{}{}", scheme, self.host, self.port, path),
+        };
+        let own = |name: &str| ["host", "content-length", "connection"].iter().any(|n| name.eq_ignore_ascii_case(n));
+        let mut headers: Vec<_> = headers.iter().filter(|(name, _)| !own(name)).cloned().collect();
+        if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("user-agent")) {
+            headers.push(("User-Agent".to_string(), format!("freebucket-client/{}", env!("CARGO_PKG_VERSION"))));
+        }
+        send(method, &url, &headers, body, usize::MAX).await
+    }
+}
+
+/// The client behind `send`. Connections aren't kept for reuse: the CLI
+/// gives some requests a runtime of their own, which an idle connection
+/// would outlive. Certificates are checked against the Mozilla roots
+/// built in, so `https://` works the same on every machine.
+fn http_client() -> &'static Client<HttpsConnector<HttpConnector>, Full<Bytes>> {
+    static CLIENT: OnceLock<Client<HttpsConnector<HttpConnector>, Full<Bytes>>> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(CONNECT_TIMEOUT));
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http);
+        Client::builder(TokioExecutor::new())
+            .pool_max_idle_per_host(0)
+            .build(connector)
+    })
+}
+
+/// Send one request to an `http://` or `https://` URL and read the whole
+/// answer, which must not be larger than `max_body` bytes. Without a `Host`
+/// header, the URL's authority is sent. There's no time limit of its own,
+/// so callers wrap it in theirs.
+pub async fn send(
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    body: &[u8],
+    max_body: usize,
+) -> Result<Response, ClientError> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(ClientError::InvalidEndpoint(format!("'{}' is not an http:// or https:// URL", url)));
+    }
+    let invalid_request = |e: &dyn std::error::Error| ClientError::InvalidEndpoint(format!("{}: {}", url, e));
+    let method = hyper::Method::from_bytes(method.as_bytes()).map_err(|e| invalid_request(&e))?;
+    let mut request = hyper::Request::builder().method(method).uri(url);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let request = request
+        .body(Full::new(Bytes::copy_from_slice(body)))
+        .map_err(|e| invalid_request(&e))?;
+
+    let response = http_client().request(request).await.map_err(|e| ClientError::Io(io_error(&e)))?;
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.as_str().to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+        .collect();
+    let body = match Limited::new(response.into_body(), max_body).collect().await {
+        Ok(collected) => collected.to_bytes().to_vec(),
+        Err(e) if e.is::<http_body_util::LengthLimitError>() => {
+            return Err(ClientError::TooLarge { status, limit: max_body })
+        }
+        Err(e) => return Err(ClientError::Io(std::io::Error::other(e))),
+    };
+    Ok(Response { status, headers, body })
+}
+
+/// An I/O error with the causes of a failed request, which the client
+/// error itself leaves out of its message
+fn io_error(e: &hyper_util::client::legacy::Error) -> std::io::Error {
+    let mut message = e.to_string();
+    let mut kind = std::io::ErrorKind::Other;
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            kind = io.kind();
+        }
+        source = cause.source();
+    }
+    std::io::Error::new(kind, message)
+}
+
+/// `bucket/key` for a URL path
+fn object_path(bucket: &str, key: &str) -> String {
+    format!(
+        "{}/{}",
+        utf8_percent_encode(bucket, NON_ALPHANUMERIC),
+        utf8_percent_encode(key, KEY_PATH)
+    )
+}
+
+fn query_value(value: &str) -> String {
+    utf8_percent_encode(value, NON_ALPHANUMERIC).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::HeaderMap;
+    use axum::routing::{get, post};
+    use axum::Router;
+
+    use super::*;
+
+    /// A server on a free loopback port for the length of the test
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn sends_headers_and_reads_streamed_answers() {
+        let chunks = || Body::from_stream(stream::iter(["hel", "lo"].map(Ok::<_, std::io::Error>)));
+        let echo = |headers: HeaderMap, body: Bytes| async move {
+            let host = headers["host"].to_str().unwrap().to_string();
+            let test = headers.get("x-test").map_or("-", |v| v.to_str().unwrap());
+            format!("{} {} {}", host, test, String::from_utf8_lossy(&body))
+        };
+        let base = serve(Router::new().route("/chunked", get(move || async move { chunks() })).route("/echo", post(echo))).await;
+
+        let response = send("GET", &format!("{}/chunked", base), &[], &[], 1024).await.unwrap();
+        assert_eq!((response.status, response.body), (200, b"hello".to_vec()));
+
+        let headers = [("X-Test".to_string(), "yes".to_string())];
+        let response = send("POST", &format!("{}/echo", base), &headers, b"body", 1024).await.unwrap();
+        assert_eq!(response.text(), format!("{} yes body", base.trim_start_matches("http://")));
+
+        let headers = [("Host".to_string(), "signed.example:80".to_string())];
+        let response = send("POST", &format!("{}/echo", base), &headers, b"", 1024).await.unwrap();
+        assert!(response.text().starts_with("signed.example:80 "), "{}", response.text());
+    }
+
+    #[test]
+    fn reads_json_and_s3_xml_errors() {
+        let response = |body: &str| Response {
+            status: 404,
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+        };
+        let json = response(r#"{"error":"NoSuchBucket","code":"NoSuchBucket","message":"Bucket 'a' not found"}"#);
+        let xml = response(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>NoSuchBucket</Code>\
+             <Message>Bucket &apos;a&apos; not found</Message><Resource>/a/k</Resource></Error>",
+        );
+        for response in [json, xml] {
+            let err = response.expect(200).unwrap_err();
+            assert_eq!((err.status(), err.code()), (Some(404), Some("NoSuchBucket")));
+            assert_eq!(err.to_string(), "NoSuchBucket (404): Bucket 'a' not found");
+        }
+        assert!(matches!(response("<html>").expect(200), Err(ClientError::InvalidResponse(_))));
+    }
+
+    #[tokio::test]
+    async fn refuses_large_answers_and_other_schemes() {
+        let base = serve(Router::new().route("/big", get(|| async { vec![b'x'; 100] }))).await;
+        match send("GET", &format!("{}/big", base), &[], &[], 10).await {
+            Err(ClientError::TooLarge { status: 200, limit: 10 }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            send("GET", "ftp://example.com/", &[], &[], 10).await,
+            Err(ClientError::InvalidEndpoint(_))
+        ));
+        // Nothing listens on a port that was just released
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        assert!(matches!(
+            send("GET", &format!("http://127.0.0.1:{}/", closed), &[], &[], 10).await,
+            Err(ClientError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn endpoints_default_their_port_by_scheme() {
+        assert_eq!(FreeBucketClient::new("127.0.0.1:3210").unwrap().address(), ("127.0.0.1", 3210));
+        assert_eq!(FreeBucketClient::new("http://[::1]/").unwrap().address(), ("::1", 80));
+        assert_eq!(FreeBucketClient::new("https://files.example").unwrap().address(), ("files.example", 443));
+    }
+}
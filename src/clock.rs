@@ -0,0 +1,124 @@
+//! Where timestamps and IDs come from. Everything that records a time or
+//! hands out an ID (object `last_modified`, upload, job, and snapshot IDs,
+//! version IDs, log names) asks the `Sources` it was built with, so tests can
+//! pin both. Names of scratch files stay random, since a leftover from an
+//! earlier run must never collide with a new one.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+/// Source of the current time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Source of unique IDs
+pub trait IdGen: Send + Sync {
+    fn new_id(&self) -> Uuid;
+}
+
+/// The system clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Random version 4 UUIDs
+pub struct RandomIds;
+
+impl IdGen for RandomIds {
+    fn new_id(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// Starts at a fixed time and moves on by `step` every time it is read. A
+/// zero step stands still.
+pub struct SteppingClock {
+    next: Mutex<DateTime<Utc>>,
+    step: Duration,
+}
+
+impl SteppingClock {
+    pub fn new(start: DateTime<Utc>, step: Duration) -> Self {
+        Self {
+            next: Mutex::new(start),
+            step,
+        }
+    }
+}
+
+impl Clock for SteppingClock {
+    fn now(&self) -> DateTime<Utc> {
+        let mut next = self.next.lock().unwrap();
+        let now = *next;
+        *next = now + self.step;
+        now
+    }
+}
+
+/// IDs 1, 2, 3, … with the counter repeated in each 32-bit word, so the
+/// short prefixes some names take of an ID differ as well
+#[derive(Default)]
+pub struct SequentialIds {
+    last: AtomicU32,
+}
+
+impl IdGen for SequentialIds {
+    fn new_id(&self) -> Uuid {
+        let n = self.last.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        Uuid::from_u128(u128::from(n) * 0x0000_0001_0000_0001_0000_0001_0000_0001)
+    }
+}
+
+/// A clock and an ID source, shared by everything built from one
+/// configuration
+#[derive(Clone)]
+pub struct Sources {
+    clock: Arc<dyn Clock>,
+    ids: Arc<dyn IdGen>,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self::new(SystemClock, RandomIds)
+    }
+}
+
+impl Sources {
+    pub fn new(clock: impl Clock + 'static, ids: impl IdGen + 'static) -> Self {
+        Self {
+            clock: Arc::new(clock),
+            ids: Arc::new(ids),
+        }
+    }
+
+    /// The real sources, with the fakes `FREEBUCKET_FAKE_CLOCK` and
+    /// `FREEBUCKET_FAKE_IDS` ask for swapped in
+    pub fn configured(fake_clock: Option<DateTime<Utc>>, fake_clock_step: Duration, fake_ids: bool) -> Self {
+        let clock: Arc<dyn Clock> = match fake_clock {
+            Some(start) => Arc::new(SteppingClock::new(start, fake_clock_step)),
+            None => Arc::new(SystemClock),
+        };
+        let ids: Arc<dyn IdGen> = if fake_ids {
+            Arc::new(SequentialIds::default())
+        } else {
+            Arc::new(RandomIds)
+        };
+        Self { clock, ids }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    pub fn new_id(&self) -> Uuid {
+        self.ids.new_id()
+    }
+}
@@ -1,3 +1,66 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env::VarError;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::clock::Sources;
+use crate::s3_mounts::{parse_mounts, DEFAULT_MOUNTS};
+use crate::size::{parse_size, SizeUnits, KIB, MIB};
+use crate::storage::{
+    DeletePolicy, GcOptions, HashMode, IoRetry, KeyCaseMode, MetadataLimits, ReconcileMode, ReconcileOptions,
+    StorageOptions,
+};
+
+/// Log filter used when `RUST_LOG` is not set
+pub const DEFAULT_LOG_FILTER: &str = "freebucket=info,tower_http=info";
+
+/// Settings that hold a size, with the unit a bare number counts in
+const SIZE_SETTINGS: &[(&str, u64)] = &[
+    ("FREEBUCKET_MAX_UPLOAD_MB", MIB),
+    ("FREEBUCKET_WRITE_BUFFER_KB", KIB),
+    ("FREEBUCKET_MAX_METADATA_SIZE", 1),
+    ("FREEBUCKET_MAX_METADATA_NAME", 1),
+    ("FREEBUCKET_MAX_METADATA_VALUE", 1),
+    ("FREEBUCKET_READ_CACHE_SIZE", 1),
+    ("FREEBUCKET_READ_CACHE_MAX_OBJECT", 1),
+    ("FREEBUCKET_AUDIT_LOG_MAX_SIZE", 1),
+    ("FREEBUCKET_RECORD_MAX_BODY", 1),
+    ("FREEBUCKET_RECORD_MAX_TOTAL", 1),
+];
+
+/// Read a size setting. A bare number counts in `unit`, as the setting's
+/// name says; anything else is a size such as `2GiB`.
+fn parse_size_setting(value: &str, unit: u64) -> Result<u64, String> {
+    match value.trim().parse::<u64>() {
+        Ok(n) => n
+            .checked_mul(unit)
+            .ok_or_else(|| format!("Invalid size '{}': larger than the largest size, {} bytes", value, u64::MAX)),
+        Err(_) => parse_size(value),
+    }
+}
+
+/// Sizes that don't parse are errors rather than quietly left at their
+/// defaults, since a typo there can turn away every upload
+fn check_size_settings(var: &impl Fn(&str) -> Result<String, VarError>) -> Result<(), String> {
+    for (name, unit) in SIZE_SETTINGS {
+        if let Ok(value) = var(name) {
+            parse_size_setting(&value, *unit).map_err(|e| format!("{}: {}", name, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Mounts that don't parse are errors for the same reason: a typo would
+/// take the S3 API off the path every client uses
+fn check_s3_mounts(var: &impl Fn(&str) -> Result<String, VarError>) -> Result<(), String> {
+    match var("FREEBUCKET_S3_MOUNTS") {
+        Ok(value) => parse_mounts(&value).map(|_| ()).map_err(|e| format!("FREEBUCKET_S3_MOUNTS: {}", e)),
+        Err(_) => Ok(()),
+    }
+}
+
 /// Application configuration
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -5,19 +68,584 @@ pub struct Config {
     pub port: u16,
     pub data_dir: String,
     pub max_upload_size: usize, // in bytes
+    /// Acknowledges that binding to a non-loopback address exposes the
+    /// unauthenticated API to the network
+    pub allow_open_bind: bool,
+    /// Multipart uploads idle for longer than this are aborted by GC
+    pub multipart_expiry_hours: u64,
+    /// Base domain for virtual-hosted-style requests: `<bucket>.<domain>`
+    pub virtual_host_domain: String,
+    /// Region given to buckets created without an explicit one
+    pub default_region: String,
+    /// How often usage is sampled into the stats history
+    pub stats_interval_hours: u64,
+    /// Samples older than this are pruned from the stats history
+    pub stats_retention_days: u64,
+    /// How often buffered access log records are written out
+    pub access_log_flush_secs: u64,
+    /// Write buffered access log records early once this many are waiting
+    pub access_log_batch_size: usize,
+    /// When bucket metadata is checked against the files on disk
+    pub reconcile_mode: ReconcileMode,
+    /// Cap on metadata files generated per bucket in one reconcile pass
+    pub reconcile_max_files: u64,
+    /// Rebuild corrupt object metadata on access rather than failing
+    pub repair_corrupt_meta: bool,
+    /// Successful reads under these path prefixes are not logged
+    pub log_exclude_paths: Vec<String>,
+    /// Fraction of the remaining successful reads that are logged
+    pub log_sample_rate: f64,
+    /// Uploads handled at once; 0 means unlimited
+    pub max_concurrent_uploads: usize,
+    /// How long an upload waits for a free slot before getting a 503
+    pub upload_wait_secs: u64,
+    /// Buffer size for streaming writes, in bytes
+    pub write_buffer_size: usize,
+    /// What deleting an object does while it is being downloaded
+    pub delete_policy: DeletePolicy,
+    /// How long a delete waits for downloads under the wait policy
+    pub delete_wait_ms: u64,
+    /// Bucket names new buckets can't use, on top of the built-in route names
+    pub reserved_bucket_names: Vec<String>,
+    /// Create a missing bucket on the first object written to it
+    pub auto_create_buckets: bool,
+    /// Buckets created at startup if they don't exist
+    pub default_buckets: Vec<String>,
+    /// Threads used to walk a bucket's directory tree
+    pub walk_threads: usize,
+    /// How long a response is replayed for a repeated `Idempotency-Key`
+    pub idempotency_ttl_secs: u64,
+    /// Idempotency keys whose responses are held in memory
+    pub idempotency_cache_size: usize,
+    /// Write idempotency keys evicted from memory to disk instead of
+    /// forgetting them
+    pub idempotency_spill: bool,
+    /// Keep object leases in a file so they survive a restart
+    pub lock_persist: bool,
+    /// Longest lease `/api/locks` grants
+    pub lock_max_ttl_secs: u64,
+    /// Count downloads and last access per object
+    pub access_tracking: bool,
+    /// How often download counters are written to disk
+    pub access_flush_secs: u64,
+    /// How often bucket stats journals are folded into bucket metadata
+    pub stats_compact_secs: u64,
+    /// How often bucket usage is checked against alert thresholds
+    pub alert_check_secs: u64,
+    /// Accept `PATCH /api/object/...` writes into part of an object
+    pub range_writes: bool,
+    /// Store object keys in Unicode NFC form
+    pub normalize_keys: bool,
+    /// Most `/`-separated segments an object key may have
+    pub max_key_depth: usize,
+    /// Whether keys differing only in case are refused or stored escaped
+    /// where the data dir's filesystem ignores case
+    pub key_case: KeyCaseMode,
+    /// Tries of a file operation failing with a transient error such as
+    /// ESTALE, and the pause before the first retry
+    pub io_retry_attempts: u32,
+    pub io_retry_backoff_ms: u64,
+    /// Serve gRPC on this port too (needs the `grpc` feature)
+    pub grpc_port: Option<u16>,
+    /// Serve buckets over WebDAV under `/dav/`
+    pub webdav: bool,
+    /// Object limit for new buckets
+    pub default_max_objects: Option<u64>,
+    /// Warn about storage operations slower than this; 0 disables
+    pub slow_op_ms: u64,
+    /// Days the settings of a deleted bucket are kept for restoring
+    pub deleted_bucket_retention_days: u64,
+    /// Which log messages are written, in `RUST_LOG` syntax
+    pub log_filter: String,
+    /// How often a light self-test runs for `/readyz`; 0 keeps `/readyz` to
+    /// a writability check
+    pub readyz_self_test_secs: u64,
+    /// Named storage roots besides the data dir, as (name, path)
+    pub volumes: Vec<(String, String)>,
+    /// Start a fake clock here instead of reading the system clock, for tests
+    pub fake_clock: Option<DateTime<Utc>>,
+    /// How far the fake clock moves on each time it is read
+    pub fake_clock_step_ms: u64,
+    /// Hand out sequential IDs instead of random ones, for tests
+    pub fake_ids: bool,
+    /// Whether sizes are shown in KiB, MiB, ... or KB, MB, ...
+    pub size_units: SizeUnits,
+    /// Bounds on the `x-amz-meta-*` metadata of each object
+    pub metadata_limits: MetadataLimits,
+    /// Paths the S3 API is served under, each as `{mount}/{bucket}/{key}`
+    pub s3_mounts: Vec<String>,
+    /// Bytes of small objects kept in memory for reads; 0 disables the cache
+    pub read_cache_size: u64,
+    /// Largest object the read cache holds
+    pub read_cache_max_object: u64,
+    /// Programs buckets may run as exec hooks, by absolute path
+    pub exec_hook_allow: Vec<String>,
+    /// Exec hooks running at once across all buckets
+    pub exec_hook_workers: usize,
+    /// Whether PUTs hash their payload before answering or in the background
+    pub hash_mode: HashMode,
+    /// Size at which the audit log is rotated
+    pub audit_log_max_size: u64,
+    /// Origins whose browser requests may change data without a CSRF token
+    pub csrf_trusted_origins: Vec<String>,
+    /// Directory S3 requests and responses are recorded to, for debugging
+    /// clients; `None` records nothing
+    pub record_requests: Option<String>,
+    /// Record even though authentication is configured
+    pub record_force: bool,
+    /// Bytes of each request and response body kept in a recording
+    pub record_max_body: u64,
+    /// Bytes all recordings may take before the oldest are removed
+    pub record_max_total: u64,
 }
 
 impl Default for Config {
+    /// The environment and the config file; settings that can't be read
+    /// are reported and left at their defaults, and so is a config file
+    /// that can't be read
     fn default() -> Self {
+        Self::load().unwrap_or_else(|e| {
+            eprintln!("Ignoring invalid configuration: {}", e);
+            Self::from_vars(|name| std::env::var(name))
+        })
+    }
+}
+
+impl Config {
+    /// The built-in settings, ignoring the environment and any config file
+    #[cfg(test)]
+    pub(crate) fn builtin() -> Self {
+        Self::from_vars(|_| Err(VarError::NotPresent))
+    }
+
+    /// The environment, overlaid with the `KEY=value` lines of the file
+    /// named by `FREEBUCKET_CONFIG`. Unlike the environment, the file can be
+    /// edited while the server runs and re-read with a reload.
+    pub fn load() -> Result<Self, String> {
+        let Ok(path) = std::env::var("FREEBUCKET_CONFIG") else {
+            check_size_settings(&|name| std::env::var(name))?;
+            check_s3_mounts(&|name| std::env::var(name))?;
+            return Ok(Self::from_vars(|name| std::env::var(name)));
+        };
+        let file = read_config_file(&path)?;
+        let read = RefCell::new(HashSet::new());
+        let lookup = |name: &str| {
+            read.borrow_mut().insert(name.to_string());
+            match file.get(name) {
+                Some(value) => Ok(value.clone()),
+                None => std::env::var(name),
+            }
+        };
+        check_size_settings(&lookup).map_err(|e| format!("{}: {}", path, e))?;
+        check_s3_mounts(&lookup).map_err(|e| format!("{}: {}", path, e))?;
+        let config = Self::from_vars(lookup);
+        // A misspelt setting would otherwise be silently ignored
+        let read = read.into_inner();
+        if let Some(unknown) = file.keys().find(|key| !read.contains(*key)) {
+            return Err(format!("{}: unknown setting {}", path, unknown));
+        }
+        Ok(config)
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Result<String, VarError>) -> Self {
         Self {
-            host: std::env::var("FREEBUCKET_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-            port: std::env::var("FREEBUCKET_PORT")
+            host: var("FREEBUCKET_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
+            port: var("FREEBUCKET_PORT")
                 .ok()
                 .and_then(|p| p.parse().ok())
                 .unwrap_or(3210),
-            data_dir: std::env::var("FREEBUCKET_DATA_DIR")
+            data_dir: var("FREEBUCKET_DATA_DIR")
                 .unwrap_or_else(|_| "./freebucket_data".to_string()),
-            max_upload_size: 500 * 1024 * 1024, // 500MB default
+            max_upload_size: var("FREEBUCKET_MAX_UPLOAD_MB")
+                .ok()
+                .and_then(|n| parse_size_setting(&n, MIB).ok())
+                .filter(|n| *n > 0)
+                .map_or(500 * MIB as usize, |n| usize::try_from(n).unwrap_or(usize::MAX)),
+            allow_open_bind: var("FREEBUCKET_ALLOW_OPEN")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            multipart_expiry_hours: var("FREEBUCKET_MULTIPART_EXPIRY_HOURS")
+                .ok()
+                .and_then(|h| h.parse().ok())
+                .unwrap_or(7 * 24),
+            virtual_host_domain: var("FREEBUCKET_DOMAIN")
+                .unwrap_or_else(|_| "localhost".to_string()),
+            default_region: var("FREEBUCKET_REGION")
+                .unwrap_or_else(|_| "local".to_string()),
+            stats_interval_hours: var("FREEBUCKET_STATS_INTERVAL_HOURS")
+                .ok()
+                .and_then(|h| h.parse().ok())
+                .unwrap_or(24),
+            stats_retention_days: var("FREEBUCKET_STATS_RETENTION_DAYS")
+                .ok()
+                .and_then(|d| d.parse().ok())
+                .unwrap_or(365),
+            access_log_flush_secs: var("FREEBUCKET_ACCESS_LOG_FLUSH_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|s| *s > 0)
+                .unwrap_or(300),
+            access_log_batch_size: var("FREEBUCKET_ACCESS_LOG_BATCH_SIZE")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(1000),
+            reconcile_mode: var("FREEBUCKET_RECONCILE")
+                .ok()
+                .and_then(|m| ReconcileMode::parse(&m))
+                .unwrap_or(ReconcileMode::Startup),
+            reconcile_max_files: var("FREEBUCKET_RECONCILE_MAX_FILES")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(10_000),
+            repair_corrupt_meta: var("FREEBUCKET_REPAIR_CORRUPT_META")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(true),
+            log_exclude_paths: var("FREEBUCKET_LOG_EXCLUDE")
+                .unwrap_or_else(|_| "/healthz,/readyz,/api/metrics,/api/dashboard-data".to_string())
+                .split(',')
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect(),
+            log_sample_rate: var("FREEBUCKET_LOG_SAMPLE_RATE")
+                .ok()
+                .and_then(|r| r.parse::<f64>().ok())
+                .map(|r| r.clamp(0.0, 1.0))
+                .unwrap_or(1.0),
+            max_concurrent_uploads: var("FREEBUCKET_MAX_UPLOADS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(8),
+            upload_wait_secs: var("FREEBUCKET_UPLOAD_WAIT_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(30),
+            write_buffer_size: var("FREEBUCKET_WRITE_BUFFER_KB")
+                .ok()
+                .and_then(|n| parse_size_setting(&n, KIB).ok())
+                .filter(|n| *n > 0)
+                .map_or(16 * KIB as usize, |n| usize::try_from(n).unwrap_or(usize::MAX)),
+            delete_policy: var("FREEBUCKET_DELETE_WHILE_READING")
+                .ok()
+                .and_then(|p| DeletePolicy::parse(&p))
+                .unwrap_or(DeletePolicy::Wait),
+            delete_wait_ms: var("FREEBUCKET_DELETE_WAIT_MS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(2000),
+            reserved_bucket_names: var("FREEBUCKET_RESERVED_BUCKETS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|n| n.trim().to_ascii_lowercase())
+                .filter(|n| !n.is_empty())
+                .collect(),
+            auto_create_buckets: var("FREEBUCKET_AUTO_CREATE_BUCKETS")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            default_buckets: var("FREEBUCKET_DEFAULT_BUCKETS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .collect(),
+            walk_threads: var("FREEBUCKET_WALK_THREADS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(4),
+            idempotency_ttl_secs: var("FREEBUCKET_IDEMPOTENCY_TTL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(24 * 60 * 60),
+            idempotency_cache_size: var("FREEBUCKET_IDEMPOTENCY_CACHE_SIZE")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(1000),
+            idempotency_spill: var("FREEBUCKET_IDEMPOTENCY_SPILL")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            lock_persist: var("FREEBUCKET_LOCKS_PERSIST")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            lock_max_ttl_secs: var("FREEBUCKET_LOCK_MAX_TTL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(60 * 60),
+            access_tracking: var("FREEBUCKET_ACCESS_TRACKING")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(true),
+            access_flush_secs: var("FREEBUCKET_ACCESS_FLUSH_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|s| *s > 0)
+                .unwrap_or(60),
+            stats_compact_secs: var("FREEBUCKET_STATS_COMPACT_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|s| *s > 0)
+                .unwrap_or(300),
+            alert_check_secs: var("FREEBUCKET_ALERT_CHECK_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|s| *s > 0)
+                .unwrap_or(60),
+            range_writes: var("FREEBUCKET_RANGE_WRITES")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            normalize_keys: var("FREEBUCKET_NORMALIZE_KEYS")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(true),
+            max_key_depth: var("FREEBUCKET_MAX_KEY_DEPTH")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(128),
+            key_case: var("FREEBUCKET_KEY_CASE")
+                .ok()
+                .and_then(|m| KeyCaseMode::parse(&m))
+                .unwrap_or(KeyCaseMode::Reject),
+            io_retry_attempts: var("FREEBUCKET_IO_RETRY_ATTEMPTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(3),
+            io_retry_backoff_ms: var("FREEBUCKET_IO_RETRY_BACKOFF_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(25),
+            grpc_port: var("FREEBUCKET_GRPC_PORT").ok().and_then(|s| s.parse().ok()),
+            webdav: var("FREEBUCKET_WEBDAV")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            default_max_objects: var("FREEBUCKET_DEFAULT_MAX_OBJECTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|n| *n > 0),
+            slow_op_ms: var("FREEBUCKET_SLOW_OP_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1000),
+            deleted_bucket_retention_days: var("FREEBUCKET_DELETED_BUCKET_RETENTION_DAYS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(30),
+            log_filter: var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_FILTER.to_string()),
+            readyz_self_test_secs: var("FREEBUCKET_READYZ_SELF_TEST_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            volumes: var("FREEBUCKET_VOLUMES")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|v| v.split_once('='))
+                .map(|(name, path)| (name.trim().to_string(), path.trim().to_string()))
+                .collect(),
+            fake_clock: var("FREEBUCKET_FAKE_CLOCK")
+                .ok()
+                .and_then(|s| DateTime::parse_from_rfc3339(s.trim()).ok())
+                .map(|t| t.with_timezone(&Utc)),
+            fake_clock_step_ms: var("FREEBUCKET_FAKE_CLOCK_STEP_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            fake_ids: var("FREEBUCKET_FAKE_IDS")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            size_units: var("FREEBUCKET_SIZE_UNITS")
+                .ok()
+                .and_then(|u| SizeUnits::parse(&u))
+                .unwrap_or(SizeUnits::Binary),
+            metadata_limits: {
+                let defaults = MetadataLimits::default();
+                let limit = |name: &str, default: usize| {
+                    var(name)
+                        .ok()
+                        .and_then(|n| parse_size_setting(&n, 1).ok())
+                        .map_or(default, |n| n.min(usize::MAX as u64) as usize)
+                };
+                MetadataLimits {
+                    max_total: limit("FREEBUCKET_MAX_METADATA_SIZE", defaults.max_total),
+                    max_name: limit("FREEBUCKET_MAX_METADATA_NAME", defaults.max_name),
+                    max_value: limit("FREEBUCKET_MAX_METADATA_VALUE", defaults.max_value),
+                }
+            },
+            s3_mounts: var("FREEBUCKET_S3_MOUNTS")
+                .ok()
+                .and_then(|m| parse_mounts(&m).ok())
+                .unwrap_or_else(|| DEFAULT_MOUNTS.iter().map(|m| m.to_string()).collect()),
+            read_cache_size: var("FREEBUCKET_READ_CACHE_SIZE")
+                .ok()
+                .and_then(|n| parse_size_setting(&n, 1).ok())
+                .unwrap_or(0),
+            read_cache_max_object: var("FREEBUCKET_READ_CACHE_MAX_OBJECT")
+                .ok()
+                .and_then(|n| parse_size_setting(&n, 1).ok())
+                .unwrap_or(64 * KIB),
+            exec_hook_allow: var("FREEBUCKET_EXEC_HOOK_ALLOW")
+                .unwrap_or_default()
+                .split(',')
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect(),
+            exec_hook_workers: var("FREEBUCKET_EXEC_HOOK_WORKERS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(4),
+            hash_mode: var("FREEBUCKET_HASH_MODE")
+                .ok()
+                .and_then(|m| HashMode::parse(&m))
+                .unwrap_or(HashMode::Inline),
+            audit_log_max_size: var("FREEBUCKET_AUDIT_LOG_MAX_SIZE")
+                .ok()
+                .and_then(|n| parse_size_setting(&n, 1).ok())
+                .unwrap_or(10 * MIB),
+            csrf_trusted_origins: var("FREEBUCKET_CSRF_TRUSTED_ORIGINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|o| o.trim().trim_end_matches('/').to_string())
+                .filter(|o| !o.is_empty())
+                .collect(),
+            record_requests: var("FREEBUCKET_RECORD_REQUESTS").ok().filter(|d| !d.is_empty()),
+            record_force: var("FREEBUCKET_RECORD_FORCE")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            record_max_body: var("FREEBUCKET_RECORD_MAX_BODY")
+                .ok()
+                .and_then(|n| parse_size_setting(&n, 1).ok())
+                .unwrap_or(64 * KIB),
+            record_max_total: var("FREEBUCKET_RECORD_MAX_TOTAL")
+                .ok()
+                .and_then(|n| parse_size_setting(&n, 1).ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(256 * MIB),
+        }
+    }
+}
+
+impl Config {
+    /// Whether the configured host only accepts connections from this machine
+    pub fn is_loopback(&self) -> bool {
+        if self.host.eq_ignore_ascii_case("localhost") {
+            return true;
+        }
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        host.parse::<std::net::IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false)
+    }
+
+    /// FreeBucket has no authentication yet, so any non-loopback bind is open
+    pub fn auth_configured(&self) -> bool {
+        false
+    }
+
+    pub fn gc_options(&self, dry_run: bool) -> GcOptions {
+        GcOptions {
+            dry_run,
+            multipart_expiry: Duration::from_secs(self.multipart_expiry_hours * 60 * 60),
+            deleted_bucket_retention: Duration::from_secs(self.deleted_bucket_retention_days * 24 * 60 * 60),
+        }
+    }
+
+    pub fn storage_options(&self) -> StorageOptions {
+        StorageOptions {
+            reconcile: ReconcileOptions {
+                mode: self.reconcile_mode,
+                max_files: self.reconcile_max_files,
+                repair_corrupt_meta: self.repair_corrupt_meta,
+            },
+            write_buffer_size: self.write_buffer_size,
+            delete_policy: self.delete_policy,
+            delete_wait: Duration::from_millis(self.delete_wait_ms),
+            reserved_bucket_names: self.reserved_bucket_names.clone(),
+            walk_threads: self.walk_threads,
+            access_tracking: self.access_tracking,
+            normalize_keys: self.normalize_keys,
+            max_key_depth: self.max_key_depth,
+            key_case: self.key_case,
+            io_retry: IoRetry {
+                attempts: self.io_retry_attempts,
+                backoff: Duration::from_millis(self.io_retry_backoff_ms),
+            },
+            default_max_objects: self.default_max_objects,
+            slow_op_threshold: Duration::from_millis(self.slow_op_ms),
+            volumes: self
+                .volumes
+                .iter()
+                .map(|(name, path)| (name.clone(), path.into()))
+                .collect(),
+            sources: Sources::configured(
+                self.fake_clock,
+                chrono::Duration::milliseconds(self.fake_clock_step_ms as i64),
+                self.fake_ids,
+            ),
+            metadata_limits: self.metadata_limits,
+            read_cache_size: self.read_cache_size,
+            read_cache_max_object: self.read_cache_max_object,
+            hash_mode: self.hash_mode,
+            audit_log_max_size: self.audit_log_max_size,
+        }
+    }
+
+    /// True when the API is reachable from other machines without credentials
+    pub fn is_exposed(&self) -> bool {
+        !self.is_loopback() && !self.auth_configured()
+    }
+}
+
+/// Parse a config file of `KEY=value` lines. Blank lines and lines starting
+/// with `#` are skipped, and a value may be wrapped in double quotes.
+fn read_config_file(path: &str) -> Result<HashMap<String, String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut settings = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("{}:{}: expected KEY=value", path, n + 1))?;
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        settings.insert(key.trim().to_string(), value.to_string());
+    }
+    Ok(settings)
+}
+
+/// Settings given on the command line, or by a socket from systemd. They
+/// win over the environment and the config file, at startup and on reload.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigOverrides {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub data_dir: Option<String>,
+    pub allow_open_bind: bool,
+    pub grpc_port: Option<u16>,
+    pub record_requests: Option<String>,
+    pub record_force: bool,
+}
+
+impl ConfigOverrides {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(host) = &self.host {
+            config.host = host.clone();
+        }
+        config.port = self.port.unwrap_or(config.port);
+        if let Some(dir) = &self.data_dir {
+            config.data_dir = dir.clone();
+        }
+        config.allow_open_bind |= self.allow_open_bind;
+        config.grpc_port = self.grpc_port.or(config.grpc_port);
+        if let Some(dir) = &self.record_requests {
+            config.record_requests = Some(dir.clone());
         }
+        config.record_force |= self.record_force;
     }
 }
@@ -0,0 +1,168 @@
+//! Cross-site request forgery protection. Any site open in the same browser
+//! can send requests to a FreeBucket on localhost, so API, S3, and WebDAV
+//! requests that change something and come from a browser must carry the
+//! token the
+//! dashboard hands its own page script. The token travels in a SameSite
+//! cookie, which other sites can neither read nor have sent along. Clients
+//! that aren't browsers, such as SDKs, the CLI, and curl, send none of the
+//! headers a browser adds and are left alone.
+
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::error::AppError;
+use crate::AppState;
+
+pub const TOKEN_COOKIE: &str = "freebucket_csrf";
+pub const TOKEN_HEADER: &str = "x-csrf-token";
+
+/// The token for this run of the server. It is random even under
+/// `FREEBUCKET_FAKE_IDS`, since a predictable one would protect nothing.
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    pub fn new() -> Self {
+        Self(uuid::Uuid::new_v4().simple().to_string())
+    }
+
+    /// `Set-Cookie` value handing the token to the dashboard. The page
+    /// script reads it, so it can't be `HttpOnly`.
+    pub fn cookie(&self) -> String {
+        format!("{}={}; Path=/; SameSite=Strict", TOKEN_COOKIE, self.0)
+    }
+
+    /// Compared in constant time, so response timing gives nothing away
+    fn matches(&self, given: &[u8]) -> bool {
+        let expected = self.0.as_bytes();
+        expected.len() == given.len() && expected.iter().zip(given).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+/// Browsers add at least one of these to every fetch; other clients
+/// normally send none
+fn from_browser(headers: &HeaderMap) -> bool {
+    [header::ORIGIN.as_str(), "sec-fetch-site", "x-requested-with", header::COOKIE.as_str()]
+        .iter()
+        .any(|name| headers.contains_key(*name))
+}
+
+/// Path prefixes of the routes that can change data
+const GUARDED: &[&str] = &["/api", "/s3", "/dav"];
+
+/// Routes outside the check: reads (a WebDAV `PROPFIND` is one), pages
+/// outside the API, S3, and WebDAV, and the upload inbox, which takes
+/// anonymous uploads from any page by design
+fn checked(method: &Method, path: &str) -> bool {
+    let read = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) || method.as_str() == "PROPFIND";
+    let guarded = GUARDED
+        .iter()
+        .any(|prefix| path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')));
+    !read && guarded && !path.starts_with("/api/inbox/")
+}
+
+/// Refuse browser requests that change something without the dashboard's
+/// token, unless they come from an origin in `FREEBUCKET_CSRF_TRUSTED_ORIGINS`
+pub async fn check_csrf(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let headers = req.headers();
+    if !checked(req.method(), req.uri().path()) || !from_browser(headers) {
+        return next.run(req).await;
+    }
+    let origin = headers.get(header::ORIGIN).and_then(|o| o.to_str().ok());
+    if matches!(origin, Some(origin) if state.config().csrf_trusted_origins.iter().any(|t| t == origin)) {
+        return next.run(req).await;
+    }
+    let rejected = match headers.get(TOKEN_HEADER) {
+        None => "Requests from a browser that change data must carry the X-CSRF-Token header from the dashboard",
+        Some(token) if !state.csrf.matches(token.as_bytes()) => {
+            "The X-CSRF-Token header does not match; reload the dashboard to get the current token"
+        }
+        Some(_) => return next.run(req).await,
+    };
+    tracing::warn!(
+        "Refused {} {} from {} without a valid CSRF token",
+        req.method(),
+        req.uri().path(),
+        origin.unwrap_or("a browser")
+    );
+    AppError::InvalidCsrfToken(rejected.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    fn put(uri: &str, headers: &[(&str, &str)]) -> Request {
+        let mut req = Request::put(uri);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(Body::from("forged")).unwrap()
+    }
+
+    #[test]
+    fn tokens_compare_whole() {
+        let token = CsrfToken::new();
+        assert!(token.matches(token.0.as_bytes()));
+        assert!(!token.matches(&token.0.as_bytes()[1..]));
+        assert!(!token.matches(b""));
+        let mut wrong = token.0.clone().into_bytes();
+        wrong[0] ^= 1;
+        assert!(!token.matches(&wrong));
+    }
+
+    #[test]
+    fn only_writes_to_api_s3_and_dav_are_checked() {
+        assert!(checked(&Method::POST, "/api/buckets"));
+        assert!(checked(&Method::PUT, "/s3/photos/a.txt"));
+        assert!(checked(&Method::DELETE, "/s3/photos"));
+        assert!(checked(&Method::from_bytes(b"MOVE").unwrap(), "/dav/photos/a.txt"));
+        assert!(checked(&Method::from_bytes(b"PROPPATCH").unwrap(), "/dav"));
+        assert!(!checked(&Method::from_bytes(b"PROPFIND").unwrap(), "/dav/photos"));
+        assert!(!checked(&Method::GET, "/s3/photos/a.txt"));
+        assert!(!checked(&Method::POST, "/api/inbox/drop/upload"));
+        assert!(!checked(&Method::POST, "/share/abc"));
+        assert!(!checked(&Method::POST, "/s3x/photos"));
+    }
+
+    #[tokio::test]
+    async fn forged_s3_and_dav_writes_are_refused() {
+        let server = TestServer::with_config(|config| config.webdav = true);
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        let forged = [("origin", "http://evil.example")];
+        for uri in ["/s3/photos/a.txt", "/dav/photos/a.txt", "/api/object/photos/a.txt"] {
+            let res = server.send(put(uri, &forged)).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", uri);
+        }
+        let res = server
+            .send(Request::delete("/s3/photos").header("sec-fetch-site", "cross-site").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(server.state.storage.get_bucket("photos").is_ok());
+        assert!(server.state.storage.get_object_meta("photos", "a.txt").is_err());
+    }
+
+    #[tokio::test]
+    async fn token_trusted_origin_and_plain_clients_get_through() {
+        let server = TestServer::with_config(|config| {
+            config.csrf_trusted_origins = vec!["http://localhost:5173".to_string()];
+        });
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        let token = server.state.csrf.0.clone();
+        let with_token = [("origin", "http://localhost:3210"), (TOKEN_HEADER, token.as_str())];
+        let wrong_token = [("origin", "http://localhost:3210"), (TOKEN_HEADER, "nope")];
+        let trusted = [("origin", "http://localhost:5173")];
+
+        assert_eq!(server.send(put("/s3/photos/a.txt", &with_token)).await.status(), StatusCode::OK);
+        assert_eq!(server.send(put("/s3/photos/b.txt", &trusted)).await.status(), StatusCode::OK);
+        assert_eq!(server.send(put("/s3/photos/c.txt", &[])).await.status(), StatusCode::OK);
+        assert_eq!(server.send(put("/s3/photos/d.txt", &wrong_token)).await.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -1,1242 +1,645 @@
 use std::sync::Arc;
 
+use askama::Template;
 use axum::{
-    Router,
-    extract::State,
-    response::Html,
+    Form, Router,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
     routing::get,
 };
+use serde::Deserialize;
 
+use crate::error::AppError;
+use crate::i18n::{LocaleOption, Messages};
+use crate::models::{AlertMetric, AuditEntry, AuditOutcome, Bucket, BucketStats, Share, StorageStats, UsageAlert};
+use crate::size::SizeUnits;
 use crate::AppState;
 
-pub fn routes() -> Router<Arc<AppState>> {
-    Router::new().route("/", get(dashboard_page))
-}
+/// Audit log entries shown under "Recent admin activity"
+pub const AUDIT_ENTRIES: usize = 8;
 
-async fn dashboard_page(State(state): State<Arc<AppState>>) -> Html<String> {
-    let stats = state.storage.get_stats();
-    let buckets = state.storage.list_buckets();
-    let port = state.config.port;
+/// Files a shared prefix's page lists at most
+const SHARE_LIST_MAX: u32 = 1000;
 
-    Html(render_dashboard(port, &stats, &buckets))
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(dashboard_page))
+        .route("/inbox/:bucket", get(inbox_page))
+        .route("/share/:token", get(share_page).post(unlock_share))
 }
 
-fn render_dashboard(
+/// The dashboard's first paint; the page script keeps it current after that
+#[derive(Template)]
+#[template(path = "dashboard.html")]
+struct DashboardTemplate<'a> {
+    t: Messages,
+    /// Offered by the language switcher
+    locales: Vec<LocaleOption>,
+    /// The catalog again, for the page script
+    messages_json: String,
     port: u16,
-    stats: &crate::models::StorageStats,
-    buckets: &[crate::models::Bucket],
-) -> String {
-    let bucket_cards: String = buckets
-        .iter()
-        .map(|b| {
-            format!(
-                r#"
-                <div class="bucket-card" onclick="openBucket('{name}')">
-                    <div class="bucket-card-header">
-                        <div class="bucket-icon">
-                            <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2">
-                                <path d="M2 7V17C2 19 4 21 8 21H16C20 21 22 19 22 17V7"/>
-                                <path d="M2 7L5 3H19L22 7"/>
-                                <path d="M2 7H22"/>
-                                <path d="M9 11H15"/>
-                            </svg>
-                        </div>
-                        <button class="btn-icon delete-btn" onclick="event.stopPropagation(); deleteBucket('{name}')" title="Delete bucket">
-                            <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2">
-                                <path d="M3 6h18M8 6V4a2 2 0 012-2h4a2 2 0 012 2v2M19 6l-1 14a2 2 0 01-2 2H8a2 2 0 01-2-2L5 6"/>
-                                <path d="M10 11v6M14 11v6"/>
-                            </svg>
-                        </button>
-                    </div>
-                    <h3 class="bucket-name">{name}</h3>
-                    <div class="bucket-meta">
-                        <span class="meta-item">
-                            <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="14" height="14">
-                                <path d="M14 2H6a2 2 0 00-2 2v16a2 2 0 002 2h12a2 2 0 002-2V8z"/>
-                                <path d="M14 2v6h6"/>
-                            </svg>
-                            {count} objects
-                        </span>
-                        <span class="meta-item">
-                            <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="14" height="14">
-                                <path d="M21 16V8a2 2 0 00-1-1.73l-7-4a2 2 0 00-2 0l-7 4A2 2 0 002 8v8a2 2 0 001 1.73l7 4a2 2 0 002 0l7-4A2 2 0 0022 16z"/>
-                            </svg>
-                            {size}
-                        </span>
-                    </div>
-                    <div class="bucket-region">{region}</div>
-                </div>"#,
-                name = b.name,
-                count = b.object_count,
-                size = crate::storage::human_readable_size(b.total_size),
-                region = b.region
-            )
-        })
-        .collect();
-
-    format!(
-        r##"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta charset="UTF-8">
-    <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>FreeBucket — Local Storage Dashboard</title>
-    <meta name="description" content="FreeBucket: A local S3-compatible object storage service dashboard">
-    <link rel="preconnect" href="https://fonts.googleapis.com">
-    <link rel="preconnect" href="https://fonts.gstatic.com" crossorigin>
-    <link href="https://fonts.googleapis.com/css2?family=Inter:wght@300;400;500;600;700;800&family=JetBrains+Mono:wght@400;500&display=swap" rel="stylesheet">
-    <style>
-        :root {{
-            --bg-primary: #FDF6E3;
-            --bg-secondary: #FFF9ED;
-            --bg-card: #FFFFFF;
-            --bg-card-hover: #FFF5D6;
-            --bg-input: #FFFCF5;
-            --border-color: #E8DFC8;
-            --border-hover: #D4C9A8;
-            --text-primary: #3D3322;
-            --text-secondary: #7A6E56;
-            --text-muted: #A89B7E;
-            --accent-primary: #C8842E;
-            --accent-primary-hover: #E09A3A;
-            --accent-glow: rgba(200, 132, 46, 0.2);
-            --accent-secondary: #8B7355;
-            --accent-success: #5D8C3E;
-            --accent-warning: #D4940A;
-            --accent-danger: #C05040;
-            --accent-danger-hover: #D46050;
-            --gradient-primary: linear-gradient(135deg, #C8842E, #E0A84C);
-            --gradient-card: linear-gradient(145deg, #FFFFFF, #FFFAF0);
-            --shadow-sm: 0 1px 4px rgba(60,50,30,0.08);
-            --shadow-md: 0 4px 16px rgba(60,50,30,0.1);
-            --shadow-lg: 0 8px 32px rgba(60,50,30,0.12);
-            --shadow-glow: 0 0 16px var(--accent-glow);
-            --radius-sm: 10px;
-            --radius-md: 14px;
-            --radius-lg: 18px;
-            --radius-xl: 24px;
-        }}
-
-        * {{ margin:0; padding:0; box-sizing:border-box; }}
-
-        body {{
-            font-family: 'Inter', -apple-system, BlinkMacSystemFont, sans-serif;
-            background: var(--bg-primary);
-            color: var(--text-primary);
-            min-height: 100vh;
-            overflow-x: hidden;
-        }}
-
-        /* Soft warm background wash */
-        body::before {{
-            content: '';
-            position: fixed;
-            top: 0; left: 0; right: 0; bottom: 0;
-            background:
-                radial-gradient(ellipse 70% 50% at 15% 10%, rgba(248,220,160,0.35), transparent),
-                radial-gradient(ellipse 50% 40% at 85% 85%, rgba(230,200,140,0.2), transparent);
-            pointer-events: none;
-            z-index: 0;
-        }}
-
-        /* Header */
-        .header {{
-            background: rgba(255,250,235,0.85);
-            backdrop-filter: blur(16px);
-            border-bottom: 1px solid var(--border-color);
-            padding: 0 2rem;
-            height: 64px;
-            display: flex;
-            align-items: center;
-            justify-content: space-between;
-            position: sticky;
-            top: 0;
-            z-index: 100;
-        }}
-
-        .logo {{
-            display: flex;
-            align-items: center;
-            gap: 12px;
-        }}
-
-        .logo-icon {{
-            width: 36px;
-            height: 36px;
-            border-radius: var(--radius-sm);
-            background: var(--gradient-primary);
-            display: flex;
-            align-items: center;
-            justify-content: center;
-            box-shadow: var(--shadow-glow);
-        }}
-
-        .logo-icon svg {{
-            width: 20px;
-            height: 20px;
-            color: white;
-        }}
-
-        .logo-text {{
-            font-size: 1.25rem;
-            font-weight: 700;
-            background: var(--gradient-primary);
-            -webkit-background-clip: text;
-            -webkit-text-fill-color: transparent;
-            background-clip: text;
-        }}
-
-        .logo-badge {{
-            font-size: 0.65rem;
-            padding: 2px 8px;
-            border-radius: 20px;
-            background: rgba(200,132,46,0.12);
-            color: var(--accent-primary);
-            font-weight: 600;
-            letter-spacing: 0.5px;
-            text-transform: uppercase;
-        }}
-
-        .header-actions {{
-            display: flex;
-            align-items: center;
-            gap: 12px;
-        }}
-
-        /* Main Content */
-        .main {{
-            position: relative;
-            z-index: 1;
-            max-width: 1400px;
-            margin: 0 auto;
-            padding: 2rem;
-        }}
-
-        /* Stats Cards */
-        .stats-grid {{
-            display: grid;
-            grid-template-columns: repeat(auto-fit, minmax(240px, 1fr));
-            gap: 1.25rem;
-            margin-bottom: 2rem;
-        }}
-
-        .stat-card {{
-            background: var(--gradient-card);
-            border: 1px solid var(--border-color);
-            border-radius: var(--radius-lg);
-            padding: 1.5rem;
-            transition: all 0.3s ease;
-        }}
-
-        .stat-card:hover {{
-            border-color: var(--border-hover);
-            transform: translateY(-2px);
-            box-shadow: var(--shadow-md);
-        }}
-
-        .stat-label {{
-            font-size: 0.8rem;
-            color: var(--text-muted);
-            text-transform: uppercase;
-            letter-spacing: 1px;
-            font-weight: 600;
-            margin-bottom: 0.5rem;
-        }}
-
-        .stat-value {{
-            font-size: 2rem;
-            font-weight: 800;
-            background: var(--gradient-primary);
-            -webkit-background-clip: text;
-            -webkit-text-fill-color: transparent;
-            background-clip: text;
-        }}
-
-        .stat-sub {{
-            font-size: 0.8rem;
-            color: var(--text-secondary);
-            margin-top: 0.25rem;
-        }}
-
-        /* Section */
-        .section {{
-            margin-bottom: 2rem;
-        }}
-
-        .section-header {{
-            display: flex;
-            align-items: center;
-            justify-content: space-between;
-            margin-bottom: 1.25rem;
-        }}
-
-        .section-title {{
-            font-size: 1.35rem;
-            font-weight: 700;
-            color: var(--text-primary);
-        }}
-
-        /* Buttons */
-        .btn {{
-            display: inline-flex;
-            align-items: center;
-            gap: 8px;
-            padding: 10px 20px;
-            border: none;
-            border-radius: var(--radius-sm);
-            font-size: 0.875rem;
-            font-weight: 600;
-            font-family: inherit;
-            cursor: pointer;
-            transition: all 0.2s ease;
-        }}
-
-        .btn-primary {{
-            background: var(--gradient-primary);
-            color: white;
-            box-shadow: var(--shadow-sm);
-        }}
-
-        .btn-primary:hover {{
-            transform: translateY(-1px);
-            box-shadow: var(--shadow-glow);
-        }}
-
-        .btn-secondary {{
-            background: var(--bg-card);
-            color: var(--text-primary);
-            border: 1px solid var(--border-color);
-        }}
-
-        .btn-secondary:hover {{
-            border-color: var(--border-hover);
-            background: var(--bg-card-hover);
-        }}
-
-        .btn-danger {{
-            background: var(--accent-danger);
-            color: white;
-        }}
-
-        .btn-danger:hover {{
-            background: var(--accent-danger-hover);
-        }}
-
-        .btn-icon {{
-            width: 32px;
-            height: 32px;
-            display: flex;
-            align-items: center;
-            justify-content: center;
-            border: none;
-            border-radius: var(--radius-sm);
-            background: transparent;
-            color: var(--text-muted);
-            cursor: pointer;
-            transition: all 0.2s ease;
-        }}
-
-        .btn-icon:hover {{
-            background: rgba(255,255,255,0.06);
-            color: var(--text-primary);
-        }}
-
-        .delete-btn:hover {{
-            color: var(--accent-danger);
-            background: rgba(192,80,64,0.08);
-        }}
-
-        .btn-icon svg {{
-            width: 16px;
-            height: 16px;
-        }}
-
-        /* Bucket Grid */
-        .bucket-grid {{
-            display: grid;
-            grid-template-columns: repeat(auto-fill, minmax(300px, 1fr));
-            gap: 1.25rem;
-        }}
-
-        .bucket-card {{
-            background: var(--gradient-card);
-            border: 1px solid var(--border-color);
-            border-radius: var(--radius-lg);
-            padding: 1.5rem;
-            cursor: pointer;
-            transition: all 0.3s cubic-bezier(0.4, 0, 0.2, 1);
-            position: relative;
-            overflow: hidden;
-        }}
-
-        .bucket-card::before {{
-            content: '';
-            position: absolute;
-            top: 0;
-            left: 0;
-            right: 0;
-            height: 3px;
-            background: var(--gradient-primary);
-            opacity: 0;
-            transition: opacity 0.3s ease;
-        }}
-
-        .bucket-card:hover {{
-            border-color: var(--border-hover);
-            transform: translateY(-4px);
-            box-shadow: var(--shadow-lg);
-        }}
-
-        .bucket-card:hover::before {{
-            opacity: 1;
-        }}
-
-        .bucket-card-header {{
-            display: flex;
-            align-items: flex-start;
-            justify-content: space-between;
-            margin-bottom: 1rem;
-        }}
-
-        .bucket-icon {{
-            width: 44px;
-            height: 44px;
-            border-radius: var(--radius-md);
-            background: rgba(200,132,46,0.1);
-            display: flex;
-            align-items: center;
-            justify-content: center;
-            color: var(--accent-primary);
-        }}
-
-        .bucket-icon svg {{
-            width: 22px;
-            height: 22px;
-        }}
-
-        .bucket-name {{
-            font-size: 1.1rem;
-            font-weight: 700;
-            color: var(--text-primary);
-            margin-bottom: 0.75rem;
-            font-family: 'JetBrains Mono', monospace;
-        }}
-
-        .bucket-meta {{
-            display: flex;
-            gap: 1rem;
-            margin-bottom: 0.5rem;
-        }}
-
-        .meta-item {{
-            display: flex;
-            align-items: center;
-            gap: 6px;
-            font-size: 0.8rem;
-            color: var(--text-secondary);
-        }}
-
-        .bucket-region {{
-            font-size: 0.75rem;
-            color: var(--text-muted);
-            display: inline-flex;
-            align-items: center;
-            padding: 3px 10px;
-            border-radius: 20px;
-            background: rgba(139,115,85,0.08);
-            color: var(--accent-secondary);
-            margin-top: 0.5rem;
-        }}
-
-        /* Empty State */
-        .empty-state {{
-            text-align: center;
-            padding: 4rem 2rem;
-            border: 2px dashed var(--border-color);
-            border-radius: var(--radius-xl);
-            background: rgba(200,132,46,0.05);
-        }}
-
-        .empty-icon {{
-            width: 72px;
-            height: 72px;
-            margin: 0 auto 1.5rem;
-            border-radius: var(--radius-lg);
-            background: rgba(200,132,46,0.07);
-            display: flex;
-            align-items: center;
-            justify-content: center;
-            color: var(--text-muted);
-        }}
-
-        .empty-icon svg {{
-            width: 36px;
-            height: 36px;
-        }}
-
-        .empty-title {{
-            font-size: 1.25rem;
-            font-weight: 600;
-            color: var(--text-secondary);
-            margin-bottom: 0.5rem;
-        }}
-
-        .empty-desc {{
-            font-size: 0.9rem;
-            color: var(--text-muted);
-            margin-bottom: 1.5rem;
-        }}
-
-        /* Modal */
-        .modal-overlay {{
-            display: none;
-            position: fixed;
-            top: 0; left: 0; right: 0; bottom: 0;
-            background: rgba(0,0,0,0.35);
-            backdrop-filter: blur(4px);
-            z-index: 1000;
-            align-items: center;
-            justify-content: center;
-        }}
-
-        .modal-overlay.active {{
-            display: flex;
-        }}
-
-        .modal {{
-            background: var(--bg-secondary);
-            border: 1px solid var(--border-color);
-            border-radius: var(--radius-xl);
-            padding: 2rem;
-            min-width: 420px;
-            max-width: 600px;
-            width: 90%;
-            box-shadow: var(--shadow-lg);
-            animation: modalIn 0.3s cubic-bezier(0.4, 0, 0.2, 1);
-        }}
-
-        @keyframes modalIn {{
-            from {{ opacity: 0; transform: scale(0.95) translateY(10px); }}
-            to {{ opacity: 1; transform: scale(1) translateY(0); }}
-        }}
-
-        .modal-title {{
-            font-size: 1.25rem;
-            font-weight: 700;
-            margin-bottom: 1.5rem;
-        }}
-
-        .form-group {{
-            margin-bottom: 1.25rem;
-        }}
-
-        .form-label {{
-            display: block;
-            font-size: 0.8rem;
-            font-weight: 600;
-            color: var(--text-secondary);
-            text-transform: uppercase;
-            letter-spacing: 0.5px;
-            margin-bottom: 0.5rem;
-        }}
-
-        .form-input {{
-            width: 100%;
-            padding: 12px 16px;
-            background: var(--bg-input);
-            border: 1px solid var(--border-color);
-            border-radius: var(--radius-sm);
-            color: var(--text-primary);
-            font-size: 0.95rem;
-            font-family: 'JetBrains Mono', monospace;
-            transition: all 0.2s ease;
-            outline: none;
-        }}
-
-        .form-input:focus {{
-            border-color: var(--accent-primary);
-            box-shadow: 0 0 0 3px var(--accent-glow);
-        }}
-
-        .form-hint {{
-            font-size: 0.75rem;
-            color: var(--text-muted);
-            margin-top: 0.4rem;
-        }}
-
-        .modal-actions {{
-            display: flex;
-            justify-content: flex-end;
-            gap: 0.75rem;
-            margin-top: 1.5rem;
-        }}
-
-        /* Object Browser Modal */
-        .object-browser {{
-            min-width: 700px;
-            max-width: 900px;
-        }}
-
-        .object-browser-header {{
-            display: flex;
-            align-items: center;
-            justify-content: space-between;
-            margin-bottom: 1.5rem;
-            padding-bottom: 1rem;
-            border-bottom: 1px solid var(--border-color);
-        }}
-
-        .browser-title {{
-            display: flex;
-            align-items: center;
-            gap: 12px;
-        }}
-
-        .browser-title h2 {{
-            font-size: 1.2rem;
-            font-weight: 700;
-            font-family: 'JetBrains Mono', monospace;
-        }}
-
-        .object-list {{
-            max-height: 400px;
-            overflow-y: auto;
-            border: 1px solid var(--border-color);
-            border-radius: var(--radius-md);
-        }}
-
-        .object-list::-webkit-scrollbar {{
-            width: 6px;
-        }}
-
-        .object-list::-webkit-scrollbar-track {{
-            background: var(--bg-primary);
-        }}
-
-        .object-list::-webkit-scrollbar-thumb {{
-            background: var(--border-color);
-            border-radius: 3px;
-        }}
-
-        .object-row {{
-            display: grid;
-            grid-template-columns: 1fr 100px 150px 80px;
-            gap: 1rem;
-            align-items: center;
-            padding: 0.85rem 1rem;
-            border-bottom: 1px solid var(--border-color);
-            transition: background 0.15s ease;
-        }}
-
-        .object-row:last-child {{
-            border-bottom: none;
-        }}
-
-        .object-row:hover {{
-            background: rgba(200,132,46,0.04);
-        }}
-
-        .object-row-header {{
-            font-size: 0.75rem;
-            font-weight: 600;
-            color: var(--text-muted);
-            text-transform: uppercase;
-            letter-spacing: 0.5px;
-            background: rgba(200,132,46,0.05);
-        }}
-
-        .object-row-header:hover {{
-            background: rgba(200,132,46,0.05);
-        }}
-
-        .object-key {{
-            font-family: 'JetBrains Mono', monospace;
-            font-size: 0.85rem;
-            color: var(--text-primary);
-            overflow: hidden;
-            text-overflow: ellipsis;
-            white-space: nowrap;
-        }}
-
-        .object-size {{
-            font-size: 0.8rem;
-            color: var(--text-secondary);
-            text-align: right;
-        }}
-
-        .object-date {{
-            font-size: 0.8rem;
-            color: var(--text-muted);
-        }}
-
-        .object-actions {{
-            display: flex;
-            justify-content: flex-end;
-            gap: 4px;
-        }}
-
-        .empty-objects {{
-            text-align: center;
-            padding: 3rem 2rem;
-            color: var(--text-muted);
-        }}
-
-        .empty-objects svg {{
-            width: 40px;
-            height: 40px;
-            margin-bottom: 1rem;
-            opacity: 0.4;
-        }}
-
-        /* Upload area */
-        .upload-area {{
-            border: 2px dashed var(--border-color);
-            border-radius: var(--radius-md);
-            padding: 2rem;
-            text-align: center;
-            margin-top: 1rem;
-            transition: all 0.3s ease;
-            cursor: pointer;
-        }}
-
-        .upload-area:hover,
-        .upload-area.drag-over {{
-            border-color: var(--accent-primary);
-            background: rgba(200,132,46,0.04);
-        }}
-
-        .upload-area svg {{
-            width: 32px;
-            height: 32px;
-            color: var(--text-muted);
-            margin-bottom: 0.75rem;
-        }}
-
-        .upload-area p {{
-            color: var(--text-secondary);
-            font-size: 0.9rem;
-        }}
-
-        .upload-area .upload-hint {{
-            color: var(--text-muted);
-            font-size: 0.8rem;
-            margin-top: 0.5rem;
-        }}
-
-        /* Toast Notifications */
-        .toast-container {{
-            position: fixed;
-            bottom: 2rem;
-            right: 2rem;
-            z-index: 2000;
-            display: flex;
-            flex-direction: column;
-            gap: 0.5rem;
-        }}
-
-        .toast {{
-            padding: 1rem 1.5rem;
-            border-radius: var(--radius-md);
-            font-size: 0.9rem;
-            font-weight: 500;
-            color: white;
-            box-shadow: var(--shadow-lg);
-            animation: toastIn 0.3s ease, toastOut 0.3s ease 2.7s forwards;
-            display: flex;
-            align-items: center;
-            gap: 10px;
-            min-width: 300px;
-        }}
-
-        .toast.success {{
-            background: linear-gradient(135deg, #4a8c2e, #5D8C3E);
-        }}
-
-        .toast.error {{
-            background: linear-gradient(135deg, #b04030, #C05040);
-        }}
-
-        .toast.info {{
-            background: linear-gradient(135deg, #b07820, #C8842E);
-        }}
-
-        @keyframes toastIn {{
-            from {{ opacity: 0; transform: translateX(100px); }}
-            to {{ opacity: 1; transform: translateX(0); }}
-        }}
-
-        @keyframes toastOut {{
-            from {{ opacity: 1; transform: translateX(0); }}
-            to {{ opacity: 0; transform: translateX(100px); }}
-        }}
-
-        /* Responsive */
-        @media (max-width: 768px) {{
-            .main {{ padding: 1rem; }}
-            .bucket-grid {{ grid-template-columns: 1fr; }}
-            .stats-grid {{ grid-template-columns: repeat(2, 1fr); }}
-            .modal {{ min-width: auto; }}
-            .object-browser {{ min-width: auto; }}
-            .object-row {{ grid-template-columns: 1fr 80px 60px; }}
-            .object-date {{ display: none; }}
-        }}
-
-        /* Loading spinner */
-        .spinner {{
-            width: 20px;
-            height: 20px;
-            border: 2px solid rgba(255,255,255,0.3);
-            border-top-color: white;
-            border-radius: 50%;
-            animation: spin 0.6s linear infinite;
-        }}
-
-        @keyframes spin {{
-            to {{ transform: rotate(360deg); }}
-        }}
-    </style>
-</head>
-<body>
-    <!-- Header -->
-    <header class="header">
-        <div class="logo">
-            <div class="logo-icon">
-                <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2">
-                    <path d="M2 7V17C2 19 4 21 8 21H16C20 21 22 19 22 17V7"/>
-                    <path d="M2 7L5 3H19L22 7"/>
-                    <path d="M2 7H22"/>
-                </svg>
-            </div>
-            <span class="logo-text">FreeBucket</span>
-            <span class="logo-badge">Local</span>
-        </div>
-        <div class="header-actions">
-            <button class="btn btn-secondary" onclick="location.reload()">
-                <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="16" height="16">
-                    <path d="M23 4v6h-6M1 20v-6h6"/>
-                    <path d="M3.51 9a9 9 0 0114.85-3.36L23 10M1 14l4.64 4.36A9 9 0 0020.49 15"/>
-                </svg>
-                Refresh
-            </button>
-        </div>
-    </header>
-
-    <!-- Main Content -->
-    <main class="main">
-        <!-- Stats -->
-        <div class="stats-grid">
-            <div class="stat-card">
-                <div class="stat-label">Total Buckets</div>
-                <div class="stat-value" id="stat-buckets">{total_buckets}</div>
-                <div class="stat-sub">Storage containers</div>
-            </div>
-            <div class="stat-card">
-                <div class="stat-label">Total Objects</div>
-                <div class="stat-value" id="stat-objects">{total_objects}</div>
-                <div class="stat-sub">Files stored</div>
-            </div>
-            <div class="stat-card">
-                <div class="stat-label">Storage Used</div>
-                <div class="stat-value" id="stat-size">{total_size}</div>
-                <div class="stat-sub">On local disk</div>
-            </div>
-            <div class="stat-card">
-                <div class="stat-label">API Endpoint</div>
-                <div class="stat-value" style="font-size:1rem; font-family:'JetBrains Mono',monospace;">:{port}</div>
-                <div class="stat-sub">http://localhost:{port}/api</div>
-            </div>
-        </div>
-
-        <!-- Buckets -->
-        <div class="section">
-            <div class="section-header">
-                <h2 class="section-title">Buckets</h2>
-                <button class="btn btn-primary" onclick="showCreateBucketModal()" id="create-bucket-btn">
-                    <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="16" height="16">
-                        <path d="M12 5v14M5 12h14"/>
-                    </svg>
-                    Create Bucket
-                </button>
-            </div>
-            <div id="bucket-list" class="bucket-grid">
-                {bucket_cards}
-            </div>
-            {empty_state}
-        </div>
-    </main>
-
-    <!-- Create Bucket Modal -->
-    <div class="modal-overlay" id="create-modal">
-        <div class="modal">
-            <h3 class="modal-title">Create New Bucket</h3>
-            <div class="form-group">
-                <label class="form-label" for="bucket-name-input">Bucket Name</label>
-                <input type="text" id="bucket-name-input" class="form-input"
-                    placeholder="my-awesome-bucket" autocomplete="off"
-                    pattern="[a-z0-9][a-z0-9.\-]{{2,62}}"
-                    onkeydown="if(event.key==='Enter')createBucket()">
-                <p class="form-hint">3–63 characters. Lowercase letters, numbers, hyphens, periods only.</p>
-            </div>
-            <div class="form-group">
-                <label class="form-label" for="bucket-region-input">Region</label>
-                <input type="text" id="bucket-region-input" class="form-input"
-                    placeholder="local" value="local">
-            </div>
-            <div class="modal-actions">
-                <button class="btn btn-secondary" onclick="closeModal('create-modal')">Cancel</button>
-                <button class="btn btn-primary" onclick="createBucket()" id="create-confirm-btn">Create Bucket</button>
-            </div>
-        </div>
-    </div>
-
-    <!-- Object Browser Modal -->
-    <div class="modal-overlay" id="browser-modal">
-        <div class="modal object-browser">
-            <div class="object-browser-header">
-                <div class="browser-title">
-                    <div class="bucket-icon" style="width:36px;height:36px;">
-                        <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="18" height="18">
-                            <path d="M2 7V17C2 19 4 21 8 21H16C20 21 22 19 22 17V7"/>
-                            <path d="M2 7L5 3H19L22 7"/>
-                            <path d="M2 7H22"/>
-                        </svg>
-                    </div>
-                    <h2 id="browser-bucket-name"></h2>
-                </div>
-                <div style="display:flex;gap:8px;">
-                    <button class="btn btn-primary" onclick="showUploadArea()" id="upload-btn">
-                        <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="16" height="16">
-                            <path d="M21 15v4a2 2 0 01-2 2H5a2 2 0 01-2-2v-4M17 8l-5-5-5 5M12 3v12"/>
-                        </svg>
-                        Upload
-                    </button>
-                    <button class="btn btn-secondary" onclick="closeModal('browser-modal')">Close</button>
-                </div>
-            </div>
-
-            <!-- Upload Area -->
-            <div id="upload-area" class="upload-area" style="display:none;"
-                ondragover="event.preventDefault();this.classList.add('drag-over')"
-                ondragleave="this.classList.remove('drag-over')"
-                ondrop="handleDrop(event)"
-                onclick="document.getElementById('file-input').click()">
-                <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2">
-                    <path d="M21 15v4a2 2 0 01-2 2H5a2 2 0 01-2-2v-4M17 8l-5-5-5 5M12 3v12"/>
-                </svg>
-                <p>Drag & drop files here, or click to browse</p>
-                <p class="upload-hint">Files will be uploaded to the current bucket</p>
-                <input type="file" id="file-input" multiple style="display:none" onchange="handleFileSelect(event)">
-            </div>
-
-            <!-- Object List -->
-            <div id="object-list-container">
-                <div class="object-list">
-                    <div class="object-row object-row-header">
-                        <span>Key</span>
-                        <span style="text-align:right">Size</span>
-                        <span>Last Modified</span>
-                        <span style="text-align:right">Actions</span>
-                    </div>
-                    <div id="object-list-body"></div>
-                </div>
-            </div>
-        </div>
-    </div>
-
-    <!-- Toast Container -->
-    <div class="toast-container" id="toasts"></div>
-
-    <script>
-        const API = '/api';
-        let currentBucket = '';
-
-        // ── Toast Notifications ─────────────────────────
-        function toast(message, type = 'info') {{
-            const container = document.getElementById('toasts');
-            const el = document.createElement('div');
-            el.className = 'toast ' + type;
-            const icons = {{
-                success: '<svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="18" height="18"><path d="M20 6L9 17l-5-5"/></svg>',
-                error: '<svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="18" height="18"><circle cx="12" cy="12" r="10"/><path d="M15 9l-6 6M9 9l6 6"/></svg>',
-                info: '<svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="18" height="18"><circle cx="12" cy="12" r="10"/><path d="M12 16v-4M12 8h.01"/></svg>'
-            }};
-            el.innerHTML = (icons[type] || icons.info) + '<span>' + message + '</span>';
-            container.appendChild(el);
-            setTimeout(() => el.remove(), 3000);
-        }}
-
-        // ── Modal Helpers ───────────────────────────────
-        function showModal(id) {{
-            document.getElementById(id).classList.add('active');
-        }}
-
-        function closeModal(id) {{
-            document.getElementById(id).classList.remove('active');
-        }}
-
-        // ── Bucket Operations ───────────────────────────
-        function showCreateBucketModal() {{
-            document.getElementById('bucket-name-input').value = '';
-            document.getElementById('bucket-region-input').value = 'local';
-            showModal('create-modal');
-            setTimeout(() => document.getElementById('bucket-name-input').focus(), 100);
-        }}
-
-        async function createBucket() {{
-            const name = document.getElementById('bucket-name-input').value.trim();
-            const region = document.getElementById('bucket-region-input').value.trim() || 'local';
-
-            if (!name) {{
-                toast('Please enter a bucket name', 'error');
-                return;
-            }}
-
-            try {{
-                const res = await fetch(API + '/buckets', {{
-                    method: 'POST',
-                    headers: {{ 'Content-Type': 'application/json' }},
-                    body: JSON.stringify({{ name, region }})
-                }});
-
-                if (!res.ok) {{
-                    const err = await res.json();
-                    toast(err.message || 'Failed to create bucket', 'error');
-                    return;
-                }}
-
-                toast('Bucket "' + name + '" created successfully!', 'success');
-                closeModal('create-modal');
-                location.reload();
-            }} catch (e) {{
-                toast('Network error: ' + e.message, 'error');
-            }}
-        }}
-
-        async function deleteBucket(name) {{
-            if (!confirm('Are you sure you want to delete bucket "' + name + '"? This action cannot be undone.')) return;
-
-            try {{
-                const res = await fetch(API + '/buckets/' + encodeURIComponent(name), {{
-                    method: 'DELETE'
-                }});
-
-                if (!res.ok) {{
-                    const err = await res.json();
-                    toast(err.message || 'Failed to delete bucket', 'error');
-                    return;
-                }}
-
-                toast('Bucket "' + name + '" deleted', 'success');
-                location.reload();
-            }} catch (e) {{
-                toast('Network error: ' + e.message, 'error');
-            }}
-        }}
-
-        // ── Object Operations ───────────────────────────
-        async function openBucket(name) {{
-            currentBucket = name;
-            document.getElementById('browser-bucket-name').textContent = name;
-            document.getElementById('upload-area').style.display = 'none';
-            showModal('browser-modal');
-            await refreshObjects();
-        }}
+    /// Show the banner warning that anyone on the network can change data
+    exposed: bool,
+    stats: &'a StorageStats,
+    buckets: Vec<BucketCard<'a>>,
+    /// Up to five non-empty buckets, largest first
+    top_buckets: Vec<TopBucket<'a>>,
+    /// Latest audit log entries, newest first
+    audit: Vec<AuditRow<'a>>,
+    /// "2 alerts" next to the bucket list, empty without any
+    alert_count: String,
+    /// Show sizes in KB, MB, ... rather than KiB, MiB, ...
+    decimal_sizes: bool,
+}
 
-        async function refreshObjects() {{
-            const body = document.getElementById('object-list-body');
-            body.innerHTML = '<div class="empty-objects"><div class="spinner" style="margin:0 auto"></div></div>';
+struct BucketCard<'a> {
+    bucket: &'a Bucket,
+    size: String,
+    /// Object count with its label, e.g. "3 objects"
+    objects: String,
+    /// One line per usage alert in effect on the bucket
+    alerts: Vec<String>,
+}
 
-            try {{
-                const res = await fetch(API + '/buckets/' + encodeURIComponent(currentBucket) + '/objects');
-                if (!res.ok) throw new Error('Failed to load objects');
+struct TopBucket<'a> {
+    stats: &'a BucketStats,
+    /// Bar width relative to the largest bucket, formatted for CSS
+    percent: String,
+}
 
-                const data = await res.json();
-                if (!data.objects || data.objects.length === 0) {{
-                    body.innerHTML = '<div class="empty-objects">' +
-                        '<svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2"><path d="M14 2H6a2 2 0 00-2 2v16a2 2 0 002 2h12a2 2 0 002-2V8z"/><path d="M14 2v6h6"/></svg>' +
-                        '<p>No objects in this bucket</p>' +
-                        '</div>';
-                    return;
-                }}
+struct AuditRow<'a> {
+    entry: &'a AuditEntry,
+    time: String,
+    failed: bool,
+}
 
-                body.innerHTML = data.objects.map(obj => {{
-                    const size = humanSize(obj.size);
-                    const date = new Date(obj.last_modified).toLocaleDateString();
-                    return '<div class="object-row">' +
-                        '<span class="object-key" title="' + escapeHtml(obj.key) + '">' + escapeHtml(obj.key) + '</span>' +
-                        '<span class="object-size">' + size + '</span>' +
-                        '<span class="object-date">' + date + '</span>' +
-                        '<div class="object-actions">' +
-                        '<button class="btn-icon" data-action="download" data-key="' + escapeAttr(obj.key) + '" title="Download">' +
-                        '<svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2"><path d="M21 15v4a2 2 0 01-2 2H5a2 2 0 01-2-2v-4M7 10l5 5 5-5M12 15V3"/></svg>' +
-                        '</button>' +
-                        '<button class="btn-icon delete-btn" data-action="delete" data-key="' + escapeAttr(obj.key) + '" title="Delete">' +
-                        '<svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2"><path d="M3 6h18M8 6V4a2 2 0 012-2h4a2 2 0 012 2v2M19 6l-1 14a2 2 0 01-2 2H8a2 2 0 01-2-2L5 6"/></svg>' +
-                        '</button>' +
-                        '</div></div>';
-                }}).join('');
-            }} catch (e) {{
-                body.innerHTML = '<div class="empty-objects"><p>Error loading objects</p></div>';
-                toast('Failed to load objects', 'error');
-            }}
-        }}
+/// Public drop-off page for an inbox bucket
+#[derive(Template)]
+#[template(path = "inbox.html")]
+struct InboxTemplate<'a> {
+    t: Messages,
+    messages_json: String,
+    name: &'a str,
+    title: String,
+    heading: String,
+    /// Upload limits shown under the title
+    hints: Vec<String>,
+}
 
-        async function downloadObject(key) {{
-            const url = API + '/object/' + encodePath(currentBucket) + '/' + encodePath(key);
-            const a = document.createElement('a');
-            a.href = url;
-            a.download = key.split('/').pop();
-            document.body.appendChild(a);
-            a.click();
-            a.remove();
-        }}
+/// Public download page for a share link
+#[derive(Template)]
+#[template(path = "share.html")]
+struct ShareTemplate {
+    t: Messages,
+    title: String,
+    heading: String,
+    /// Expiry and downloads left, shown under the title
+    hints: Vec<String>,
+    /// Why the page shows no files: a wrong password or an ended link
+    problem: Option<String>,
+    /// Ask for the share's password instead of showing its files
+    ask_password: bool,
+    files: Vec<SharedFile>,
+}
 
-        async function deleteObject(key) {{
-            if (!confirm('Delete object "' + key + '"?')) return;
+struct SharedFile {
+    name: String,
+    size: String,
+    /// The download link, relative to the page
+    href: String,
+}
 
-            try {{
-                const res = await fetch(API + '/object/' + encodePath(currentBucket) + '/' + encodePath(key), {{
-                    method: 'DELETE'
-                }});
+/// Render a page, or a bare 500 if a template fails at runtime
+fn render_page(template: &impl Template) -> Response {
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to render page: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("<!DOCTYPE html><html><body><h1>Page failed to render</h1></body></html>"),
+            )
+                .into_response()
+        }
+    }
+}
 
-                if (!res.ok) {{
-                    toast('Failed to delete object', 'error');
-                    return;
-                }}
+async fn dashboard_page(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let stats = state.storage.get_stats();
+    let buckets = state.storage.list_buckets();
+    let top_buckets = state.storage.bucket_stats();
+    let port = state.config().port;
+    let exposed = state.config().is_exposed();
+    let audit = state.storage.recent_audit(AUDIT_ENTRIES);
+    let alerts = state.storage.active_alerts();
+
+    let t = Messages::for_request(&headers);
+    let data = DashboardSnapshot {
+        stats: &stats,
+        buckets: &buckets,
+        top_buckets: &top_buckets,
+        audit: &audit,
+        alerts: &alerts,
+    };
+    let page = render_page(&dashboard_template(t, port, exposed, data));
+    ([(header::SET_COOKIE, state.csrf.cookie())], page).into_response()
+}
 
-                toast('Object deleted', 'success');
-                await refreshObjects();
-            }} catch (e) {{
-                toast('Network error: ' + e.message, 'error');
-            }}
-        }}
+/// Public drop-off page for an inbox bucket. It only uploads; nothing about
+/// the bucket's existing contents is shown.
+async fn inbox_page(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let t = Messages::for_request(&headers);
+    match state.storage.get_bucket(&bucket) {
+        Ok(info) if info.inbox => render_page(&inbox_template(t, &info)),
+        _ => (
+            StatusCode::NOT_FOUND,
+            Html(format!(
+                "<!DOCTYPE html><html lang=\"{}\"><body><h1>{}</h1></body></html>",
+                t.locale(),
+                t.get("inbox.not_found")
+            )),
+        )
+            .into_response(),
+    }
+}
 
-        // ── Upload ──────────────────────────────────────
-        function showUploadArea() {{
-            const area = document.getElementById('upload-area');
-            area.style.display = area.style.display === 'none' ? 'block' : 'none';
-        }}
+fn inbox_template(t: Messages, bucket: &Bucket) -> InboxTemplate<'_> {
+    let limits = &bucket.inbox_limits;
+    let mut hints = Vec::new();
+    if let Some(max_size) = limits.max_size {
+        let size = crate::size::human_readable_size(max_size);
+        hints.push(t.fill("inbox.max_size", &[("size", &size)]));
+    }
+    if !limits.content_types.is_empty() {
+        hints.push(t.fill("inbox.accepted", &[("types", &limits.content_types.join(", "))]));
+    }
+    InboxTemplate {
+        t,
+        messages_json: t.to_json(),
+        name: &bucket.name,
+        title: t.fill("inbox.title", &[("name", &bucket.name)]),
+        heading: t.fill("inbox.heading", &[("name", &bucket.name)]),
+        hints,
+    }
+}
 
-        function handleDrop(event) {{
-            event.preventDefault();
-            event.currentTarget.classList.remove('drag-over');
-            const files = event.dataTransfer.files;
-            if (files.length > 0) uploadFiles(files);
-        }}
+/// Query of a share link: `dl=1` downloads rather than showing the page,
+/// and `key` picks a file of a shared prefix
+#[derive(Deserialize)]
+struct ShareQuery {
+    #[serde(default)]
+    dl: Option<String>,
+    #[serde(default)]
+    key: Option<String>,
+}
 
-        function handleFileSelect(event) {{
-            const files = event.target.files;
-            if (files.length > 0) uploadFiles(files);
-            event.target.value = '';
-        }}
+#[derive(Deserialize)]
+struct ShareUnlock {
+    password: String,
+}
 
-        async function uploadFiles(files) {{
-            const formData = new FormData();
-            for (let i = 0; i < files.length; i++) {{
-                formData.append('file', files[i]);
-            }}
+/// Public page of a share link, or with `?dl=1` the shared file itself.
+/// Each download counts against the share's limit; an expired or used up
+/// share answers `410`.
+async fn share_page(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+    Query(query): Query<ShareQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let t = Messages::for_request(&headers);
+    let (share, password_hash) = match state.shares.get(&token) {
+        Ok(found) => found,
+        Err(e) => return share_ended(t, &e),
+    };
+    let download = query.dl.as_deref() == Some("1");
+    if let Some(hash) = &password_hash {
+        if !share_unlocked(&headers, &token, hash) {
+            let status = if download { StatusCode::FORBIDDEN } else { StatusCode::OK };
+            return (status, render_page(&share_template(t, &share, None, true))).into_response();
+        }
+    }
+    if download {
+        return match download_share(&state, &share, query.key.as_deref()) {
+            Ok(response) => response,
+            Err(e @ (AppError::ShareExpired(_) | AppError::ShareExhausted(_))) => share_ended(t, &e),
+            Err(e) => e.into_response(),
+        };
+    }
+    let mut template = share_template(t, &share, None, false);
+    template.files = shared_files(&state, &share);
+    render_page(&template)
+}
 
-            try {{
-                toast('Uploading ' + files.length + ' file(s)...', 'info');
-                const res = await fetch(API + '/buckets/' + encodeURIComponent(currentBucket) + '/upload', {{
-                    method: 'POST',
-                    body: formData
-                }});
+/// The password form of a share link. The right password sets a cookie for
+/// the share's path and goes back to the page.
+async fn unlock_share(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<ShareUnlock>,
+) -> Response {
+    let t = Messages::for_request(&headers);
+    let (share, password_hash) = match state.shares.get(&token) {
+        Ok(found) => found,
+        Err(e) => return share_ended(t, &e),
+    };
+    let Some(hash) = password_hash else {
+        return axum::response::Redirect::to(&format!("/share/{}", token)).into_response();
+    };
+    let given = form.password;
+    let checked = hash.clone();
+    let correct = tokio::task::spawn_blocking(move || bcrypt::verify(given, &checked).unwrap_or(false))
+        .await
+        .unwrap_or(false);
+    if !correct {
+        tracing::warn!("Wrong password given for share link {}", token);
+        let template = share_template(t, &share, Some(t.get("share.wrong_password").to_string()), true);
+        return (StatusCode::FORBIDDEN, render_page(&template)).into_response();
+    }
+    let cookie = format!(
+        "{}={}; Path=/share/{}; HttpOnly; SameSite=Lax",
+        crate::shares::UNLOCK_COOKIE,
+        crate::shares::unlock_value(&token, &hash),
+        token
+    );
+    (
+        StatusCode::SEE_OTHER,
+        [(header::SET_COOKIE, cookie), (header::LOCATION, format!("/share/{}", token))],
+    )
+        .into_response()
+}
 
-                if (!res.ok) {{
-                    toast('Upload failed', 'error');
-                    return;
-                }}
+/// Whether the request carries the cookie a share's password sets
+fn share_unlocked(headers: &HeaderMap, token: &str, password_hash: &str) -> bool {
+    let expected = crate::shares::unlock_value(token, password_hash);
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().strip_prefix(crate::shares::UNLOCK_COOKIE)?.strip_prefix('='))
+        .any(|value| value == expected)
+}
 
-                const data = await res.json();
-                toast(data.uploaded + ' file(s) uploaded successfully!', 'success');
-                document.getElementById('upload-area').style.display = 'none';
-                await refreshObjects();
-            }} catch (e) {{
-                toast('Upload error: ' + e.message, 'error');
-            }}
-        }}
+/// Serve a shared file and count the download. The file is read before the
+/// download is counted, so one that is gone doesn't use one up.
+fn download_share(state: &AppState, share: &Share, key: Option<&str>) -> Result<Response, AppError> {
+    let key = match key {
+        Some(key) if share.prefix && key.starts_with(&share.key) => key,
+        None if !share.prefix => &share.key,
+        _ => {
+            return Err(AppError::ObjectNotFound {
+                bucket: share.bucket.clone(),
+                key: key.unwrap_or_default().to_string(),
+            })
+        }
+    };
+    let (meta, data) = state.storage.get_object(&share.bucket, key)?;
+    let share = state.shares.claim(&share.token)?;
+    state.storage.record_access(&share.bucket, key);
+    tracing::info!(
+        "Share link {} served {}/{} (download {})",
+        share.token,
+        share.bucket,
+        key,
+        share.downloads
+    );
+    let filename = meta
+        .original_filename
+        .clone()
+        .unwrap_or_else(|| key.rsplit('/').next().unwrap_or(key).to_string());
+    Ok((
+        [
+            (header::CONTENT_TYPE, meta.content_type.clone()),
+            (header::CONTENT_LENGTH, data.len().to_string()),
+            (header::CONTENT_DISPOSITION, crate::handlers::content_disposition(&filename)),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        data,
+    )
+        .into_response())
+}
 
-        // ── Utilities ───────────────────────────────────
-        function humanSize(bytes) {{
-            const units = ['B', 'KB', 'MB', 'GB', 'TB'];
-            let i = 0;
-            let size = bytes;
-            while (size >= 1024 && i < units.length - 1) {{
-                size /= 1024;
-                i++;
-            }}
-            return i === 0 ? bytes + ' B' : size.toFixed(1) + ' ' + units[i];
-        }}
+/// The page of a share link that no longer works
+fn share_ended(t: Messages, e: &AppError) -> Response {
+    let (status, message) = match e {
+        AppError::ShareExpired(_) => (StatusCode::GONE, t.get("share.expired")),
+        AppError::ShareExhausted(_) => (StatusCode::GONE, t.get("share.exhausted")),
+        _ => (StatusCode::NOT_FOUND, t.get("share.not_found")),
+    };
+    let template = ShareTemplate {
+        t,
+        title: t.get("share.title").to_string(),
+        heading: t.get("share.unavailable").to_string(),
+        hints: Vec::new(),
+        problem: Some(message.to_string()),
+        ask_password: false,
+        files: Vec::new(),
+    };
+    (status, render_page(&template)).into_response()
+}
 
-        function escapeHtml(str) {{
-            const div = document.createElement('div');
-            div.textContent = str;
-            return div.innerHTML;
-        }}
+fn share_template(t: Messages, share: &Share, problem: Option<String>, ask_password: bool) -> ShareTemplate {
+    let name = match share.key.trim_end_matches('/').rsplit('/').next() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => share.bucket.clone(),
+    };
+    let mut hints = vec![t.fill(
+        "share.expires",
+        &[("time", &share.expires_at.format("%Y-%m-%d %H:%M UTC").to_string())],
+    )];
+    if let Some(left) = share.remaining_downloads() {
+        hints.push(t.plural("share.remaining", left));
+    }
+    ShareTemplate {
+        t,
+        title: t.fill("share.title_named", &[("name", &name)]),
+        heading: name,
+        hints,
+        problem,
+        ask_password,
+        files: Vec::new(),
+    }
+}
 
-        function escapeAttr(str) {{
-            return str.replace(/&/g, '&amp;').replace(/"/g, '&quot;').replace(/'/g, '&#39;').replace(/</g, '&lt;').replace(/>/g, '&gt;');
-        }}
+/// The files a share hands out, each with its download link
+fn shared_files(state: &AppState, share: &Share) -> Vec<SharedFile> {
+    let objects = if share.prefix {
+        match state.storage.list_objects(&share.bucket, &share.key, None, SHARE_LIST_MAX) {
+            Ok(listing) => listing.objects,
+            Err(e) => {
+                tracing::warn!("Cannot list share link {}: {:?}", share.token, e);
+                Vec::new()
+            }
+        }
+    } else {
+        state.storage.stat_object(&share.bucket, &share.key).into_iter().collect()
+    };
+    objects
+        .into_iter()
+        .map(|meta| {
+            let (name, href) = if share.prefix {
+                let key = percent_encoding::utf8_percent_encode(&meta.key, percent_encoding::NON_ALPHANUMERIC);
+                (meta.key[share.key.len()..].to_string(), format!("?dl=1&key={}", key))
+            } else {
+                let name = meta.key.rsplit('/').next().unwrap_or(&meta.key).to_string();
+                (name, "?dl=1".to_string())
+            };
+            SharedFile {
+                name,
+                size: crate::size::human_readable_size(meta.size),
+                href,
+            }
+        })
+        .collect()
+}
 
-        function encodePath(str) {{
-            return str.split('/').map(segment => encodeURIComponent(segment)).join('/');
-        }}
+/// What the dashboard's first paint shows
+struct DashboardSnapshot<'a> {
+    stats: &'a StorageStats,
+    buckets: &'a [Bucket],
+    top_buckets: &'a [BucketStats],
+    audit: &'a [AuditEntry],
+    alerts: &'a [UsageAlert],
+}
 
-        // Event delegation for object action buttons
-        document.getElementById('object-list-body').addEventListener('click', function(e) {{
-            const btn = e.target.closest('[data-action]');
-            if (!btn) return;
-            const action = btn.dataset.action;
-            const key = btn.dataset.key;
-            if (action === 'download') downloadObject(key);
-            else if (action === 'delete') deleteObject(key);
-        }});
+/// One line of a bucket card's alert banner, as the page script writes it
+fn alert_text(t: Messages, alert: &UsageAlert, bucket: &Bucket) -> String {
+    let (value, threshold) = match alert.metric {
+        AlertMetric::Bytes => (
+            crate::size::human_readable_size(alert.value),
+            crate::size::human_readable_size(alert.threshold),
+        ),
+        _ => (alert.value.to_string(), alert.threshold.to_string()),
+    };
+    let max = bucket.max_objects.unwrap_or_default().to_string();
+    let key = format!("alert.{}", alert.metric.name());
+    t.fill(&key, &[("value", &value), ("threshold", &threshold), ("max", &max)])
+}
 
-        // Close modals on overlay click
-        document.querySelectorAll('.modal-overlay').forEach(overlay => {{
-            overlay.addEventListener('click', (e) => {{
-                if (e.target === overlay) {{
-                    overlay.classList.remove('active');
-                }}
-            }});
-        }});
+fn dashboard_template(t: Messages, port: u16, exposed: bool, data: DashboardSnapshot<'_>) -> DashboardTemplate<'_> {
+    let DashboardSnapshot {
+        stats,
+        buckets,
+        top_buckets,
+        audit,
+        alerts,
+    } = data;
+    // Bars are scaled against the largest bucket
+    let largest = top_buckets.first().map_or(0, |b| b.total_size).max(1);
+    DashboardTemplate {
+        t,
+        locales: crate::i18n::locales(),
+        messages_json: t.to_json(),
+        port,
+        exposed,
+        stats,
+        buckets: buckets
+            .iter()
+            .map(|bucket| BucketCard {
+                bucket,
+                size: crate::size::human_readable_size(bucket.total_size),
+                objects: t.plural("bucket.objects", bucket.object_count),
+                alerts: alerts
+                    .iter()
+                    .filter(|alert| alert.bucket == bucket.name)
+                    .map(|alert| alert_text(t, alert, bucket))
+                    .collect(),
+            })
+            .collect(),
+        top_buckets: top_buckets
+            .iter()
+            .take(5)
+            .filter(|b| b.total_size > 0)
+            .map(|stats| TopBucket {
+                stats,
+                percent: format!("{:.1}", stats.total_size as f64 * 100.0 / largest as f64),
+            })
+            .collect(),
+        audit: audit
+            .iter()
+            .map(|entry| AuditRow {
+                entry,
+                time: entry.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+                failed: entry.outcome == AuditOutcome::Failed,
+            })
+            .collect(),
+        alert_count: if alerts.is_empty() { String::new() } else { t.plural("alert.count", alerts.len() as u64) },
+        decimal_sizes: crate::size::units() == SizeUnits::Decimal,
+    }
+}
 
-        // Close modals on Escape
-        document.addEventListener('keydown', (e) => {{
-            if (e.key === 'Escape') {{
-                document.querySelectorAll('.modal-overlay.active').forEach(m => m.classList.remove('active'));
-            }}
-        }});
-    </script>
-</body>
-</html>"##,
-        total_buckets = stats.total_buckets,
-        total_objects = stats.total_objects,
-        total_size = stats.total_size_human,
-        port = port,
-        bucket_cards = bucket_cards,
-        empty_state = if buckets.is_empty() {
-            r#"<div class="empty-state">
-                <div class="empty-icon">
-                    <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2">
-                        <path d="M2 7V17C2 19 4 21 8 21H16C20 21 22 19 22 17V7"/>
-                        <path d="M2 7L5 3H19L22 7"/>
-                        <path d="M2 7H22"/>
-                        <path d="M12 11v6M9 14h6"/>
-                    </svg>
-                </div>
-                <h3 class="empty-title">No buckets yet</h3>
-                <p class="empty-desc">Create your first bucket to start storing objects</p>
-                <button class="btn btn-primary" onclick="showCreateBucketModal()">
-                    <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" width="16" height="16">
-                        <path d="M12 5v14M5 12h14"/>
-                    </svg>
-                    Create First Bucket
-                </button>
-            </div>"#
-        } else {
-            ""
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::Request;
+
+    use super::*;
+    use crate::storage::PutOptions;
+    use crate::testing::{body_string, TestServer};
+
+    fn shared_report(server: &TestServer, max_downloads: Option<u64>, password: Option<&str>) -> String {
+        server.state.storage.create_bucket("docs", "local").unwrap();
+        server
+            .state
+            .storage
+            .put_object("docs", "report.pdf", b"%PDF", PutOptions::default())
+            .unwrap();
+        // The lowest cost bcrypt allows keeps the test quick
+        let hash = password.map(|p| bcrypt::hash(p, 4).unwrap());
+        server.state.shares.create("docs", "report.pdf", false, 3600, max_downloads, hash).token
+    }
+
+    async fn unlock(server: &TestServer, token: &str, password: &str) -> Response {
+        let req = Request::post(format!("/share/{}", token))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!("password={}", password)))
+            .unwrap();
+        server.send(req).await
+    }
+
+    async fn download(server: &TestServer, token: &str, cookie: Option<&str>) -> Response {
+        let mut req = Request::get(format!("/share/{}?dl=1", token));
+        if let Some(cookie) = cookie {
+            req = req.header(header::COOKIE, cookie);
         }
-    )
+        server.send(req.body(Body::empty()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn serves_the_allowed_downloads_then_answers_gone() {
+        let server = TestServer::new();
+        let token = shared_report(&server, Some(2), None);
+        for _ in 0..2 {
+            let res = download(&server, &token, None).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(body_string(res).await, "%PDF");
+        }
+        assert_eq!(download(&server, &token, None).await.status(), StatusCode::GONE);
+        assert_eq!(server.request("GET", &format!("/share/{}", token)).await.status(), StatusCode::GONE);
+
+        server.state.shares.revoke(&token).unwrap();
+        assert_eq!(download(&server, &token, None).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn downloads_need_the_password_cookie() {
+        let server = TestServer::new();
+        let token = shared_report(&server, Some(1), Some("s3cret"));
+        assert_eq!(download(&server, &token, None).await.status(), StatusCode::FORBIDDEN);
+
+        let res = unlock(&server, &token, "guess").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(res.headers().get(header::SET_COOKIE).is_none());
+        let forged = format!("{}=0000", crate::shares::UNLOCK_COOKIE);
+        assert_eq!(download(&server, &token, Some(&forged)).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(server.state.shares.list(None)[0].downloads, 0, "refused downloads don't count");
+
+        let res = unlock(&server, &token, "s3cret").await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let set_cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains(&format!("Path=/share/{}; HttpOnly", token)), "{}", set_cookie);
+        let cookie = set_cookie.split(';').next().unwrap();
+        let res = download(&server, &token, Some(cookie)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_string(res).await, "%PDF");
+        assert_eq!(download(&server, &token, Some(cookie)).await.status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn shared_prefixes_stop_at_their_folder() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("docs", "local").unwrap();
+        for key in ["team/plan.txt", "team-private/salaries.txt"] {
+            server
+                .state
+                .storage
+                .put_object("docs", key, key.as_bytes(), PutOptions::default())
+                .unwrap();
+        }
+        let create = |prefix: &str| {
+            Request::post("/api/shares")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(format!(r#"{{"bucket":"docs","prefix":"{}"}}"#, prefix)))
+                .unwrap()
+        };
+        for prefix in ["", "/"] {
+            assert_eq!(server.send(create(prefix)).await.status(), StatusCode::BAD_REQUEST, "{:?}", prefix);
+        }
+        let res = server.send(create("team")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let created: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        assert_eq!(created["key"], "team/");
+        let url = created["url"].as_str().unwrap();
+
+        let res = server.request("GET", &format!("{}?dl=1&key=team%2Fplan.txt", url)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_string(res).await, "team/plan.txt");
+        let res = server.request("GET", &format!("{}?dl=1&key=team-private%2Fsalaries.txt", url)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let page = body_string(server.request("GET", url).await).await;
+        assert!(page.contains("plan.txt") && !page.contains("salaries"), "{}", page);
+    }
+
+    /// Bucket names are checked on the way in, but the pages mustn't rely on it
+    const HOSTILE: &str = "<img src=x onerror=alert('x')>";
+
+    fn assert_escaped(html: &str) {
+        assert!(!html.contains("<img"), "{}", html);
+        assert!(!html.contains("'x'"), "{}", html);
+        assert!(html.contains("&lt;img src=x onerror=alert("), "{}", html);
+    }
+
+    #[test]
+    fn the_dashboard_escapes_names() {
+        let server = TestServer::new();
+        let storage = &server.state.storage;
+        storage.create_bucket("docs", "local").unwrap();
+        storage
+            .put_object("docs", "a.txt", b"hello", PutOptions::default())
+            .unwrap();
+        let stats = storage.get_stats();
+        let mut buckets = storage.list_buckets();
+        let mut top_buckets = storage.bucket_stats();
+        buckets[0].name = HOSTILE.to_string();
+        top_buckets[0].name = HOSTILE.to_string();
+        let audit = [AuditEntry {
+            time: chrono::Utc::now(),
+            actor: "cli".to_string(),
+            action: crate::models::AuditAction::BucketCreate,
+            bucket: Some(HOSTILE.to_string()),
+            target: HOSTILE.to_string(),
+            changes: Vec::new(),
+            outcome: AuditOutcome::Failed,
+            error: Some(format!("\"{}\" is taken", HOSTILE)),
+        }];
+        let data = DashboardSnapshot {
+            stats: &stats,
+            buckets: &buckets,
+            top_buckets: &top_buckets,
+            audit: &audit,
+            alerts: &[],
+        };
+        let html = dashboard_template(Messages::for_request(&HeaderMap::new()), 9000, false, data).render().unwrap();
+        assert_escaped(&html);
+        assert!(html.contains(r#"onclick="openBucket(this.dataset.bucket)""#), "{}", html);
+    }
+
+    #[test]
+    fn the_inbox_page_escapes_its_bucket_name() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("uploads", "local").unwrap();
+        let mut bucket = server.state.storage.get_bucket("uploads").unwrap();
+        bucket.name = HOSTILE.to_string();
+        bucket.inbox_limits.content_types = vec![HOSTILE.to_string()];
+        let html = inbox_template(Messages::for_request(&HeaderMap::new()), &bucket).render().unwrap();
+        assert_escaped(&html);
+        assert!(!html.contains("/api/inbox/&"), "the upload URL comes from the data attribute");
+    }
 }
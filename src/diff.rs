@@ -0,0 +1,201 @@
+//! Content diffs between two listings, each a bucket or a prefix of one,
+//! local or on another server. Both listings come in key order and are
+//! walked in lockstep, so only the current object of each side is held
+//! however many objects they have.
+
+use crate::error::AppError;
+use crate::models::{DiffChange, DiffEntry, DiffSide, DiffSummary, ObjectMeta};
+use crate::storage::StorageEngine;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::Left => "left",
+            Side::Right => "right",
+        }
+    }
+}
+
+/// A listing that went backwards, which would make the diff wrong
+#[derive(Debug)]
+pub struct OutOfOrder {
+    pub side: Side,
+    pub key: String,
+}
+
+impl From<OutOfOrder> for AppError {
+    fn from(e: OutOfOrder) -> Self {
+        AppError::StorageError(format!("The {} listing is not in key order at '{}'", e.side.name(), e.key))
+    }
+}
+
+impl From<OutOfOrder> for String {
+    fn from(e: OutOfOrder) -> Self {
+        format!("The {} listing is not in key order at '{}'", e.side.name(), e.key)
+    }
+}
+
+/// An object of a listing, by its key relative to the listing's prefix
+pub type Listed = (String, ObjectMeta);
+
+/// The objects of a local bucket under `prefix`, in key order. Objects
+/// deleted while the walk runs are left out.
+pub fn local_listing<'a>(
+    storage: &'a StorageEngine,
+    side: &'a DiffSide,
+) -> Result<impl Iterator<Item = Result<Listed, AppError>> + 'a, AppError> {
+    storage.get_bucket(&side.bucket)?;
+    Ok(storage.sorted_keys(&side.bucket, &side.prefix).filter_map(move |key| {
+        let meta = storage.get_object_meta(&side.bucket, &key).ok()?;
+        Some(Ok((key[side.prefix.len()..].to_string(), meta)))
+    }))
+}
+
+/// The ETag, when it is the SHA-256 of the content. A provisional ETag, or
+/// one made by another algorithm, says nothing about the content.
+fn content_etag(meta: &ObjectMeta) -> Option<String> {
+    let sha256 = meta.etag.len() == 64 && meta.etag.bytes().all(|b| b.is_ascii_hexdigit());
+    (sha256 && !meta.etag_provisional).then(|| meta.etag.to_ascii_lowercase())
+}
+
+/// Takes from a listing, refusing keys that don't come after the last one
+struct Ordered<I> {
+    side: Side,
+    listing: I,
+    last: Option<String>,
+    taken: u64,
+}
+
+impl<I, E> Ordered<I>
+where
+    I: Iterator<Item = Result<Listed, E>>,
+    E: From<OutOfOrder>,
+{
+    fn next(&mut self) -> Result<Option<Listed>, E> {
+        let Some(listed) = self.listing.next().transpose()? else {
+            return Ok(None);
+        };
+        if self.last.as_ref().is_some_and(|last| *last >= listed.0) {
+            return Err(OutOfOrder {
+                side: self.side,
+                key: listed.0,
+            }
+            .into());
+        }
+        self.last = Some(listed.0.clone());
+        self.taken += 1;
+        Ok(Some(listed))
+    }
+}
+
+/// Compare two listings, passing each difference to `emit` in key order.
+/// Keys on both sides differ when their sizes do, or else their content
+/// hashes. ETags are used as those hashes when they are SHA-256; otherwise
+/// `content_hash` reads the object.
+pub fn diff_listings<L, R, H, F, E>(left: L, right: R, mut content_hash: H, mut emit: F) -> Result<DiffSummary, E>
+where
+    L: Iterator<Item = Result<Listed, E>>,
+    R: Iterator<Item = Result<Listed, E>>,
+    H: FnMut(Side, &ObjectMeta) -> Result<String, E>,
+    F: FnMut(DiffEntry) -> Result<(), E>,
+    E: From<OutOfOrder>,
+{
+    let mut left = Ordered {
+        side: Side::Left,
+        listing: left.fuse(),
+        last: None,
+        taken: 0,
+    };
+    let mut right = Ordered {
+        side: Side::Right,
+        listing: right.fuse(),
+        last: None,
+        taken: 0,
+    };
+    let mut summary = DiffSummary::default();
+    let mut next_left = left.next()?;
+    let mut next_right = right.next()?;
+    loop {
+        let (l, r) = match (next_left.take(), next_right.take()) {
+            (None, None) => break,
+            (Some(l), Some(r)) if l.0 < r.0 => {
+                next_right = Some(r);
+                (Some(l), None)
+            }
+            (Some(l), Some(r)) if l.0 > r.0 => {
+                next_left = Some(l);
+                (None, Some(r))
+            }
+            sides => sides,
+        };
+        let entry = match (l, r) {
+            (Some((key, meta)), None) => {
+                summary.removed += 1;
+                Some(DiffEntry {
+                    key,
+                    change: DiffChange::Removed,
+                    left_size: Some(meta.size),
+                    right_size: None,
+                    left_etag: Some(meta.etag),
+                    right_etag: None,
+                })
+            }
+            (None, Some((key, meta))) => {
+                summary.added += 1;
+                Some(DiffEntry {
+                    key,
+                    change: DiffChange::Added,
+                    left_size: None,
+                    right_size: Some(meta.size),
+                    left_etag: None,
+                    right_etag: Some(meta.etag),
+                })
+            }
+            (Some((key, l)), Some((_, r))) => {
+                let same = l.size == r.size
+                    && match (content_etag(&l), content_etag(&r)) {
+                        (Some(a), Some(b)) => a == b,
+                        (a, b) => {
+                            summary.hashed += 1;
+                            let a = a.map_or_else(|| content_hash(Side::Left, &l), Ok)?;
+                            let b = b.map_or_else(|| content_hash(Side::Right, &r), Ok)?;
+                            a.eq_ignore_ascii_case(&b)
+                        }
+                    };
+                if same {
+                    summary.unchanged += 1;
+                    None
+                } else {
+                    summary.changed += 1;
+                    Some(DiffEntry {
+                        key,
+                        change: DiffChange::Changed,
+                        left_size: Some(l.size),
+                        right_size: Some(r.size),
+                        left_etag: Some(l.etag),
+                        right_etag: Some(r.etag),
+                    })
+                }
+            }
+            (None, None) => None,
+        };
+        if let Some(entry) = entry {
+            emit(entry)?;
+        }
+        if next_left.is_none() {
+            next_left = left.next()?;
+        }
+        if next_right.is_none() {
+            next_right = right.next()?;
+        }
+    }
+    summary.left_objects = left.taken;
+    summary.right_objects = right.taken;
+    Ok(summary)
+}
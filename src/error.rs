@@ -1,6 +1,8 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+
+use crate::models::ErrorResponse;
 
 #[derive(Debug)]
 pub enum AppError {
@@ -8,21 +10,54 @@ pub enum AppError {
     BucketAlreadyExists(String),
     ObjectNotFound { bucket: String, key: String },
     InvalidBucketName(String),
+    ReservedBucketName { name: String, reserved: Vec<String> },
     InvalidObjectKey(String),
+    InvalidRange(String),
+    NoSuchUpload(String),
+    InvalidPart(String),
+    InvalidRequest(String),
+    SnapshotNotFound { bucket: String, id: String },
+    VersionNotFound { key: String, version_id: String },
+    JobNotFound(String),
+    RecordingNotFound(String),
+    DeletedBucketNotFound(String),
+    BadDigest(String),
+    AccessDenied(String),
+    InvalidCsrfToken(String),
+    SlowDown(String),
+    ObjectBusy { bucket: String, key: String },
+    ObjectAlreadyExists { bucket: String, key: String },
+    KeyCaseCollision { bucket: String, key: String, existing: String },
+    ObjectPending { bucket: String, key: String },
+    ObjectLocked { bucket: String, key: String, until: DateTime<Utc> },
+    LeaseNotFound { bucket: String, key: String },
+    ShareNotFound(String),
+    ShareExpired(String),
+    ShareExhausted(String),
+    QuarantinedUploadNotFound { bucket: String, id: String },
+    TooManyObjects { bucket: String, count: u64, limit: u64, adding: u64 },
+    MetadataTooLarge { what: String, size: usize, limit: usize },
+    PreconditionFailed(String),
+    IdempotencyKeyReused(String),
+    IdempotencyKeyInUse(String),
+    NotImplemented(String),
+    MethodNotAllowed(String),
+    RouteNotFound(String),
+    TransactionFailed { step: usize, reason: String },
+    RecomputeInProgress(String),
+    OriginUnavailable(String),
     StorageError(String),
     IoError(std::io::Error),
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-    error: String,
-    code: String,
-    message: String,
-}
+/// The S3 error code of a failed response, for access logging
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorCode(pub &'static str);
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, code, message) = match &self {
+impl AppError {
+    /// HTTP status, S3-style error code, and human readable message
+    pub fn parts(&self) -> (StatusCode, &'static str, String) {
+        match self {
             AppError::BucketNotFound(name) => (
                 StatusCode::NOT_FOUND,
                 "NoSuchBucket",
@@ -43,11 +78,210 @@ impl IntoResponse for AppError {
                 "InvalidBucketName",
                 format!("Invalid bucket name: {}", reason),
             ),
+            AppError::ReservedBucketName { name, reserved } => (
+                StatusCode::BAD_REQUEST,
+                "ReservedBucketName",
+                format!(
+                    "The bucket name '{}' is reserved; reserved names are: {}",
+                    name,
+                    reserved.join(", ")
+                ),
+            ),
             AppError::InvalidObjectKey(reason) => (
                 StatusCode::BAD_REQUEST,
                 "InvalidObjectKey",
                 format!("Invalid object key: {}", reason),
             ),
+            AppError::InvalidRange(reason) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "InvalidRange",
+                format!("The requested range is not satisfiable: {}", reason),
+            ),
+            AppError::NoSuchUpload(upload_id) => (
+                StatusCode::NOT_FOUND,
+                "NoSuchUpload",
+                format!("The specified multipart upload '{}' does not exist", upload_id),
+            ),
+            AppError::InvalidPart(reason) => (
+                StatusCode::BAD_REQUEST,
+                "InvalidPart",
+                format!("Invalid part: {}", reason),
+            ),
+            AppError::InvalidRequest(reason) => (
+                StatusCode::BAD_REQUEST,
+                "InvalidRequest",
+                reason.clone(),
+            ),
+            AppError::SnapshotNotFound { bucket, id } => (
+                StatusCode::NOT_FOUND,
+                "NoSuchSnapshot",
+                format!("The snapshot '{}' does not exist in bucket '{}'", id, bucket),
+            ),
+            AppError::VersionNotFound { key, version_id } => (
+                StatusCode::NOT_FOUND,
+                "NoSuchVersion",
+                format!("The version '{}' of key '{}' does not exist", version_id, key),
+            ),
+            AppError::JobNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                "NoSuchJob",
+                format!("The job '{}' does not exist or has expired", id),
+            ),
+            AppError::RecordingNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                "NoSuchRecording",
+                format!("No recorded request '{}' is kept", id),
+            ),
+            AppError::DeletedBucketNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                "NoSuchDeletedBucket",
+                format!("No deleted bucket '{}' is retained", id),
+            ),
+            AppError::BadDigest(algorithm) => (
+                StatusCode::BAD_REQUEST,
+                "BadDigest",
+                format!(
+                    "The {} you specified did not match the calculated checksum",
+                    algorithm
+                ),
+            ),
+            AppError::AccessDenied(reason) => (
+                StatusCode::FORBIDDEN,
+                "AccessDenied",
+                reason.clone(),
+            ),
+            AppError::InvalidCsrfToken(reason) => (
+                StatusCode::FORBIDDEN,
+                "InvalidCsrfToken",
+                reason.clone(),
+            ),
+            AppError::SlowDown(reason) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "SlowDown",
+                reason.clone(),
+            ),
+            AppError::ObjectBusy { bucket, key } => (
+                StatusCode::CONFLICT,
+                "OperationAborted",
+                format!("The key '{}' in bucket '{}' is being read; retry the delete shortly", key, bucket),
+            ),
+            AppError::ObjectAlreadyExists { bucket, key } => (
+                StatusCode::CONFLICT,
+                "ObjectAlreadyExists",
+                format!(
+                    "The key '{}' already exists in bucket '{}', whose overwrite policy is deny",
+                    key, bucket
+                ),
+            ),
+            AppError::KeyCaseCollision { bucket, key, existing } => (
+                StatusCode::CONFLICT,
+                "KeyCaseCollision",
+                format!(
+                    "The key '{}' differs only in case from '{}' in bucket '{}', which the server's filesystem can't keep apart",
+                    key, existing, bucket
+                ),
+            ),
+            AppError::ObjectPending { bucket, key } => (
+                StatusCode::LOCKED,
+                "ObjectPendingScan",
+                format!("The key '{}' in bucket '{}' is waiting for its upload to be scanned", key, bucket),
+            ),
+            AppError::ObjectLocked { bucket, key, until } => (
+                StatusCode::LOCKED,
+                "ObjectLocked",
+                format!(
+                    "The key '{}' in bucket '{}' is leased until {}; writes need the lease's X-Freebucket-Lock-Token",
+                    key,
+                    bucket,
+                    until.to_rfc3339()
+                ),
+            ),
+            AppError::LeaseNotFound { bucket, key } => (
+                StatusCode::NOT_FOUND,
+                "NoSuchLease",
+                format!("No lease is held on the key '{}' in bucket '{}'", key, bucket),
+            ),
+            AppError::ShareNotFound(token) => (
+                StatusCode::NOT_FOUND,
+                "NoSuchShare",
+                format!("The share link '{}' does not exist or was revoked", token),
+            ),
+            AppError::ShareExpired(token) => (
+                StatusCode::GONE,
+                "ShareExpired",
+                format!("The share link '{}' has expired", token),
+            ),
+            AppError::ShareExhausted(token) => (
+                StatusCode::GONE,
+                "ShareExhausted",
+                format!("The share link '{}' has used up its downloads", token),
+            ),
+            AppError::QuarantinedUploadNotFound { bucket, id } => (
+                StatusCode::NOT_FOUND,
+                "NoSuchQuarantinedUpload",
+                format!("No upload '{}' is held in the quarantine of bucket '{}'", id, bucket),
+            ),
+            AppError::TooManyObjects { bucket, count, limit, adding } => (
+                StatusCode::FORBIDDEN,
+                "TooManyObjects",
+                format!(
+                    "Bucket '{}' holds {} of at most {} objects, so adding {} would exceed its max_objects limit",
+                    bucket, count, limit, adding
+                ),
+            ),
+            AppError::MetadataTooLarge { what, size, limit } => (
+                StatusCode::BAD_REQUEST,
+                "MetadataTooLarge",
+                format!(
+                    "The {} is {} bytes, over the limit of {} bytes",
+                    what, size, limit
+                ),
+            ),
+            AppError::PreconditionFailed(reason) => (
+                StatusCode::PRECONDITION_FAILED,
+                "PreconditionFailed",
+                reason.clone(),
+            ),
+            AppError::IdempotencyKeyReused(key) => (
+                StatusCode::CONFLICT,
+                "IdempotencyKeyReused",
+                format!("The Idempotency-Key '{}' was already used for a different request", key),
+            ),
+            AppError::IdempotencyKeyInUse(key) => (
+                StatusCode::CONFLICT,
+                "IdempotencyKeyInUse",
+                format!("A request with the Idempotency-Key '{}' is still in progress", key),
+            ),
+            AppError::TransactionFailed { step, reason } => (
+                StatusCode::CONFLICT,
+                "TransactionAborted",
+                format!("Operation {} failed, so nothing was applied: {}", step, reason),
+            ),
+            AppError::RecomputeInProgress(bucket) => (
+                StatusCode::CONFLICT,
+                "RecomputeInProgress",
+                format!("The statistics of bucket '{}' are already being recomputed", bucket),
+            ),
+            AppError::OriginUnavailable(reason) => (
+                StatusCode::BAD_GATEWAY,
+                "OriginUnavailable",
+                reason.clone(),
+            ),
+            AppError::NotImplemented(reason) => (
+                StatusCode::NOT_IMPLEMENTED,
+                "NotImplemented",
+                reason.clone(),
+            ),
+            AppError::MethodNotAllowed(reason) => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "MethodNotAllowed",
+                reason.clone(),
+            ),
+            AppError::RouteNotFound(reason) => (
+                StatusCode::NOT_FOUND,
+                "NotFound",
+                reason.clone(),
+            ),
             AppError::StorageError(msg) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "InternalError",
@@ -58,7 +292,13 @@ impl IntoResponse for AppError {
                 "InternalError",
                 format!("I/O error: {}", e),
             ),
-        };
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, code, message) = self.parts();
 
         let body = serde_json::to_string(&ErrorResponse {
             error: code.to_string(),
@@ -67,7 +307,9 @@ impl IntoResponse for AppError {
         })
         .unwrap();
 
-        (status, [("content-type", "application/json")], body).into_response()
+        let mut response = (status, [("content-type", "application/json")], body).into_response();
+        response.extensions_mut().insert(ErrorCode(code));
+        response
     }
 }
 
@@ -76,3 +318,34 @@ impl From<std::io::Error> for AppError {
         AppError::IoError(e)
     }
 }
+
+/// gRPC status for an error: the canonical code closest to its HTTP status,
+/// its message, and the same JSON body the HTTP API sends as details
+#[cfg(feature = "grpc")]
+impl From<AppError> for tonic::Status {
+    fn from(e: AppError) -> Self {
+        use tonic::Code;
+
+        let (status, code, message) = e.parts();
+        let grpc_code = match (status, code) {
+            (_, "BucketAlreadyOwnedByYou" | "ObjectAlreadyExists" | "KeyCaseCollision") => Code::AlreadyExists,
+            (_, "SlowDown" | "TooManyObjects") => Code::ResourceExhausted,
+            (StatusCode::BAD_REQUEST, _) => Code::InvalidArgument,
+            (StatusCode::FORBIDDEN, _) => Code::PermissionDenied,
+            (StatusCode::NOT_FOUND | StatusCode::GONE, _) => Code::NotFound,
+            (StatusCode::CONFLICT, _) => Code::Aborted,
+            (StatusCode::PRECONDITION_FAILED | StatusCode::LOCKED, _) => Code::FailedPrecondition,
+            (StatusCode::RANGE_NOT_SATISFIABLE, _) => Code::OutOfRange,
+            (StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED, _) => Code::Unimplemented,
+            (StatusCode::BAD_GATEWAY, _) => Code::Unavailable,
+            _ => Code::Internal,
+        };
+        let details = serde_json::to_vec(&ErrorResponse {
+            error: code.to_string(),
+            code: code.to_string(),
+            message: message.clone(),
+        })
+        .unwrap();
+        tonic::Status::with_details(grpc_code, message, details.into())
+    }
+}
@@ -0,0 +1,264 @@
+//! Exec hooks: local programs a bucket runs on its object events, such as
+//! a transcoder for uploaded videos. Only programs on the server's
+//! allowlist run, directly rather than through a shell, so neither a bucket
+//! setting nor an object key can smuggle in a command of its own. Runs
+//! happen after the request that caused them has been answered, and their
+//! failures only show up in the log and the bucket's hook runs.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Semaphore;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::models::{Bucket, ExecHook, ExecHooks, HookOutcome, HookRun, HookTrigger};
+use crate::storage::{EventKind, ObjectEvent};
+use crate::AppState;
+
+/// Bytes of stdout and of stderr kept from each run
+const OUTPUT_LIMIT: usize = 4096;
+/// Runs kept for `GET /api/buckets/{bucket}/hook-runs`, across all buckets
+const RUN_HISTORY: usize = 200;
+
+/// Hooks waiting for and holding a worker, and the record of past runs
+pub struct ExecHookRunner {
+    workers: Arc<Semaphore>,
+    /// Each bucket's slots with the `max_running` they were made for, so a
+    /// changed limit gets fresh ones
+    buckets: Mutex<HashMap<String, (usize, Arc<Semaphore>)>>,
+    runs: Mutex<VecDeque<HookRun>>,
+}
+
+impl ExecHookRunner {
+    pub fn new(workers: usize) -> Self {
+        Self {
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            buckets: Mutex::new(HashMap::new()),
+            runs: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn bucket_slots(&self, bucket: &str, max_running: usize) -> Arc<Semaphore> {
+        let mut buckets = self.buckets.lock().unwrap();
+        match buckets.get(bucket) {
+            Some((limit, slots)) if *limit == max_running => slots.clone(),
+            _ => {
+                let slots = Arc::new(Semaphore::new(max_running.max(1)));
+                buckets.insert(bucket.to_string(), (max_running, slots.clone()));
+                slots
+            }
+        }
+    }
+
+    fn record(&self, run: HookRun) {
+        let mut runs = self.runs.lock().unwrap();
+        if runs.len() == RUN_HISTORY {
+            runs.pop_front();
+        }
+        runs.push_back(run);
+    }
+
+    /// A bucket's most recent hook runs, newest first
+    pub fn runs(&self, bucket: &str) -> Vec<HookRun> {
+        let runs = self.runs.lock().unwrap();
+        runs.iter().rev().filter(|run| run.bucket == bucket).cloned().collect()
+    }
+}
+
+/// Refuse hooks whose program is not on `FREEBUCKET_EXEC_HOOK_ALLOW`
+pub fn check_allowed(config: &Config, hooks: &ExecHooks) -> Result<(), AppError> {
+    for hook in &hooks.commands {
+        let Some(program) = hook.command.first() else {
+            continue;
+        };
+        if !config.exec_hook_allow.contains(program) {
+            return Err(AppError::InvalidRequest(format!(
+                "Exec hook program '{}' is not in FREEBUCKET_EXEC_HOOK_ALLOW{}",
+                program,
+                if config.exec_hook_allow.is_empty() { ", which is empty" } else { "" }
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn trigger(kind: EventKind) -> Option<HookTrigger> {
+    match kind {
+        EventKind::Put | EventKind::Copy | EventKind::CompleteMultipartUpload => Some(HookTrigger::Created),
+        EventKind::Delete | EventKind::DeleteMarkerCreated => Some(HookTrigger::Removed),
+        EventKind::AlertRaised | EventKind::AlertCleared => Some(HookTrigger::Alert),
+        _ => None,
+    }
+}
+
+/// Start the bucket's hooks that run on `event`. Each waits for one of the
+/// bucket's `max_running` slots, then for a server-wide worker.
+pub fn enqueue(state: &Arc<AppState>, bucket: &Bucket, event: &ObjectEvent) {
+    let (Some(hooks), Some(trigger)) = (&bucket.exec_hooks, trigger(event.kind)) else {
+        return;
+    };
+    for hook in hooks.commands.iter().filter(|h| h.events.contains(&trigger)) {
+        let slots = state.exec_hooks.bucket_slots(&bucket.name, hooks.max_running);
+        let (state, hook, event) = (state.clone(), hook.clone(), event.clone());
+        tokio::spawn(async move {
+            let Ok(_slot) = slots.acquire_owned().await else {
+                return;
+            };
+            let Ok(_worker) = state.exec_hooks.workers.clone().acquire_owned().await else {
+                return;
+            };
+            let run = run_hook(&state, &hook, &event, trigger).await;
+            match run.outcome {
+                HookOutcome::Succeeded => tracing::info!(
+                    "Exec hook {} ran for {}/{} in {} ms",
+                    run.program,
+                    run.bucket,
+                    run.key,
+                    run.duration_ms
+                ),
+                _ => tracing::warn!(
+                    "Exec hook {} for {}/{} did not succeed ({}){}{}",
+                    run.program,
+                    run.bucket,
+                    run.key,
+                    run.error
+                        .clone()
+                        .unwrap_or_else(|| format!("exit status {}", run.exit_code.unwrap_or_default())),
+                    if run.stderr.trim().is_empty() { "" } else { ": " },
+                    run.stderr.trim()
+                ),
+            }
+            state.exec_hooks.record(run);
+        });
+    }
+}
+
+/// Replace each placeholder in `arg` in one pass, so a key that happens to
+/// contain `{path}` is passed on as it is
+fn fill(arg: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match values.iter().find(|(name, _)| rest.starts_with(name)) {
+            Some((name, value)) => {
+                filled.push_str(value);
+                rest = &rest[name.len()..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Lossy text of the first `OUTPUT_LIMIT` bytes
+fn captured(output: &[u8]) -> String {
+    String::from_utf8_lossy(&output[..output.len().min(OUTPUT_LIMIT)]).into_owned()
+}
+
+async fn run_hook(state: &Arc<AppState>, hook: &ExecHook, event: &ObjectEvent, trigger: HookTrigger) -> HookRun {
+    let started = Instant::now();
+    let program = hook.command[0].clone();
+    let mut run = HookRun {
+        bucket: event.bucket.clone(),
+        key: event.key.clone(),
+        event: event.kind.s3_name().to_string(),
+        program: program.clone(),
+        outcome: HookOutcome::Skipped,
+        exit_code: None,
+        error: None,
+        stdout: String::new(),
+        stderr: String::new(),
+        started_at: state.storage.now(),
+        duration_ms: 0,
+    };
+    // The allowlist may have shrunk since the hook was set
+    if !state.config().exec_hook_allow.contains(&program) {
+        run.error = Some("the program is no longer in FREEBUCKET_EXEC_HOOK_ALLOW".to_string());
+        return run;
+    }
+
+    let copy: Option<PathBuf> = match trigger {
+        HookTrigger::Created => {
+            let copy_state = state.clone();
+            let (bucket, key) = (event.bucket.clone(), event.key.clone());
+            match tokio::task::spawn_blocking(move || copy_state.storage.hook_copy(&bucket, &key)).await {
+                Ok(Ok(path)) => Some(path),
+                Ok(Err(e)) => {
+                    run.error = Some(format!("no copy of the object to run on: {}", e.parts().2));
+                    return run;
+                }
+                Err(e) => {
+                    run.error = Some(format!("copying the object panicked: {}", e));
+                    return run;
+                }
+            }
+        }
+        HookTrigger::Removed | HookTrigger::Alert => None,
+    };
+    let path = copy.as_ref().map(|p| p.display().to_string()).unwrap_or_default();
+    let (size, content_type) = match &event.object {
+        Some(meta) => (meta.size.to_string(), meta.content_type.clone()),
+        None => (String::new(), String::new()),
+    };
+    let (metric, value, threshold) = match &event.alert {
+        Some(alert) => (alert.metric.name(), alert.value.to_string(), alert.threshold.to_string()),
+        None => ("", String::new(), String::new()),
+    };
+    let values = [
+        ("{bucket}", event.bucket.as_str()),
+        ("{key}", event.key.as_str()),
+        ("{size}", size.as_str()),
+        ("{content_type}", content_type.as_str()),
+        ("{event}", run.event.as_str()),
+        ("{path}", path.as_str()),
+        ("{metric}", metric),
+        ("{value}", value.as_str()),
+        ("{threshold}", threshold.as_str()),
+    ];
+    let args: Vec<String> = hook.command[1..].iter().map(|arg| fill(arg, &values)).collect();
+
+    let output = tokio::process::Command::new(&program)
+        .args(&args)
+        .env("FREEBUCKET_BUCKET", &event.bucket)
+        .env("FREEBUCKET_KEY", &event.key)
+        .env("FREEBUCKET_EVENT", &run.event)
+        .env("FREEBUCKET_SIZE", &size)
+        .env("FREEBUCKET_CONTENT_TYPE", &content_type)
+        .env("FREEBUCKET_PATH", &path)
+        .env("FREEBUCKET_ALERT_METRIC", metric)
+        .env("FREEBUCKET_ALERT_VALUE", &value)
+        .env("FREEBUCKET_ALERT_THRESHOLD", &threshold)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(Duration::from_secs(hook.timeout_secs), output).await {
+        Ok(Ok(output)) => {
+            run.exit_code = output.status.code();
+            run.outcome = if output.status.success() { HookOutcome::Succeeded } else { HookOutcome::Failed };
+            if run.exit_code.is_none() {
+                run.error = Some("killed by a signal".to_string());
+            }
+            run.stdout = captured(&output.stdout);
+            run.stderr = captured(&output.stderr);
+        }
+        Ok(Err(e)) => run.error = Some(format!("cannot run the program: {}", e)),
+        Err(_) => {
+            run.outcome = HookOutcome::TimedOut;
+            run.error = Some(format!("killed after {}s", hook.timeout_secs));
+        }
+    }
+    if let Some(copy) = copy {
+        state.storage.release_hook_copy(&copy);
+    }
+    run.duration_ms = started.elapsed().as_millis() as u64;
+    run
+}
@@ -0,0 +1,369 @@
+//! The `grpc` feature's service: the core bucket and object operations of
+//! `proto/freebucket.proto`, on the same storage engine as the HTTP API
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::Stream;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::error::AppError;
+use crate::handlers::audited;
+use crate::models::{AuditAction, ListOrder, ObjectMeta};
+use crate::storage::PutOptions;
+use crate::AppState;
+
+pub mod proto;
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/freebucket.v1.FreeBucket.rs"));
+}
+
+use generated::free_bucket_server::{FreeBucket, FreeBucketServer};
+use proto::{get_object_response, put_object_request};
+
+/// Size of the body chunks GetObject streams back
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Largest page ListObjects returns, as for S3 listings
+const MAX_KEYS: u32 = 1000;
+
+struct Service {
+    state: Arc<AppState>,
+}
+
+/// Start serving gRPC on the HTTP host and `port` until `shutdown`
+/// resolves. Binding happens up front, so a taken port stops startup like
+/// the HTTP port does.
+pub fn spawn(
+    state: Arc<AppState>,
+    port: u16,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> tokio::task::JoinHandle<()> {
+    let listener = std::net::TcpListener::bind((state.config().host.as_str(), port))
+        .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+        .and_then(tokio::net::TcpListener::from_std)
+        .expect("Failed to bind the gRPC port");
+    if let Ok(addr) = listener.local_addr() {
+        tracing::info!("Serving gRPC on {}", addr);
+    }
+    let incoming = TcpIncoming::from_listener(listener, true, None).expect("Failed to bind the gRPC port");
+
+    let service = FreeBucketServer::new(Service { state });
+    tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming_shutdown(incoming, shutdown)
+            .await
+        {
+            tracing::error!("gRPC server failed: {}", e);
+        }
+    })
+}
+
+fn bucket_message(bucket: crate::models::Bucket) -> proto::Bucket {
+    proto::Bucket {
+        name: bucket.name,
+        region: bucket.region,
+        created_at: bucket.created_at.to_rfc3339(),
+        object_count: bucket.object_count,
+        total_size: bucket.total_size,
+    }
+}
+
+fn object_message(meta: ObjectMeta) -> proto::Object {
+    let last_modified = meta.modified().to_rfc3339();
+    proto::Object {
+        bucket: meta.bucket,
+        key: meta.key,
+        size: meta.size,
+        content_type: meta.content_type,
+        etag: meta.etag,
+        last_modified,
+        metadata: meta.metadata,
+    }
+}
+
+/// Proto3 has no unset strings; an empty one means "not given"
+fn non_empty(s: &str) -> Option<&str> {
+    (!s.is_empty()).then_some(s)
+}
+
+// The generated trait fixes the error type to tonic's large `Status`
+#[allow(clippy::result_large_err)]
+#[tonic::async_trait]
+impl FreeBucket for Service {
+    async fn list_buckets(
+        &self,
+        _request: Request<proto::ListBucketsRequest>,
+    ) -> Result<Response<proto::ListBucketsResponse>, Status> {
+        let buckets = self.state.storage.list_buckets().into_iter().map(bucket_message).collect();
+        Ok(Response::new(proto::ListBucketsResponse { buckets }))
+    }
+
+    async fn create_bucket(
+        &self,
+        request: Request<proto::CreateBucketRequest>,
+    ) -> Result<Response<proto::Bucket>, Status> {
+        let req = request.into_inner();
+        let config = self.state.config();
+        let region = non_empty(&req.region).unwrap_or(&config.default_region);
+        let created = self.state.storage.create_bucket(&req.name, region);
+        let bucket = audited(&self.state, AuditAction::BucketCreate, &req.name, created)?;
+        Ok(Response::new(bucket_message(bucket)))
+    }
+
+    async fn delete_bucket(
+        &self,
+        request: Request<proto::DeleteBucketRequest>,
+    ) -> Result<Response<proto::DeleteBucketResponse>, Status> {
+        let name = request.into_inner().name;
+        let deleted = self.state.storage.delete_bucket(&name, false);
+        audited(&self.state, AuditAction::BucketDelete, &name, deleted)?;
+        Ok(Response::new(proto::DeleteBucketResponse {}))
+    }
+
+    async fn list_objects(
+        &self,
+        request: Request<proto::ListObjectsRequest>,
+    ) -> Result<Response<proto::ListObjectsResponse>, Status> {
+        let req = request.into_inner();
+        let max_keys = match req.max_keys {
+            0 => MAX_KEYS,
+            n => n.min(MAX_KEYS),
+        };
+        let listing = self.state.storage.list_objects_sorted(
+            &req.bucket,
+            &req.prefix,
+            non_empty(&req.delimiter),
+            max_keys,
+            ListOrder::default(),
+            non_empty(&req.continuation_token),
+        )?;
+        Ok(Response::new(proto::ListObjectsResponse {
+            objects: listing.objects.into_iter().map(object_message).collect(),
+            common_prefixes: listing.common_prefixes,
+            is_truncated: listing.is_truncated,
+            next_continuation_token: listing.next_continuation_token.unwrap_or_default(),
+        }))
+    }
+
+    async fn put_object(
+        &self,
+        request: Request<Streaming<proto::PutObjectRequest>>,
+    ) -> Result<Response<proto::Object>, Status> {
+        let mut stream = request.into_inner();
+        let header = match stream.message().await?.and_then(|m| m.part) {
+            Some(put_object_request::Part::Header(header)) => header,
+            _ => {
+                return Err(AppError::InvalidRequest(
+                    "The first PutObject message must carry the header".to_string(),
+                )
+                .into())
+            }
+        };
+        self.state.storage.get_bucket(&header.bucket)?;
+        crate::storage::check_content_type(&header.content_type)?;
+
+        // Bodies are buffered like HTTP uploads, so they take an upload slot
+        let _slot = self.state.upload_limiter.acquire().await?;
+        let limit = self.state.config().max_upload_size;
+        let mut data = Vec::new();
+        while let Some(message) = stream.message().await? {
+            match message.part {
+                Some(put_object_request::Part::Chunk(chunk)) => {
+                    if data.len() + chunk.len() > limit {
+                        return Err(AppError::InvalidRequest(format!(
+                            "Object is larger than the {} upload limit",
+                            crate::size::human_readable_size(limit as u64)
+                        ))
+                        .into());
+                    }
+                    data.extend_from_slice(&chunk);
+                }
+                Some(put_object_request::Part::Header(_)) => {
+                    return Err(AppError::InvalidRequest(
+                        "Only the first PutObject message may carry a header".to_string(),
+                    )
+                    .into())
+                }
+                None => {}
+            }
+        }
+
+        let meta = self.state.storage.put_object(
+            &header.bucket,
+            &header.key,
+            &data,
+            PutOptions {
+                content_type: non_empty(&header.content_type),
+                metadata: header.metadata,
+                ..Default::default()
+            },
+        )?;
+        Ok(Response::new(object_message(meta)))
+    }
+
+    type GetObjectStream = Pin<Box<dyn Stream<Item = Result<proto::GetObjectResponse, Status>> + Send>>;
+
+    async fn get_object(
+        &self,
+        request: Request<proto::GetObjectRequest>,
+    ) -> Result<Response<Self::GetObjectStream>, Status> {
+        let req = request.into_inner();
+        let (meta, data) = self.state.storage.get_object(&req.bucket, &req.key)?;
+        self.state.storage.record_access(&req.bucket, &req.key);
+
+        let data = Bytes::from(data);
+        let chunks = (0..data.len()).step_by(CHUNK_SIZE).map(move |start| {
+            let end = (start + CHUNK_SIZE).min(data.len());
+            get_object_response::Part::Chunk(data.slice(start..end))
+        });
+        let parts = std::iter::once(get_object_response::Part::Object(object_message(meta)))
+            .chain(chunks)
+            .map(|part| Ok(proto::GetObjectResponse { part: Some(part) }));
+        Ok(Response::new(Box::pin(futures::stream::iter(parts))))
+    }
+
+    async fn delete_object(
+        &self,
+        request: Request<proto::DeleteObjectRequest>,
+    ) -> Result<Response<proto::DeleteObjectResponse>, Status> {
+        let req = request.into_inner();
+        self.state.storage.delete_object(&req.bucket, &req.key)?;
+        Ok(Response::new(proto::DeleteObjectResponse {}))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<proto::GetStatsRequest>,
+    ) -> Result<Response<proto::Stats>, Status> {
+        let stats = self.state.storage.get_stats();
+        Ok(Response::new(proto::Stats {
+            total_buckets: stats.total_buckets,
+            total_objects: stats.total_objects,
+            total_size: stats.total_size,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use sha2::{Digest, Sha256};
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::http::uri::PathAndQuery;
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    #[test]
+    fn invalid_content_type_is_an_invalid_argument() {
+        let status = Status::from(crate::storage::check_content_type("a\u{1}b").unwrap_err());
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let details: serde_json::Value = serde_json::from_slice(status.details()).unwrap();
+        assert_eq!(details["code"], "InvalidRequest");
+    }
+
+    #[test]
+    fn error_codes_follow_http_statuses() {
+        let code = |e: AppError| Status::from(e).code();
+        assert_eq!(code(AppError::BucketNotFound("b".to_string())), tonic::Code::NotFound);
+        assert_eq!(code(AppError::InvalidObjectKey("k".to_string())), tonic::Code::InvalidArgument);
+        assert_eq!(code(AppError::StorageError("disk".to_string())), tonic::Code::Internal);
+    }
+
+    #[test]
+    fn empty_strings_are_unset() {
+        assert_eq!(non_empty(""), None);
+        assert_eq!(non_empty("text/plain"), Some("text/plain"));
+    }
+
+    /// Chunk `i` of a 50 MiB test object, made when it is sent so the
+    /// whole object is never held at once
+    fn chunk(i: usize) -> Bytes {
+        (0..CHUNK_SIZE).map(|j| ((i * 7 + j) % 251) as u8).collect()
+    }
+
+    const CHUNKS: usize = 50;
+
+    /// Serve the test server's state over gRPC on a free port, returning a
+    /// client connection
+    async fn connect(server: &TestServer) -> tonic::client::Grpc<tonic::transport::Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let service = FreeBucketServer::new(Service { state: server.state.clone() });
+        tokio::spawn(tonic::transport::Server::builder().add_service(service).serve_with_incoming(incoming));
+        let channel = tonic::transport::Endpoint::from_shared(url).unwrap().connect().await.unwrap();
+        tonic::client::Grpc::new(channel)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn streams_50_mib_both_ways_a_chunk_at_a_time() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("media", "local").unwrap();
+        let mut grpc = connect(&server).await;
+        let mut sent = Sha256::new();
+        for i in 0..CHUNKS {
+            sent.update(chunk(i));
+        }
+        let sent = hex::encode(sent.finalize());
+
+        let header = put_object_request::Part::Header(proto::PutObjectHeader {
+            bucket: "media".to_string(),
+            key: "big.bin".to_string(),
+            content_type: String::new(),
+            metadata: Default::default(),
+        });
+        let parts = std::iter::once(header)
+            .chain((0..CHUNKS).map(|i| put_object_request::Part::Chunk(chunk(i))))
+            .map(|part| proto::PutObjectRequest { part: Some(part) });
+        grpc.ready().await.unwrap();
+        let object = grpc
+            .client_streaming(
+                Request::new(futures::stream::iter(parts)),
+                PathAndQuery::from_static("/freebucket.v1.FreeBucket/PutObject"),
+                ProstCodec::<proto::PutObjectRequest, proto::Object>::default(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(object.size, (CHUNKS * CHUNK_SIZE) as u64);
+        assert_eq!(object.etag, sent);
+
+        grpc.ready().await.unwrap();
+        let request = proto::GetObjectRequest {
+            bucket: "media".to_string(),
+            key: "big.bin".to_string(),
+        };
+        let mut stream = grpc
+            .server_streaming(
+                Request::new(request),
+                PathAndQuery::from_static("/freebucket.v1.FreeBucket/GetObject"),
+                ProstCodec::<proto::GetObjectRequest, proto::GetObjectResponse>::default(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(get_object_response::Part::Object(object)) = stream.next().await.unwrap().unwrap().part else {
+            panic!("GetObject must start with the object");
+        };
+        assert_eq!(object.size, (CHUNKS * CHUNK_SIZE) as u64);
+        // Hashed as it arrives; no message is larger than a chunk
+        let (mut received, mut size, mut largest) = (Sha256::new(), 0, 0);
+        while let Some(message) = stream.next().await {
+            let Some(get_object_response::Part::Chunk(chunk)) = message.unwrap().part else {
+                panic!("only chunks follow the object");
+            };
+            largest = largest.max(chunk.len());
+            size += chunk.len();
+            received.update(&chunk);
+        }
+        assert_eq!(size, CHUNKS * CHUNK_SIZE);
+        assert_eq!(largest, CHUNK_SIZE);
+        assert_eq!(hex::encode(received.finalize()), sent);
+    }
+}
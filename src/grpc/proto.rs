@@ -0,0 +1,164 @@
+//! Messages of `proto/freebucket.proto`, written out by hand so building
+//! doesn't need `protoc`. Field tags must match the `.proto`.
+
+use std::collections::HashMap;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Bucket {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub region: String,
+    #[prost(string, tag = "3")]
+    pub created_at: String,
+    #[prost(uint64, tag = "4")]
+    pub object_count: u64,
+    #[prost(uint64, tag = "5")]
+    pub total_size: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Object {
+    #[prost(string, tag = "1")]
+    pub bucket: String,
+    #[prost(string, tag = "2")]
+    pub key: String,
+    #[prost(uint64, tag = "3")]
+    pub size: u64,
+    #[prost(string, tag = "4")]
+    pub content_type: String,
+    #[prost(string, tag = "5")]
+    pub etag: String,
+    #[prost(string, tag = "6")]
+    pub last_modified: String,
+    #[prost(map = "string, string", tag = "7")]
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListBucketsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListBucketsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CreateBucketRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub region: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteBucketRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteBucketResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListObjectsRequest {
+    #[prost(string, tag = "1")]
+    pub bucket: String,
+    #[prost(string, tag = "2")]
+    pub prefix: String,
+    #[prost(string, tag = "3")]
+    pub delimiter: String,
+    #[prost(uint32, tag = "4")]
+    pub max_keys: u32,
+    #[prost(string, tag = "5")]
+    pub continuation_token: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListObjectsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub objects: Vec<Object>,
+    #[prost(string, repeated, tag = "2")]
+    pub common_prefixes: Vec<String>,
+    #[prost(bool, tag = "3")]
+    pub is_truncated: bool,
+    #[prost(string, tag = "4")]
+    pub next_continuation_token: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PutObjectHeader {
+    #[prost(string, tag = "1")]
+    pub bucket: String,
+    #[prost(string, tag = "2")]
+    pub key: String,
+    #[prost(string, tag = "3")]
+    pub content_type: String,
+    #[prost(map = "string, string", tag = "4")]
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PutObjectRequest {
+    #[prost(oneof = "put_object_request::Part", tags = "1, 2")]
+    pub part: Option<put_object_request::Part>,
+}
+
+pub mod put_object_request {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Part {
+        #[prost(message, tag = "1")]
+        Header(super::PutObjectHeader),
+        #[prost(bytes = "bytes", tag = "2")]
+        Chunk(bytes::Bytes),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetObjectRequest {
+    #[prost(string, tag = "1")]
+    pub bucket: String,
+    #[prost(string, tag = "2")]
+    pub key: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetObjectResponse {
+    #[prost(oneof = "get_object_response::Part", tags = "1, 2")]
+    pub part: Option<get_object_response::Part>,
+}
+
+pub mod get_object_response {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Part {
+        #[prost(message, tag = "1")]
+        Object(super::Object),
+        #[prost(bytes = "bytes", tag = "2")]
+        Chunk(bytes::Bytes),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteObjectRequest {
+    #[prost(string, tag = "1")]
+    pub bucket: String,
+    #[prost(string, tag = "2")]
+    pub key: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteObjectResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetStatsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Stats {
+    #[prost(uint64, tag = "1")]
+    pub total_buckets: u64,
+    #[prost(uint64, tag = "2")]
+    pub total_objects: u64,
+    #[prost(uint64, tag = "3")]
+    pub total_size: u64,
+}
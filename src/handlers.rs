@@ -3,54 +3,434 @@ use std::sync::Arc;
 
 use axum::{
     Router,
-    extract::{Multipart, Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    extract::{Multipart, OriginalUri, Path, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    middleware::Next,
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Json,
 };
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
 
 use crate::error::AppError;
+use crate::exec_hooks;
 use crate::models::*;
+use crate::s3_mounts::{self, CANONICAL_MOUNT};
+use crate::s3_xml::{self, XmlBody};
+use crate::storage::{Audit, ChecksumRequest, ContentEncoding, OpHistogram, PutOptions, StorageEngine, OP_BUCKETS, TRAFFIC_HOURS};
 
 use crate::AppState;
 
 type AppResult<T> = Result<T, AppError>;
 
+/// Who the audit log names for API requests, which are not authenticated
+pub const API_ACTOR: &str = "anonymous";
+
+/// Record an action on something inside a bucket, such as a prefix
+fn audited_in_bucket<T>(
+    state: &AppState,
+    action: AuditAction,
+    bucket: &str,
+    target: &str,
+    result: AppResult<T>,
+) -> AppResult<T> {
+    state.storage.audited(
+        Audit {
+            actor: API_ACTOR,
+            action,
+            bucket: Some(bucket),
+            target,
+            changes: Vec::new(),
+        },
+        result,
+    )
+}
+
+/// Record a change to one of a bucket's settings
+fn audited_settings<T>(state: &AppState, bucket: &str, setting: &str, result: AppResult<T>) -> AppResult<T> {
+    state.storage.audited(
+        Audit {
+            actor: API_ACTOR,
+            action: AuditAction::BucketSettings,
+            bucket: Some(bucket),
+            target: bucket,
+            changes: vec![setting.to_string()],
+        },
+        result,
+    )
+}
+
+/// Record an action on a bucket in the audit log before it is answered
+pub fn audited<T>(state: &AppState, action: AuditAction, bucket: &str, result: AppResult<T>) -> AppResult<T> {
+    state.storage.audited(
+        Audit {
+            actor: API_ACTOR,
+            action,
+            bucket: Some(bucket),
+            target: bucket,
+            changes: Vec::new(),
+        },
+        result,
+    )
+}
+
 // ─── REST API Routes ─────────────────────────────────────────────
 
 pub fn api_routes() -> Router<Arc<AppState>> {
     Router::new()
         // Stats
         .route("/stats", get(get_stats))
+        .route("/stats/history", get(get_stats_history))
+        .route("/stats/buckets", get(get_bucket_stats))
+        .route("/stats/volumes", get(get_volume_stats))
+        .route("/metrics", get(get_metrics))
+        .route("/dashboard-data", get(get_dashboard_data))
+        .route("/alerts", get(list_alerts))
+        .route("/server-info", get(get_server_info))
+        // Maintenance
+        .route("/admin/gc", post(run_gc))
+        .route("/admin/bulk", post(start_bulk_job))
+        .route("/admin/bulk/:id", get(get_bulk_job))
+        .route("/admin/slow-ops", get(get_slow_ops))
+        .route("/admin/audit", get(get_audit_log))
+        .route("/admin/reload", post(reload_config))
+        .route("/admin/self-test", post(run_self_test))
+        .route("/admin/backup", post(create_backup))
+        .route("/admin/cache/clear", post(clear_read_cache))
+        .route("/admin/recordings", get(list_recordings))
+        .route("/admin/recordings/:id/request", get(get_recorded_request))
+        .route("/admin/recordings/:id/response", get(get_recorded_response))
+        .route("/admin/deleted-buckets", get(list_deleted_buckets))
+        .route("/admin/deleted-buckets/:id/restore", post(restore_deleted_bucket))
+        .route("/admin/quarantine", get(list_quarantine))
+        .route("/admin/quarantine/:bucket/:id", delete(dismiss_quarantined))
+        .route("/admin/quarantine/:bucket/:id/content", get(get_quarantined_content))
+        .route("/admin/quarantine/:bucket/:id/approve", post(approve_quarantined))
+        .route("/admin/quarantine/:bucket/:id/reject", post(reject_quarantined))
+        .route("/transactions", post(apply_transaction))
+        // Share links
+        .route("/shares", get(list_shares).post(create_share))
+        .route("/shares/:token", delete(revoke_share))
+        .route("/diff", post(diff_buckets))
         // Bucket operations
         .route("/buckets", get(list_buckets).post(create_bucket))
-        .route("/buckets/:bucket", get(get_bucket).delete(delete_bucket))
+        .route(
+            "/buckets/:bucket",
+            get(get_bucket).patch(update_bucket_settings).delete(delete_bucket),
+        )
+        // Versioning
+        .route("/buckets/:bucket/versioning", put(set_versioning))
+        .route("/buckets/:bucket/versions", get(list_object_versions))
+        .route("/buckets/:bucket/connect-info", get(get_connect_info))
+        .route(
+            "/buckets/:bucket/logging",
+            get(get_bucket_logging)
+                .put(put_bucket_logging)
+                .delete(delete_bucket_logging),
+        )
+        .route(
+            "/buckets/:bucket/origin",
+            get(get_bucket_origin)
+                .put(put_bucket_origin)
+                .delete(delete_bucket_origin),
+        )
+        .route(
+            "/buckets/:bucket/quarantine",
+            get(get_bucket_quarantine)
+                .put(put_bucket_quarantine)
+                .delete(delete_bucket_quarantine),
+        )
+        // Public upload inbox
+        .route("/inbox/:bucket", post(inbox_upload))
         // Object listing
         .route("/buckets/:bucket/objects", get(list_objects))
+        .route("/buckets/:bucket/objects.ndjson", get(stream_objects))
+        // Prefix ("folder") operations
+        .route("/buckets/:bucket/prefix-stats", get(get_prefix_stats))
+        .route("/buckets/:bucket/largest", get(get_largest_objects))
+        .route("/buckets/:bucket/search", get(search_objects))
+        .route("/buckets/:bucket/traffic", get(get_bucket_traffic))
+        .route("/buckets/:bucket/hook-runs", get(get_hook_runs))
+        .route("/buckets/:bucket/fix-content-types", post(fix_content_types))
+        .route(
+            "/buckets/:bucket/recompute-stats",
+            post(start_stats_recompute).delete(cancel_stats_recompute),
+        )
+        .route("/buckets/:bucket/recompute-stats/status", get(get_stats_recompute))
+        .route("/buckets/:bucket/metadata-recovery", delete(confirm_bucket_metadata))
+        .route("/buckets/:bucket/prefix", delete(delete_prefix))
+        // Snapshots
+        .route("/buckets/:bucket/snapshots", get(list_snapshots).post(create_snapshot))
+        .route("/buckets/:bucket/snapshots/:id", delete(delete_snapshot))
+        .route("/buckets/:bucket/snapshots/:id/restore", post(restore_snapshot))
+        // Inventory reports
+        .route("/buckets/:bucket/inventory", post(generate_inventory))
+        .route(
+            "/buckets/:bucket/inventory-config",
+            get(get_inventory_config)
+                .put(put_inventory_config)
+                .delete(delete_inventory_config),
+        )
+        // Content hash lookups
+        .route("/buckets/:bucket/check-hash", post(check_hash))
+        .route("/buckets/:bucket/exists", post(objects_exist))
+        .route("/find-by-hash/:sha256", get(find_by_hash))
         // Upload via multipart
         .route("/buckets/:bucket/upload", post(upload_object))
+        .method_not_allowed_fallback(wrong_method)
 }
 
 /// Wildcard routes that MUST be registered at top level (cannot be nested in Axum 0.7)
 pub fn api_wildcard_routes() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/api/object/*path", get(get_object).delete(delete_object))
+        .route(
+            "/api/object/*path",
+            get(get_object)
+                .head(head_object)
+                .post(restore_object_version)
+                .patch(write_object_range)
+                .delete(delete_object),
+        )
+        .route("/api/object-meta/*path", post(rebuild_object_meta))
+        .route(
+            "/api/locks/*path",
+            get(get_locks)
+                .post(acquire_lock)
+                .put(refresh_lock)
+                .delete(release_lock),
+        )
+        .method_not_allowed_fallback(wrong_method)
+}
+
+/// Probes for load balancers and orchestrators, outside `/api`
+pub fn probe_routes() -> Router<Arc<AppState>> {
+    Router::new().route("/readyz", get(readyz))
 }
 
 // ─── S3-Compatible Routes ─────────────────────────────────────────
+//
+// Registered once, under `/s3`; `s3_mounts::rewrite_mounts` maps every
+// configured mount and both object path shapes onto these.
 
 pub fn s3_routes() -> Router<Arc<AppState>> {
     Router::new()
         .route("/s3", get(s3_list_buckets))
         .route("/s3/:bucket", get(s3_list_objects).put(s3_create_bucket).delete(s3_delete_bucket))
+        .method_not_allowed_fallback(wrong_method)
 }
 
 /// S3 wildcard routes — must be registered at top level
 pub fn s3_wildcard_routes() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/s3/obj/*path", get(s3_get_object).put(s3_put_object).delete(s3_delete_object))
+        .route(
+            "/s3/obj/*path",
+            get(s3_get_object)
+                .head(head_object)
+                .put(s3_put_object)
+                .post(s3_post_object)
+                .delete(s3_delete_object),
+        )
+        .method_not_allowed_fallback(wrong_method)
+}
+
+/// Every API and S3 route with the methods it accepts, used to point clients
+/// that miss at the routes they probably meant. Keep in step with the routers
+/// above; S3 objects are listed in the shape clients should use.
+const ROUTE_TABLE: &[(&str, &str)] = &[
+    ("/api/stats", "GET"),
+    ("/api/stats/history", "GET"),
+    ("/api/stats/buckets", "GET"),
+    ("/api/stats/volumes", "GET"),
+    ("/api/metrics", "GET"),
+    ("/api/dashboard-data", "GET"),
+    ("/api/alerts", "GET"),
+    ("/api/server-info", "GET"),
+    ("/api/admin/gc", "POST"),
+    ("/api/admin/bulk", "POST"),
+    ("/api/admin/bulk/:id", "GET"),
+    ("/api/admin/slow-ops", "GET"),
+    ("/api/admin/audit", "GET"),
+    ("/api/admin/reload", "POST"),
+    ("/api/admin/self-test", "POST"),
+    ("/api/admin/backup", "POST"),
+    ("/api/admin/cache/clear", "POST"),
+    ("/api/admin/recordings", "GET"),
+    ("/api/admin/recordings/:id/request", "GET"),
+    ("/api/admin/recordings/:id/response", "GET"),
+    ("/api/admin/deleted-buckets", "GET"),
+    ("/api/admin/deleted-buckets/:id/restore", "POST"),
+    ("/api/admin/quarantine", "GET"),
+    ("/api/admin/quarantine/:bucket/:id", "DELETE"),
+    ("/api/admin/quarantine/:bucket/:id/content", "GET"),
+    ("/api/admin/quarantine/:bucket/:id/approve", "POST"),
+    ("/api/admin/quarantine/:bucket/:id/reject", "POST"),
+    ("/api/transactions", "POST"),
+    ("/api/shares", "GET, POST"),
+    ("/api/shares/:token", "DELETE"),
+    ("/api/diff", "POST"),
+    ("/api/buckets", "GET, POST"),
+    ("/api/buckets/:bucket", "GET, PATCH, DELETE"),
+    ("/api/buckets/:bucket/versioning", "PUT"),
+    ("/api/buckets/:bucket/versions", "GET"),
+    ("/api/buckets/:bucket/connect-info", "GET"),
+    ("/api/buckets/:bucket/logging", "GET, PUT, DELETE"),
+    ("/api/buckets/:bucket/origin", "GET, PUT, DELETE"),
+    ("/api/buckets/:bucket/quarantine", "GET, PUT, DELETE"),
+    ("/api/inbox/:bucket", "POST"),
+    ("/api/buckets/:bucket/objects", "GET"),
+    ("/api/buckets/:bucket/objects.ndjson", "GET"),
+    ("/api/buckets/:bucket/prefix-stats", "GET"),
+    ("/api/buckets/:bucket/largest", "GET"),
+    ("/api/buckets/:bucket/traffic", "GET"),
+    ("/api/buckets/:bucket/hook-runs", "GET"),
+    ("/api/buckets/:bucket/fix-content-types", "POST"),
+    ("/api/buckets/:bucket/recompute-stats", "POST, DELETE"),
+    ("/api/buckets/:bucket/recompute-stats/status", "GET"),
+    ("/api/buckets/:bucket/metadata-recovery", "DELETE"),
+    ("/api/buckets/:bucket/prefix", "DELETE"),
+    ("/api/buckets/:bucket/snapshots", "GET, POST"),
+    ("/api/buckets/:bucket/snapshots/:id", "DELETE"),
+    ("/api/buckets/:bucket/snapshots/:id/restore", "POST"),
+    ("/api/buckets/:bucket/inventory", "POST"),
+    ("/api/buckets/:bucket/inventory-config", "GET, PUT, DELETE"),
+    ("/api/buckets/:bucket/check-hash", "POST"),
+    ("/api/buckets/:bucket/exists", "POST"),
+    ("/api/find-by-hash/:sha256", "GET"),
+    ("/api/buckets/:bucket/upload", "POST"),
+    ("/api/object/*path", "GET, HEAD, POST, PATCH, DELETE"),
+    ("/api/object-meta/*path", "POST"),
+    ("/api/locks/*path", "GET, POST, PUT, DELETE"),
+    ("/s3", "GET"),
+    ("/s3/:bucket", "GET, PUT, DELETE"),
+    ("/s3/:bucket/*key", "GET, HEAD, PUT, POST, DELETE"),
+];
+
+/// How many leading segments of `path` a route pattern accounts for, and
+/// whether it accounts for all of them. With `fuzzy`, a segment that is a
+/// prefix of the literal (or the other way round) counts, so `/api/bucket/x`
+/// still lands near `/api/buckets/:bucket`.
+fn route_match(pattern: &str, path: &str, fuzzy: bool) -> (usize, bool) {
+    let path: Vec<&str> = path.split('/').skip(1).collect();
+    let mut matched = 0;
+    for (i, want) in pattern.split('/').skip(1).enumerate() {
+        if want.starts_with('*') {
+            let rest = &path[i.min(path.len())..];
+            let whole = !rest.is_empty() && !rest[0].is_empty();
+            return (matched + rest.len(), whole);
+        }
+        let Some(got) = path.get(i) else {
+            return (matched, false);
+        };
+        let hit = want == *got
+            || (!got.is_empty()
+                && (want.starts_with(':')
+                    || (fuzzy && (want.starts_with(got) || got.starts_with(want)))));
+        if !hit {
+            return (matched, false);
+        }
+        matched += 1;
+    }
+    (matched, matched == path.len())
+}
+
+/// Sentence listing the routes closest to a path nothing matched, or an
+/// empty string when nothing shares more than the `/api` or `/s3` root
+fn nearby_routes_hint(path: &str) -> String {
+    let mut scored: Vec<(usize, &(&str, &str))> = ROUTE_TABLE
+        .iter()
+        .map(|route| (route_match(route.0, path, true).0, route))
+        .filter(|(score, _)| *score >= 2)
+        .collect();
+    let Some(best) = scored.iter().map(|(score, _)| *score).max() else {
+        return String::new();
+    };
+    scored.retain(|(score, _)| *score == best);
+    // Shortest first: the route the client most likely meant, then its children
+    scored.sort_by_key(|(_, route)| route.0.matches('/').count());
+    let nearby: Vec<String> = scored
+        .iter()
+        .take(5)
+        .map(|(_, (pattern, methods))| format!("{} ({})", pattern, methods))
+        .collect();
+    format!(". Nearby routes: {}", nearby.join("; "))
+}
+
+/// Fallback for paths no route matches. Under `/api` and `/s3` the client
+/// gets a JSON error naming the path and nearby routes; anything else keeps
+/// the bare 404 the dashboard always had.
+pub async fn unknown_route(method: Method, uri: Uri) -> Response {
+    let path = uri.path();
+    let under = |root: &str| path == root || path.starts_with(&format!("{}/", root));
+    if !under("/api") && !under("/s3") {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    AppError::RouteNotFound(format!(
+        "No route for {} {}{}",
+        method,
+        path,
+        nearby_routes_hint(path)
+    ))
+    .into_response()
+}
+
+/// Fallback for a known API or S3 path hit with a method it doesn't accept
+pub async fn wrong_method(method: Method, OriginalUri(uri): OriginalUri) -> AppError {
+    let path = uri.path();
+    let accepted = ROUTE_TABLE
+        .iter()
+        .find(|(pattern, _)| route_match(pattern, path, false).1)
+        .map_or(String::new(), |(pattern, methods)| {
+            format!("; {} accepts {}", pattern, methods)
+        });
+    AppError::MethodNotAllowed(format!("{} is not supported on {}{}", method, path, accepted))
+}
+
+/// Bucket named by a virtual-hosted-style Host header such as
+/// `my-bucket.localhost:3210`, or None for the bare host and anything else
+fn virtual_host_bucket(host: &str, domain: &str) -> Option<String> {
+    // IPv6 literals can never carry a bucket label
+    if host.starts_with('[') {
+        return None;
+    }
+    let host = host.rsplit_once(':').map_or(host, |(name, _)| name).to_ascii_lowercase();
+    let bucket = host
+        .strip_suffix(&domain.to_ascii_lowercase())?
+        .strip_suffix('.')?;
+    StorageEngine::validate_bucket_syntax(bucket).ok()?;
+    Some(bucket.to_string())
+}
+
+/// Rewrite virtual-hosted-style requests (`http://bucket.localhost/key`) onto
+/// the path-style S3 routes before routing. Must wrap the whole router: a
+/// layer added with `Router::layer` runs after the route is already chosen.
+pub async fn virtual_host_rewrite(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| req.uri().authority().map(|a| a.as_str()));
+    let Some(bucket) = host.and_then(|h| virtual_host_bucket(h, &state.config().virtual_host_domain))
+    else {
+        return next.run(req).await;
+    };
+
+    let path = req.uri().path();
+    let rewritten = if path == "/" || path.is_empty() {
+        format!("{}/{}", CANONICAL_MOUNT, bucket)
+    } else {
+        format!("{}/obj/{}{}", CANONICAL_MOUNT, bucket, path)
+    };
+    if let Err(e) = s3_mounts::set_path(&mut req, &rewritten) {
+        return e.into_response();
+    }
+    req.extensions_mut().insert(s3_mounts::Rewritten);
+    next.run(req).await
 }
 
 // ─── Stats ───────────────────────────────────────────────────────
@@ -59,95 +439,377 @@ async fn get_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     Json(state.storage.get_stats())
 }
 
-// ─── Bucket Handlers ─────────────────────────────────────────────
+async fn get_stats_history(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StatsHistoryQuery>,
+) -> AppResult<impl IntoResponse> {
+    let bucket = query.bucket.filter(|b| !b.is_empty());
+    let points = state.storage.stats_history(bucket.as_deref(), query.days)?;
+    Ok(Json(StatsHistoryResponse {
+        bucket,
+        days: query.days,
+        points,
+    }))
+}
 
-async fn list_buckets(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let buckets = state.storage.list_buckets();
-    Json(ListBucketsResponse {
-        buckets,
-        owner: "freebucket-local".to_string(),
+async fn get_bucket_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.storage.bucket_stats())
+}
+
+async fn get_volume_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.storage.volume_stats())
+}
+
+/// Also renews the dashboard's CSRF cookie, so a page left open across a
+/// restart picks up the new token on its next refresh
+async fn get_dashboard_data(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let data = Json(DashboardData {
+        stats: state.storage.get_stats(),
+        buckets: state.storage.list_buckets(),
+        bucket_stats: state.storage.bucket_stats(),
+        audit: state.storage.recent_audit(crate::dashboard::AUDIT_ENTRIES),
+        alerts: state.storage.active_alerts(),
+    });
+    ([(header::SET_COOKIE, state.csrf.cookie())], data)
+}
+
+/// Usage alerts in effect, by bucket
+async fn list_alerts(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.storage.active_alerts())
+}
+
+/// Per-bucket gauges in the Prometheus text exposition format
+async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let stats = state.storage.bucket_stats();
+    let mut body = String::new();
+    push_gauge(&mut body, &stats, "freebucket_bucket_objects", "Number of objects in the bucket", |b| {
+        b.object_count as i64
+    });
+    push_gauge(&mut body, &stats, "freebucket_bucket_bytes", "Total size of the bucket's objects in bytes", |b| {
+        b.total_size as i64
+    });
+    push_gauge(
+        &mut body,
+        &stats,
+        "freebucket_bucket_last_activity_timestamp_seconds",
+        "Unix time of the last write or delete in the bucket",
+        |b| b.last_activity.timestamp(),
+    );
+    body.push_str(&format!(
+        "# HELP freebucket_uploads_in_flight Uploads being handled right now\n\
+         # TYPE freebucket_uploads_in_flight gauge\n\
+         freebucket_uploads_in_flight {}\n",
+        state.upload_limiter.in_flight()
+    ));
+    body.push_str(&format!(
+        "# HELP freebucket_idempotency_hits_total Requests answered with a stored response for their Idempotency-Key\n\
+         # TYPE freebucket_idempotency_hits_total counter\n\
+         freebucket_idempotency_hits_total {}\n\
+         # HELP freebucket_idempotency_misses_total Requests whose Idempotency-Key was new and ran normally\n\
+         # TYPE freebucket_idempotency_misses_total counter\n\
+         freebucket_idempotency_misses_total {}\n",
+        state.idempotency.hits(),
+        state.idempotency.misses()
+    ));
+    body.push_str(&format!(
+        "# HELP freebucket_s3_legacy_requests_total S3 object requests addressed with the deprecated /obj/ path segment\n\
+         # TYPE freebucket_s3_legacy_requests_total counter\n\
+         freebucket_s3_legacy_requests_total {}\n",
+        state.s3_legacy.total()
+    ));
+    let cache = state.storage.read_cache_stats();
+    body.push_str(&format!(
+        "# HELP freebucket_read_cache_hits_total Object reads answered from the in-memory cache\n\
+         # TYPE freebucket_read_cache_hits_total counter\n\
+         freebucket_read_cache_hits_total {}\n\
+         # HELP freebucket_read_cache_misses_total Object reads that went to disk while the cache was on\n\
+         # TYPE freebucket_read_cache_misses_total counter\n\
+         freebucket_read_cache_misses_total {}\n\
+         # HELP freebucket_read_cache_bytes Payload bytes held by the read cache\n\
+         # TYPE freebucket_read_cache_bytes gauge\n\
+         freebucket_read_cache_bytes {}\n\
+         # HELP freebucket_read_cache_objects Objects held by the read cache\n\
+         # TYPE freebucket_read_cache_objects gauge\n\
+         freebucket_read_cache_objects {}\n",
+        cache.hits, cache.misses, cache.bytes, cache.objects
+    ));
+    body.push_str(&format!(
+        "# HELP freebucket_rehash_pending Objects stored with a provisional ETag, waiting to be hashed\n\
+         # TYPE freebucket_rehash_pending gauge\n\
+         freebucket_rehash_pending {}\n",
+        state.storage.pending_rehashes()
+    ));
+    push_op_histograms(&mut body, &state.storage.op_histograms());
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// Storage operation latencies as one Prometheus histogram, labelled by
+/// operation
+fn push_op_histograms(body: &mut String, histograms: &[(&str, OpHistogram)]) {
+    let name = "freebucket_storage_operation_duration_seconds";
+    body.push_str(&format!(
+        "# HELP {} Time taken by storage engine operations\n# TYPE {} histogram\n",
+        name, name
+    ));
+    for (op, histogram) in histograms {
+        let mut cumulative = 0;
+        for (bound, count) in OP_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            body.push_str(&format!("{}_bucket{{operation=\"{}\",le=\"{}\"}} {}\n", name, op, bound, cumulative));
+        }
+        body.push_str(&format!(
+            "{}_bucket{{operation=\"{}\",le=\"+Inf\"}} {}\n{}_sum{{operation=\"{}\"}} {}\n{}_count{{operation=\"{}\"}} {}\n",
+            name, op, histogram.count, name, op, histogram.sum_seconds, name, op, histogram.count
+        ));
+    }
+}
+
+fn push_gauge(
+    body: &mut String,
+    stats: &[BucketStats],
+    name: &str,
+    help: &str,
+    value: impl Fn(&BucketStats) -> i64,
+) {
+    body.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name));
+    for bucket in stats {
+        body.push_str(&format!(
+            "{}{{bucket=\"{}\"}} {}\n",
+            name,
+            bucket.name.replace('\\', "\\\\").replace('"', "\\\""),
+            value(bucket)
+        ));
+    }
+}
+
+async fn get_server_info(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let config = state.config();
+    Json(ServerInfo {
+        name: "FreeBucket".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        bind_address: format!("{}:{}", config.host, config.port),
+        loopback_only: config.is_loopback(),
+        auth_configured: config.auth_configured(),
+        exposed: config.is_exposed(),
+        default_region: config.default_region.clone(),
+        s3_mounts: config.s3_mounts.clone(),
+        read_cache: state.storage.read_cache_stats(),
+        uploads_in_flight: state.upload_limiter.in_flight(),
+        max_concurrent_uploads: state.upload_limiter.max(),
+        filesystem: state.storage.filesystem_features(),
     })
 }
 
-async fn create_bucket(
+/// The most recent storage operations over the slow-op threshold
+async fn run_self_test(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let report = state.storage.self_test(false);
+    let status = if report.passed { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report))
+}
+
+/// `POST /api/admin/cache/clear` empties the in-memory read cache
+async fn clear_read_cache(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.storage.clear_read_cache())
+}
+
+/// The request recorder, or why there is none
+fn recorder(state: &AppState) -> AppResult<&crate::recording::Recorder> {
+    state.recorder.as_deref().ok_or_else(|| {
+        AppError::NotImplemented(
+            "Requests are not being recorded; start the server with --record-requests DIR".to_string(),
+        )
+    })
+}
+
+/// `GET /api/admin/recordings` lists the recorded S3 requests, newest first
+async fn list_recordings(State(state): State<Arc<AppState>>) -> AppResult<impl IntoResponse> {
+    Ok(Json(recorder(&state)?.list()))
+}
+
+async fn get_recorded_request(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> AppResult<Response> {
+    recorded_message(&state, &id, crate::recording::Side::Request).await
+}
+
+async fn get_recorded_response(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> AppResult<Response> {
+    recorded_message(&state, &id, crate::recording::Side::Response).await
+}
+
+/// One side of a recording, as the HTTP message written to disk
+async fn recorded_message(state: &AppState, id: &str, side: crate::recording::Side) -> AppResult<Response> {
+    let path = recorder(state)?.file(id, side)?;
+    let data = tokio::fs::read(&path).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => AppError::RecordingNotFound(id.to_string()),
+        _ => e.into(),
+    })?;
+    Ok(([("content-type", "message/http")], data).into_response())
+}
+
+/// `POST /api/admin/backup` backs up every bucket into a new directory
+/// under the given one, on the server's filesystem
+async fn create_backup(
     State(state): State<Arc<AppState>>,
-    Json(body): Json<CreateBucketRequest>,
+    Json(body): Json<BackupRequest>,
 ) -> AppResult<impl IntoResponse> {
-    let bucket = state.storage.create_bucket(&body.name, &body.region)?;
-    Ok((StatusCode::CREATED, Json(bucket)))
+    let report = tokio::task::spawn_blocking(move || state.storage.create_backup(std::path::Path::new(&body.dir)))
+        .await
+        .map_err(|e| AppError::StorageError(format!("Backup panicked: {}", e)))??;
+    Ok((StatusCode::CREATED, Json(report)))
 }
 
-async fn get_bucket(
+/// Ready when the data dir takes writes, or, with a self-test interval
+/// configured, when the latest light self-test passed. A result older than
+/// three intervals means the checks have stalled and counts as not ready.
+async fn readyz(State(state): State<Arc<AppState>>) -> Response {
+    let interval = state.config().readyz_self_test_secs;
+    if interval == 0 {
+        return match state.storage.probe_writable() {
+            Ok(()) => Json(json!({ "status": "ready" })).into_response(),
+            Err(e) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "status": "failing", "error": e.parts().2 })),
+            )
+                .into_response(),
+        };
+    }
+    let Some(report) = state.readiness.lock().unwrap().clone() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "status": "starting" }))).into_response();
+    };
+    let age = (state.storage.now() - report.finished_at).to_std().unwrap_or_default();
+    let status = if age > std::time::Duration::from_secs(interval * 3) {
+        "stale"
+    } else if report.passed {
+        "ready"
+    } else {
+        "failing"
+    };
+    let code = if status == "ready" { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(json!({ "status": status, "self_test": report }))).into_response()
+}
+
+async fn get_slow_ops(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.storage.slow_ops())
+}
+
+async fn reload_config(State(state): State<Arc<AppState>>) -> AppResult<impl IntoResponse> {
+    Ok(Json(crate::reload::audited_reload(&state, API_ACTOR)?))
+}
+
+async fn get_audit_log(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    Json(json!({ "entries": state.storage.query_audit(&query) }))
+}
+
+async fn run_gc(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<GcQuery>,
+) -> AppResult<impl IntoResponse> {
+    let report = state
+        .storage
+        .collect_garbage(&state.config().gc_options(query.dry_run))?;
+    Ok(Json(report))
+}
+
+async fn fix_content_types(
     State(state): State<Arc<AppState>>,
     Path(bucket): Path<String>,
+    Query(query): Query<FixContentTypesQuery>,
 ) -> AppResult<impl IntoResponse> {
-    let bucket = state.storage.get_bucket(&bucket)?;
-    Ok(Json(bucket))
+    Ok(Json(state.storage.fix_content_types(&bucket, &query)?))
 }
 
-async fn delete_bucket(
+async fn start_stats_recompute(
     State(state): State<Arc<AppState>>,
     Path(bucket): Path<String>,
 ) -> AppResult<impl IntoResponse> {
-    state.storage.delete_bucket(&bucket)?;
-    Ok(StatusCode::NO_CONTENT)
+    let job = crate::jobs::start_stats_recompute(state, &bucket)?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
 }
 
-// ─── Object Handlers ─────────────────────────────────────────────
+async fn get_stats_recompute(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    Ok(Json(state.storage.stats_recompute(&bucket)?))
+}
 
-async fn list_objects(
+async fn cancel_stats_recompute(
     State(state): State<Arc<AppState>>,
     Path(bucket): Path<String>,
-    Query(query): Query<ListObjectsQuery>,
 ) -> AppResult<impl IntoResponse> {
-    let prefix = query.prefix.as_deref().unwrap_or("");
-    let delimiter = query.delimiter.as_deref();
-    let max_keys = query.max_keys.unwrap_or(1000);
+    Ok(Json(state.storage.cancel_stats_recompute(&bucket)?))
+}
 
-    let response = state.storage.list_objects(&bucket, prefix, delimiter, max_keys)?;
-    Ok(Json(response))
+/// Accept a bucket's recovered metadata, as `fsck --confirm-metadata`
+/// does, without stopping the server
+async fn confirm_bucket_metadata(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    state.storage.confirm_bucket_metadata(&bucket)?;
+    Ok(Json(state.storage.get_bucket(&bucket)?))
 }
 
-/// Parse a catch-all path like "mybucket/path/to/key.txt" into (bucket, key)
-fn parse_bucket_key(path: &str) -> Result<(&str, &str), AppError> {
-    let path = path.strip_prefix('/').unwrap_or(path);
-    match path.find('/') {
-        Some(pos) => Ok((&path[..pos], &path[pos + 1..])),
-        None => Err(AppError::InvalidObjectKey(
-            "Path must be in the format: {bucket}/{key}".to_string(),
-        )),
-    }
+async fn start_bulk_job(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<BulkRequest>,
+) -> AppResult<impl IntoResponse> {
+    let job = crate::jobs::start_bulk_job(state, body)?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
 }
 
-async fn get_object(
+async fn get_bulk_job(
     State(state): State<Arc<AppState>>,
-    Path(path): Path<String>,
-) -> AppResult<Response> {
-    let (bucket, key) = parse_bucket_key(&path)?;
-    let (meta, data) = state.storage.get_object(bucket, key)?;
+    Path(id): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let job = state.bulk_jobs.get(&id).ok_or(AppError::JobNotFound(id))?;
+    Ok(Json(job))
+}
 
-    let mut headers = HeaderMap::new();
-    headers.insert("content-type", meta.content_type.parse().unwrap());
-    headers.insert("etag", meta.etag.parse().unwrap());
-    headers.insert(
-        "last-modified",
-        meta.last_modified.to_rfc2822().parse().unwrap(),
-    );
-    headers.insert("content-length", meta.size.to_string().parse().unwrap());
+async fn apply_transaction(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<TransactionRequest>,
+) -> AppResult<impl IntoResponse> {
+    Ok(Json(state.storage.apply_transaction(&body)?))
+}
+
+// ─── Bucket Handlers ─────────────────────────────────────────────
 
-    Ok((StatusCode::OK, headers, data).into_response())
+async fn list_buckets(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let buckets = state.storage.list_buckets();
+    Json(ListBucketsResponse {
+        buckets,
+        owner: "freebucket-local".to_string(),
+    })
 }
 
-async fn delete_object(
+async fn create_bucket(
     State(state): State<Arc<AppState>>,
-    Path(path): Path<String>,
+    Json(body): Json<CreateBucketRequest>,
 ) -> AppResult<impl IntoResponse> {
-    let (bucket, key) = parse_bucket_key(&path)?;
-    state.storage.delete_object(bucket, key)?;
-    Ok(StatusCode::NO_CONTENT)
+    let region = body.region.unwrap_or_else(|| state.config().default_region.clone());
+    let created = state.storage.create_bucket_on(&body.name, &region, body.volume.as_deref());
+    let bucket = audited(&state, AuditAction::BucketCreate, &body.name, created)?;
+    Ok((StatusCode::CREATED, Json(bucket)))
 }
 
-async fn upload_object(
+/// Bucket with usage and recent objects; `?view=basic` returns just the
+/// stored bucket, as this endpoint did originally
+async fn get_bucket(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> AppResult<Response> {
+    if params.get("view").map(String::as_str) == Some("basic") {
+        return Ok(Json(state.storage.get_bucket(&bucket)?).into_response());
+    }
+    Ok(Json(state.storage.bucket_details(&bucket)?).into_response())
+}
+
+/// Uploads from the public inbox page. Only what was just stored is
+/// echoed back, so the inbox never reveals other objects in the bucket.
+async fn inbox_upload(
     State(state): State<Arc<AppState>>,
     Path(bucket): Path<String>,
     mut multipart: Multipart,
@@ -157,147 +819,3293 @@ async fn upload_object(
     while let Some(field) = multipart.next_field().await.map_err(|e| {
         AppError::StorageError(format!("Multipart error: {}", e))
     })? {
-        let file_name = field
-            .file_name()
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| format!("upload-{}", uuid::Uuid::new_v4()));
-
+        let file_name = field.file_name().unwrap_or("upload").to_string();
         let content_type = field.content_type().map(|s| s.to_string());
         let data = field.bytes().await.map_err(|e| {
             AppError::StorageError(format!("Failed to read upload data: {}", e))
         })?;
 
-        let meta = state.storage.put_object(
-            &bucket,
-            &file_name,
-            &data,
-            content_type.as_deref(),
-            HashMap::new(),
-        )?;
-
-        uploaded.push(meta);
+        let meta = state
+            .storage
+            .put_inbox_object(&bucket, &file_name, &data, content_type.as_deref())?;
+        uploaded.push(json!({ "name": file_name, "key": meta.key, "size": meta.size }));
     }
 
-    Ok((StatusCode::CREATED, Json(json!({
-        "uploaded": uploaded.len(),
-        "objects": uploaded
-    }))))
+    Ok((StatusCode::CREATED, Json(json!({ "uploaded": uploaded }))))
 }
 
-// ─── S3-Compatible Handlers ──────────────────────────────────────
+/// Connection details built from the Host the client used, so a dashboard
+/// opened via a LAN address gets snippets that work from that machine
+async fn get_connect_info(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let info = state.storage.get_bucket(&bucket)?;
+    let config = state.config();
 
-async fn s3_list_buckets(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let buckets = state.storage.list_buckets();
-    // Return XML-like JSON for simplicity (real S3 uses XML)
-    Json(json!({
-        "ListAllMyBucketsResult": {
-            "Buckets": buckets.iter().map(|b| json!({
-                "Name": b.name,
-                "CreationDate": b.created_at.to_rfc3339()
-            })).collect::<Vec<_>>(),
-            "Owner": {
-                "DisplayName": "freebucket-local",
-                "ID": "freebucket"
-            }
-        }
+    let bind_address = format!("{}:{}", config.host, config.port);
+    let host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or(&bind_address);
+    // TLS is only ever terminated by a proxy in front of the server
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .filter(|p| p.eq_ignore_ascii_case("https"))
+        .map_or("http", |_| "https");
+    let endpoint = format!("{}://{}", scheme, host);
+
+    // `<bucket>.<host>` only reaches the bucket when the host is the configured domain
+    let hostname = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    let virtual_hosted = hostname.eq_ignore_ascii_case(&config.virtual_host_domain);
+
+    Ok(Json(ConnectInfo {
+        object_url: format!("{}{}/{}/", endpoint, config.s3_mounts[0], info.name),
+        virtual_hosted_url: virtual_hosted.then(|| format!("{}://{}.{}/", scheme, info.name, host)),
+        bucket: info.name,
+        region: info.region,
+        endpoint,
+        path_style: false,
+        virtual_hosted,
+        tls: scheme == "https",
+        auth_required: config.auth_configured(),
     }))
 }
 
-async fn s3_create_bucket(
+async fn update_bucket_settings(
     State(state): State<Arc<AppState>>,
     Path(bucket): Path<String>,
+    Json(req): Json<BucketSettingsRequest>,
 ) -> AppResult<impl IntoResponse> {
-    state.storage.create_bucket(&bucket, "local")?;
-    Ok(StatusCode::OK)
+    let changes = settings_changed(&req);
+    let updated = apply_bucket_settings(&state, &bucket, req);
+    let audit = Audit {
+        actor: API_ACTOR,
+        action: AuditAction::BucketSettings,
+        bucket: Some(&bucket),
+        target: &bucket,
+        changes,
+    };
+    Ok(Json(state.storage.audited(audit, updated)?))
 }
 
-async fn s3_delete_bucket(
+/// Names of the settings a PATCH sets, for the audit log. Values are left
+/// out, since webhooks carry secrets.
+fn settings_changed(req: &BucketSettingsRequest) -> Vec<String> {
+    [
+        ("region", req.region.is_some()),
+        ("versioning", req.versioning.is_some()),
+        ("inbox", req.inbox.is_some()),
+        ("inbox_limits", req.inbox_limits.is_some()),
+        ("precompressed", req.precompressed.is_some()),
+        ("overwrite_policy", req.overwrite_policy.is_some()),
+        ("max_objects", req.max_objects.is_some()),
+        ("webhooks", req.webhooks.is_some()),
+        ("exec_hooks", req.exec_hooks.is_some()),
+        ("alerts", req.alerts.is_some()),
+    ]
+    .into_iter()
+    .filter(|(_, set)| *set)
+    .map(|(name, _)| name.to_string())
+    .collect()
+}
+
+fn apply_bucket_settings(state: &AppState, bucket: &str, req: BucketSettingsRequest) -> AppResult<Bucket> {
+    let mut info = state.storage.get_bucket(bucket)?;
+    if let Some(hooks) = &req.exec_hooks {
+        exec_hooks::check_allowed(&state.config(), hooks)?;
+    }
+    if let Some(region) = &req.region {
+        info = state.storage.set_bucket_region(bucket, region)?;
+    }
+    // `version` needs versioning on; any other policy goes first so that
+    // leaving `version` and turning versioning off work in one request
+    let policy = req.overwrite_policy;
+    if let Some(policy) = policy.filter(|p| *p != OverwritePolicy::Version) {
+        info = state.storage.set_bucket_overwrite_policy(bucket, policy)?;
+    }
+    if let Some(enabled) = req.versioning {
+        info = state.storage.set_bucket_versioning(bucket, enabled)?;
+    }
+    if policy == Some(OverwritePolicy::Version) {
+        info = state.storage.set_bucket_overwrite_policy(bucket, OverwritePolicy::Version)?;
+    }
+    if req.inbox.is_some() || req.inbox_limits.is_some() {
+        let enabled = req.inbox.unwrap_or(info.inbox);
+        info = state.storage.set_bucket_inbox(bucket, enabled, req.inbox_limits)?;
+    }
+    if let Some(enabled) = req.precompressed {
+        info = state.storage.set_bucket_precompressed(bucket, enabled)?;
+    }
+    if let Some(limit) = req.max_objects {
+        info = state.storage.set_bucket_max_objects(bucket, (limit > 0).then_some(limit))?;
+    }
+    if let Some(webhooks) = req.webhooks {
+        info = state.storage.set_bucket_webhooks(bucket, webhooks)?;
+    }
+    if let Some(hooks) = req.exec_hooks {
+        info = state.storage.set_bucket_exec_hooks(bucket, Some(hooks))?;
+    }
+    if let Some(alerts) = req.alerts {
+        info = state.storage.set_bucket_alerts(bucket, Some(alerts))?;
+    }
+    Ok(info)
+}
+
+async fn set_versioning(
     State(state): State<Arc<AppState>>,
     Path(bucket): Path<String>,
+    Json(req): Json<VersioningRequest>,
 ) -> AppResult<impl IntoResponse> {
-    state.storage.delete_bucket(&bucket)?;
-    Ok(StatusCode::NO_CONTENT)
+    let updated = state.storage.set_bucket_versioning(&bucket, req.enabled);
+    Ok(Json(audited_settings(&state, &bucket, "versioning", updated)?))
 }
 
-async fn s3_list_objects(
+async fn list_object_versions(
     State(state): State<Arc<AppState>>,
     Path(bucket): Path<String>,
-    Query(query): Query<ListObjectsQuery>,
+    Query(query): Query<VersionsQuery>,
 ) -> AppResult<impl IntoResponse> {
-    let prefix = query.prefix.as_deref().unwrap_or("");
-    let delimiter = query.delimiter.as_deref();
-    let max_keys = query.max_keys.unwrap_or(1000);
+    Ok(Json(state.storage.list_object_versions(&bucket, &query.key)?))
+}
 
-    let response = state.storage.list_objects(&bucket, prefix, delimiter, max_keys)?;
+async fn delete_bucket(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let deleted = state.storage.delete_bucket(&bucket, false);
+    audited(&state, AuditAction::BucketDelete, &bucket, deleted)?;
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    Ok(Json(json!({
-        "ListBucketResult": {
-            "Name": response.bucket,
-            "Prefix": response.prefix,
-            "MaxKeys": response.max_keys,
-            "IsTruncated": response.is_truncated,
-            "Contents": response.objects.iter().map(|o| json!({
-                "Key": o.key,
-                "Size": o.size,
-                "LastModified": o.last_modified.to_rfc3339(),
-                "ETag": o.etag,
-                "StorageClass": "STANDARD"
-            })).collect::<Vec<_>>(),
-            "CommonPrefixes": response.common_prefixes.iter().map(|cp| json!({
-                "Prefix": cp
-            })).collect::<Vec<_>>()
-        }
-    })))
+async fn list_deleted_buckets(State(state): State<Arc<AppState>>) -> AppResult<impl IntoResponse> {
+    Ok(Json(state.storage.list_deleted_buckets()?))
 }
 
-async fn s3_get_object(
+async fn restore_deleted_bucket(
     State(state): State<Arc<AppState>>,
-    Path(path): Path<String>,
-) -> AppResult<Response> {
-    get_object(State(state), Path(path)).await
+    Path(id): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let restored = state.storage.restore_deleted_bucket(&id);
+    let name = restored.as_ref().ok().map(|b| b.name.clone());
+    let audit = Audit {
+        actor: API_ACTOR,
+        action: AuditAction::BucketRestore,
+        bucket: name.as_deref(),
+        target: &id,
+        changes: Vec::new(),
+    };
+    let bucket = state.storage.audited(audit, restored)?;
+    Ok((StatusCode::CREATED, Json(bucket)))
 }
 
-async fn s3_put_object(
+// ─── Quarantine Handlers ─────────────────────────────────────────
+
+async fn list_quarantine(
     State(state): State<Arc<AppState>>,
-    Path(path): Path<String>,
-    headers: HeaderMap,
-    body: axum::body::Bytes,
+    Query(query): Query<QuarantineQuery>,
 ) -> AppResult<impl IntoResponse> {
-    let (bucket, key) = parse_bucket_key(&path)?;
-    let content_type = headers
-        .get("content-type")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
-
-    // Extract custom metadata from x-amz-meta-* headers
-    let mut metadata = HashMap::new();
-    for (hdr_key, value) in headers.iter() {
-        if let Some(meta_key) = hdr_key.as_str().strip_prefix("x-amz-meta-") {
-            if let Ok(val) = value.to_str() {
-                metadata.insert(meta_key.to_string(), val.to_string());
-            }
-        }
-    }
+    let uploads = state.storage.list_quarantine(query.bucket.as_deref(), query.status)?;
+    Ok(Json(json!({ "uploads": uploads })))
+}
 
-    let meta = state.storage.put_object(
-        bucket,
-        key,
-        &body,
-        content_type.as_deref(),
-        metadata,
-    )?;
+/// The payload of a pending upload, for scanners that can't read the data dir
+async fn get_quarantined_content(
+    State(state): State<Arc<AppState>>,
+    Path((bucket, id)): Path<(String, String)>,
+) -> AppResult<Response> {
+    let path = state.storage.quarantined_payload(&bucket, &id)?;
+    let file = tokio::fs::File::open(&path).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => AppError::QuarantinedUploadNotFound { bucket, id },
+        _ => e.into(),
+    })?;
+    let body = axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(file));
+    Ok(([("content-type", "application/octet-stream")], body).into_response())
+}
 
-    let mut resp_headers = HeaderMap::new();
-    resp_headers.insert("etag", meta.etag.parse().unwrap());
+async fn approve_quarantined(
+    State(state): State<Arc<AppState>>,
+    Path((bucket, id)): Path<(String, String)>,
+) -> AppResult<impl IntoResponse> {
+    Ok(Json(state.storage.approve_quarantined(&bucket, &id)?))
+}
 
-    Ok((StatusCode::OK, resp_headers))
+async fn reject_quarantined(
+    State(state): State<Arc<AppState>>,
+    Path((bucket, id)): Path<(String, String)>,
+    body: Option<Json<RejectUploadRequest>>,
+) -> AppResult<impl IntoResponse> {
+    let reason = body
+        .and_then(|Json(r)| r.reason)
+        .unwrap_or_else(|| "Rejected by an administrator".to_string());
+    Ok(Json(state.storage.reject_quarantined(&bucket, &id, &reason)?))
 }
 
-async fn s3_delete_object(
+async fn dismiss_quarantined(
     State(state): State<Arc<AppState>>,
-    Path(path): Path<String>,
+    Path((bucket, id)): Path<(String, String)>,
 ) -> AppResult<impl IntoResponse> {
-    let (bucket, key) = parse_bucket_key(&path)?;
-    state.storage.delete_object(bucket, key)?;
+    state.storage.dismiss_quarantined(&bucket, &id)?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+async fn get_bucket_quarantine(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    Ok(Json(json!({
+        "bucket": bucket,
+        "config": state.storage.get_bucket(&bucket)?.quarantine
+    })))
+}
+
+async fn put_bucket_quarantine(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Json(config): Json<QuarantineConfig>,
+) -> AppResult<impl IntoResponse> {
+    let updated = state.storage.set_bucket_quarantine(&bucket, Some(config));
+    Ok(Json(audited_settings(&state, &bucket, "quarantine", updated)?.quarantine))
+}
+
+async fn delete_bucket_quarantine(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let updated = state.storage.set_bucket_quarantine(&bucket, None);
+    audited_settings(&state, &bucket, "quarantine", updated)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ─── Snapshot Handlers ───────────────────────────────────────────
+
+async fn create_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let snapshot = state.storage.create_snapshot(&bucket)?;
+    Ok((StatusCode::CREATED, Json(snapshot)))
+}
+
+async fn list_snapshots(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let snapshots = state.storage.list_snapshots(&bucket)?;
+    Ok(Json(json!({
+        "bucket": bucket,
+        "snapshots": snapshots
+    })))
+}
+
+async fn restore_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path((bucket, id)): Path<(String, String)>,
+    Query(query): Query<RestoreSnapshotQuery>,
+) -> AppResult<impl IntoResponse> {
+    let restored = state.storage.restore_snapshot(&bucket, &id, query.force);
+    Ok(Json(audited_in_bucket(&state, AuditAction::SnapshotRestore, &bucket, &id, restored)?))
+}
+
+async fn delete_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path((bucket, id)): Path<(String, String)>,
+) -> AppResult<impl IntoResponse> {
+    let deleted = state.storage.delete_snapshot(&bucket, &id);
+    audited_in_bucket(&state, AuditAction::SnapshotDelete, &bucket, &id, deleted)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ─── Inventory Handlers ──────────────────────────────────────────
+
+/// Buckets with more objects than this must write their inventory to a destination bucket
+const INLINE_INVENTORY_LIMIT: u64 = 10_000;
+
+async fn generate_inventory(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    body: Option<Json<InventoryRequest>>,
+) -> AppResult<Response> {
+    let request = body.map(|Json(r)| r).unwrap_or_default();
+    let info = state.storage.get_bucket(&bucket)?;
+
+    if let Some(dest_bucket) = &request.destination_bucket {
+        let meta = state.storage.generate_inventory_object(
+            &bucket,
+            request.format,
+            dest_bucket,
+            &request.destination_prefix,
+        )?;
+        return Ok((StatusCode::CREATED, Json(meta)).into_response());
+    }
+
+    if info.object_count > INLINE_INVENTORY_LIMIT {
+        return Err(AppError::InvalidRequest(format!(
+            "Bucket '{}' has {} objects; set destination_bucket to write the inventory as an object",
+            bucket, info.object_count
+        )));
+    }
+
+    let mut report = Vec::new();
+    state.storage.write_inventory(&bucket, request.format, &mut report)?;
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", request.format.content_type().parse().unwrap());
+    Ok((StatusCode::OK, headers, report).into_response())
+}
+
+async fn get_inventory_config(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    Ok(Json(json!({
+        "bucket": bucket,
+        "config": state.storage.get_inventory_config(&bucket)?
+    })))
+}
+
+async fn put_inventory_config(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Json(config): Json<InventoryConfig>,
+) -> AppResult<impl IntoResponse> {
+    let updated = state.storage.set_inventory_config(&bucket, &config);
+    audited_settings(&state, &bucket, "inventory", updated)?;
+    Ok(Json(config))
+}
+
+async fn delete_inventory_config(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let updated = state.storage.delete_inventory_config(&bucket);
+    audited_settings(&state, &bucket, "inventory", updated)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_bucket_logging(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    Ok(Json(json!({
+        "bucket": bucket,
+        "config": state.storage.get_bucket(&bucket)?.logging
+    })))
+}
+
+async fn put_bucket_logging(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Json(config): Json<LoggingConfig>,
+) -> AppResult<impl IntoResponse> {
+    let updated = state.storage.set_bucket_logging(&bucket, Some(config));
+    Ok(Json(audited_settings(&state, &bucket, "logging", updated)?.logging))
+}
+
+async fn delete_bucket_logging(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let updated = state.storage.set_bucket_logging(&bucket, None);
+    audited_settings(&state, &bucket, "logging", updated)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_bucket_origin(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    Ok(Json(json!({
+        "bucket": bucket,
+        "config": state.storage.get_bucket(&bucket)?.origin
+    })))
+}
+
+async fn put_bucket_origin(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Json(config): Json<OriginConfig>,
+) -> AppResult<impl IntoResponse> {
+    let updated = state.storage.set_bucket_origin(&bucket, Some(config));
+    Ok(Json(audited_settings(&state, &bucket, "origin", updated)?.origin))
+}
+
+async fn delete_bucket_origin(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let updated = state.storage.set_bucket_origin(&bucket, None);
+    audited_settings(&state, &bucket, "origin", updated)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ─── Lease Handlers ──────────────────────────────────────────────
+
+/// The lease token a write or lease request carries
+fn lock_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get("x-freebucket-lock-token").and_then(|v| v.to_str().ok())
+}
+
+/// While a key is leased, writes to it need the lease's token
+fn check_lock(state: &AppState, bucket: &str, key: &str, headers: &HeaderMap) -> AppResult<()> {
+    state.locks.check_write(bucket, key, lock_token(headers))
+}
+
+/// The bucket and key of `/api/locks/{bucket}/{key}`, with the key optional
+fn parse_lock_path(state: &AppState, path: &str) -> AppResult<(String, Option<String>)> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    let (bucket, key) = match path.split_once('/') {
+        Some((bucket, key)) => (bucket, Some(key.to_string()).filter(|k| !k.is_empty())),
+        None => (path, None),
+    };
+    state.storage.get_bucket(bucket)?;
+    Ok((bucket.to_string(), key))
+}
+
+fn require_lock_key(key: Option<String>) -> AppResult<String> {
+    key.ok_or_else(|| AppError::InvalidObjectKey("Path must be in the format: {bucket}/{key}".to_string()))
+}
+
+/// A requested lease TTL, between a second and `FREEBUCKET_LOCK_MAX_TTL_SECS`
+fn lease_ttl(state: &AppState, ttl_secs: Option<u64>) -> AppResult<Option<u64>> {
+    let max = state.config().lock_max_ttl_secs;
+    match ttl_secs {
+        Some(ttl) if ttl == 0 || ttl > max => Err(AppError::InvalidRequest(format!(
+            "ttl_secs must be between 1 and {}",
+            max
+        ))),
+        ttl => Ok(ttl),
+    }
+}
+
+/// `GET /api/locks/{bucket}` lists a bucket's active leases (`?prefix=`
+/// narrows them); with a key it shows that key's lease
+async fn get_locks(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> AppResult<Response> {
+    match parse_lock_path(&state, &path)? {
+        (bucket, Some(key)) => {
+            let lease = state
+                .locks
+                .get(&bucket, &key)
+                .ok_or(AppError::LeaseNotFound { bucket, key })?;
+            Ok(Json(lease).into_response())
+        }
+        (bucket, None) => {
+            let prefix = params.get("prefix").map(String::as_str).unwrap_or("");
+            Ok(Json(json!({ "leases": state.locks.list(&bucket, prefix) })).into_response())
+        }
+    }
+}
+
+/// `POST /api/locks/{bucket}/{key}` takes the lease on a key and returns
+/// its token, or `423` while someone else holds it
+async fn acquire_lock(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    body: Option<Json<LeaseRequest>>,
+) -> AppResult<impl IntoResponse> {
+    let (bucket, key) = parse_lock_path(&state, &path)?;
+    let key = require_lock_key(key)?;
+    let request = body.map(|Json(r)| r).unwrap_or_default();
+    let ttl = lease_ttl(&state, request.ttl_secs)?.unwrap_or(crate::locks::DEFAULT_TTL_SECS);
+    let owner = request.owner.filter(|o| !o.trim().is_empty());
+    let acquired = state.locks.acquire(&bucket, &key, owner, ttl)?;
+    Ok((StatusCode::CREATED, Json(acquired)))
+}
+
+/// `PUT /api/locks/{bucket}/{key}` with the lease's token extends it
+async fn refresh_lock(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    body: Option<Json<LeaseRequest>>,
+) -> AppResult<impl IntoResponse> {
+    let (bucket, key) = parse_lock_path(&state, &path)?;
+    let key = require_lock_key(key)?;
+    let ttl = lease_ttl(&state, body.and_then(|Json(r)| r.ttl_secs))?;
+    Ok(Json(state.locks.refresh(&bucket, &key, lock_token(&headers), ttl)?))
+}
+
+/// `DELETE /api/locks/{bucket}/{key}` with the lease's token gives it up
+async fn release_lock(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let (bucket, key) = parse_lock_path(&state, &path)?;
+    let key = require_lock_key(key)?;
+    state.locks.release(&bucket, &key, lock_token(&headers))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ─── Share Link Handlers ─────────────────────────────────────────
+
+/// `POST /api/shares` creates a link to a key, or to everything under a
+/// prefix, and returns its token and path
+async fn create_share(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<CreateShareRequest>,
+) -> AppResult<impl IntoResponse> {
+    state.storage.get_bucket(&request.bucket)?;
+    let (key, prefix) = match (request.key, request.prefix) {
+        (Some(key), None) => {
+            state.storage.stat_object(&request.bucket, &key)?;
+            (key, false)
+        }
+        (None, Some(prefix)) => {
+            // Stored ending in '/', so a share of `docs` can't reach `docs-private/`
+            let prefix = prefix.trim_end_matches('/');
+            if prefix.is_empty() {
+                return Err(AppError::InvalidRequest(
+                    "A shared prefix can't be empty; share a folder of the bucket".to_string(),
+                ));
+            }
+            (format!("{}/", prefix), true)
+        }
+        _ => {
+            return Err(AppError::InvalidRequest(
+                "A share needs exactly one of key and prefix".to_string(),
+            ))
+        }
+    };
+    let expires_in = request.expires_in_secs.unwrap_or(crate::shares::DEFAULT_EXPIRY_SECS);
+    if expires_in == 0 || expires_in > crate::shares::MAX_EXPIRY_SECS {
+        return Err(AppError::InvalidRequest(format!(
+            "expires_in_secs must be between 1 and {}",
+            crate::shares::MAX_EXPIRY_SECS
+        )));
+    }
+    if request.max_downloads == Some(0) {
+        return Err(AppError::InvalidRequest("max_downloads must be at least 1".to_string()));
+    }
+    // bcrypt is slow on purpose, so it stays off the async workers
+    let password_hash = match request.password.filter(|p| !p.is_empty()) {
+        Some(password) => Some(
+            tokio::task::spawn_blocking(move || bcrypt::hash(password, bcrypt::DEFAULT_COST))
+                .await
+                .map_err(|e| AppError::StorageError(e.to_string()))?
+                .map_err(|e| AppError::InvalidRequest(format!("Cannot hash the password: {}", e)))?,
+        ),
+        None => None,
+    };
+    let share = state
+        .shares
+        .create(&request.bucket, &key, prefix, expires_in, request.max_downloads, password_hash);
+    let url = format!("/share/{}", share.token);
+    Ok((StatusCode::CREATED, Json(CreatedShare { share, url })))
+}
+
+/// `GET /api/shares` lists share links, including ended ones still kept;
+/// `?bucket=` narrows them to one bucket
+async fn list_shares(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> AppResult<impl IntoResponse> {
+    let bucket = params.get("bucket").map(String::as_str);
+    if let Some(bucket) = bucket {
+        state.storage.get_bucket(bucket)?;
+    }
+    Ok(Json(json!({ "shares": state.shares.list(bucket) })))
+}
+
+/// `DELETE /api/shares/{token}` revokes a link; it answers `404` from then on
+async fn revoke_share(State(state): State<Arc<AppState>>, Path(token): Path<String>) -> AppResult<impl IntoResponse> {
+    let share = state.shares.revoke(&token)?;
+    tracing::info!("Revoked share link {} to {}/{}", token, share.bucket, share.key);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ─── Diff Handlers ───────────────────────────────────────────────
+
+/// Differences a diff lists when the request doesn't say
+const DIFF_DEFAULT_LIMIT: usize = 1000;
+/// The most differences a diff lists; the summary counts the rest
+const DIFF_MAX_LIMIT: usize = 10_000;
+
+/// `POST /api/diff` compares two buckets (or prefixes) of this server and
+/// lists the keys added, removed, and changed from left to right
+async fn diff_buckets(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<DiffRequest>,
+) -> AppResult<impl IntoResponse> {
+    let limit = request.limit.unwrap_or(DIFF_DEFAULT_LIMIT);
+    if limit > DIFF_MAX_LIMIT {
+        return Err(AppError::InvalidRequest(format!("limit must be at most {}", DIFF_MAX_LIMIT)));
+    }
+    let report = tokio::task::spawn_blocking(move || {
+        let storage = &state.storage;
+        let (left, right) = (&request.left, &request.right);
+        let mut differences = Vec::new();
+        let mut truncated = false;
+        let summary = crate::diff::diff_listings(
+            crate::diff::local_listing(storage, left)?,
+            crate::diff::local_listing(storage, right)?,
+            |side, meta: &ObjectMeta| {
+                let bucket = if side == crate::diff::Side::Left { &left.bucket } else { &right.bucket };
+                storage.content_sha256(bucket, &meta.key)
+            },
+            |entry| {
+                match differences.len() < limit {
+                    true => differences.push(entry),
+                    false => truncated = true,
+                }
+                Ok::<_, AppError>(())
+            },
+        )?;
+        Ok::<_, AppError>(DiffReport {
+            left: request.left,
+            right: request.right,
+            summary,
+            differences,
+            truncated,
+        })
+    })
+    .await
+    .map_err(|e| AppError::StorageError(e.to_string()))??;
+    Ok(Json(report))
+}
+
+// ─── Object Handlers ─────────────────────────────────────────────
+
+/// Object listings are cut short once their entries add up to this many
+/// bytes of JSON, so large metadata maps cannot produce a response that
+/// freezes the browser
+const LIST_RESPONSE_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+async fn list_objects(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(query): Query<ListObjectsQuery>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let prefix = query.prefix.as_deref().unwrap_or("");
+    let delimiter = query.delimiter.as_deref();
+    let max_keys = query.max_keys.unwrap_or(1000);
+    let order = query.order.unwrap_or(query.sort.default_order());
+    let continuation_token = query.continuation_token.as_deref();
+    let window = query.window();
+
+    // A listing only changes when the bucket does, so its revision plus the
+    // query identifies it without doing the walk; downloads change the
+    // access columns, so they count too
+    let revision = state.storage.get_bucket(&bucket)?.revision;
+    let access_generation = state.storage.access_generation(&bucket);
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{}\0{:?}\0{}\0{:?}\0{:?}\0{:?}\0{:?}\0{:?}",
+        prefix, delimiter, max_keys, query.fields, query.sort, order, window, continuation_token
+    ));
+    let etag = format!(
+        "\"{}.{}-{}\"",
+        revision,
+        access_generation,
+        &hex::encode(hasher.finalize())[..16]
+    );
+    let cached = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if cached {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    let listing = state.storage.list_objects_sorted(
+        &bucket,
+        prefix,
+        delimiter,
+        max_keys,
+        ListOrder { sort: query.sort, order, window },
+        continuation_token,
+    )?;
+    let access = state.storage.bucket_access(&bucket);
+    let tracked = |meta| TrackedObject {
+        meta,
+        access: access.as_ref().map(|a| a.get(&meta.key).copied().unwrap_or_default()),
+    };
+    let token_after = |kept: usize| {
+        kept.checked_sub(1)
+            .map(|last| state.storage.list_token_after(query.sort, &listing.objects[last], listing.modified_before))
+    };
+    let mut response = match query.fields {
+        ListFields::Full => {
+            Json(object_list_page(&listing, listing.objects.iter().map(tracked), token_after)).into_response()
+        }
+        ListFields::Slim => Json(object_list_page(
+            &listing,
+            listing.objects.iter().map(tracked).map(ObjectSummary::from),
+            token_after,
+        ))
+        .into_response(),
+    };
+    response.headers_mut().insert(header::ETAG, etag.parse().unwrap());
+    Ok(response)
+}
+
+/// Bytes of NDJSON gathered before they are handed to the connection
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+/// Chunks the walk may get ahead of the client
+const STREAM_CHUNKS_AHEAD: usize = 4;
+
+/// Every object under a prefix as newline-delimited JSON, from a walk that
+/// runs only as fast as the client reads. The last line is
+/// `{"summary":{"count":…,"bytes":…}}`, or `{"error":{…}}` if the walk
+/// failed; a stream with neither was cut short. A client that hangs up
+/// stops the walk at its next chunk. With a modification-time window the
+/// objects come oldest first, and the summary gives the window's end.
+async fn stream_objects(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(query): Query<StreamObjectsQuery>,
+) -> AppResult<Response> {
+    state.storage.get_bucket(&bucket)?;
+    let fields = query.fields.as_deref().map(parse_object_fields).transpose()?;
+    let prefix = query.prefix.unwrap_or_default();
+    let window = ModifiedWindow {
+        after: query.modified_after,
+        before: query.modified_before,
+    };
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(STREAM_CHUNKS_AHEAD);
+    tokio::task::spawn_blocking(move || {
+        let mut chunk = Vec::new();
+        let mut summary = ListingSummary::default();
+        let mut visit = |meta: ObjectMeta| {
+            summary.count += 1;
+            summary.bytes += meta.size;
+            match &fields {
+                Some(fields) => push_ndjson(&mut chunk, &project_fields(meta, fields)),
+                None => push_ndjson(&mut chunk, &meta),
+            }
+            if chunk.len() >= STREAM_CHUNK_BYTES && tx.blocking_send(std::mem::take(&mut chunk)).is_err() {
+                return Err(AppError::StorageError("Client closed the listing stream".to_string()));
+            }
+            Ok(())
+        };
+        let result = if window.is_set() {
+            state
+                .storage
+                .visit_modified(&bucket, &prefix, window, &mut visit)
+                .map(|before| summary.modified_before = Some(before))
+        } else {
+            state.storage.visit_objects(&bucket, &prefix, &mut visit)
+        };
+        if tx.is_closed() {
+            return;
+        }
+        let last = match result {
+            Ok(()) => json!({ "summary": summary }),
+            Err(e) => {
+                let (_, code, message) = e.parts();
+                json!({ "error": { "code": code, "message": message } })
+            }
+        };
+        push_ndjson(&mut chunk, &last);
+        let _ = tx.blocking_send(chunk);
+    });
+    let chunks = futures::stream::poll_fn(move |cx| rx.poll_recv(cx).map(|chunk| chunk.map(Ok::<_, std::convert::Infallible>)));
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+/// Field names from a comma-separated `fields` parameter
+fn parse_object_fields(fields: &str) -> AppResult<Vec<String>> {
+    fields
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            if OBJECT_META_FIELDS.contains(&field) {
+                Ok(field.to_string())
+            } else {
+                Err(AppError::InvalidRequest(format!(
+                    "Unknown field '{}'; fields are {}",
+                    field,
+                    OBJECT_META_FIELDS.join(", ")
+                )))
+            }
+        })
+        .collect()
+}
+
+/// An object's metadata as JSON with only the given fields
+fn project_fields(meta: ObjectMeta, fields: &[String]) -> serde_json::Value {
+    let mut value = serde_json::to_value(meta).unwrap();
+    if let Some(map) = value.as_object_mut() {
+        map.retain(|name, _| fields.iter().any(|field| field == name));
+    }
+    value
+}
+
+fn push_ndjson(buf: &mut Vec<u8>, value: &impl serde::Serialize) {
+    serde_json::to_writer(&mut *buf, value).unwrap();
+    buf.push(b'\n');
+}
+
+/// Borrow a listing for serialization, keeping as many entries as fit in
+/// `LIST_RESPONSE_MAX_BYTES`. When entries are dropped, `token_after` gives
+/// the continuation token that resumes after the first `n` kept.
+fn object_list_page<'a, T: serde::Serialize>(
+    listing: &'a ListObjectsResponse,
+    entries: impl Iterator<Item = T>,
+    token_after: impl FnOnce(usize) -> Option<String>,
+) -> ObjectListPage<'a, T> {
+    let mut objects = Vec::new();
+    let mut size_limited = false;
+    let mut budget = LIST_RESPONSE_MAX_BYTES;
+    for entry in entries {
+        let len = serde_json::to_vec(&entry).map_or(0, |json| json.len()) + 1;
+        if len > budget {
+            size_limited = true;
+            break;
+        }
+        budget -= len;
+        objects.push(entry);
+    }
+    if size_limited {
+        tracing::warn!(
+            "Listing of {} truncated to {} of {} object(s) to stay under {}",
+            listing.bucket,
+            objects.len(),
+            listing.objects.len(),
+            crate::size::human_readable_size(LIST_RESPONSE_MAX_BYTES as u64)
+        );
+    }
+
+    let next_continuation_token = if size_limited {
+        token_after(objects.len())
+    } else {
+        listing.next_continuation_token.clone()
+    };
+    ObjectListPage {
+        bucket: &listing.bucket,
+        prefix: &listing.prefix,
+        objects,
+        common_prefixes: &listing.common_prefixes,
+        is_truncated: listing.is_truncated || size_limited,
+        max_keys: listing.max_keys,
+        next_continuation_token,
+        modified_before: listing.modified_before,
+        size_limited,
+    }
+}
+
+async fn get_prefix_stats(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(query): Query<PrefixQuery>,
+) -> AppResult<impl IntoResponse> {
+    Ok(Json(state.storage.prefix_stats(&bucket, &query.prefix)?))
+}
+
+async fn get_largest_objects(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(query): Query<LargestObjectsQuery>,
+) -> AppResult<impl IntoResponse> {
+    let objects = state.storage.largest_objects(&bucket, query.limit)?;
+    Ok(Json(json!({
+        "bucket": bucket,
+        "objects": objects
+    })))
+}
+
+/// Matches returned by one search
+const SEARCH_MAX_LIMIT: usize = 1000;
+
+/// `GET /api/buckets/{bucket}/search?q=...`: objects whose key or original
+/// filename contains `q`, ignoring case, in key order
+async fn search_objects(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(query): Query<SearchObjectsQuery>,
+) -> AppResult<impl IntoResponse> {
+    if query.q.is_empty() {
+        return Err(AppError::InvalidRequest("The search needs a non-empty q".to_string()));
+    }
+    let limit = query.limit.min(SEARCH_MAX_LIMIT);
+    let (objects, count) = state.storage.search_objects(&bucket, &query.prefix, &query.q, limit)?;
+    Ok(Json(json!({
+        "bucket": bucket,
+        "query": query.q,
+        "count": count,
+        "truncated": count > objects.len(),
+        "objects": objects
+    })))
+}
+
+/// `GET /api/buckets/{bucket}/hook-runs`: the bucket's latest exec hook
+/// runs with their output, newest first
+async fn get_hook_runs(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    state.storage.get_bucket(&bucket)?;
+    Ok(Json(state.exec_hooks.runs(&bucket)))
+}
+
+/// `GET /api/buckets/{bucket}/traffic?window=24h`: requests and bytes per
+/// hour over the window, which is whole hours or days up to a week
+async fn get_bucket_traffic(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(query): Query<TrafficQuery>,
+) -> AppResult<impl IntoResponse> {
+    let invalid = || {
+        AppError::InvalidRequest(format!(
+            "Invalid traffic window '{}': expected hours or days such as 24h or 7d, up to {}h",
+            query.window, TRAFFIC_HOURS
+        ))
+    };
+    let window = query.window.trim();
+    let (number, hours_per_unit) = match window.strip_suffix('d') {
+        Some(days) => (days, 24),
+        None => (window.strip_suffix('h').unwrap_or(window), 1),
+    };
+    let hours = number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(hours_per_unit))
+        .filter(|hours| (1..=TRAFFIC_HOURS).contains(hours))
+        .ok_or_else(invalid)?;
+    Ok(Json(state.storage.bucket_traffic(&bucket, hours)?))
+}
+
+async fn delete_prefix(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(query): Query<PrefixQuery>,
+) -> AppResult<impl IntoResponse> {
+    let deleted = state.storage.delete_prefix(&bucket, &query.prefix);
+    Ok(Json(audited_in_bucket(&state, AuditAction::PrefixDelete, &bucket, &query.prefix, deleted)?))
+}
+
+async fn find_by_hash(
+    State(state): State<Arc<AppState>>,
+    Path(sha256): Path<String>,
+) -> impl IntoResponse {
+    let matches = state.storage.find_by_hash(&sha256);
+    Json(json!({
+        "sha256": sha256,
+        "matches": matches
+    }))
+}
+
+async fn check_hash(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Json(body): Json<CheckHashRequest>,
+) -> AppResult<impl IntoResponse> {
+    state.storage.get_bucket(&bucket)?;
+    let matches = state.storage.find_by_hash(&body.sha256);
+
+    // Never copy an object onto itself: if the key already holds this content there is nothing to do
+    let source = matches
+        .iter()
+        .find(|m| !(m.bucket == bucket && Some(&m.key) == body.key.as_ref()));
+    let already_present = matches
+        .iter()
+        .any(|m| m.bucket == bucket && Some(&m.key) == body.key.as_ref());
+
+    let created = match (&body.key, source) {
+        (Some(_), _) if already_present => None,
+        (Some(key), Some(source)) => Some(state.storage.copy_object(
+            &source.bucket,
+            &source.key,
+            &bucket,
+            key,
+        )?),
+        _ => None,
+    };
+
+    Ok(Json(CheckHashResponse {
+        exists: !matches.is_empty(),
+        matches,
+        created,
+    }))
+}
+
+/// Keys accepted by one bulk existence check
+const EXISTS_MAX_KEYS: usize = 1000;
+
+/// `POST /api/buckets/{bucket}/exists` with a JSON array of keys reports
+/// which of them exist, replacing one HEAD per key
+async fn objects_exist(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Json(keys): Json<Vec<String>>,
+) -> AppResult<impl IntoResponse> {
+    if keys.len() > EXISTS_MAX_KEYS {
+        return Err(AppError::InvalidRequest(format!(
+            "At most {} keys can be checked at once, got {}",
+            EXISTS_MAX_KEYS,
+            keys.len()
+        )));
+    }
+    Ok(Json(state.storage.objects_exist(&bucket, &keys)?))
+}
+
+/// Parse a catch-all path like "mybucket/path/to/key.txt" into (bucket, key)
+fn parse_bucket_key(path: &str) -> Result<(&str, &str), AppError> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    match path.find('/') {
+        Some(pos) => Ok((&path[..pos], &path[pos + 1..])),
+        None => Err(AppError::InvalidObjectKey(
+            "Path must be in the format: {bucket}/{key}".to_string(),
+        )),
+    }
+}
+
+async fn get_object(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    req_headers: HeaderMap,
+) -> AppResult<Response> {
+    let (bucket, key) = parse_bucket_key(&path)?;
+    // `?details` returns the full metadata that slim listings leave out;
+    // older versions already carry theirs in the versions listing
+    if params.contains_key("details") {
+        let meta = state.storage.get_object_meta(bucket, key)?;
+        let access = state.storage.object_access(bucket, key);
+        return Ok(Json(TrackedObject { meta: &meta, access }).into_response());
+    }
+    // A bucket with an origin fetches or revalidates its copy first
+    let origin_read = match params.get("versionId") {
+        Some(_) => None,
+        None => crate::origin::read_through(&state, bucket, key).await?,
+    };
+    // Buckets with pre-compressed variants pick one by Accept-Encoding
+    let precompressed = state.storage.get_bucket(bucket)?.precompressed;
+    // `?versionId=` reads one specific version of a versioned object
+    let (meta, data, encoding) = match params.get("versionId") {
+        Some(version_id) => {
+            let (version, data) = state.storage.get_object_version(bucket, key, version_id)?;
+            (version.into_meta(bucket), data, None)
+        }
+        None if precompressed => {
+            let accepted = accepted_encodings(&req_headers);
+            match state.storage.get_precompressed_object(bucket, key, &accepted)? {
+                Some((meta, data, encoding)) => (meta, data, Some(encoding)),
+                None => {
+                    let (meta, data) = state.storage.get_object(bucket, key)?;
+                    (meta, data, None)
+                }
+            }
+        }
+        None => {
+            let (meta, data) = state.storage.get_object(bucket, key)?;
+            (meta, data, None)
+        }
+    };
+    if let Some(not_modified) = check_preconditions(&meta, &req_headers)? {
+        return Ok(not_modified);
+    }
+    let range = req_headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| parse_range(v, data.len() as u64))
+        .transpose()?
+        .flatten();
+    // Only reads that are answered with content count
+    state.storage.record_access(bucket, key);
+
+    let mut headers = object_headers(&meta, key, &params, &req_headers);
+    if precompressed {
+        headers.insert("vary", "Accept-Encoding".parse().unwrap());
+    }
+    if let Some(encoding) = encoding {
+        headers.insert("content-encoding", encoding.token().parse().unwrap());
+    }
+    if let Some(read) = origin_read {
+        insert_origin_headers(&mut headers, read);
+    }
+
+    Ok(object_body(headers, data, range))
+}
+
+/// A read's answer: the inclusive `range` of `data` as a `206`, or all of
+/// it as a `200`
+pub fn object_body(mut headers: HeaderMap, data: Vec<u8>, range: Option<(u64, u64)>) -> Response {
+    match range {
+        Some((first, last)) => {
+            headers.insert(
+                "content-range",
+                format!("bytes {}-{}/{}", first, last, data.len()).parse().unwrap(),
+            );
+            let body = data[first as usize..=last as usize].to_vec();
+            headers.insert("content-length", body.len().to_string().parse().unwrap());
+            (StatusCode::PARTIAL_CONTENT, headers, body).into_response()
+        }
+        None => {
+            headers.insert("content-length", data.len().to_string().parse().unwrap());
+            (StatusCode::OK, headers, data).into_response()
+        }
+    }
+}
+
+/// `HEAD` on an object: the headers a `GET` would send, without reading the
+/// payload. The size comes from the payload file, like a `GET`'s. A
+/// precompressed bucket describes the plain object.
+async fn head_object(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    req_headers: HeaderMap,
+) -> AppResult<Response> {
+    let (bucket, key) = parse_bucket_key(&path)?;
+    let precompressed = state.storage.get_bucket(bucket)?.precompressed;
+    let (meta, origin_read) = match params.get("versionId") {
+        Some(version_id) => (
+            state.storage.get_object_version_meta(bucket, key, version_id)?.into_meta(bucket),
+            None,
+        ),
+        None => {
+            let origin_read = crate::origin::read_through(&state, bucket, key).await?;
+            (state.storage.stat_object(bucket, key)?, origin_read)
+        }
+    };
+    if let Some(not_modified) = check_preconditions(&meta, &req_headers)? {
+        return Ok(not_modified);
+    }
+    state.storage.record_access(bucket, key);
+
+    let mut headers = object_headers(&meta, key, &params, &req_headers);
+    if precompressed {
+        headers.insert("vary", "Accept-Encoding".parse().unwrap());
+    }
+    if let Some(read) = origin_read {
+        insert_origin_headers(&mut headers, read);
+    }
+    headers.insert("content-length", meta.size.to_string().parse().unwrap());
+    Ok((StatusCode::OK, headers).into_response())
+}
+
+/// Tell the client how a read of a bucket with an origin was served. A
+/// stale copy also gets the `Warning` HTTP caches use for one.
+fn insert_origin_headers(headers: &mut HeaderMap, read: crate::origin::OriginRead) {
+    headers.insert("x-freebucket-origin", read.as_str().parse().unwrap());
+    if read == crate::origin::OriginRead::Stale {
+        headers.insert(header::WARNING, "111 - \"Revalidation Failed\"".parse().unwrap());
+    }
+}
+
+/// Headers describing an object, shared by `GET` and `HEAD`. User metadata
+/// goes back out as the `x-amz-meta-*` headers it came in as, so clients
+/// that keep their own attributes there (rclone's `mtime`) read them back.
+pub fn object_headers(
+    meta: &ObjectMeta,
+    key: &str,
+    params: &HashMap<String, String>,
+    req_headers: &HeaderMap,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    // Stored before content types were checked, or edited by hand
+    let content_type = meta
+        .content_type
+        .parse()
+        .unwrap_or_else(|_| header::HeaderValue::from_static("application/octet-stream"));
+    headers.insert("content-type", content_type);
+    headers.insert("etag", quoted_etag(&meta.etag).parse().unwrap());
+    headers.insert(
+        "x-freebucket-etag-final",
+        (!meta.etag_provisional).to_string().parse().unwrap(),
+    );
+    headers.insert("last-modified", http_date(&meta.modified()).parse().unwrap());
+    headers.insert("accept-ranges", "bytes".parse().unwrap());
+    for (name, value) in &meta.metadata {
+        let name = header::HeaderName::from_bytes(format!("x-amz-meta-{}", name).as_bytes());
+        if let (Ok(name), Ok(value)) = (name, value.parse()) {
+            headers.insert(name, value);
+        }
+    }
+    if let Some(version_id) = params.get("versionId") {
+        headers.insert("x-amz-version-id", version_id.parse().unwrap());
+    }
+    if checksum_mode_enabled(req_headers) {
+        insert_checksum_headers(&mut headers, meta.checksum.as_ref());
+    }
+    if let Some(original) = &meta.original_filename {
+        headers.insert("x-original-filename", encode_rfc5987(original).parse().unwrap());
+    }
+    if let Some(location) = &meta.website_redirect_location {
+        headers.insert("x-amz-website-redirect-location", location.parse().unwrap());
+    }
+    if let Some(filename) = attachment_filename(params, key, meta.original_filename.as_deref()) {
+        headers.insert(
+            "content-disposition",
+            content_disposition(&filename).parse().unwrap(),
+        );
+    }
+    headers
+}
+
+/// ETags are stored as bare hex; HTTP headers and S3 responses quote them
+pub fn quoted_etag(etag: &str) -> String {
+    format!("\"{}\"", etag)
+}
+
+/// A timestamp in the RFC 7231 form HTTP date headers use
+pub fn http_date(time: &chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Apply `If-Match`, `If-Unmodified-Since`, `If-None-Match`, and
+/// `If-Modified-Since` to a read, in RFC 7232 order. A failed `If-Match` or
+/// `If-Unmodified-Since` is an error; a satisfied `If-None-Match` or
+/// `If-Modified-Since` yields the `304` to send instead of the object.
+pub fn check_preconditions(meta: &ObjectMeta, req_headers: &HeaderMap) -> AppResult<Option<Response>> {
+    let value = |name: header::HeaderName| req_headers.get(name).and_then(|v| v.to_str().ok());
+    // Dates in headers have whole seconds
+    let modified = meta.modified().timestamp();
+    let since = |v: &str| chrono::DateTime::parse_from_rfc2822(v.trim()).ok().map(|d| d.timestamp());
+
+    match value(header::IF_MATCH) {
+        Some(tags) if !etag_matches(tags, meta) => {
+            return Err(AppError::PreconditionFailed(format!(
+                "'{}' no longer has the ETag the request expected",
+                meta.key
+            )));
+        }
+        Some(_) => {}
+        None => {
+            if value(header::IF_UNMODIFIED_SINCE).and_then(since).is_some_and(|since| modified > since) {
+                return Err(AppError::PreconditionFailed(format!(
+                    "'{}' was modified after the request's If-Unmodified-Since",
+                    meta.key
+                )));
+            }
+        }
+    }
+
+    let not_modified = match value(header::IF_NONE_MATCH) {
+        Some(tags) => etag_matches(tags, meta),
+        None => value(header::IF_MODIFIED_SINCE).and_then(since).is_some_and(|since| modified <= since),
+    };
+    if !not_modified {
+        return Ok(None);
+    }
+    let headers = [
+        (header::ETAG, quoted_etag(&meta.etag)),
+        (header::LAST_MODIFIED, http_date(&meta.modified())),
+    ];
+    Ok(Some((StatusCode::NOT_MODIFIED, headers).into_response()))
+}
+
+/// Whether a comma-separated list of entity tags names the object's bare-hex
+/// ETag. Clients send the tags back quoted, possibly marked weak. A
+/// provisional ETag only matches `*`: the same bytes get a different ETag
+/// once hashed, so a match could never be trusted.
+fn etag_matches(tags: &str, meta: &ObjectMeta) -> bool {
+    tags.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || (!meta.etag_provisional && tag.strip_prefix("W/").unwrap_or(tag).trim_matches('"') == meta.etag)
+    })
+}
+
+/// Stored encodings the client accepts, most preferred first. Encodings the
+/// client rates equally keep the server's order (brotli before gzip).
+pub fn accepted_encodings(headers: &HeaderMap) -> Vec<ContentEncoding> {
+    let header = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let quality = |token: &str| {
+        header.split(',').find_map(|item| {
+            let mut parts = item.split(';');
+            if !parts.next()?.trim().eq_ignore_ascii_case(token) {
+                return None;
+            }
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            Some(q)
+        })
+    };
+
+    let mut accepted: Vec<(ContentEncoding, f32)> = ContentEncoding::ALL
+        .into_iter()
+        .map(|e| (e, quality(e.token()).or_else(|| quality("*")).unwrap_or(0.0)))
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    accepted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    accepted.into_iter().map(|(e, _)| e).collect()
+}
+
+/// Resolve a `Range: bytes=...` header against an object of `size` bytes to
+/// an inclusive `(first, last)` pair. Multi-range and non-byte requests are
+/// answered with the whole object, as HTTP allows.
+pub fn parse_range(value: &str, size: u64) -> AppResult<Option<(u64, u64)>> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let invalid = || AppError::InvalidRange(format!("'{}' for an object of {} bytes", value, size));
+    let (first, last) = spec.split_once('-').ok_or_else(invalid)?;
+    let (first, last) = match (first.trim(), last.trim()) {
+        // `bytes=-n`: the final n bytes
+        ("", suffix) => {
+            let n: u64 = suffix.parse().map_err(|_| invalid())?;
+            if n == 0 || size == 0 {
+                return Err(invalid());
+            }
+            (size.saturating_sub(n), size - 1)
+        }
+        (first, "") => (first.parse().map_err(|_| invalid())?, size.saturating_sub(1)),
+        (first, last) => {
+            let first: u64 = first.parse().map_err(|_| invalid())?;
+            let last: u64 = last.parse().map_err(|_| invalid())?;
+            if last < first {
+                return Err(invalid());
+            }
+            (first, last.min(size.saturating_sub(1)))
+        }
+    };
+    if first >= size {
+        return Err(invalid());
+    }
+    Ok(Some((first, last)))
+}
+
+/// `?attachment=name` forces a download as `name`; `?download=1` forces a
+/// download named after the original upload filename, or the last segment of
+/// the key. Otherwise the object is served inline.
+fn attachment_filename(
+    params: &HashMap<String, String>,
+    key: &str,
+    original_filename: Option<&str>,
+) -> Option<String> {
+    if let Some(name) = params.get("attachment").filter(|n| !n.is_empty()) {
+        return Some(name.clone());
+    }
+    match params.get("download").map(|v| v.as_str()) {
+        Some("1") | Some("true") | Some("") => Some(
+            original_filename
+                .unwrap_or_else(|| key.rsplit('/').next().unwrap_or(key))
+                .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+/// Build an attachment `Content-Disposition` value with an ASCII fallback
+/// `filename` and an RFC 5987 encoded `filename*` for the exact name. The
+/// fallback keeps accented letters' base letter, so `café.txt` → `cafe.txt`.
+pub fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .nfd()
+        .filter(|c| !unicode_normalization::char::is_combining_mark(*c))
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        encode_rfc5987(filename)
+    )
+}
+
+/// Percent-encode everything outside the RFC 5987 `attr-char` set
+fn encode_rfc5987(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        let attr_char = byte.is_ascii_alphanumeric()
+            || matches!(
+                byte,
+                b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~'
+            );
+        if attr_char {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Original filename sent with a write: `X-Original-Filename` (percent-encoded
+/// when it isn't plain ASCII) or, failing that, `x-amz-meta-filename`
+fn original_filename(headers: &HeaderMap) -> Option<String> {
+    if let Some(value) = headers.get("x-original-filename").and_then(|v| v.to_str().ok()) {
+        let name = percent_encoding::percent_decode_str(value.trim()).decode_utf8_lossy();
+        return Some(name.to_string()).filter(|name| !name.is_empty());
+    }
+    headers
+        .get("x-amz-meta-filename")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Modification time of the file a write was uploaded from:
+/// `X-Freebucket-Last-Modified` as RFC 3339 or an HTTP date or, failing
+/// that, rclone's `x-amz-meta-mtime` in Unix seconds. A malformed
+/// `x-amz-meta-mtime` is only ignored, since it is also plain user metadata.
+fn source_mtime(headers: &HeaderMap) -> Result<Option<chrono::DateTime<chrono::Utc>>, AppError> {
+    if let Some(value) = headers.get("x-freebucket-last-modified") {
+        let value = value.to_str().unwrap_or_default().trim();
+        let parsed = chrono::DateTime::parse_from_rfc3339(value)
+            .or_else(|_| chrono::DateTime::parse_from_rfc2822(value))
+            .map_err(|_| {
+                AppError::InvalidRequest(format!(
+                    "X-Freebucket-Last-Modified '{}' is neither an RFC 3339 timestamp nor an HTTP date",
+                    value
+                ))
+            })?;
+        return Ok(Some(parsed.with_timezone(&chrono::Utc)));
+    }
+    let seconds = headers
+        .get("x-amz-meta-mtime")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|s| s.is_finite());
+    Ok(seconds.and_then(|s| {
+        chrono::DateTime::from_timestamp(s.floor() as i64, ((s - s.floor()) * 1e9) as u32)
+    }))
+}
+
+async fn delete_object(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let (bucket, key) = parse_bucket_key(&path)?;
+    check_lock(&state, bucket, key, &headers)?;
+    // With `?versionId=` the version is removed permanently; without it a
+    // versioned bucket keeps the history and records a delete marker
+    match params.get("versionId") {
+        Some(version_id) => state.storage.delete_object_version(bucket, key, version_id)?,
+        None => delete_through(&state, bucket, key).await?,
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `PATCH /api/object/{bucket}/{key}` with `Content-Range: bytes first-last/*`
+/// writes the body into that range of an existing object. Not part of S3,
+/// so it is only served with `FREEBUCKET_RANGE_WRITES` on.
+async fn write_object_range(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> AppResult<impl IntoResponse> {
+    if !state.config().range_writes {
+        return Err(AppError::NotImplemented(
+            "Range writes are disabled; set FREEBUCKET_RANGE_WRITES=true to enable them".to_string(),
+        ));
+    }
+    let (bucket, key) = parse_bucket_key(&path)?;
+    check_lock(&state, bucket, key, &headers)?;
+    let value = headers
+        .get(header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| AppError::InvalidRequest("Range writes need a Content-Range header".to_string()))?;
+    let (first, last) = parse_content_range(value)?;
+    // `bytes 0-18446744073709551615` covers one byte more than a u64 counts
+    let len = (last - first).checked_add(1).ok_or_else(|| {
+        AppError::InvalidRequest(format!("Content-Range '{}' is longer than any object can be", value))
+    })?;
+    if len != body.len() as u64 {
+        return Err(AppError::InvalidRequest(format!(
+            "Content-Range covers {} bytes but the body is {} bytes",
+            len,
+            body.len()
+        )));
+    }
+
+    let meta = state.storage.write_object_range(bucket, key, first, &body)?;
+    let mut resp_headers = HeaderMap::new();
+    resp_headers.insert("etag", quoted_etag(&meta.etag).parse().unwrap());
+    Ok((StatusCode::OK, resp_headers, Json(meta)))
+}
+
+/// Parse a `Content-Range` of the form `bytes first-last/total`. The total
+/// may be `*`; a number must at least cover the range.
+fn parse_content_range(value: &str) -> Result<(u64, u64), AppError> {
+    let invalid = || AppError::InvalidRequest(format!("Malformed Content-Range '{}'", value));
+    let spec = value.trim().strip_prefix("bytes ").ok_or_else(invalid)?;
+    let (range, total) = spec.split_once('/').ok_or_else(invalid)?;
+    let (first, last) = range.split_once('-').ok_or_else(invalid)?;
+    let first: u64 = first.trim().parse().map_err(|_| invalid())?;
+    let last: u64 = last.trim().parse().map_err(|_| invalid())?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse::<u64>().map_err(|_| invalid())?),
+    };
+    if last < first || total.is_some_and(|total| total <= last) {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+/// `POST /api/object-meta/{bucket}/{key}/rebuild` regenerates an object's
+/// metadata from its payload. The wildcard has to come last in the route, so
+/// the action suffix is checked here.
+async fn rebuild_object_meta(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let path = path.strip_suffix("/rebuild").ok_or_else(|| {
+        AppError::InvalidRequest("Path must be in the format: {bucket}/{key}/rebuild".to_string())
+    })?;
+    let (bucket, key) = parse_bucket_key(path)?;
+    Ok(Json(state.storage.rebuild_object_meta(bucket, key)?))
+}
+
+/// `POST /api/object/{bucket}/{key}?versionId=ID` makes that version the
+/// latest again by copying it onto the key server-side
+async fn restore_object_version(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let (bucket, key) = parse_bucket_key(&path)?;
+    check_lock(&state, bucket, key, &headers)?;
+    let version_id = params
+        .get("versionId")
+        .ok_or_else(|| AppError::InvalidRequest("Missing versionId".to_string()))?;
+    let meta = state.storage.restore_object_version(bucket, key, version_id)?;
+    Ok(Json(meta))
+}
+
+async fn upload_object(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> AppResult<impl IntoResponse> {
+    auto_create_bucket(&state, &bucket)?;
+    let mut uploaded = Vec::new();
+    // Files the bucket's overwrite policy or a lease refused; the rest still go in
+    let mut conflicts = Vec::new();
+    // A `content_type` text field sets the type of the files that follow it
+    let mut explicit_type: Option<String> = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        AppError::StorageError(format!("Multipart error: {}", e))
+    })? {
+        if field.name() == Some("content_type") && field.file_name().is_none() {
+            let value = field.text().await.map_err(|e| {
+                AppError::InvalidRequest(format!("Invalid content_type field: {}", e))
+            })?;
+            let value = value.trim();
+            crate::storage::check_content_type(value)?;
+            explicit_type = Some(value.to_string()).filter(|v| !v.is_empty());
+            continue;
+        }
+
+        let original_name = field.file_name().map(|s| s.to_string());
+        let file_name = original_name
+            .clone()
+            .unwrap_or_else(|| format!("upload-{}", state.storage.new_id()));
+
+        // Browsers label any file they don't recognise as octet-stream, which
+        // says nothing, so leave those to content sniffing
+        let content_type = explicit_type.clone().or_else(|| {
+            field
+                .content_type()
+                .filter(|t| *t != "application/octet-stream")
+                .map(|s| s.to_string())
+        });
+        let data = field.bytes().await.map_err(|e| {
+            AppError::StorageError(format!("Failed to read upload data: {}", e))
+        })?;
+
+        let result = check_lock(&state, &bucket, &file_name, &headers).and_then(|_| {
+            state.storage.put_object(
+                &bucket,
+                &file_name,
+                &data,
+                PutOptions {
+                    content_type: content_type.as_deref(),
+                    original_filename: original_name.as_deref(),
+                    ..Default::default()
+                },
+            )
+        });
+        match result {
+            Ok(meta) => uploaded.push(meta),
+            Err(e @ (AppError::ObjectAlreadyExists { .. } | AppError::ObjectLocked { .. })) => conflicts.push(json!({
+                "key": file_name,
+                "message": e.parts().2
+            })),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let status = if conflicts.is_empty() { StatusCode::CREATED } else { StatusCode::CONFLICT };
+    Ok((status, Json(json!({
+        "uploaded": uploaded.len(),
+        "objects": uploaded,
+        "conflicts": conflicts
+    }))))
+}
+
+// ─── S3-Compatible Handlers ──────────────────────────────────────
+
+/// S3 sub-resource query parameters as (name, methods implemented here,
+/// methods S3 defines for it). A request naming one with a method S3
+/// defines but FreeBucket doesn't implement gets 501 NotImplemented, and
+/// one with a method S3 doesn't define gets 405 MethodNotAllowed, instead
+/// of falling through to a listing or a plain object request.
+type Subresource = (&'static str, &'static [Method], &'static [Method]);
+
+const S3_BUCKET_SUBRESOURCES: &[Subresource] = &[
+    ("location", &[Method::GET], &[Method::GET]),
+    ("acl", &[], &[Method::GET, Method::PUT]),
+    ("policy", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("policyStatus", &[], &[Method::GET]),
+    ("cors", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("lifecycle", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("versioning", &[], &[Method::GET, Method::PUT]),
+    ("versions", &[], &[Method::GET]),
+    ("tagging", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("website", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("logging", &[], &[Method::GET, Method::PUT]),
+    ("notification", &[], &[Method::GET, Method::PUT]),
+    ("replication", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("encryption", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("object-lock", &[], &[Method::GET, Method::PUT]),
+    ("ownershipControls", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("publicAccessBlock", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("accelerate", &[], &[Method::GET, Method::PUT]),
+    ("requestPayment", &[], &[Method::GET, Method::PUT]),
+    ("analytics", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("metrics", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("inventory", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("intelligent-tiering", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("uploads", &[], &[Method::GET]),
+    ("delete", &[], &[Method::POST]),
+];
+
+const S3_OBJECT_SUBRESOURCES: &[Subresource] = &[
+    ("attributes", &[Method::GET], &[Method::GET]),
+    ("uploads", &[Method::POST], &[Method::POST]),
+    (
+        "uploadId",
+        &[Method::PUT, Method::POST, Method::DELETE],
+        &[Method::GET, Method::PUT, Method::POST, Method::DELETE],
+    ),
+    (
+        "versionId",
+        &[Method::GET],
+        &[Method::GET, Method::PUT, Method::DELETE],
+    ),
+    ("acl", &[], &[Method::GET, Method::PUT]),
+    ("tagging", &[], &[Method::GET, Method::PUT, Method::DELETE]),
+    ("retention", &[], &[Method::GET, Method::PUT]),
+    ("legal-hold", &[], &[Method::GET, Method::PUT]),
+    ("restore", &[], &[Method::POST]),
+    ("select", &[], &[Method::POST]),
+    ("torrent", &[], &[Method::GET]),
+];
+
+/// Reject S3 requests whose query names a sub-resource this method can't
+/// be served for here. Must only wrap the S3 routes.
+pub async fn s3_subresources(req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    let (table, kind) = if path.starts_with("/s3/obj/") {
+        (S3_OBJECT_SUBRESOURCES, "objects")
+    } else {
+        (S3_BUCKET_SUBRESOURCES, "buckets")
+    };
+    let params = req.uri().query().unwrap_or("").split('&').map(|p| p.split('=').next().unwrap_or(""));
+    for param in params {
+        let Some((name, implemented, defined)) = table.iter().find(|(name, _, _)| *name == param) else {
+            continue;
+        };
+        // HEAD is answered like GET
+        let method = if req.method() == Method::HEAD { &Method::GET } else { req.method() };
+        if implemented.contains(method) {
+            continue;
+        }
+        let err = if defined.contains(method) {
+            AppError::NotImplemented(format!(
+                "{} ?{} on {} is not implemented by FreeBucket",
+                method, name, kind
+            ))
+        } else {
+            AppError::MethodNotAllowed(format!(
+                "{} is not allowed with ?{} on {}",
+                method, name, kind
+            ))
+        };
+        return err.into_response();
+    }
+    next.run(req).await
+}
+
+async fn s3_list_buckets(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut result = XmlBody::new("ListAllMyBucketsResult");
+    result
+        .open("Owner")
+        .element("ID", "freebucket")
+        .element("DisplayName", "freebucket-local")
+        .close("Owner")
+        .open("Buckets");
+    for bucket in state.storage.list_buckets() {
+        result
+            .open("Bucket")
+            .element("Name", &bucket.name)
+            .element("CreationDate", s3_xml::timestamp(&bucket.created_at))
+            .element("BucketRegion", &bucket.region)
+            .close("Bucket");
+    }
+    result.close("Buckets");
+    result
+}
+
+async fn s3_create_bucket(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    body: axum::body::Bytes,
+) -> AppResult<impl IntoResponse> {
+    // An optional CreateBucketConfiguration body carries the region
+    let body = String::from_utf8_lossy(&body);
+    let constraint = xml_tag(&body, "LocationConstraint")
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+    let config = state.config();
+    let region = constraint.unwrap_or(&config.default_region);
+
+    // SDKs retry CreateBucket on timeouts and "ensure it exists" tools call
+    // it blindly. Everything belongs to the single local owner, so creating
+    // an existing bucket succeeds like it does in us-east-1, unless the
+    // request asks for a different region than the bucket has.
+    match state.storage.create_bucket(&bucket, region) {
+        Err(AppError::BucketAlreadyExists(name)) => {
+            let existing = state.storage.get_bucket(&name)?;
+            if constraint.is_some_and(|r| r != existing.region) {
+                return Err(AppError::BucketAlreadyExists(name));
+            }
+        }
+        result => {
+            audited(&state, AuditAction::BucketCreate, &bucket, result)?;
+        }
+    }
+    Ok((StatusCode::OK, [(header::LOCATION, format!("/{}", bucket))]))
+}
+
+async fn s3_delete_bucket(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let deleted = state.storage.delete_bucket(&bucket, false);
+    audited(&state, AuditAction::BucketDelete, &bucket, deleted)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// ListObjects (v1) and ListObjectsV2 (`list-type=2`), and
+/// GetBucketLocation (`?location`)
+async fn s3_list_objects(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> AppResult<Response> {
+    if params.contains_key("location") {
+        let info = state.storage.get_bucket(&bucket)?;
+        let mut result = XmlBody::new("LocationConstraint");
+        result.text(&info.region);
+        return Ok(result.into_response());
+    }
+
+    let v2 = params.get("list-type").map(String::as_str) == Some("2");
+    let prefix = params.get("prefix").map_or("", String::as_str);
+    let delimiter = params.get("delimiter").map(String::as_str).filter(|d| !d.is_empty());
+    let max_keys = match params.get("max-keys") {
+        Some(value) => value
+            .parse::<u32>()
+            .map_err(|_| AppError::InvalidRequest(format!("Invalid max-keys '{}'", value)))?
+            .min(1000),
+        None => 1000,
+    };
+    let url_encoded = match params.get("encoding-type").map(String::as_str) {
+        None => false,
+        Some(t) if t.eq_ignore_ascii_case("url") => true,
+        Some(other) => return Err(AppError::InvalidRequest(format!("Invalid encoding-type '{}'", other))),
+    };
+    let encode = |value: &str| match url_encoded {
+        true => percent_encoding::utf8_percent_encode(value, S3_LISTING_ENCODE).to_string(),
+        false => value.to_string(),
+    };
+    // v2 resumes from its own token, v1 and `start-after` from a key
+    let start_after = if v2 { params.get("start-after") } else { params.get("marker") };
+    let token = match params.get("continuation-token").filter(|_| v2) {
+        Some(token) => Some(token.clone()),
+        None => start_after.filter(|key| !key.is_empty()).map(|key| state.storage.list_token_after_key(key)),
+    };
+
+    let response = state.storage.list_objects_sorted(
+        &bucket,
+        prefix,
+        delimiter,
+        max_keys,
+        ListOrder::default(),
+        token.as_deref(),
+    )?;
+
+    let mut result = XmlBody::new("ListBucketResult");
+    result
+        .element("Name", &response.bucket)
+        .element("Prefix", encode(prefix))
+        .optional("Delimiter", delimiter.map(encode))
+        .element("MaxKeys", max_keys)
+        .optional("EncodingType", url_encoded.then_some("url"))
+        .element("IsTruncated", response.is_truncated);
+    if v2 {
+        result
+            .element("KeyCount", response.objects.len() + response.common_prefixes.len())
+            .optional("ContinuationToken", params.get("continuation-token"))
+            .optional("NextContinuationToken", response.next_continuation_token.as_ref())
+            .optional("StartAfter", start_after.map(|key| encode(key)));
+    } else {
+        // S3 only sends NextMarker with a delimiter; without one clients
+        // resume from the last key, which is the same thing here
+        let next_marker = response.objects.last().filter(|_| response.is_truncated).map(|o| encode(&o.key));
+        result.element("Marker", encode(start_after.map_or("", String::as_str)));
+        result.optional("NextMarker", next_marker.filter(|_| delimiter.is_some()));
+    }
+    for object in &response.objects {
+        result
+            .open("Contents")
+            .element("Key", encode(&object.key))
+            .element("LastModified", s3_xml::timestamp(&object.modified()))
+            .element("ETag", quoted_etag(&object.etag))
+            .element("Size", object.size)
+            .element("StorageClass", "STANDARD")
+            .close("Contents");
+    }
+    for common_prefix in &response.common_prefixes {
+        result.open("CommonPrefixes").element("Prefix", encode(common_prefix)).close("CommonPrefixes");
+    }
+    Ok(result.into_response())
+}
+
+/// What `encoding-type=url` listings escape: everything but unreserved
+/// characters and `/`, so spaces are `%20` and `+` is `%2B` whichever way a
+/// client decodes
+const S3_LISTING_ENCODE: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Echo the addressed bucket's region in `x-amz-bucket-region`, which SDKs
+/// use to check they are talking to the region they configured
+pub async fn bucket_region_header(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let bucket = req
+        .uri()
+        .path()
+        .strip_prefix("/s3/")
+        .map(|rest| rest.strip_prefix("obj/").unwrap_or(rest))
+        .and_then(|rest| rest.split('/').next())
+        .filter(|b| !b.is_empty())
+        .map(str::to_string);
+
+    let mut res = next.run(req).await;
+    if let Some(info) = bucket.and_then(|b| state.storage.get_bucket(&b).ok()) {
+        if let Ok(value) = info.region.parse() {
+            res.headers_mut().insert("x-amz-bucket-region", value);
+        }
+    }
+    res
+}
+
+async fn s3_get_object(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    query: Query<HashMap<String, String>>,
+) -> AppResult<Response> {
+    if query.contains_key("attributes") {
+        return s3_get_object_attributes(&state, &path, &headers, &query);
+    }
+    get_object(State(state), Path(path), query, headers).await
+}
+
+/// Checksums are only returned when asked for with `x-amz-checksum-mode: ENABLED`
+fn checksum_mode_enabled(headers: &HeaderMap) -> bool {
+    headers
+        .get("x-amz-checksum-mode")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("ENABLED"))
+}
+
+fn insert_checksum_headers(headers: &mut HeaderMap, checksum: Option<&ObjectChecksum>) {
+    if let Some(checksum) = checksum {
+        headers.insert(checksum.algorithm.header_name(), checksum.value.parse().unwrap());
+        headers.insert(
+            "x-amz-checksum-type",
+            checksum_type_name(checksum.checksum_type).parse().unwrap(),
+        );
+    }
+}
+
+fn checksum_type_name(checksum_type: ChecksumType) -> &'static str {
+    match checksum_type {
+        ChecksumType::FullObject => "FULL_OBJECT",
+        ChecksumType::Composite => "COMPOSITE",
+    }
+}
+
+/// The checksum a write asks for: an `x-amz-checksum-<algorithm>` header (or
+/// trailer) carries the expected value, while `x-amz-sdk-checksum-algorithm`
+/// alone only names the algorithm to compute
+fn requested_checksum(headers: &HeaderMap) -> AppResult<Option<ChecksumRequest>> {
+    for algorithm in ChecksumAlgorithm::ALL {
+        if let Some(value) = headers.get(algorithm.header_name()).and_then(|v| v.to_str().ok()) {
+            return Ok(Some(ChecksumRequest {
+                algorithm,
+                expected: Some(value.trim().to_string()),
+            }));
+        }
+    }
+    match headers.get("x-amz-sdk-checksum-algorithm").and_then(|v| v.to_str().ok()) {
+        Some(name) => checksum_algorithm(name).map(|algorithm| {
+            Some(ChecksumRequest {
+                algorithm,
+                expected: None,
+            })
+        }),
+        None => Ok(None),
+    }
+}
+
+fn checksum_algorithm(name: &str) -> AppResult<ChecksumAlgorithm> {
+    ChecksumAlgorithm::from_name(name).ok_or_else(|| {
+        AppError::InvalidRequest(format!("Unsupported checksum algorithm '{}'", name))
+    })
+}
+
+/// SDK streaming uploads send `aws-chunked` bodies
+fn is_aws_chunked(headers: &HeaderMap) -> bool {
+    let encoding = headers
+        .get("content-encoding")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let content_sha = headers
+        .get("x-amz-content-sha256")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    encoding.contains("aws-chunked") || content_sha.starts_with("STREAMING-")
+}
+
+/// Unwrap an `aws-chunked` body: hex length-prefixed chunks ending with a
+/// zero-length chunk, followed by trailing headers such as the checksum.
+/// Chunk signatures are ignored like every other signature, and the
+/// trailers never reach the stored object.
+fn decode_aws_chunked(body: &[u8]) -> AppResult<(Vec<u8>, HeaderMap)> {
+    let malformed = || AppError::InvalidRequest("Malformed aws-chunked request body".to_string());
+    let mut data = Vec::with_capacity(body.len());
+    let mut rest = body;
+    loop {
+        let line_end = rest
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(malformed)?;
+        let line = std::str::from_utf8(&rest[..line_end]).map_err(|_| malformed())?;
+        let size_hex = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_hex, 16).map_err(|_| malformed())?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            break;
+        }
+        if rest.len() < size {
+            return Err(malformed());
+        }
+        data.extend_from_slice(&rest[..size]);
+        rest = rest[size..].strip_prefix(&b"\r\n"[..]).ok_or_else(malformed)?;
+    }
+
+    let mut trailers = HeaderMap::new();
+    for line in String::from_utf8_lossy(rest).lines() {
+        if let Some((name, value)) = line.split_once(':') {
+            if let (Ok(name), Ok(value)) = (
+                header::HeaderName::from_bytes(name.trim().as_bytes()),
+                value.trim().parse(),
+            ) {
+                trailers.insert(name, value);
+            }
+        }
+    }
+    Ok((data, trailers))
+}
+
+/// SHA-256 checksums travel base64-encoded in S3, while ETags here are hex
+fn sha256_checksum(etag: &str) -> Option<String> {
+    use base64::Engine;
+    let digest = hex::decode(etag).ok()?;
+    Some(base64::engine::general_purpose::STANDARD.encode(digest))
+}
+
+/// GetObjectAttributes: the attributes named in `x-amz-object-attributes`
+/// in a single response, without the object body
+fn s3_get_object_attributes(
+    state: &AppState,
+    path: &str,
+    headers: &HeaderMap,
+    query: &HashMap<String, String>,
+) -> AppResult<Response> {
+    let (bucket, key) = parse_bucket_key(path)?;
+    let meta = state.storage.get_object_meta(bucket, key)?;
+
+    let requested: Vec<&str> = headers
+        .get_all("x-amz-object-attributes")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .collect();
+    if requested.is_empty() {
+        return Err(AppError::InvalidRequest(
+            "The x-amz-object-attributes header must list at least one attribute".to_string(),
+        ));
+    }
+
+    let mut result = XmlBody::new("GetObjectAttributesResponse");
+    for attribute in requested {
+        match attribute {
+            "ETag" => {
+                result.element("ETag", &meta.etag);
+            }
+            "Checksum" => {
+                // Without a client-requested checksum the SHA-256 ETag doubles as one
+                result.open("Checksum");
+                match &meta.checksum {
+                    Some(c) => result
+                        .element(&format!("Checksum{}", c.algorithm.name()), &c.value)
+                        .element("ChecksumType", checksum_type_name(c.checksum_type)),
+                    None => result
+                        .optional("ChecksumSHA256", sha256_checksum(&meta.etag))
+                        .element("ChecksumType", "FULL_OBJECT"),
+                };
+                result.close("Checksum");
+            }
+            "ObjectSize" => {
+                result.element("ObjectSize", meta.size);
+            }
+            "StorageClass" => {
+                result.element("StorageClass", "STANDARD");
+            }
+            "ObjectParts" => {
+                // Objects not assembled from parts have no ObjectParts in S3
+                if meta.parts.is_empty() {
+                    continue;
+                }
+                let max_parts: usize = query
+                    .get("max-parts")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1000);
+                let marker: u32 = query
+                    .get("part-number-marker")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0);
+                let remaining: Vec<_> =
+                    meta.parts.iter().filter(|p| p.part_number > marker).collect();
+                let page = &remaining[..remaining.len().min(max_parts)];
+                result
+                    .open("ObjectParts")
+                    .element("TotalPartsCount", meta.parts.len())
+                    .element("PartNumberMarker", marker)
+                    .element("NextPartNumberMarker", page.last().map_or(marker, |p| p.part_number))
+                    .element("MaxParts", max_parts)
+                    .element("IsTruncated", remaining.len() > page.len());
+                for p in page {
+                    let (name, value) = match (&meta.checksum, &p.checksum) {
+                        (Some(c), Some(value)) => {
+                            (format!("Checksum{}", c.algorithm.name()), Some(value.clone()))
+                        }
+                        _ => ("ChecksumSHA256".to_string(), sha256_checksum(&p.etag)),
+                    };
+                    result
+                        .open("Part")
+                        .element("PartNumber", p.part_number)
+                        .element("Size", p.size)
+                        .optional(&name, value)
+                        .close("Part");
+                }
+                result.close("ObjectParts");
+            }
+            other => {
+                return Err(AppError::InvalidRequest(format!(
+                    "Unknown object attribute '{}'",
+                    other
+                )));
+            }
+        }
+    }
+
+    let mut response = result.into_response();
+    response.headers_mut().insert(
+        "last-modified",
+        http_date(&meta.modified()).parse().unwrap(),
+    );
+    Ok(response)
+}
+
+async fn s3_put_object(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    mut headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> AppResult<Response> {
+    let (bucket, key) = parse_bucket_key(&path)?;
+    check_lock(&state, bucket, key, &headers)?;
+
+    // Trailing checksums of streamed bodies are treated like headers
+    let body = if is_aws_chunked(&headers) {
+        let (data, trailers) = decode_aws_chunked(&body)?;
+        if let Some(expected) = headers
+            .get("x-amz-decoded-content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<usize>().ok())
+        {
+            if expected != data.len() {
+                return Err(AppError::InvalidRequest(format!(
+                    "Decoded body is {} bytes but x-amz-decoded-content-length is {}",
+                    data.len(),
+                    expected
+                )));
+            }
+        }
+        headers.extend(trailers);
+        axum::body::Bytes::from(data)
+    } else {
+        body
+    };
+    let checksum = requested_checksum(&headers)?;
+
+    // UploadPart / UploadPartCopy
+    if let (Some(upload_id), Some(part_number)) = (params.get("uploadId"), params.get("partNumber")) {
+        let part_number: u32 = part_number.parse().map_err(|_| {
+            AppError::InvalidPart(format!("Invalid part number '{}'", part_number))
+        })?;
+
+        if let Some(copy_source) = headers.get("x-amz-copy-source").and_then(|v| v.to_str().ok()) {
+            let decoded = percent_encoding::percent_decode_str(copy_source)
+                .decode_utf8_lossy()
+                .to_string();
+            let (src_bucket, src_key) = parse_bucket_key(&decoded)?;
+            let range = headers
+                .get("x-amz-copy-source-range")
+                .and_then(|v| v.to_str().ok())
+                .map(parse_copy_source_range)
+                .transpose()?;
+
+            let part = state.storage.upload_part_copy(
+                bucket,
+                key,
+                upload_id,
+                part_number,
+                src_bucket,
+                src_key,
+                range,
+            )?;
+
+            let mut result = XmlBody::new("CopyPartResult");
+            result
+                .element("ETag", quoted_etag(&part.etag))
+                .element("LastModified", s3_xml::timestamp(&part.last_modified));
+            return Ok(result.into_response());
+        }
+
+        let part = state
+            .storage
+            .upload_part(bucket, key, upload_id, part_number, &body, checksum.as_ref())?;
+        let mut resp_headers = HeaderMap::new();
+        resp_headers.insert("etag", quoted_etag(&part.etag).parse().unwrap());
+        if let (Some(value), Some(request)) = (&part.checksum, &checksum) {
+            resp_headers.insert(request.algorithm.header_name(), value.parse().unwrap());
+        }
+        return Ok((StatusCode::OK, resp_headers).into_response());
+    }
+
+    auto_create_bucket(&state, bucket)?;
+    if let Some(copy_source) = headers.get("x-amz-copy-source").and_then(|v| v.to_str().ok()) {
+        return s3_copy_object(&state, bucket, key, copy_source, &headers);
+    }
+    let content_type = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+
+    // A bucket writing through to its origin stores there first, and
+    // keeps the result as the origin's copy
+    let metadata = extract_amz_metadata(&headers);
+    let origin_etag =
+        crate::origin::write_through_put(&state, bucket, key, &body, content_type.as_deref(), &metadata).await?;
+    let meta = state.storage.put_object(
+        bucket,
+        key,
+        &body,
+        PutOptions {
+            content_type: content_type.as_deref(),
+            metadata,
+            checksum: checksum.as_ref(),
+            original_filename: original_filename(&headers).as_deref(),
+            website_redirect_location: website_redirect_location(&headers)?.as_deref(),
+            source_mtime: source_mtime(&headers)?,
+        },
+    )
+    .map_err(s3_overwrite_error)?;
+    if let Some(origin_etag) = origin_etag {
+        state.storage.mark_origin_copy(bucket, key, &meta.etag, &origin_etag)?;
+    }
+
+    let mut resp_headers = HeaderMap::new();
+    resp_headers.insert("etag", quoted_etag(&meta.etag).parse().unwrap());
+    insert_checksum_headers(&mut resp_headers, meta.checksum.as_ref());
+
+    Ok((StatusCode::OK, resp_headers).into_response())
+}
+
+/// CopyObject. With `x-amz-metadata-directive: REPLACE` the copy takes its
+/// content type and metadata from the request, which is also how clients
+/// change them on an object in place.
+fn s3_copy_object(
+    state: &AppState,
+    bucket: &str,
+    key: &str,
+    copy_source: &str,
+    headers: &HeaderMap,
+) -> AppResult<Response> {
+    let decoded = percent_encoding::percent_decode_str(copy_source)
+        .decode_utf8_lossy()
+        .to_string();
+    // Only the current version exists here, so a version ID names nothing else
+    let source = decoded.split_once("?versionId=").map_or(&*decoded, |(source, _)| source);
+    let (src_bucket, src_key) = parse_bucket_key(source)?;
+    let replace = match headers.get("x-amz-metadata-directive").and_then(|v| v.to_str().ok()) {
+        None => false,
+        Some(d) if d.eq_ignore_ascii_case("COPY") => false,
+        Some(d) if d.eq_ignore_ascii_case("REPLACE") => true,
+        Some(other) => {
+            return Err(AppError::InvalidRequest(format!(
+                "Unknown x-amz-metadata-directive '{}'",
+                other
+            )))
+        }
+    };
+
+    let meta = if replace {
+        let content_type = headers.get("content-type").and_then(|v| v.to_str().ok());
+        let (_, data) = state.storage.get_object(src_bucket, src_key)?;
+        state.storage.put_object(
+            bucket,
+            key,
+            &data,
+            PutOptions {
+                content_type,
+                metadata: extract_amz_metadata(headers),
+                checksum: requested_checksum(headers)?.as_ref(),
+                original_filename: original_filename(headers).as_deref(),
+                website_redirect_location: website_redirect_location(headers)?.as_deref(),
+                source_mtime: source_mtime(headers)?,
+            },
+        )
+    } else {
+        state.storage.copy_object(src_bucket, src_key, bucket, key)
+    }
+    .map_err(s3_overwrite_error)?;
+
+    let mut result = XmlBody::new("CopyObjectResult");
+    result
+        .element("ETag", quoted_etag(&meta.etag))
+        .element("LastModified", s3_xml::timestamp(&meta.last_modified));
+    if let Some(c) = &meta.checksum {
+        result
+            .element(&format!("Checksum{}", c.algorithm.name()), &c.value)
+            .element("ChecksumType", checksum_type_name(c.checksum_type));
+    }
+    Ok(result.into_response())
+}
+
+/// With `FREEBUCKET_AUTO_CREATE_BUCKETS`, create the bucket a write goes to
+/// if it doesn't exist yet. Names are validated like any other bucket.
+fn auto_create_bucket(state: &AppState, bucket: &str) -> AppResult<()> {
+    let config = state.config();
+    if !config.auto_create_buckets || state.storage.get_bucket(bucket).is_ok() {
+        return Ok(());
+    }
+    match state.storage.create_bucket(bucket, &config.default_region) {
+        Err(AppError::BucketAlreadyExists(_)) => Ok(()),
+        created => audited(state, AuditAction::BucketCreate, bucket, created).map(|_| ()),
+    }
+}
+
+/// S3 has no error for a bucket that refuses overwrites; the closest is a
+/// conditional write (`If-None-Match: *`) that finds the key taken
+fn s3_overwrite_error(e: AppError) -> AppError {
+    match e {
+        AppError::ObjectAlreadyExists { bucket, key } => AppError::PreconditionFailed(format!(
+            "The key '{}' already exists in bucket '{}', which does not allow overwrites",
+            key, bucket
+        )),
+        e => e,
+    }
+}
+
+/// The `x-amz-website-redirect-location` of a PUT. Like S3, only a key
+/// starting with `/` or an http(s) URL is accepted.
+fn website_redirect_location(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get("x-amz-website-redirect-location") else {
+        return Ok(None);
+    };
+    let location = value.to_str().unwrap_or_default().trim();
+    let valid = location.starts_with('/') || location.starts_with("http://") || location.starts_with("https://");
+    if !valid || location.len() > 2048 {
+        return Err(AppError::InvalidRequest(
+            "x-amz-website-redirect-location must be a key starting with '/' or an http(s) URL of at most 2 KB"
+                .to_string(),
+        ));
+    }
+    Ok(Some(location.to_string()))
+}
+
+/// Extract custom metadata from x-amz-meta-* headers
+fn extract_amz_metadata(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    for (hdr_key, value) in headers.iter() {
+        if let Some(meta_key) = hdr_key.as_str().strip_prefix("x-amz-meta-") {
+            if let Ok(val) = value.to_str() {
+                metadata.insert(meta_key.to_string(), val.to_string());
+            }
+        }
+    }
+    metadata
+}
+
+/// Parse an `x-amz-copy-source-range` value of the form `bytes=first-last`
+fn parse_copy_source_range(value: &str) -> Result<(u64, u64), AppError> {
+    let invalid = || AppError::InvalidRange(format!("Malformed copy source range '{}'", value));
+    let spec = value.trim().strip_prefix("bytes=").ok_or_else(invalid)?;
+    let (first, last) = spec.split_once('-').ok_or_else(invalid)?;
+    let first: u64 = first.trim().parse().map_err(|_| invalid())?;
+    let last: u64 = last.trim().parse().map_err(|_| invalid())?;
+    Ok((first, last))
+}
+
+/// Parse the part list of a CompleteMultipartUpload body. Accepts the JSON
+/// shape used by the rest of this API as well as the XML real S3 clients send.
+fn parse_completed_parts(body: &[u8]) -> Result<Vec<CompletedPart>, AppError> {
+    let text = String::from_utf8_lossy(body);
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if text.starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(text)
+            .map_err(|e| AppError::InvalidPart(format!("Malformed request body: {}", e)))?;
+        let parts = value
+            .get("CompleteMultipartUpload")
+            .unwrap_or(&value)
+            .get("Parts")
+            .and_then(|p| p.as_array())
+            .cloned()
+            .unwrap_or_default();
+        return parts
+            .iter()
+            .map(|p| {
+                let part_number = p
+                    .get("PartNumber")
+                    .and_then(|n| n.as_u64())
+                    .ok_or_else(|| AppError::InvalidPart("Missing PartNumber".to_string()))?;
+                Ok(CompletedPart {
+                    part_number: part_number as u32,
+                    etag: p.get("ETag").and_then(|e| e.as_str()).unwrap_or("").to_string(),
+                })
+            })
+            .collect();
+    }
+
+    let mut parts = Vec::new();
+    for chunk in text.split("<Part>").skip(1) {
+        let part_number = xml_tag(chunk, "PartNumber")
+            .and_then(|n| n.trim().parse::<u32>().ok())
+            .ok_or_else(|| AppError::InvalidPart("Missing PartNumber".to_string()))?;
+        let etag = xml_tag(chunk, "ETag")
+            .unwrap_or("")
+            .replace("&quot;", "\"")
+            .trim()
+            .to_string();
+        parts.push(CompletedPart { part_number, etag });
+    }
+    Ok(parts)
+}
+
+fn xml_tag<'a>(text: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = text.find(&open)? + open.len();
+    let end = text[start..].find(&close)? + start;
+    Some(&text[start..end])
+}
+
+/// CreateMultipartUpload (`?uploads`) and CompleteMultipartUpload (`?uploadId=`)
+async fn s3_post_object(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> AppResult<Response> {
+    let (bucket, key) = parse_bucket_key(&path)?;
+    check_lock(&state, bucket, key, &headers)?;
+
+    if params.contains_key("uploads") {
+        auto_create_bucket(&state, bucket)?;
+        let content_type = headers.get("content-type").and_then(|v| v.to_str().ok());
+        let checksum_algorithm = headers
+            .get("x-amz-checksum-algorithm")
+            .and_then(|v| v.to_str().ok())
+            .map(checksum_algorithm)
+            .transpose()?;
+        let checksum_type = match headers.get("x-amz-checksum-type").and_then(|v| v.to_str().ok()) {
+            Some(name) if name.eq_ignore_ascii_case("COMPOSITE") => Some(ChecksumType::Composite),
+            Some(name) if name.eq_ignore_ascii_case("FULL_OBJECT") => Some(ChecksumType::FullObject),
+            Some(name) => {
+                return Err(AppError::InvalidRequest(format!(
+                    "Unsupported checksum type '{}'",
+                    name
+                )))
+            }
+            None => None,
+        };
+        let upload = state.storage.create_multipart_upload(
+            bucket,
+            key,
+            PutOptions {
+                content_type,
+                metadata: extract_amz_metadata(&headers),
+                checksum: None,
+                original_filename: original_filename(&headers).as_deref(),
+                website_redirect_location: website_redirect_location(&headers)?.as_deref(),
+                source_mtime: source_mtime(&headers)?,
+            },
+            checksum_algorithm,
+            checksum_type,
+        )?;
+        let mut result = XmlBody::new("InitiateMultipartUploadResult");
+        result
+            .element("Bucket", &upload.bucket)
+            .element("Key", &upload.key)
+            .element("UploadId", &upload.upload_id)
+            .optional("ChecksumAlgorithm", upload.checksum_algorithm.map(|a| a.name()))
+            .optional("ChecksumType", upload.checksum_type.map(checksum_type_name));
+        return Ok(result.into_response());
+    }
+
+    if let Some(upload_id) = params.get("uploadId") {
+        let parts = parse_completed_parts(&body)?;
+        let checksum = requested_checksum(&headers)?;
+        let meta = state
+            .storage
+            .complete_multipart_upload(bucket, key, upload_id, &parts, checksum.as_ref())
+            .map_err(s3_overwrite_error)?;
+        let mut result = XmlBody::new("CompleteMultipartUploadResult");
+        result
+            .element("Location", format!("/{}/{}", meta.bucket, meta.key))
+            .element("Bucket", &meta.bucket)
+            .element("Key", &meta.key)
+            .element("ETag", quoted_etag(&meta.etag))
+            .element("Size", meta.size);
+        if let Some(c) = &meta.checksum {
+            result
+                .element(&format!("Checksum{}", c.algorithm.name()), &c.value)
+                .element("ChecksumType", checksum_type_name(c.checksum_type));
+        }
+        return Ok(result.into_response());
+    }
+
+    Err(AppError::MethodNotAllowed(
+        "POST on an object requires either ?uploads or ?uploadId".to_string(),
+    ))
+}
+
+async fn s3_delete_object(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let (bucket, key) = parse_bucket_key(&path)?;
+    check_lock(&state, bucket, key, &headers)?;
+    match params.get("uploadId") {
+        Some(upload_id) => state.storage.abort_multipart_upload(bucket, key, upload_id)?,
+        None => delete_through(&state, bucket, key).await?,
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete an object, first on the bucket's origin if it writes through.
+/// The origin had the key even if no copy of it was cached here.
+async fn delete_through(state: &AppState, bucket: &str, key: &str) -> AppResult<()> {
+    let forwarded = crate::origin::write_through_delete(state, bucket, key).await?;
+    match state.storage.delete_object(bucket, key) {
+        Err(AppError::ObjectNotFound { .. }) if forwarded => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+
+    use crate::storage::PutOptions;
+    use crate::testing::{body_string, TestServer};
+
+    fn put_hello(server: &TestServer) {
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        server
+            .state
+            .storage
+            .put_object("photos", "a.txt", b"hello", PutOptions::default())
+            .unwrap();
+    }
+
+    fn downloads(server: &TestServer) -> u64 {
+        server.state.storage.object_access("photos", "a.txt").unwrap().downloads
+    }
+
+    #[tokio::test]
+    async fn get_and_head_count_as_downloads() {
+        let server = TestServer::new();
+        put_hello(&server);
+        assert_eq!(server.request("GET", "/api/object/photos/a.txt").await.status(), StatusCode::OK);
+        assert_eq!(server.request("HEAD", "/api/object/photos/a.txt").await.status(), StatusCode::OK);
+        assert_eq!(downloads(&server), 2);
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_ranges_and_revalidations_do_not_count() {
+        let server = TestServer::new();
+        put_hello(&server);
+        let res = server
+            .send(
+                Request::get("/api/object/photos/a.txt")
+                    .header("range", "bytes=100-200")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let etag = server.state.storage.get_object_meta("photos", "a.txt").unwrap().etag;
+        for method in ["GET", "HEAD"] {
+            let res = server
+                .send(
+                    Request::builder()
+                        .method(method)
+                        .uri("/api/object/photos/a.txt")
+                        .header("if-none-match", super::quoted_etag(&etag))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await;
+            assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        }
+        assert_eq!(downloads(&server), 0);
+    }
+
+    fn s3(method: &str, uri: &str, headers: &[(&str, &str)], body: &'static str) -> Request {
+        let mut req = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn multipart_copy_answers_in_s3_xml() {
+        let server = TestServer::new();
+        put_hello(&server);
+
+        let res = server.send(s3("POST", "/s3/photos/joined.txt?uploads", &[], "")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "application/xml");
+        let body = body_string(res).await;
+        assert!(body.contains("<InitiateMultipartUploadResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">"), "{}", body);
+        let upload_id = super::xml_tag(&body, "UploadId").unwrap().to_string();
+
+        let uri = format!("/s3/photos/joined.txt?partNumber=1&uploadId={}", upload_id);
+        let copy = [("x-amz-copy-source", "/photos/a.txt"), ("x-amz-copy-source-range", "bytes=1-3")];
+        let res = server.send(s3("PUT", &uri, &copy, "")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_string(res).await;
+        assert!(body.contains("<CopyPartResult"), "{}", body);
+        let etag = super::xml_tag(&body, "ETag").unwrap().replace("&quot;", "\"");
+        assert!(super::xml_tag(&body, "LastModified").unwrap().ends_with('Z'));
+
+        let complete = format!(
+            "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>{}</ETag></Part></CompleteMultipartUpload>",
+            etag
+        );
+        let res = server
+            .send(
+                Request::post(format!("/s3/photos/joined.txt?uploadId={}", upload_id))
+                    .body(Body::from(complete))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_string(res).await;
+        assert!(body.contains("<CompleteMultipartUploadResult"), "{}", body);
+        assert_eq!(super::xml_tag(&body, "Size"), Some("3"));
+        assert_eq!(server.state.storage.get_object("photos", "joined.txt").unwrap().1, b"ell");
+    }
+
+    #[tokio::test]
+    async fn s3_listings_page_and_encode_keys() {
+        let server = TestServer::new();
+        put_hello(&server);
+        for key in ["b c.txt", "d+e.txt", "dir/f.txt"] {
+            server.state.storage.put_object("photos", key, b"x", PutOptions::default()).unwrap();
+        }
+
+        let res = server.request("GET", "/s3/photos?list-type=2&max-keys=2&delimiter=/&encoding-type=url").await;
+        let body = body_string(res).await;
+        assert!(body.contains("<Key>a.txt</Key>") && body.contains("<Key>b%20c.txt</Key>"), "{}", body);
+        assert!(body.contains("<CommonPrefixes><Prefix>dir/</Prefix></CommonPrefixes>"), "{}", body);
+        assert_eq!(super::xml_tag(&body, "KeyCount"), Some("3"));
+        assert_eq!(super::xml_tag(&body, "IsTruncated"), Some("true"));
+        let token = super::xml_tag(&body, "NextContinuationToken").unwrap().to_string();
+
+        let uri = format!("/s3/photos?list-type=2&max-keys=2&delimiter=/&encoding-type=url&continuation-token={}", token);
+        let body = body_string(server.request("GET", &uri).await).await;
+        assert!(body.contains("<Key>d%2Be.txt</Key>"), "{}", body);
+        assert!(!body.contains("<CommonPrefixes>"), "{}", body);
+        assert_eq!(super::xml_tag(&body, "IsTruncated"), Some("false"));
+
+        // start-after and V1's marker skip up to and including the key
+        for uri in ["/s3/photos?list-type=2&start-after=b%20c.txt", "/s3/photos?marker=b%20c.txt"] {
+            let body = body_string(server.request("GET", uri).await).await;
+            assert!(!body.contains("<Key>b c.txt</Key>") && body.contains("<Key>d+e.txt</Key>"), "{}", body);
+        }
+        let res = server.request("GET", "/s3/photos?encoding-type=base64").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn s3_buckets_location_and_attributes_are_xml() {
+        let server = TestServer::new();
+        put_hello(&server);
+
+        let body = body_string(server.request("GET", "/s3").await).await;
+        assert!(body.contains("<ListAllMyBucketsResult xmlns="), "{}", body);
+        assert!(body.contains("<Bucket><Name>photos</Name><CreationDate>"), "{}", body);
+
+        let body = body_string(server.request("GET", "/s3/photos?location").await).await;
+        assert!(body.ends_with("2006-03-01/\">local</LocationConstraint>"), "{}", body);
+
+        let attributes = [("x-amz-object-attributes", "ETag,ObjectSize")];
+        let res = server.send(s3("GET", "/s3/photos/a.txt?attributes", &attributes, "")).await;
+        let body = body_string(res).await;
+        assert!(body.contains("<GetObjectAttributesResponse"), "{}", body);
+        assert_eq!(super::xml_tag(&body, "ObjectSize"), Some("5"));
+    }
+
+    #[tokio::test]
+    async fn s3_copy_object_keeps_or_replaces_metadata() {
+        let server = TestServer::new();
+        put_hello(&server);
+
+        let copy = [("x-amz-copy-source", "photos/a.txt")];
+        let res = server.send(s3("PUT", "/s3/photos/b.txt", &copy, "")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(body_string(res).await.contains("<CopyObjectResult"));
+        let copied = server.state.storage.get_object("photos", "b.txt").unwrap();
+        assert_eq!(copied.1, b"hello");
+        assert_eq!(copied.0.content_type, "text/plain");
+
+        let replace = [
+            ("x-amz-copy-source", "/photos/a.txt"),
+            ("x-amz-metadata-directive", "REPLACE"),
+            ("content-type", "application/x-greeting"),
+            ("x-amz-meta-colour", "blue"),
+        ];
+        assert_eq!(server.send(s3("PUT", "/s3/photos/b.txt", &replace, "")).await.status(), StatusCode::OK);
+        let replaced = server.state.storage.get_object_meta("photos", "b.txt").unwrap();
+        assert_eq!(replaced.content_type, "application/x-greeting");
+        assert_eq!(replaced.metadata["colour"], "blue");
+
+        let bad = [("x-amz-copy-source", "photos/a.txt"), ("x-amz-metadata-directive", "MERGE")];
+        assert_eq!(server.send(s3("PUT", "/s3/photos/c.txt", &bad, "")).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn s3_errors_are_xml_and_the_api_keeps_json() {
+        let server = TestServer::new();
+        put_hello(&server);
+
+        let res = server.request("GET", "/s3/photos/missing.txt").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["content-type"], "application/xml");
+        let body = body_string(res).await;
+        assert_eq!(super::xml_tag(&body, "Code"), Some("NoSuchKey"));
+        assert_eq!(super::xml_tag(&body, "Resource"), Some("/photos/missing.txt"));
+
+        let res = server.request("PATCH", "/s3/photos").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(super::xml_tag(&body_string(res).await, "Code"), Some("MethodNotAllowed"));
+
+        let res = server.request("HEAD", "/s3/photos/missing.txt").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(body_string(res).await.is_empty());
+
+        let res = server.request("GET", "/api/object/photos/missing.txt").await;
+        assert_eq!(res.headers()["content-type"], "application/json");
+    }
+
+    #[test]
+    fn attachment_names_are_rfc_5987_encoded() {
+        assert_eq!(
+            super::content_disposition("q1 summary.pdf"),
+            "attachment; filename=\"q1 summary.pdf\"; filename*=UTF-8''q1%20summary.pdf"
+        );
+        // Quotes and semicolons can't break out of either parameter
+        assert_eq!(
+            super::content_disposition("naïve \"draft\"; v2.txt"),
+            "attachment; filename=\"naive _draft_; v2.txt\"; filename*=UTF-8''na%C3%AFve%20%22draft%22%3B%20v2.txt"
+        );
+        assert_eq!(
+            super::content_disposition("报告.pdf"),
+            "attachment; filename=\"__.pdf\"; filename*=UTF-8''%E6%8A%A5%E5%91%8A.pdf"
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_are_attachments_and_previews_stay_inline() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        let key = "reports/2024/q1 summary.pdf";
+        server
+            .state
+            .storage
+            .put_object("photos", key, b"%PDF", PutOptions::default())
+            .unwrap();
+        let uri = "/api/object/photos/reports/2024/q1%20summary.pdf";
+
+        let res = server.request("GET", uri).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("content-disposition").is_none());
+
+        let res = server.request("GET", &format!("{}?download=1", uri)).await;
+        assert_eq!(
+            res.headers()["content-disposition"],
+            "attachment; filename=\"q1 summary.pdf\"; filename*=UTF-8''q1%20summary.pdf"
+        );
+        assert_eq!(body_string(res).await, "%PDF");
+
+        let res = server.request("GET", &format!("{}?attachment=%C3%A9t%C3%A9.pdf", uri)).await;
+        assert_eq!(
+            res.headers()["content-disposition"],
+            "attachment; filename=\"ete.pdf\"; filename*=UTF-8''%C3%A9t%C3%A9.pdf"
+        );
+        let res = server.request("GET", &format!("{}?download=0", uri)).await;
+        assert!(res.headers().get("content-disposition").is_none());
+    }
+
+    #[test]
+    fn buckets_come_from_the_label_before_the_domain() {
+        let bucket = |host| super::virtual_host_bucket(host, "localhost");
+        assert_eq!(bucket("photos.localhost:3210").as_deref(), Some("photos"));
+        assert_eq!(bucket("Photos.LocalHost").as_deref(), Some("photos"));
+        assert_eq!(bucket("localhost:3210"), None);
+        assert_eq!(bucket("photoslocalhost"), None);
+        assert_eq!(bucket("photos.example.com"), None);
+        assert_eq!(bucket("[::1]:3210"), None);
+        assert_eq!(bucket("x.localhost"), None, "not a valid bucket name");
+    }
+
+    fn on_host(method: &str, host: &str, uri: &str, body: &'static str) -> Request {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("host", host)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn virtual_hosted_requests_reach_the_s3_routes() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        let host = "photos.localhost:3210";
+
+        let res = server.send(on_host("PUT", host, "/2024/cat.txt", "meow")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let (_, data) = server.state.storage.get_object("photos", "2024/cat.txt").unwrap();
+        assert_eq!(data, b"meow");
+
+        let res = server.send(on_host("GET", host, "/2024/cat.txt", "")).await;
+        assert_eq!(body_string(res).await, "meow");
+        let res = server.send(on_host("HEAD", host, "/2024/cat.txt", "")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-length"], "4");
+
+        // The bucket itself is at the root
+        let res = server.send(on_host("GET", host, "/?list-type=2", "")).await;
+        let body = body_string(res).await;
+        assert_eq!(super::xml_tag(&body, "Key"), Some("2024/cat.txt"), "{}", body);
+
+        let res = server.send(on_host("DELETE", host, "/2024/cat.txt", "")).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(server.state.storage.get_object("photos", "2024/cat.txt").is_err());
+
+        // The bare host is still the dashboard
+        let res = server.send(on_host("GET", "localhost:3210", "/", "")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+    }
+
+    #[tokio::test]
+    async fn s3_create_bucket_is_idempotent_and_the_api_is_strict() {
+        let server = TestServer::new();
+        for _ in 0..2 {
+            let res = server.send(s3("PUT", "/s3/photos", &[], "")).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["location"], "/photos");
+        }
+        let same = "<CreateBucketConfiguration><LocationConstraint>local</LocationConstraint></CreateBucketConfiguration>";
+        assert_eq!(server.send(s3("PUT", "/s3/photos", &[], same)).await.status(), StatusCode::OK);
+        let other = "<CreateBucketConfiguration><LocationConstraint>eu-west-1</LocationConstraint></CreateBucketConfiguration>";
+        let res = server.send(s3("PUT", "/s3/photos", &[], other)).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(super::xml_tag(&body_string(res).await, "Code"), Some("BucketAlreadyOwnedByYou"));
+
+        let json = [("content-type", "application/json")];
+        let res = server.send(s3("POST", "/api/buckets", &json, r#"{"name": "photos"}"#)).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(server.state.storage.list_buckets().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn bucket_gauges_are_in_the_prometheus_format() {
+        let server = TestServer::new();
+        put_hello(&server);
+        server.state.storage.create_bucket("empty", "local").unwrap();
+
+        let res = server.request("GET", "/api/metrics").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_string(res).await;
+        assert!(body.contains("# TYPE freebucket_bucket_bytes gauge\n"), "{}", body);
+        assert!(body.contains("freebucket_bucket_objects{bucket=\"photos\"} 1\n"), "{}", body);
+        assert!(body.contains("freebucket_bucket_bytes{bucket=\"photos\"} 5\n"), "{}", body);
+        assert!(body.contains("freebucket_bucket_bytes{bucket=\"empty\"} 0\n"), "{}", body);
+
+        let res = server.request("GET", "/api/buckets/photos/largest?limit=1").await;
+        let largest: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        assert_eq!(largest["objects"], serde_json::json!([{"bucket": "photos", "key": "a.txt", "size": 5}]));
+    }
+
+    #[tokio::test]
+    async fn object_metadata_is_rebuilt_on_request() {
+        let server = TestServer::new();
+        put_hello(&server);
+        let meta_path = std::path::Path::new(&server.state.config().data_dir).join("photos/.meta/a.txt.json");
+        std::fs::write(meta_path, b"\0\0\0").unwrap();
+
+        let res = server.request("POST", "/api/object-meta/photos/a.txt/rebuild").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let meta: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        assert_eq!((meta["key"].as_str(), meta["size"].as_u64()), (Some("a.txt"), Some(5)));
+
+        let res = server.request("POST", "/api/object-meta/photos/a.txt").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = server.request("POST", "/api/object-meta/photos/gone.txt/rebuild").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn unsupported_s3_subresources_are_not_listings() {
+        let server = TestServer::new();
+        put_hello(&server);
+        for (method, uri, status, code) in [
+            ("GET", "/s3/photos?acl", StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+            ("PUT", "/s3/photos?policy", StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+            ("GET", "/s3/photos?max-keys=5&lifecycle", StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+            ("DELETE", "/s3/photos/a.txt?tagging", StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+            ("POST", "/s3/photos/a.txt?restore", StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+            ("POST", "/s3/photos?acl", StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+            ("PUT", "/s3/photos?location", StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+        ] {
+            let res = server.request(method, uri).await;
+            assert_eq!(res.status(), status, "{} {}", method, uri);
+            let body = body_string(res).await;
+            assert_eq!(super::xml_tag(&body, "Code"), Some(code), "{} {}: {}", method, uri, body);
+        }
+        let res = server.request("HEAD", "/s3/photos/a.txt?acl").await;
+        assert_eq!(res.status(), StatusCode::NOT_IMPLEMENTED);
+
+        // Implemented ones, and parameters that aren't sub-resources, get through
+        let res = server.request("GET", "/s3/photos?location").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(body_string(res).await.ends_with(">local</LocationConstraint>"));
+        let res = server.request("GET", "/s3/photos/a.txt?response-content-type=text/plain").await;
+        assert_eq!(body_string(res).await, "hello");
+    }
+
+    async fn error_json(res: axum::response::Response) -> serde_json::Value {
+        assert_eq!(res.headers()["content-type"], "application/json");
+        serde_json::from_str(&body_string(res).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unknown_api_routes_answer_json_with_nearby_routes() {
+        let server = TestServer::new();
+        put_hello(&server);
+
+        let res = server.request("GET", "/api/bucket/photos").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let err = error_json(res).await;
+        assert_eq!(err["code"], "NotFound");
+        let message = err["message"].as_str().unwrap();
+        assert!(message.starts_with("No route for GET /api/bucket/photos"), "{}", message);
+        assert!(message.contains("Nearby routes: /api/buckets/:bucket (GET, PATCH, DELETE)"), "{}", message);
+
+        let res = server.request("PATCH", "/api/buckets").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let err = error_json(res).await;
+        assert_eq!(err["code"], "MethodNotAllowed");
+        assert!(err["message"].as_str().unwrap().contains("/api/buckets accepts GET, POST"), "{}", err);
+
+        // The dashboard and other pages are untouched
+        let res = server.request("GET", "/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.request("GET", "/no-such-page").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(body_string(res).await.is_empty());
+    }
+
+    /// `key` percent-encoded the way a browser or SDK puts it in a path
+    fn encoded_key(key: &str) -> String {
+        let segments: Vec<String> = key
+            .split('/')
+            .map(|s| percent_encoding::utf8_percent_encode(s, percent_encoding::NON_ALPHANUMERIC).to_string())
+            .collect();
+        segments.join("/")
+    }
+
+    #[tokio::test]
+    async fn non_ascii_keys_come_back_byte_for_byte() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        let key = "фото/日本語/🎉.txt";
+        let path = encoded_key(key);
+
+        let res = server.send(s3("PUT", &format!("/s3/photos/{}", path), &[], "party")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = server.request("GET", &format!("/api/object/photos/{}?download=1", path)).await;
+        assert_eq!(res.headers()["content-disposition"], "attachment; filename=\"_.txt\"; filename*=UTF-8''%F0%9F%8E%89.txt");
+        assert_eq!(body_string(res).await, "party");
+        let res = server.request("GET", &format!("/s3/photos/{}", path)).await;
+        assert_eq!(body_string(res).await, "party");
+
+        let res = server.request("GET", "/api/buckets/photos/objects").await;
+        let listing: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        assert_eq!(listing["objects"][0]["key"], key);
+        let res = server.request("GET", &format!("/s3/photos?list-type=2&prefix={}", encoded_key("фото/"))).await;
+        assert_eq!(super::xml_tag(&body_string(res).await, "Key"), Some(key));
+        let res = server.request("GET", &format!("/api/buckets/photos/search?q={}", encoded_key("日本"))).await;
+        let found: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        assert_eq!(found["count"], 1);
+
+        let res = server.request("DELETE", &format!("/api/object/photos/{}", path)).await;
+        assert!(res.status().is_success());
+        assert!(server.state.storage.list_objects("photos", "", None, 10).unwrap().objects.is_empty());
+    }
+
+    #[tokio::test]
+    async fn every_s3_write_limits_user_metadata() {
+        let server = TestServer::new();
+        put_hello(&server);
+        let under = "v".repeat(2047);
+        let over = "v".repeat(2048);
+
+        for (value, status) in [(&under, StatusCode::OK), (&over, StatusCode::BAD_REQUEST)] {
+            let meta = [("x-amz-meta-n", value.as_str())];
+            let copy = [
+                ("x-amz-meta-n", value.as_str()),
+                ("x-amz-copy-source", "photos/a.txt"),
+                ("x-amz-metadata-directive", "REPLACE"),
+            ];
+            for req in [
+                s3("PUT", "/s3/photos/put.txt", &meta, "body"),
+                s3("PUT", "/s3/photos/copy.txt", &copy, ""),
+                s3("POST", "/s3/photos/parts.bin?uploads", &meta, ""),
+            ] {
+                let what = format!("{} {}", req.method(), req.uri());
+                let res = server.send(req).await;
+                assert_eq!(res.status(), status, "{}", what);
+                if status == StatusCode::BAD_REQUEST {
+                    let body = body_string(res).await;
+                    assert_eq!(super::xml_tag(&body, "Code"), Some("MetadataTooLarge"), "{}", what);
+                    assert!(body.contains("is 2049 bytes, over the limit of 2048 bytes"), "{}", body);
+                }
+            }
+        }
+        let (meta, _) = server.state.storage.get_object("photos", "copy.txt").unwrap();
+        assert_eq!(meta.metadata["n"], under);
+    }
+
+    #[tokio::test]
+    async fn metadata_limits_come_from_the_config() {
+        let server = TestServer::with_config(|c| c.metadata_limits.max_value = 8);
+        put_hello(&server);
+        let res = server
+            .send(s3("PUT", "/s3/photos/b.txt", &[("x-amz-meta-note", "too long!")], "body"))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = body_string(res).await;
+        assert!(body.contains("value of metadata &apos;note&apos; is 9 bytes, over the limit of 8"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn uploads_keep_the_source_modification_time() {
+        let server = TestServer::new();
+        put_hello(&server);
+        let old = [("x-freebucket-last-modified", "2020-01-02T03:04:05Z")];
+        let res = server.send(s3("PUT", "/s3/photos/old.txt", &old, "old")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server
+            .send(s3("PUT", "/s3/photos/rclone.txt", &[("x-amz-meta-mtime", "1577934245.5")], "old"))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        for key in ["old.txt", "rclone.txt"] {
+            let res = server.request("HEAD", &format!("/s3/photos/{}", key)).await;
+            assert_eq!(res.headers()["last-modified"], "Thu, 02 Jan 2020 03:04:05 GMT", "{}", key);
+        }
+        let listing = body_string(server.request("GET", "/s3/photos?list-type=2").await).await;
+        assert!(listing.contains("<Key>old.txt</Key><LastModified>2020-01-02T03:04:05"), "{}", listing);
+
+        // The payload file agrees, and copies keep the time
+        let data_dir = server.state.config().data_dir.clone();
+        let file_mtime = std::fs::metadata(format!("{}/photos/objects/old.txt", data_dir))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(chrono::DateTime::<chrono::Utc>::from(file_mtime).to_rfc3339(), "2020-01-02T03:04:05+00:00");
+        let copy = [("x-amz-copy-source", "photos/old.txt")];
+        assert_eq!(server.send(s3("PUT", "/s3/photos/copy.txt", &copy, "")).await.status(), StatusCode::OK);
+        let res = server.request("HEAD", "/s3/photos/copy.txt").await;
+        assert_eq!(res.headers()["last-modified"], "Thu, 02 Jan 2020 03:04:05 GMT");
+
+        // Conditional reads go by the source time too
+        let since = [("if-modified-since", "Fri, 03 Jan 2020 00:00:00 GMT")];
+        let res = server.send(s3("GET", "/s3/photos/old.txt", &since, "")).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let bad = [("x-freebucket-last-modified", "yesterday")];
+        let res = server.send(s3("PUT", "/s3/photos/bad.txt", &bad, "x")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        // A malformed rclone time is only metadata
+        let res = server.send(s3("PUT", "/s3/photos/odd.txt", &[("x-amz-meta-mtime", "soon")], "x")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let meta = server.state.storage.get_object_meta("photos", "odd.txt").unwrap();
+        assert_eq!((meta.source_mtime, meta.metadata["mtime"].as_str()), (None, "soon"));
+    }
+
+    #[tokio::test]
+    async fn writes_create_missing_buckets_when_configured() {
+        let server = TestServer::new();
+        let res = server.send(s3("PUT", "/s3/fixtures/a.txt", &[], "a")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(server.state.storage.get_bucket("fixtures").is_err());
+
+        let server = TestServer::with_config(|c| c.auto_create_buckets = true);
+        let res = server.send(s3("PUT", "/s3/fixtures/a.txt", &[], "a")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let bucket = server.state.storage.get_bucket("fixtures").unwrap();
+        assert_eq!((bucket.object_count, bucket.region.as_str()), (1, "local"));
+        let res = server.send(s3("POST", "/s3/uploads-later/big.bin?uploads", &[], "")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(server.state.storage.get_bucket("uploads-later").is_ok());
+
+        // Reads and listings of a missing bucket don't create it
+        for uri in ["/s3/missing/a.txt", "/s3/missing"] {
+            let res = server.request("GET", uri).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+        assert!(server.state.storage.get_bucket("missing").is_err());
+
+        // Names are validated as for any new bucket
+        for (uri, code) in [
+            ("/s3/Not_Valid/a.txt", "InvalidBucketName"),
+            ("/s3/metrics/a.txt", "ReservedBucketName"),
+        ] {
+            let res = server.send(s3("PUT", uri, &[], "a")).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body = body_string(res).await;
+            assert_eq!(super::xml_tag(&body, "Code"), Some(code), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn listings_take_a_modification_time_window() {
+        let server = TestServer::new();
+        put_hello(&server);
+        let uri = "/api/buckets/photos/objects?modified_after=2000-01-01T00:00:00Z";
+        let page: serde_json::Value = serde_json::from_str(&body_string(server.request("GET", uri).await).await).unwrap();
+        assert_eq!(page["objects"][0]["key"], "a.txt");
+        assert!(page["modified_before"].is_string(), "{}", page);
+
+        let uri = format!("/api/buckets/photos/objects?modified_after={}", page["modified_before"].as_str().unwrap());
+        let page: serde_json::Value = serde_json::from_str(&body_string(server.request("GET", &uri).await).await).unwrap();
+        assert_eq!(page["objects"].as_array().map(Vec::len), Some(0), "{}", page);
+
+        let uri = "/api/buckets/photos/objects.ndjson?modified_before=2000-01-01T00:00:00Z";
+        let lines = body_string(server.request("GET", uri).await).await;
+        assert_eq!(lines.trim(), r#"{"summary":{"bytes":0,"count":0,"modified_before":"2000-01-01T00:00:00Z"}}"#);
+        let res = server.request("GET", "/api/buckets/photos/objects?modified_after=yesterday").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn range_writes_need_a_range_that_matches_the_body() {
+        let server = TestServer::with_config(|c| c.range_writes = true);
+        put_hello(&server);
+        let patch = |range: &str, body: &'static str| {
+            s3("PATCH", "/api/object/photos/a.txt", &[("content-range", range)], body)
+        };
+
+        let res = server.send(patch("bytes 0-18446744073709551615/*", "x")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let err = error_json(res).await;
+        assert!(err["message"].as_str().unwrap().contains("longer than any object"), "{}", err);
+
+        let res = server.send(patch("bytes 1-2/*", "xyz")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let err = error_json(res).await;
+        assert!(err["message"].as_str().unwrap().contains("covers 2 bytes but the body is 3"), "{}", err);
+
+        let res = server.send(patch("bytes 1-2/*", "EL")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.request("GET", "/api/object/photos/a.txt").await;
+        assert_eq!(body_string(res).await, "hELlo");
+    }
+
+    #[test]
+    fn aws_chunked_bodies_lose_their_framing_and_keep_their_trailers() {
+        let body = b"6;chunk-signature=ab\r\nhello \r\n5\r\nworld\r\n0\r\nx-amz-checksum-crc32:DUoRhQ==\r\n\r\n";
+        let (data, trailers) = super::decode_aws_chunked(body).unwrap();
+        assert_eq!(data, b"hello world");
+        assert_eq!(trailers["x-amz-checksum-crc32"], "DUoRhQ==");
+
+        // Sizes are hex, and a body may end right after the last chunk
+        let (data, trailers) = super::decode_aws_chunked(b"A\r\n0123456789\r\n0\r\n").unwrap();
+        assert_eq!(data, b"0123456789");
+        assert!(trailers.is_empty());
+
+        for body in [
+            &b"b\r\nhello world\r\n"[..],
+            b"6\r\nhel",
+            b"6\r\nhello world\r\n0\r\n",
+            b"zz\r\nhello\r\n0\r\n",
+            b"-1\r\nhello\r\n0\r\n",
+            b"hello world",
+        ] {
+            let err = super::decode_aws_chunked(body).unwrap_err();
+            assert!(matches!(err, crate::error::AppError::InvalidRequest(_)), "{:?}: {:?}", String::from_utf8_lossy(body), err);
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_s3_puts_store_only_the_payload() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        let headers = [
+            ("x-amz-content-sha256", "STREAMING-UNSIGNED-PAYLOAD-TRAILER"),
+            ("content-encoding", "aws-chunked"),
+            ("x-amz-decoded-content-length", "11"),
+            ("x-amz-trailer", "x-amz-checksum-sha256"),
+        ];
+        let body = "6\r\nhello \r\n5\r\nworld\r\n0\r\nx-amz-checksum-sha256:uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=\r\n\r\n";
+        let res = server.send(s3("PUT", "/s3/photos/a.txt", &headers, body)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let (meta, data) = server.state.storage.get_object("photos", "a.txt").unwrap();
+        assert_eq!(data, b"hello world");
+        assert_eq!(meta.size, 11);
+
+        // The trailing checksum is checked like a header
+        let body = "6\r\nhello \r\n5\r\nWORLD\r\n0\r\nx-amz-checksum-sha256:uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=\r\n\r\n";
+        let res = server.send(s3("PUT", "/s3/photos/b.txt", &headers, body)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(super::xml_tag(&body_string(res).await, "Code"), Some("BadDigest"));
+
+        let res = server.send(s3("PUT", "/s3/photos/c.txt", &headers, "6\r\nhello \r\n")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(server.state.storage.stat_object("photos", "c.txt").is_err());
+    }
+
+    #[tokio::test]
+    async fn slim_listings_leave_the_metadata_out() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        let metadata: HashMap<String, String> =
+            (0..20).map(|i| (format!("field-{:02}", i), format!("value of field {} for this object", i))).collect();
+        for i in 0..1000 {
+            server
+                .state
+                .storage
+                .put_object(
+                    "photos",
+                    &format!("img-{:04}.jpg", i),
+                    b"jpeg",
+                    PutOptions { metadata: metadata.clone(), ..Default::default() },
+                )
+                .unwrap();
+        }
+
+        let server = &server;
+        let list = |fields: &'static str| async move {
+            let uri = format!("/api/buckets/photos/objects?max_keys=1000{}", fields);
+            let body = body_string(server.request("GET", &uri).await).await;
+            let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(page["objects"].as_array().map(Vec::len), Some(1000));
+            assert_ne!(page["size_limited"], true);
+            (body.len(), page)
+        };
+        let (full, page) = list("").await;
+        assert_eq!(page["objects"][0]["metadata"].as_object().map(|m| m.len()), Some(20));
+        let (slim, page) = list("&fields=slim").await;
+        assert!(page["objects"][0].get("metadata").is_none(), "{}", page["objects"][0]);
+        assert_eq!(page["objects"][999]["key"], "img-0999.jpg");
+        // 1000 objects with 20 entries each: about 1.3 MB in full, 80 KB slim
+        assert!(slim * 10 < full, "slim {} bytes, full {} bytes", slim, full);
+
+        // The details endpoint still has everything
+        let res = server.request("GET", "/api/object/photos/img-0000.jpg?details").await;
+        let meta: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        assert_eq!(meta["metadata"]["field-19"], "value of field 19 for this object");
+    }
+}
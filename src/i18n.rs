@@ -0,0 +1,180 @@
+//! Message catalogs for the dashboard and inbox pages. Each locale is a
+//! JSON map from message key to text, embedded in the binary. Keys a
+//! locale lacks fall back to English.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use axum::http::{header, HeaderMap};
+use serde::{Deserialize, Serialize};
+
+/// Every locale shipped, English first
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.json")),
+    ("es", include_str!("../locales/es.json")),
+];
+
+/// Cookie the dashboard's language switcher sets, so pages are rendered in
+/// the chosen locale rather than the one `Accept-Language` prefers
+pub const LOCALE_COOKIE: &str = "freebucket_locale";
+
+/// A catalog entry: plain text, or one text per CLDR plural category
+/// (`one`, `other`, ...)
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Message {
+    Text(String),
+    Plural(BTreeMap<String, String>),
+}
+
+type Catalog = BTreeMap<String, Message>;
+
+/// Each locale's catalog, with English filled in for the keys it lacks
+fn catalogs() -> &'static [(&'static str, Catalog)] {
+    static LOADED: OnceLock<Vec<(&'static str, Catalog)>> = OnceLock::new();
+    LOADED.get_or_init(|| {
+        let parsed: Vec<(&str, Catalog)> = CATALOGS
+            .iter()
+            .map(|(code, json)| {
+                let catalog = serde_json::from_str(json).unwrap_or_else(|e| {
+                    tracing::error!("Ignoring invalid message catalog locales/{}.json: {}", code, e);
+                    Catalog::new()
+                });
+                (*code, catalog)
+            })
+            .collect();
+        let english = parsed[0].1.clone();
+        parsed
+            .into_iter()
+            .map(|(code, catalog)| {
+                let mut merged = english.clone();
+                merged.extend(catalog);
+                (code, merged)
+            })
+            .collect()
+    })
+}
+
+/// A locale the dashboard's switcher offers
+pub struct LocaleOption {
+    pub code: &'static str,
+    pub name: String,
+}
+
+pub fn locales() -> Vec<LocaleOption> {
+    catalogs()
+        .iter()
+        .map(|(code, _)| LocaleOption {
+            code,
+            name: Messages::new(code).get("locale.name").to_string(),
+        })
+        .collect()
+}
+
+/// The messages of one locale
+#[derive(Clone, Copy)]
+pub struct Messages {
+    locale: &'static str,
+    catalog: &'static Catalog,
+}
+
+impl Messages {
+    fn new(locale: &str) -> Self {
+        let (locale, catalog) = catalogs()
+            .iter()
+            .find(|(code, _)| *code == locale)
+            .unwrap_or(&catalogs()[0]);
+        Self { locale, catalog }
+    }
+
+    /// The locale the switcher's cookie names, else the first
+    /// `Accept-Language` match, else English
+    pub fn for_request(headers: &HeaderMap) -> Self {
+        let chosen = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().strip_prefix(LOCALE_COOKIE)?.strip_prefix('='))
+            .find(|code| is_known(code));
+        let locale = chosen
+            .or_else(|| {
+                headers
+                    .get(header::ACCEPT_LANGUAGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(negotiate)
+            })
+            .unwrap_or(CATALOGS[0].0);
+        Self::new(locale)
+    }
+
+    pub fn locale(&self) -> &'static str {
+        self.locale
+    }
+
+    /// The text of `key`, or the key itself when no catalog has it
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        match self.catalog.get(key) {
+            Some(Message::Text(text)) => text,
+            Some(Message::Plural(forms)) => forms.get("other").map_or(key, String::as_str),
+            None => key,
+        }
+    }
+
+    /// The text of `key` with its `{name}` placeholders filled in
+    pub fn fill(&self, key: &str, vars: &[(&str, &str)]) -> String {
+        let mut text = self.get(key).to_string();
+        for (name, value) in vars {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        text
+    }
+
+    /// The form of `key` for a count of `n`, with `{n}` filled in. English
+    /// and Spanish only tell one from many; the page script goes through
+    /// `Intl.PluralRules` for the rest.
+    pub fn plural(&self, key: &str, n: u64) -> String {
+        let text = match self.catalog.get(key) {
+            Some(Message::Plural(forms)) => {
+                let category = if n == 1 { "one" } else { "other" };
+                forms.get(category).or_else(|| forms.get("other")).map_or(key, String::as_str)
+            }
+            _ => self.get(key),
+        };
+        text.replace("{n}", &n.to_string())
+    }
+
+    /// The whole catalog as JSON, safe to put inside a `<script>` element
+    pub fn to_json(self) -> String {
+        serde_json::to_string(self.catalog)
+            .unwrap_or_else(|_| "{}".to_string())
+            .replace("</", "<\\/")
+    }
+}
+
+fn is_known(code: &str) -> bool {
+    CATALOGS.iter().any(|(known, _)| *known == code)
+}
+
+/// The shipped locale an `Accept-Language` header ranks highest, matching
+/// on the primary subtag (`es-MX` is `es`)
+fn negotiate(accept_language: &str) -> Option<&'static str> {
+    let mut ranges: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse().ok())
+                .unwrap_or(1.0);
+            (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // Stable, so equally ranked languages keep the client's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.iter().find_map(|(tag, _)| {
+        let primary = tag.split('-').next().unwrap_or(tag).to_ascii_lowercase();
+        CATALOGS.iter().map(|(code, _)| *code).find(|code| *code == primary)
+    })
+}
@@ -0,0 +1,431 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::clock::Sources;
+use crate::error::AppError;
+use crate::AppState;
+
+/// Longest `Idempotency-Key` accepted
+const MAX_KEY_LEN: usize = 255;
+
+/// A finished response, kept so a retry with the same key gets it back
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredResponse {
+    /// SHA-256 of the method, URI, and body that produced it
+    request_hash: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// Hex-encoded body
+    body: String,
+    stored_at: DateTime<Utc>,
+}
+
+enum Slot {
+    /// The first request with this key is still running
+    InFlight { request_hash: String },
+    Done(StoredResponse),
+}
+
+#[derive(Default)]
+struct Entries {
+    slots: HashMap<String, Slot>,
+    /// Keys from least to most recently used
+    order: VecDeque<String>,
+}
+
+enum Lookup {
+    Fresh,
+    Replay(StoredResponse),
+    Mismatch,
+    InFlight,
+}
+
+/// Responses to mutating API calls, keyed by the client's `Idempotency-Key`.
+/// The most recent `capacity` keys are held in memory; with a spill
+/// directory, older ones are written there instead of being forgotten.
+pub struct IdempotencyCache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+    ttl: Duration,
+    spill_dir: Option<PathBuf>,
+    sources: Sources,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl IdempotencyCache {
+    pub fn new(capacity: usize, ttl: Duration, spill_dir: Option<PathBuf>, sources: Sources) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            capacity: capacity.max(1),
+            ttl,
+            spill_dir,
+            sources,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Repeats answered from a stored response
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Keys seen for the first time (or after they expired)
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn expired(&self, stored: &StoredResponse) -> bool {
+        let age = self.sources.now().signed_duration_since(stored.stored_at);
+        age.to_std().is_ok_and(|age| age > self.ttl)
+    }
+
+    fn spill_path(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.spill_dir.as_ref()?;
+        Some(dir.join(format!("{}.json", hex::encode(Sha256::digest(key.as_bytes())))))
+    }
+
+    /// Claim `key` for a request, or find what an earlier one left behind
+    fn begin(&self, key: &str, request_hash: &str) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        let existing = match entries.slots.get(key) {
+            Some(Slot::InFlight { request_hash: running }) if running != request_hash => {
+                return Lookup::Mismatch
+            }
+            Some(Slot::InFlight { .. }) => return Lookup::InFlight,
+            Some(Slot::Done(stored)) => Some(stored.clone()),
+            None => self.read_spilled(key),
+        };
+
+        match existing.filter(|stored| !self.expired(stored)) {
+            Some(stored) if stored.request_hash != request_hash => Lookup::Mismatch,
+            Some(stored) => {
+                touch(&mut entries.order, key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Lookup::Replay(stored)
+            }
+            None => {
+                entries.slots.insert(
+                    key.to_string(),
+                    Slot::InFlight { request_hash: request_hash.to_string() },
+                );
+                touch(&mut entries.order, key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Lookup::Fresh
+            }
+        }
+    }
+
+    fn finish(&self, key: &str, stored: StoredResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.slots.insert(key.to_string(), Slot::Done(stored));
+        self.evict(&mut entries);
+    }
+
+    /// Forget a key whose request failed or was cancelled, so it can be retried
+    fn release(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if matches!(entries.slots.get(key), Some(Slot::InFlight { .. })) {
+            entries.slots.remove(key);
+            entries.order.retain(|k| k != key);
+        }
+    }
+
+    /// Drop the least recently used finished responses past `capacity`,
+    /// spilling them to disk when that's configured
+    fn evict(&self, entries: &mut Entries) {
+        let mut skipped = 0;
+        while entries.slots.len() > self.capacity && skipped < entries.order.len() {
+            let key = entries.order.pop_front().unwrap();
+            match entries.slots.remove(&key) {
+                Some(Slot::Done(stored)) if !self.expired(&stored) => self.write_spilled(&key, &stored),
+                Some(running @ Slot::InFlight { .. }) => {
+                    entries.slots.insert(key.clone(), running);
+                    entries.order.push_back(key);
+                    skipped += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn read_spilled(&self, key: &str) -> Option<StoredResponse> {
+        let path = self.spill_path(key)?;
+        let data = fs::read(&path).ok()?;
+        match serde_json::from_slice::<StoredResponse>(&data) {
+            Ok(stored) if !self.expired(&stored) => Some(stored),
+            _ => {
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    fn write_spilled(&self, key: &str, stored: &StoredResponse) {
+        let Some(path) = self.spill_path(key) else { return };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_vec(stored).unwrap_or_default()));
+        if let Err(e) = result {
+            tracing::warn!("Cannot spill idempotency key to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Delete spilled responses older than the TTL. Returns how many went.
+    pub fn purge_expired(&self) -> usize {
+        let Some(dir) = &self.spill_dir else { return 0 };
+        let Ok(entries) = fs::read_dir(dir) else { return 0 };
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let stored = fs::read(entry.path())
+                .ok()
+                .and_then(|data| serde_json::from_slice::<StoredResponse>(&data).ok());
+            let expired = !matches!(stored, Some(stored) if !self.expired(&stored));
+            if expired && fs::remove_file(entry.path()).is_ok() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
+
+/// Mark `key` as the most recently used
+fn touch(order: &mut VecDeque<String>, key: &str) {
+    order.retain(|k| k != key);
+    order.push_back(key.to_string());
+}
+
+/// Releases an in-flight key unless the response was stored, including
+/// when the client disconnects and the request future is dropped
+struct InFlightGuard<'a> {
+    cache: &'a IdempotencyCache,
+    key: &'a str,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.cache.release(self.key);
+    }
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let mut res = Response::new(Body::from(hex::decode(&stored.body).unwrap_or_default()));
+    *res.status_mut() = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    for (name, value) in &stored.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::from_str(value)) {
+            res.headers_mut().append(name, value);
+        }
+    }
+    res.headers_mut()
+        .insert("idempotent-replayed", HeaderValue::from_static("true"));
+    res
+}
+
+/// Run a mutating API call at most once per `Idempotency-Key`. A repeat
+/// with the same method, URI, and body gets the stored response back; a
+/// different request under the same key, or a repeat while the first is
+/// still running, gets a 409. Server errors aren't stored, so they can be
+/// retried with the same key.
+pub async fn idempotent_requests(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    if !matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE)
+        || !req.uri().path().starts_with("/api/")
+    {
+        return next.run(req).await;
+    }
+    let Some(key) = req.headers().get("idempotency-key") else {
+        return next.run(req).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        _ => {
+            return AppError::InvalidRequest(format!(
+                "Idempotency-Key must be 1 to {} visible ASCII characters",
+                MAX_KEY_LEN
+            ))
+            .into_response()
+        }
+    };
+
+    let (parts, body) = req.into_parts();
+    let body = match to_bytes(body, state.config().max_upload_size).await {
+        Ok(body) => body,
+        Err(e) => return AppError::InvalidRequest(format!("Cannot read request body: {}", e)).into_response(),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{} {}\n", parts.method, parts.uri));
+    hasher.update(&body);
+    let request_hash = hex::encode(hasher.finalize());
+
+    let cache = &state.idempotency;
+    match cache.begin(&key, &request_hash) {
+        Lookup::Replay(stored) => return replay(stored),
+        Lookup::Mismatch => return AppError::IdempotencyKeyReused(key).into_response(),
+        Lookup::InFlight => return AppError::IdempotencyKeyInUse(key).into_response(),
+        Lookup::Fresh => {}
+    }
+    let _guard = InFlightGuard { cache, key: &key };
+
+    let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if res.status().is_server_error() {
+        return res;
+    }
+    let (parts, body) = res.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => return AppError::StorageError(format!("Cannot buffer response: {}", e)).into_response(),
+    };
+    cache.finish(
+        &key,
+        StoredResponse {
+            request_hash,
+            status: parts.status.as_u16(),
+            headers: parts
+                .headers
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+                .collect(),
+            body: hex::encode(&body),
+            stored_at: cache.sources.now(),
+        },
+    );
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{SequentialIds, SteppingClock};
+    use crate::storage::PutOptions;
+    use crate::testing::{body_string, TestServer};
+
+    fn call(method: &str, uri: &str, key: Option<&str>, body: &'static str) -> Request {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(key) = key {
+            req = req.header("idempotency-key", key);
+        }
+        req.body(Body::from(body)).unwrap()
+    }
+
+    fn stored(request_hash: &str, at: DateTime<Utc>) -> StoredResponse {
+        StoredResponse {
+            request_hash: request_hash.to_string(),
+            status: 201,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: hex::encode(b"{}"),
+            stored_at: at,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_repeated_bucket_create_runs_once() {
+        let server = TestServer::new();
+        let create = || call("POST", "/api/buckets", Some("create-photos"), r#"{"name": "photos"}"#);
+
+        let first = server.send(create()).await;
+        let status = first.status();
+        assert!(status.is_success(), "{}", status);
+        assert!(first.headers().get("idempotent-replayed").is_none());
+        let body = body_string(first).await;
+
+        let again = server.send(create()).await;
+        assert_eq!(again.status(), status);
+        assert_eq!(again.headers()["idempotent-replayed"], "true");
+        assert_eq!(body_string(again).await, body);
+        assert_eq!(server.state.storage.list_buckets().len(), 1);
+        assert_eq!((server.state.idempotency.hits(), server.state.idempotency.misses()), (1, 1));
+
+        // The same key on a different request is refused
+        let other = call("POST", "/api/buckets", Some("create-photos"), r#"{"name": "videos"}"#);
+        let res = server.send(other).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert!(server.state.storage.get_bucket("videos").is_err());
+        // Without a key the create runs again, and fails
+        let res = server.send(call("POST", "/api/buckets", None, r#"{"name": "photos"}"#)).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let metrics = body_string(server.request("GET", "/api/metrics").await).await;
+        assert!(metrics.contains("\nfreebucket_idempotency_hits_total 1\n"), "{}", metrics);
+        assert!(metrics.contains("\nfreebucket_idempotency_misses_total 1\n"), "{}", metrics);
+    }
+
+    #[tokio::test]
+    async fn a_repeated_batch_delete_runs_once() {
+        let server = TestServer::new();
+        let storage = &server.state.storage;
+        storage.create_bucket("photos", "local").unwrap();
+        let put = |key: &str| {
+            storage
+                .put_object("photos", key, b"x", PutOptions::default())
+                .unwrap();
+        };
+        put("raw/a.jpg");
+        put("raw/b.jpg");
+        let delete = || call("DELETE", "/api/buckets/photos/prefix?prefix=raw/", Some("clean-raw"), "");
+
+        let first = server.send(delete()).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let body = body_string(first).await;
+        // Written after the delete; a second run would take it too
+        put("raw/c.jpg");
+
+        let again = server.send(delete()).await;
+        assert_eq!(again.headers()["idempotent-replayed"], "true");
+        assert_eq!(body_string(again).await, body);
+        assert!(storage.get_object("photos", "raw/c.jpg").is_ok());
+    }
+
+    #[test]
+    fn old_keys_spill_to_disk_and_come_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let sources = Sources::configured(None, chrono::Duration::zero(), false);
+        let cache = IdempotencyCache::new(1, Duration::from_secs(60), Some(dir.path().to_path_buf()), sources.clone());
+
+        for key in ["k1", "k2"] {
+            assert!(matches!(cache.begin(key, "hash"), Lookup::Fresh));
+            cache.finish(key, stored("hash", sources.now()));
+        }
+        // k1 was pushed out of memory onto disk
+        assert_eq!(cache.entries.lock().unwrap().slots.len(), 1);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert!(matches!(cache.begin("k1", "hash"), Lookup::Replay(r) if r.status == 201));
+        assert!(matches!(cache.begin("k1", "other"), Lookup::Mismatch));
+    }
+
+    #[test]
+    fn expired_keys_run_again() {
+        let clock = SteppingClock::new("2026-03-01T12:00:00Z".parse().unwrap(), chrono::Duration::hours(1));
+        let sources = Sources::new(clock, SequentialIds::default());
+        let dir = tempfile::tempdir().unwrap();
+        let cache = IdempotencyCache::new(1, Duration::from_secs(60), Some(dir.path().to_path_buf()), sources.clone());
+
+        assert!(matches!(cache.begin("k1", "hash"), Lookup::Fresh));
+        // A retry while the first is running is told to wait
+        assert!(matches!(cache.begin("k1", "hash"), Lookup::InFlight));
+        cache.finish("k1", stored("hash", sources.now()));
+        // Each read of the clock is an hour on, well past the TTL
+        assert!(matches!(cache.begin("k1", "hash"), Lookup::Fresh));
+        assert_eq!((cache.hits(), cache.misses()), (0, 2));
+
+        cache.write_spilled("old", &stored("hash", sources.now()));
+        assert_eq!(cache.purge_expired(), 1);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
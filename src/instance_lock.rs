@@ -0,0 +1,103 @@
+//! `<data_dir>/.freebucket.lock`, held by a running server. A second server
+//! on the same data dir refuses to start, and `freebucket doctor` can tell
+//! that one is running. The lock goes with the process, so a crash never
+//! leaves it held; the file only records the pid of the last holder.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+pub const LOCK_FILE: &str = ".freebucket.lock";
+
+/// Held for as long as the server runs
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// Who holds the lock on a data dir
+#[derive(Debug, PartialEq, Eq)]
+pub enum LockState {
+    /// No lock file: no server has run here
+    Absent,
+    /// A lock file nobody holds, left by a server that stopped
+    Free { last_pid: Option<u32> },
+    /// Another process is running on the data dir
+    Held { pid: Option<u32> },
+}
+
+fn open(data_dir: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(data_dir.join(LOCK_FILE))
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+/// Take the data dir for this process, creating the dir if needed. Fails
+/// with a message naming the other server's pid when one holds it.
+pub fn acquire(data_dir: &Path) -> Result<InstanceLock, String> {
+    let describe = |e: io::Error| format!("Cannot lock {}: {}", data_dir.join(LOCK_FILE).display(), e);
+    fs::create_dir_all(data_dir).map_err(describe)?;
+    let mut file = open(data_dir).map_err(describe)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let holder = read_pid(&mut file).map(|pid| format!(" (pid {})", pid)).unwrap_or_default();
+            return Err(format!(
+                "Another FreeBucket server{} is using the data dir {}",
+                holder,
+                data_dir.display()
+            ));
+        }
+        Err(TryLockError::Error(e)) => return Err(describe(e)),
+    }
+    file.set_len(0)
+        .and_then(|_| file.rewind())
+        .and_then(|_| writeln!(file, "{}", std::process::id()))
+        .map_err(describe)?;
+    Ok(InstanceLock { _file: file })
+}
+
+/// Whether another process holds the data dir, without taking it
+pub fn state(data_dir: &Path) -> io::Result<LockState> {
+    let path = data_dir.join(LOCK_FILE);
+    if !path.exists() {
+        return Ok(LockState::Absent);
+    }
+    let mut file = File::open(&path)?;
+    let pid = read_pid(&mut file);
+    match file.try_lock_shared() {
+        Ok(()) => Ok(LockState::Free { last_pid: pid }),
+        Err(TryLockError::WouldBlock) => Ok(LockState::Held { pid }),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_second_server_is_refused_until_the_first_stops() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        assert_eq!(state(&data_dir).unwrap(), LockState::Absent);
+
+        let lock = acquire(&data_dir).unwrap();
+        let pid = Some(std::process::id());
+        assert_eq!(state(&data_dir).unwrap(), LockState::Held { pid });
+        let err = acquire(&data_dir).err().unwrap();
+        assert!(err.contains(&format!("(pid {})", std::process::id())), "{}", err);
+
+        drop(lock);
+        assert_eq!(state(&data_dir).unwrap(), LockState::Free { last_pid: pid });
+        assert!(acquire(&data_dir).is_ok());
+    }
+}
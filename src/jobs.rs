@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::Duration;
+
+use crate::clock::Sources;
+use crate::error::AppError;
+use crate::models::{BulkError, BulkJob, BulkJobStatus, BulkRequest, StatsRecompute};
+use crate::AppState;
+
+/// How long a finished job stays available for polling
+const JOB_RETENTION: Duration = Duration::hours(1);
+
+/// In-memory registry of bulk copy/move jobs. Jobs do not survive a restart.
+pub struct BulkJobs {
+    jobs: Mutex<HashMap<String, BulkJob>>,
+    sources: Sources,
+}
+
+impl BulkJobs {
+    pub fn new(sources: Sources) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            sources,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<BulkJob> {
+        self.prune();
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    fn insert(&self, job: BulkJob) {
+        self.prune();
+        self.jobs.lock().unwrap().insert(job.id.clone(), job);
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut BulkJob)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            f(job);
+        }
+    }
+
+    /// Forget jobs that finished longer than `JOB_RETENTION` ago
+    fn prune(&self) {
+        let cutoff = self.sources.now() - JOB_RETENTION;
+        self.jobs
+            .lock()
+            .unwrap()
+            .retain(|_, job| match job.finished_at {
+                Some(finished) => finished > cutoff,
+                None => true,
+            });
+    }
+}
+
+/// Register a bulk copy/move and run it on the blocking pool. Unknown
+/// buckets are rejected up front; everything else is reported on the job.
+pub fn start_bulk_job(state: Arc<AppState>, req: BulkRequest) -> Result<BulkJob, AppError> {
+    state.storage.get_bucket(&req.source_bucket)?;
+    state.storage.get_bucket(&req.destination_bucket)?;
+
+    let job = BulkJob {
+        id: state.storage.new_id().to_string(),
+        operation: req.operation,
+        source_bucket: req.source_bucket.clone(),
+        source_prefix: req.source_prefix.clone(),
+        destination_bucket: req.destination_bucket.clone(),
+        destination_prefix: req.destination_prefix.clone(),
+        status: BulkJobStatus::Running,
+        total: 0,
+        done: 0,
+        errors: Vec::new(),
+        started_at: state.storage.now(),
+        finished_at: None,
+    };
+    state.bulk_jobs.insert(job.clone());
+
+    let id = job.id.clone();
+    tokio::task::spawn_blocking(move || run_bulk_job(&state, &id, &req));
+    Ok(job)
+}
+
+fn bulk_error(key: &str, e: &AppError) -> BulkError {
+    let (_, code, message) = e.parts();
+    BulkError {
+        key: key.to_string(),
+        code: code.to_string(),
+        message,
+    }
+}
+
+fn run_bulk_job(state: &AppState, id: &str, req: &BulkRequest) {
+    let jobs = &state.bulk_jobs;
+    let plan = match state.storage.plan_bulk_transfer(req) {
+        Ok(plan) => plan,
+        Err(e) => {
+            jobs.update(id, |job| {
+                job.errors.push(bulk_error(&req.source_prefix, &e));
+                job.status = BulkJobStatus::Failed;
+                job.finished_at = Some(state.storage.now());
+            });
+            tracing::error!("Bulk job {} failed: {:?}", id, e);
+            return;
+        }
+    };
+    jobs.update(id, |job| job.total = plan.len());
+
+    for (src_key, dst_key) in &plan {
+        let result = state.storage.bulk_transfer_object(req, src_key, dst_key);
+        jobs.update(id, |job| {
+            job.done += 1;
+            if let Err(e) = &result {
+                job.errors.push(bulk_error(src_key, e));
+            }
+        });
+    }
+
+    let finished = state.storage.finish_bulk_transfer(req);
+    jobs.update(id, |job| {
+        if let Err(e) = &finished {
+            job.errors.push(bulk_error(&req.destination_prefix, e));
+        }
+        job.status = BulkJobStatus::Completed;
+        job.finished_at = Some(state.storage.now());
+    });
+    tracing::info!(
+        "Bulk {:?} {}/{} → {}/{} finished ({} objects)",
+        req.operation,
+        req.source_bucket,
+        req.source_prefix,
+        req.destination_bucket,
+        req.destination_prefix,
+        plan.len()
+    );
+}
+
+/// Recompute a bucket's statistics one batch at a time on the blocking
+/// pool, so a huge bucket never ties up a thread for the whole walk
+pub fn start_stats_recompute(state: Arc<AppState>, bucket: &str) -> Result<StatsRecompute, AppError> {
+    let mut recount = state.storage.begin_stats_recompute(bucket)?;
+    let job = state.storage.stats_recompute(bucket)?;
+    tokio::spawn(async move {
+        loop {
+            let batch_state = state.clone();
+            let (stepped, more) = tokio::task::spawn_blocking(move || {
+                let more = batch_state.storage.step_stats_recompute(&mut recount);
+                (recount, more)
+            })
+            .await
+            .expect("a recompute batch panicked");
+            recount = stepped;
+            if !more {
+                break;
+            }
+        }
+        tokio::task::spawn_blocking(move || state.storage.finish_stats_recompute(recount));
+    });
+    Ok(job)
+}
@@ -0,0 +1,7 @@
+//! The parts of FreeBucket other programs can build on: the API's models,
+//! byte size formatting, and a typed client for a running server. The
+//! server and the CLI are the `freebucket` binary.
+
+pub mod client;
+pub mod models;
+pub mod size;
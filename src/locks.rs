@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::clock::Sources;
+use crate::error::AppError;
+use crate::models::{AcquiredLease, ObjectLease};
+
+/// How long a lease lasts when the request doesn't say
+pub const DEFAULT_TTL_SECS: u64 = 60;
+
+/// Advisory leases on object keys, for writers in different processes
+/// that share keys. Expired leases are dropped whenever the table is
+/// touched. With a file, every change is written to it, so leases outlive
+/// a restart.
+pub struct LockTable {
+    leases: Mutex<HashMap<(String, String), AcquiredLease>>,
+    path: Option<PathBuf>,
+    sources: Sources,
+}
+
+impl LockTable {
+    pub fn new(path: Option<PathBuf>, sources: Sources) -> Self {
+        let mut leases = HashMap::new();
+        if let Some(path) = &path {
+            match fs::read(path).map(|data| serde_json::from_slice::<Vec<AcquiredLease>>(&data)) {
+                Ok(Ok(saved)) => {
+                    let now = sources.now();
+                    for held in saved.into_iter().filter(|held| held.lease.expires_at > now) {
+                        leases.insert((held.lease.bucket.clone(), held.lease.key.clone()), held);
+                    }
+                    tracing::info!("Loaded {} object lease(s) from {}", leases.len(), path.display());
+                }
+                Ok(Err(e)) => tracing::warn!("Ignoring unreadable lease file {}: {}", path.display(), e),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Cannot read lease file {}: {}", path.display(), e),
+            }
+        }
+        Self {
+            leases: Mutex::new(leases),
+            path,
+            sources,
+        }
+    }
+
+    /// Take the lease on a key that nobody holds, or whose lease expired
+    pub fn acquire(
+        &self,
+        bucket: &str,
+        key: &str,
+        owner: Option<String>,
+        ttl_secs: u64,
+    ) -> Result<AcquiredLease, AppError> {
+        let mut leases = self.leases.lock().unwrap();
+        self.prune(&mut leases);
+        if let Some(held) = leases.get(&(bucket.to_string(), key.to_string())) {
+            return Err(locked(&held.lease));
+        }
+        let now = self.sources.now();
+        let held = AcquiredLease {
+            token: self.sources.new_id().to_string(),
+            lease: ObjectLease {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+                owner,
+                ttl_secs,
+                acquired_at: now,
+                expires_at: now + chrono::Duration::seconds(ttl_secs as i64),
+            },
+        };
+        leases.insert((bucket.to_string(), key.to_string()), held.clone());
+        self.save(&leases);
+        Ok(held)
+    }
+
+    /// Extend a lease to `ttl_secs` from now, or its own TTL without one
+    pub fn refresh(
+        &self,
+        bucket: &str,
+        key: &str,
+        token: Option<&str>,
+        ttl_secs: Option<u64>,
+    ) -> Result<ObjectLease, AppError> {
+        let mut leases = self.leases.lock().unwrap();
+        self.prune(&mut leases);
+        let held = held_by(&mut leases, bucket, key, token)?;
+        if let Some(ttl_secs) = ttl_secs {
+            held.lease.ttl_secs = ttl_secs;
+        }
+        held.lease.expires_at = self.sources.now() + chrono::Duration::seconds(held.lease.ttl_secs as i64);
+        let lease = held.lease.clone();
+        self.save(&leases);
+        Ok(lease)
+    }
+
+    pub fn release(&self, bucket: &str, key: &str, token: Option<&str>) -> Result<(), AppError> {
+        let mut leases = self.leases.lock().unwrap();
+        self.prune(&mut leases);
+        held_by(&mut leases, bucket, key, token)?;
+        leases.remove(&(bucket.to_string(), key.to_string()));
+        self.save(&leases);
+        Ok(())
+    }
+
+    pub fn get(&self, bucket: &str, key: &str) -> Option<ObjectLease> {
+        let mut leases = self.leases.lock().unwrap();
+        self.prune(&mut leases);
+        leases.get(&(bucket.to_string(), key.to_string())).map(|held| held.lease.clone())
+    }
+
+    /// Active leases in a bucket under `prefix`, by key
+    pub fn list(&self, bucket: &str, prefix: &str) -> Vec<ObjectLease> {
+        let mut leases = self.leases.lock().unwrap();
+        self.prune(&mut leases);
+        let mut listed: Vec<ObjectLease> = leases
+            .values()
+            .filter(|held| held.lease.bucket == bucket && held.lease.key.starts_with(prefix))
+            .map(|held| held.lease.clone())
+            .collect();
+        listed.sort_by(|a, b| a.key.cmp(&b.key));
+        listed
+    }
+
+    /// Refuse a write to a leased key unless it carries the lease's token
+    pub fn check_write(&self, bucket: &str, key: &str, token: Option<&str>) -> Result<(), AppError> {
+        let mut leases = self.leases.lock().unwrap();
+        self.prune(&mut leases);
+        match leases.get(&(bucket.to_string(), key.to_string())) {
+            Some(held) if Some(held.token.as_str()) != token => Err(locked(&held.lease)),
+            _ => Ok(()),
+        }
+    }
+
+    fn prune(&self, leases: &mut HashMap<(String, String), AcquiredLease>) {
+        let now = self.sources.now();
+        let before = leases.len();
+        leases.retain(|_, held| held.lease.expires_at > now);
+        if leases.len() != before {
+            self.save(leases);
+        }
+    }
+
+    fn save(&self, leases: &HashMap<(String, String), AcquiredLease>) {
+        let Some(path) = &self.path else { return };
+        let saved: Vec<&AcquiredLease> = leases.values().collect();
+        let tmp = path.with_extension("json.tmp");
+        let result = fs::write(&tmp, serde_json::to_vec(&saved).unwrap_or_default())
+            .and_then(|_| fs::rename(&tmp, path));
+        if let Err(e) = result {
+            tracing::warn!("Cannot save object leases to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// The lease on a key, if `token` is its token
+fn held_by<'a>(
+    leases: &'a mut HashMap<(String, String), AcquiredLease>,
+    bucket: &str,
+    key: &str,
+    token: Option<&str>,
+) -> Result<&'a mut AcquiredLease, AppError> {
+    match leases.get_mut(&(bucket.to_string(), key.to_string())) {
+        Some(held) if Some(held.token.as_str()) == token => Ok(held),
+        Some(held) => Err(locked(&held.lease)),
+        None => Err(AppError::LeaseNotFound {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        }),
+    }
+}
+
+fn locked(lease: &ObjectLease) -> AppError {
+    AppError::ObjectLocked {
+        bucket: lease.bucket.clone(),
+        key: lease.key.clone(),
+        until: lease.expires_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::*;
+    use crate::clock::{Clock, SequentialIds};
+    use crate::testing::{body_string, TestServer};
+
+    /// A clock the test moves by hand
+    #[derive(Clone)]
+    struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+    impl ManualClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn table(path: Option<PathBuf>) -> (LockTable, ManualClock) {
+        let clock = ManualClock(Arc::new(Mutex::new("2026-03-01T12:00:00Z".parse().unwrap())));
+        let sources = Sources::new(clock.clone(), SequentialIds::default());
+        (LockTable::new(path, sources), clock)
+    }
+
+    #[test]
+    fn one_holder_at_a_time() {
+        let (locks, _) = table(None);
+        let held = locks.acquire("jobs", "queue.json", Some("worker-a".to_string()), 60).unwrap();
+        let err = locks.acquire("jobs", "queue.json", None, 60).unwrap_err();
+        assert!(matches!(err, AppError::ObjectLocked { .. }));
+        assert!(locks.acquire("jobs", "other.json", None, 60).is_ok());
+
+        assert!(matches!(locks.release("jobs", "queue.json", Some("guess")), Err(AppError::ObjectLocked { .. })));
+        assert!(locks.check_write("jobs", "queue.json", None).is_err());
+        assert!(locks.check_write("jobs", "queue.json", Some(&held.token)).is_ok());
+        locks.release("jobs", "queue.json", Some(&held.token)).unwrap();
+        assert!(matches!(locks.release("jobs", "queue.json", Some(&held.token)), Err(AppError::LeaseNotFound { .. })));
+        assert!(locks.check_write("jobs", "queue.json", None).is_ok());
+        assert!(locks.acquire("jobs", "queue.json", None, 60).is_ok());
+    }
+
+    #[test]
+    fn leases_expire_unless_refreshed() {
+        let (locks, clock) = table(None);
+        let held = locks.acquire("jobs", "queue.json", None, 30).unwrap();
+        clock.advance(Duration::seconds(20));
+        let lease = locks.refresh("jobs", "queue.json", Some(&held.token), None).unwrap();
+        assert_eq!(lease.expires_at, clock.now() + Duration::seconds(30));
+        clock.advance(Duration::seconds(20));
+        assert!(locks.get("jobs", "queue.json").is_some());
+        let lease = locks.refresh("jobs", "queue.json", Some(&held.token), Some(5)).unwrap();
+        assert_eq!(lease.ttl_secs, 5);
+
+        clock.advance(Duration::seconds(5));
+        assert!(locks.get("jobs", "queue.json").is_none());
+        assert!(locks.list("jobs", "").is_empty());
+        assert!(locks.check_write("jobs", "queue.json", None).is_ok());
+        let err = locks.refresh("jobs", "queue.json", Some(&held.token), None).unwrap_err();
+        assert!(matches!(err, AppError::LeaseNotFound { .. }));
+        assert!(locks.acquire("jobs", "queue.json", None, 30).is_ok());
+    }
+
+    #[test]
+    fn saved_leases_outlive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".locks.json");
+        let (locks, _) = table(Some(path.clone()));
+        let held = locks.acquire("jobs", "queue.json", None, 60).unwrap();
+        locks.acquire("jobs", "short.json", None, 1).unwrap();
+        drop(locks);
+
+        let (locks, clock) = table(Some(path.clone()));
+        assert_eq!(locks.list("jobs", "").len(), 2);
+        clock.advance(Duration::seconds(1));
+        drop(locks);
+        let (locks, clock) = table(Some(path));
+        clock.advance(Duration::seconds(1));
+        let listed: Vec<String> = locks.list("jobs", "").into_iter().map(|l| l.key).collect();
+        assert_eq!(listed, ["queue.json"]);
+        assert!(locks.check_write("jobs", "queue.json", Some(&held.token)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn writes_to_a_leased_key_need_its_token() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("jobs", "local").unwrap();
+        let res = server.request("POST", "/api/locks/jobs/queue.json").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let acquired: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        let token = acquired["token"].as_str().unwrap().to_string();
+        let res = server.request("POST", "/api/locks/jobs/queue.json").await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+
+        let write = |method: &str, uri: &str, token: Option<&str>| {
+            let mut req = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                req = req.header("x-freebucket-lock-token", token);
+            }
+            req.body(Body::from("[]")).unwrap()
+        };
+        let res = server.send(write("PUT", "/s3/jobs/queue.json", None)).await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+        let res = server.send(write("PUT", "/s3/jobs/queue.json", Some(&token))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.send(write("DELETE", "/api/object/jobs/queue.json", Some("guess"))).await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+        assert!(server.state.storage.get_object("jobs", "queue.json").is_ok());
+
+        // Listings leave the token out
+        let listing = body_string(server.request("GET", "/api/locks/jobs").await).await;
+        assert!(listing.contains("\"key\":\"queue.json\""), "{}", listing);
+        assert!(!listing.contains(&token), "{}", listing);
+
+        let res = server.send(write("DELETE", "/api/locks/jobs/queue.json", Some(&token))).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = server.send(write("DELETE", "/api/object/jobs/queue.json", None)).await;
+        assert!(res.status().is_success());
+    }
+}
@@ -1,26 +1,126 @@
+mod clock;
 mod config;
+mod csrf;
+mod diff;
 mod error;
-mod models;
+mod exec_hooks;
+mod origin;
 mod storage;
 mod handlers;
 mod dashboard;
 mod cli;
+mod tasks;
+mod traffic;
+mod jobs;
+mod access_log;
+mod request_log;
+mod recording;
+mod upload_limit;
+mod idempotency;
+mod instance_lock;
+mod i18n;
+mod locks;
+mod shares;
+mod systemd;
+mod webdav;
+mod webhooks;
+mod scanner;
+mod reload;
+mod s3_mounts;
+mod s3_xml;
+mod website;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(test)]
+mod testing;
 
-use std::sync::Arc;
-use axum::Router;
-use axum::extract::DefaultBodyLimit;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use axum::{middleware, Router, ServiceExt};
+use axum::extract::Request;
+use axum::response::Response;
 use clap::Parser;
+use tower::Layer;
 use tower_http::cors::CorsLayer;
-use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::config::Config;
+use freebucket::{models, size};
+
+use crate::config::{Config, ConfigOverrides, DEFAULT_LOG_FILTER};
 use crate::storage::StorageEngine;
 use crate::cli::{Cli, Commands};
 
 pub struct AppState {
     pub storage: StorageEngine,
-    pub config: Config,
+    /// Read through `config()`; swapped whole by a reload
+    config: RwLock<Arc<Config>>,
+    pub config_overrides: ConfigOverrides,
+    pub log_filter: reload::LogFilter,
+    pub bulk_jobs: jobs::BulkJobs,
+    pub access_log: access_log::AccessLog,
+    /// Where S3 requests are recorded, with `--record-requests`
+    pub recorder: Option<Arc<recording::Recorder>>,
+    pub upload_limiter: upload_limit::UploadLimiter,
+    pub idempotency: idempotency::IdempotencyCache,
+    pub locks: locks::LockTable,
+    pub shares: shares::ShareTable,
+    pub s3_legacy: s3_mounts::LegacyUses,
+    pub exec_hooks: exec_hooks::ExecHookRunner,
+    /// Latest background self-test, served by `/readyz`
+    pub readiness: Mutex<Option<models::SelfTestReport>>,
+    pub csrf: csrf::CsrfToken,
+}
+
+impl AppState {
+    /// Everything the server shares between requests, around an opened
+    /// storage engine
+    pub fn new(
+        config: Config,
+        overrides: ConfigOverrides,
+        storage: StorageEngine,
+        log_filter: reload::LogFilter,
+        recorder: Option<Arc<recording::Recorder>>,
+        sources: clock::Sources,
+    ) -> Self {
+        let data_dir = std::path::Path::new(&config.data_dir);
+        AppState {
+            bulk_jobs: jobs::BulkJobs::new(sources.clone()),
+            access_log: Default::default(),
+            recorder,
+            upload_limiter: upload_limit::UploadLimiter::new(
+                config.max_concurrent_uploads,
+                std::time::Duration::from_secs(config.upload_wait_secs),
+            ),
+            idempotency: idempotency::IdempotencyCache::new(
+                config.idempotency_cache_size,
+                std::time::Duration::from_secs(config.idempotency_ttl_secs),
+                config.idempotency_spill.then(|| data_dir.join(".idempotency")),
+                sources.clone(),
+            ),
+            locks: locks::LockTable::new(config.lock_persist.then(|| data_dir.join(".locks.json")), sources.clone()),
+            shares: shares::ShareTable::new(data_dir.join(".shares.json"), sources),
+            s3_legacy: Default::default(),
+            exec_hooks: exec_hooks::ExecHookRunner::new(config.exec_hook_workers),
+            readiness: Default::default(),
+            csrf: csrf::CsrfToken::new(),
+            storage,
+            config: RwLock::new(Arc::new(config)),
+            config_overrides: overrides,
+            log_filter,
+        }
+    }
+
+    /// The current configuration. Hold on to it for the length of one
+    /// request or job, so a reload midway doesn't mix old and new settings.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Install a reloaded configuration for everything that reads it next
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
 }
 
 #[tokio::main]
@@ -39,52 +139,110 @@ async fn main() {
 }
 
 async fn start_server(cli: Cli) {
-    // Initialize tracing
+    let mut config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Cannot load the configuration: {}", e);
+        std::process::exit(1);
+    });
+
+    // Initialize tracing; a config reload can swap the filter
+    let filter = tracing_subscriber::EnvFilter::try_new(&config.log_filter)
+        .unwrap_or_else(|_| DEFAULT_LOG_FILTER.into());
+    let (filter, log_filter) = tracing_subscriber::reload::Layer::new(filter);
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "freebucket=info,tower_http=info".into()),
-        )
+        .with(filter)
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let mut config = Config::default();
-
     // Override from CLI args if serve subcommand
-    if let Some(Commands::Serve { host, port }) = &cli.command {
-        config.host = host.clone();
-        config.port = *port;
+    let mut overrides = ConfigOverrides {
+        data_dir: cli.data_dir.clone(),
+        ..Default::default()
+    };
+    let mut manifest = None;
+    if let Some(Commands::Serve {
+        host,
+        port,
+        i_know_this_is_open,
+        apply,
+        init_manifest,
+        grpc_port,
+        record_requests,
+        record_force,
+    }) = &cli.command
+    {
+        overrides.host = Some(host.clone());
+        overrides.port = Some(*port);
+        overrides.allow_open_bind = *i_know_this_is_open;
+        // An init manifest only fills in what is missing, so a restarted
+        // container keeps the objects it changed
+        manifest = apply
+            .clone()
+            .map(|file| (file, false))
+            .or_else(|| init_manifest.clone().map(|file| (file, true)));
+        overrides.grpc_port = *grpc_port;
+        overrides.record_requests = record_requests.clone();
+        overrides.record_force = *record_force;
+    }
+
+    // A socket from systemd decides the address, so the exposure check and
+    // everything that reports the address go by it
+    let activated = systemd::activated_listener();
+    if let Some(addr) = activated.as_ref().and_then(|l| l.local_addr().ok()) {
+        overrides.host = Some(addr.ip().to_string());
+        overrides.port = Some(addr.port());
     }
-    if let Some(dir) = cli.data_dir {
-        config.data_dir = dir;
+    overrides.apply(&mut config);
+    size::set_units(config.size_units);
+
+    if config.is_exposed() {
+        confirm_open_bind(&config);
     }
 
-    let storage = StorageEngine::new(&config.data_dir).expect("Failed to initialize storage engine");
+    let recorder = config.record_requests.as_deref().map(|dir| open_recorder(&config, dir));
+
+    // Held until the server exits
+    let _instance_lock = instance_lock::acquire(std::path::Path::new(&config.data_dir)).unwrap_or_else(|msg| {
+        tracing::error!("{}", msg);
+        std::process::exit(1);
+    });
+
+    let options = config.storage_options();
+    let sources = options.sources.clone();
+    let storage = StorageEngine::new(&config.data_dir, options).expect("Failed to initialize storage engine");
+
+    if let Some((file, keep_objects)) = &manifest {
+        if let Err(msg) = cli::apply_at_startup(&storage, file, &config.default_region, *keep_objects) {
+            tracing::error!("Cannot apply manifest {}: {}", file, msg);
+            std::process::exit(1);
+        }
+    }
+    if let Err(msg) = cli::create_default_buckets(&storage, &config.default_buckets, &config.default_region) {
+        tracing::error!("Cannot create default bucket {}", msg);
+        std::process::exit(1);
+    }
 
     tracing::info!("Storage directory: {}", config.data_dir);
     tracing::info!("Starting FreeBucket on http://{}:{}", config.host, config.port);
 
-    let state = Arc::new(AppState { storage, config: config.clone() });
+    let state = Arc::new(AppState::new(config.clone(), overrides, storage, log_filter, recorder, sources));
+    tasks::spawn_background_tasks(state.clone());
+    reload::spawn_sighup_listener(state.clone());
 
-    let app = Router::new()
-        // Dashboard routes (web UI)
-        .merge(dashboard::routes())
-        // API routes (nestable, no wildcards)
-        .nest("/api", handlers::api_routes())
-        // API wildcard routes (must be at top level)
-        .merge(handlers::api_wildcard_routes())
-        // S3-compatible routes (no nesting needed)
-        .merge(handlers::s3_routes())
-        .merge(handlers::s3_wildcard_routes())
-        .layer(DefaultBodyLimit::max(config.max_upload_size))
-        .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+    let app = app(&state);
 
     let addr = format!("{}:{}", config.host, config.port);
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .expect("Failed to bind to address");
+    let listener = match activated {
+        Some(listener) => {
+            tracing::info!("Using the socket passed by systemd");
+            listener
+                .set_nonblocking(true)
+                .and_then(|_| tokio::net::TcpListener::from_std(listener))
+                .expect("Failed to use the socket passed by systemd")
+        }
+        None => tokio::net::TcpListener::bind(&addr)
+            .await
+            .expect("Failed to bind to address"),
+    };
 
     println!(r#"
     ╔═══════════════════════════════════════════════════════╗
@@ -105,5 +263,192 @@ async fn start_server(cli: Cli) {
     ╚═══════════════════════════════════════════════════════╝
     "#, &addr, format!("{}/api", &addr));
 
-    axum::serve(listener, app).await.unwrap();
+    // gRPC stops along with HTTP: dropping `stop` wakes its shutdown
+    let (stop, mut stopped) = tokio::sync::watch::channel(());
+    let grpc = spawn_grpc(&state, async move {
+        let _ = stopped.changed().await;
+    });
+
+    systemd::notify_ready();
+    axum::serve(
+        listener,
+        ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app),
+    )
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            drop(stop);
+        })
+        .await
+        .unwrap();
+    if let Some(grpc) = grpc {
+        let _ = grpc.await;
+    }
+    // Leaves the next start nothing to replay
+    state.storage.compact_stats_journals();
+}
+
+/// The HTTP service: every route with its middleware, behind the URI
+/// rewrites that have to run before routing
+fn app(
+    state: &Arc<AppState>,
+) -> impl tower::Service<Request, Response = Response, Error = Infallible, Future = impl Send> + Clone + Send + 'static {
+    let region_header = middleware::from_fn_with_state(state.clone(), handlers::bucket_region_header);
+    let subresources = middleware::from_fn(handlers::s3_subresources);
+    let access_log = middleware::from_fn_with_state(state.clone(), access_log::record_access);
+    let recording = middleware::from_fn_with_state(state.clone(), recording::record_exchange);
+    let request_log = middleware::from_fn_with_state(state.clone(), request_log::log_request);
+    let upload_limit = middleware::from_fn_with_state(state.clone(), upload_limit::limit_uploads);
+    let idempotency = middleware::from_fn_with_state(state.clone(), idempotency::idempotent_requests);
+    let body_limit = middleware::from_fn_with_state(state.clone(), upload_limit::limit_body_size);
+    let dav_options = middleware::from_fn_with_state(state.clone(), webdav::advertise);
+    let traffic = middleware::from_fn_with_state(state.clone(), traffic::count_traffic);
+    let csrf = middleware::from_fn_with_state(state.clone(), csrf::check_csrf);
+    let mut app = Router::new()
+        // Dashboard routes (web UI)
+        .merge(dashboard::routes())
+        // API routes (nestable, no wildcards)
+        .nest("/api", handlers::api_routes())
+        .merge(handlers::probe_routes())
+        // API wildcard routes (must be at top level)
+        .merge(handlers::api_wildcard_routes())
+        // S3-compatible routes (no nesting needed)
+        .merge(
+            handlers::s3_routes()
+                .route_layer(subresources.clone())
+                .route_layer(region_header.clone())
+                .route_layer(access_log.clone())
+                .route_layer(recording.clone()),
+        )
+        .merge(
+            handlers::s3_wildcard_routes()
+                .route_layer(subresources)
+                .route_layer(region_header)
+                .route_layer(access_log)
+                .route_layer(recording),
+        );
+    app = app.merge(website::routes());
+    if state.config().webdav {
+        app = app.merge(webdav::routes());
+    }
+    let app = app
+        // JSON (XML under /s3) errors for unknown /api and /s3 paths
+        .fallback(handlers::unknown_route)
+        .layer(idempotency)
+        .layer(upload_limit)
+        .layer(body_limit)
+        .layer(csrf)
+        .layer(middleware::from_fn(s3_xml::xml_errors))
+        .layer(CorsLayer::permissive())
+        .layer(dav_options)
+        .layer(traffic)
+        .layer(middleware::from_fn(webhooks::event_source))
+        .layer(request_log)
+        .with_state(state.clone());
+    // Mounts and host-based bucket addressing have to rewrite the URI
+    // before routing; the virtual host runs first and takes precedence
+    let app = middleware::from_fn_with_state(state.clone(), s3_mounts::rewrite_mounts).layer(app);
+    middleware::from_fn_with_state(state.clone(), handlers::virtual_host_rewrite).layer(app)
+}
+
+/// Serve gRPC next to HTTP when a port is configured
+#[cfg(feature = "grpc")]
+fn spawn_grpc(
+    state: &Arc<AppState>,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let port = state.config().grpc_port?;
+    Some(grpc::spawn(state.clone(), port, shutdown))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn spawn_grpc(
+    state: &Arc<AppState>,
+    _shutdown: impl std::future::Future<Output = ()>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    if state.config().grpc_port.is_some() {
+        tracing::warn!("Ignoring the gRPC port: this build lacks the grpc feature");
+    }
+    None
+}
+
+/// Resolves on Ctrl+C or SIGTERM, after which in-flight requests finish
+/// before the server exits
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down");
+    systemd::notify_stopping();
+}
+
+/// Open the request recording directory. Recordings hold what clients
+/// sent, so with authentication configured they are only made when forced.
+fn open_recorder(config: &Config, dir: &str) -> Arc<recording::Recorder> {
+    if config.auth_configured() && !config.record_force {
+        tracing::error!(
+            "Refusing to record requests while authentication is configured; pass --record-force or set FREEBUCKET_RECORD_FORCE=1 to record anyway"
+        );
+        std::process::exit(1);
+    }
+    let recorder = recording::Recorder::open(std::path::Path::new(dir), config.record_max_body, config.record_max_total)
+        .unwrap_or_else(|e| {
+            tracing::error!("Cannot record requests to {}: {}", dir, e);
+            std::process::exit(1);
+        });
+    tracing::warn!(
+        "Recording S3 requests and responses to {}; bodies are kept up to {} bytes each",
+        recorder.dir().display(),
+        config.record_max_body
+    );
+    Arc::new(recorder)
+}
+
+/// Warn loudly before exposing the unauthenticated API beyond this machine.
+/// Interactive terminals must confirm; non-interactive starts are refused
+/// unless the exposure was acknowledged up front.
+fn confirm_open_bind(config: &Config) {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    eprintln!();
+    eprintln!("  ⚠  WARNING: FreeBucket is about to listen on {}:{}", config.host, config.port);
+    eprintln!("     No authentication is configured. Anyone who can reach this address");
+    eprintln!("     can read, modify, and delete ALL stored data.");
+    eprintln!();
+
+    if config.allow_open_bind {
+        tracing::warn!(
+            "Serving unauthenticated API on non-loopback address {} (acknowledged)",
+            config.host
+        );
+        return;
+    }
+
+    if !std::io::stdin().is_terminal() {
+        eprintln!("     Refusing to start in a non-interactive session.");
+        eprintln!("     Pass --i-know-this-is-open or set FREEBUCKET_ALLOW_OPEN=1 to continue.");
+        std::process::exit(1);
+    }
+
+    eprint!("     Type 'yes' to continue: ");
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut answer);
+    if answer.trim() != "yes" {
+        eprintln!("     Aborted.");
+        std::process::exit(1);
+    }
 }
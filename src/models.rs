@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 /// Represents a storage bucket
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,6 +10,409 @@ pub struct Bucket {
     pub region: String,
     pub object_count: u64,
     pub total_size: u64,
+    /// Bytes the payloads take on disk, below `total_size` where they have
+    /// holes; 0 for buckets not recounted since this was added
+    #[serde(default)]
+    pub allocated_size: u64,
+    /// Bumped on every change to the bucket, for validating cached listings
+    #[serde(default)]
+    pub revision: u64,
+    /// Keep every version of each object instead of overwriting in place
+    #[serde(default)]
+    pub versioning: bool,
+    /// Accept uploads through the public `/inbox/<bucket>` page
+    #[serde(default)]
+    pub inbox: bool,
+    #[serde(default)]
+    pub inbox_limits: InboxLimits,
+    /// Serve `<key>.br` / `<key>.gz` siblings to clients that accept them
+    #[serde(default)]
+    pub precompressed: bool,
+    /// What a write to a key that already holds an object does
+    #[serde(default)]
+    pub overwrite_policy: OverwritePolicy,
+    /// Most objects the bucket may hold; writes of new keys past it fail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_objects: Option<u64>,
+    /// Receivers of the bucket's object events
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+    /// Local commands run on the bucket's object events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec_hooks: Option<ExecHooks>,
+    /// Where S3-style server access logs for this bucket are delivered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<LoggingConfig>,
+    /// Set at load time for buckets created before their name was reserved;
+    /// never read back from disk
+    #[serde(default, skip_deserializing, skip_serializing_if = "std::ops::Not::not")]
+    pub reserved: bool,
+    /// Storage volume holding the whole bucket; `None` is the data dir
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<String>,
+    /// Hold uploads back until a scan approves them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<QuarantineConfig>,
+    /// Remote S3 bucket that keys missing here are fetched from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<OriginConfig>,
+    /// Usage levels that raise an alert
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alerts: Option<AlertThresholds>,
+    /// Set when the metadata file was unreadable and this was salvaged from
+    /// it, until an admin confirms the result with `fsck --confirm-metadata`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_recovered: Option<MetadataRecovery>,
+}
+
+/// How a bucket's unreadable metadata was recovered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataRecovery {
+    pub recovered_at: DateTime<Utc>,
+    /// Why the file couldn't be read
+    pub error: String,
+    /// The unreadable file, kept byte for byte beside the new one; `None`
+    /// if it couldn't be kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub corrupt_file: Option<String>,
+    /// Settings read back from the file
+    #[serde(default)]
+    pub salvaged: Vec<String>,
+    /// Settings found in the file but unusable, now at their defaults
+    #[serde(default)]
+    pub discarded: Vec<String>,
+    /// The object count and size were recounted from the stored files
+    #[serde(default)]
+    pub recounted: bool,
+}
+
+/// Usage levels at which a bucket raises an alert. Each one set is checked
+/// on its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertThresholds {
+    /// Percent of `max_objects`; not checked while the bucket has no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_percent: Option<u8>,
+    /// Total size in bytes; also read from sizes such as `"10GiB"`
+    #[serde(
+        default,
+        deserialize_with = "crate::size::deserialize_optional_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub objects: Option<u64>,
+}
+
+impl AlertThresholds {
+    pub fn is_empty(&self) -> bool {
+        self.quota_percent.is_none() && self.bytes.is_none() && self.objects.is_none()
+    }
+}
+
+/// What a usage alert measures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    /// Objects, against a percentage of the bucket's object limit
+    Quota,
+    Bytes,
+    Objects,
+}
+
+impl AlertMetric {
+    pub fn name(self) -> &'static str {
+        match self {
+            AlertMetric::Quota => "quota",
+            AlertMetric::Bytes => "bytes",
+            AlertMetric::Objects => "objects",
+        }
+    }
+}
+
+/// A bucket whose usage is past one of its alert thresholds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageAlert {
+    pub bucket: String,
+    pub metric: AlertMetric,
+    /// Usage as of the latest check: objects for `quota` and `objects`,
+    /// bytes for `bytes`
+    pub value: u64,
+    /// The level crossed, in the same unit
+    pub threshold: u64,
+    pub raised_at: DateTime<Utc>,
+}
+
+/// An HTTP endpoint that object events are POSTed to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Key for the HMAC-SHA256 signature in `X-FreeBucket-Signature`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// Body of a webhook request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookFormat {
+    /// One flat JSON object per event
+    #[default]
+    Freebucket,
+    /// A `{"Records": [...]}` S3 event notification
+    S3Event,
+}
+
+/// Local commands a bucket runs on its object events
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecHooks {
+    pub commands: Vec<ExecHook>,
+    /// Hooks of this bucket running at once; the rest wait their turn
+    #[serde(default = "default_max_running_hooks")]
+    pub max_running: usize,
+}
+
+fn default_max_running_hooks() -> usize {
+    1
+}
+
+/// One command run for each matching object event. The program must be on
+/// the server's `FREEBUCKET_EXEC_HOOK_ALLOW` list; it is run directly, not
+/// through a shell, with `{bucket}`, `{key}`, `{size}`, `{content_type}`,
+/// `{event}`, and `{path}` replaced in each argument.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecHook {
+    /// Absolute path of the program, then its arguments
+    pub command: Vec<String>,
+    #[serde(default = "default_hook_events")]
+    pub events: Vec<HookTrigger>,
+    /// Runs taking longer are killed
+    #[serde(default = "default_hook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_hook_events() -> Vec<HookTrigger> {
+    vec![HookTrigger::Created]
+}
+
+fn default_hook_timeout_secs() -> u64 {
+    60
+}
+
+/// Object events an exec hook runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookTrigger {
+    /// `ObjectCreated:*`: puts, copies, and completed multipart uploads
+    Created,
+    /// `ObjectRemoved:*`: deletes and delete markers
+    Removed,
+    /// `UsageAlert:*`: a usage alert raised or cleared
+    Alert,
+}
+
+/// How one exec hook run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookOutcome {
+    /// Exited with status 0
+    Succeeded,
+    /// Exited with another status or was killed by a signal
+    Failed,
+    TimedOut,
+    /// Never started: the program isn't allowed or couldn't be run, or
+    /// the object was gone
+    Skipped,
+}
+
+/// One run of an exec hook, kept for `GET /api/buckets/{bucket}/hook-runs`
+#[derive(Debug, Clone, Serialize)]
+pub struct HookRun {
+    pub bucket: String,
+    pub key: String,
+    pub event: String,
+    pub program: String,
+    pub outcome: HookOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Why a run was skipped or could not finish
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Output, cut to the first few KB of each stream
+    pub stdout: String,
+    pub stderr: String,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+}
+
+/// Server access logging target, as in S3's `PutBucketLogging`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    pub target_bucket: String,
+    #[serde(default)]
+    pub target_prefix: String,
+}
+
+/// A remote S3 bucket a bucket acts as a read-through cache of
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OriginConfig {
+    /// `http://host:port` or `https://host`, followed by the path the S3
+    /// API is served under if it isn't the root
+    pub endpoint: String,
+    pub bucket: String,
+    /// Put in front of every key on the origin
+    #[serde(default)]
+    pub prefix: String,
+    /// Requests are signed (SigV4) when both keys are set, and sent
+    /// unsigned otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_access_key: Option<String>,
+    #[serde(default = "default_origin_region")]
+    pub region: String,
+    /// Check a cached copy against the origin's ETag once it was fetched or
+    /// checked this long ago; never when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revalidate_secs: Option<u64>,
+    /// Send PUTs and DELETEs to the origin before applying them here;
+    /// otherwise they only change the local copy
+    #[serde(default)]
+    pub write_through: bool,
+}
+
+fn default_origin_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// How a bucket scans uploads before publishing them. With neither a
+/// command nor a webhook, uploads wait for manual approval.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuarantineConfig {
+    /// Run through `sh -c` for each upload, with `{path}` standing for the
+    /// payload. Exit status 0 approves, 1 rejects with the output as the
+    /// reason, anything else is a failed scan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// POSTed the upload's details; answers `{"approved": bool, "reason": ...}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+    /// Scans taking longer count as failed and are retried
+    #[serde(default = "default_scan_timeout_secs")]
+    pub timeout_secs: u64,
+    /// What a read of a key whose upload is still pending answers
+    #[serde(default)]
+    pub pending_status: PendingStatus,
+}
+
+fn default_scan_timeout_secs() -> u64 {
+    60
+}
+
+/// Answer to reads of an object that is waiting for its scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingStatus {
+    /// 404, as if the object did not exist
+    #[default]
+    NotFound,
+    /// 423 Locked, so clients can tell it is on its way
+    Locked,
+}
+
+/// An upload held in a bucket's quarantine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedObject {
+    pub id: String,
+    pub status: QuarantineStatus,
+    /// The object as it is published once approved
+    pub object: ObjectMeta,
+    pub received_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_at: Option<DateTime<Utc>>,
+    /// Why the upload was rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Scans that ended without a verdict
+    #[serde(default)]
+    pub failed_scans: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Earliest time of the next scan after a failed one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+}
+
+/// Where a quarantined upload stands. Approved uploads are published and
+/// leave the quarantine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuarantineStatus {
+    Pending,
+    Rejected,
+}
+
+/// Filters for listing quarantined uploads
+#[derive(Debug, Deserialize)]
+pub struct QuarantineQuery {
+    pub bucket: Option<String>,
+    pub status: Option<QuarantineStatus>,
+}
+
+/// Body of a manual rejection
+#[derive(Debug, Default, Deserialize)]
+pub struct RejectUploadRequest {
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Whether writes may replace an existing object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverwritePolicy {
+    /// Replace it, as S3 does
+    #[default]
+    Allow,
+    /// Refuse with `ObjectAlreadyExists`
+    Deny,
+    /// Replace it, but only while versioning keeps the old content
+    Version,
+}
+
+impl OverwritePolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            OverwritePolicy::Allow => "allow",
+            OverwritePolicy::Deny => "deny",
+            OverwritePolicy::Version => "version",
+        }
+    }
+}
+
+/// Constraints on uploads received through a bucket's inbox page
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboxLimits {
+    /// Largest accepted file in bytes; also read from sizes such as `"10MiB"`
+    #[serde(default, deserialize_with = "crate::size::deserialize_optional_size")]
+    pub max_size: Option<u64>,
+    /// Accepted content types; `image/*` matches a whole family. Empty accepts anything.
+    #[serde(default)]
+    pub content_types: Vec<String>,
+}
+
+/// ETags are stored as bare hex and quoted only in HTTP headers. Metadata
+/// written by earlier versions kept the quotes; they are dropped on load.
+fn unquoted_etag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let etag = String::deserialize(deserializer)?;
+    Ok(etag.trim_matches('"').to_string())
 }
 
 /// Represents an object stored in a bucket
@@ -19,25 +422,191 @@ pub struct ObjectMeta {
     pub bucket: String,
     pub size: u64,
     pub content_type: String,
+    /// SHA-256 of the content in hex, without quotes
+    #[serde(deserialize_with = "unquoted_etag")]
     pub etag: String,
     pub last_modified: DateTime<Utc>,
     pub metadata: HashMap<String, String>,
+    /// Parts the object was assembled from, for multipart uploads
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ObjectPart>,
+    /// Checksum requested by the client when the object was written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<ObjectChecksum>,
+    /// Name of the file as uploaded, for keys that are hashes or ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_filename: Option<String>,
+    /// Key (`/other/key`) or URL a website request for this object is
+    /// redirected to, from `x-amz-website-redirect-location`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website_redirect_location: Option<String>,
+    /// Modification time of the file the object was uploaded from, sent as
+    /// `X-Freebucket-Last-Modified` or rclone's `x-amz-meta-mtime`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mtime: Option<DateTime<Utc>>,
+    /// The ETag is a placeholder until the payload is hashed in the
+    /// background (`FREEBUCKET_HASH_MODE=deferred`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub etag_provisional: bool,
+    /// Set on copies fetched from the bucket's origin; any local write
+    /// drops it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<ObjectOrigin>,
+    /// Bytes the payload took on disk when the metadata was last written,
+    /// less than `size` where zero-filled ranges were left as holes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allocated_size: Option<u64>,
+}
+
+/// The origin's side of a cached object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectOrigin {
+    /// The origin's ETag, without quotes
+    pub etag: String,
+    /// When the copy was fetched or last found current
+    pub checked_at: DateTime<Utc>,
+}
+
+impl ObjectMeta {
+    /// When the object was last modified as clients see it: the source's
+    /// time if the upload carried one, otherwise when it was written here
+    pub fn modified(&self) -> DateTime<Utc> {
+        self.source_mtime.unwrap_or(self.last_modified)
+    }
+}
+
+/// Serialized names of `ObjectMeta`'s fields, for projections
+pub const OBJECT_META_FIELDS: &[&str] = &[
+    "key",
+    "bucket",
+    "size",
+    "content_type",
+    "etag",
+    "last_modified",
+    "metadata",
+    "parts",
+    "checksum",
+    "original_filename",
+    "website_redirect_location",
+    "source_mtime",
+    "etag_provisional",
+    "origin",
+    "allocated_size",
+];
+
+/// One part of an object completed from a multipart upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectPart {
+    pub part_number: u32,
+    pub size: u64,
+    #[serde(deserialize_with = "unquoted_etag")]
+    pub etag: String,
+    /// Base64 checksum of the part, in the object's checksum algorithm
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+/// Checksum algorithms accepted in `x-amz-checksum-*` headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    Crc32c,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: [ChecksumAlgorithm; 4] = [
+        ChecksumAlgorithm::Crc32,
+        ChecksumAlgorithm::Crc32c,
+        ChecksumAlgorithm::Sha1,
+        ChecksumAlgorithm::Sha256,
+    ];
+
+    /// Parse an algorithm name as used in `x-amz-sdk-checksum-algorithm`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|a| a.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "CRC32",
+            ChecksumAlgorithm::Crc32c => "CRC32C",
+            ChecksumAlgorithm::Sha1 => "SHA1",
+            ChecksumAlgorithm::Sha256 => "SHA256",
+        }
+    }
+
+    /// Request/response header carrying a checksum of this algorithm
+    pub fn header_name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "x-amz-checksum-crc32",
+            ChecksumAlgorithm::Crc32c => "x-amz-checksum-crc32c",
+            ChecksumAlgorithm::Sha1 => "x-amz-checksum-sha1",
+            ChecksumAlgorithm::Sha256 => "x-amz-checksum-sha256",
+        }
+    }
+
+    /// Only the CRCs can be combined into a full-object multipart checksum
+    pub fn supports_full_object_multipart(self) -> bool {
+        matches!(self, ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::Crc32c)
+    }
+}
+
+/// Whether a checksum covers the whole object or is a checksum of the
+/// part checksums of a multipart upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ChecksumType {
+    FullObject,
+    Composite,
+}
+
+/// A stored object checksum; `value` is base64, with a `-<parts>` suffix
+/// for composite checksums
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub checksum_type: ChecksumType,
+    pub value: String,
 }
 
 /// Request to create a new bucket
 #[derive(Debug, Deserialize)]
 pub struct CreateBucketRequest {
     pub name: String,
-    #[serde(default = "default_region")]
-    pub region: String,
+    /// Falls back to the server's configured default region
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Storage volume to place the bucket on; the data dir by default
+    #[serde(default)]
+    pub volume: Option<String>,
 }
 
-fn default_region() -> String {
-    "local".to_string()
+/// Changes to an existing bucket's settings; omitted fields stay as they are
+#[derive(Debug, Deserialize)]
+pub struct BucketSettingsRequest {
+    pub region: Option<String>,
+    pub versioning: Option<bool>,
+    pub inbox: Option<bool>,
+    pub inbox_limits: Option<InboxLimits>,
+    pub precompressed: Option<bool>,
+    pub overwrite_policy: Option<OverwritePolicy>,
+    /// 0 removes the limit
+    pub max_objects: Option<u64>,
+    /// Replaces the bucket's webhooks; `[]` removes them all
+    pub webhooks: Option<Vec<Webhook>>,
+    /// Replaces the bucket's exec hooks; no `commands` removes them
+    pub exec_hooks: Option<ExecHooks>,
+    /// Replaces the bucket's alert thresholds; `{}` removes them
+    pub alerts: Option<AlertThresholds>,
 }
 
 /// Response for listing objects  
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ListObjectsResponse {
     pub bucket: String,
     pub prefix: String,
@@ -45,6 +614,139 @@ pub struct ListObjectsResponse {
     pub common_prefixes: Vec<String>,
     pub is_truncated: bool,
     pub max_keys: u32,
+    /// Resumes a sorted listing after its last object; set when truncated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_continuation_token: Option<String>,
+    /// End of a modification-time window, fixed when its first page was listed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_before: Option<DateTime<Utc>>,
+}
+
+/// Objects modified at or after `after` and before `before`. Both ends are
+/// optional; a window with neither lists everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModifiedWindow {
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl ModifiedWindow {
+    pub fn is_set(&self) -> bool {
+        self.after.is_some() || self.before.is_some()
+    }
+}
+
+/// Which object fields the REST listing returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFields {
+    #[default]
+    Full,
+    /// Just what a list view shows: key, size, and modification time
+    Slim,
+}
+
+/// Order of a REST object listing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListSort {
+    #[default]
+    Key,
+    LastModified,
+    Size,
+    LastAccessed,
+    Downloads,
+}
+
+impl ListSort {
+    /// Direction used when the query gives none: newest and largest first,
+    /// everything else ascending
+    pub fn default_order(self) -> SortOrder {
+        match self {
+            ListSort::LastModified | ListSort::Size => SortOrder::Desc,
+            _ => SortOrder::Asc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// How a listing is ordered, and which modification times it covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListOrder {
+    pub sort: ListSort,
+    pub order: SortOrder,
+    pub window: ModifiedWindow,
+}
+
+/// How often an object has been read, when access tracking is on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectAccess {
+    pub downloads: u64,
+    pub last_accessed: Option<DateTime<Utc>>,
+}
+
+/// An object's metadata with its access stats, for full listings and the
+/// details endpoint
+#[derive(Debug, Serialize)]
+pub struct TrackedObject<'a> {
+    #[serde(flatten)]
+    pub meta: &'a ObjectMeta,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub access: Option<ObjectAccess>,
+}
+
+/// Borrowed slim projection of an `ObjectMeta`
+#[derive(Debug, Serialize)]
+pub struct ObjectSummary<'a> {
+    pub key: &'a str,
+    pub size: u64,
+    pub last_modified: &'a DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_filename: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub website_redirect_location: Option<&'a str>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub access: Option<ObjectAccess>,
+}
+
+impl<'a> From<TrackedObject<'a>> for ObjectSummary<'a> {
+    fn from(object: TrackedObject<'a>) -> Self {
+        let meta = object.meta;
+        Self {
+            key: &meta.key,
+            size: meta.size,
+            last_modified: &meta.last_modified,
+            original_filename: meta.original_filename.as_deref(),
+            website_redirect_location: meta.website_redirect_location.as_deref(),
+            access: object.access,
+        }
+    }
+}
+
+/// REST object listing, serialized from borrowed entries so a projection
+/// never clones the listing
+#[derive(Debug, Serialize)]
+pub struct ObjectListPage<'a, T> {
+    pub bucket: &'a str,
+    pub prefix: &'a str,
+    pub objects: Vec<T>,
+    pub common_prefixes: &'a [String],
+    pub is_truncated: bool,
+    pub max_keys: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_continuation_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_before: Option<DateTime<Utc>>,
+    /// Set when entries were dropped to keep the response under the size cap
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub size_limited: bool,
 }
 
 /// Query params for listing objects
@@ -53,22 +755,1263 @@ pub struct ListObjectsQuery {
     pub prefix: Option<String>,
     pub delimiter: Option<String>,
     pub max_keys: Option<u32>,
-    #[allow(dead_code)]
+    /// `slim` for the list-view projection; only the REST API reads this
+    #[serde(default)]
+    pub fields: ListFields,
+    /// `last_modified` or `size` to order by the object index,
+    /// `last_accessed` or `downloads` to order by access stats; REST only
+    #[serde(default)]
+    pub sort: ListSort,
+    /// Defaults to `desc` for `last_modified` and `size`, `asc` otherwise
+    pub order: Option<SortOrder>,
+    /// `next_continuation_token` of the previous page; REST only
     pub continuation_token: Option<String>,
+    /// Only objects modified at or after this time
+    pub modified_after: Option<DateTime<Utc>>,
+    /// Only objects modified before this time
+    pub modified_before: Option<DateTime<Utc>>,
+}
+
+impl ListObjectsQuery {
+    pub fn window(&self) -> ModifiedWindow {
+        ModifiedWindow {
+            after: self.modified_after,
+            before: self.modified_before,
+        }
+    }
+}
+
+/// Query params for streaming a listing as NDJSON
+#[derive(Debug, Deserialize)]
+pub struct StreamObjectsQuery {
+    pub prefix: Option<String>,
+    /// Comma-separated `ObjectMeta` fields to keep, e.g. `key,size,etag`
+    pub fields: Option<String>,
+    /// Only objects modified at or after this time
+    pub modified_after: Option<DateTime<Utc>>,
+    /// Only objects modified before this time
+    pub modified_before: Option<DateTime<Utc>>,
+}
+
+/// Last record of a streamed listing
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ListingSummary {
+    pub count: u64,
+    pub bytes: u64,
+    /// End of the modification-time window the stream covered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_before: Option<DateTime<Utc>>,
+}
+
+/// One side of a diff: a bucket, or the keys under a prefix of one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiffSide {
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// Body of `POST /api/diff`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiffRequest {
+    pub left: DiffSide,
+    pub right: DiffSide,
+    /// Differences listed at most; the summary still counts all of them
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffChange {
+    /// Only on the right
+    Added,
+    /// Only on the left
+    Removed,
+    /// On both sides with different content
+    Changed,
+}
+
+/// A key that differs between the sides of a diff. Keys are relative to
+/// each side's prefix.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffEntry {
+    pub key: String,
+    pub change: DiffChange,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right_etag: Option<String>,
+}
+
+/// Counts of a diff. `hashed` is how many keys of equal size were compared
+/// by hashing their content, because an ETag couldn't be trusted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffSummary {
+    pub left_objects: u64,
+    pub right_objects: u64,
+    pub added: u64,
+    pub removed: u64,
+    pub changed: u64,
+    pub unchanged: u64,
+    pub hashed: u64,
+}
+
+impl DiffSummary {
+    pub fn differences(&self) -> u64 {
+        self.added + self.removed + self.changed
+    }
 }
 
-/// Response for listing buckets
 #[derive(Debug, Serialize)]
+pub struct DiffReport {
+    pub left: DiffSide,
+    pub right: DiffSide,
+    pub summary: DiffSummary,
+    pub differences: Vec<DiffEntry>,
+    /// More differences were found than `limit` lets through
+    pub truncated: bool,
+}
+
+/// The body of every error response; `error` and `code` hold the same
+/// error code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// Response for listing buckets
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ListBucketsResponse {
     pub buckets: Vec<Bucket>,
     pub owner: String,
 }
 
 /// Stats about storage usage
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct StorageStats {
     pub total_buckets: u64,
     pub total_objects: u64,
     pub total_size: u64,
     pub total_size_human: String,
+    /// Bytes on disk, below `total_size` when sparse payloads have holes
+    pub allocated_size: u64,
+    pub allocated_size_human: String,
+}
+
+/// Usage of a single bucket, as reported by `/api/stats/buckets`
+#[derive(Debug, Serialize)]
+pub struct BucketStats {
+    pub name: String,
+    pub object_count: u64,
+    pub total_size: u64,
+    pub total_size_human: String,
+    pub allocated_size: u64,
+    pub allocated_size_human: String,
+    /// Time of the newest write or delete (deletes are only tracked since
+    /// startup), never earlier than the bucket's creation
+    pub last_activity: DateTime<Utc>,
+}
+
+/// An object as listed in a bucket's recent activity
+#[derive(Debug, Serialize)]
+pub struct RecentObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: DateTime<Utc>,
+}
+
+/// A bucket with usage and activity, as returned by `GET /api/buckets/:bucket`.
+/// The stored bucket fields come first, so it is a superset of `Bucket`.
+#[derive(Debug, Serialize)]
+pub struct BucketDetails {
+    #[serde(flatten)]
+    pub bucket: Bucket,
+    pub total_size_human: String,
+    pub allocated_size_human: String,
+    /// Time of the newest write or delete, as in `BucketStats`
+    pub last_activity: DateTime<Utc>,
+    /// Most recently modified objects, newest first
+    pub recent_objects: Vec<RecentObject>,
+}
+
+/// Object count and size under a key prefix
+#[derive(Debug, Serialize)]
+pub struct PrefixStats {
+    pub bucket: String,
+    pub prefix: String,
+    pub object_count: u64,
+    pub total_size: u64,
+    pub total_size_human: String,
+}
+
+/// Outcome of a batch delete
+#[derive(Debug, Serialize)]
+pub struct DeleteObjectsResult {
+    pub deleted: Vec<String>,
+    pub errors: Vec<DeleteError>,
+}
+
+/// A single key that could not be deleted in a batch
+#[derive(Debug, Serialize)]
+pub struct DeleteError {
+    pub key: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// Query params for prefix-scoped operations
+#[derive(Debug, Deserialize)]
+pub struct PrefixQuery {
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// Where a piece of content is stored
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectLocation {
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+}
+
+/// Query params for the largest-objects report
+#[derive(Debug, Deserialize)]
+pub struct LargestObjectsQuery {
+    #[serde(default = "default_largest_limit")]
+    pub limit: usize,
+}
+
+fn default_largest_limit() -> usize {
+    20
+}
+
+/// Query params for searching a bucket's keys and original filenames
+#[derive(Debug, Deserialize)]
+pub struct SearchObjectsQuery {
+    pub q: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "default_search_limit")]
+    pub limit: usize,
+}
+
+fn default_search_limit() -> usize {
+    100
+}
+
+/// A set of objects with identical content
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub sha256: String,
+    pub size: u64,
+    pub wasted_bytes: u64,
+    pub objects: Vec<ObjectLocation>,
+}
+
+/// Pre-upload check: does this content already exist?
+#[derive(Debug, Deserialize)]
+pub struct CheckHashRequest {
+    pub sha256: String,
+    /// When set and the content exists, create this key as a server-side copy
+    pub key: Option<String>,
+}
+
+/// Response for a pre-upload hash check
+#[derive(Debug, Serialize)]
+pub struct CheckHashResponse {
+    pub exists: bool,
+    pub matches: Vec<ObjectLocation>,
+    pub created: Option<ObjectMeta>,
+}
+
+/// Whether one key of a bulk existence check holds an object
+#[derive(Debug, Serialize)]
+pub struct ObjectExistence {
+    pub key: String,
+    pub exists: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+}
+
+/// A point-in-time snapshot of a bucket's contents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub bucket: String,
+    pub created_at: DateTime<Utc>,
+    pub object_count: u64,
+    pub total_size: u64,
+}
+
+/// Body of `POST /api/admin/backup`
+#[derive(Debug, Deserialize)]
+pub struct BackupRequest {
+    /// Directory on the server to write the backup into
+    pub dir: String,
+}
+
+/// A bucket's stats at the moment a backup was cut
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupBucket {
+    pub name: String,
+    pub object_count: u64,
+    pub total_size: u64,
+}
+
+/// One file in a backup, by its path relative to the backup directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// `backup.json`, written last, so a backup without one is incomplete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    /// How long object writes were held off while the cut was taken
+    pub write_pause_ms: f64,
+    pub buckets: Vec<BackupBucket>,
+    pub files: Vec<BackupFile>,
+}
+
+/// A finished backup; the file list stays in its `backup.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupReport {
+    pub id: String,
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+    pub write_pause_ms: f64,
+    pub buckets: Vec<BackupBucket>,
+    pub file_count: usize,
+    pub total_size: u64,
+}
+
+/// A backup re-read against the checksums in its manifest
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupVerification {
+    pub checked: usize,
+    pub missing: Vec<String>,
+    pub mismatched: Vec<String>,
+}
+
+impl BackupVerification {
+    pub fn is_intact(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Query params for restoring a snapshot
+#[derive(Debug, Deserialize)]
+pub struct RestoreSnapshotQuery {
+    /// Must be true: restoring deletes objects created after the snapshot
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Output format for inventory reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InventoryFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl InventoryFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            InventoryFormat::Csv => "csv",
+            InventoryFormat::Json => "json",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            InventoryFormat::Csv => "text/csv",
+            InventoryFormat::Json => "application/json",
+        }
+    }
+}
+
+/// Request to generate an inventory report
+#[derive(Debug, Default, Deserialize)]
+pub struct InventoryRequest {
+    #[serde(default)]
+    pub format: InventoryFormat,
+    /// Store the report as an object in this bucket instead of returning it
+    pub destination_bucket: Option<String>,
+    #[serde(default = "default_inventory_prefix")]
+    pub destination_prefix: String,
+}
+
+/// Per-bucket daily inventory schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InventoryConfig {
+    #[serde(default)]
+    pub format: InventoryFormat,
+    pub destination_bucket: String,
+    #[serde(default = "default_inventory_prefix")]
+    pub destination_prefix: String,
+    #[serde(default)]
+    pub last_run: Option<DateTime<Utc>>,
+}
+
+fn default_inventory_prefix() -> String {
+    "inventory/".to_string()
+}
+
+/// Everything the dashboard redraws after a change, in one payload
+#[derive(Debug, Serialize)]
+pub struct DashboardData {
+    pub stats: StorageStats,
+    pub buckets: Vec<Bucket>,
+    /// Per-bucket usage, largest first
+    pub bucket_stats: Vec<BucketStats>,
+    /// Latest audit log entries, newest first
+    pub audit: Vec<AuditEntry>,
+    /// Usage alerts in effect
+    pub alerts: Vec<UsageAlert>,
+}
+
+/// State of the in-memory cache of small objects
+#[derive(Debug, Serialize)]
+pub struct ReadCacheStats {
+    pub enabled: bool,
+    /// Payload bytes it may hold, and the largest object it takes
+    pub capacity: u64,
+    pub max_object_size: u64,
+    pub objects: usize,
+    pub bytes: u64,
+    /// Object reads answered from memory, and ones that went to disk
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// What `POST /api/admin/cache/clear` dropped
+#[derive(Debug, Serialize)]
+pub struct ReadCacheCleared {
+    pub objects: usize,
+    pub bytes: u64,
+}
+
+/// Information about the running server
+#[derive(Debug, Serialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub version: String,
+    pub bind_address: String,
+    pub loopback_only: bool,
+    pub auth_configured: bool,
+    pub exposed: bool,
+    pub default_region: String,
+    /// Paths the S3 API is served under; the first is the one to advertise
+    pub s3_mounts: Vec<String>,
+    pub read_cache: ReadCacheStats,
+    /// Uploads being handled right now, and the configured cap (0 = none)
+    pub uploads_in_flight: usize,
+    pub max_concurrent_uploads: usize,
+    /// What the data dir's filesystem supports
+    pub filesystem: FilesystemFeatures,
+}
+
+/// Features of the data dir's filesystem that FreeBucket uses when they
+/// are there, probed at startup
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FilesystemFeatures {
+    /// Versions, snapshots, and exec hooks link objects instead of copying
+    pub hard_links: bool,
+    /// Modification times keep fractions of a second
+    pub fine_mtimes: bool,
+    /// Names differing only in case are different files; where they
+    /// aren't, keys are checked or escaped as `FREEBUCKET_KEY_CASE` says
+    pub case_sensitive: bool,
+}
+
+/// Endpoints and capabilities for pointing a client at one bucket, as seen
+/// from the host the request came in on
+#[derive(Debug, Serialize)]
+pub struct ConnectInfo {
+    pub bucket: String,
+    pub region: String,
+    /// Base URL of the server, e.g. `http://192.168.1.20:3210`
+    pub endpoint: String,
+    /// Path-style base URL for plain HTTP clients (append the key)
+    pub object_url: String,
+    /// `http://<bucket>.<host>/`, when virtual-hosted addressing works for this host
+    pub virtual_hosted_url: Option<String>,
+    /// SDK path-style requests (`<endpoint>/<bucket>/<key>`) are not routed
+    pub path_style: bool,
+    pub virtual_hosted: bool,
+    pub tls: bool,
+    pub auth_required: bool,
+}
+
+/// An in-progress multipart upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartUpload {
+    pub upload_id: String,
+    pub bucket: String,
+    pub key: String,
+    pub content_type: Option<String>,
+    pub metadata: HashMap<String, String>,
+    pub initiated: DateTime<Utc>,
+    /// Algorithm every part is checksummed with (`x-amz-checksum-algorithm`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_type: Option<ChecksumType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website_redirect_location: Option<String>,
+    /// Given to the object on completion, like `original_filename`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mtime: Option<DateTime<Utc>>,
+}
+
+/// A single uploaded (or copied) part of a multipart upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartInfo {
+    pub part_number: u32,
+    #[serde(deserialize_with = "unquoted_etag")]
+    pub etag: String,
+    pub size: u64,
+    pub last_modified: DateTime<Utc>,
+    /// Base64 checksum in the upload's checksum algorithm, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+/// A part reference sent by the client when completing a multipart upload
+#[derive(Debug, Clone)]
+pub struct CompletedPart {
+    pub part_number: u32,
+    pub etag: String,
+}
+
+/// Outcome of a garbage collection pass
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    /// Transactions a crash left half committed or half rolled back
+    pub transactions_recovered: u64,
+    pub temp_files_removed: u64,
+    pub stale_uploads_aborted: u64,
+    pub orphan_meta_removed: u64,
+    pub empty_dirs_removed: u64,
+    /// Retained settings of buckets deleted longer ago than the retention
+    pub deleted_buckets_purged: u64,
+    pub reclaimed_bytes: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GcQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Which objects a content type fix-up pass looks at
+#[derive(Debug, Default, Deserialize)]
+pub struct FixContentTypesQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub prefix: String,
+    /// Comma-separated extensions to leave alone, with or without the dot
+    #[serde(default)]
+    pub exclude: String,
+    /// Also look at the first bytes of objects whose extension says nothing
+    #[serde(default)]
+    pub sniff: bool,
+}
+
+/// Outcome of a content type fix-up pass
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContentTypeFixReport {
+    pub bucket: String,
+    pub dry_run: bool,
+    /// Objects that had a generic or missing content type
+    pub scanned: u64,
+    pub changed: u64,
+    /// Changed keys grouped by their new content type
+    pub by_type: BTreeMap<String, Vec<String>>,
+    /// Generic objects nothing better could be found for
+    pub unresolved: Vec<String>,
+}
+
+/// One recorded version of an object in a versioned bucket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectVersion {
+    pub version_id: String,
+    pub key: String,
+    pub size: u64,
+    pub content_type: String,
+    #[serde(deserialize_with = "unquoted_etag")]
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ObjectPart>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<ObjectChecksum>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website_redirect_location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mtime: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub etag_provisional: bool,
+    /// Records a delete; there is no payload behind it
+    #[serde(default)]
+    pub is_delete_marker: bool,
+    /// Set when listing, never persisted
+    #[serde(default, skip_deserializing)]
+    pub is_latest: bool,
+}
+
+impl ObjectVersion {
+    /// The version an object write records
+    pub fn from_meta(meta: &ObjectMeta, version_id: String) -> Self {
+        ObjectVersion {
+            version_id,
+            key: meta.key.clone(),
+            size: meta.size,
+            content_type: meta.content_type.clone(),
+            etag: meta.etag.clone(),
+            last_modified: meta.last_modified,
+            metadata: meta.metadata.clone(),
+            parts: meta.parts.clone(),
+            checksum: meta.checksum.clone(),
+            original_filename: meta.original_filename.clone(),
+            website_redirect_location: meta.website_redirect_location.clone(),
+            source_mtime: meta.source_mtime,
+            etag_provisional: meta.etag_provisional,
+            is_delete_marker: false,
+            is_latest: false,
+        }
+    }
+
+    /// The version as the object it was, for serving it like the live one
+    pub fn into_meta(self, bucket: &str) -> ObjectMeta {
+        ObjectMeta {
+            key: self.key,
+            bucket: bucket.to_string(),
+            size: self.size,
+            content_type: self.content_type,
+            etag: self.etag,
+            last_modified: self.last_modified,
+            metadata: self.metadata,
+            parts: self.parts,
+            checksum: self.checksum,
+            original_filename: self.original_filename,
+            website_redirect_location: self.website_redirect_location,
+            source_mtime: self.source_mtime,
+            etag_provisional: self.etag_provisional,
+            origin: None,
+            allocated_size: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListVersionsResponse {
+    pub bucket: String,
+    pub key: String,
+    pub versions: Vec<ObjectVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionsQuery {
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersioningRequest {
+    pub enabled: bool,
+}
+
+/// One bucket's usage at a point in time, as stored in `.stats_history.jsonl`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsSample {
+    pub timestamp: DateTime<Utc>,
+    pub bucket: String,
+    pub object_count: u64,
+    pub total_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsPoint {
+    pub timestamp: DateTime<Utc>,
+    pub object_count: u64,
+    pub total_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsHistoryResponse {
+    pub bucket: Option<String>,
+    pub days: u32,
+    pub points: Vec<StatsPoint>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsHistoryQuery {
+    pub bucket: Option<String>,
+    #[serde(default = "default_history_days")]
+    pub days: u32,
+}
+
+fn default_history_days() -> u32 {
+    30
+}
+
+/// What a request counted in bucket traffic did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficKind {
+    /// GET and HEAD
+    Get,
+    /// PUT, POST, and PATCH
+    Put,
+    Delete,
+}
+
+/// Requests to a bucket and the bytes they moved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficCounts {
+    pub get_requests: u64,
+    pub put_requests: u64,
+    pub delete_requests: u64,
+    /// Response bodies sent
+    pub bytes_served: u64,
+    /// Request bodies received
+    pub bytes_received: u64,
+}
+
+impl TrafficCounts {
+    pub fn is_empty(&self) -> bool {
+        *self == TrafficCounts::default()
+    }
+
+    pub fn merge(&mut self, other: &TrafficCounts) {
+        self.get_requests += other.get_requests;
+        self.put_requests += other.put_requests;
+        self.delete_requests += other.delete_requests;
+        self.bytes_served += other.bytes_served;
+        self.bytes_received += other.bytes_received;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrafficHour {
+    /// Start of the hour
+    pub hour: DateTime<Utc>,
+    #[serde(flatten)]
+    pub counts: TrafficCounts,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrafficDay {
+    pub date: chrono::NaiveDate,
+    #[serde(flatten)]
+    pub counts: TrafficCounts,
+}
+
+/// A bucket's traffic over a window: per-hour counts since the server
+/// started, and the persisted daily totals for the days it covers
+#[derive(Debug, Serialize)]
+pub struct BucketTraffic {
+    pub bucket: String,
+    pub window_hours: usize,
+    pub total: TrafficCounts,
+    pub hours: Vec<TrafficHour>,
+    pub days: Vec<TrafficDay>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrafficQuery {
+    /// `24h`, `3d`, ...; at most a week
+    #[serde(default = "default_traffic_window")]
+    pub window: String,
+}
+
+fn default_traffic_window() -> String {
+    "24h".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkOperation {
+    Copy,
+    Move,
+}
+
+/// Copy or move every object under a prefix, possibly into another bucket.
+/// Destination keys are `destination_prefix` followed by the source key with
+/// `source_prefix` removed (or kept whole with `keep_source_prefix`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkRequest {
+    pub operation: BulkOperation,
+    pub source_bucket: String,
+    #[serde(default)]
+    pub source_prefix: String,
+    pub destination_bucket: String,
+    #[serde(default)]
+    pub destination_prefix: String,
+    #[serde(default)]
+    pub keep_source_prefix: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A single key that could not be copied or moved in a bulk job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkError {
+    pub key: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// Progress of a bulk copy/move; `done` counts processed keys, failed or not
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkJob {
+    pub id: String,
+    pub operation: BulkOperation,
+    pub source_bucket: String,
+    pub source_prefix: String,
+    pub destination_bucket: String,
+    pub destination_prefix: String,
+    pub status: BulkJobStatus,
+    pub total: usize,
+    pub done: usize,
+    pub errors: Vec<BulkError>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecomputeStatus {
+    Running,
+    /// The counted totals replaced the recorded ones
+    Completed,
+    /// A write recounted the bucket while the walk ran, so its totals are
+    /// newer than the walk's and were kept
+    Superseded,
+    Cancelled,
+    Failed,
+}
+
+/// Progress of walking a bucket in batches to correct its object count
+/// and size
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsRecompute {
+    pub bucket: String,
+    pub status: RecomputeStatus,
+    /// Totals the bucket had when the walk started
+    pub recorded_object_count: u64,
+    pub recorded_total_size: u64,
+    /// Totals counted so far, and in the end
+    pub object_count: u64,
+    pub total_size: u64,
+    pub batches: u64,
+    /// Directories found but not read yet
+    pub directories_left: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Settings kept from a deleted bucket, from which it can be recreated
+#[derive(Debug, Clone, Serialize)]
+pub struct DeletedBucket {
+    /// `<name>-<deletion time>`, as used in the restore URL
+    pub id: String,
+    pub name: String,
+    pub deleted_at: DateTime<Utc>,
+    /// The bucket as it was when deleted
+    pub settings: Bucket,
+}
+
+/// Usage and free space of one storage volume
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeStats {
+    pub name: String,
+    pub path: String,
+    /// False when the directory is missing, such as an unmounted drive
+    pub available: bool,
+    pub bucket_count: u64,
+    pub object_count: u64,
+    pub total_size: u64,
+    pub total_size_human: String,
+    /// Space left on the filesystem, where the platform reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capacity_bytes: Option<u64>,
+}
+
+/// Outcome of one storage self-test step
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestStep {
+    pub step: String,
+    pub passed: bool,
+    pub duration_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A write/read/list/delete cycle through the storage engine
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub steps: Vec<SelfTestStep>,
+    pub duration_ms: f64,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// Result of re-reading the configuration while the server runs
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigReload {
+    /// Settings now in effect with new values
+    pub changed: Vec<String>,
+    /// Settings whose new values wait for a restart
+    pub needs_restart: Vec<String>,
+}
+
+/// Administrative and destructive actions recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditAction {
+    #[serde(rename = "bucket.create")]
+    BucketCreate,
+    /// A delete that keeps the bucket's settings for restoring
+    #[serde(rename = "bucket.delete")]
+    BucketDelete,
+    /// A delete that keeps nothing, `freebucket remove-bucket --purge`
+    #[serde(rename = "bucket.purge")]
+    BucketPurge,
+    #[serde(rename = "bucket.restore")]
+    BucketRestore,
+    /// A bucket moved to another volume
+    #[serde(rename = "bucket.move")]
+    BucketMove,
+    /// Settings such as the object limit, inbox, versioning, or webhooks
+    #[serde(rename = "bucket.settings")]
+    BucketSettings,
+    #[serde(rename = "prefix.delete")]
+    PrefixDelete,
+    #[serde(rename = "snapshot.restore")]
+    SnapshotRestore,
+    #[serde(rename = "snapshot.delete")]
+    SnapshotDelete,
+    #[serde(rename = "config.reload")]
+    ConfigReload,
+    /// A bucket's usage crossed one of its alert thresholds
+    #[serde(rename = "alert.raised")]
+    AlertRaised,
+    #[serde(rename = "alert.cleared")]
+    AlertCleared,
+}
+
+impl AuditAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            AuditAction::BucketCreate => "bucket.create",
+            AuditAction::BucketDelete => "bucket.delete",
+            AuditAction::BucketPurge => "bucket.purge",
+            AuditAction::BucketRestore => "bucket.restore",
+            AuditAction::BucketMove => "bucket.move",
+            AuditAction::BucketSettings => "bucket.settings",
+            AuditAction::PrefixDelete => "prefix.delete",
+            AuditAction::SnapshotRestore => "snapshot.restore",
+            AuditAction::SnapshotDelete => "snapshot.delete",
+            AuditAction::ConfigReload => "config.reload",
+            AuditAction::AlertRaised => "alert.raised",
+            AuditAction::AlertCleared => "alert.cleared",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Succeeded,
+    Failed,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    /// Who asked: `anonymous` for API requests, which are not
+    /// authenticated, `cli` for the command line, `signal` for a SIGHUP,
+    /// `alerts` for the usage alert checker
+    pub actor: String,
+    pub action: AuditAction,
+    /// The bucket acted on, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// What was acted on, such as a bucket name, prefix, or snapshot ID
+    pub target: String,
+    /// Settings a `bucket.settings` action set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<String>,
+    pub outcome: AuditOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Filters for reading the audit log
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub limit: Option<usize>,
+    pub action: Option<AuditAction>,
+    pub bucket: Option<String>,
+}
+
+/// A storage operation that took longer than the slow-op threshold
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowOperation {
+    pub operation: String,
+    pub bucket: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Other arguments, such as a listing's prefix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub duration_ms: u64,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// One S3 request and its response in the request recording, as listed
+/// in its `index.jsonl`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub id: String,
+    pub recorded_at: DateTime<Utc>,
+    pub method: String,
+    /// Path and query as routed, such as `/s3/obj/photos/cat.jpg`
+    pub path: String,
+    pub status: u16,
+    /// From the request arriving to the last byte of the response
+    pub duration_ms: u64,
+    /// Body sizes as sent, before truncation
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    /// Whether either body was cut short on disk
+    pub truncated: bool,
+    /// Bytes the two recording files take
+    pub stored_bytes: u64,
+}
+
+/// Desired buckets of an instance, for `freebucket apply` and
+/// `freebucket export-config`
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub buckets: Vec<BucketManifest>,
+}
+
+/// One bucket in a manifest. Omitted settings are left as they are on an
+/// existing bucket and take their defaults on a new one.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketManifest {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versioning: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inbox: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inbox_limits: Option<InboxLimits>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precompressed: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<LoggingConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overwrite_policy: Option<OverwritePolicy>,
+    /// Object limit; 0 removes it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_objects: Option<u64>,
+    /// Local files uploaded into the bucket when missing or different
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seed: Vec<SeedObject>,
+}
+
+/// A local file, or a directory uploaded recursively, relative to the
+/// manifest. `key` is the object key for a file and the key prefix for a
+/// directory; by default a file keeps its name.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedObject {
+    pub path: String,
+    #[serde(default)]
+    pub key: String,
+}
+
+/// Writes across one or more buckets that are applied together or not at all
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransactionRequest {
+    pub operations: Vec<TransactionOperation>,
+}
+
+/// One write in a transaction. A `put` takes its content from an inline
+/// `body` (text, or base64 with `body_base64`) or from a previously uploaded
+/// `staging_bucket`/`staging_key`, which is removed when the transaction
+/// commits.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransactionOperation {
+    Put {
+        bucket: String,
+        key: String,
+        #[serde(default)]
+        body: Option<String>,
+        #[serde(default)]
+        body_base64: Option<String>,
+        #[serde(default)]
+        staging_bucket: Option<String>,
+        #[serde(default)]
+        staging_key: Option<String>,
+        #[serde(default)]
+        content_type: Option<String>,
+    },
+    Copy {
+        source_bucket: String,
+        source_key: String,
+        bucket: String,
+        key: String,
+    },
+    Delete {
+        bucket: String,
+        key: String,
+    },
+}
+
+/// What a committed transaction did to one key
+#[derive(Debug, Serialize)]
+pub struct TransactionStep {
+    pub op: String,
+    pub bucket: String,
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionReport {
+    pub id: String,
+    pub steps: Vec<TransactionStep>,
+}
+
+/// An advisory lease on one key. While it lasts, writes to the key must
+/// carry its token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectLease {
+    pub bucket: String,
+    pub key: String,
+    /// Who holds it, as given when it was acquired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    pub ttl_secs: u64,
+    pub acquired_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Body of acquiring or refreshing a lease; both fields are optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaseRequest {
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+/// A lease just acquired, with the token its holder writes with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcquiredLease {
+    pub token: String,
+    #[serde(flatten)]
+    pub lease: ObjectLease,
+}
+
+/// A link handing one object, or every object under a prefix, to people
+/// without access to the server. It stops working at `expires_at` or after
+/// `max_downloads` downloads, and is gone once revoked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Share {
+    pub token: String,
+    pub bucket: String,
+    /// The shared key, or the shared prefix when `prefix` is set
+    pub key: String,
+    #[serde(default)]
+    pub prefix: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    #[serde(default)]
+    pub downloads: u64,
+    /// Downloads need the password the share was created with
+    #[serde(default)]
+    pub password: bool,
+}
+
+impl Share {
+    /// Downloads left before the link stops working, without a limit none
+    pub fn remaining_downloads(&self) -> Option<u64> {
+        self.max_downloads.map(|max| max.saturating_sub(self.downloads))
+    }
+}
+
+/// Body of `POST /api/shares`: a `key` or a `prefix` in `bucket`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateShareRequest {
+    pub bucket: String,
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Seconds until the link expires; a week without it
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+    #[serde(default)]
+    pub max_downloads: Option<u64>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// A share just created, with the path of its link
+#[derive(Debug, Serialize)]
+pub struct CreatedShare {
+    #[serde(flatten)]
+    pub share: Share,
+    pub url: String,
 }
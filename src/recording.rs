@@ -0,0 +1,399 @@
+//! Request recording, for finding out what an S3 client really sends when
+//! it misbehaves. With `--record-requests <dir>`, every request to an S3
+//! route and the response to it are written to `<id>.request` and
+//! `<id>.response` in that directory, as HTTP messages: the request or
+//! status line, the headers with secrets redacted, a blank line, and the
+//! body cut off at `FREEBUCKET_RECORD_MAX_BODY`. `index.jsonl` lists the
+//! recordings, oldest first. Once they take more than
+//! `FREEBUCKET_RECORD_MAX_TOTAL`, the oldest are removed.
+//!
+//! Bodies are copied as they stream through, so handlers see them exactly
+//! as without recording, and the files are written once both bodies are
+//! done with. Without a directory the middleware passes requests straight
+//! through.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use http_body::{Frame, SizeHint};
+
+use crate::error::AppError;
+use crate::models::RecordedRequest;
+use crate::AppState;
+
+const INDEX: &str = "index.jsonl";
+
+/// Header added to a recorded message whose body was cut short, giving the
+/// size it had; never sent by `freebucket replay`
+pub const TRUNCATED_HEADER: &str = "x-freebucket-recording-truncated";
+
+/// What a redacted secret is replaced with
+pub const REDACTED: &str = "[redacted]";
+
+/// `REDACTED` percent-encoded, for query strings
+const REDACTED_PARAM: &str = "%5Bredacted%5D";
+
+/// Headers whose values are left out of recordings
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-amz-security-token",
+    "x-amz-server-side-encryption-customer-key",
+    "x-amz-copy-source-server-side-encryption-customer-key",
+    crate::csrf::TOKEN_HEADER,
+];
+
+/// Query parameters of presigned URLs whose values are left out
+const SECRET_PARAMS: &[&str] = &["X-Amz-Signature", "X-Amz-Security-Token", "Signature"];
+
+/// Pruning goes this far below the limit, so a full directory isn't
+/// pruned again on every request
+const PRUNE_TO_PERCENT: u64 = 90;
+
+/// One of the two files of a recording
+#[derive(Debug, Clone, Copy)]
+pub enum Side {
+    Request,
+    Response,
+}
+
+impl Side {
+    fn extension(self) -> &'static str {
+        match self {
+            Side::Request => "request",
+            Side::Response => "response",
+        }
+    }
+}
+
+/// The recordings kept, oldest first, and the bytes they take
+#[derive(Default)]
+struct Recordings {
+    entries: VecDeque<RecordedRequest>,
+    total: u64,
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    max_body: usize,
+    max_total: u64,
+    recordings: Mutex<Recordings>,
+}
+
+impl Recorder {
+    /// Record into `dir`, creating it, and pick up the recordings an earlier
+    /// run left there
+    pub fn open(dir: &Path, max_body: u64, max_total: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut recordings = Recordings::default();
+        if let Ok(file) = fs::File::open(dir.join(INDEX)) {
+            for line in io::BufReader::new(file).lines() {
+                let Ok(entry) = serde_json::from_str::<RecordedRequest>(&line?) else {
+                    continue;
+                };
+                recordings.total += entry.stored_bytes;
+                recordings.entries.push_back(entry);
+            }
+        }
+        let recorder = Self {
+            dir: dir.to_path_buf(),
+            max_body: usize::try_from(max_body).unwrap_or(usize::MAX),
+            max_total,
+            recordings: Mutex::new(recordings),
+        };
+        recorder.prune(&mut recorder.recordings.lock().unwrap())?;
+        Ok(recorder)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Recordings kept, newest first
+    pub fn list(&self) -> Vec<RecordedRequest> {
+        self.recordings.lock().unwrap().entries.iter().rev().cloned().collect()
+    }
+
+    /// The file holding one side of a kept recording
+    pub fn file(&self, id: &str, side: Side) -> Result<PathBuf, AppError> {
+        // Only ids from the index, so a path can't be smuggled in
+        let kept = self.recordings.lock().unwrap().entries.iter().any(|e| e.id == id);
+        if !kept {
+            return Err(AppError::RecordingNotFound(id.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.{}", id, side.extension())))
+    }
+
+    fn save(&self, exchange: &Exchange, parts: ExchangeParts) -> io::Result<()> {
+        let Some(response_head) = parts.response_head else {
+            return Ok(());
+        };
+        let request = message(&exchange.request_head, &parts.request);
+        let response = message(&response_head, &parts.response);
+        let entry = RecordedRequest {
+            id: exchange.id.clone(),
+            recorded_at: exchange.recorded_at,
+            method: exchange.method.clone(),
+            path: exchange.path.clone(),
+            status: parts.status,
+            duration_ms: exchange.started.elapsed().as_millis() as u64,
+            request_bytes: parts.request.size,
+            response_bytes: parts.response.size,
+            truncated: parts.request.truncated() || parts.response.truncated(),
+            stored_bytes: (request.len() + response.len()) as u64,
+        };
+
+        let mut recordings = self.recordings.lock().unwrap();
+        fs::write(self.dir.join(format!("{}.request", entry.id)), &request)?;
+        fs::write(self.dir.join(format!("{}.response", entry.id)), &response)?;
+        let mut index = fs::OpenOptions::new().create(true).append(true).open(self.dir.join(INDEX))?;
+        writeln!(index, "{}", serde_json::to_string(&entry).unwrap())?;
+        recordings.total += entry.stored_bytes;
+        recordings.entries.push_back(entry);
+        self.prune(&mut recordings)
+    }
+
+    /// Remove the oldest recordings while they take more than the limit,
+    /// then rewrite the index without them
+    fn prune(&self, recordings: &mut Recordings) -> io::Result<()> {
+        if recordings.total <= self.max_total {
+            return Ok(());
+        }
+        let target = self.max_total / 100 * PRUNE_TO_PERCENT;
+        while recordings.total > target {
+            let Some(oldest) = recordings.entries.pop_front() else {
+                break;
+            };
+            recordings.total = recordings.total.saturating_sub(oldest.stored_bytes);
+            for side in [Side::Request, Side::Response] {
+                let _ = fs::remove_file(self.dir.join(format!("{}.{}", oldest.id, side.extension())));
+            }
+        }
+        let mut lines = String::new();
+        for entry in &recordings.entries {
+            lines.push_str(&serde_json::to_string(entry).unwrap());
+            lines.push('\n');
+        }
+        let path = self.dir.join(INDEX);
+        let tmp = path.with_extension("jsonl.tmp");
+        fs::write(&tmp, lines)?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+/// The start of a body, and how long it really was
+#[derive(Default)]
+struct Captured {
+    head: Vec<u8>,
+    size: u64,
+}
+
+impl Captured {
+    fn push(&mut self, data: &[u8], max: usize) {
+        let room = max.saturating_sub(self.head.len());
+        self.head.extend_from_slice(&data[..data.len().min(room)]);
+        self.size += data.len() as u64;
+    }
+
+    fn truncated(&self) -> bool {
+        (self.head.len() as u64) < self.size
+    }
+}
+
+#[derive(Default)]
+struct ExchangeParts {
+    request: Captured,
+    /// Set once the handler has answered
+    response_head: Option<String>,
+    status: u16,
+    response: Captured,
+}
+
+/// One request and its response while they stream through. Saved when the
+/// last body holding it is dropped, by which time both have ended or been
+/// abandoned.
+struct Exchange {
+    recorder: Arc<Recorder>,
+    id: String,
+    recorded_at: DateTime<Utc>,
+    started: Instant,
+    method: String,
+    path: String,
+    request_head: String,
+    parts: Mutex<ExchangeParts>,
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        let parts = std::mem::take(&mut *self.parts.lock().unwrap());
+        if let Err(e) = self.recorder.save(self, parts) {
+            tracing::warn!("Cannot record request {}: {}", self.id, e);
+        }
+    }
+}
+
+/// A body passed along untouched, copying what goes through into its
+/// exchange
+struct Tapped {
+    inner: Body,
+    exchange: Arc<Exchange>,
+    side: Side,
+}
+
+impl HttpBody for Tapped {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled {
+            if let Some(data) = frame.data_ref() {
+                let max = self.exchange.recorder.max_body;
+                let mut parts = self.exchange.parts.lock().unwrap();
+                match self.side {
+                    Side::Request => parts.request.push(data, max),
+                    Side::Response => parts.response.push(data, max),
+                }
+            }
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// An HTTP message: the head, a truncation note if the body was cut, a
+/// blank line, and the body as kept
+fn message(head: &str, body: &Captured) -> Vec<u8> {
+    let mut out = head.as_bytes().to_vec();
+    if body.truncated() {
+        out.extend_from_slice(format!("{}: {}\r\n", TRUNCATED_HEADER, body.size).as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(&body.head);
+    out
+}
+
+/// Header lines, each ending in CRLF, with secret values redacted
+fn header_lines(headers: &HeaderMap) -> String {
+    let mut lines = String::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        lines.push_str(&format!("{}: {}\r\n", name, redact_header(name.as_str(), &value)));
+    }
+    lines
+}
+
+fn redact_header<'a>(name: &str, value: &'a str) -> Cow<'a, str> {
+    if !SECRET_HEADERS.contains(&name) {
+        return Cow::Borrowed(value);
+    }
+    // SigV4 keeps everything but the signature, which is what tells why a
+    // client's signing went wrong
+    match value.split_once("Signature=") {
+        Some((before, _)) if name == "authorization" && value.starts_with("AWS4-") => {
+            Cow::Owned(format!("{}Signature={}", before, REDACTED))
+        }
+        _ => Cow::Borrowed(REDACTED),
+    }
+}
+
+/// A path and query with the signature parameters of presigned URLs redacted
+fn redact_query(path_and_query: &str) -> Cow<'_, str> {
+    let Some((path, query)) = path_and_query.split_once('?') else {
+        return Cow::Borrowed(path_and_query);
+    };
+    let secret = |pair: &&str| SECRET_PARAMS.iter().any(|p| pair.split('=').next() == Some(*p));
+    if !query.split('&').any(|pair| secret(&pair)) {
+        return Cow::Borrowed(path_and_query);
+    }
+    let query: Vec<Cow<str>> = query
+        .split('&')
+        .map(|pair| match secret(&pair) {
+            true => Cow::Owned(format!("{}={}", pair.split('=').next().unwrap_or(pair), REDACTED_PARAM)),
+            false => Cow::Borrowed(pair),
+        })
+        .collect();
+    Cow::Owned(format!("{}?{}", path, query.join("&")))
+}
+
+/// Record S3 requests and their responses when a recording directory is set
+pub async fn record_exchange(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let Some(recorder) = &state.recorder else {
+        return next.run(req).await;
+    };
+    let recorded_at = state.storage.now();
+    let id = format!(
+        "{}-{}",
+        recorded_at.format("%Y%m%dT%H%M%S%.3fZ"),
+        &state.storage.new_id().simple().to_string()[..8]
+    );
+    let (parts, body) = req.into_parts();
+    let path = redact_query(parts.uri.path_and_query().map_or("/", |p| p.as_str())).into_owned();
+    let request_head = format!(
+        "{} {} {:?}\r\n{}",
+        parts.method,
+        path,
+        parts.version,
+        header_lines(&parts.headers)
+    );
+    let exchange = Arc::new(Exchange {
+        recorder: recorder.clone(),
+        id,
+        recorded_at,
+        started: Instant::now(),
+        method: parts.method.to_string(),
+        path,
+        request_head,
+        parts: Mutex::default(),
+    });
+    let body = Body::new(Tapped {
+        inner: body,
+        exchange: exchange.clone(),
+        side: Side::Request,
+    });
+
+    let res = next.run(Request::from_parts(parts, body)).await;
+
+    let (parts, body) = res.into_parts();
+    {
+        let mut recorded = exchange.parts.lock().unwrap();
+        recorded.status = parts.status.as_u16();
+        recorded.response_head = Some(format!(
+            "{:?} {}\r\n{}",
+            parts.version,
+            parts.status,
+            header_lines(&parts.headers)
+        ));
+    }
+    let body = Body::new(Tapped {
+        inner: body,
+        exchange,
+        side: Side::Response,
+    });
+    Response::from_parts(parts, body)
+}
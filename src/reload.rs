@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::models::{AuditAction, ConfigReload};
+use crate::storage::Audit;
+use crate::AppState;
+
+/// Handle for swapping the log filter installed at startup
+pub type LogFilter = reload::Handle<EnvFilter, Registry>;
+
+/// Sort every `Config` field into one of three groups. The destructuring
+/// makes a new field fail to compile until it is placed in one.
+macro_rules! config_fields {
+    (live: [$($live:ident),* $(,)?], restart: [$($restart:ident),* $(,)?], fixed: [$($fixed:ident),* $(,)?] $(,)?) => {
+        /// Fields that differ between `old` and `new`, by group. Fields
+        /// that only apply at startup are reset to their `old` values.
+        fn diff_config(old: &Config, new: &mut Config) -> [Vec<&'static str>; 3] {
+            let Config { $($live: _,)* $($restart: _,)* $($fixed: _,)* } = new;
+            let mut live = Vec::new();
+            let mut restart = Vec::new();
+            let mut fixed = Vec::new();
+            $(if old.$live != new.$live {
+                live.push(stringify!($live));
+            })*
+            $(if old.$restart != new.$restart {
+                restart.push(stringify!($restart));
+                new.$restart = old.$restart.clone();
+            })*
+            $(if old.$fixed != new.$fixed {
+                fixed.push(stringify!($fixed));
+            })*
+            [live, restart, fixed]
+        }
+    };
+}
+
+config_fields! {
+    // Read per request or per background tick
+    live: [
+        max_upload_size,
+        log_filter,
+        log_exclude_paths,
+        log_sample_rate,
+        multipart_expiry_hours,
+        deleted_bucket_retention_days,
+        virtual_host_domain,
+        default_region,
+        stats_interval_hours,
+        stats_retention_days,
+        access_log_flush_secs,
+        access_log_batch_size,
+        access_flush_secs,
+        stats_compact_secs,
+        alert_check_secs,
+        range_writes,
+        size_units,
+        s3_mounts,
+        exec_hook_allow,
+        csrf_trusted_origins,
+        auto_create_buckets,
+        lock_max_ttl_secs,
+    ],
+    // Built into the storage engine, the limiters, or the routes at startup
+    restart: [
+        allow_open_bind,
+        reconcile_mode,
+        reconcile_max_files,
+        repair_corrupt_meta,
+        max_concurrent_uploads,
+        upload_wait_secs,
+        write_buffer_size,
+        delete_policy,
+        delete_wait_ms,
+        reserved_bucket_names,
+        default_buckets,
+        walk_threads,
+        idempotency_ttl_secs,
+        idempotency_cache_size,
+        idempotency_spill,
+        lock_persist,
+        access_tracking,
+        normalize_keys,
+        max_key_depth,
+        key_case,
+        io_retry_attempts,
+        io_retry_backoff_ms,
+        grpc_port,
+        webdav,
+        default_max_objects,
+        slow_op_ms,
+        readyz_self_test_secs,
+        fake_clock,
+        fake_clock_step_ms,
+        fake_ids,
+        metadata_limits,
+        read_cache_size,
+        read_cache_max_object,
+        exec_hook_workers,
+        hash_mode,
+        audit_log_max_size,
+        record_requests,
+        record_force,
+        record_max_body,
+        record_max_total,
+    ],
+    // Where the server listens and what it serves
+    fixed: [host, port, data_dir, volumes],
+}
+
+/// Re-read the environment and config file and swap in the settings that can
+/// change while the server runs. Nothing changes if the new configuration is
+/// invalid or moves the bind address or data directory.
+fn reload_config(state: &AppState) -> Result<ConfigReload, AppError> {
+    let mut new = Config::load().map_err(AppError::InvalidRequest)?;
+    state.config_overrides.apply(&mut new);
+    let filter = EnvFilter::try_new(&new.log_filter)
+        .map_err(|e| AppError::InvalidRequest(format!("Invalid log filter {:?}: {}", new.log_filter, e)))?;
+
+    let old = state.config();
+    let [changed, needs_restart, fixed] = diff_config(&old, &mut new);
+    if !fixed.is_empty() {
+        return Err(AppError::InvalidRequest(format!(
+            "Cannot change {} without a restart; configuration not reloaded",
+            fixed.join(", ")
+        )));
+    }
+    if old.log_filter != new.log_filter {
+        state
+            .log_filter
+            .reload(filter)
+            .map_err(|e| AppError::StorageError(format!("Cannot swap the log filter: {}", e)))?;
+    }
+    crate::size::set_units(new.size_units);
+    state.set_config(new);
+
+    if changed.is_empty() {
+        tracing::info!("Configuration reloaded; nothing changed");
+    } else {
+        tracing::info!("Configuration reloaded; changed {}", changed.join(", "));
+    }
+    if !needs_restart.is_empty() {
+        tracing::warn!("Ignoring changes to {} until the next restart", needs_restart.join(", "));
+    }
+    Ok(ConfigReload {
+        changed: changed.into_iter().map(str::to_string).collect(),
+        needs_restart: needs_restart.into_iter().map(str::to_string).collect(),
+    })
+}
+
+/// `reload_config`, recorded in the audit log as done by `actor`
+pub fn audited_reload(state: &AppState, actor: &str) -> Result<ConfigReload, AppError> {
+    let reload = reload_config(state);
+    let audit = Audit {
+        actor,
+        action: AuditAction::ConfigReload,
+        bucket: None,
+        target: "config",
+        changes: reload.as_ref().map(|r| r.changed.clone()).unwrap_or_default(),
+    };
+    state.storage.audited(audit, reload)
+}
+
+/// Reload the configuration on every SIGHUP
+#[cfg(unix)]
+pub fn spawn_sighup_listener(state: Arc<AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!("Cannot listen for SIGHUP; reload with POST /api/admin/reload: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            tracing::info!("Received SIGHUP; reloading the configuration");
+            let reload_state = state.clone();
+            match tokio::task::spawn_blocking(move || audited_reload(&reload_state, "signal")).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::error!("Configuration reload failed: {:?}", e),
+                Err(e) => tracing::error!("Configuration reload panicked: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_sighup_listener(_state: Arc<AppState>) {}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, Bytes};
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    #[test]
+    fn changes_are_sorted_by_when_they_can_apply() {
+        let old = Config::builtin();
+        let mut new = Config::builtin();
+        new.max_upload_size = 1024;
+        new.csrf_trusted_origins = vec!["https://app.example.com".to_string()];
+        new.webdav = !old.webdav;
+        new.data_dir = "/elsewhere".to_string();
+
+        let [live, restart, fixed] = diff_config(&old, &mut new);
+        assert_eq!(live, ["max_upload_size", "csrf_trusted_origins"]);
+        assert_eq!(restart, ["webdav"]);
+        assert_eq!(fixed, ["data_dir"]);
+        // Startup-only settings keep their running values
+        assert_eq!(new.webdav, old.webdav);
+        assert_eq!(new.max_upload_size, 1024);
+
+        let mut same = Config::builtin();
+        assert!(diff_config(&old, &mut same).iter().all(Vec::is_empty));
+    }
+
+    fn upload(key: &str, body: Body) -> Request {
+        Request::put(format!("/s3/photos/{}", key)).body(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_new_upload_limit_applies_from_the_next_request() {
+        let server = std::sync::Arc::new(TestServer::with_config(|c| c.max_upload_size = 4096));
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        let payload = Bytes::from(vec![7u8; 2048]);
+
+        // An upload already under way when the limit drops
+        let (release, held) = oneshot::channel::<()>();
+        let body = futures::stream::once({
+            let payload = payload.clone();
+            async move {
+                let _ = held.await;
+                Ok::<_, std::io::Error>(payload)
+            }
+        });
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { server.send(upload("before.bin", Body::from_stream(body))).await.status() }
+        });
+        while server.state.upload_limiter.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let mut config = (*server.state.config()).clone();
+        config.max_upload_size = 1024;
+        server.state.set_config(config);
+
+        let res = server.send(upload("after.bin", Body::from(payload.clone()))).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        release.send(()).unwrap();
+        assert_eq!(running.await.unwrap(), StatusCode::OK);
+        assert_eq!(server.state.storage.get_object("photos", "before.bin").unwrap().1.len(), 2048);
+
+        let res = server.send(upload("small.bin", Body::from(vec![1u8; 512]))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
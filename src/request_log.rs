@@ -0,0 +1,227 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+use crate::AppState;
+
+const REQUEST_ID: &str = "x-request-id";
+
+/// Whether a successful read falls inside the sample. The random bits come
+/// from a v4 UUID, which saves pulling in an RNG for this alone.
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    let roll = (uuid::Uuid::new_v4().as_u128() >> 64) as u64 as f64 / u64::MAX as f64;
+    roll < rate
+}
+
+/// The request's `X-Request-Id`, or a new one like the ids in S3 access
+/// logs when the client sent none
+fn request_id(state: &AppState, req: &Request) -> String {
+    req.headers()
+        .get(REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| state.storage.new_id().simple().to_string()[..16].to_ascii_uppercase())
+}
+
+/// Log one line per request. Errors and mutations are always logged;
+/// successful reads are dropped for excluded path prefixes and otherwise
+/// sampled at the configured rate. Everything the handler logs is recorded
+/// inside a per-request span either way, which carries the request id and,
+/// once the handler is done, the status. The id goes back to the client in
+/// `X-Request-Id`.
+pub async fn log_request(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let id = request_id(&state, &req);
+    let span = tracing::info_span!("request", request_id = %id, %method, uri = %req.uri(), status = Empty);
+    let started = Instant::now();
+
+    let mut res = next.run(req).instrument(span.clone()).await;
+
+    let status = res.status();
+    span.record("status", status.as_u16());
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID, value);
+    }
+    let latency = started.elapsed();
+    let config = state.config();
+    let is_read = matches!(method, Method::GET | Method::HEAD | Method::OPTIONS);
+    let _entered = span.enter();
+    if status.is_server_error() {
+        tracing::error!(status = status.as_u16(), "{} {} {} in {:?}", method, path, status.as_u16(), latency);
+    } else if status.is_client_error() {
+        tracing::warn!(status = status.as_u16(), "{} {} {} in {:?}", method, path, status.as_u16(), latency);
+    } else if !is_read
+        || (!config.log_exclude_paths.iter().any(|p| path.starts_with(p.as_str()))
+            && sampled(config.log_sample_rate))
+    {
+        tracing::info!(status = status.as_u16(), "{} {} {} in {:?}", method, path, status.as_u16(), latency);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    #[derive(Default)]
+    struct Fields(HashMap<String, String>);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    /// A request line as logged: its level, and its own fields over those
+    /// of the request span
+    #[derive(Debug)]
+    struct Line {
+        level: Level,
+        fields: HashMap<String, String>,
+    }
+
+    impl Line {
+        fn field(&self, name: &str) -> &str {
+            self.fields.get(name).map_or("", |v| v.as_str())
+        }
+    }
+
+    /// Keeps the lines this module logs
+    #[derive(Default)]
+    struct Capture {
+        spans: Mutex<HashMap<Id, Fields>>,
+        lines: Arc<Mutex<Vec<Line>>>,
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _: Context<'_, S>) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            self.spans.lock().unwrap().insert(id.clone(), fields);
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            if let Some(fields) = self.spans.lock().unwrap().get_mut(id) {
+                values.record(fields);
+            }
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            if event.metadata().target() != "freebucket::request_log" {
+                return;
+            }
+            let mut fields = Fields::default();
+            if let Some(span) = ctx.event_span(event) {
+                fields.0 = self.spans.lock().unwrap()[&span.id()].0.clone();
+            }
+            event.record(&mut fields);
+            self.lines.lock().unwrap().push(Line {
+                level: *event.metadata().level(),
+                fields: fields.0,
+            });
+        }
+    }
+
+    /// Send `requests` as `(method, uri)` and return what was logged, with
+    /// the response to each
+    async fn logged(server: &TestServer, requests: &[(&str, &str)]) -> (Vec<Line>, Vec<Response>) {
+        let capture = Capture::default();
+        let lines = capture.lines.clone();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(capture));
+        let mut responses = Vec::new();
+        for (method, uri) in requests {
+            let body = match *method {
+                "POST" => Body::from(r#"{"name":"photos"}"#),
+                _ => Body::empty(),
+            };
+            let req = Request::builder()
+                .method(*method)
+                .uri(*uri)
+                .header("content-type", "application/json")
+                .body(body)
+                .unwrap();
+            responses.push(server.send(req).await);
+        }
+        let lines = std::mem::take(&mut *lines.lock().unwrap());
+        (lines, responses)
+    }
+
+    #[tokio::test]
+    async fn excluded_paths_are_not_logged_and_other_reads_are() {
+        let server = TestServer::new();
+        let (lines, responses) = logged(&server, &[("GET", "/readyz"), ("GET", "/api/buckets")]).await;
+        assert_eq!(responses[0].status(), StatusCode::OK);
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        assert_eq!(lines[0].level, Level::INFO);
+        assert_eq!(lines[0].field("method"), "GET");
+        assert_eq!(lines[0].field("uri"), "/api/buckets");
+        assert_eq!(lines[0].field("status"), "200");
+        assert_eq!(lines[0].field("request_id"), responses[1].headers()["x-request-id"]);
+        assert_eq!(lines[0].field("request_id").len(), 16);
+    }
+
+    #[tokio::test]
+    async fn unsampled_reads_are_dropped_but_errors_and_mutations_are_not() {
+        let server = TestServer::with_config(|config| config.log_sample_rate = 0.0);
+        let (lines, _) = logged(
+            &server,
+            &[
+                ("GET", "/api/buckets"),
+                ("POST", "/api/buckets"),
+                ("GET", "/api/buckets/missing"),
+            ],
+        )
+        .await;
+        let summary: Vec<_> = lines.iter().map(|l| (l.level, l.field("method"), l.field("status"))).collect();
+        assert_eq!(summary, [(Level::INFO, "POST", "201"), (Level::WARN, "GET", "404")]);
+
+        // Excluding a path only drops successful reads there
+        let server = TestServer::with_config(|config| config.log_exclude_paths = vec!["/api".to_string()]);
+        let (lines, _) = logged(&server, &[("POST", "/api/buckets"), ("GET", "/api/buckets/missing")]).await;
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+    }
+
+    #[tokio::test]
+    async fn a_request_id_from_the_client_is_kept() {
+        let server = TestServer::new();
+        let capture = Capture::default();
+        let lines = capture.lines.clone();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(capture));
+        let req = Request::builder()
+            .uri("/api/buckets")
+            .header("x-request-id", "trace-42")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.send(req).await;
+        assert_eq!(res.headers()["x-request-id"], "trace-42");
+        assert_eq!(lines.lock().unwrap()[0].field("request_id"), "trace-42");
+    }
+}
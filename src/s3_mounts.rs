@@ -0,0 +1,254 @@
+//! The S3 API under every configured mount. Requests are rewritten onto the
+//! canonical `/s3` routes before routing, so the router and the middleware
+//! behind it see one path shape whichever mount or shape the client used.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{Request, State};
+use axum::http::Uri;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::error::AppError;
+use crate::AppState;
+
+/// Where the S3 routes are registered; every mount is rewritten onto it
+pub const CANONICAL_MOUNT: &str = "/s3";
+/// Mounts used when none are configured
+pub const DEFAULT_MOUNTS: &[&str] = &[CANONICAL_MOUNT];
+/// Segment that object paths carried before `{mount}/{bucket}/{key}`
+const LEGACY_SEGMENT: &str = "obj";
+/// Top-level paths other routes own, which no mount may take over
+const TAKEN_ROOTS: &[&str] = &["api", "dav", "inbox", "share", "readyz"];
+/// At most one deprecation warning per interval; the rest are counted
+const WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Read a comma-separated list of mounts such as `/s3,/storage`
+pub fn parse_mounts(value: &str) -> Result<Vec<String>, String> {
+    let mut mounts: Vec<String> = Vec::new();
+    for raw in value.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+        let mount = format!("/{}", raw.trim_matches('/'));
+        let mut segments = mount[1..].split('/');
+        let root = segments.next().unwrap_or("");
+        if root.is_empty() {
+            return Err(format!("Invalid S3 mount '{}': the dashboard owns /", raw));
+        }
+        if TAKEN_ROOTS.contains(&root) {
+            return Err(format!("Invalid S3 mount '{}': /{} is used by other routes", raw, root));
+        }
+        let valid = |s: &str| !s.is_empty() && s != "." && s != ".." && !s.contains(['?', '#', '%']);
+        if !valid(root) || !segments.all(valid) {
+            return Err(format!("Invalid S3 mount '{}'", raw));
+        }
+        if !mounts.contains(&mount) {
+            mounts.push(mount);
+        }
+    }
+    if mounts.is_empty() {
+        return Err("Name at least one S3 mount, such as /s3".to_string());
+    }
+    Ok(mounts)
+}
+
+/// A request path under one of the mounts, resolved onto the canonical routes
+#[derive(Debug, PartialEq, Eq)]
+struct Resolved<'a> {
+    mount: &'a str,
+    canonical: String,
+    /// Addressed an object as `{mount}/obj/{bucket}/{key}`
+    legacy: bool,
+}
+
+/// Map `path` onto the canonical routes: `{mount}` and `{mount}/{bucket}`
+/// keep their shape, and objects, whether addressed as
+/// `{mount}/{bucket}/{key}` or `{mount}/obj/{bucket}/{key}`, land on
+/// `/s3/obj/{bucket}/{key}`. The longest matching mount wins.
+fn resolve<'a>(mounts: &'a [String], path: &str) -> Option<Resolved<'a>> {
+    let (mount, rest) = mounts
+        .iter()
+        .filter_map(|mount| {
+            let rest = path.strip_prefix(mount.as_str())?;
+            (rest.is_empty() || rest.starts_with('/')).then_some((mount.as_str(), rest))
+        })
+        .max_by_key(|(mount, _)| mount.len())?;
+
+    let legacy = rest
+        .strip_prefix('/')
+        .and_then(|r| r.strip_prefix(LEGACY_SEGMENT))
+        .and_then(|r| r.strip_prefix('/'))
+        .is_some_and(|r| !r.is_empty());
+    let object = legacy
+        || rest
+            .strip_prefix('/')
+            .and_then(|r| r.split_once('/'))
+            .is_some_and(|(bucket, key)| !bucket.is_empty() && !key.is_empty());
+    let canonical = if legacy {
+        format!("{}{}", CANONICAL_MOUNT, rest)
+    } else if object {
+        format!("{}/{}{}", CANONICAL_MOUNT, LEGACY_SEGMENT, rest)
+    } else {
+        format!("{}{}", CANONICAL_MOUNT, rest)
+    };
+    Some(Resolved { mount, canonical, legacy })
+}
+
+/// Point `req` at `path`, keeping its query
+pub fn set_path(req: &mut Request, path: &str) -> Result<(), AppError> {
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    *req.uri_mut() = path_and_query
+        .parse::<Uri>()
+        .map_err(|_| AppError::InvalidObjectKey(path_and_query))?;
+    Ok(())
+}
+
+/// Marks a request already rewritten onto the canonical routes, by
+/// virtual-hosted-style addressing
+#[derive(Clone, Copy)]
+pub struct Rewritten;
+
+/// Rewrite requests under the configured mounts onto the canonical routes.
+/// Must wrap the whole router, inside `virtual_host_rewrite`.
+pub async fn rewrite_mounts(State(state): State<Arc<AppState>>, mut req: Request, next: Next) -> Response {
+    if req.extensions().get::<Rewritten>().is_some() {
+        return next.run(req).await;
+    }
+    let config = state.config();
+    let path = req.uri().path();
+    let Some(resolved) = resolve(&config.s3_mounts, path) else {
+        let canonical = path == CANONICAL_MOUNT || path.starts_with(&format!("{}/", CANONICAL_MOUNT));
+        if canonical {
+            // Only reachable through a mount when /s3 itself isn't one
+            return AppError::RouteNotFound(format!(
+                "No route for {} {}. The S3 API is mounted at {}",
+                req.method(),
+                path,
+                config.s3_mounts.join(", ")
+            ))
+            .into_response();
+        }
+        return next.run(req).await;
+    };
+    if resolved.legacy {
+        state.s3_legacy.record(resolved.mount, path);
+    }
+    if let Err(e) = set_path(&mut req, &resolved.canonical) {
+        return e.into_response();
+    }
+    next.run(req).await
+}
+
+/// Requests that used the `{mount}/obj/` object shape, warned about at most
+/// once per `WARN_INTERVAL`
+#[derive(Default)]
+pub struct LegacyUses {
+    total: AtomicU64,
+    /// When the last warning went out, and the uses since
+    window: Mutex<(Option<Instant>, u64)>,
+}
+
+impl LegacyUses {
+    fn record(&self, mount: &str, path: &str) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        let mut window = self.window.lock().unwrap();
+        window.1 += 1;
+        if matches!(window.0, Some(warned) if warned.elapsed() < WARN_INTERVAL) {
+            return;
+        }
+        tracing::warn!(
+            "Deprecated S3 path {}: address objects as {}/{{bucket}}/{{key}}, without /{}/ \
+             ({} request(s) used the old shape since the last warning)",
+            path,
+            mount,
+            LEGACY_SEGMENT,
+            window.1
+        );
+        *window = (Some(Instant::now()), 0);
+    }
+
+    /// Requests that used the old shape since startup
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::storage::PutOptions;
+    use crate::testing::{body_string, TestServer};
+
+    #[test]
+    fn mounts_are_normalized_and_checked() {
+        assert_eq!(parse_mounts("s3/, /storage/v1 ,/s3").unwrap(), ["/s3", "/storage/v1"]);
+        assert!(parse_mounts("/").is_err());
+        assert!(parse_mounts("/api/s3").is_err());
+        assert!(parse_mounts("/files/../s3").is_err());
+        assert!(parse_mounts(" , ").is_err());
+    }
+
+    #[test]
+    fn objects_land_on_one_route_whatever_their_shape() {
+        let mounts = parse_mounts("/s3,/s3/v2").unwrap();
+        let canonical = |path| resolve(&mounts, path).map(|r| (r.mount, r.canonical, r.legacy));
+        let at = |mount, path: &str, legacy| Some((mount, path.to_string(), legacy));
+        assert_eq!(canonical("/s3"), at("/s3", "/s3", false));
+        assert_eq!(canonical("/s3/photos"), at("/s3", "/s3/photos", false));
+        assert_eq!(canonical("/s3/photos/a/b.txt"), at("/s3", "/s3/obj/photos/a/b.txt", false));
+        assert_eq!(canonical("/s3/obj/photos/a.txt"), at("/s3", "/s3/obj/photos/a.txt", true));
+        // The longest mount wins, and a bucket may be called "obj"
+        assert_eq!(canonical("/s3/v2/photos/a.txt"), at("/s3/v2", "/s3/obj/photos/a.txt", false));
+        assert_eq!(canonical("/s3/obj"), at("/s3", "/s3/obj", false));
+        assert_eq!(canonical("/s3x/photos"), None);
+        assert_eq!(canonical("/api/buckets"), None);
+    }
+
+    #[tokio::test]
+    async fn every_mounted_shape_answers_the_same() {
+        let server = TestServer::with_config(|c| c.s3_mounts = vec!["/s3".to_string(), "/storage/v1".to_string()]);
+        let storage = &server.state.storage;
+        storage.create_bucket("photos", "local").unwrap();
+        storage
+            .put_object("photos", "trips/a.txt", b"hello", PutOptions::default())
+            .unwrap();
+
+        let mut answers = Vec::new();
+        for path in [
+            "/s3/photos/trips/a.txt",
+            "/s3/obj/photos/trips/a.txt",
+            "/storage/v1/photos/trips/a.txt",
+            "/storage/v1/obj/photos/trips/a.txt",
+        ] {
+            let res = server.request("GET", path).await;
+            let headers = res.headers().clone();
+            let shown = |name: &str| headers.get(name).map(|v| v.to_str().unwrap().to_string());
+            let answer = (res.status(), shown("etag"), shown("content-type"), body_string(res).await);
+            answers.push(answer);
+        }
+        assert_eq!(answers[0].0, StatusCode::OK);
+        assert_eq!(answers[0].3, "hello");
+        assert!(answers.iter().all(|a| *a == answers[0]), "{:?}", answers);
+        assert_eq!(server.state.s3_legacy.total(), 2);
+
+        let listing = body_string(server.request("GET", "/storage/v1/photos?list-type=2").await).await;
+        assert!(listing.contains("<Key>trips/a.txt</Key>"), "{}", listing);
+    }
+
+    #[tokio::test]
+    async fn s3_is_only_served_where_it_is_mounted() {
+        let server = TestServer::with_config(|c| c.s3_mounts = vec!["/storage".to_string()]);
+        server.state.storage.create_bucket("photos", "local").unwrap();
+        assert_eq!(server.request("GET", "/storage/photos").await.status(), StatusCode::OK);
+
+        let res = server.request("GET", "/s3/photos").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body = body_string(res).await;
+        assert!(body.contains("The S3 API is mounted at /storage"), "{}", body);
+    }
+}
@@ -0,0 +1,175 @@
+//! XML bodies of the S3 API. Responses are small and flat, so they are
+//! written out directly instead of going through a serializer.
+
+use std::fmt::Display;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::error::ErrorCode;
+use crate::models::ErrorResponse;
+
+/// Namespace S3 puts on the root element of every result
+const S3_NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// One S3 XML document, built element by element
+pub struct XmlBody {
+    root: &'static str,
+    xml: String,
+}
+
+impl XmlBody {
+    /// A document whose root element carries the S3 namespace
+    pub fn new(root: &'static str) -> Self {
+        Self::start(root, &format!(" xmlns=\"{}\"", S3_NAMESPACE))
+    }
+
+    /// A document with a bare root element, as S3 sends for errors
+    pub fn bare(root: &'static str) -> Self {
+        Self::start(root, "")
+    }
+
+    fn start(root: &'static str, attributes: &str) -> Self {
+        Self {
+            root,
+            xml: format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<{}{}>", root, attributes),
+        }
+    }
+
+    /// `<name>value</name>`, with the value escaped
+    pub fn element(&mut self, name: &str, value: impl Display) -> &mut Self {
+        self.xml.push_str(&format!("<{}>{}</{}>", name, escape(&value.to_string()), name));
+        self
+    }
+
+    /// An element that is left out when there's no value
+    pub fn optional(&mut self, name: &str, value: Option<impl Display>) -> &mut Self {
+        if let Some(value) = value {
+            self.element(name, value);
+        }
+        self
+    }
+
+    /// Open an element whose children follow
+    pub fn open(&mut self, name: &str) -> &mut Self {
+        self.xml.push_str(&format!("<{}>", name));
+        self
+    }
+
+    pub fn close(&mut self, name: &str) -> &mut Self {
+        self.xml.push_str(&format!("</{}>", name));
+        self
+    }
+
+    /// Text directly inside the current element, escaped
+    pub fn text(&mut self, value: impl Display) -> &mut Self {
+        self.xml.push_str(&escape(&value.to_string()));
+        self
+    }
+
+    pub fn into_string(mut self) -> String {
+        self.xml.push_str(&format!("</{}>", self.root));
+        self.xml
+    }
+
+    /// The document as a response with the given status
+    pub fn with_status(self, status: StatusCode) -> Response {
+        (status, [(header::CONTENT_TYPE, "application/xml")], self.into_string()).into_response()
+    }
+}
+
+impl IntoResponse for XmlBody {
+    fn into_response(self) -> Response {
+        self.with_status(StatusCode::OK)
+    }
+}
+
+/// A timestamp the way S3 XML carries them, e.g. `2026-10-16T12:00:00.000Z`
+pub fn timestamp(time: &chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+/// Send the errors of the S3 routes the way S3 does: an `<Error>` document
+/// with the code, message, and resource. The rest of the API keeps JSON.
+/// Must wrap everything that can fail an S3 request, inside the rewrites
+/// that map mounts and virtual hosts onto `/s3`.
+pub async fn xml_errors(req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if path != "/s3" && !path.starts_with("/s3/") {
+        return next.run(req).await;
+    }
+    // What S3 calls the resource: `/bucket/key`, without the routing prefix
+    let resource = match path.strip_prefix("/s3/obj/") {
+        Some(object) => format!("/{}", object),
+        None if path == "/s3" => "/".to_string(),
+        None => path[3..].to_string(),
+    };
+    let head = req.method() == Method::HEAD;
+
+    let res = next.run(req).await;
+    if res.extensions().get::<ErrorCode>().is_none() {
+        return res;
+    }
+    let (mut parts, body) = res.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, 64 * 1024).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let Ok(error) = serde_json::from_slice::<ErrorResponse>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/xml"));
+    // HEAD responses only carry the status
+    if head {
+        return Response::from_parts(parts, Body::empty());
+    }
+    let mut xml = XmlBody::bare("Error");
+    xml.element("Code", &error.code)
+        .element("Message", &error.message)
+        .element("Resource", &resource);
+    Response::from_parts(parts, Body::from(xml.into_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_escaped_documents() {
+        let mut body = XmlBody::new("CopyPartResult");
+        body.element("ETag", "\"abc\"").optional("Missing", None::<&str>).open("Nested").element("Key", "a&b<c>").close("Nested");
+        assert_eq!(
+            body.into_string(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <CopyPartResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+             <ETag>&quot;abc&quot;</ETag><Nested><Key>a&amp;b&lt;c&gt;</Key></Nested></CopyPartResult>"
+        );
+    }
+
+    #[test]
+    fn errors_have_a_bare_root_and_text_roots_work() {
+        let mut error = XmlBody::bare("Error");
+        error.element("Code", "NoSuchKey");
+        assert!(error.into_string().ends_with("\n<Error><Code>NoSuchKey</Code></Error>"));
+        let mut location = XmlBody::new("LocationConstraint");
+        location.text("eu-west-1");
+        assert!(location.into_string().ends_with("2006-03-01/\">eu-west-1</LocationConstraint>"));
+    }
+
+    #[test]
+    fn timestamps_have_milliseconds_and_z() {
+        let time = chrono::DateTime::parse_from_rfc3339("2026-10-16T12:34:56.789123+00:00").unwrap();
+        assert_eq!(timestamp(&time.with_timezone(&chrono::Utc)), "2026-10-16T12:34:56.789Z");
+    }
+}
@@ -0,0 +1,274 @@
+//! Scans of quarantined uploads. Each bucket's scan command or scan webhook
+//! gives a verdict that approves or rejects the upload; a scan that fails or
+//! runs out of time leaves it pending and is tried again later, so nothing
+//! is published unscanned.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::models::{QuarantineConfig, QuarantinedObject};
+use crate::AppState;
+
+/// Pause before an upload whose scan gave no verdict is scanned again; also
+/// how often the scanner looks for such uploads
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+/// Longest rejection reason kept from a scan command's output
+const MAX_REASON_CHARS: usize = 1000;
+
+enum Verdict {
+    Approve,
+    Reject(String),
+}
+
+/// What a scan webhook answers
+#[derive(Deserialize)]
+struct ScanReply {
+    approved: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Scan quarantined uploads one at a time, oldest first, as they arrive.
+/// Uploads still pending at startup are picked up on the first pass.
+pub fn spawn_quarantine_scanner(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            let due_state = state.clone();
+            let due = tokio::task::spawn_blocking(move || due_state.storage.due_scans())
+                .await
+                .unwrap_or_default();
+            for (entry, config) in due {
+                scan(&state, entry, config).await;
+            }
+            tokio::select! {
+                _ = state.storage.quarantine_changed() => {}
+                _ = tokio::time::sleep(RETRY_DELAY) => {}
+            }
+        }
+    });
+}
+
+async fn scan(state: &Arc<AppState>, entry: QuarantinedObject, config: QuarantineConfig) {
+    let bucket = entry.object.bucket.clone();
+    let id = entry.id.clone();
+    let payload_state = state.clone();
+    let (payload_bucket, payload_id) = (bucket.clone(), id.clone());
+    let payload = tokio::task::spawn_blocking(move || payload_state.storage.quarantined_payload(&payload_bucket, &payload_id)).await;
+    // Decided by hand since it was listed
+    let Ok(Ok(payload)) = payload else {
+        return;
+    };
+
+    let run = async {
+        match (&config.command, &config.webhook) {
+            (Some(command), _) => run_command(command, &payload, &entry).await,
+            (None, Some(url)) => call_webhook(url, &payload, &entry).await,
+            (None, None) => Err("no scanner is configured".to_string()),
+        }
+    };
+    let verdict = match tokio::time::timeout(Duration::from_secs(config.timeout_secs), run).await {
+        Ok(verdict) => verdict,
+        Err(_) => Err(format!("no verdict within {}s", config.timeout_secs)),
+    };
+
+    let decide_state = state.clone();
+    let decided = tokio::task::spawn_blocking(move || {
+        let storage = &decide_state.storage;
+        match verdict {
+            Ok(Verdict::Approve) => storage.approve_quarantined(&bucket, &id).map(|_| ()),
+            Ok(Verdict::Reject(reason)) => storage.reject_quarantined(&bucket, &id, &reason).map(|_| ()),
+            Err(error) => {
+                tracing::warn!(
+                    "Scan of upload {} ({}/{}) failed; retrying in {}s: {}",
+                    id,
+                    bucket,
+                    entry.object.key,
+                    RETRY_DELAY.as_secs(),
+                    error
+                );
+                let retry_at = storage.now() + chrono::Duration::seconds(RETRY_DELAY.as_secs() as i64);
+                storage.record_failed_scan(&bucket, &id, &error, retry_at).map(|_| ())
+            }
+        }
+    })
+    .await;
+    match decided {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::warn!("Could not apply the scan verdict for upload {}: {:?}", entry.id, e),
+        Err(e) => tracing::error!("Applying a scan verdict panicked: {}", e),
+    }
+}
+
+/// Run a scan command through `sh -c`. The payload path is passed as `$1`,
+/// so no file name can break out of the command; the bucket, key, and
+/// upload ID are in the environment. Exit status 0 approves, 1 rejects,
+/// as with `clamscan`.
+#[cfg(unix)]
+async fn run_command(template: &str, payload: &Path, entry: &QuarantinedObject) -> Result<Verdict, String> {
+    let script = template.replace("{path}", "\"$1\"");
+    let output = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .arg("freebucket-scan")
+        .arg(payload)
+        .env("FREEBUCKET_BUCKET", &entry.object.bucket)
+        .env("FREEBUCKET_KEY", &entry.object.key)
+        .env("FREEBUCKET_UPLOAD_ID", &entry.id)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("cannot run the scan command: {}", e))?;
+    let said = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let said: String = said.trim().chars().take(MAX_REASON_CHARS).collect();
+    match output.status.code() {
+        Some(0) => Ok(Verdict::Approve),
+        Some(1) if said.is_empty() => Ok(Verdict::Reject("Rejected by the scan command".to_string())),
+        Some(1) => Ok(Verdict::Reject(said)),
+        Some(code) => Err(format!("scan command exited with status {}: {}", code, said)),
+        None => Err("scan command was killed by a signal".to_string()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn run_command(_template: &str, _payload: &Path, _entry: &QuarantinedObject) -> Result<Verdict, String> {
+    Err("scan commands need a Unix shell; use a scan webhook".to_string())
+}
+
+/// POST the upload's details to a scan webhook and read its verdict. The
+/// payload can be read from `path` on a shared disk or fetched from
+/// `content_url` on this server.
+async fn call_webhook(url: &str, payload: &Path, entry: &QuarantinedObject) -> Result<Verdict, String> {
+    let object = &entry.object;
+    let body = json!({
+        "id": entry.id,
+        "bucket": object.bucket,
+        "key": object.key,
+        "size": object.size,
+        "etag": object.etag,
+        "content_type": object.content_type,
+        "received_at": entry.received_at.to_rfc3339(),
+        "path": payload.display().to_string(),
+        "content_url": format!("/api/admin/quarantine/{}/{}/content", object.bucket, entry.id),
+    })
+    .to_string();
+    let (status, reply) = crate::webhooks::post_for_reply(url, &[("X-FreeBucket-Event", "ObjectQuarantined:Scan")], &body)
+        .await
+        .map_err(|e| format!("scan webhook {}: {}", url, e))?;
+    if !(200..300).contains(&status) {
+        return Err(format!("scan webhook {} answered {}", url, status));
+    }
+    let reply: ScanReply =
+        serde_json::from_str(&reply).map_err(|e| format!("scan webhook {} gave an unreadable verdict: {}", url, e))?;
+    Ok(if reply.approved {
+        Verdict::Approve
+    } else {
+        Verdict::Reject(reply.reason.unwrap_or_else(|| "Rejected by the scan webhook".to_string()))
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::models::{PendingStatus, QuarantineStatus};
+    use crate::storage::PutOptions;
+    use crate::testing::TestServer;
+
+    fn scanned_by(command: Option<&str>, webhook: Option<String>, timeout_secs: u64) -> TestServer {
+        let server = TestServer::new();
+        let storage = &server.state.storage;
+        storage.create_bucket("uploads", "local").unwrap();
+        let config = QuarantineConfig {
+            command: command.map(str::to_string),
+            webhook,
+            timeout_secs,
+            pending_status: PendingStatus::Locked,
+        };
+        storage.set_bucket_quarantine("uploads", Some(config)).unwrap();
+        storage
+            .put_object("uploads", "upload.bin", b"payload", PutOptions::default())
+            .unwrap();
+        server
+    }
+
+    /// Run the scans that are due, as one pass of the scanner would
+    async fn scan_due(server: &TestServer) {
+        for (entry, config) in server.state.storage.due_scans() {
+            scan(&server.state, entry, config).await;
+        }
+    }
+
+    fn entry(server: &TestServer) -> QuarantinedObject {
+        let mut entries = server.state.storage.list_quarantine(Some("uploads"), None).unwrap();
+        assert_eq!(entries.len(), 1);
+        entries.remove(0)
+    }
+
+    #[tokio::test]
+    async fn a_clean_scan_publishes_the_upload() {
+        let server = scanned_by(Some("test -s {path}"), None, 5);
+        scan_due(&server).await;
+        let (_, data) = server.state.storage.get_object("uploads", "upload.bin").unwrap();
+        assert_eq!(&data[..], b"payload");
+        assert!(server.state.storage.list_quarantine(None, None).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_failing_scan_rejects_with_its_output() {
+        let server = scanned_by(Some("echo \"$FREEBUCKET_KEY: Eicar-Signature FOUND\"; exit 1"), None, 5);
+        scan_due(&server).await;
+        let rejected = entry(&server);
+        assert_eq!(rejected.status, QuarantineStatus::Rejected);
+        assert_eq!(rejected.reason.as_deref(), Some("upload.bin: Eicar-Signature FOUND"));
+        assert!(server.state.storage.get_object("uploads", "upload.bin").is_err());
+    }
+
+    #[tokio::test]
+    async fn a_scan_that_times_out_is_retried_later() {
+        let server = scanned_by(Some("sleep 10"), None, 1);
+        scan_due(&server).await;
+        let pending = entry(&server);
+        assert_eq!(pending.status, QuarantineStatus::Pending);
+        assert_eq!(pending.failed_scans, 1);
+        assert_eq!(pending.last_error.as_deref(), Some("no verdict within 1s"));
+        assert!(pending.retry_at.unwrap() > server.state.storage.now());
+        // Not scanned again until the retry is due
+        assert!(server.state.storage.due_scans().is_empty());
+
+        // Other exit statuses are failures too, not verdicts
+        let server = scanned_by(Some("exit 2"), None, 5);
+        scan_due(&server).await;
+        let pending = entry(&server);
+        assert_eq!((pending.status, pending.failed_scans), (QuarantineStatus::Pending, 1));
+    }
+
+    #[tokio::test]
+    async fn a_webhook_gives_the_verdict() {
+        use axum::{routing::post, Json, Router};
+
+        let verdict = Router::new().route(
+            "/scan",
+            post(|Json(upload): Json<serde_json::Value>| async move {
+                let clean = upload["key"] == "upload.bin" && upload["size"] == 7;
+                Json(json!({ "approved": false, "reason": format!("clean: {}", clean) }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/scan", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, verdict).await.unwrap() });
+
+        let server = scanned_by(None, Some(url), 5);
+        scan_due(&server).await;
+        let rejected = entry(&server);
+        assert_eq!(rejected.status, QuarantineStatus::Rejected);
+        assert_eq!(rejected.reason.as_deref(), Some("clean: true"));
+    }
+}
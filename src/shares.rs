@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::clock::Sources;
+use crate::error::AppError;
+use crate::models::Share;
+
+/// How long a share lasts when the request doesn't say
+pub const DEFAULT_EXPIRY_SECS: u64 = 7 * 24 * 3600;
+
+/// The longest a share can last
+pub const MAX_EXPIRY_SECS: u64 = 365 * 24 * 3600;
+
+/// Expired and used up shares are kept this long after they expire, so
+/// their links say so instead of claiming they never existed
+const KEEP_ENDED_DAYS: i64 = 30;
+
+/// Cookie a share's page sets once its password is given
+pub const UNLOCK_COOKIE: &str = "freebucket_share";
+
+/// A share as saved, with the hash of its password, which never leaves
+/// the server
+#[derive(Clone, Serialize, Deserialize)]
+struct SavedShare {
+    #[serde(flatten)]
+    share: Share,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+}
+
+/// Share links by token, saved to a file on every change. Downloads are
+/// counted under the table's lock, so a link allowing three downloads
+/// serves three however many people click it at once.
+pub struct ShareTable {
+    shares: Mutex<HashMap<String, SavedShare>>,
+    path: PathBuf,
+    sources: Sources,
+}
+
+impl ShareTable {
+    pub fn new(path: PathBuf, sources: Sources) -> Self {
+        let mut shares = HashMap::new();
+        match fs::read(&path).map(|data| serde_json::from_slice::<Vec<SavedShare>>(&data)) {
+            Ok(Ok(saved)) => {
+                for saved in saved {
+                    shares.insert(saved.share.token.clone(), saved);
+                }
+                tracing::info!("Loaded {} share link(s) from {}", shares.len(), path.display());
+            }
+            Ok(Err(e)) => tracing::warn!("Ignoring unreadable share file {}: {}", path.display(), e),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Cannot read share file {}: {}", path.display(), e),
+        }
+        Self {
+            shares: Mutex::new(shares),
+            path,
+            sources,
+        }
+    }
+
+    /// Share `key` of `bucket`, or everything under it with `prefix`. The
+    /// password is hashed by the caller.
+    pub fn create(
+        &self,
+        bucket: &str,
+        key: &str,
+        prefix: bool,
+        expires_in_secs: u64,
+        max_downloads: Option<u64>,
+        password_hash: Option<String>,
+    ) -> Share {
+        let now = self.sources.now();
+        let share = Share {
+            token: new_token(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            prefix,
+            created_at: now,
+            expires_at: now + chrono::Duration::seconds(expires_in_secs as i64),
+            max_downloads,
+            downloads: 0,
+            password: password_hash.is_some(),
+        };
+        let mut shares = self.shares.lock().unwrap();
+        self.prune(&mut shares);
+        shares.insert(
+            share.token.clone(),
+            SavedShare {
+                share: share.clone(),
+                password_hash,
+            },
+        );
+        self.save(&shares);
+        share
+    }
+
+    /// Shares by creation time, optionally only those of one bucket
+    pub fn list(&self, bucket: Option<&str>) -> Vec<Share> {
+        let mut shares = self.shares.lock().unwrap();
+        self.prune(&mut shares);
+        let mut listed: Vec<Share> = shares
+            .values()
+            .filter(|saved| bucket.is_none_or(|b| saved.share.bucket == b))
+            .map(|saved| saved.share.clone())
+            .collect();
+        listed.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.token.cmp(&b.token)));
+        listed
+    }
+
+    /// A share that can still be downloaded from, and its password hash
+    pub fn get(&self, token: &str) -> Result<(Share, Option<String>), AppError> {
+        let mut shares = self.shares.lock().unwrap();
+        self.prune(&mut shares);
+        let saved = shares.get(token).ok_or_else(|| AppError::ShareNotFound(token.to_string()))?;
+        self.check_live(&saved.share)?;
+        Ok((saved.share.clone(), saved.password_hash.clone()))
+    }
+
+    /// Count a download, unless the share ended since it was looked up
+    pub fn claim(&self, token: &str) -> Result<Share, AppError> {
+        let mut shares = self.shares.lock().unwrap();
+        let saved = shares
+            .get_mut(token)
+            .ok_or_else(|| AppError::ShareNotFound(token.to_string()))?;
+        self.check_live(&saved.share)?;
+        saved.share.downloads += 1;
+        let share = saved.share.clone();
+        self.save(&shares);
+        Ok(share)
+    }
+
+    pub fn revoke(&self, token: &str) -> Result<Share, AppError> {
+        let mut shares = self.shares.lock().unwrap();
+        let saved = shares
+            .remove(token)
+            .ok_or_else(|| AppError::ShareNotFound(token.to_string()))?;
+        self.save(&shares);
+        Ok(saved.share)
+    }
+
+    fn check_live(&self, share: &Share) -> Result<(), AppError> {
+        if share.expires_at <= self.sources.now() {
+            return Err(AppError::ShareExpired(share.token.clone()));
+        }
+        if share.remaining_downloads() == Some(0) {
+            return Err(AppError::ShareExhausted(share.token.clone()));
+        }
+        Ok(())
+    }
+
+    fn prune(&self, shares: &mut HashMap<String, SavedShare>) {
+        let cutoff = self.sources.now() - chrono::Duration::days(KEEP_ENDED_DAYS);
+        let before = shares.len();
+        shares.retain(|_, saved| saved.share.expires_at > cutoff);
+        if shares.len() != before {
+            self.save(shares);
+        }
+    }
+
+    fn save(&self, shares: &HashMap<String, SavedShare>) {
+        let saved: Vec<&SavedShare> = shares.values().collect();
+        let tmp = self.path.with_extension("json.tmp");
+        let result = fs::write(&tmp, serde_json::to_vec(&saved).unwrap_or_default())
+            .and_then(|_| fs::rename(&tmp, &self.path));
+        if let Err(e) = result {
+            tracing::warn!("Cannot save share links to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// A short token that can't be guessed. It is random even under
+/// `FREEBUCKET_FAKE_IDS`, since a predictable one would give the file away.
+fn new_token() -> String {
+    let random = uuid::Uuid::new_v4();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&random.as_bytes()[..12])
+}
+
+/// Value of the cookie showing a share's password was given. Working it
+/// out takes the password's hash, which only the server has.
+pub fn unlock_value(token: &str, password_hash: &str) -> String {
+    hex::encode(Sha256::digest(format!("{}\n{}", token, password_hash)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::*;
+    use crate::clock::{Clock, SequentialIds};
+
+    /// A clock the test moves by hand
+    #[derive(Clone)]
+    struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+    impl ManualClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn table(dir: &tempfile::TempDir) -> (ShareTable, ManualClock) {
+        let clock = ManualClock(Arc::new(Mutex::new("2026-03-01T12:00:00Z".parse().unwrap())));
+        let sources = Sources::new(clock.clone(), SequentialIds::default());
+        (ShareTable::new(dir.path().join(".shares.json"), sources), clock)
+    }
+
+    #[test]
+    fn expires_and_is_dropped_a_month_later() {
+        let dir = tempfile::tempdir().unwrap();
+        let (shares, clock) = table(&dir);
+        let share = shares.create("docs", "report.pdf", false, 60, None, None);
+        assert_eq!(share.token.len(), 16);
+        assert!(shares.get(&share.token).is_ok());
+
+        clock.advance(Duration::seconds(60));
+        assert!(matches!(shares.get(&share.token), Err(AppError::ShareExpired(_))));
+        assert!(matches!(shares.claim(&share.token), Err(AppError::ShareExpired(_))));
+        assert_eq!(shares.list(Some("docs")).len(), 1, "ended links stay listed");
+
+        clock.advance(Duration::days(KEEP_ENDED_DAYS));
+        assert!(matches!(shares.get(&share.token), Err(AppError::ShareNotFound(_))));
+        assert!(shares.list(None).is_empty());
+    }
+
+    #[test]
+    fn counts_each_download_once_however_many_race() {
+        let dir = tempfile::tempdir().unwrap();
+        let (shares, _) = table(&dir);
+        let share = shares.create("docs", "report.pdf", false, 3600, Some(3), None);
+
+        let served = std::thread::scope(|scope| {
+            let claims: Vec<_> = (0..8).map(|_| scope.spawn(|| shares.claim(&share.token))).collect();
+            claims.into_iter().filter_map(|c| c.join().unwrap().ok()).count()
+        });
+        assert_eq!(served, 3);
+        assert!(matches!(shares.get(&share.token), Err(AppError::ShareExhausted(_))));
+        let listed = &shares.list(None)[0];
+        assert_eq!((listed.downloads, listed.remaining_downloads()), (3, Some(0)));
+    }
+
+    #[test]
+    fn revoked_links_are_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (shares, _) = table(&dir);
+        let share = shares.create("docs", "", true, 3600, None, None);
+        assert_eq!(shares.revoke(&share.token).unwrap().token, share.token);
+        assert!(matches!(shares.get(&share.token), Err(AppError::ShareNotFound(_))));
+        assert!(matches!(shares.revoke(&share.token), Err(AppError::ShareNotFound(_))));
+    }
+
+    #[test]
+    fn outlives_a_restart_without_showing_the_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let token = {
+            let (shares, _) = table(&dir);
+            let share = shares.create("docs", "report.pdf", false, 3600, Some(2), Some("$2b$04$hash".to_string()));
+            shares.claim(&share.token).unwrap();
+            share.token
+        };
+        let saved = fs::read_to_string(dir.path().join(".shares.json")).unwrap();
+        assert!(saved.contains("$2b$04$hash"));
+
+        let (shares, _) = table(&dir);
+        let (share, hash) = shares.get(&token).unwrap();
+        assert_eq!((share.downloads, share.password), (1, true));
+        assert_eq!(hash.as_deref(), Some("$2b$04$hash"));
+        assert!(!serde_json::to_string(&shares.list(None)).unwrap().contains("$2b$"));
+        // The unlock cookie depends on the hash, so a new password voids it
+        assert_ne!(unlock_value(&token, "$2b$04$hash"), unlock_value(&token, "$2b$04$other"));
+    }
+}
@@ -0,0 +1,262 @@
+//! Byte sizes for people: shown in binary (KiB, MiB) or decimal (KB, MB)
+//! units as configured, and read back from either.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Deserializer};
+
+pub const KIB: u64 = 1024;
+pub const MIB: u64 = 1024 * KIB;
+
+/// Which units sizes are shown in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeUnits {
+    /// Powers of 1024: KiB, MiB, GiB
+    Binary,
+    /// Powers of 1000: KB, MB, GB, as `ls --si` and disk vendors count
+    Decimal,
+}
+
+impl SizeUnits {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "binary" | "iec" => Some(SizeUnits::Binary),
+            "decimal" | "si" => Some(SizeUnits::Decimal),
+            _ => None,
+        }
+    }
+
+    fn step(self) -> u64 {
+        match self {
+            SizeUnits::Binary => 1024,
+            SizeUnits::Decimal => 1000,
+        }
+    }
+
+    fn names(self) -> &'static [&'static str] {
+        match self {
+            SizeUnits::Binary => &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"],
+            SizeUnits::Decimal => &["B", "KB", "MB", "GB", "TB", "PB", "EB"],
+        }
+    }
+}
+
+/// Units `human_readable_size` uses, set from the configuration. Sizes are
+/// shown from storage and CLI code that has no configuration at hand.
+static DECIMAL_UNITS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_units(units: SizeUnits) {
+    DECIMAL_UNITS.store(units == SizeUnits::Decimal, Ordering::Relaxed);
+}
+
+pub fn units() -> SizeUnits {
+    if DECIMAL_UNITS.load(Ordering::Relaxed) {
+        SizeUnits::Decimal
+    } else {
+        SizeUnits::Binary
+    }
+}
+
+/// A size in the configured units, such as `1.50 GiB` or `1.61 GB`
+pub fn human_readable_size(bytes: u64) -> String {
+    format_size(bytes, units())
+}
+
+pub fn format_size(bytes: u64, units: SizeUnits) -> String {
+    let names = units.names();
+    let step = units.step();
+    if bytes < step {
+        return format!("{} {}", bytes, names[0]);
+    }
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= step as f64 && unit < names.len() - 1 {
+        size /= step as f64;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, names[unit])
+}
+
+/// Bytes in one of a unit, by its lowercase name. `K`, `KB` are decimal;
+/// `Ki`, `KiB` are binary.
+fn unit_bytes(unit: &str) -> Option<u64> {
+    let (prefix, binary) = match unit.strip_suffix('b').unwrap_or(unit) {
+        "" => return Some(1),
+        prefix => match prefix.strip_suffix('i') {
+            Some(prefix) => (prefix, true),
+            None => (prefix, false),
+        },
+    };
+    let power = match prefix {
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        "e" => 6,
+        _ => return None,
+    };
+    Some(if binary { 1024u64.pow(power) } else { 1000u64.pow(power) })
+}
+
+/// Read a size such as `512`, `64KB`, `1.5GiB`, or `2 m`. A bare number is
+/// bytes; unit names ignore case. Fractions of a byte are dropped.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let trimmed = text.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    if number.is_empty() || number == "." {
+        return Err(format!("Invalid size '{}': expected a number such as 512, 64KB, or 1.5GiB", text));
+    }
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if fraction.contains('.') {
+        return Err(format!("Invalid size '{}': the number has more than one decimal point", text));
+    }
+    let unit_name = unit.trim().to_ascii_lowercase();
+    let multiplier = unit_bytes(&unit_name).ok_or_else(|| {
+        format!(
+            "Invalid size '{}': unknown unit '{}'; use B, KB, MB, GB, TB, PB, EB or KiB, MiB, GiB, TiB, PiB, EiB",
+            text,
+            unit.trim()
+        )
+    })?;
+
+    let too_large = || format!("Invalid size '{}': larger than the largest size, {} bytes", text, u64::MAX);
+    let whole: u128 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().map_err(|_| too_large())?
+    };
+    let mut bytes = whole.checked_mul(multiplier as u128).ok_or_else(too_large)?;
+    // Digits past the 19th can't add a whole byte at any unit up to EiB
+    let fraction = &fraction[..fraction.len().min(19)];
+    if !fraction.is_empty() {
+        let digits: u128 = fraction.parse().map_err(|_| too_large())?;
+        bytes += digits * multiplier as u128 / 10u128.pow(fraction.len() as u32);
+    }
+    u64::try_from(bytes).map_err(|_| too_large())
+}
+
+/// A size field that takes either a byte count or a string such as `"10MiB"`
+pub fn deserialize_optional_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Size::Text(text)) => parse_size(&text).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_in_either_units() {
+        assert_eq!(format_size(0, SizeUnits::Binary), "0 B");
+        assert_eq!(format_size(1023, SizeUnits::Binary), "1023 B");
+        assert_eq!(format_size(1024, SizeUnits::Binary), "1.00 KiB");
+        assert_eq!(format_size(1536 * MIB, SizeUnits::Binary), "1.50 GiB");
+        assert_eq!(format_size(999, SizeUnits::Decimal), "999 B");
+        assert_eq!(format_size(1000, SizeUnits::Decimal), "1.00 KB");
+        assert_eq!(format_size(1536 * MIB, SizeUnits::Decimal), "1.61 GB");
+        assert_eq!(format_size(u64::MAX, SizeUnits::Binary), "16.00 EiB");
+        assert_eq!(format_size(u64::MAX, SizeUnits::Decimal), "18.45 EB");
+    }
+
+    #[test]
+    fn parses_numbers_with_and_without_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size(" 64KB "), Ok(64_000));
+        assert_eq!(parse_size("64kib"), Ok(64 * KIB));
+        assert_eq!(parse_size("2 m"), Ok(2_000_000));
+        assert_eq!(parse_size("2Mi"), Ok(2 * MIB));
+        assert_eq!(parse_size("1.5GiB"), Ok(1536 * MIB));
+        assert_eq!(parse_size(".5KiB"), Ok(512));
+        assert_eq!(parse_size("1.5"), Ok(1), "fractions of a byte are dropped");
+        assert_eq!(parse_size("0B"), Ok(0));
+        assert_eq!(parse_size("1EiB"), Ok(1 << 60));
+    }
+
+    #[test]
+    fn sizes_up_to_u64_max_parse_and_larger_ones_fail() {
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+        assert_eq!(parse_size("18446744073709551615B"), Ok(u64::MAX));
+        assert_eq!(parse_size("18.446744073709551615EB"), Ok(u64::MAX));
+        assert_eq!(parse_size("15.999999999999999999EiB"), Ok(u64::MAX - 1));
+        assert_eq!(parse_size("16EB"), Ok(16_000_000_000_000_000_000));
+        for text in [
+            "18446744073709551616",
+            "18.446744073709551616EB",
+            "16EiB",
+            "16.0000000000000000001EiB",
+            "19EB",
+            "99999999999999999999999999999999999999999",
+        ] {
+            let err = parse_size(text).unwrap_err();
+            assert!(err.contains("larger than the largest size"), "{}: {}", text, err);
+        }
+        // What the formatter rounds up to at the very top doesn't parse back
+        assert!(parse_size(&format_size(u64::MAX, SizeUnits::Binary)).is_err());
+    }
+
+    #[test]
+    fn malformed_sizes_say_what_is_wrong() {
+        for (text, problem) in [
+            ("", "expected a number"),
+            ("GB", "expected a number"),
+            (".", "expected a number"),
+            ("-1", "expected a number"),
+            ("1.5.2GB", "more than one decimal point"),
+            ("10 XB", "unknown unit 'XB'"),
+            ("1e3", "unknown unit 'e3'"),
+            ("5 GiBs", "unknown unit 'GiBs'"),
+        ] {
+            let err = parse_size(text).unwrap_err();
+            assert!(err.contains(problem), "{:?}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn formatted_sizes_parse_back_to_about_the_same_value() {
+        for units in [SizeUnits::Binary, SizeUnits::Decimal] {
+            for bytes in [1, 999, 1000, 1024, 123_456_789, 5 * (1 << 40), u64::MAX / 3] {
+                let parsed = parse_size(&format_size(bytes, units)).unwrap();
+                // Two decimals keep the value within 0.5% for anything over a unit
+                let error = parsed.abs_diff(bytes) as f64 / bytes as f64;
+                assert!(error < 0.005, "{} -> {} ({:?})", bytes, parsed, units);
+            }
+        }
+    }
+
+    #[test]
+    fn size_fields_take_numbers_or_strings() {
+        #[derive(Deserialize)]
+        struct Limits {
+            #[serde(default, deserialize_with = "deserialize_optional_size")]
+            max_size: Option<u64>,
+        }
+        let max_size = |json: &str| serde_json::from_str::<Limits>(json).map(|l| l.max_size);
+        assert_eq!(max_size(r#"{"max_size": 1048576}"#).unwrap(), Some(MIB));
+        assert_eq!(max_size(r#"{"max_size": "10MiB"}"#).unwrap(), Some(10 * MIB));
+        assert_eq!(max_size(r#"{"max_size": null}"#).unwrap(), None);
+        assert_eq!(max_size("{}").unwrap(), None);
+        let err = max_size(r#"{"max_size": "10 parsecs"}"#).err().unwrap();
+        assert!(err.to_string().contains("unknown unit"), "{}", err);
+    }
+
+    #[test]
+    fn unit_names_parse_in_either_spelling() {
+        assert_eq!(SizeUnits::parse("Binary"), Some(SizeUnits::Binary));
+        assert_eq!(SizeUnits::parse(" iec "), Some(SizeUnits::Binary));
+        assert_eq!(SizeUnits::parse("SI"), Some(SizeUnits::Decimal));
+        assert_eq!(SizeUnits::parse("metric"), None);
+    }
+}
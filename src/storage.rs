@@ -1,52 +1,345 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use sha2::{Digest, Sha256};
 
+use crate::clock::Sources;
 use crate::error::AppError;
-use crate::models::{Bucket, ListObjectsResponse, ObjectMeta, StorageStats};
+use crate::models::{
+    Bucket, BucketDetails, BucketStats, ChecksumType, DeleteError, DeleteObjectsResult, ListObjectsResponse, ListOrder,
+    ListSort, ModifiedWindow, ObjectAccess, ObjectChecksum, ObjectMeta, OverwritePolicy, PrefixStats, SortOrder,
+    StorageStats,
+};
+use crate::size::human_readable_size;
+
+mod access_log;
+mod alerts;
+mod access_tracking;
+mod audit;
+mod backup;
+mod bucket_meta;
+mod bulk;
+mod checksum;
+mod content_type;
+mod deferred_hash;
+mod deleted_buckets;
+mod events;
+mod fs_compat;
+mod gc;
+mod hash_index;
+mod inbox;
+mod inventory;
+mod key_case;
+mod key_locks;
+mod key_names;
+mod key_paths;
+mod manifest;
+mod metadata_limits;
+mod multipart;
+mod object_limit;
+mod op_timing;
+mod origin;
+mod overwrite;
+mod precompressed;
+mod quarantine;
+mod range_write;
+mod read_cache;
+mod read_refs;
+mod reconcile;
+mod search;
+mod self_test;
+mod snapshots;
+mod sparse;
+mod stats_history;
+mod stats_journal;
+mod stats_recompute;
+mod traffic;
+mod transactions;
+mod versions;
+mod volumes;
+mod walk;
+
+pub use audit::Audit;
+pub use backup::{restore_backup, verify_backup};
+pub use checksum::ChecksumRequest;
+pub(crate) use content_type::check_content_type;
+pub use deferred_hash::HashMode;
+pub use gc::GcOptions;
+pub use key_case::KeyCaseMode;
+pub use events::{EventKind, ObjectEvent, EVENT_SOURCE};
+pub use fs_compat::IoRetry;
+pub use manifest::ManifestChange;
+pub use metadata_limits::{metadata_size, MetadataLimits};
+pub use op_timing::{OpHistogram, OP_BUCKETS};
+pub use precompressed::ContentEncoding;
+pub use read_refs::DeletePolicy;
+pub use reconcile::{ReconcileMode, ReconcileOptions};
+pub use stats_journal::{read_stats_journal, replay_stats_journal};
+pub use traffic::TRAFFIC_HOURS;
+pub use volumes::DEFAULT_VOLUME;
+pub use walk::SortedKeys;
+use access_tracking::AccessTracker;
+use hash_index::HashIndex;
+use key_locks::KeyLocks;
+use self_test::is_system_bucket;
+use sparse::SparseWriter;
+use read_refs::ReadRefs;
+use stats_journal::JournaledBuckets;
+
+/// Objects listed in a bucket's details
+const RECENT_OBJECTS: usize = 5;
+
+/// Bucket names that collide with top-level routes. Config can add more.
+const RESERVED_BUCKET_NAMES: &[&str] = &["api", "s3", "assets", "healthz", "readyz", "metrics", "buckets", "inbox", "share", "dav", "obj"];
 
 /// File-system backed storage engine
 pub struct StorageEngine {
     root: PathBuf,
     /// In-memory bucket metadata index (persisted to disk)
     buckets: RwLock<HashMap<String, Bucket>>,
+    /// Content hash → object locations, for duplicate detection
+    hash_index: RwLock<HashIndex>,
+    /// Temp files belonging to writes that are still in progress
+    active_temp: Mutex<HashSet<PathBuf>>,
+    reconcile: ReconcileOptions,
+    /// Buffer size for copying data between files
+    write_buffer_size: usize,
+    /// Buckets still waiting for their lazy reconcile pass
+    unreconciled: Mutex<HashSet<String>>,
+    /// Objects with a GET in progress
+    read_refs: ReadRefs,
+    /// Objects with a PUT or range write in progress
+    key_locks: KeyLocks,
+    /// Held exclusively while a transaction swaps its objects in, so object
+    /// reads and listings see all of it or none of it
+    commit_gate: RwLock<()>,
+    delete_policy: DeletePolicy,
+    /// How long a delete waits for readers under `DeletePolicy::Wait`
+    delete_wait: Duration,
+    /// Names new buckets can't take, sorted
+    reserved_names: Vec<String>,
+    /// Threads that share a directory walk
+    walk_workers: usize,
+    /// Per-object download counters
+    access: AccessTracker,
+    access_tracking: bool,
+    /// Buckets with stats changes only in their journal so far
+    journaled: JournaledBuckets,
+    normalize_keys: bool,
+    max_key_depth: usize,
+    /// Retries of file operations failing with transient errors
+    io_retry: IoRetry,
+    /// What the data dir's filesystem supports
+    fs_features: crate::models::FilesystemFeatures,
+    /// Writes are checked for keys differing only in case from existing ones
+    key_case_checked: bool,
+    /// Object limit given to new buckets
+    default_max_objects: Option<u64>,
+    events: events::EventSink,
+    alerts: alerts::ActiveAlerts,
+    op_timings: op_timing::OpTimings,
+    /// Operations taking at least this long are logged; zero disables
+    slow_op_threshold: Duration,
+    /// Named roots besides the data dir, by name
+    volumes: BTreeMap<String, PathBuf>,
+    /// Root of each bucket that isn't in the data dir
+    bucket_roots: RwLock<HashMap<String, PathBuf>>,
+    sources: Sources,
+    quarantine: quarantine::Quarantine,
+    /// Per-bucket request and byte counters
+    traffic: traffic::TrafficTracker,
+    /// Bounds on the user metadata of new writes
+    metadata_limits: MetadataLimits,
+    read_cache: read_cache::ReadCache,
+    deferred_hashing: deferred_hash::DeferredHashing,
+    audit: audit::AuditLog,
+    /// Latest statistics recompute of each bucket
+    recomputes: stats_recompute::Recomputes,
 }
 
-struct WalkContext<'a> {
-    root: &'a Path,
-    bucket: &'a str,
-    prefix: &'a str,
-    delimiter: Option<&'a str>,
-    objects: &'a mut Vec<ObjectMeta>,
-    common_prefixes: &'a mut Vec<String>,
+/// What `put_object` stores with the payload besides its bytes
+#[derive(Debug, Default)]
+pub struct PutOptions<'a> {
+    /// Sniffed from the content and the key when not given
+    pub content_type: Option<&'a str>,
+    pub metadata: HashMap<String, String>,
+    /// Checksum the client sent, verified before anything is written
+    pub checksum: Option<&'a ChecksumRequest>,
+    pub original_filename: Option<&'a str>,
+    pub website_redirect_location: Option<&'a str>,
+    /// Modification time of the file the object was uploaded from
+    pub source_mtime: Option<DateTime<Utc>>,
+}
+
+/// Settings the engine is opened with
+pub struct StorageOptions {
+    pub reconcile: ReconcileOptions,
+    /// Buffer size for copying data between files; smaller saves memory
+    /// per concurrent write on small machines
+    pub write_buffer_size: usize,
+    /// What a delete does while the object is being read
+    pub delete_policy: DeletePolicy,
+    pub delete_wait: Duration,
+    /// Bucket names reserved on top of `RESERVED_BUCKET_NAMES`
+    pub reserved_bucket_names: Vec<String>,
+    /// Threads used to walk directory trees for listings and recounts
+    pub walk_threads: usize,
+    /// Count downloads and remember when each object was last read
+    pub access_tracking: bool,
+    /// Store keys in NFC form, so composed and decomposed spellings of a
+    /// name are the same object
+    pub normalize_keys: bool,
+    /// Most `/`-separated segments a key may have, which bounds how deep
+    /// the directories under `objects/` go
+    pub max_key_depth: usize,
+    /// What writes of keys differing only in case do where the filesystem
+    /// ignores case
+    pub key_case: KeyCaseMode,
+    /// Retries of file operations failing with transient errors, for data
+    /// dirs on network filesystems
+    pub io_retry: IoRetry,
+    /// Object limit given to new buckets; `None` leaves them unlimited
+    pub default_max_objects: Option<u64>,
+    /// Warn about storage operations taking at least this long; zero
+    /// disables the warnings
+    pub slow_op_threshold: Duration,
+    /// Named storage roots buckets can be placed on besides the data dir
+    pub volumes: Vec<(String, PathBuf)>,
+    /// Where timestamps and IDs come from; fakes make runs reproducible
+    pub sources: Sources,
+    /// Bounds on the user metadata of new writes
+    pub metadata_limits: MetadataLimits,
+    /// Bytes of small objects kept in memory for reads; 0 disables it
+    pub read_cache_size: u64,
+    /// Largest object the read cache holds
+    pub read_cache_max_object: u64,
+    /// Whether PUTs hash their payload before answering or leave it to a
+    /// background worker
+    pub hash_mode: HashMode,
+    /// Size at which the audit log is rotated
+    pub audit_log_max_size: u64,
+}
+
+/// A scratch file (or directory) for an in-flight write. Garbage collection
+/// skips registered paths; dropping the guard without committing deletes it.
+struct TempFile<'a> {
+    engine: &'a StorageEngine,
+    path: PathBuf,
+}
+
+impl TempFile<'_> {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Move the fully written file into place. Renaming instead of writing
+    /// in place gives the object a fresh inode, so hard-linked snapshots keep
+    /// their bytes and readers never observe a half-written file.
+    fn commit(self, dest: &Path) -> Result<(), AppError> {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.engine.rename_file(&self.path, dest)?;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile<'_> {
+    fn drop(&mut self) {
+        if self.path.is_dir() {
+            let _ = fs::remove_dir_all(&self.path);
+        } else if self.path.exists() {
+            let _ = fs::remove_file(&self.path);
+        }
+        self.engine.active_temp.lock().unwrap().remove(&self.path);
+    }
 }
 
 impl StorageEngine {
     /// Initialize the storage engine, creating the root data directory if needed
-    pub fn new(root: &str) -> Result<Self, AppError> {
+    pub fn new(root: &str, options: StorageOptions) -> Result<Self, AppError> {
         let root = PathBuf::from(root);
         fs::create_dir_all(&root)
             .map_err(|e| AppError::StorageError(format!("Cannot create data dir: {}", e)))?;
+        // Before any key is mapped to a path
+        let fs_features = fs_compat::probe_features(&root);
+        let key_case_checked = key_case::configure(options.key_case, fs_features.case_sensitive);
 
         let engine = Self {
             root: root.clone(),
             buckets: RwLock::new(HashMap::new()),
+            hash_index: RwLock::new(HashIndex::default()),
+            active_temp: Mutex::new(HashSet::new()),
+            reconcile: options.reconcile,
+            write_buffer_size: options.write_buffer_size.max(1),
+            unreconciled: Mutex::new(HashSet::new()),
+            read_refs: ReadRefs::default(),
+            key_locks: KeyLocks::default(),
+            commit_gate: RwLock::new(()),
+            delete_policy: options.delete_policy,
+            delete_wait: options.delete_wait,
+            reserved_names: {
+                let mut names: Vec<String> = RESERVED_BUCKET_NAMES.iter().map(|n| n.to_string()).collect();
+                names.extend(options.reserved_bucket_names);
+                names.sort();
+                names.dedup();
+                names
+            },
+            walk_workers: options.walk_threads.max(1),
+            access: AccessTracker::default(),
+            access_tracking: options.access_tracking,
+            journaled: JournaledBuckets::default(),
+            normalize_keys: options.normalize_keys,
+            max_key_depth: options.max_key_depth,
+            io_retry: options.io_retry,
+            fs_features,
+            key_case_checked,
+            default_max_objects: options.default_max_objects,
+            events: Default::default(),
+            alerts: Default::default(),
+            op_timings: Default::default(),
+            slow_op_threshold: options.slow_op_threshold,
+            volumes: volumes::check_volumes(options.volumes)?,
+            bucket_roots: RwLock::new(HashMap::new()),
+            sources: options.sources,
+            quarantine: Default::default(),
+            traffic: Default::default(),
+            metadata_limits: options.metadata_limits,
+            read_cache: read_cache::ReadCache::new(options.read_cache_size, options.read_cache_max_object),
+            deferred_hashing: deferred_hash::DeferredHashing::new(options.hash_mode),
+            audit: audit::AuditLog::new(options.audit_log_max_size),
+            recomputes: Default::default(),
         };
 
         // Load existing buckets from disk
         engine.scan_buckets()?;
+        engine.load_traffic();
+        engine.load_recent_audit();
+        // Before anything appends to the journals just replayed
+        engine.compact_stats_journals();
+        // Ahead of indexing, so files without metadata are only hashed once
+        if engine.reconcile.mode == ReconcileMode::Startup {
+            engine.reconcile_all();
+        }
+        engine.rebuild_hash_index()?;
+        if engine.reconcile.mode == ReconcileMode::Lazy {
+            engine.queue_reconcile();
+        }
         Ok(engine)
     }
 
-    /// Scan the root directory for existing bucket folders
+    /// Scan the data dir and every mounted volume for existing bucket folders
     fn scan_buckets(&self) -> Result<(), AppError> {
         let mut buckets = self.buckets.write().unwrap();
-        if let Ok(entries) = fs::read_dir(&self.root) {
+        for (volume, root) in self.available_volumes() {
+            let Ok(entries) = fs::read_dir(&root) else {
+                continue;
+            };
             for entry in entries.flatten() {
                 if entry.path().is_dir() {
                     let name = entry.file_name().to_string_lossy().to_string();
@@ -56,18 +349,65 @@ impl StorageEngine {
 
                     // Try to load metadata
                     let meta_path = entry.path().join(".bucket_meta.json");
-                    let bucket = if meta_path.exists() {
-                        let data = fs::read_to_string(&meta_path)?;
-                        serde_json::from_str::<Bucket>(&data)
-                            .unwrap_or_else(|_| self.create_bucket_meta(&name))
-                    } else {
-                        let b = self.create_bucket_meta(&name);
-                        // Save it
-                        let json = serde_json::to_string_pretty(&b).unwrap();
-                        let _ = fs::write(&meta_path, json);
-                        b
+                    let data = fs::read(&meta_path);
+                    let loaded = data
+                        .as_ref()
+                        .ok()
+                        .and_then(|data| serde_json::from_slice::<Bucket>(data).ok());
+                    // A move names the target in the old copy before switching
+                    // over, so a copy pointing elsewhere is the stale one
+                    if let Some(bucket) = &loaded {
+                        if bucket.volume != volume && self.volume_has_bucket(bucket.volume.as_deref(), &name) {
+                            tracing::warn!(
+                                "Ignoring {}, left over from moving bucket {} to volume {}; remove it",
+                                entry.path().display(),
+                                name,
+                                bucket.volume.as_deref().unwrap_or(DEFAULT_VOLUME)
+                            );
+                            continue;
+                        }
+                    }
+                    if let Some(existing) = buckets.get(&name) {
+                        tracing::error!(
+                            "Bucket {} exists on volume {} and on volume {}; ignoring {}",
+                            name,
+                            existing.volume.as_deref().unwrap_or(DEFAULT_VOLUME),
+                            volume.as_deref().unwrap_or(DEFAULT_VOLUME),
+                            entry.path().display()
+                        );
+                        continue;
+                    }
+                    self.set_bucket_root(&name, volume.as_deref(), &root);
+
+                    let mut bucket = match loaded {
+                        Some(mut bucket) => {
+                            self.recover_bucket_stats(&mut bucket);
+                            if bucket.metadata_recovered.is_some() {
+                                tracing::warn!(
+                                    "Bucket {} runs on recovered metadata; check its settings and run `freebucket fsck {} --confirm-metadata`",
+                                    name,
+                                    name
+                                );
+                            }
+                            bucket
+                        }
+                        None => match data {
+                            Ok(data) => self.recover_bucket_meta(&entry.path(), &name, &data),
+                            Err(_) => self.repair_bucket(&entry.path(), &name),
+                        },
                     };
+                    bucket.volume = volume.clone();
+                    // Still served, but flagged so it can be renamed
+                    if self.is_reserved_name(&name) {
+                        tracing::warn!(
+                            "Bucket {} uses a reserved name and may be unreachable through some routes",
+                            name
+                        );
+                        bucket.reserved = true;
+                    }
 
+                    self.load_access_stats(&name);
+                    self.load_quarantine(&name, &entry.path());
                     buckets.insert(name, bucket);
                 }
             }
@@ -75,35 +415,139 @@ impl StorageEngine {
         Ok(())
     }
 
+    /// Rebuild a bucket whose metadata is missing or unreadable, such as one
+    /// left behind by a create that failed part-way, instead of leaving it
+    /// broken for good
+    fn repair_bucket(&self, dir: &Path, name: &str) -> Bucket {
+        tracing::warn!("Bucket {} has no readable metadata; recreating it", name);
+        let mut bucket = self.create_bucket_meta(name);
+        let stats = self.dir_stats(&dir.join("objects"));
+        bucket.object_count = stats.count;
+        bucket.total_size = stats.size;
+        bucket.allocated_size = stats.allocated;
+
+        let result = fs::create_dir_all(dir.join("objects"))
+            .and_then(|_| fs::create_dir_all(dir.join(".meta")))
+            .and_then(|_| self.write_bucket_meta(&bucket));
+        if let Err(e) = result {
+            tracing::error!("Failed to repair bucket {}: {}", name, e);
+        }
+        // Its entries are relative to the metadata that was lost
+        let _ = fs::remove_file(dir.join(".stats_journal"));
+        bucket
+    }
+
+    /// Persist bucket metadata via a temp file so a crash never leaves it half written
+    fn write_bucket_meta(&self, bucket: &Bucket) -> std::io::Result<()> {
+        let meta_path = self.bucket_path(&bucket.name).join(".bucket_meta.json");
+        self.replace_file(&meta_path, serde_json::to_string_pretty(bucket).unwrap().as_bytes())
+    }
+
     fn create_bucket_meta(&self, name: &str) -> Bucket {
         Bucket {
             name: name.to_string(),
-            created_at: Utc::now(),
+            created_at: self.now(),
             region: "local".to_string(),
             object_count: 0,
             total_size: 0,
+            allocated_size: 0,
+            versioning: false,
+            inbox: false,
+            inbox_limits: Default::default(),
+            precompressed: false,
+            overwrite_policy: OverwritePolicy::Allow,
+            max_objects: None,
+            webhooks: Vec::new(),
+            exec_hooks: None,
+            revision: 0,
+            logging: None,
+            reserved: false,
+            volume: None,
+            quarantine: None,
+            origin: None,
+            alerts: None,
+            metadata_recovered: None,
         }
     }
 
+    /// The current time, from the engine's clock
+    pub fn now(&self) -> DateTime<Utc> {
+        self.sources.now()
+    }
+
+    /// A fresh ID from the engine's ID source
+    pub fn new_id(&self) -> uuid::Uuid {
+        self.sources.new_id()
+    }
+
     fn bucket_path(&self, name: &str) -> PathBuf {
-        self.root.join(name)
+        match self.bucket_roots.read().unwrap().get(name) {
+            Some(root) => root.join(name),
+            None => self.root.join(name),
+        }
     }
 
     fn object_path(&self, bucket: &str, key: &str) -> PathBuf {
-        self.root.join(bucket).join("objects").join(key)
+        let stored = self.stored_key(bucket, key);
+        self.bucket_path(bucket).join("objects").join(&*key_paths::key_to_path(&stored))
     }
 
     fn object_meta_path(&self, bucket: &str, key: &str) -> PathBuf {
-        let safe_key = key.replace('/', "__SLASH__");
-        self.root
-            .join(bucket)
+        let flat = key_paths::flat_name(&self.stored_key(bucket, key));
+        self.bucket_path(bucket)
             .join(".meta")
-            .join(format!("{}.json", safe_key))
+            .join(format!("{}.json", flat))
+    }
+
+    /// A fresh scratch file inside the bucket, on the same filesystem as its
+    /// objects. The path is registered as in-flight until the guard is dropped.
+    fn temp_file(&self, bucket: &str) -> Result<TempFile<'_>, AppError> {
+        let dir = self.bucket_path(bucket).join(".tmp");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(".tmp-{}", uuid::Uuid::new_v4()));
+        self.active_temp.lock().unwrap().insert(path.clone());
+        Ok(TempFile { engine: self, path })
+    }
+
+    /// Give a payload just committed for `meta` its source's modification
+    /// time, so tools looking at the files directly see the same time as
+    /// clients. Only ever done after the commit: a temp file dated in the
+    /// past would look abandoned to GC.
+    fn stamp_source_mtime(&self, meta: &ObjectMeta) {
+        let Some(mtime) = meta.source_mtime.filter(|_| self.fs_features.fine_mtimes) else {
+            return;
+        };
+        if let Err(e) = set_file_mtime(&self.object_path(&meta.bucket, &meta.key), mtime) {
+            tracing::debug!("Cannot set the mtime of {}/{}: {}", meta.bucket, meta.key, e);
+        }
+    }
+
+    /// Whether a temp path belongs to an operation that is still running
+    fn is_temp_active(&self, path: &Path) -> bool {
+        self.active_temp.lock().unwrap().contains(path)
     }
 
     // ─── Bucket Operations ────────────────────────────────────────
 
-    pub fn validate_bucket_name(name: &str) -> Result<(), AppError> {
+    /// Whether a bucket name is valid for a new bucket: well formed and not
+    /// reserved
+    pub fn validate_bucket_name(&self, name: &str) -> Result<(), AppError> {
+        Self::validate_bucket_syntax(name)?;
+        if self.is_reserved_name(name) {
+            return Err(AppError::ReservedBucketName {
+                name: name.to_string(),
+                reserved: self.reserved_names.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn is_reserved_name(&self, name: &str) -> bool {
+        self.reserved_names.binary_search_by(|n| n.as_str().cmp(name)).is_ok()
+    }
+
+    /// S3 naming rules alone; existing buckets with reserved names pass
+    pub fn validate_bucket_syntax(name: &str) -> Result<(), AppError> {
         if name.len() < 3 || name.len() > 63 {
             return Err(AppError::InvalidBucketName(
                 "Bucket name must be between 3 and 63 characters".to_string(),
@@ -126,30 +570,80 @@ impl StorageEngine {
         Ok(())
     }
 
+    /// Region labels are free-form but end up in headers and file contents
+    pub fn validate_region(region: &str) -> Result<(), AppError> {
+        if region.is_empty()
+            || region.len() > 64
+            || !region.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(AppError::InvalidRequest(format!(
+                "Invalid region '{}': use letters, digits, and hyphens",
+                region
+            )));
+        }
+        Ok(())
+    }
+
     pub fn create_bucket(&self, name: &str, region: &str) -> Result<Bucket, AppError> {
-        Self::validate_bucket_name(name)?;
+        self.create_bucket_on(name, region, None)
+    }
+
+    /// Create a bucket on a storage volume; `None` is the data dir
+    pub fn create_bucket_on(&self, name: &str, region: &str, volume: Option<&str>) -> Result<Bucket, AppError> {
+        self.validate_bucket_name(name)?;
+        Self::validate_region(region)?;
+        let volume = volume.filter(|v| *v != DEFAULT_VOLUME);
 
         let mut buckets = self.buckets.write().unwrap();
         if buckets.contains_key(name) {
             return Err(AppError::BucketAlreadyExists(name.to_string()));
         }
+        let root = self.new_bucket_root(name, volume)?;
 
-        let bucket_dir = self.bucket_path(name);
-        fs::create_dir_all(bucket_dir.join("objects"))?;
-        fs::create_dir_all(bucket_dir.join(".meta"))?;
+        // A directory that isn't a registered bucket is left over from a
+        // create that failed part-way; creating over it repairs it
+        let bucket_dir = root.join(name);
+        let leftover = bucket_dir.exists();
 
         let bucket = Bucket {
             name: name.to_string(),
-            created_at: Utc::now(),
+            created_at: self.now(),
             region: region.to_string(),
             object_count: 0,
             total_size: 0,
+            allocated_size: 0,
+            versioning: false,
+            inbox: false,
+            inbox_limits: Default::default(),
+            precompressed: false,
+            overwrite_policy: OverwritePolicy::Allow,
+            max_objects: self.default_max_objects,
+            webhooks: Vec::new(),
+            exec_hooks: None,
+            revision: 0,
+            logging: None,
+            reserved: false,
+            volume: volume.map(str::to_string),
+            quarantine: None,
+            origin: None,
+            alerts: None,
+            metadata_recovered: None,
         };
 
-        // Persist metadata
-        let meta_path = bucket_dir.join(".bucket_meta.json");
-        let json = serde_json::to_string_pretty(&bucket).unwrap();
-        fs::write(&meta_path, json)?;
+        self.set_bucket_root(name, volume, &root);
+        let result = fs::create_dir_all(bucket_dir.join("objects"))
+            .and_then(|_| fs::create_dir_all(bucket_dir.join(".meta")))
+            .and_then(|_| self.write_bucket_meta(&bucket));
+        if let Err(e) = result {
+            if !leftover {
+                let _ = fs::remove_dir_all(&bucket_dir);
+            }
+            self.set_bucket_root(name, None, &root);
+            return Err(e.into());
+        }
+        if leftover {
+            tracing::warn!("Repaired partially created bucket: {}", name);
+        }
 
         buckets.insert(name.to_string(), bucket.clone());
         tracing::info!("Created bucket: {}", name);
@@ -158,7 +652,11 @@ impl StorageEngine {
 
     pub fn list_buckets(&self) -> Vec<Bucket> {
         let buckets = self.buckets.read().unwrap();
-        let mut list: Vec<Bucket> = buckets.values().cloned().collect();
+        let mut list: Vec<Bucket> = buckets
+            .values()
+            .filter(|b| !is_system_bucket(&b.name))
+            .cloned()
+            .collect();
         list.sort_by(|a, b| a.name.cmp(&b.name));
         list
     }
@@ -171,24 +669,49 @@ impl StorageEngine {
             .ok_or_else(|| AppError::BucketNotFound(name.to_string()))
     }
 
-    pub fn delete_bucket(&self, name: &str) -> Result<(), AppError> {
+    pub fn set_bucket_region(&self, name: &str, region: &str) -> Result<Bucket, AppError> {
+        Self::validate_region(region)?;
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let bucket = buckets
+                .get_mut(name)
+                .ok_or_else(|| AppError::BucketNotFound(name.to_string()))?;
+            bucket.region = region.to_string();
+        }
+        // Persists the new region along with the stats
+        self.persist_bucket_meta(name)?;
+        tracing::info!("Set region of bucket {} to {}", name, region);
+        self.get_bucket(name)
+    }
+
+    /// Delete an empty bucket. Its settings are kept under
+    /// `.deleted_buckets/` so the delete can be undone, unless `purge` is set.
+    pub fn delete_bucket(&self, name: &str, purge: bool) -> Result<(), AppError> {
         let mut buckets = self.buckets.write().unwrap();
-        if !buckets.contains_key(name) {
+        let Some(info) = buckets.get(name) else {
             return Err(AppError::BucketNotFound(name.to_string()));
-        }
+        };
 
         let objects_dir = self.bucket_path(name).join("objects");
         if objects_dir.exists() {
-            let (count, _) = Self::dir_stats(&objects_dir);
-            if count > 0 {
+            let stats = self.dir_stats(&objects_dir);
+            if stats.count > 0 {
                 return Err(AppError::StorageError(
                     "Bucket is not empty. Delete all objects first.".to_string(),
                 ));
             }
         }
 
+        if !purge {
+            self.retain_deleted_bucket(info)?;
+        }
         fs::remove_dir_all(self.bucket_path(name))?;
         buckets.remove(name);
+        self.read_cache.forget_bucket(name);
+        self.set_bucket_root(name, None, &self.root);
+        self.remove_bucket_access(name);
+        self.forget_quarantine(name);
+        self.forget_traffic(name);
         tracing::info!("Deleted bucket: {}", name);
         Ok(())
     }
@@ -200,9 +723,17 @@ impl StorageEngine {
         bucket: &str,
         key: &str,
         data: &[u8],
-        content_type: Option<&str>,
-        metadata: HashMap<String, String>,
+        options: PutOptions,
     ) -> Result<ObjectMeta, AppError> {
+        let PutOptions {
+            content_type,
+            metadata,
+            checksum,
+            original_filename,
+            website_redirect_location,
+            source_mtime,
+        } = options;
+        let _op = self.time_op("put_object", bucket, Some(key));
         // Check bucket exists
         {
             let buckets = self.buckets.read().unwrap();
@@ -211,58 +742,86 @@ impl StorageEngine {
             }
         }
 
-        if key.is_empty() || key.len() > 1024 {
-            return Err(AppError::InvalidObjectKey(
-                "Key must be between 1 and 1024 characters".to_string(),
-            ));
-        }
-
-        // Determine content type
-        let content_type = content_type.map(|s| s.to_string()).unwrap_or_else(|| {
-            mime_guess::from_path(key)
-                .first_or_octet_stream()
-                .to_string()
-        });
+        self.check_new_key(key)?;
+        let key = &*self.normalize_key(key);
+        self.metadata_limits.check(&metadata)?;
+
+        // An explicit type wins; otherwise sniff the content and the key
+        let content_type = content_type
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| content_type::detect_content_type(key, data));
+
+        // Compute ETag (SHA-256 hash), or leave that to the background
+        let etag_provisional = self.defers_hashing(bucket);
+        let etag = if etag_provisional {
+            self.provisional_etag(data.len() as u64)
+        } else {
+            let _op = self.time_op("hash", bucket, Some(key));
+            hex::encode(Sha256::digest(data))
+        };
 
-        // Compute ETag (SHA-256 hash)
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        let etag = format!("\"{}\"", hex::encode(hasher.finalize()));
+        // Validate the client's checksum before anything is written
+        let checksum = match checksum {
+            Some(request) => {
+                let value = checksum::checksum_of(request.algorithm, data);
+                checksum::verify_checksum(request, &value)?;
+                Some(ObjectChecksum {
+                    algorithm: request.algorithm,
+                    checksum_type: ChecksumType::FullObject,
+                    value,
+                })
+            }
+            None => None,
+        };
 
         // Write the file
-        let obj_path = self.object_path(bucket, key);
-        if let Some(parent) = obj_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        let mut file = fs::File::create(&obj_path)?;
+        let _lock = self.lock_key(bucket, key);
+        self.check_overwrite(bucket, key)?;
+        self.check_new_object(bucket, key)?;
+        let tmp = self.temp_file(bucket)?;
+        let mut file = SparseWriter::new(fs::File::create(tmp.path())?);
         file.write_all(data)?;
+        file.finish()?;
 
-        // Write metadata
         let meta = ObjectMeta {
             key: key.to_string(),
             bucket: bucket.to_string(),
             size: data.len() as u64,
             content_type,
             etag,
-            last_modified: Utc::now(),
+            last_modified: self.now(),
             metadata,
+            parts: Vec::new(),
+            checksum,
+            original_filename: original_filename.map(str::to_string),
+            website_redirect_location: website_redirect_location.map(str::to_string),
+            source_mtime,
+            etag_provisional,
+            origin: None,
+            allocated_size: None,
         };
-
-        let meta_path = self.object_meta_path(bucket, key);
-        if let Some(parent) = meta_path.parent() {
-            fs::create_dir_all(parent)?;
+        if self.is_quarantined(bucket) {
+            return self.quarantine_upload(tmp, meta);
+        }
+        tmp.commit(&self.object_path(bucket, key))?;
+        self.stamp_source_mtime(&meta);
+        if meta.etag_provisional {
+            self.queue_rehash(&meta)?;
         }
-        let json = serde_json::to_string_pretty(&meta).unwrap();
-        fs::write(&meta_path, json)?;
+
+        // Write metadata
+        self.save_object_meta(&meta)?;
 
         // Update bucket stats
         self.update_bucket_stats(bucket)?;
+        self.object_created(EventKind::Put, &meta);
 
         tracing::info!("Put object: {}/{} ({} bytes)", bucket, key, data.len());
         Ok(meta)
     }
 
     pub fn get_object(&self, bucket: &str, key: &str) -> Result<(ObjectMeta, Vec<u8>), AppError> {
+        let _op = self.time_op("get_object", bucket, Some(key));
         // Check bucket exists
         {
             let buckets = self.buckets.read().unwrap();
@@ -270,31 +829,61 @@ impl StorageEngine {
                 return Err(AppError::BucketNotFound(bucket.to_string()));
             }
         }
-
-        let obj_path = self.object_path(bucket, key);
-        if !obj_path.exists() {
-            return Err(AppError::ObjectNotFound {
-                bucket: bucket.to_string(),
-                key: key.to_string(),
-            });
+        let cached = self.read_cache.enabled().then(|| self.stored_key(bucket, key).into_owned());
+        if let Some(stored) = &cached {
+            if let Some(hit) = self.read_cache.get(bucket, stored) {
+                return Ok(hit);
+            }
         }
+        let generation = self.read_cache.generation();
+
+        let (meta, data) = {
+            // Deletes hold off (or park the file) until the read is done; the
+            // metadata is read first so it can't outlive a deleted payload
+            let _committed = self.commit_gate.read().unwrap();
+            let _reading = self.begin_read(bucket, key);
+            let obj_path = self.object_path(bucket, key);
+            if !obj_path.exists() {
+                return Err(self.missing_object(bucket, key));
+            }
 
-        let data = fs::read(&obj_path)?;
-        let meta = self.get_object_meta(bucket, key)?;
+            let meta = self.get_object_meta(bucket, key)?;
+            // Once open, the read completes even if the payload is parked by a
+            // deferred delete
+            let mut file = self.retry_io(|| fs::File::open(&obj_path)).map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => AppError::ObjectNotFound {
+                    bucket: bucket.to_string(),
+                    key: key.to_string(),
+                },
+                _ => e.into(),
+            })?;
+            let mut data = Vec::with_capacity(meta.size as usize);
+            file.read_to_end(&mut data)?;
+            (meta, data)
+        };
+        // The bytes read are what gets served, whatever the metadata says
+        let meta = self.check_payload_size(meta, data.len() as u64);
 
+        if let Some(stored) = &cached {
+            self.read_cache.insert(generation, stored, &meta, &data);
+        }
         Ok((meta, data))
     }
 
     pub fn get_object_meta(&self, bucket: &str, key: &str) -> Result<ObjectMeta, AppError> {
+        let _op = self.time_op("read_meta", bucket, Some(key));
+        if self.read_cache.enabled() {
+            if let Some(meta) = self.read_cache.meta(bucket, &self.stored_key(bucket, key)) {
+                return Ok(meta);
+            }
+        }
+        self.ensure_reconciled(bucket);
         let meta_path = self.object_meta_path(bucket, key);
         if !meta_path.exists() {
             // Try to reconstruct metadata from file
             let obj_path = self.object_path(bucket, key);
             if !obj_path.exists() {
-                return Err(AppError::ObjectNotFound {
-                    bucket: bucket.to_string(),
-                    key: key.to_string(),
-                });
+                return Err(self.missing_object(bucket, key));
             }
 
             let file_meta = fs::metadata(&obj_path)?;
@@ -304,7 +893,7 @@ impl StorageEngine {
 
             let mut hasher = Sha256::new();
             hasher.update(&fs::read(&obj_path)?);
-            let etag = format!("\"{}\"", hex::encode(hasher.finalize()));
+            let etag = hex::encode(hasher.finalize());
 
             return Ok(ObjectMeta {
                 key: key.to_string(),
@@ -312,17 +901,141 @@ impl StorageEngine {
                 size: file_meta.len(),
                 content_type,
                 etag,
-                last_modified: Utc::now(),
+                last_modified: self.now(),
                 metadata: HashMap::new(),
+                parts: Vec::new(),
+                checksum: None,
+                original_filename: None,
+                website_redirect_location: None,
+                source_mtime: None,
+                etag_provisional: false,
+                origin: None,
+                allocated_size: None,
             });
         }
 
-        let json = fs::read_to_string(&meta_path)?;
-        serde_json::from_str(&json)
-            .map_err(|e| AppError::StorageError(format!("Corrupt metadata: {}", e)))
+        let json = self.retry_io(|| fs::read(&meta_path))?;
+        match serde_json::from_slice(&json) {
+            Ok(meta) => Ok(meta),
+            Err(e) if self.reconcile.repair_corrupt_meta => {
+                tracing::warn!(
+                    "Corrupt metadata for {}/{} ({}); rebuilding it from the payload",
+                    bucket,
+                    key,
+                    e
+                );
+                self.rebuild_object_meta(bucket, key)
+            }
+            Err(e) => Err(AppError::StorageError(format!("Corrupt metadata: {}", e))),
+        }
     }
 
     pub fn delete_object(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        let _op = self.time_op("delete_object", bucket, Some(key));
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+
+        self.remove_object_files(bucket, key)?;
+        self.update_bucket_stats(bucket)?;
+        tracing::info!("Deleted object: {}/{}", bucket, key);
+        Ok(())
+    }
+
+    /// Server-side copy of an object, keeping its content type and metadata
+    pub fn copy_object(
+        &self,
+        src_bucket: &str,
+        src_key: &str,
+        dst_bucket: &str,
+        dst_key: &str,
+    ) -> Result<ObjectMeta, AppError> {
+        let mut _op = self.time_op("copy_object", dst_bucket, Some(dst_key));
+        _op.detail(|| format!("from {}/{}", src_bucket, src_key));
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(src_bucket) {
+                return Err(AppError::BucketNotFound(src_bucket.to_string()));
+            }
+            if !buckets.contains_key(dst_bucket) {
+                return Err(AppError::BucketNotFound(dst_bucket.to_string()));
+            }
+        }
+
+        self.check_new_key(dst_key)?;
+        let dst_key = &*self.normalize_key(dst_key);
+        if src_bucket != dst_bucket {
+            self.check_unscanned_write(dst_bucket, "copying from another bucket")?;
+        }
+
+        let src_path = self.object_path(src_bucket, src_key);
+        if !src_path.is_file() {
+            return Err(AppError::ObjectNotFound {
+                bucket: src_bucket.to_string(),
+                key: src_key.to_string(),
+            });
+        }
+        let src_meta = self.get_object_meta(src_bucket, src_key)?;
+        if src_bucket == dst_bucket && self.normalize_key(src_key) == dst_key {
+            return Ok(src_meta);
+        }
+        // Bulk transfers check their whole plan instead
+        self.check_new_object(dst_bucket, dst_key)?;
+
+        let meta = self.copy_object_files(src_meta, dst_bucket, dst_key)?;
+
+        self.update_bucket_stats(dst_bucket)?;
+        self.object_created(EventKind::Copy, &meta);
+        tracing::info!(
+            "Copied object: {}/{} → {}/{}",
+            src_bucket,
+            src_key,
+            dst_bucket,
+            dst_key
+        );
+        Ok(meta)
+    }
+
+    /// Copy an object's payload and metadata to a new key without touching
+    /// bucket stats, so batch callers can recompute them once at the end
+    fn copy_object_files(
+        &self,
+        src_meta: ObjectMeta,
+        dst_bucket: &str,
+        dst_key: &str,
+    ) -> Result<ObjectMeta, AppError> {
+        let _lock = self.lock_key(dst_bucket, dst_key);
+        self.check_overwrite(dst_bucket, dst_key)?;
+        let tmp = self.temp_file(dst_bucket)?;
+        sparse::copy_file(&self.object_path(&src_meta.bucket, &src_meta.key), tmp.path())?;
+        let etag = self.final_etag(&src_meta, tmp.path())?;
+        tmp.commit(&self.object_path(dst_bucket, dst_key))?;
+
+        let meta = ObjectMeta {
+            key: dst_key.to_string(),
+            bucket: dst_bucket.to_string(),
+            last_modified: self.now(),
+            etag,
+            etag_provisional: false,
+            origin: None,
+            ..src_meta
+        };
+        self.stamp_source_mtime(&meta);
+
+        self.save_object_meta(&meta)?;
+        Ok(meta)
+    }
+
+    /// Delete many objects at once, recomputing bucket stats a single time.
+    /// Failures are collected per key instead of aborting the whole batch.
+    pub fn delete_objects(
+        &self,
+        bucket: &str,
+        keys: &[String],
+    ) -> Result<DeleteObjectsResult, AppError> {
         {
             let buckets = self.buckets.read().unwrap();
             if !buckets.contains_key(bucket) {
@@ -330,51 +1043,155 @@ impl StorageEngine {
             }
         }
 
+        let mut result = DeleteObjectsResult {
+            deleted: Vec::new(),
+            errors: Vec::new(),
+        };
+        for key in keys {
+            match self.remove_object_files(bucket, key) {
+                Ok(()) => result.deleted.push(key.clone()),
+                Err(e) => {
+                    let (_, code, message) = e.parts();
+                    result.errors.push(DeleteError {
+                        key: key.clone(),
+                        code: code.to_string(),
+                        message,
+                    });
+                }
+            }
+        }
+
+        self.update_bucket_stats(bucket)?;
+        tracing::info!(
+            "Batch deleted {} object(s) from {} ({} failed)",
+            result.deleted.len(),
+            bucket,
+            result.errors.len()
+        );
+        Ok(result)
+    }
+
+    /// Delete every object under a prefix and prune the directories it leaves behind
+    pub fn delete_prefix(&self, bucket: &str, prefix: &str) -> Result<DeleteObjectsResult, AppError> {
+        let mut _op = self.time_op("delete_prefix", bucket, None);
+        _op.detail(|| format!("prefix={:?}", prefix));
+        if prefix.is_empty() {
+            return Err(AppError::InvalidObjectKey(
+                "Refusing to delete an empty prefix; delete the bucket instead".to_string(),
+            ));
+        }
+
+        let keys: Vec<String> = self
+            .list_objects(bucket, prefix, None, u32::MAX)?
+            .objects
+            .into_iter()
+            .map(|o| o.key)
+            .collect();
+        let result = self.delete_objects(bucket, &keys)?;
+
+        let objects_root = self.bucket_path(bucket).join("objects");
+        Self::prune_empty_dirs(&objects_root, &objects_root);
+        Ok(result)
+    }
+
+    /// Remove an object's payload and metadata without touching bucket
+    /// stats. Versioned buckets keep the history and gain a delete marker.
+    fn remove_object_files(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        let _lock = self.lock_key(bucket, key);
+        self.unlink_object(bucket, key)?;
+        self.record_delete_marker(bucket, key)?;
+        self.object_removed(bucket, key);
+        Ok(())
+    }
+
+    /// Remove the live copy of an object, leaving any version history alone
+    fn unlink_object(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        let stored = self.stored_key(bucket, key).into_owned();
         let obj_path = self.object_path(bucket, key);
-        if !obj_path.exists() {
+        if !obj_path.is_file() {
             return Err(AppError::ObjectNotFound {
                 bucket: bucket.to_string(),
                 key: key.to_string(),
             });
         }
 
-        fs::remove_file(&obj_path)?;
+        self.remove_payload(bucket, key, &obj_path)?;
 
         // Remove metadata
         let meta_path = self.object_meta_path(bucket, key);
         if meta_path.exists() {
             fs::remove_file(&meta_path)?;
         }
+        self.hash_index.write().unwrap().remove(bucket, key, self.now());
+        self.read_cache.forget(bucket, &stored);
+        self.forget_access(bucket, key);
 
         // Clean up empty parent directories inside objects/
         let objects_root = self.bucket_path(bucket).join("objects");
         if let Some(parent) = obj_path.parent() {
             Self::cleanup_empty_dirs(parent, &objects_root);
         }
+        Ok(())
+    }
 
-        self.update_bucket_stats(bucket)?;
-        tracing::info!("Deleted object: {}/{}", bucket, key);
+    /// Persist an object's metadata, index its hash, and record it as the
+    /// newest version if the bucket keeps versions. Call after the payload
+    /// has been committed.
+    fn save_object_meta(&self, meta: &ObjectMeta) -> Result<(), AppError> {
+        self.write_meta_file(meta)?;
+        self.record_version(meta)
+    }
+
+    fn write_meta_file(&self, meta: &ObjectMeta) -> Result<(), AppError> {
+        let meta_path = self.object_meta_path(&meta.bucket, &meta.key);
+        if let Some(parent) = meta_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Read from the payload as it is now, so each rewrite refreshes it
+        let allocated_size = fs::metadata(self.object_path(&meta.bucket, &meta.key))
+            .ok()
+            .map(|m| sparse::allocated_size(&m));
+        let json = serde_json::to_string_pretty(&ObjectMeta {
+            allocated_size,
+            ..meta.clone()
+        })
+        .unwrap();
+        self.retry_io(|| fs::write(&meta_path, &json))?;
+        self.hash_index.write().unwrap().insert(meta);
+        self.read_cache.forget(&meta.bucket, &meta.key);
         Ok(())
     }
 
+    /// Remove `dir` and each empty ancestor up to (but excluding) `stop_at`.
+    /// A directory that vanished underneath us (a concurrent delete got there
+    /// first) is treated as already pruned and the walk keeps going upward.
     fn cleanup_empty_dirs(dir: &Path, stop_at: &Path) {
         let mut current = dir.to_path_buf();
-        while current != stop_at.to_path_buf() {
-            if let Ok(entries) = fs::read_dir(&current) {
-                if entries.count() == 0 {
-                    let _ = fs::remove_dir(&current);
-                } else {
-                    break;
-                }
-            } else {
-                break;
+        while current != stop_at && current.starts_with(stop_at) {
+            match fs::remove_dir(&current) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                // Not empty (or not removable): nothing above it can be empty either
+                Err(_) => break,
             }
-            if let Some(parent) = current.parent() {
-                current = parent.to_path_buf();
-            } else {
-                break;
+            match current.parent() {
+                Some(parent) => current = parent.to_path_buf(),
+                None => break,
+            }
+        }
+    }
+
+    /// Removal of every empty directory below `dir`, deepest first, leaving
+    /// `keep` itself in place. Returns true if `dir` was removed.
+    fn prune_empty_dirs(dir: &Path, keep: &Path) -> bool {
+        let mut removed = false;
+        for (path, has_files) in walk::dirs_deepest_first(dir) {
+            // Fails on a directory whose subdirectories couldn't all go
+            if !has_files && path != keep && fs::remove_dir(&path).is_ok() {
+                removed |= path == dir;
             }
         }
+        removed
     }
 
     pub fn list_objects(
@@ -384,36 +1201,126 @@ impl StorageEngine {
         delimiter: Option<&str>,
         max_keys: u32,
     ) -> Result<ListObjectsResponse, AppError> {
+        self.list_objects_sorted(
+            bucket,
+            prefix,
+            delimiter,
+            max_keys,
+            ListOrder::default(),
+            None,
+        )
+    }
+
+    /// Like `list_objects`, but ordered by modification time, size, or
+    /// access stats when asked, with ties in key order. Objects never read
+    /// sort as the least recently accessed. The order applies before
+    /// `max_keys`, so the first page holds e.g. the newest objects.
+    ///
+    /// A continuation token names the sort value and key of the last object
+    /// on the previous page, and the next page starts just after that
+    /// position. Writes between pages therefore never repeat or skip an
+    /// object that stays put; one that moves (e.g. is rewritten and becomes
+    /// the newest) shows up wherever its new position falls.
+    ///
+    /// A modification-time window takes its keys from the index's
+    /// modification order instead of walking the bucket. When it has no end,
+    /// the time of its first page becomes the end, and the continuation
+    /// token carries it. Objects written after that, or rewritten before
+    /// their page came, fall in the next window rather than this one.
+    pub fn list_objects_sorted(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        delimiter: Option<&str>,
+        max_keys: u32,
+        ordering: ListOrder,
+        continuation_token: Option<&str>,
+    ) -> Result<ListObjectsResponse, AppError> {
+        let ListOrder { sort, order, window } = ordering;
+        let mut _op = self.time_op("list_objects", bucket, None);
+        _op.detail(|| format!("prefix={:?} delimiter={:?} sort={:?}", prefix, delimiter, sort));
+        if matches!(sort, ListSort::LastAccessed | ListSort::Downloads) && !self.access_tracking {
+            return Err(AppError::InvalidRequest(
+                "Sorting by access requires access tracking, which is disabled".to_string(),
+            ));
+        }
+        let token = continuation_token.map(|token| decode_list_token(token, sort)).transpose()?;
+        let modified_before = window.is_set().then(|| {
+            token
+                .as_ref()
+                .and_then(|token| token.modified_before)
+                .or(window.before)
+                .unwrap_or_else(|| self.now())
+        });
+        let after = token.map(|token| (token.value, token.key));
         {
             let buckets = self.buckets.read().unwrap();
             if !buckets.contains_key(bucket) {
                 return Err(AppError::BucketNotFound(bucket.to_string()));
             }
         }
+        self.ensure_reconciled(bucket);
 
+        // A transaction's changes appear in a listing all at once
+        let _committed = self.commit_gate.read().unwrap();
         let objects_dir = self.bucket_path(bucket).join("objects");
-        let mut objects = Vec::new();
-        let mut common_prefixes = Vec::new();
+        let mut keys = if let Some(before) = modified_before {
+            self.hash_index
+                .read()
+                .unwrap()
+                .modified_between(bucket, prefix, window.after, before)
+        } else if objects_dir.exists() {
+            self.collect_keys(&objects_dir, prefix)
+        } else {
+            Vec::new()
+        };
 
-        if objects_dir.exists() {
-            let mut ctx = WalkContext {
-                root: &objects_dir,
-                bucket,
-                prefix,
-                delimiter,
-                objects: &mut objects,
-                common_prefixes: &mut common_prefixes,
-            };
-            self.walk_objects(&objects_dir, &mut ctx)?;
+        let mut common_prefixes = Vec::new();
+        if let Some(delim) = delimiter {
+            keys.retain(|key| {
+                let after_prefix = &key[prefix.len()..];
+                match after_prefix.find(delim) {
+                    Some(pos) => {
+                        common_prefixes.push(format!("{}{}{}", prefix, &after_prefix[..pos], delim));
+                        false
+                    }
+                    None => true,
+                }
+            });
+        }
+        // Prefixes have no sort position, so they all come with the first page
+        if after.is_some() {
+            common_prefixes.clear();
         }
-
-        // Sort by key
-        objects.sort_by(|a, b| a.key.cmp(&b.key));
         common_prefixes.sort();
         common_prefixes.dedup();
 
-        let is_truncated = objects.len() > max_keys as usize;
-        objects.truncate(max_keys as usize);
+        let directed = |ordering: std::cmp::Ordering| match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        let compare = |a: &(i64, String), b: &(i64, String)| {
+            if sort == ListSort::Key {
+                directed(a.1.cmp(&b.1))
+            } else {
+                directed(a.0.cmp(&b.0)).then_with(|| a.1.cmp(&b.1))
+            }
+        };
+        let mut entries = self.sort_positions(bucket, keys, sort);
+        if let Some(after) = &after {
+            entries.retain(|entry| compare(entry, after) == std::cmp::Ordering::Greater);
+        }
+        entries.sort_by(compare);
+
+        // Only the returned page's metadata is read
+        let is_truncated = entries.len() > max_keys as usize;
+        entries.truncate(max_keys as usize);
+        let next_continuation_token = entries
+            .last()
+            .filter(|_| is_truncated)
+            .map(|(value, key)| encode_list_token(sort, *value, key, modified_before));
+        let keys: Vec<String> = entries.into_iter().map(|(_, key)| key).collect();
+        let objects = self.load_object_metas(bucket, &keys);
 
         Ok(ListObjectsResponse {
             bucket: bucket.to_string(),
@@ -422,111 +1329,601 @@ impl StorageEngine {
             common_prefixes,
             is_truncated,
             max_keys,
+            next_continuation_token,
+            modified_before,
         })
     }
 
-    fn walk_objects(&self, dir: &Path, ctx: &mut WalkContext<'_>) -> Result<(), AppError> {
-        if !dir.exists() {
-            return Ok(());
-        }
+    /// A continuation token for a key-ordered listing that resumes just
+    /// after `key`, for S3's `marker` and `start-after`
+    pub fn list_token_after_key(&self, key: &str) -> String {
+        encode_list_token(ListSort::Key, 0, key, None)
+    }
 
-        for entry in fs::read_dir(dir)?.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                self.walk_objects(&path, ctx)?;
-            } else {
-                let rel = path
-                    .strip_prefix(ctx.root)
-                    .unwrap()
-                    .to_string_lossy()
-                    .replace('\\', "/");
+    /// Pair each key with its value under `sort`. Sizes and modification
+    /// times come from the hash index, so a sorted listing reads no more
+    /// metadata files than a plain one.
+    fn sort_positions(&self, bucket: &str, keys: Vec<String>, sort: ListSort) -> Vec<(i64, String)> {
+        match sort {
+            ListSort::Key => keys.into_iter().map(|key| (0, key)).collect(),
+            ListSort::LastModified | ListSort::Size => {
+                let indexed: Vec<_> = {
+                    let index = self.hash_index.read().unwrap();
+                    keys.iter().map(|key| index.key_stats(bucket, key)).collect()
+                };
+                keys.into_iter()
+                    .zip(indexed)
+                    .map(|(key, stats)| {
+                        // Objects another process wrote since startup aren't
+                        // indexed yet
+                        let stats = stats.or_else(|| {
+                            self.get_object_meta(bucket, &key)
+                                .ok()
+                                .map(|meta| (meta.size, meta.last_modified))
+                        });
+                        let value = stats.map_or(0, |(size, last_modified)| index_value(sort, size, last_modified));
+                        (value, key)
+                    })
+                    .collect()
+            }
+            ListSort::LastAccessed | ListSort::Downloads => {
+                let access = self.bucket_access(bucket).unwrap_or_default();
+                keys.into_iter()
+                    .map(|key| (access_value(sort, access.get(&key).copied().unwrap_or_default()), key))
+                    .collect()
+            }
+        }
+    }
 
-                if !rel.starts_with(ctx.prefix) {
-                    continue;
-                }
+    /// Continuation token for a listing that should resume after `meta`, for
+    /// callers that return fewer objects than `list_objects_sorted` did
+    pub fn list_token_after(
+        &self,
+        sort: ListSort,
+        meta: &ObjectMeta,
+        modified_before: Option<DateTime<Utc>>,
+    ) -> String {
+        let value = match sort {
+            ListSort::Key => 0,
+            ListSort::LastModified | ListSort::Size => index_value(sort, meta.size, meta.last_modified),
+            ListSort::LastAccessed | ListSort::Downloads => {
+                let access = self
+                    .bucket_access(&meta.bucket)
+                    .and_then(|access| access.get(&meta.key).copied())
+                    .unwrap_or_default();
+                access_value(sort, access)
+            }
+        };
+        encode_list_token(sort, value, &meta.key, modified_before)
+    }
 
-                if let Some(delim) = ctx.delimiter {
-                    let after_prefix = &rel[ctx.prefix.len()..];
-                    if let Some(pos) = after_prefix.find(delim) {
-                        let cp = format!("{}{}{}", ctx.prefix, &after_prefix[..pos], delim);
-                        ctx.common_prefixes.push(cp);
-                        continue;
-                    }
-                }
+    /// Call `f` for every object under `prefix`, in key order, without
+    /// collecting the whole listing, so callers can process buckets far
+    /// larger than memory
+    pub fn visit_objects<F>(&self, bucket: &str, prefix: &str, mut f: F) -> Result<(), AppError>
+    where
+        F: FnMut(ObjectMeta) -> Result<(), AppError>,
+    {
+        let mut _op = self.time_op("walk", bucket, None);
+        _op.detail(|| format!("prefix={:?}", prefix));
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+        self.ensure_reconciled(bucket);
 
-                if let Ok(meta) = self.get_object_meta(ctx.bucket, &rel) {
-                    ctx.objects.push(meta);
-                }
+        for key in self.sorted_keys(bucket, prefix) {
+            if let Ok(meta) = self.get_object_meta(bucket, &key) {
+                f(meta)?;
             }
         }
         Ok(())
     }
 
+    /// Keys of a bucket under `prefix`, in key order, read from disk as
+    /// the iterator is advanced. The bucket must exist.
+    pub fn sorted_keys(&self, bucket: &str, prefix: &str) -> SortedKeys {
+        SortedKeys::new(&self.bucket_path(bucket).join("objects"), prefix)
+    }
+
+    /// SHA-256 of an object's content in hex, read from its file in chunks
+    pub fn content_sha256(&self, bucket: &str, key: &str) -> Result<String, AppError> {
+        let path = self.object_path(bucket, key);
+        let mut file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(self.missing_object(bucket, key)),
+            Err(e) => return Err(e.into()),
+        };
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Like `visit_objects`, but only objects in a modification-time window,
+    /// oldest first, taken from the index. Returns the window's end, which is
+    /// the time of the call when the window has none.
+    pub fn visit_modified<F>(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        window: ModifiedWindow,
+        mut f: F,
+    ) -> Result<DateTime<Utc>, AppError>
+    where
+        F: FnMut(ObjectMeta) -> Result<(), AppError>,
+    {
+        let mut _op = self.time_op("walk", bucket, None);
+        _op.detail(|| format!("prefix={:?} window={:?}", prefix, window));
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+        self.ensure_reconciled(bucket);
+
+        let before = window.before.unwrap_or_else(|| self.now());
+        let keys = self
+            .hash_index
+            .read()
+            .unwrap()
+            .modified_between(bucket, prefix, window.after, before);
+        for key in keys {
+            // Deleted or rewritten since the index was read
+            match self.get_object_meta(bucket, &key) {
+                Ok(meta) if meta.last_modified < before => f(meta)?,
+                _ => {}
+            }
+        }
+        Ok(before)
+    }
+
+    /// Publish a temp file that was written and hashed incrementally as an object
+    fn store_streamed_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        tmp: TempFile<'_>,
+        etag: String,
+        content_type: &str,
+    ) -> Result<ObjectMeta, AppError> {
+        let _lock = self.lock_key(bucket, key);
+        let size = fs::metadata(tmp.path())?.len();
+        let meta = ObjectMeta {
+            key: key.to_string(),
+            bucket: bucket.to_string(),
+            size,
+            content_type: content_type.to_string(),
+            etag,
+            last_modified: self.now(),
+            metadata: HashMap::new(),
+            parts: Vec::new(),
+            checksum: None,
+            original_filename: None,
+            website_redirect_location: None,
+            source_mtime: None,
+            etag_provisional: false,
+            origin: None,
+            allocated_size: None,
+        };
+        if self.is_quarantined(bucket) {
+            return self.quarantine_upload(tmp, meta);
+        }
+        tmp.commit(&self.object_path(bucket, key))?;
+
+        self.save_object_meta(&meta)?;
+
+        self.update_bucket_stats(bucket)?;
+        tracing::info!("Put object: {}/{} ({} bytes)", bucket, key, size);
+        Ok(meta)
+    }
+
+    /// Recount a bucket after a change and bump its revision, so cached
+    /// listings of it stop validating
     fn update_bucket_stats(&self, bucket_name: &str) -> Result<(), AppError> {
+        let _op = self.time_op("recount", bucket_name, None);
         let objects_dir = self.bucket_path(bucket_name).join("objects");
-        let (count, size) = Self::dir_stats(&objects_dir);
+        // A walk cut short by the filesystem would undercount; the last good
+        // numbers stand until a later recount gets through
+        let stats = match self.retry_io(|| self.try_dir_stats(&objects_dir)) {
+            Ok(stats) => stats,
+            Err(e) => {
+                tracing::warn!("Cannot recount bucket {}, keeping its previous stats: {}", bucket_name, e);
+                return Ok(());
+            }
+        };
 
         let mut buckets = self.buckets.write().unwrap();
         if let Some(bucket) = buckets.get_mut(bucket_name) {
-            bucket.object_count = count;
-            bucket.total_size = size;
-
-            // Persist
-            let meta_path = self.bucket_path(bucket_name).join(".bucket_meta.json");
-            let json = serde_json::to_string_pretty(&bucket).unwrap();
-            let _ = fs::write(&meta_path, json);
+            // Journaled; the metadata file is only rewritten on compaction
+            self.journal_bucket_stats(bucket, stats);
         }
 
         Ok(())
     }
 
-    fn dir_stats(dir: &Path) -> (u64, u64) {
-        let mut count = 0u64;
-        let mut size = 0u64;
+    /// Count objects and bytes stored under a key prefix
+    pub fn prefix_stats(&self, bucket: &str, prefix: &str) -> Result<PrefixStats, AppError> {
+        let mut _op = self.time_op("prefix_stats", bucket, None);
+        _op.detail(|| format!("prefix={:?}", prefix));
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+
+        let objects_dir = self.bucket_path(bucket).join("objects");
+        let mut object_count = 0u64;
+        let mut total_size = 0u64;
+        Self::prefix_walk(&objects_dir, &objects_dir, prefix, &mut object_count, &mut total_size);
+
+        Ok(PrefixStats {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            object_count,
+            total_size,
+            total_size_human: human_readable_size(total_size),
+        })
+    }
 
-        if let Ok(entries) = fs::read_dir(dir) {
+    fn prefix_walk(dir: &Path, root: &Path, prefix: &str, count: &mut u64, size: &mut u64) {
+        let mut stack = vec![dir.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else { continue };
             for entry in entries.flatten() {
                 let path = entry.path();
+                let rel = walk::relative_key(root, &path);
                 if path.is_dir() {
-                    let (c, s) = Self::dir_stats(&path);
-                    count += c;
-                    size += s;
-                } else {
-                    count += 1;
-                    size += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                    // Only descend into directories that can still contain matches
+                    let dir_rel = format!("{}/", rel);
+                    if dir_rel.starts_with(prefix) || prefix.starts_with(&dir_rel) {
+                        stack.push(path);
+                    }
+                } else if rel.starts_with(prefix) {
+                    *count += 1;
+                    *size += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
                 }
             }
         }
-
-        (count, size)
     }
 
     pub fn get_stats(&self) -> StorageStats {
         let buckets = self.buckets.read().unwrap();
-        let total_buckets = buckets.len() as u64;
-        let total_objects: u64 = buckets.values().map(|b| b.object_count).sum();
-        let total_size: u64 = buckets.values().map(|b| b.total_size).sum();
+        let user_buckets = || buckets.values().filter(|b| !is_system_bucket(&b.name));
+        let total_buckets = user_buckets().count() as u64;
+        let total_objects: u64 = user_buckets().map(|b| b.object_count).sum();
+        let total_size: u64 = user_buckets().map(|b| b.total_size).sum();
+        let allocated_size: u64 = user_buckets().map(|b| b.allocated_size).sum();
 
         StorageStats {
             total_buckets,
             total_objects,
             total_size,
             total_size_human: human_readable_size(total_size),
+            allocated_size,
+            allocated_size_human: human_readable_size(allocated_size),
         }
     }
+
+    /// Per-bucket usage from the in-memory counters, largest bucket first
+    pub fn bucket_stats(&self) -> Vec<BucketStats> {
+        let buckets = self.buckets.read().unwrap();
+        let index = self.hash_index.read().unwrap();
+        let mut stats: Vec<BucketStats> = buckets
+            .values()
+            .filter(|b| !is_system_bucket(&b.name))
+            .map(|b| BucketStats {
+                name: b.name.clone(),
+                object_count: b.object_count,
+                total_size: b.total_size,
+                total_size_human: human_readable_size(b.total_size),
+                allocated_size: b.allocated_size,
+                allocated_size_human: human_readable_size(b.allocated_size),
+                last_activity: index
+                    .last_activity(&b.name)
+                    .map_or(b.created_at, |at| at.max(b.created_at)),
+            })
+            .collect();
+        stats.sort_by(|a, b| b.total_size.cmp(&a.total_size).then_with(|| a.name.cmp(&b.name)));
+        stats
+    }
+
+    /// A bucket with its usage and recent activity, all from in-memory state
+    pub fn bucket_details(&self, name: &str) -> Result<BucketDetails, AppError> {
+        let bucket = self.get_bucket(name)?;
+        let last_activity = self
+            .hash_index
+            .read()
+            .unwrap()
+            .last_activity(name)
+            .map_or(bucket.created_at, |at| at.max(bucket.created_at));
+        Ok(BucketDetails {
+            total_size_human: human_readable_size(bucket.total_size),
+            allocated_size_human: human_readable_size(bucket.allocated_size),
+            last_activity,
+            recent_objects: self.recent_objects(name, RECENT_OBJECTS),
+            bucket,
+        })
+    }
+}
+
+/// Sort value of an object under `ListSort::LastModified` or `ListSort::Size`
+fn index_value(sort: ListSort, size: u64, last_modified: DateTime<Utc>) -> i64 {
+    match sort {
+        ListSort::Size => size as i64,
+        _ => last_modified.timestamp_micros(),
+    }
 }
 
-pub fn human_readable_size(bytes: u64) -> String {
-    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB", "PB"];
-    let mut size = bytes as f64;
-    let mut unit_idx = 0;
-    while size >= 1024.0 && unit_idx < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit_idx += 1;
-    }
-    if unit_idx == 0 {
-        format!("{} {}", bytes, UNITS[0])
-    } else {
-        format!("{:.2} {}", size, UNITS[unit_idx])
+/// Sort value of an object under the access sorts; never read sorts first
+fn access_value(sort: ListSort, access: ObjectAccess) -> i64 {
+    match sort {
+        ListSort::Downloads => access.downloads as i64,
+        _ => access.last_accessed.map_or(i64::MIN, |t| t.timestamp_micros()),
+    }
+}
+
+/// Continuation tokens are opaque to clients: the sort they belong to and
+/// the end of the listing's modification-time window, the last object's
+/// sort value, and its key, base64-encoded
+fn encode_list_token(sort: ListSort, value: i64, key: &str, modified_before: Option<DateTime<Utc>>) -> String {
+    use base64::Engine;
+    let listing = match modified_before {
+        Some(before) => format!("{:?} {}", sort, before.timestamp_nanos_opt().unwrap_or(i64::MAX)),
+        None => format!("{:?}", sort),
+    };
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!("{}\n{}\n{}", listing, value, key))
+}
+
+/// Where a continuation token resumes
+struct ListToken {
+    value: i64,
+    key: String,
+    modified_before: Option<DateTime<Utc>>,
+}
+
+fn decode_list_token(token: &str, sort: ListSort) -> Result<ListToken, AppError> {
+    use base64::Engine;
+    let invalid = || AppError::InvalidRequest("Invalid continuation token".to_string());
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(token)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(invalid)?;
+    let mut fields = decoded.splitn(3, '\n');
+    let (Some(listing), Some(value), Some(key)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(invalid());
+    };
+    let (token_sort, modified_before) = match listing.split_once(' ') {
+        Some((token_sort, before)) => {
+            let nanos = before.parse().map_err(|_| invalid())?;
+            (token_sort, Some(DateTime::from_timestamp_nanos(nanos)))
+        }
+        None => (listing, None),
+    };
+    if token_sort != format!("{:?}", sort) {
+        return Err(AppError::InvalidRequest(
+            "Continuation token belongs to a listing with a different sort".to_string(),
+        ));
+    }
+    Ok(ListToken {
+        value: value.parse().map_err(|_| invalid())?,
+        key: key.to_string(),
+        modified_before,
+    })
+}
+
+
+#[cfg(unix)]
+fn set_file_mtime(path: &Path, mtime: DateTime<Utc>) -> std::io::Result<()> {
+    use nix::sys::time::TimeVal;
+    let time = TimeVal::new(mtime.timestamp() as _, mtime.timestamp_subsec_micros() as _);
+    // A freshly written payload has not been read, so its access time goes along
+    nix::sys::stat::utimes(path, &time, &time).map_err(std::io::Error::from)
+}
+
+#[cfg(not(unix))]
+fn set_file_mtime(_path: &Path, _mtime: DateTime<Utc>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Engines on scratch data dirs for the tests of the storage modules
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::{StorageEngine, StorageOptions};
+
+    /// An engine with the built-in settings on a fresh data dir, which is
+    /// removed when the returned guard is dropped
+    pub(crate) fn engine() -> (tempfile::TempDir, StorageEngine) {
+        engine_with(|_| {})
+    }
+
+    /// Like `engine`, with the settings adjusted first
+    pub(crate) fn engine_with(adjust: impl FnOnce(&mut StorageOptions)) -> (tempfile::TempDir, StorageEngine) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = crate::config::Config::builtin().storage_options();
+        adjust(&mut options);
+        let engine = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+        (dir, engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::storage::testing::engine;
+
+    #[test]
+    fn a_half_created_bucket_is_repaired_at_startup() {
+        let (dir, storage) = engine();
+        storage.create_bucket("photos", "local").unwrap();
+        storage
+            .put_object("photos", "a.txt", b"hello", PutOptions::default())
+            .unwrap();
+        drop(storage);
+        // As if the create stopped before its metadata was written
+        fs::remove_file(dir.path().join("photos/.bucket_meta.json")).unwrap();
+
+        let options = crate::config::Config::builtin().storage_options();
+        let storage = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+        let bucket = storage.get_bucket("photos").unwrap();
+        assert_eq!((bucket.object_count, bucket.total_size), (1, 5));
+        assert!(dir.path().join("photos/.bucket_meta.json").exists());
+        assert_eq!(storage.get_object("photos", "a.txt").unwrap().1, b"hello");
+    }
+
+    #[test]
+    fn creating_over_a_leftover_directory_repairs_it() {
+        let (dir, storage) = engine();
+        // Made after startup, so not a registered bucket
+        fs::create_dir_all(dir.path().join("photos/objects")).unwrap();
+
+        storage.create_bucket("photos", "eu-west").unwrap();
+        assert_eq!(storage.get_bucket("photos").unwrap().region, "eu-west");
+        assert!(dir.path().join("photos/.bucket_meta.json").exists());
+        storage
+            .put_object("photos", "a.txt", b"hello", PutOptions::default())
+            .unwrap();
+        assert!(matches!(
+            storage.create_bucket("photos", "eu-west"),
+            Err(AppError::BucketAlreadyExists(_))
+        ));
+    }
+
+    #[test]
+    fn reserved_names_are_refused_for_new_buckets() {
+        let (_dir, storage) = crate::storage::testing::engine_with(|o| {
+            o.reserved_bucket_names.push("backups".to_string());
+        });
+        for name in ["api", "metrics", "backups"] {
+            match storage.create_bucket(name, "local") {
+                Err(AppError::ReservedBucketName { name: refused, reserved }) => {
+                    assert_eq!(refused, name);
+                    assert!(reserved.iter().any(|r| r == "healthz") && reserved.iter().any(|r| r == "backups"));
+                }
+                other => panic!("{} was not refused: {:?}", name, other),
+            }
+        }
+        let (status, code, message) = storage.create_bucket("inbox", "local").unwrap_err().parts();
+        assert_eq!((status.as_u16(), code), (400, "ReservedBucketName"));
+        assert!(message.contains("reserved names are: "), "{}", message);
+        // Only whole names are reserved
+        assert!(storage.create_bucket("api-logs", "local").is_ok());
+    }
+
+    #[test]
+    fn legacy_buckets_with_reserved_names_still_load() {
+        let (dir, storage) = engine();
+        drop(storage);
+        // Left by a version that didn't reserve the name
+        fs::create_dir_all(dir.path().join("healthz/objects")).unwrap();
+        fs::write(dir.path().join("healthz/objects/status.txt"), b"ok").unwrap();
+
+        let options = crate::config::Config::builtin().storage_options();
+        let storage = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+        let bucket = storage.get_bucket("healthz").unwrap();
+        assert!(bucket.reserved);
+        assert_eq!(storage.get_object("healthz", "status.txt").unwrap().1, b"ok");
+        storage
+            .put_object("healthz", "more.txt", b"ok", PutOptions::default())
+            .unwrap();
+        // The flag is worked out at load time, never stored
+        let stored = fs::read_to_string(dir.path().join("healthz/.bucket_meta.json")).unwrap();
+        assert!(!stored.contains("reserved"), "{}", stored);
+    }
+
+    /// An engine whose clock moves on a minute every time it is read, with
+    /// `keys` written in order; returns each key's modification time
+    fn written_in_turn(keys: &[&str]) -> (tempfile::TempDir, StorageEngine, Vec<DateTime<Utc>>) {
+        let (dir, storage) = crate::storage::testing::engine_with(|options| {
+            let start = "2026-06-01T00:00:00Z".parse().unwrap();
+            let clock = crate::clock::SteppingClock::new(start, chrono::Duration::minutes(1));
+            options.sources = Sources::new(clock, crate::clock::SequentialIds::default());
+        });
+        storage.create_bucket("photos", "local").unwrap();
+        let times = keys
+            .iter()
+            .map(|key| {
+                storage
+                    .put_object("photos", key, b"x", PutOptions::default())
+                    .unwrap()
+                    .last_modified
+            })
+            .collect();
+        (dir, storage, times)
+    }
+
+    fn windowed(
+        storage: &StorageEngine,
+        prefix: &str,
+        window: ModifiedWindow,
+        max_keys: u32,
+        token: Option<&str>,
+    ) -> ListObjectsResponse {
+        storage
+            .list_objects_sorted("photos", prefix, None, max_keys, ListOrder { window, ..Default::default() }, token)
+            .unwrap()
+    }
+
+    fn listed(listing: &ListObjectsResponse) -> Vec<&str> {
+        listing.objects.iter().map(|o| o.key.as_str()).collect()
+    }
+
+    #[test]
+    fn windows_include_their_start_and_exclude_their_end() {
+        let (_dir, storage, times) = written_in_turn(&["a", "raw/b", "c", "raw/d", "e"]);
+        let window = |after: Option<usize>, before: Option<usize>| ModifiedWindow {
+            after: after.map(|i| times[i]),
+            before: before.map(|i| times[i]),
+        };
+        assert_eq!(listed(&windowed(&storage, "", window(Some(1), Some(3)), 100, None)), ["c", "raw/b"]);
+        assert_eq!(listed(&windowed(&storage, "", window(Some(3), None), 100, None)), ["e", "raw/d"]);
+        assert_eq!(listed(&windowed(&storage, "", window(None, Some(1)), 100, None)), ["a"]);
+        assert!(windowed(&storage, "", window(Some(2), Some(2)), 100, None).objects.is_empty());
+        // The prefix narrows the window further
+        assert_eq!(listed(&windowed(&storage, "raw/", window(Some(1), None), 100, None)), ["raw/b", "raw/d"]);
+        assert_eq!(listed(&windowed(&storage, "raw/", window(Some(2), Some(4)), 100, None)), ["raw/d"]);
+
+        // A rewrite moves an object to its new time
+        storage
+            .put_object("photos", "a", b"y", PutOptions::default())
+            .unwrap();
+        assert_eq!(listed(&windowed(&storage, "", window(Some(4), None), 100, None)), ["a", "e"]);
+        assert!(windowed(&storage, "", window(None, Some(1)), 100, None).objects.is_empty());
+    }
+
+    #[test]
+    fn pages_of_an_open_window_keep_its_first_end() {
+        let (_dir, storage, times) = written_in_turn(&["a", "b", "c", "d"]);
+        let window = ModifiedWindow {
+            after: Some(times[1]),
+            before: None,
+        };
+        let first = windowed(&storage, "", window, 2, None);
+        assert_eq!(listed(&first), ["b", "c"]);
+        assert!(first.is_truncated);
+        let end = first.modified_before.unwrap();
+        assert!(end > times[3]);
+
+        storage
+            .put_object("photos", "0-new", b"x", PutOptions::default())
+            .unwrap();
+        let token = first.next_continuation_token.as_deref();
+        let second = windowed(&storage, "", window, 2, token);
+        assert_eq!(listed(&second), ["d"]);
+        assert!(!second.is_truncated);
+        assert_eq!(second.modified_before, Some(end));
+
+        // The next window starts where this one ended and picks up the new object
+        let next = ModifiedWindow {
+            after: Some(end),
+            before: None,
+        };
+        assert_eq!(listed(&windowed(&storage, "", next, 100, None)), ["0-new"]);
     }
 }
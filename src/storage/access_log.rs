@@ -0,0 +1,62 @@
+use super::{PutOptions, StorageEngine};
+use crate::error::AppError;
+use crate::models::{Bucket, LoggingConfig, ObjectMeta};
+
+impl StorageEngine {
+    /// Turn server access logging for a bucket on (`Some`) or off (`None`)
+    pub fn set_bucket_logging(
+        &self,
+        bucket: &str,
+        logging: Option<LoggingConfig>,
+    ) -> Result<Bucket, AppError> {
+        if let Some(config) = &logging {
+            self.get_bucket(&config.target_bucket)?;
+        }
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.logging = logging;
+        }
+        // Persists the settings along with the stats
+        self.persist_bucket_meta(bucket)?;
+        let info = self.get_bucket(bucket)?;
+        match &info.logging {
+            Some(config) => tracing::info!(
+                "Access logging for {} enabled into {}/{}",
+                bucket,
+                config.target_bucket,
+                config.target_prefix
+            ),
+            None => tracing::info!("Access logging for {} disabled", bucket),
+        }
+        Ok(info)
+    }
+
+    /// Store a batch of access log lines as one object, named like S3's
+    /// `<prefix>YYYY-mm-DD-HH-MM-SS-<UniqueString>`
+    pub fn write_access_log(
+        &self,
+        target: &LoggingConfig,
+        lines: &[String],
+    ) -> Result<ObjectMeta, AppError> {
+        let key = format!(
+            "{}{}-{}",
+            target.target_prefix,
+            self.now().format("%Y-%m-%d-%H-%M-%S"),
+            self.new_id().simple().to_string()[..16].to_ascii_uppercase()
+        );
+        let mut body = lines.join("\n");
+        body.push('\n');
+        self.put_object(
+            &target.target_bucket,
+            &key,
+            body.as_bytes(),
+            PutOptions {
+                content_type: Some("text/plain"),
+                ..Default::default()
+            },
+        )
+    }
+}
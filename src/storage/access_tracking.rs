@@ -0,0 +1,161 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use super::StorageEngine;
+use crate::models::ObjectAccess;
+
+/// Read counts of one bucket's objects
+#[derive(Default)]
+struct BucketAccess {
+    objects: HashMap<String, ObjectAccess>,
+    /// Changed since the sidecar file was last written
+    dirty: bool,
+    /// Bumped by every change, so cached listings that show access
+    /// columns stop validating
+    generation: u64,
+}
+
+/// In-memory download counters. Reads only touch memory; each bucket's
+/// counters are written to `<bucket>/.access.json` by `flush_access_stats`,
+/// so a crash loses at most one flush interval of counts.
+#[derive(Default)]
+pub(super) struct AccessTracker {
+    buckets: Mutex<HashMap<String, BucketAccess>>,
+}
+
+impl StorageEngine {
+    fn access_path(&self, bucket: &str) -> PathBuf {
+        self.bucket_path(bucket).join(".access.json")
+    }
+
+    /// Load a bucket's counters from its sidecar file
+    pub(super) fn load_access_stats(&self, bucket: &str) {
+        if !self.access_tracking {
+            return;
+        }
+        let objects = fs::read(self.access_path(bucket))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        self.access.buckets.lock().unwrap().insert(
+            bucket.to_string(),
+            BucketAccess {
+                objects,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Count a GET or HEAD of an object
+    pub fn record_access(&self, bucket: &str, key: &str) {
+        if !self.access_tracking {
+            return;
+        }
+        let mut buckets = self.access.buckets.lock().unwrap();
+        let stats = buckets.entry(bucket.to_string()).or_default();
+        let entry = stats.objects.entry(key.to_string()).or_default();
+        entry.downloads += 1;
+        entry.last_accessed = Some(self.now());
+        stats.dirty = true;
+        stats.generation += 1;
+    }
+
+    /// Download count and last read of an object, or None with tracking off
+    pub fn object_access(&self, bucket: &str, key: &str) -> Option<ObjectAccess> {
+        if !self.access_tracking {
+            return None;
+        }
+        let buckets = self.access.buckets.lock().unwrap();
+        Some(
+            buckets
+                .get(bucket)
+                .and_then(|stats| stats.objects.get(key))
+                .copied()
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Counters for every tracked object in a bucket, or None with tracking off
+    pub fn bucket_access(&self, bucket: &str) -> Option<HashMap<String, ObjectAccess>> {
+        if !self.access_tracking {
+            return None;
+        }
+        let buckets = self.access.buckets.lock().unwrap();
+        Some(buckets.get(bucket).map(|stats| stats.objects.clone()).unwrap_or_default())
+    }
+
+    /// Changes to a bucket's counters so far, for listing ETags
+    pub fn access_generation(&self, bucket: &str) -> u64 {
+        let buckets = self.access.buckets.lock().unwrap();
+        buckets.get(bucket).map_or(0, |stats| stats.generation)
+    }
+
+    /// Drop a deleted object's counters
+    pub(super) fn forget_access(&self, bucket: &str, key: &str) {
+        let mut buckets = self.access.buckets.lock().unwrap();
+        if let Some(stats) = buckets.get_mut(bucket) {
+            if stats.objects.remove(key).is_some() {
+                stats.dirty = true;
+                stats.generation += 1;
+            }
+        }
+    }
+
+    /// Keep only the counters of `keys`, after a bucket's contents were
+    /// replaced wholesale
+    pub(super) fn retain_access(&self, bucket: &str, keys: &HashSet<&str>) {
+        let mut buckets = self.access.buckets.lock().unwrap();
+        if let Some(stats) = buckets.get_mut(bucket) {
+            let before = stats.objects.len();
+            stats.objects.retain(|key, _| keys.contains(key.as_str()));
+            if stats.objects.len() != before {
+                stats.dirty = true;
+                stats.generation += 1;
+            }
+        }
+    }
+
+    pub(super) fn remove_bucket_access(&self, bucket: &str) {
+        self.access.buckets.lock().unwrap().remove(bucket);
+    }
+
+    /// Write the counters of every bucket that changed since the last flush.
+    /// Each file is swapped in whole, so a crash mid-flush leaves the
+    /// previous one intact. Returns how many buckets were written.
+    pub fn flush_access_stats(&self) -> usize {
+        if !self.access_tracking {
+            return 0;
+        }
+        let dirty: Vec<(String, Vec<u8>)> = {
+            let mut buckets = self.access.buckets.lock().unwrap();
+            buckets
+                .iter_mut()
+                .filter(|(_, stats)| stats.dirty)
+                .map(|(name, stats)| {
+                    stats.dirty = false;
+                    (name.clone(), serde_json::to_vec(&stats.objects).unwrap())
+                })
+                .collect()
+        };
+
+        let mut written = 0;
+        for (bucket, data) in dirty {
+            let path = self.access_path(&bucket);
+            if !self.bucket_path(&bucket).is_dir() {
+                continue;
+            }
+            match self.replace_file(&path, &data) {
+                Ok(()) => written += 1,
+                Err(e) => {
+                    tracing::warn!("Cannot write access stats for {}: {}", bucket, e);
+                    if let Some(stats) = self.access.buckets.lock().unwrap().get_mut(&bucket) {
+                        stats.dirty = true;
+                    }
+                }
+            }
+        }
+        written
+    }
+}
@@ -0,0 +1,177 @@
+//! Usage alerts: buckets whose object count or size crossed a threshold in
+//! their `alerts` setting. A periodic check compares the in-memory bucket
+//! stats with each threshold. An alert is raised when usage reaches its
+//! threshold and cleared only once usage falls `CLEAR_MARGIN_PERCENT` below
+//! it, so a bucket hovering around the line doesn't flap. Each change is
+//! written to the audit log and sent to the bucket's webhooks and exec
+//! hooks; nothing is sent while an alert simply stays in effect. Alerts
+//! are kept in memory, so the first check after a restart raises the ones
+//! still in effect again.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::Mutex;
+
+use super::events::EventKind;
+use super::{Audit, StorageEngine};
+use crate::error::AppError;
+use crate::models::{AlertMetric, AlertThresholds, AuditAction, Bucket, UsageAlert};
+
+/// How far below its threshold, in percent of it, usage must fall before
+/// an alert clears
+const CLEAR_MARGIN_PERCENT: u64 = 5;
+
+/// Who the audit log names for alert changes
+const ACTOR: &str = "alerts";
+
+/// Alerts in effect, by bucket and metric
+#[derive(Default)]
+pub(super) struct ActiveAlerts(Mutex<BTreeMap<(String, AlertMetric), UsageAlert>>);
+
+impl StorageEngine {
+    /// Replace the usage levels that raise alerts on a bucket; `None` or no
+    /// thresholds removes them
+    pub fn set_bucket_alerts(&self, bucket: &str, alerts: Option<AlertThresholds>) -> Result<Bucket, AppError> {
+        let alerts = alerts.filter(|a| !a.is_empty());
+        if let Some(alerts) = &alerts {
+            if alerts.quota_percent.is_some_and(|p| !(1..=100).contains(&p)) {
+                return Err(AppError::InvalidRequest(
+                    "quota_percent must be between 1 and 100".to_string(),
+                ));
+            }
+            if alerts.bytes == Some(0) || alerts.objects == Some(0) {
+                return Err(AppError::InvalidRequest(
+                    "Alert thresholds must be at least 1".to_string(),
+                ));
+            }
+        }
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.alerts = alerts;
+        }
+        self.persist_bucket_meta(bucket)?;
+        tracing::info!("Alert thresholds of bucket {} updated", bucket);
+        self.get_bucket(bucket)
+    }
+
+    /// Alerts in effect, by bucket
+    pub fn active_alerts(&self) -> Vec<UsageAlert> {
+        self.alerts.0.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Compare every bucket's usage with its thresholds, raising and
+    /// clearing alerts. Returns how many were raised or cleared.
+    pub fn check_alerts(&self) -> usize {
+        let buckets = self.buckets.read().unwrap();
+        let names: HashSet<String> = buckets.keys().cloned().collect();
+        let levels: Vec<(String, AlertMetric, u64, u64)> = buckets
+            .values()
+            .filter(|b| !super::is_system_bucket(&b.name))
+            .flat_map(|b| {
+                alert_levels(b)
+                    .into_iter()
+                    .map(|(metric, value, threshold)| (b.name.clone(), metric, value, threshold))
+            })
+            .collect();
+        drop(buckets);
+        let now = self.now();
+        let mut changes = Vec::new();
+        {
+            let mut active = self.alerts.0.lock().unwrap();
+            let checked: BTreeSet<(String, AlertMetric)> =
+                levels.iter().map(|(bucket, metric, ..)| (bucket.clone(), *metric)).collect();
+            // A deleted bucket's alerts go quietly; a removed threshold's clear
+            active.retain(|key, alert| {
+                if checked.contains(key) {
+                    return true;
+                }
+                if names.contains(&key.0) {
+                    changes.push((EventKind::AlertCleared, alert.clone()));
+                }
+                false
+            });
+            for (bucket, metric, value, threshold) in levels {
+                let key = (bucket, metric);
+                match active.get_mut(&key) {
+                    Some(alert) => {
+                        alert.value = value;
+                        alert.threshold = threshold;
+                        if value.saturating_mul(100) < threshold.saturating_mul(100 - CLEAR_MARGIN_PERCENT) {
+                            changes.push((EventKind::AlertCleared, alert.clone()));
+                            active.remove(&key);
+                        }
+                    }
+                    None if value >= threshold => {
+                        let alert = UsageAlert {
+                            bucket: key.0.clone(),
+                            metric,
+                            value,
+                            threshold,
+                            raised_at: now,
+                        };
+                        changes.push((EventKind::AlertRaised, alert.clone()));
+                        active.insert(key, alert);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        for (kind, alert) in &changes {
+            let action = if *kind == EventKind::AlertRaised {
+                tracing::warn!(
+                    "Bucket {} is at {} {}, past its alert threshold of {}",
+                    alert.bucket,
+                    alert.value,
+                    unit(alert.metric),
+                    alert.threshold
+                );
+                AuditAction::AlertRaised
+            } else {
+                tracing::info!("Usage alert on {} of bucket {} cleared", alert.metric.name(), alert.bucket);
+                AuditAction::AlertCleared
+            };
+            let audit = Audit {
+                actor: ACTOR,
+                action,
+                bucket: Some(&alert.bucket),
+                target: alert.metric.name(),
+                changes: vec![format!("{} of {} {}", alert.value, alert.threshold, unit(alert.metric))],
+            };
+            if let Err(e) = self.audited(audit, Ok(())) {
+                tracing::error!("{}", e.parts().2);
+            }
+            self.alert_event(*kind, alert);
+        }
+        changes.len()
+    }
+}
+
+/// Each threshold a bucket has, as `(metric, usage, threshold)`
+fn alert_levels(bucket: &Bucket) -> Vec<(AlertMetric, u64, u64)> {
+    let Some(alerts) = &bucket.alerts else {
+        return Vec::new();
+    };
+    let mut levels = Vec::new();
+    if let (Some(percent), Some(limit)) = (alerts.quota_percent, bucket.max_objects) {
+        // Rounded up, so 90% of a 15-object limit alerts at 14 objects
+        let threshold = limit.saturating_mul(percent as u64).div_ceil(100);
+        levels.push((AlertMetric::Quota, bucket.object_count, threshold.max(1)));
+    }
+    if let Some(bytes) = alerts.bytes {
+        levels.push((AlertMetric::Bytes, bucket.total_size, bytes));
+    }
+    if let Some(objects) = alerts.objects {
+        levels.push((AlertMetric::Objects, bucket.object_count, objects));
+    }
+    levels
+}
+
+fn unit(metric: AlertMetric) -> &'static str {
+    match metric {
+        AlertMetric::Bytes => "bytes",
+        AlertMetric::Quota | AlertMetric::Objects => "objects",
+    }
+}
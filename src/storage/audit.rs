@@ -0,0 +1,198 @@
+//! Audit log: who created, deleted, or reconfigured what, and whether it
+//! worked. Entries are appended as JSON lines to `<data_dir>/.audit/` and
+//! synced to disk before the action is answered, so the trail can't miss a
+//! delete that went through. The file is rotated once it reaches its size
+//! cap, keeping `ROTATED_FILES` older files.
+
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{AuditAction, AuditEntry, AuditOutcome, AuditQuery};
+
+/// Under the data dir; hidden, so it is never scanned as a bucket
+const AUDIT_DIR: &str = ".audit";
+/// Older files kept after rotation, as `audit.1.jsonl` (newest) onwards
+const ROTATED_FILES: usize = 4;
+/// Latest entries kept in memory for the dashboard
+const RECENT_ENTRIES: usize = 20;
+/// Entries a query returns unless it asks for fewer, and at most
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// An action to record; the time and outcome are filled in
+pub struct Audit<'a> {
+    pub actor: &'a str,
+    pub action: AuditAction,
+    pub bucket: Option<&'a str>,
+    pub target: &'a str,
+    pub changes: Vec<String>,
+}
+
+/// Serializes appends and rotation, and holds the latest entries
+pub(super) struct AuditLog {
+    max_size: u64,
+    recent: Mutex<Recent>,
+}
+
+#[derive(Default)]
+struct Recent {
+    entries: VecDeque<AuditEntry>,
+    /// Length of the current file as of the last read or append. Another
+    /// length means another process, such as the CLI, wrote to it.
+    file_len: u64,
+}
+
+impl AuditLog {
+    pub(super) fn new(max_size: u64) -> Self {
+        Self {
+            max_size,
+            recent: Mutex::new(Recent::default()),
+        }
+    }
+}
+
+impl StorageEngine {
+    fn audit_path(&self, generation: usize) -> PathBuf {
+        let name = match generation {
+            0 => "audit.jsonl".to_string(),
+            n => format!("audit.{}.jsonl", n),
+        };
+        self.root.join(AUDIT_DIR).join(name)
+    }
+
+    /// Fill the in-memory list of latest entries from the last run's log,
+    /// ending a line torn by a crash so the next entry starts on its own
+    pub(super) fn load_recent_audit(&self) {
+        let path = self.audit_path(0);
+        if fs::read(&path).is_ok_and(|data| data.last().is_some_and(|b| *b != b'\n')) {
+            let appended = OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(b"\n"));
+            if let Err(e) = appended {
+                tracing::warn!("Cannot end the torn last line of the audit log: {}", e);
+            }
+        }
+        self.reload_recent_audit(&mut self.audit.recent.lock().unwrap());
+    }
+
+    fn reload_recent_audit(&self, recent: &mut Recent) {
+        let path = self.audit_path(0);
+        let mut entries = read_audit_file(&path);
+        let skip = entries.len().saturating_sub(RECENT_ENTRIES);
+        recent.entries = entries.drain(skip..).collect();
+        recent.file_len = fs::metadata(&path).map_or(0, |m| m.len());
+    }
+
+    /// Record `action` with the outcome of `result`, then hand the result
+    /// back. An action that succeeded but cannot be recorded is reported
+    /// as an error, since it would otherwise leave no trace.
+    pub fn audited<T>(&self, action: Audit<'_>, result: Result<T, AppError>) -> Result<T, AppError> {
+        let entry = AuditEntry {
+            time: self.now(),
+            actor: action.actor.to_string(),
+            action: action.action,
+            bucket: action.bucket.map(str::to_string),
+            target: action.target.to_string(),
+            changes: action.changes,
+            outcome: if result.is_ok() { AuditOutcome::Succeeded } else { AuditOutcome::Failed },
+            error: result.as_ref().err().map(|e| e.parts().2),
+        };
+        match self.append_audit(&entry) {
+            Ok(()) => result,
+            Err(e) if result.is_ok() => Err(AppError::StorageError(format!(
+                "The change to {} went through but could not be written to the audit log: {}",
+                entry.target, e
+            ))),
+            Err(e) => {
+                tracing::error!("Cannot write to the audit log: {}", e);
+                result
+            }
+        }
+    }
+
+    fn append_audit(&self, entry: &AuditEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(entry).unwrap();
+        line.push('\n');
+        let mut recent = self.audit.recent.lock().unwrap();
+        let path = self.audit_path(0);
+        fs::create_dir_all(self.root.join(AUDIT_DIR))?;
+        let size = fs::metadata(&path).map_or(0, |m| m.len());
+        if size > 0 && size + line.len() as u64 > self.audit.max_size {
+            self.rotate_audit()?;
+            recent.file_len = 0;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        let len = file.metadata()?.len();
+        if len != recent.file_len + line.len() as u64 {
+            // Written to by another process as well
+            self.reload_recent_audit(&mut recent);
+            return Ok(());
+        }
+        if recent.entries.len() == RECENT_ENTRIES {
+            recent.entries.pop_front();
+        }
+        recent.entries.push_back(entry.clone());
+        recent.file_len = len;
+        Ok(())
+    }
+
+    /// Shift each file one generation older, dropping the oldest
+    fn rotate_audit(&self) -> std::io::Result<()> {
+        for generation in (0..ROTATED_FILES).rev() {
+            match fs::rename(self.audit_path(generation), self.audit_path(generation + 1)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The latest entries, newest first. The log is only read when
+    /// another process wrote to it.
+    pub fn recent_audit(&self, limit: usize) -> Vec<AuditEntry> {
+        let mut recent = self.audit.recent.lock().unwrap();
+        let len = fs::metadata(self.audit_path(0)).map_or(0, |m| m.len());
+        if len != recent.file_len {
+            self.reload_recent_audit(&mut recent);
+        }
+        recent.entries.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Entries matching `query`, newest first, from the current file and
+    /// then the rotated ones
+    pub fn query_audit(&self, query: &AuditQuery) -> Vec<AuditEntry> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+        // Holding the lock keeps a rotation from moving files mid-read
+        let _recent = self.audit.recent.lock().unwrap();
+        let mut found = Vec::new();
+        for generation in 0..=ROTATED_FILES {
+            let entries = read_audit_file(&self.audit_path(generation));
+            found.extend(entries.into_iter().rev().filter(|entry| {
+                !matches!(query.action, Some(action) if entry.action != action)
+                    && !matches!(&query.bucket, Some(bucket) if entry.bucket.as_ref() != Some(bucket))
+            }));
+            if found.len() >= limit {
+                break;
+            }
+        }
+        found.truncate(limit);
+        found
+    }
+}
+
+/// Every readable entry of one file, oldest first. A line torn by a crash
+/// is skipped.
+fn read_audit_file(path: &std::path::Path) -> Vec<AuditEntry> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect()
+}
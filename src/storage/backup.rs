@@ -0,0 +1,247 @@
+//! Backups of every bucket, taken while the server runs. Object writes are
+//! paused only while payloads are hard-linked and metadata is copied into a
+//! staging directory beside each bucket, so the pause grows with the number
+//! of objects rather than their size. The staged cut is then moved into the
+//! backup and checksummed with writes flowing again.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+
+use super::manifest::file_etag;
+use super::{is_system_bucket, sparse, StorageEngine};
+use crate::error::AppError;
+use crate::models::{BackupBucket, BackupFile, BackupManifest, BackupReport, BackupVerification};
+
+/// Written last, so a backup without one is incomplete
+const MANIFEST: &str = "backup.json";
+/// Holds the bucket directories, named as in the data dir
+const BUCKETS_DIR: &str = "buckets";
+/// Entries of a bucket directory that belong to writes still in progress
+const SKIPPED: &[&str] = &[".tmp", ".uploads"];
+/// Entries rewritten or appended to in place, which a hard link would let
+/// change under the backup
+const COPIED: &[&str] = &[".meta", ".stats_journal"];
+
+/// Hard-link or copy one bucket's stored files into `stage`
+fn stage_bucket(bucket_dir: &Path, stage: &Path) -> io::Result<()> {
+    fs::create_dir_all(stage)?;
+    for entry in fs::read_dir(bucket_dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if SKIPPED.contains(&name.as_str()) || name.ends_with(".tmp") {
+            continue;
+        }
+        let from = entry.path();
+        let to = stage.join(&name);
+        let hard_link = !COPIED.contains(&name.as_str());
+        if from.is_dir() {
+            StorageEngine::link_tree(&from, &to, hard_link)?;
+        } else if !hard_link || fs::hard_link(&from, &to).is_err() {
+            sparse::copy_file(&from, &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Every file under `dir` with its checksum, by path relative to `root`
+fn list_files(root: &Path, dir: &Path, files: &mut Vec<BackupFile>) -> io::Result<()> {
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            files.push(BackupFile {
+                path: relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                size: entry.metadata()?.len(),
+                sha256: file_etag(&path)?,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Read the manifest of the backup in `dir`
+fn read_backup_manifest(dir: &Path) -> Result<BackupManifest, AppError> {
+    let data = fs::read(dir.join(MANIFEST)).map_err(|e| {
+        AppError::InvalidRequest(format!("{} is not a complete backup: cannot read {}: {}", dir.display(), MANIFEST, e))
+    })?;
+    serde_json::from_slice(&data)
+        .map_err(|e| AppError::InvalidRequest(format!("{} has an unreadable {}: {}", dir.display(), MANIFEST, e)))
+}
+
+/// Re-read every file of the backup in `dir` and compare it with the size
+/// and checksum its manifest recorded
+pub fn verify_backup(dir: &Path) -> Result<BackupVerification, AppError> {
+    let manifest = read_backup_manifest(dir)?;
+    let mut check = BackupVerification {
+        checked: 0,
+        missing: Vec::new(),
+        mismatched: Vec::new(),
+    };
+    for file in &manifest.files {
+        let path = dir.join(&file.path);
+        check.checked += 1;
+        match fs::metadata(&path) {
+            Err(_) => check.missing.push(file.path.clone()),
+            Ok(stat) if stat.len() != file.size => check.mismatched.push(file.path.clone()),
+            Ok(_) => {
+                if file_etag(&path).ok().as_deref() != Some(file.sha256.as_str()) {
+                    check.mismatched.push(file.path.clone());
+                }
+            }
+        }
+    }
+    Ok(check)
+}
+
+/// Copy the buckets of an intact backup into `data_dir`, which must not
+/// hold any of them yet. Open the result with `StorageEngine::new` and
+/// check it with `compare_with_backup`.
+pub fn restore_backup(backup: &Path, data_dir: &Path) -> Result<BackupManifest, AppError> {
+    let manifest = read_backup_manifest(backup)?;
+    let check = verify_backup(backup)?;
+    if !check.is_intact() {
+        return Err(AppError::InvalidRequest(format!(
+            "Backup {} is damaged ({} missing, {} changed file(s)); see `freebucket backup --verify {}`",
+            manifest.id,
+            check.missing.len(),
+            check.mismatched.len(),
+            backup.display()
+        )));
+    }
+    if let Some(taken) = manifest.buckets.iter().find(|b| data_dir.join(&b.name).exists()) {
+        return Err(AppError::InvalidRequest(format!(
+            "{} already has a bucket directory '{}'; restore into an empty data dir",
+            data_dir.display(),
+            taken.name
+        )));
+    }
+    fs::create_dir_all(data_dir)?;
+    for bucket in &manifest.buckets {
+        // Copied, not linked: the restored data dir rewrites metadata in place
+        StorageEngine::link_tree(&backup.join(BUCKETS_DIR).join(&bucket.name), &data_dir.join(&bucket.name), false)?;
+    }
+    Ok(manifest)
+}
+
+impl StorageEngine {
+    /// Back up every bucket into a new directory under `dir`, named after
+    /// the time of the cut. Reads and listings carry on throughout; object
+    /// writes wait while the cut is taken.
+    pub fn create_backup(&self, dir: &Path) -> Result<BackupReport, AppError> {
+        if dir.as_os_str().is_empty() {
+            return Err(AppError::InvalidRequest("Name a directory to write the backup into".to_string()));
+        }
+        let created_at = self.now();
+        let id = format!(
+            "{}-{}",
+            created_at.format("%Y%m%dT%H%M%SZ"),
+            &self.new_id().simple().to_string()[..8]
+        );
+        let target = dir.join(&id);
+        fs::create_dir_all(target.join(BUCKETS_DIR))?;
+        match self.write_backup(&id, created_at, &target) {
+            Ok(manifest) => {
+                tracing::info!(
+                    "Backed up {} bucket(s) to {} with writes paused for {:.2} ms",
+                    manifest.buckets.len(),
+                    target.display(),
+                    manifest.write_pause_ms
+                );
+                Ok(BackupReport {
+                    id: manifest.id,
+                    path: target.display().to_string(),
+                    created_at: manifest.created_at,
+                    write_pause_ms: manifest.write_pause_ms,
+                    buckets: manifest.buckets,
+                    file_count: manifest.files.len(),
+                    total_size: manifest.files.iter().map(|f| f.size).sum(),
+                })
+            }
+            Err(e) => {
+                let _ = fs::remove_dir_all(&target);
+                Err(e)
+            }
+        }
+    }
+
+    fn write_backup(&self, id: &str, created_at: DateTime<Utc>, target: &Path) -> Result<BackupManifest, AppError> {
+        let paused_at = Instant::now();
+        // Staging dirs are registered scratch space, so GC leaves them
+        // alone and they are removed however this ends
+        let staged = {
+            let _paused = self.pause_writes();
+            // Held so no bucket is created or deleted during the cut
+            let buckets = self.buckets.read().unwrap();
+            let mut staged = Vec::new();
+            for name in buckets.keys().filter(|name| !is_system_bucket(name)) {
+                let stage = self.temp_file(name)?;
+                stage_bucket(&self.bucket_path(name), stage.path())?;
+                staged.push((name.clone(), stage));
+            }
+            staged
+        };
+        let write_pause_ms = paused_at.elapsed().as_secs_f64() * 1000.0;
+
+        let mut buckets = Vec::new();
+        for (name, stage) in &staged {
+            let dest = target.join(BUCKETS_DIR).join(name);
+            if fs::rename(stage.path(), &dest).is_err() {
+                Self::link_tree(stage.path(), &dest, true)?;
+            }
+            let stats = self.dir_stats(&dest.join("objects"));
+            buckets.push(BackupBucket {
+                name: name.clone(),
+                object_count: stats.count,
+                total_size: stats.size,
+            });
+        }
+        drop(staged);
+        buckets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut files = Vec::new();
+        list_files(target, &target.join(BUCKETS_DIR), &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let manifest = BackupManifest {
+            id: id.to_string(),
+            created_at,
+            write_pause_ms,
+            buckets,
+            files,
+        };
+        let path: PathBuf = target.join(MANIFEST);
+        self.replace_file(&path, serde_json::to_string_pretty(&manifest).unwrap().as_bytes())?;
+        Ok(manifest)
+    }
+
+    /// Recount each bucket of a restored backup from its stored files and
+    /// describe any that don't match what the backup recorded
+    pub fn compare_with_backup(&self, manifest: &BackupManifest) -> Vec<String> {
+        let mut problems = Vec::new();
+        for bucket in &manifest.buckets {
+            if self.get_bucket(&bucket.name).is_err() {
+                problems.push(format!("bucket '{}' did not load", bucket.name));
+                continue;
+            }
+            let stats = self.dir_stats(&self.bucket_path(&bucket.name).join("objects"));
+            if (stats.count, stats.size) != (bucket.object_count, bucket.total_size) {
+                problems.push(format!(
+                    "bucket '{}' holds {} object(s) in {} bytes; the backup recorded {} in {} bytes",
+                    bucket.name, stats.count, stats.size, bucket.object_count, bucket.total_size
+                ));
+            }
+        }
+        problems
+    }
+}
@@ -0,0 +1,344 @@
+//! Recovery of `.bucket_meta.json` files that no longer parse, after a bad
+//! shutdown or a disk fault. Starting the bucket over would lose its
+//! creation time, region, and settings, so the file is kept byte for byte
+//! as `.bucket_meta.json.corrupt-<time>`, every field that still reads is
+//! carried into the new metadata, and the bucket is flagged with
+//! `metadata_recovered` until an admin confirms it with
+//! `freebucket fsck --confirm-metadata`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{Bucket, MetadataRecovery};
+
+/// Fields worked out at load time, never taken from a damaged file
+const NOT_SALVAGED: &[&str] = &["name", "reserved", "volume", "metadata_recovered"];
+
+/// Fields the stats journal is replayed onto; without all of them the
+/// bucket is recounted instead
+const STATS_FIELDS: &[&str] = &["object_count", "total_size", "revision"];
+
+/// The top-level object of `text`, or, when it was cut off, the fields
+/// that came complete before the cut
+fn parse_object(text: &str) -> Option<Map<String, Value>> {
+    // Files cut short by a crash are often padded with zeros
+    let text = text.trim_end_matches(|c: char| c == '\0' || c.is_whitespace());
+    if let Ok(Value::Object(fields)) = serde_json::from_str(text) {
+        return Some(fields);
+    }
+    let mut ends = top_level_commas(text);
+    ends.push(text.len());
+    ends.into_iter()
+        .rev()
+        .find_map(|end| match serde_json::from_str(&format!("{}}}", &text[..end])) {
+            Ok(Value::Object(fields)) => Some(fields),
+            _ => None,
+        })
+}
+
+/// Offsets of the commas between fields of the outermost object
+fn top_level_commas(text: &str) -> Vec<usize> {
+    let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+    let mut commas = Vec::new();
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 1 => commas.push(i),
+            _ => {}
+        }
+    }
+    commas
+}
+
+/// `fallback` with each field of `found` that reads as what it should be.
+/// A field is tried against the whole bucket, so a value of the wrong type
+/// or shape is dropped on its own. Returns the bucket and the names of the
+/// fields kept and dropped; fields the bucket has no use for are in
+/// neither.
+fn salvage_fields(fallback: &Bucket, found: Map<String, Value>) -> (Bucket, Vec<String>, Vec<String>) {
+    let mut merged = match serde_json::to_value(fallback) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+    let (mut salvaged, mut discarded) = (Vec::new(), Vec::new());
+    for (field, value) in found {
+        if NOT_SALVAGED.contains(&field.as_str()) {
+            continue;
+        }
+        let previous = merged.insert(field.clone(), value);
+        match serde_json::from_value::<Bucket>(Value::Object(merged.clone())) {
+            Ok(bucket) => {
+                let used = matches!(serde_json::to_value(&bucket), Ok(Value::Object(kept)) if kept.contains_key(&field));
+                if used {
+                    salvaged.push(field);
+                }
+            }
+            Err(_) => {
+                match previous {
+                    Some(previous) => merged.insert(field.clone(), previous),
+                    None => merged.remove(&field),
+                };
+                discarded.push(field);
+            }
+        }
+    }
+    let bucket = serde_json::from_value(Value::Object(merged)).unwrap_or_else(|_| fallback.clone());
+    (bucket, salvaged, discarded)
+}
+
+/// A name beside `meta_path` for keeping its damaged copy, unused so far
+fn corrupt_copy_path(meta_path: &Path, stamp: &str) -> PathBuf {
+    let name = meta_path.file_name().unwrap_or_default().to_string_lossy();
+    let mut path = meta_path.with_file_name(format!("{}.corrupt-{}", name, stamp));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = meta_path.with_file_name(format!("{}.corrupt-{}-{}", name, stamp, n));
+    }
+    path
+}
+
+impl StorageEngine {
+    /// Rebuild a bucket from metadata that doesn't parse, keeping what it
+    /// can. The damaged file is copied aside first; if that fails it is
+    /// left in place until the bucket next changes.
+    pub(super) fn recover_bucket_meta(&self, dir: &Path, name: &str, data: &[u8]) -> Bucket {
+        let error = match serde_json::from_slice::<Bucket>(data) {
+            Ok(_) => "the file was unreadable".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let now = self.now();
+        let meta_path = dir.join(".bucket_meta.json");
+
+        let mut fallback = self.create_bucket_meta(name);
+        // The closest thing to a creation time left, where the filesystem has one
+        if let Ok(created) = fs::metadata(dir).and_then(|m| m.created()) {
+            fallback.created_at = created.into();
+        }
+        let found = parse_object(&String::from_utf8_lossy(data)).unwrap_or_default();
+        let (mut bucket, salvaged, discarded) = salvage_fields(&fallback, found);
+        bucket.name = name.to_string();
+
+        let recounted = !STATS_FIELDS.iter().all(|f| salvaged.iter().any(|s| s == f));
+        if recounted {
+            let stats = self.dir_stats(&dir.join("objects"));
+            bucket.object_count = stats.count;
+            bucket.total_size = stats.size;
+            bucket.allocated_size = stats.allocated;
+            // Its entries are relative to the counts that were lost
+            let _ = fs::remove_file(dir.join(".stats_journal"));
+        } else {
+            self.recover_bucket_stats(&mut bucket);
+        }
+
+        let copy = corrupt_copy_path(&meta_path, &now.format("%Y%m%dT%H%M%SZ").to_string());
+        let kept = match fs::copy(&meta_path, &copy) {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::error!(
+                    "Cannot keep a copy of the unreadable metadata of bucket {} at {}: {}; leaving {} as it is",
+                    name,
+                    copy.display(),
+                    e,
+                    meta_path.display()
+                );
+                false
+            }
+        };
+        bucket.metadata_recovered = Some(MetadataRecovery {
+            recovered_at: now,
+            error: error.clone(),
+            corrupt_file: kept.then(|| copy.file_name().unwrap_or_default().to_string_lossy().into_owned()),
+            salvaged,
+            discarded,
+            recounted,
+        });
+        tracing::error!(
+            "Metadata of bucket {} is unreadable ({}). Recovered {} field(s){}{}; check its settings and run `freebucket fsck {} --confirm-metadata`",
+            name,
+            error,
+            bucket.metadata_recovered.as_ref().map_or(0, |r| r.salvaged.len()),
+            if recounted { ", recounted its objects" } else { "" },
+            if kept { format!(", kept the original as {}", copy.display()) } else { String::new() },
+            name
+        );
+        if kept {
+            if let Err(e) = self.write_bucket_meta(&bucket) {
+                tracing::error!("Failed to save the recovered metadata of bucket {}: {}", name, e);
+            }
+        }
+        bucket
+    }
+
+    /// Clear a bucket's `metadata_recovered` flag once an admin has checked
+    /// its settings. Returns the recovery that was confirmed, if any. The
+    /// kept copy of the damaged file is left for the admin to remove.
+    pub fn confirm_bucket_metadata(&self, bucket: &str) -> Result<Option<MetadataRecovery>, AppError> {
+        let recovery = {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.metadata_recovered.take()
+        };
+        if recovery.is_some() {
+            self.persist_bucket_meta(bucket)?;
+            tracing::info!("Recovered metadata of bucket {} confirmed", bucket);
+        }
+        Ok(recovery)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    /// The engine a restart would give, on the same data dir
+    fn reopen(root: &Path) -> StorageEngine {
+        let options = crate::config::Config::builtin().storage_options();
+        StorageEngine::new(root.to_str().unwrap(), options).unwrap()
+    }
+
+    /// A bucket with settings away from their defaults and two objects
+    fn photos(storage: &StorageEngine) {
+        storage.create_bucket("photos", "eu-west").unwrap();
+        storage.set_bucket_precompressed("photos", true).unwrap();
+        storage.set_bucket_max_objects("photos", Some(50)).unwrap();
+        for key in ["a.jpg", "b.jpg"] {
+            storage
+                .put_object("photos", key, b"jpeg", PutOptions::default())
+                .unwrap();
+        }
+    }
+
+    /// Replace the bucket's metadata file, and reload the data dir
+    fn damage(root: &Path, damaged: &[u8]) -> (StorageEngine, Bucket, MetadataRecovery) {
+        fs::write(root.join("photos/.bucket_meta.json"), damaged).unwrap();
+        let storage = reopen(root);
+        let bucket = storage.get_bucket("photos").unwrap();
+        let recovery = bucket.metadata_recovered.clone().expect("flagged as recovered");
+        (storage, bucket, recovery)
+    }
+
+    /// The damaged file as it was kept beside the new one
+    fn kept_copy(root: &Path, recovery: &MetadataRecovery) -> Vec<u8> {
+        let name = recovery.corrupt_file.as_ref().expect("a copy was kept");
+        assert!(name.starts_with(".bucket_meta.json.corrupt-"), "{}", name);
+        fs::read(root.join("photos").join(name)).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_fields_before_a_cut() {
+        let (dir, storage) = engine();
+        photos(&storage);
+        let created_at = storage.get_bucket("photos").unwrap().created_at;
+        drop(storage);
+
+        let text = fs::read_to_string(dir.path().join("photos/.bucket_meta.json")).unwrap();
+        // Cut in the middle of the policy, as a crash would, with zero padding
+        let cut = text.find("\"overwrite_policy\"").unwrap() + 8;
+        let mut damaged = text.as_bytes()[..cut].to_vec();
+        damaged.extend_from_slice(&[0; 64]);
+        let (storage, bucket, recovery) = damage(dir.path(), &damaged);
+
+        assert_eq!(bucket.region, "eu-west");
+        assert_eq!(bucket.created_at, created_at);
+        assert!(bucket.precompressed);
+        assert_eq!((bucket.object_count, bucket.total_size), (2, 8));
+        // max_objects came after the cut and is back at its default
+        assert_eq!(bucket.max_objects, None);
+        for field in ["region", "created_at", "precompressed", "object_count", "total_size", "revision"] {
+            assert!(recovery.salvaged.iter().any(|f| f == field), "{} in {:?}", field, recovery.salvaged);
+        }
+        assert!(!recovery.salvaged.iter().any(|f| f == "max_objects"));
+        assert!(recovery.discarded.is_empty());
+        assert!(!recovery.recounted);
+        assert_eq!(kept_copy(dir.path(), &recovery), damaged);
+
+        // The new file reads again, and the flag lasts until confirmed
+        drop(storage);
+        let storage = reopen(dir.path());
+        assert!(storage.get_bucket("photos").unwrap().metadata_recovered.is_some());
+        assert_eq!(storage.confirm_bucket_metadata("photos").unwrap(), Some(recovery));
+        drop(storage);
+        let storage = reopen(dir.path());
+        assert!(storage.get_bucket("photos").unwrap().metadata_recovered.is_none());
+        assert_eq!(storage.confirm_bucket_metadata("photos").unwrap(), None);
+    }
+
+    #[test]
+    fn drops_fields_of_the_wrong_type_on_their_own() {
+        let (dir, storage) = engine();
+        photos(&storage);
+        drop(storage);
+
+        let path = dir.path().join("photos/.bucket_meta.json");
+        let mut fields: Map<String, Value> = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        fields.insert("precompressed".into(), Value::from("yes"));
+        fields.insert("object_count".into(), Value::from(-1));
+        fields.insert("name".into(), Value::from("elsewhere"));
+        let damaged = serde_json::to_vec(&fields).unwrap();
+        let (_storage, bucket, recovery) = damage(dir.path(), &damaged);
+
+        assert_eq!(bucket.name, "photos");
+        assert_eq!(bucket.region, "eu-west");
+        assert_eq!(bucket.max_objects, Some(50));
+        assert!(!bucket.precompressed);
+        let mut discarded = recovery.discarded.clone();
+        discarded.sort();
+        assert_eq!(discarded, ["object_count", "precompressed"]);
+        assert!(!recovery.salvaged.iter().any(|f| f == "name"));
+        // Without a usable count the objects are counted again
+        assert!(recovery.recounted);
+        assert_eq!((bucket.object_count, bucket.total_size), (2, 8));
+        assert_eq!(kept_copy(dir.path(), &recovery), damaged);
+    }
+
+    #[test]
+    fn recounts_a_bucket_whose_file_is_garbage() {
+        let (dir, storage) = engine();
+        photos(&storage);
+        drop(storage);
+
+        let damaged: Vec<u8> = (0..=255u8).rev().cycle().take(700).collect();
+        let (storage, bucket, recovery) = damage(dir.path(), &damaged);
+
+        assert_eq!(bucket.region, "local");
+        assert!(!bucket.precompressed);
+        assert_eq!(bucket.max_objects, None);
+        assert!(recovery.salvaged.is_empty());
+        assert!(recovery.recounted);
+        assert!(!recovery.error.is_empty());
+        assert_eq!((bucket.object_count, bucket.total_size), (2, 8));
+        assert_eq!(kept_copy(dir.path(), &recovery), damaged);
+        // The objects are all still there
+        assert_eq!(storage.get_object("photos", "b.jpg").unwrap().1, b"jpeg");
+    }
+
+    #[test]
+    fn a_cut_never_ends_inside_a_string_or_a_nested_value() {
+        let text = r#"{"region": "eu, \"west\"", "inbox_limits": {"max_size": 1, "content_types": ["a", "b"]}, "versioning": tr"#;
+        let fields = parse_object(text).unwrap();
+        assert_eq!(fields["region"], "eu, \"west\"");
+        assert_eq!(fields["inbox_limits"]["content_types"], serde_json::json!(["a", "b"]));
+        assert!(!fields.contains_key("versioning"));
+        assert!(parse_object("not json").is_none());
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+
+use super::{EventKind, StorageEngine};
+use crate::error::AppError;
+use crate::models::{BulkOperation, BulkRequest};
+
+impl StorageEngine {
+    /// Pair every object under the source prefix with its destination key.
+    /// Keys are listed once up front, so objects written while a job runs
+    /// are not picked up.
+    pub fn plan_bulk_transfer(&self, req: &BulkRequest) -> Result<Vec<(String, String)>, AppError> {
+        self.get_bucket(&req.destination_bucket)?;
+        if req.source_bucket != req.destination_bucket {
+            self.check_unscanned_write(&req.destination_bucket, "copying from another bucket")?;
+        }
+
+        let mut plan = Vec::new();
+        self.visit_objects(&req.source_bucket, &req.source_prefix, |meta| {
+            let rest = if req.keep_source_prefix {
+                meta.key.as_str()
+            } else {
+                &meta.key[req.source_prefix.len()..]
+            };
+            let dst_key = format!("{}{}", req.destination_prefix, rest);
+            plan.push((meta.key, dst_key));
+            Ok(())
+        })?;
+
+        // Writing onto a key that is still to be read would copy the wrong content
+        if req.source_bucket == req.destination_bucket {
+            let sources: HashSet<&str> = plan.iter().map(|(src, _)| src.as_str()).collect();
+            if plan
+                .iter()
+                .any(|(src, dst)| src != dst && sources.contains(dst.as_str()))
+            {
+                return Err(AppError::InvalidRequest(
+                    "Destination keys overlap source keys in the same bucket".to_string(),
+                ));
+            }
+        }
+        self.check_bulk_object_limit(req, &plan)?;
+        Ok(plan)
+    }
+
+    /// Copy (and for moves, delete) one planned key. Bucket stats are left
+    /// alone; call `finish_bulk_transfer` once the whole plan has run.
+    pub fn bulk_transfer_object(&self, req: &BulkRequest, src_key: &str, dst_key: &str) -> Result<(), AppError> {
+        self.check_new_key(dst_key)?;
+        let dst_key = &*self.normalize_key(dst_key);
+        let src_meta = self.get_object_meta(&req.source_bucket, src_key)?;
+        if req.source_bucket == req.destination_bucket && self.normalize_key(src_key) == dst_key {
+            return Ok(());
+        }
+
+        let meta = self.copy_object_files(src_meta, &req.destination_bucket, dst_key)?;
+        self.object_created(EventKind::Copy, &meta);
+        if req.operation == BulkOperation::Move {
+            self.remove_object_files(&req.source_bucket, src_key)?;
+        }
+        Ok(())
+    }
+
+    /// Recompute stats for the buckets a bulk transfer touched
+    pub fn finish_bulk_transfer(&self, req: &BulkRequest) -> Result<(), AppError> {
+        self.update_bucket_stats(&req.destination_bucket)?;
+        if req.operation == BulkOperation::Move && req.source_bucket != req.destination_bucket {
+            self.update_bucket_stats(&req.source_bucket)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use base64::Engine;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::error::AppError;
+use crate::models::ChecksumAlgorithm;
+
+/// A checksum asked for on a write, with the client's value when it sent one
+#[derive(Debug, Clone)]
+pub struct ChecksumRequest {
+    pub algorithm: ChecksumAlgorithm,
+    pub expected: Option<String>,
+}
+
+/// Incremental checksum in one of the supported algorithms
+pub(crate) enum Checksummer {
+    Crc32(crc32fast::Hasher),
+    Crc32c(u32),
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Checksummer {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => Checksummer::Crc32(crc32fast::Hasher::new()),
+            ChecksumAlgorithm::Crc32c => Checksummer::Crc32c(0),
+            ChecksumAlgorithm::Sha1 => Checksummer::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Checksummer::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Checksummer::Crc32(h) => h.update(data),
+            Checksummer::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            Checksummer::Sha1(h) => h.update(data),
+            Checksummer::Sha256(h) => h.update(data),
+        }
+    }
+
+    /// Raw digest bytes; CRCs are big-endian as S3 encodes them
+    pub fn finalize_bytes(self) -> Vec<u8> {
+        match self {
+            Checksummer::Crc32(h) => h.finalize().to_be_bytes().to_vec(),
+            Checksummer::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+            Checksummer::Sha1(h) => h.finalize().to_vec(),
+            Checksummer::Sha256(h) => h.finalize().to_vec(),
+        }
+    }
+
+    pub fn finalize(self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.finalize_bytes())
+    }
+}
+
+/// Base64 checksum of a buffer
+pub fn checksum_of(algorithm: ChecksumAlgorithm, data: &[u8]) -> String {
+    let mut checksummer = Checksummer::new(algorithm);
+    checksummer.update(data);
+    checksummer.finalize()
+}
+
+/// Composite multipart checksum: the checksum of the concatenated raw part
+/// checksums, suffixed with the part count
+pub fn composite_checksum(algorithm: ChecksumAlgorithm, part_checksums: &[String]) -> Result<String, AppError> {
+    let mut checksummer = Checksummer::new(algorithm);
+    for part in part_checksums {
+        let raw = base64::engine::general_purpose::STANDARD
+            .decode(part)
+            .map_err(|_| AppError::StorageError(format!("Corrupt part checksum '{}'", part)))?;
+        checksummer.update(&raw);
+    }
+    Ok(format!("{}-{}", checksummer.finalize(), part_checksums.len()))
+}
+
+/// Fail with BadDigest when the client sent a value that doesn't match
+pub fn verify_checksum(request: &ChecksumRequest, actual: &str) -> Result<(), AppError> {
+    match &request.expected {
+        Some(expected) if expected.trim() != actual => {
+            Err(AppError::BadDigest(request.algorithm.name().to_string()))
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs;
+use std::io::Read;
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{ContentTypeFixReport, FixContentTypesQuery};
+
+/// Magic-number signatures for formats whose bytes identify them reliably
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (8, b"WEBP", "image/webp"),
+    (8, b"WAVE", "audio/wav"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"%!PS", "application/postscript"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"\x00asm", "application/wasm"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+];
+
+/// Container formats that many file types are built on (a .docx or .jar is a
+/// zip, an .m4a is an MP4), so a recognised extension says more than these
+const GENERIC_SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (4, b"ftyp", "video/mp4"),
+];
+
+/// How much of the content is inspected
+const SNIFF_LEN: usize = 1024;
+
+fn match_signature(data: &[u8], table: &[(usize, &[u8], &'static str)]) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(offset, magic, _)| data.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|(_, _, mime)| *mime)
+}
+
+/// UTF-8 without NUL bytes; a multi-byte character cut off by the sniff
+/// window still counts
+fn looks_like_text(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(SNIFF_LEN)];
+    if head.is_empty() || head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// Content type for an object stored without an explicit one. Precedence:
+/// a specific magic number, then the key's extension, then a generic sniff
+/// (container formats, plain text), then `application/octet-stream`.
+/// An explicit content type from the client beats all of these and is
+/// handled by the caller.
+pub(crate) fn detect_content_type(key: &str, data: &[u8]) -> String {
+    if let Some(mime) = match_signature(data, SIGNATURES) {
+        return mime.to_string();
+    }
+    if let Some(mime) = mime_guess::from_path(key).first() {
+        return mime.to_string();
+    }
+    if let Some(mime) = match_signature(data, GENERIC_SIGNATURES) {
+        return mime.to_string();
+    }
+    if looks_like_text(data) {
+        return "text/plain".to_string();
+    }
+    "application/octet-stream".to_string()
+}
+
+/// Reject a client-supplied content type that can't be sent back as a
+/// `Content-Type` header, such as one with control characters
+pub(crate) fn check_content_type(content_type: &str) -> Result<(), AppError> {
+    axum::http::HeaderValue::from_str(content_type)
+        .map(|_| ())
+        .map_err(|_| AppError::InvalidRequest(format!("Invalid content type: {:?}", content_type)))
+}
+
+/// Content types that say nothing about the content
+fn is_generic_content_type(content_type: &str) -> bool {
+    matches!(
+        content_type.trim().to_ascii_lowercase().as_str(),
+        "" | "application/octet-stream" | "binary/octet-stream"
+    )
+}
+
+impl StorageEngine {
+    /// Re-guess the content type of every object under the query's prefix
+    /// that has a generic or missing one, from its key's extension and, with
+    /// `sniff`, its first bytes. Only metadata files are rewritten.
+    pub fn fix_content_types(&self, bucket: &str, query: &FixContentTypesQuery) -> Result<ContentTypeFixReport, AppError> {
+        let excluded: Vec<String> = query
+            .exclude
+            .split(',')
+            .map(|ext| ext.trim().trim_start_matches('.').to_ascii_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect();
+        let mut report = ContentTypeFixReport {
+            bucket: bucket.to_string(),
+            dry_run: query.dry_run,
+            ..Default::default()
+        };
+
+        self.visit_objects(bucket, &query.prefix, |mut meta| {
+            if !is_generic_content_type(&meta.content_type) {
+                return Ok(());
+            }
+            let extension = std::path::Path::new(&meta.key)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+            if extension.is_some_and(|ext| excluded.contains(&ext)) {
+                return Ok(());
+            }
+            report.scanned += 1;
+
+            let guessed = if query.sniff {
+                let mut head = Vec::with_capacity(SNIFF_LEN);
+                fs::File::open(self.object_path(bucket, &meta.key))?
+                    .take(SNIFF_LEN as u64)
+                    .read_to_end(&mut head)?;
+                detect_content_type(&meta.key, &head)
+            } else {
+                mime_guess::from_path(&meta.key)
+                    .first()
+                    .map_or_else(|| "application/octet-stream".to_string(), |mime| mime.to_string())
+            };
+            if is_generic_content_type(&guessed) {
+                report.unresolved.push(meta.key);
+                return Ok(());
+            }
+
+            report.changed += 1;
+            report.by_type.entry(guessed.clone()).or_default().push(meta.key.clone());
+            if !query.dry_run {
+                meta.content_type = guessed;
+                self.write_meta_file(&meta)?;
+            }
+            Ok(())
+        })?;
+
+        if report.changed > 0 && !query.dry_run {
+            // Full listings carry the content type, so cached ones must go
+            self.update_bucket_stats(bucket)?;
+            tracing::info!("Fixed the content type of {} object(s) in {}", report.changed, bucket);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic_number_beats_extension() {
+        assert_eq!(detect_content_type("photo.txt", b"\x89PNG\r\n\x1a\nrest"), "image/png");
+    }
+
+    #[test]
+    fn extension_beats_container_signature() {
+        let docx = detect_content_type("report.docx", b"PK\x03\x04rest");
+        assert!(docx.contains("wordprocessingml"), "{}", docx);
+        assert_eq!(detect_content_type("archive", b"PK\x03\x04rest"), "application/zip");
+    }
+
+    #[test]
+    fn text_and_binary_fallbacks() {
+        assert_eq!(detect_content_type("README", b"hello world\n"), "text/plain");
+        // A multi-byte character cut off at the end is still text
+        assert_eq!(detect_content_type("notes", "caf\u{e9}".as_bytes().split_last().unwrap().1), "text/plain");
+        assert_eq!(detect_content_type("blob", b"\x00\x01\x02"), "application/octet-stream");
+        assert_eq!(detect_content_type("empty", b""), "application/octet-stream");
+    }
+
+    #[test]
+    fn content_types_must_be_valid_header_values() {
+        assert!(check_content_type("text/plain; charset=utf-8").is_ok());
+        assert!(check_content_type("").is_ok());
+        for bad in ["a\u{1}b", "text/plain\r\nx-evil: 1", "a\u{7f}"] {
+            assert!(matches!(check_content_type(bad), Err(AppError::InvalidRequest(_))), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn generic_types() {
+        assert!(is_generic_content_type(" Application/Octet-Stream "));
+        assert!(is_generic_content_type(""));
+        assert!(!is_generic_content_type("text/plain"));
+    }
+}
@@ -0,0 +1,330 @@
+//! Deferred hashing: under `FREEBUCKET_HASH_MODE=deferred` a PUT stores the
+//! payload without hashing it, under a provisional ETag that no conditional
+//! request or sync comparison ever matches. A background worker hashes the
+//! payload afterwards and swaps in the real ETag. The work is queued as one
+//! file per object under `<data_dir>/.rehash/`, so a restart picks up
+//! whatever was left.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use super::manifest::file_etag;
+use super::{is_system_bucket, EventKind, StorageEngine};
+use crate::error::AppError;
+use crate::models::ObjectMeta;
+
+const QUEUE_DIR: &str = ".rehash";
+
+/// When object payloads are hashed into their ETag
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashMode {
+    /// While the PUT is handled, before it is answered
+    Inline,
+    /// By a background worker after the PUT is answered
+    Deferred,
+}
+
+impl HashMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "inline" => Some(Self::Inline),
+            "deferred" => Some(Self::Deferred),
+            _ => None,
+        }
+    }
+}
+
+/// The hash mode and a wake-up for the worker
+pub(super) struct DeferredHashing {
+    mode: HashMode,
+    queued: Notify,
+}
+
+impl DeferredHashing {
+    pub(super) fn new(mode: HashMode) -> Self {
+        Self {
+            mode,
+            queued: Notify::new(),
+        }
+    }
+}
+
+/// An object waiting for its hash, valid while it still carries `etag`
+#[derive(Serialize, Deserialize)]
+struct Pending {
+    bucket: String,
+    key: String,
+    etag: String,
+}
+
+impl StorageEngine {
+    fn rehash_dir(&self) -> PathBuf {
+        self.root.join(QUEUE_DIR)
+    }
+
+    /// Whether a PUT to `bucket` leaves hashing to the worker. The self-test
+    /// checks ETags straight away, and a quarantine scan reads the whole
+    /// upload anyway.
+    pub(super) fn defers_hashing(&self, bucket: &str) -> bool {
+        self.deferred_hashing.mode == HashMode::Deferred && !is_system_bucket(bucket) && !self.is_quarantined(bucket)
+    }
+
+    /// A placeholder ETag from the size, the time, and a random part. It is
+    /// never valid hex, so it can't pass for a hash.
+    pub(super) fn provisional_etag(&self, size: u64) -> String {
+        format!(
+            "p-{:x}-{:x}-{}",
+            size,
+            self.now().timestamp_micros(),
+            self.new_id().simple()
+        )
+    }
+
+    /// Queue an object for hashing. Done before its metadata is written, so
+    /// a crash in between leaves an entry that no longer matches rather than
+    /// an object that is never hashed.
+    pub(super) fn queue_rehash(&self, meta: &ObjectMeta) -> Result<(), AppError> {
+        let dir = self.rehash_dir();
+        fs::create_dir_all(&dir)?;
+        let pending = Pending {
+            bucket: meta.bucket.clone(),
+            key: meta.key.clone(),
+            etag: meta.etag.clone(),
+        };
+        let path = dir.join(format!("{}.json", self.new_id().simple()));
+        self.replace_file(&path, &serde_json::to_vec(&pending).unwrap())?;
+        self.deferred_hashing.queued.notify_one();
+        Ok(())
+    }
+
+    /// The ETag for a copy of `meta` written to `copy`: its own, or the
+    /// copy's hash if it is still provisional, since the copy has the same
+    /// bytes and a queued entry only covers the original key
+    pub(super) fn final_etag(&self, meta: &ObjectMeta, copy: &Path) -> Result<String, AppError> {
+        if !meta.etag_provisional {
+            return Ok(meta.etag.clone());
+        }
+        let _op = self.time_op("hash", &meta.bucket, Some(&meta.key));
+        Ok(file_etag(copy)?)
+    }
+
+    /// Wait until an upload queues an object for hashing
+    pub async fn rehash_queued(&self) {
+        self.deferred_hashing.queued.notified().await
+    }
+
+    /// Objects still waiting for their hash
+    pub fn pending_rehashes(&self) -> usize {
+        fs::read_dir(self.rehash_dir()).map_or(0, |entries| {
+            entries
+                .flatten()
+                .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+                .count()
+        })
+    }
+
+    /// Hash every queued object, returning how many got their real ETag.
+    /// Entries that fail for a reason other than the object being gone are
+    /// kept for the next pass.
+    pub fn rehash_pending(&self) -> usize {
+        let Ok(entries) = fs::read_dir(self.rehash_dir()) else {
+            return 0;
+        };
+        let mut hashed = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let pending = fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<Pending>(&json).ok());
+            if let Some(pending) = pending {
+                match self.rehash(&pending) {
+                    Ok(true) => hashed += 1,
+                    Ok(false) => {}
+                    Err(e) => {
+                        tracing::warn!("Cannot hash {}/{} yet: {}", pending.bucket, pending.key, e.parts().2);
+                        continue;
+                    }
+                }
+            }
+            let _ = fs::remove_file(&path);
+        }
+        hashed
+    }
+
+    /// Give one object its real ETag, unless it was replaced or removed
+    /// since it was queued. The key stays locked while hashing, so a new
+    /// write can't slip in between reading the payload and the metadata.
+    fn rehash(&self, pending: &Pending) -> Result<bool, AppError> {
+        let _lock = self.lock_key(&pending.bucket, &pending.key);
+        let mut meta = match self.get_object_meta(&pending.bucket, &pending.key) {
+            Ok(meta) => meta,
+            Err(AppError::ObjectNotFound { .. } | AppError::BucketNotFound(_)) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if !meta.etag_provisional || meta.etag != pending.etag {
+            return Ok(false);
+        }
+        let etag = {
+            let _op = self.time_op("hash", &meta.bucket, Some(&meta.key));
+            file_etag(&self.object_path(&meta.bucket, &meta.key))?
+        };
+        meta.etag = etag;
+        meta.etag_provisional = false;
+        self.write_meta_file(&meta)?;
+        self.finalize_version_etag(&meta, &pending.etag)?;
+        self.object_created(EventKind::Hashed, &meta);
+        tracing::debug!("Hashed {}/{}: {}", meta.bucket, meta.key, meta.etag);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::storage::testing::engine_with;
+    use crate::storage::PutOptions;
+    use crate::testing::TestServer;
+
+    fn deferred() -> (tempfile::TempDir, StorageEngine) {
+        let (dir, storage) = engine_with(|options| options.hash_mode = HashMode::Deferred);
+        storage.create_bucket("media", "local").unwrap();
+        (dir, storage)
+    }
+
+    fn put(storage: &StorageEngine, key: &str, data: &[u8]) -> ObjectMeta {
+        storage
+            .put_object("media", key, data, PutOptions::default())
+            .unwrap()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn the_worker_swaps_in_the_real_etag() {
+        let (_dir, storage) = deferred();
+        let mut events = storage.subscribe_events();
+        let meta = put(&storage, "clip.mp4", b"frames");
+        assert!(meta.etag_provisional);
+        assert!(meta.etag.starts_with("p-6-"), "{}", meta.etag);
+        assert_eq!(storage.pending_rehashes(), 1);
+
+        assert_eq!(storage.rehash_pending(), 1);
+        let meta = storage.get_object_meta("media", "clip.mp4").unwrap();
+        assert_eq!((meta.etag.as_str(), meta.etag_provisional), (sha256(b"frames").as_str(), false));
+        assert_eq!(storage.pending_rehashes(), 0);
+        let kinds: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).map(|e| e.kind).collect();
+        assert_eq!(kinds, [EventKind::Put, EventKind::Hashed]);
+    }
+
+    #[test]
+    fn queued_hashes_survive_a_restart() {
+        let (dir, storage) = deferred();
+        for key in ["a", "b", "c"] {
+            put(&storage, key, key.as_bytes());
+        }
+        drop(storage);
+
+        let mut options = crate::config::Config::builtin().storage_options();
+        options.hash_mode = HashMode::Deferred;
+        let storage = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+        assert_eq!(storage.pending_rehashes(), 3);
+        assert_eq!(storage.rehash_pending(), 3);
+        for key in ["a", "b", "c"] {
+            assert_eq!(storage.get_object_meta("media", key).unwrap().etag, sha256(key.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn entries_for_replaced_or_deleted_objects_are_dropped() {
+        let (_dir, storage) = deferred();
+        put(&storage, "a", b"one");
+        put(&storage, "a", b"two");
+        put(&storage, "gone", b"x");
+        storage.delete_object("media", "gone").unwrap();
+
+        // Only the entry for the current content of "a" hashes anything
+        assert_eq!(storage.pending_rehashes(), 3);
+        assert_eq!(storage.rehash_pending(), 1);
+        assert_eq!(storage.pending_rehashes(), 0);
+        assert_eq!(storage.get_object_meta("media", "a").unwrap().etag, sha256(b"two"));
+
+        // Inline mode hashes before answering and queues nothing
+        let (_dir, storage) = engine_with(|_| {});
+        storage.create_bucket("media", "local").unwrap();
+        assert_eq!(put(&storage, "a", b"one").etag, sha256(b"one"));
+        assert_eq!(storage.pending_rehashes(), 0);
+    }
+
+    #[tokio::test]
+    async fn provisional_etags_never_match_a_condition() {
+        let server = TestServer::with_config(|c| c.hash_mode = HashMode::Deferred);
+        let storage = &server.state.storage;
+        storage.create_bucket("media", "local").unwrap();
+        let meta = storage
+            .put_object("media", "a.txt", b"hello", PutOptions::default())
+            .unwrap();
+        let conditional = |etag: &str| {
+            Request::get("/api/object/media/a.txt")
+                .header("if-none-match", format!("\"{}\"", etag))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = server.send(conditional(&meta.etag)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-freebucket-etag-final"], "false");
+
+        storage.rehash_pending();
+        let res = server.send(conditional(&sha256(b"hello"))).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let res = server.request("HEAD", "/api/object/media/a.txt").await;
+        assert_eq!(res.headers()["x-freebucket-etag-final"], "true");
+    }
+
+    /// Upload throughput with hashing inline, as by default, against
+    /// deferred, and how long the worker then takes to catch up
+    #[test]
+    #[ignore = "benchmark; run with cargo test --release -- --ignored --nocapture"]
+    fn bench_upload_throughput() {
+        const OBJECTS: usize = 64;
+        let data: Vec<u8> = (0..16 << 20).map(|i: u32| (i % 251) as u8).collect();
+        let rate = |elapsed: std::time::Duration| (OBJECTS * data.len()) as f64 / elapsed.as_secs_f64() / (1 << 20) as f64;
+
+        let (_dir, inline) = engine_with(|_| {});
+        inline.create_bucket("media", "local").unwrap();
+        let started = std::time::Instant::now();
+        for i in 0..OBJECTS {
+            put(&inline, &format!("{}.bin", i), &data);
+        }
+        let inline_rate = rate(started.elapsed());
+
+        let (_dir, storage) = deferred();
+        let started = std::time::Instant::now();
+        for i in 0..OBJECTS {
+            put(&storage, &format!("{}.bin", i), &data);
+        }
+        let deferred_rate = rate(started.elapsed());
+        let started = std::time::Instant::now();
+        storage.rehash_pending();
+        println!(
+            "{} uploads of 16 MiB: {:.0} MiB/s inline, {:.0} MiB/s deferred, then {:?} to hash them",
+            OBJECTS,
+            inline_rate,
+            deferred_rate,
+            started.elapsed()
+        );
+    }
+}
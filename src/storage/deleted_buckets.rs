@@ -0,0 +1,149 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+
+use super::{sparse, StorageEngine};
+use crate::error::AppError;
+use crate::models::{Bucket, DeletedBucket};
+
+/// Under the data dir; hidden, so it is never scanned as a bucket
+const DELETED_DIR: &str = ".deleted_buckets";
+/// Deletion time in retained bucket IDs
+const DELETED_AT_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+/// Bucket files besides `.bucket_meta.json` that hold settings rather than
+/// data or caches
+const SETTINGS_FILES: &[&str] = &[".inventory.json"];
+
+impl StorageEngine {
+    fn deleted_buckets_dir(&self) -> PathBuf {
+        self.root.join(DELETED_DIR)
+    }
+
+    /// Move a bucket's settings out of its directory before the directory
+    /// is removed, so the delete can be undone. `info` is the in-memory
+    /// bucket, which has the latest stats and settings.
+    pub(super) fn retain_deleted_bucket(&self, info: &Bucket) -> Result<(), AppError> {
+        let deleted_at = self.now();
+        let id = format!("{}-{}", info.name, deleted_at.format(DELETED_AT_FORMAT));
+        let dir = self.deleted_buckets_dir().join(&id);
+        fs::create_dir_all(&dir)?;
+
+        let bucket_dir = self.bucket_path(&info.name);
+        for file in SETTINGS_FILES {
+            move_file(&bucket_dir.join(file), &dir.join(file))?;
+        }
+        fs::write(dir.join(".bucket_meta.json"), serde_json::to_string_pretty(info).unwrap())?;
+        tracing::info!("Kept settings of deleted bucket {} as {}", info.name, id);
+        Ok(())
+    }
+
+    /// Settings retained from deleted buckets, most recently deleted first
+    pub fn list_deleted_buckets(&self) -> Result<Vec<DeletedBucket>, AppError> {
+        let Ok(entries) = fs::read_dir(self.deleted_buckets_dir()) else {
+            return Ok(Vec::new());
+        };
+        let mut deleted: Vec<DeletedBucket> = entries
+            .flatten()
+            .filter_map(|entry| self.read_deleted_bucket(&entry.file_name().to_string_lossy()).ok())
+            .collect();
+        deleted.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then_with(|| a.id.cmp(&b.id)));
+        Ok(deleted)
+    }
+
+    fn read_deleted_bucket(&self, id: &str) -> Result<DeletedBucket, AppError> {
+        let not_found = || AppError::DeletedBucketNotFound(id.to_string());
+        // IDs are used as a path component
+        if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+            return Err(not_found());
+        }
+        let (name, stamp) = id.rsplit_once('-').ok_or_else(not_found)?;
+        let deleted_at = NaiveDateTime::parse_from_str(stamp, DELETED_AT_FORMAT)
+            .map_err(|_| not_found())?
+            .and_utc();
+        let data = fs::read(self.deleted_buckets_dir().join(id).join(".bucket_meta.json"))
+            .map_err(|_| not_found())?;
+        let settings: Bucket = serde_json::from_slice(&data)
+            .map_err(|e| AppError::StorageError(format!("Corrupt retained bucket metadata: {}", e)))?;
+        Ok(DeletedBucket {
+            id: id.to_string(),
+            name: name.to_string(),
+            deleted_at,
+            settings,
+        })
+    }
+
+    /// Recreate a deleted bucket, empty, with the settings it had. The name
+    /// must be free again.
+    pub fn restore_deleted_bucket(&self, id: &str) -> Result<Bucket, AppError> {
+        let deleted = self.read_deleted_bucket(id)?;
+        let created = self.create_bucket_on(
+            &deleted.name,
+            &deleted.settings.region,
+            deleted.settings.volume.as_deref(),
+        )?;
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(&deleted.name)
+                .ok_or_else(|| AppError::BucketNotFound(deleted.name.clone()))?;
+            *info = Bucket {
+                created_at: created.created_at,
+                object_count: 0,
+                total_size: 0,
+                allocated_size: 0,
+                revision: created.revision,
+                reserved: created.reserved,
+                volume: created.volume,
+                ..deleted.settings
+            };
+        }
+        self.persist_bucket_meta(&deleted.name)?;
+
+        let dir = self.deleted_buckets_dir().join(id);
+        let bucket_dir = self.bucket_path(&deleted.name);
+        for file in SETTINGS_FILES {
+            move_file(&dir.join(file), &bucket_dir.join(file))?;
+        }
+        fs::remove_dir_all(&dir)?;
+        tracing::info!("Restored deleted bucket {} from {}", deleted.name, id);
+        self.get_bucket(&deleted.name)
+    }
+
+    /// Forget deleted buckets retained for longer than `retention`.
+    /// Returns how many were (or, in a dry run, would be) removed.
+    pub(super) fn purge_deleted_buckets(&self, retention: Duration, dry_run: bool) -> Result<u64, AppError> {
+        let cutoff = chrono::Duration::from_std(retention)
+            .ok()
+            .and_then(|retention| self.now().checked_sub_signed(retention));
+        let Some(cutoff) = cutoff else {
+            return Ok(0);
+        };
+        let mut purged = 0;
+        for deleted in self.list_deleted_buckets()? {
+            if deleted.deleted_at > cutoff {
+                continue;
+            }
+            if !dry_run {
+                fs::remove_dir_all(self.deleted_buckets_dir().join(&deleted.id))?;
+            }
+            purged += 1;
+        }
+        Ok(purged)
+    }
+}
+
+/// Move a file that may be missing. Buckets on other volumes are on other
+/// filesystems, where a rename fails, so it falls back to copying.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(_) if from.is_file() => {
+            sparse::copy_file(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
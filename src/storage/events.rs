@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use super::{sparse, StorageEngine};
+use crate::error::AppError;
+use crate::models::{Bucket, ExecHooks, ObjectMeta, UsageAlert, Webhook};
+
+/// Longest an exec hook may run. Kept under GC's age for temp files, so
+/// the copy a hook is reading is never collected from under it.
+pub const MAX_HOOK_TIMEOUT_SECS: u64 = 30 * 60;
+
+/// What happened to an object, named after S3's event types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Put,
+    Copy,
+    CompleteMultipartUpload,
+    Delete,
+    DeleteMarkerCreated,
+    /// An upload to a quarantined bucket is waiting for its scan
+    QuarantinePending,
+    /// A quarantined upload was approved; its `ObjectCreated` event follows
+    QuarantineApproved,
+    QuarantineRejected,
+    /// An object stored under a provisional ETag got its real one
+    Hashed,
+    /// A bucket's usage crossed one of its alert thresholds
+    AlertRaised,
+    /// Usage fell back below a raised alert's threshold
+    AlertCleared,
+}
+
+impl EventKind {
+    /// The S3 `eventName`
+    pub fn s3_name(self) -> &'static str {
+        match self {
+            EventKind::Put => "ObjectCreated:Put",
+            EventKind::Copy => "ObjectCreated:Copy",
+            EventKind::CompleteMultipartUpload => "ObjectCreated:CompleteMultipartUpload",
+            EventKind::Delete => "ObjectRemoved:Delete",
+            EventKind::DeleteMarkerCreated => "ObjectRemoved:DeleteMarkerCreated",
+            EventKind::QuarantinePending => "ObjectQuarantined:Pending",
+            EventKind::QuarantineApproved => "ObjectQuarantined:Approved",
+            EventKind::QuarantineRejected => "ObjectQuarantined:Rejected",
+            EventKind::Hashed => "ObjectHashed:Final",
+            EventKind::AlertRaised => "UsageAlert:Raised",
+            EventKind::AlertCleared => "UsageAlert:Cleared",
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Address of the client whose request is being handled, for the
+    /// events it causes. Unset for the server's own changes.
+    pub static EVENT_SOURCE: String;
+}
+
+/// One change to an object, or a usage alert on its bucket. Size, ETag,
+/// and content type are only known for objects that were created.
+#[derive(Debug, Clone)]
+pub struct ObjectEvent {
+    pub kind: EventKind,
+    pub bucket: String,
+    pub key: String,
+    pub object: Option<ObjectMeta>,
+    /// Set for alert events, which have no key
+    pub alert: Option<UsageAlert>,
+    pub time: DateTime<Utc>,
+    /// Increases with every event, also across restarts
+    pub sequence: u64,
+    /// Client that made the change, if a request did
+    pub source_ip: Option<String>,
+}
+
+/// Where object events go. Nothing listens in CLI runs, so nothing is sent.
+#[derive(Default)]
+pub(super) struct EventSink {
+    sender: Mutex<Option<UnboundedSender<ObjectEvent>>>,
+    sequence: AtomicU64,
+}
+
+impl StorageEngine {
+    /// Receive every object event from now on. There is one subscriber at a
+    /// time; subscribing again cuts off the previous one.
+    pub fn subscribe_events(&self) -> UnboundedReceiver<ObjectEvent> {
+        let (tx, rx) = unbounded_channel();
+        *self.events.sender.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    pub(super) fn object_created(&self, kind: EventKind, meta: &ObjectMeta) {
+        self.emit_event(kind, &meta.bucket, &meta.key, Some(meta), None);
+    }
+
+    /// A quarantined upload changed state
+    pub(super) fn quarantine_event(&self, kind: EventKind, meta: &ObjectMeta) {
+        self.emit_event(kind, &meta.bucket, &meta.key, Some(meta), None);
+    }
+
+    /// A usage alert was raised or cleared
+    pub(super) fn alert_event(&self, kind: EventKind, alert: &UsageAlert) {
+        self.emit_event(kind, &alert.bucket, "", None, Some(alert));
+    }
+
+    /// Versioned buckets keep the content, so S3 reports a delete marker
+    pub(super) fn object_removed(&self, bucket: &str, key: &str) {
+        let kind = if self.is_versioned(bucket) {
+            EventKind::DeleteMarkerCreated
+        } else {
+            EventKind::Delete
+        };
+        self.emit_event(kind, bucket, key, None, None);
+    }
+
+    fn emit_event(
+        &self,
+        kind: EventKind,
+        bucket: &str,
+        key: &str,
+        object: Option<&ObjectMeta>,
+        alert: Option<&UsageAlert>,
+    ) {
+        if super::is_system_bucket(bucket) {
+            return;
+        }
+        let sender = self.events.sender.lock().unwrap();
+        let Some(sender) = sender.as_ref() else {
+            return;
+        };
+        let time = self.now();
+        // Seeded from the clock, so sequencers keep growing after a restart
+        let now = time.timestamp_nanos_opt().unwrap_or_default() as u64;
+        let previous = self
+            .events
+            .sequence
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+            .unwrap_or_default();
+        let _ = sender.send(ObjectEvent {
+            kind,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            object: object.cloned(),
+            alert: alert.cloned(),
+            time,
+            sequence: now.max(previous + 1),
+            source_ip: EVENT_SOURCE.try_with(String::clone).ok(),
+        });
+    }
+
+    /// Replace the webhooks a bucket's object events are sent to
+    pub fn set_bucket_webhooks(&self, bucket: &str, webhooks: Vec<Webhook>) -> Result<Bucket, AppError> {
+        for webhook in &webhooks {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                return Err(AppError::InvalidRequest(format!(
+                    "Webhook URL '{}' must start with http:// or https://",
+                    webhook.url
+                )));
+            }
+        }
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.webhooks = webhooks;
+        }
+        self.persist_bucket_meta(bucket)?;
+        tracing::info!("Webhooks of bucket {} updated", bucket);
+        self.get_bucket(bucket)
+    }
+
+    /// Replace the exec hooks run on a bucket's object events; `None` or
+    /// no commands removes them
+    pub fn set_bucket_exec_hooks(&self, bucket: &str, hooks: Option<ExecHooks>) -> Result<Bucket, AppError> {
+        let hooks = hooks.filter(|h| !h.commands.is_empty());
+        if let Some(hooks) = &hooks {
+            if hooks.max_running == 0 {
+                return Err(AppError::InvalidRequest("max_running must be at least 1".to_string()));
+            }
+            for hook in &hooks.commands {
+                let Some(program) = hook.command.first() else {
+                    return Err(AppError::InvalidRequest("An exec hook needs a command".to_string()));
+                };
+                if !Path::new(program).is_absolute() {
+                    return Err(AppError::InvalidRequest(format!(
+                        "Exec hook program '{}' must be an absolute path",
+                        program
+                    )));
+                }
+                if hook.events.is_empty() {
+                    return Err(AppError::InvalidRequest(format!(
+                        "Exec hook '{}' has no events to run on",
+                        program
+                    )));
+                }
+                if !(1..=MAX_HOOK_TIMEOUT_SECS).contains(&hook.timeout_secs) {
+                    return Err(AppError::InvalidRequest(format!(
+                        "Exec hook timeout must be between 1 and {} seconds",
+                        MAX_HOOK_TIMEOUT_SECS
+                    )));
+                }
+            }
+        }
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.exec_hooks = hooks;
+        }
+        self.persist_bucket_meta(bucket)?;
+        tracing::info!("Exec hooks of bucket {} updated", bucket);
+        self.get_bucket(bucket)
+    }
+
+    /// Hard-link an object into the bucket's scratch space, so an exec hook
+    /// reads it as it is now however it changes later. The link shares the
+    /// object's old mtime, so it is registered to keep GC off it until
+    /// `release_hook_copy`; GC collects it if the server stops first.
+    pub fn hook_copy(&self, bucket: &str, key: &str) -> Result<PathBuf, AppError> {
+        let _reading = self.begin_read(bucket, key);
+        let source = self.object_path(bucket, key);
+        if !source.is_file() {
+            return Err(self.missing_object(bucket, key));
+        }
+        let dir = self.bucket_path(bucket).join(".tmp");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(".tmp-hook-{}", uuid::Uuid::new_v4()));
+        self.active_temp.lock().unwrap().insert(path.clone());
+        if !self.fs_features.hard_links || fs::hard_link(&source, &path).is_err() {
+            if let Err(e) = sparse::copy_file(&source, &path) {
+                self.release_hook_copy(&path);
+                return Err(e.into());
+            }
+        }
+        Ok(path)
+    }
+
+    /// Remove a copy made by `hook_copy`
+    pub fn release_hook_copy(&self, path: &Path) {
+        let _ = fs::remove_file(path);
+        self.active_temp.lock().unwrap().remove(path);
+    }
+}
@@ -0,0 +1,341 @@
+//! Living with data dirs on network filesystems. NFS and SMB now and then
+//! fail an operation that works when tried again (a stale file handle, a
+//! busy file), refuse renames that local filesystems allow, and may lack
+//! hard links or sub-second modification times. The file operations that
+//! matter go through here, so a blip is retried instead of answering 500,
+//! a rename the filesystem won't do is done by copying, and features that
+//! need what the filesystem lacks are turned off at startup.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use super::{sparse, StorageEngine};
+use crate::models::FilesystemFeatures;
+
+/// How often an operation failing with a transient error is tried, and how
+/// long to wait before the first retry; each further wait doubles
+#[derive(Debug, Clone, Copy)]
+pub struct IoRetry {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+/// Set once the first rename has fallen back to copying, so the warning is
+/// logged once per run
+static RENAME_FALLBACK_LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// Errors that tend to go away on their own on a network filesystem
+fn is_transient(e: &io::Error) -> bool {
+    if matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    ) {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use nix::errno::Errno;
+        if let Some(code) = e.raw_os_error() {
+            return matches!(Errno::from_raw(code), Errno::ESTALE | Errno::EBUSY | Errno::EAGAIN);
+        }
+    }
+    false
+}
+
+/// Errors from a rename the filesystem can't do as asked: across devices,
+/// not at all, or (on some SMB servers) not over an existing file
+fn is_rename_unsupported(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        use nix::errno::Errno;
+        if let Some(code) = e.raw_os_error() {
+            return matches!(
+                Errno::from_raw(code),
+                Errno::EXDEV | Errno::ENOTSUP | Errno::ENOSYS | Errno::EEXIST
+            );
+        }
+    }
+    e.kind() == io::ErrorKind::AlreadyExists
+}
+
+/// Run `op`, trying again after a pause when it fails with a transient error
+pub(super) fn retry<T>(policy: IoRetry, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut delay = policy.backoff;
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if attempt < policy.attempts && is_transient(&e) => {
+                tracing::debug!("Retrying a file operation after a transient error: {}", e);
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A path beside `path` for staging a replacement of it
+fn sibling(path: &Path, tag: &str) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}-{}", name, tag, uuid::Uuid::new_v4().simple()))
+}
+
+impl StorageEngine {
+    /// `op`, retried on transient errors as configured
+    pub(super) fn retry_io<T>(&self, op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        retry(self.io_retry, op)
+    }
+
+    /// Move a file into place, retrying transient errors. Where the
+    /// filesystem won't rename it, the file is copied beside `to`, synced,
+    /// and swapped in; `to` is briefly missing during the swap.
+    pub(super) fn rename_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match self.retry_io(|| fs::rename(from, to)) {
+            Err(e) if is_rename_unsupported(&e) && from.is_file() => {
+                if !RENAME_FALLBACK_LOGGED.swap(true, Ordering::Relaxed) {
+                    tracing::warn!(
+                        "The filesystem refused to rename {} ({}); copying files into place instead",
+                        from.display(),
+                        e
+                    );
+                }
+                self.copy_and_swap(from, to)
+            }
+            result => result,
+        }
+    }
+
+    fn copy_and_swap(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let staged = sibling(to, "swap");
+        let copied = self
+            .retry_io(|| sparse::copy_file(from, &staged))
+            .and_then(|_| self.retry_io(|| fs::File::open(&staged)?.sync_all()));
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&staged);
+            return Err(e);
+        }
+        // Moving into a name that is free works where renaming over one doesn't
+        let old = to.is_file().then(|| sibling(to, "old"));
+        if let Some(old) = &old {
+            if let Err(e) = self.retry_io(|| fs::rename(to, old)) {
+                let _ = fs::remove_file(&staged);
+                return Err(e);
+            }
+        }
+        if let Err(e) = self.retry_io(|| fs::rename(&staged, to)) {
+            if let Some(old) = &old {
+                let _ = fs::rename(old, to);
+            }
+            let _ = fs::remove_file(&staged);
+            return Err(e);
+        }
+        if let Some(old) = &old {
+            let _ = fs::remove_file(old);
+        }
+        self.retry_io(|| fs::remove_file(from))
+    }
+
+    /// Replace `path` with `data` whole, through a temp file beside it
+    pub(super) fn replace_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let tmp = path.with_extension(match path.extension() {
+            Some(ext) => format!("{}.tmp", ext.to_string_lossy()),
+            None => "tmp".to_string(),
+        });
+        self.retry_io(|| fs::write(&tmp, data))?;
+        self.rename_file(&tmp, path)
+    }
+
+    /// What the data dir's filesystem supports, as probed at startup
+    pub fn filesystem_features(&self) -> FilesystemFeatures {
+        self.fs_features
+    }
+}
+
+/// Try what FreeBucket relies on in a scratch directory under `root`, and
+/// log what is missing along with what that turns off
+pub(super) fn probe_features(root: &Path) -> FilesystemFeatures {
+    let dir = root.join(format!(".fsprobe-{}", uuid::Uuid::new_v4().simple()));
+    let file = dir.join("probe");
+    let features = match fs::create_dir_all(&dir).and_then(|_| fs::write(&file, b"probe")) {
+        Ok(()) => FilesystemFeatures {
+            hard_links: fs::hard_link(&file, dir.join("link")).is_ok(),
+            fine_mtimes: keeps_subsecond_mtime(&file),
+            case_sensitive: keeps_case_apart(&dir),
+        },
+        Err(e) => {
+            tracing::warn!("Cannot probe the filesystem of {}: {}", root.display(), e);
+            FilesystemFeatures {
+                hard_links: true,
+                fine_mtimes: true,
+                case_sensitive: true,
+            }
+        }
+    };
+    let _ = fs::remove_dir_all(&dir);
+
+    if !features.hard_links {
+        tracing::warn!(
+            "The data dir's filesystem has no hard links; versions, snapshots, and exec hooks copy objects instead"
+        );
+    }
+    if !features.fine_mtimes {
+        tracing::warn!(
+            "The data dir's filesystem keeps only whole-second modification times; payloads keep their write time instead of their source's"
+        );
+    }
+    features
+}
+
+/// Whether two files whose names differ only in case can be created side
+/// by side
+fn keeps_case_apart(dir: &Path) -> bool {
+    let create = |name: &str| fs::File::options().write(true).create_new(true).open(dir.join(name));
+    match create("case-probe").and_then(|_| create("CASE-PROBE")) {
+        Ok(_) => true,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => false,
+        // Not knowing, assume the common case
+        Err(_) => true,
+    }
+}
+
+/// Whether a modification time with a fraction of a second reads back as set
+fn keeps_subsecond_mtime(file: &Path) -> bool {
+    let wanted = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_000);
+    let set = fs::File::options()
+        .write(true)
+        .open(file)
+        .and_then(|f| f.set_modified(wanted));
+    set.is_ok() && fs::metadata(file).and_then(|m| m.modified()).is_ok_and(|read| read == wanted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    fn policy(attempts: u32) -> IoRetry {
+        IoRetry {
+            attempts,
+            backoff: Duration::from_millis(2),
+        }
+    }
+
+    /// An operation that fails with `error` its first `failures` calls,
+    /// counting every call
+    fn flaky(failures: u32, error: fn() -> io::Error, calls: &Cell<u32>) -> impl FnMut() -> io::Result<&'static str> + '_ {
+        move || {
+            calls.set(calls.get() + 1);
+            match calls.get() <= failures {
+                true => Err(error()),
+                false => Ok("done"),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn stale() -> io::Error {
+        io::Error::from_raw_os_error(nix::errno::Errno::ESTALE as i32)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn transient_errors_are_retried_with_a_growing_pause() {
+        let calls = Cell::new(0);
+        let started = Instant::now();
+        assert_eq!(retry(policy(3), flaky(2, stale, &calls)).unwrap(), "done");
+        assert_eq!(calls.get(), 3);
+        // 2ms, then 4ms
+        assert!(started.elapsed() >= Duration::from_millis(6));
+
+        let calls = Cell::new(0);
+        let err = retry(policy(3), flaky(5, stale, &calls)).unwrap_err();
+        assert_eq!((calls.get(), err.raw_os_error()), (3, Some(nix::errno::Errno::ESTALE as i32)));
+
+        let busy = || io::Error::from_raw_os_error(nix::errno::Errno::EBUSY as i32);
+        let calls = Cell::new(0);
+        assert!(retry(policy(2), flaky(1, busy, &calls)).is_ok());
+        let calls = Cell::new(0);
+        assert!(retry(policy(2), flaky(1, || io::ErrorKind::Interrupted.into(), &calls)).is_ok());
+    }
+
+    #[test]
+    fn other_errors_fail_at_once() {
+        for error in [
+            (|| io::ErrorKind::PermissionDenied.into()) as fn() -> io::Error,
+            || io::ErrorKind::NotFound.into(),
+        ] {
+            let calls = Cell::new(0);
+            assert!(retry(policy(5), flaky(1, error, &calls)).is_err());
+            assert_eq!(calls.get(), 1);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn renames_the_filesystem_refuses_are_copied_into_place() {
+        use nix::errno::Errno;
+        let refused = |errno: Errno| is_rename_unsupported(&io::Error::from_raw_os_error(errno as i32));
+        assert!(refused(Errno::EXDEV) && refused(Errno::ENOTSUP) && refused(Errno::EEXIST));
+        assert!(!refused(Errno::ENOENT) && !refused(Errno::EACCES));
+
+        let (dir, storage) = engine();
+        let from = dir.path().join("upload.tmp");
+        let to = dir.path().join("object");
+        fs::write(&from, b"new").unwrap();
+        fs::write(&to, b"old").unwrap();
+        storage.copy_and_swap(&from, &to).unwrap();
+        assert_eq!(fs::read(&to).unwrap(), b"new");
+        assert!(!from.exists());
+
+        // Into a free name as well, leaving nothing staged behind
+        fs::write(&from, b"again").unwrap();
+        let fresh = dir.path().join("fresh");
+        storage.copy_and_swap(&from, &fresh).unwrap();
+        assert_eq!(fs::read(&fresh).unwrap(), b"again");
+        let staged: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".swap-") || name.contains(".old-"))
+            .collect();
+        assert!(staged.is_empty(), "{:?}", staged);
+
+        // A missing source fails without touching the destination
+        assert!(storage.copy_and_swap(&dir.path().join("missing"), &to).is_err());
+        assert_eq!(fs::read(&to).unwrap(), b"new");
+    }
+
+    #[test]
+    fn the_probe_finds_what_a_local_filesystem_has() {
+        let dir = tempfile::tempdir().unwrap();
+        let features = probe_features(dir.path());
+        assert!(features.hard_links);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0, "the probe cleans up");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn without_hard_links_versions_are_copies() {
+        use std::os::unix::fs::MetadataExt;
+
+        for hard_links in [true, false] {
+            let (dir, mut storage) = engine();
+            storage.fs_features.hard_links = hard_links;
+            storage.create_bucket("photos", "local").unwrap();
+            storage.set_bucket_versioning("photos", true).unwrap();
+            storage
+                .put_object("photos", "a.txt", b"hello", PutOptions::default())
+                .unwrap();
+            let links = fs::metadata(dir.path().join("photos/objects/a.txt")).unwrap().nlink();
+            assert_eq!(links, if hard_links { 2 } else { 1 });
+        }
+    }
+}
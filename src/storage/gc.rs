@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::GcReport;
+
+/// Temp files younger than this may still belong to a write in another
+/// process (e.g. the CLI running next to the server), so GC leaves them alone
+const TEMP_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+pub struct GcOptions {
+    /// Report what would be removed without deleting anything
+    pub dry_run: bool,
+    /// Multipart uploads with no activity for this long are aborted
+    pub multipart_expiry: Duration,
+    /// Settings of deleted buckets are kept this long
+    pub deleted_bucket_retention: Duration,
+}
+
+/// Most recent modification time of a file or anything below a directory
+fn newest_mtime(path: &Path) -> Option<SystemTime> {
+    let mut newest = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        if !path.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&path).into_iter().flatten().flatten() {
+            if let Ok(t) = fs::metadata(entry.path()).and_then(|m| m.modified()) {
+                newest = newest.max(t);
+            }
+            stack.push(entry.path());
+        }
+    }
+    Some(newest)
+}
+
+pub(super) fn older_than(path: &Path, age: Duration) -> bool {
+    match newest_mtime(path).map(|t| t.elapsed()) {
+        Some(Ok(elapsed)) => elapsed > age,
+        // Missing or timestamped in the future: not provably stale
+        _ => false,
+    }
+}
+
+fn path_size(path: &Path) -> u64 {
+    let mut size = 0;
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        if path.is_dir() {
+            stack.extend(fs::read_dir(&path).into_iter().flatten().flatten().map(|e| e.path()));
+        } else {
+            size += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        }
+    }
+    size
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+impl StorageEngine {
+    /// Reclaim space left behind by crashes and abandoned operations:
+    /// interrupted transactions, stale temp files, idle multipart uploads,
+    /// metadata without an object, empty directories under `objects/`, and
+    /// the retained settings of buckets deleted long enough ago
+    pub fn collect_garbage(&self, opts: &GcOptions) -> Result<GcReport, AppError> {
+        let bucket_names: Vec<String> = self.buckets.read().unwrap().keys().cloned().collect();
+        let mut report = GcReport {
+            dry_run: opts.dry_run,
+            ..Default::default()
+        };
+        // Ahead of the temp file sweep, which would take their staged payloads
+        report.transactions_recovered = self.recover_transactions(opts.dry_run);
+
+        for bucket in &bucket_names {
+            self.gc_temp_files(bucket, opts, &mut report)?;
+            self.gc_stale_uploads(bucket, opts, &mut report)?;
+            self.gc_orphan_meta(bucket, opts, &mut report)?;
+
+            let objects_root = self.bucket_path(bucket).join("objects");
+            report.empty_dirs_removed += Self::gc_empty_dirs(&objects_root, &objects_root, opts.dry_run);
+        }
+        report.deleted_buckets_purged =
+            self.purge_deleted_buckets(opts.deleted_bucket_retention, opts.dry_run)?;
+
+        tracing::info!(
+            "GC{}: {} transaction(s) finished, {} temp file(s), {} stale upload(s), {} orphan meta, {} empty dir(s), {} deleted bucket(s), {} reclaimed",
+            if opts.dry_run { " (dry run)" } else { "" },
+            report.transactions_recovered,
+            report.temp_files_removed,
+            report.stale_uploads_aborted,
+            report.orphan_meta_removed,
+            report.empty_dirs_removed,
+            report.deleted_buckets_purged,
+            crate::size::human_readable_size(report.reclaimed_bytes)
+        );
+        Ok(report)
+    }
+
+    fn gc_temp_files(
+        &self,
+        bucket: &str,
+        opts: &GcOptions,
+        report: &mut GcReport,
+    ) -> Result<(), AppError> {
+        let Ok(entries) = fs::read_dir(self.bucket_path(bucket).join(".tmp")) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Registered paths belong to a write still running in this process
+            if self.is_temp_active(&path) || !older_than(&path, TEMP_MAX_AGE) {
+                continue;
+            }
+            let size = path_size(&path);
+            if !opts.dry_run {
+                remove_path(&path)?;
+            }
+            report.temp_files_removed += 1;
+            report.reclaimed_bytes += size;
+        }
+        Ok(())
+    }
+
+    fn gc_stale_uploads(
+        &self,
+        bucket: &str,
+        opts: &GcOptions,
+        report: &mut GcReport,
+    ) -> Result<(), AppError> {
+        let Ok(entries) = fs::read_dir(self.bucket_path(bucket).join(".uploads")) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Every part upload touches the directory, so an upload is idle
+            // only when nothing inside it has changed within the expiry
+            if !older_than(&path, opts.multipart_expiry) {
+                continue;
+            }
+            let size = path_size(&path);
+            if !opts.dry_run {
+                remove_path(&path)?;
+                tracing::info!(
+                    "Aborted stale multipart upload {:?} in bucket {}",
+                    entry.file_name(),
+                    bucket
+                );
+            }
+            report.stale_uploads_aborted += 1;
+            report.reclaimed_bytes += size;
+        }
+        Ok(())
+    }
+
+    fn gc_orphan_meta(
+        &self,
+        bucket: &str,
+        opts: &GcOptions,
+        report: &mut GcReport,
+    ) -> Result<(), AppError> {
+        let Ok(entries) = fs::read_dir(self.bucket_path(bucket).join(".meta")) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(encoded) = name.strip_suffix(".json") else {
+                continue;
+            };
+            let Some(key) = super::key_paths::meta_file_key(&path, encoded) else {
+                continue;
+            };
+            // A delete removes the payload just before its metadata; the age
+            // check keeps GC out of that window
+            if self.object_path(bucket, &key).is_file() || !older_than(&path, TEMP_MAX_AGE) {
+                continue;
+            }
+            let size = path_size(&path);
+            if !opts.dry_run {
+                fs::remove_file(&path)?;
+                self.hash_index.write().unwrap().remove(bucket, &key, self.now());
+            }
+            report.orphan_meta_removed += 1;
+            report.reclaimed_bytes += size;
+        }
+        Ok(())
+    }
+
+    /// Like `prune_empty_dirs`, but counts what it removes and can simulate
+    fn gc_empty_dirs(dir: &Path, keep: &Path, dry_run: bool) -> u64 {
+        // Directories with something left in a subdirectory, which a dry
+        // run can't learn from a failed removal
+        let mut occupied = HashSet::new();
+        let mut removed = 0;
+        for (path, has_files) in super::walk::dirs_deepest_first(dir) {
+            let empty = !has_files && !occupied.contains(&path);
+            if empty && path != keep && (dry_run || fs::remove_dir(&path).is_ok()) {
+                removed += 1;
+            } else if let Some(parent) = path.parent() {
+                occupied.insert(parent.to_path_buf());
+            }
+        }
+        removed
+    }
+}
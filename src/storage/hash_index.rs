@@ -0,0 +1,329 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+use chrono::{DateTime, Utc};
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{DuplicateGroup, ObjectExistence, ObjectLocation, ObjectMeta, RecentObject};
+
+/// In-memory index from SHA-256 content hash to every object holding that
+/// content, each object's size and modification time, the objects of each
+/// bucket in modification order, and the last time anything in each bucket
+/// was written or deleted
+#[derive(Default)]
+pub(super) struct HashIndex {
+    by_hash: HashMap<String, Vec<ObjectLocation>>,
+    by_key: HashMap<(String, String), KeyEntry>,
+    by_modified: BTreeSet<(String, DateTime<Utc>, String)>,
+    last_activity: HashMap<String, DateTime<Utc>>,
+}
+
+struct KeyEntry {
+    hash: String,
+    size: u64,
+    last_modified: DateTime<Utc>,
+}
+
+impl HashIndex {
+    pub(super) fn insert(&mut self, meta: &ObjectMeta) {
+        self.unlink(&meta.bucket, &meta.key);
+        self.touch(&meta.bucket, meta.last_modified);
+
+        let hash = meta.etag.to_ascii_lowercase();
+        self.by_key.insert(
+            (meta.bucket.clone(), meta.key.clone()),
+            KeyEntry {
+                hash: hash.clone(),
+                size: meta.size,
+                last_modified: meta.last_modified,
+            },
+        );
+        self.by_modified
+            .insert((meta.bucket.clone(), meta.last_modified, meta.key.clone()));
+        self.by_hash.entry(hash).or_default().push(ObjectLocation {
+            bucket: meta.bucket.clone(),
+            key: meta.key.clone(),
+            size: meta.size,
+        });
+    }
+
+    pub(super) fn remove(&mut self, bucket: &str, key: &str, at: DateTime<Utc>) {
+        self.unlink(bucket, key);
+        self.touch(bucket, at);
+    }
+
+    /// Indexed size and modification time of an object
+    pub(super) fn key_stats(&self, bucket: &str, key: &str) -> Option<(u64, DateTime<Utc>)> {
+        self.by_key
+            .get(&(bucket.to_string(), key.to_string()))
+            .map(|entry| (entry.size, entry.last_modified))
+    }
+
+    /// Keys under `prefix` modified at or after `after` and before `before`,
+    /// oldest first
+    pub(super) fn modified_between(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        after: Option<DateTime<Utc>>,
+        before: DateTime<Utc>,
+    ) -> Vec<String> {
+        let after = after.unwrap_or(DateTime::<Utc>::MIN_UTC);
+        if after >= before {
+            return Vec::new();
+        }
+        self.by_modified
+            .range((
+                Bound::Included((bucket.to_string(), after, String::new())),
+                Bound::Excluded((bucket.to_string(), before, String::new())),
+            ))
+            .filter(|(_, _, key)| key.starts_with(prefix))
+            .map(|(_, _, key)| key.clone())
+            .collect()
+    }
+
+    pub(super) fn last_activity(&self, bucket: &str) -> Option<DateTime<Utc>> {
+        self.last_activity.get(bucket).copied()
+    }
+
+    fn touch(&mut self, bucket: &str, at: DateTime<Utc>) {
+        let last = self.last_activity.entry(bucket.to_string()).or_insert(at);
+        if at > *last {
+            *last = at;
+        }
+    }
+
+    fn unlink(&mut self, bucket: &str, key: &str) {
+        let Some(KeyEntry { hash, last_modified, .. }) = self.by_key.remove(&(bucket.to_string(), key.to_string()))
+        else {
+            return;
+        };
+        self.by_modified
+            .remove(&(bucket.to_string(), last_modified, key.to_string()));
+        if let Some(locations) = self.by_hash.get_mut(&hash) {
+            locations.retain(|l| !(l.bucket == bucket && l.key == key));
+            if locations.is_empty() {
+                self.by_hash.remove(&hash);
+            }
+        }
+    }
+
+    fn remove_bucket(&mut self, bucket: &str) {
+        let keys: Vec<String> = self
+            .by_key
+            .keys()
+            .filter(|(b, _)| b == bucket)
+            .map(|(_, k)| k.clone())
+            .collect();
+        for key in keys {
+            self.unlink(bucket, &key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.by_hash.clear();
+        self.by_key.clear();
+        self.by_modified.clear();
+        self.last_activity.clear();
+    }
+}
+
+impl StorageEngine {
+    /// Rebuild the content hash index from the metadata of every stored object
+    pub fn rebuild_hash_index(&self) -> Result<usize, AppError> {
+        let bucket_names: Vec<String> = self.buckets.read().unwrap().keys().cloned().collect();
+
+        let mut entries = Vec::new();
+        for bucket in &bucket_names {
+            entries.extend(self.list_objects(bucket, "", None, u32::MAX)?.objects);
+        }
+
+        let mut index = self.hash_index.write().unwrap();
+        index.clear();
+        for meta in &entries {
+            index.insert(meta);
+        }
+        tracing::debug!("Indexed {} object hash(es)", entries.len());
+        Ok(entries.len())
+    }
+
+    /// Re-index one bucket after its contents were replaced wholesale
+    pub(super) fn reindex_bucket(&self, bucket: &str) -> Result<(), AppError> {
+        let objects = self.list_objects(bucket, "", None, u32::MAX)?.objects;
+        self.retain_access(bucket, &objects.iter().map(|o| o.key.as_str()).collect());
+        let mut index = self.hash_index.write().unwrap();
+        index.remove_bucket(bucket);
+        for meta in &objects {
+            index.insert(meta);
+        }
+        index.touch(bucket, self.now());
+        Ok(())
+    }
+
+    /// All objects whose content hashes to the given SHA-256 hex digest
+    pub fn find_by_hash(&self, sha256: &str) -> Vec<ObjectLocation> {
+        let index = self.hash_index.read().unwrap();
+        let mut matches = index
+            .by_hash
+            .get(&sha256.trim_matches('"').to_ascii_lowercase())
+            .cloned()
+            .unwrap_or_default();
+        matches.sort_by(|a, b| (&a.bucket, &a.key).cmp(&(&b.bucket, &b.key)));
+        matches
+    }
+
+    /// Which of `keys` hold an object, with size and ETag for those that do,
+    /// in request order. Answered from the index without touching the disk.
+    pub fn objects_exist(&self, bucket: &str, keys: &[String]) -> Result<Vec<ObjectExistence>, AppError> {
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+
+        let index = self.hash_index.read().unwrap();
+        Ok(keys
+            .iter()
+            .map(|key| {
+                let entry = index.by_key.get(&(bucket.to_string(), key.clone()));
+                ObjectExistence {
+                    key: key.clone(),
+                    exists: entry.is_some(),
+                    size: entry.map(|e| e.size),
+                    etag: entry.map(|e| e.hash.clone()),
+                }
+            })
+            .collect())
+    }
+
+    /// The `limit` biggest objects in a bucket, largest first
+    pub fn largest_objects(&self, bucket: &str, limit: usize) -> Result<Vec<ObjectLocation>, AppError> {
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+
+        let index = self.hash_index.read().unwrap();
+        let mut objects: Vec<ObjectLocation> = index
+            .by_hash
+            .values()
+            .flatten()
+            .filter(|l| l.bucket == bucket)
+            .cloned()
+            .collect();
+        objects.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.key.cmp(&b.key)));
+        objects.truncate(limit);
+        Ok(objects)
+    }
+
+    /// The `limit` most recently modified objects in a bucket, newest first
+    pub(super) fn recent_objects(&self, bucket: &str, limit: usize) -> Vec<RecentObject> {
+        let index = self.hash_index.read().unwrap();
+        let mut objects: Vec<RecentObject> = index
+            .by_key
+            .iter()
+            .filter(|((b, _), _)| b == bucket)
+            .map(|((_, key), entry)| RecentObject {
+                key: key.clone(),
+                size: entry.size,
+                last_modified: entry.last_modified,
+            })
+            .collect();
+        objects.sort_by(|a, b| b.last_modified.cmp(&a.last_modified).then_with(|| a.key.cmp(&b.key)));
+        objects.truncate(limit);
+        objects
+    }
+
+    /// Groups of identical objects, optionally restricted to one bucket,
+    /// largest wasted space first
+    pub fn duplicate_groups(&self, bucket: Option<&str>) -> Vec<DuplicateGroup> {
+        let index = self.hash_index.read().unwrap();
+        let mut groups: Vec<DuplicateGroup> = index
+            .by_hash
+            .iter()
+            .filter_map(|(hash, locations)| {
+                let mut objects: Vec<ObjectLocation> = locations
+                    .iter()
+                    .filter(|l| !matches!(bucket, Some(b) if l.bucket != b))
+                    .cloned()
+                    .collect();
+                if objects.len() < 2 {
+                    return None;
+                }
+                objects.sort_by(|a, b| (&a.bucket, &a.key).cmp(&(&b.bucket, &b.key)));
+                let size = objects[0].size;
+                Some(DuplicateGroup {
+                    sha256: hash.clone(),
+                    size,
+                    wasted_bytes: size * (objects.len() as u64 - 1),
+                    objects,
+                })
+            })
+            .collect();
+        groups.sort_by(|a, b| {
+            b.wasted_bytes
+                .cmp(&a.wasted_bytes)
+                .then_with(|| a.sha256.cmp(&b.sha256))
+        });
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    /// Three buckets: `video` biggest, `docs` smallest, `empty` with nothing
+    fn fill(storage: &StorageEngine) {
+        for (bucket, sizes) in [("docs", &[10, 20][..]), ("video", &[500, 3000, 1200]), ("empty", &[])] {
+            storage.create_bucket(bucket, "local").unwrap();
+            for (i, size) in sizes.iter().enumerate() {
+                let data = vec![i as u8 + 1; *size];
+                storage
+                    .put_object(bucket, &format!("f{}", i), &data, PutOptions::default())
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn buckets_are_listed_biggest_first() {
+        let (_dir, storage) = engine();
+        fill(&storage);
+        let stats = storage.bucket_stats();
+        let rows: Vec<(&str, u64, u64)> = stats
+            .iter()
+            .map(|s| (s.name.as_str(), s.object_count, s.total_size))
+            .collect();
+        assert_eq!(rows, [("video", 3, 4700), ("docs", 2, 30), ("empty", 0, 0)]);
+        assert_eq!(stats[0].total_size_human, "4.59 KiB");
+        let video = storage.get_bucket("video").unwrap();
+        assert!(stats[0].last_activity >= video.created_at);
+    }
+
+    #[test]
+    fn largest_objects_come_from_the_index() {
+        let (_dir, storage) = engine();
+        fill(&storage);
+        // Same contents under two keys are both listed
+        storage.copy_object("video", "f1", "video", "copy-of-f1").unwrap();
+
+        let largest = |bucket, limit| -> Vec<(String, u64)> {
+            let objects = storage.largest_objects(bucket, limit).unwrap();
+            objects.into_iter().map(|o| (o.key, o.size)).collect()
+        };
+        assert_eq!(largest("video", 3), [("copy-of-f1".into(), 3000), ("f1".into(), 3000), ("f2".into(), 1200)]);
+        assert_eq!(largest("docs", 20), [("f1".into(), 20), ("f0".into(), 10)]);
+        assert!(largest("empty", 20).is_empty());
+
+        storage.delete_object("video", "f1").unwrap();
+        assert_eq!(largest("video", 1), [("copy-of-f1".into(), 3000)]);
+        assert!(matches!(storage.largest_objects("missing", 5), Err(AppError::BucketNotFound(_))));
+    }
+}
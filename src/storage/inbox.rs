@@ -0,0 +1,122 @@
+use super::{PutOptions, StorageEngine};
+use crate::error::AppError;
+use crate::models::{Bucket, InboxLimits, ObjectMeta};
+
+/// Whether `content_type` is covered by an allow-list entry such as
+/// `application/pdf` or `image/*`
+fn content_type_allowed(allowed: &[String], content_type: &str) -> bool {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    allowed.is_empty()
+        || allowed.iter().any(|pattern| {
+            let pattern = pattern.trim().to_ascii_lowercase();
+            match pattern.strip_suffix("/*") {
+                Some(family) => content_type
+                    .split_once('/')
+                    .is_some_and(|(f, _)| f == family),
+                None => pattern == content_type,
+            }
+        })
+}
+
+/// Keep only the last path segment of a client-supplied file name
+fn sanitize_file_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("").trim();
+    if name.is_empty() || name == "." || name == ".." {
+        "upload".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+impl StorageEngine {
+    /// Turn a bucket's upload inbox on or off, optionally replacing its limits
+    pub fn set_bucket_inbox(
+        &self,
+        bucket: &str,
+        enabled: bool,
+        limits: Option<InboxLimits>,
+    ) -> Result<Bucket, AppError> {
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.inbox = enabled;
+            if let Some(limits) = limits {
+                info.inbox_limits = limits;
+            }
+        }
+        // Persists the settings along with the stats
+        self.persist_bucket_meta(bucket)?;
+        tracing::info!(
+            "Inbox {} for bucket {}",
+            if enabled { "enabled" } else { "disabled" },
+            bucket
+        );
+        self.get_bucket(bucket)
+    }
+
+    /// Store a file dropped into a bucket's inbox. Keys are prefixed with the
+    /// date and a random id so uploads never overwrite each other (or
+    /// anything else in the bucket).
+    pub fn put_inbox_object(
+        &self,
+        bucket: &str,
+        file_name: &str,
+        data: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<ObjectMeta, AppError> {
+        let info = self.get_bucket(bucket)?;
+        if !info.inbox {
+            return Err(AppError::AccessDenied(format!(
+                "Bucket '{}' does not accept inbox uploads",
+                bucket
+            )));
+        }
+
+        let limits = &info.inbox_limits;
+        if let Some(max_size) = limits.max_size {
+            if data.len() as u64 > max_size {
+                return Err(AppError::InvalidRequest(format!(
+                    "File is larger than the {} limit",
+                    crate::size::human_readable_size(max_size)
+                )));
+            }
+        }
+
+        let file_name = sanitize_file_name(file_name);
+        let content_type = content_type
+            .map(str::to_string)
+            .unwrap_or_else(|| super::content_type::detect_content_type(&file_name, data));
+        if !content_type_allowed(&limits.content_types, &content_type) {
+            return Err(AppError::InvalidRequest(format!(
+                "Files of type '{}' are not accepted",
+                content_type
+            )));
+        }
+
+        let key = format!(
+            "{}/{}-{}",
+            self.now().format("%Y-%m-%d"),
+            &self.new_id().simple().to_string()[..8],
+            file_name
+        );
+        let meta = self.put_object(
+            bucket,
+            &key,
+            data,
+            PutOptions {
+                content_type: Some(&content_type),
+                original_filename: Some(file_name.as_str()),
+                ..Default::default()
+            },
+        )?;
+        tracing::info!("Inbox upload: {}/{} ({} bytes)", bucket, key, meta.size);
+        Ok(meta)
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+
+use chrono::Duration;
+use sha2::{Digest, Sha256};
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{InventoryConfig, InventoryFormat, ObjectMeta};
+
+const CSV_HEADER: &str = "bucket,key,size,etag,last_modified,content_type,storage_class,metadata_count";
+
+/// Tees written bytes into a SHA-256 hasher so streamed objects get an ETag
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn inventory_row(meta: &ObjectMeta, format: InventoryFormat) -> String {
+    match format {
+        InventoryFormat::Csv => format!(
+            "{},{},{},{},{},{},STANDARD,{}\n",
+            csv_field(&meta.bucket),
+            csv_field(&meta.key),
+            meta.size,
+            csv_field(&meta.etag),
+            meta.modified().to_rfc3339(),
+            csv_field(&meta.content_type),
+            meta.metadata.len()
+        ),
+        InventoryFormat::Json => serde_json::json!({
+            "bucket": meta.bucket,
+            "key": meta.key,
+            "size": meta.size,
+            "etag": meta.etag,
+            "last_modified": meta.modified().to_rfc3339(),
+            "content_type": meta.content_type,
+            "storage_class": "STANDARD",
+            "metadata_count": meta.metadata.len(),
+        })
+        .to_string(),
+    }
+}
+
+impl StorageEngine {
+    /// Stream an inventory of every object in `bucket` to `out`, one object
+    /// at a time. Returns the number of objects written.
+    pub fn write_inventory<W: Write>(
+        &self,
+        bucket: &str,
+        format: InventoryFormat,
+        out: &mut W,
+    ) -> Result<u64, AppError> {
+        let mut count = 0u64;
+        match format {
+            InventoryFormat::Csv => writeln!(out, "{}", CSV_HEADER)?,
+            InventoryFormat::Json => write!(out, "[")?,
+        }
+
+        self.visit_objects(bucket, "", |meta| {
+            let row = inventory_row(&meta, format);
+            if format == InventoryFormat::Json {
+                out.write_all(if count == 0 { b"\n  " } else { b",\n  " })?;
+            }
+            out.write_all(row.as_bytes())?;
+            count += 1;
+            Ok(())
+        })?;
+
+        if format == InventoryFormat::Json {
+            writeln!(out, "{}]", if count == 0 { "" } else { "\n" })?;
+        }
+        out.flush()?;
+        Ok(count)
+    }
+
+    /// Generate an inventory of `bucket` and store it as an object under
+    /// `dest_prefix` in `dest_bucket`
+    pub fn generate_inventory_object(
+        &self,
+        bucket: &str,
+        format: InventoryFormat,
+        dest_bucket: &str,
+        dest_prefix: &str,
+    ) -> Result<ObjectMeta, AppError> {
+        self.get_bucket(bucket)?;
+        self.get_bucket(dest_bucket)?;
+
+        let key = format!(
+            "{}{}/{}.{}",
+            dest_prefix,
+            bucket,
+            self.now().format("%Y-%m-%dT%H-%M-%SZ"),
+            format.extension()
+        );
+
+        let tmp = self.temp_file(dest_bucket)?;
+        let mut writer = HashingWriter {
+            inner: BufWriter::new(fs::File::create(tmp.path())?),
+            hasher: Sha256::new(),
+        };
+        let count = self.write_inventory(bucket, format, &mut writer)?;
+        let HashingWriter { inner, hasher } = writer;
+        drop(inner.into_inner().map_err(|e| e.into_error())?);
+
+        let etag = hex::encode(hasher.finalize());
+        let meta =
+            self.store_streamed_object(dest_bucket, &key, tmp, etag, format.content_type())?;
+        tracing::info!(
+            "Wrote inventory of {} ({} objects) to {}/{}",
+            bucket,
+            count,
+            dest_bucket,
+            key
+        );
+        Ok(meta)
+    }
+
+    // ─── Scheduled inventories ────────────────────────────────────
+
+    pub fn get_inventory_config(&self, bucket: &str) -> Result<Option<InventoryConfig>, AppError> {
+        self.get_bucket(bucket)?;
+        let path = self.bucket_path(bucket).join(".inventory.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path)?;
+        serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| AppError::StorageError(format!("Corrupt inventory config: {}", e)))
+    }
+
+    pub fn set_inventory_config(
+        &self,
+        bucket: &str,
+        config: &InventoryConfig,
+    ) -> Result<(), AppError> {
+        self.get_bucket(bucket)?;
+        self.get_bucket(&config.destination_bucket)?;
+        let json = serde_json::to_string_pretty(config).unwrap();
+        fs::write(self.bucket_path(bucket).join(".inventory.json"), json)?;
+        Ok(())
+    }
+
+    pub fn delete_inventory_config(&self, bucket: &str) -> Result<(), AppError> {
+        self.get_bucket(bucket)?;
+        let path = self.bucket_path(bucket).join(".inventory.json");
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Generate the daily inventory for every bucket whose last run is more
+    /// than a day old. Returns how many reports were written.
+    pub fn run_scheduled_inventories(&self) -> usize {
+        let mut generated = 0;
+        for bucket in self.list_buckets() {
+            let Ok(Some(mut config)) = self.get_inventory_config(&bucket.name) else {
+                continue;
+            };
+            let due = match config.last_run {
+                Some(last_run) => self.now() - last_run >= Duration::days(1),
+                None => true,
+            };
+            if !due {
+                continue;
+            }
+
+            match self.generate_inventory_object(
+                &bucket.name,
+                config.format,
+                &config.destination_bucket,
+                &config.destination_prefix,
+            ) {
+                Ok(_) => {
+                    config.last_run = Some(self.now());
+                    if let Err(e) = self.set_inventory_config(&bucket.name, &config) {
+                        tracing::warn!("Failed to record inventory run for {}: {:?}", bucket.name, e);
+                    }
+                    generated += 1;
+                }
+                Err(e) => tracing::warn!("Scheduled inventory of {} failed: {:?}", bucket.name, e),
+            }
+        }
+        generated
+    }
+}
@@ -0,0 +1,181 @@
+//! Keys that differ only in case on filesystems that ignore it, such as
+//! APFS and NTFS as usually set up. There `Readme.md` and `readme.md` are
+//! one file, so the second PUT would quietly replace the first while
+//! listings show a single key. Whether the data dir's filesystem ignores
+//! case is probed at startup; if it does, writes of such keys are either
+//! refused or stored under case-escaped paths (see `key_paths`), as
+//! `FREEBUCKET_KEY_CASE` says.
+
+use std::fs;
+use std::path::Path;
+
+use super::{key_paths, StorageEngine};
+use crate::error::AppError;
+
+/// What a write does to a key differing only in case from an existing one,
+/// on a filesystem that ignores case
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCaseMode {
+    /// Refuse it with `KeyCaseCollision`
+    Reject,
+    /// Store segments with capitals escaped, so both keys are kept
+    Escape,
+}
+
+impl KeyCaseMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "reject" => Some(Self::Reject),
+            "escape" => Some(Self::Escape),
+            _ => None,
+        }
+    }
+}
+
+/// Turn key case handling on as the filesystem needs. Returns whether
+/// writes must be checked for collisions.
+pub(super) fn configure(mode: KeyCaseMode, case_sensitive: bool) -> bool {
+    key_paths::set_case_escaping(!case_sensitive && mode == KeyCaseMode::Escape);
+    match (case_sensitive, mode) {
+        (true, _) => false,
+        (false, KeyCaseMode::Reject) => {
+            tracing::warn!(
+                "The data dir's filesystem ignores case; writes of keys differing only in case from an existing one are refused"
+            );
+            true
+        }
+        (false, KeyCaseMode::Escape) => {
+            tracing::info!("The data dir's filesystem ignores case; key segments with capitals are stored escaped");
+            false
+        }
+    }
+}
+
+/// Which of `names` spells `name` in another case, if any
+fn other_spelling(names: impl IntoIterator<Item = String>, name: &str) -> Option<String> {
+    let folded = name.to_lowercase();
+    names.into_iter().find(|n| n != name && n.to_lowercase() == folded)
+}
+
+/// The entry of `dir` spelling `name` in another case
+fn spelled_differently(dir: &Path, name: &str) -> Option<String> {
+    let entries = fs::read_dir(dir).ok()?;
+    other_spelling(
+        entries.flatten().map(|e| e.file_name().to_string_lossy().into_owned()),
+        name,
+    )
+}
+
+impl StorageEngine {
+    /// Refuse a write to `key` when the bucket holds a key or prefix
+    /// spelled the same but for case. Each existing directory on the way
+    /// to the key is read, so this only runs where it is needed.
+    pub(super) fn check_key_case(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        if !self.key_case_checked {
+            return Ok(());
+        }
+        let stored = self.stored_key(bucket, key);
+        let path = key_paths::key_to_path(&stored);
+        let segments: Vec<&str> = path.split('/').collect();
+        let mut dir = self.bucket_path(bucket).join("objects");
+        for (i, segment) in segments.iter().enumerate() {
+            let next = dir.join(segment);
+            // Nothing below a missing entry can collide
+            if fs::symlink_metadata(&next).is_err() {
+                return Ok(());
+            }
+            if let Some(existing) = spelled_differently(&dir, segment) {
+                let mut taken = segments[..i].to_vec();
+                taken.push(&existing);
+                let mut existing = key_paths::path_to_key(&taken.join("/")).into_owned();
+                if i + 1 < segments.len() {
+                    existing.push('/');
+                }
+                return Err(AppError::KeyCaseCollision {
+                    bucket: bucket.to_string(),
+                    key: key.to_string(),
+                    existing,
+                });
+            }
+            dir = next;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    fn put(storage: &StorageEngine, key: &str) -> Result<(), AppError> {
+        storage
+            .put_object("docs", key, key.as_bytes(), PutOptions::default())
+            .map(|_| ())
+    }
+
+    fn collision(result: Result<(), AppError>) -> (String, String) {
+        match result {
+            Err(AppError::KeyCaseCollision { key, existing, .. }) => (key, existing),
+            other => panic!("expected a KeyCaseCollision, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn finds_other_spellings_of_a_name() {
+        let names = || ["Readme.md", "notes", "ÉTÉ.txt"].map(String::from);
+        assert_eq!(other_spelling(names(), "readme.md").as_deref(), Some("Readme.md"));
+        assert_eq!(other_spelling(names(), "README.MD").as_deref(), Some("Readme.md"));
+        assert_eq!(other_spelling(names(), "été.txt").as_deref(), Some("ÉTÉ.txt"));
+        assert_eq!(other_spelling(names(), "Readme.md"), None, "the same spelling is the same key");
+        assert_eq!(other_spelling(names(), "readme.txt"), None);
+    }
+
+    #[test]
+    fn checks_only_where_the_probe_found_case_ignored() {
+        assert!(!configure(KeyCaseMode::Reject, true));
+        assert!(!configure(KeyCaseMode::Escape, true));
+        assert!(configure(KeyCaseMode::Reject, false));
+        assert!(!key_paths::case_escaping());
+        assert_eq!(KeyCaseMode::parse("Escape"), Some(KeyCaseMode::Escape));
+        assert_eq!(KeyCaseMode::parse("ignore"), None);
+    }
+
+    /// A case-sensitive data dir standing in for one that ignores case:
+    /// both spellings are stored first, which is what a lookup on such a
+    /// filesystem would find, and then the check is switched on
+    #[test]
+    fn names_the_key_or_prefix_a_write_collides_with() {
+        let (_dir, mut storage) = engine();
+        storage.create_bucket("docs", "local").unwrap();
+        for key in ["Readme.md", "readme.md", "Guides/a.txt", "guides/b.txt"] {
+            put(&storage, key).unwrap();
+        }
+        storage.key_case_checked = true;
+
+        let err = put(&storage, "readme.md").unwrap_err();
+        let (status, code, message) = err.parts();
+        assert_eq!((status.as_u16(), code), (409, "KeyCaseCollision"));
+        assert!(message.contains("'readme.md' differs only in case from 'Readme.md'"), "{}", message);
+        assert_eq!(collision(put(&storage, "guides/c.txt")).1, "Guides/");
+        // Keys whose segments have no other spelling are written
+        put(&storage, "CHANGELOG.md").unwrap();
+    }
+
+    /// Runs on the filesystem the tests run on only where it ignores case,
+    /// as macOS and Windows usually do
+    #[test]
+    fn the_second_spelling_is_refused_on_a_case_insensitive_filesystem() {
+        let (_dir, storage) = engine();
+        if storage.fs_features.case_sensitive {
+            return;
+        }
+        storage.create_bucket("docs", "local").unwrap();
+        put(&storage, "Readme.md").unwrap();
+        assert_eq!(collision(put(&storage, "readme.md")).1, "Readme.md");
+        assert_eq!(storage.get_object("docs", "Readme.md").unwrap().1, b"Readme.md");
+        storage.delete_object("docs", "Readme.md").unwrap();
+        put(&storage, "readme.md").unwrap();
+    }
+}
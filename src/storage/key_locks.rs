@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+use std::sync::{Condvar, Mutex};
+
+use super::StorageEngine;
+
+/// Objects with a write in progress. Whole-object PUTs and range writes
+/// both hold a key's lock, so a range write never patches a payload that a
+/// PUT is about to replace, and two range writes can't lose each other's
+/// bytes. Deletes hold it too, so pausing new locks stops every object write.
+#[derive(Default)]
+pub(super) struct KeyLocks {
+    state: Mutex<LockState>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct LockState {
+    held: HashSet<(String, String)>,
+    /// New locks wait while a backup takes its cut
+    paused: bool,
+}
+
+/// Held while an object is being written
+pub(super) struct KeyLock<'a> {
+    locks: &'a KeyLocks,
+    id: (String, String),
+}
+
+impl Drop for KeyLock<'_> {
+    fn drop(&mut self) {
+        self.locks.state.lock().unwrap().held.remove(&self.id);
+        self.locks.released.notify_all();
+    }
+}
+
+/// Held while object writes are paused; dropping it lets them go on
+pub(super) struct WritePause<'a> {
+    locks: &'a KeyLocks,
+}
+
+impl Drop for WritePause<'_> {
+    fn drop(&mut self) {
+        self.locks.state.lock().unwrap().paused = false;
+        self.locks.released.notify_all();
+    }
+}
+
+impl StorageEngine {
+    /// Wait until no other write holds `bucket/key`, then hold it until the
+    /// guard is dropped
+    pub(super) fn lock_key(&self, bucket: &str, key: &str) -> KeyLock<'_> {
+        self.lock_keys([(bucket, key)]).pop().unwrap()
+    }
+
+    /// Hold several keys at once. Taking them together rather than one by
+    /// one means a write never holds some keys while waiting out a pause.
+    pub(super) fn lock_keys<'k>(&self, keys: impl IntoIterator<Item = (&'k str, &'k str)>) -> Vec<KeyLock<'_>> {
+        let ids: Vec<(String, String)> = keys
+            .into_iter()
+            .map(|(bucket, key)| (bucket.to_string(), key.to_string()))
+            .collect();
+        let locks = &self.key_locks;
+        let mut state = locks.state.lock().unwrap();
+        while state.paused || ids.iter().any(|id| state.held.contains(id)) {
+            state = locks.released.wait(state).unwrap();
+        }
+        state.held.extend(ids.iter().cloned());
+        ids.into_iter().map(|id| KeyLock { locks, id }).collect()
+    }
+
+    /// Stop new object writes and wait for those in progress to finish.
+    /// Reads and listings carry on.
+    pub(super) fn pause_writes(&self) -> WritePause<'_> {
+        let locks = &self.key_locks;
+        let mut state = locks.state.lock().unwrap();
+        // Another pause must end first, or its guard would lift this one
+        while state.paused {
+            state = locks.released.wait(state).unwrap();
+        }
+        state.paused = true;
+        while !state.held.is_empty() {
+            state = locks.released.wait(state).unwrap();
+        }
+        WritePause { locks }
+    }
+}
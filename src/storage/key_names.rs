@@ -0,0 +1,145 @@
+use std::borrow::Cow;
+
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+use super::StorageEngine;
+use crate::error::AppError;
+
+impl StorageEngine {
+    /// The spelling a key is written under: its NFC form, unless key
+    /// normalization is off. Writes store this form, so `é` sent as one code
+    /// point or as `e` plus a combining accent is the same object.
+    pub fn normalize_key<'k>(&self, key: &'k str) -> Cow<'k, str> {
+        if !self.normalize_keys || is_nfc_quick(key.chars()) == IsNormalized::Yes {
+            return Cow::Borrowed(key);
+        }
+        let normalized: String = key.nfc().collect();
+        if normalized == key {
+            Cow::Borrowed(key)
+        } else {
+            Cow::Owned(normalized)
+        }
+    }
+
+    /// Refuse a key for a new object when it is empty, longer than S3
+    /// allows, or has more `/`-separated segments than the configured
+    /// depth. Each segment is a directory level on disk.
+    pub(super) fn check_new_key(&self, key: &str) -> Result<(), AppError> {
+        if key.is_empty() || key.len() > 1024 {
+            return Err(AppError::InvalidObjectKey(
+                "Key must be between 1 and 1024 characters".to_string(),
+            ));
+        }
+        if super::key_paths::key_to_path(key).split('/').any(|segment| segment.len() > 255) {
+            return Err(AppError::InvalidObjectKey(if super::key_paths::case_escaping() {
+                "Each '/'-separated part of a key must be at most 255 bytes, or 126 if it has capitals".to_string()
+            } else {
+                "Each '/'-separated part of a key must be at most 255 bytes".to_string()
+            }));
+        }
+        let depth = key.split('/').count();
+        if depth > self.max_key_depth {
+            return Err(AppError::InvalidObjectKey(format!(
+                "Key has {} path segments; at most {} are allowed (FREEBUCKET_MAX_KEY_DEPTH)",
+                depth, self.max_key_depth
+            )));
+        }
+        Ok(())
+    }
+
+    /// The spelling a key is found under on disk. Lookups normalize like
+    /// writes do, except that an object written in another form before
+    /// normalization was turned on keeps answering to that form.
+    pub(super) fn stored_key<'k>(&self, bucket: &str, key: &'k str) -> Cow<'k, str> {
+        let normalized = self.normalize_key(key);
+        if let Cow::Owned(nfc) = &normalized {
+            if !self.has_files_under(bucket, nfc) && self.has_files_under(bucket, key) {
+                return Cow::Borrowed(key);
+            }
+        }
+        normalized
+    }
+
+    /// Whether a payload or metadata file exists under this exact spelling.
+    /// A delete removes the payload first, so the metadata counts too.
+    fn has_files_under(&self, bucket: &str, key: &str) -> bool {
+        let bucket_path = self.bucket_path(bucket);
+        bucket_path.join("objects").join(&*super::key_paths::key_to_path(key)).exists()
+            || bucket_path
+                .join(".meta")
+                .join(format!("{}.json", super::key_paths::flat_name(key)))
+                .exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing::{engine, engine_with};
+    use crate::storage::PutOptions;
+
+    const COMPOSED: &str = "caf\u{e9}.txt";
+    const DECOMPOSED: &str = "cafe\u{301}.txt";
+
+    fn put(storage: &StorageEngine, key: &str, data: &[u8]) {
+        storage
+            .put_object("docs", key, data, PutOptions::default())
+            .unwrap();
+    }
+
+    fn keys(storage: &StorageEngine) -> Vec<String> {
+        let listing = storage.list_objects("docs", "", None, 1000).unwrap();
+        listing.objects.into_iter().map(|o| o.key).collect()
+    }
+
+    #[test]
+    fn either_spelling_is_the_same_object() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("docs", "local").unwrap();
+        put(&storage, DECOMPOSED, b"one");
+        put(&storage, COMPOSED, b"two");
+
+        assert_eq!(keys(&storage), [COMPOSED]);
+        assert_eq!(storage.get_object("docs", DECOMPOSED).unwrap().1, b"two");
+        storage.delete_object("docs", DECOMPOSED).unwrap();
+        assert!(keys(&storage).is_empty());
+    }
+
+    #[test]
+    fn spellings_stay_apart_with_normalization_off() {
+        let (_dir, storage) = engine_with(|o| o.normalize_keys = false);
+        storage.create_bucket("docs", "local").unwrap();
+        put(&storage, DECOMPOSED, b"one");
+        put(&storage, COMPOSED, b"two");
+
+        let mut found = keys(&storage);
+        found.sort();
+        assert_eq!(found, [DECOMPOSED, COMPOSED]);
+        assert_eq!(storage.get_object("docs", DECOMPOSED).unwrap().1, b"one");
+    }
+
+    #[test]
+    fn objects_written_before_normalization_keep_their_spelling() {
+        let (dir, storage) = engine_with(|o| o.normalize_keys = false);
+        storage.create_bucket("docs", "local").unwrap();
+        put(&storage, DECOMPOSED, b"old");
+        drop(storage);
+
+        let options = crate::config::Config::builtin().storage_options();
+        let storage = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+        assert_eq!(storage.normalize_key(DECOMPOSED), COMPOSED);
+        assert_eq!(storage.get_object("docs", DECOMPOSED).unwrap().1, b"old");
+        assert_eq!(keys(&storage), [DECOMPOSED]);
+    }
+
+    #[test]
+    fn keys_are_limited_in_bytes_and_depth() {
+        let (_dir, storage) = engine_with(|o| o.max_key_depth = 3);
+        assert!(storage.check_new_key("фото/日本語/🎉.txt").is_ok());
+        // 100 three-byte characters are 300 bytes on disk
+        assert!(storage.check_new_key(&"日".repeat(100)).is_err());
+        assert!(storage.check_new_key(&"日".repeat(85)).is_ok());
+        assert!(storage.check_new_key("a/b/c/d").is_err());
+        assert!(storage.check_new_key("").is_err());
+    }
+}
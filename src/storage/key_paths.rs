@@ -0,0 +1,236 @@
+//! How object keys map to paths under a bucket's `objects/` directory.
+//!
+//! Keys follow S3: every byte is significant, so `a//b`, `/leading`, and
+//! `trailing/` are keys of their own, distinct from `a/b`, `leading`, and
+//! `trailing`. Each `/`-separated segment of a key becomes one path
+//! component. Segments the filesystem would collapse or resolve (empty, `.`
+//! and `..`) are stored with a `.%` prefix, and so are segments that already
+//! start with `.%`, which keeps the mapping reversible. Keys without such
+//! segments are stored exactly as they are spelled.
+//!
+//! With `FREEBUCKET_KEY_CASE=escape` on a filesystem that ignores case,
+//! segments with letters that have a lowercase form are stored as `.%x`
+//! followed by their bytes in hex, which the filesystem can't fold, so
+//! `Readme.md` and `readme.md` are two files.
+//!
+//! Metadata files in `.meta/` and version directories in `.versions/` are
+//! named by the key with each `/` spelled `__SLASH__`. A name that would
+//! pass the 255-byte limit most filesystems put on a file name is replaced
+//! by the key's SHA-256 instead, and the key itself is read from the
+//! metadata. So is the name of a key that spells `__SLASH__` itself, which
+//! would otherwise share a name with the key that has a `/` there. Keys with
+//! such letters get a hashed name too when segments are case-escaped.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const ESCAPE: &str = ".%";
+const SLASH: &str = "__SLASH__";
+/// Start of every hashed flat name; flat names that would begin with it
+/// are hashed too, so it is never ambiguous
+const HASHED: &str = ".%sha256-";
+/// Longest flat name kept as spelled, leaving room for `.json`
+const FLAT_NAME_MAX: usize = 250;
+/// Start of a case-escaped segment. Escaping other segments never makes
+/// `.%` followed by `x`, so it is never ambiguous either.
+const CASED: &str = ".%x";
+
+/// Set at startup when segments are case-escaped. Key paths are worked out
+/// by code that has no engine at hand, as with the size units.
+static ESCAPE_CASE: AtomicBool = AtomicBool::new(false);
+
+pub(super) fn set_case_escaping(on: bool) {
+    ESCAPE_CASE.store(on, Ordering::Relaxed);
+}
+
+pub(super) fn case_escaping() -> bool {
+    ESCAPE_CASE.load(Ordering::Relaxed)
+}
+
+fn needs_escape(segment: &str) -> bool {
+    segment.is_empty() || segment == "." || segment == ".." || segment.starts_with(ESCAPE)
+}
+
+/// Whether a filesystem that ignores case could fold part of `text`
+fn has_case(text: &str) -> bool {
+    text.chars().any(|c| !c.to_lowercase().eq(std::iter::once(c)))
+}
+
+/// Relative path (with `/` separators) a key's payload is stored under
+pub(super) fn key_to_path(key: &str) -> Cow<'_, str> {
+    escaped_path(key, case_escaping())
+}
+
+fn escaped_path(key: &str, escape_case: bool) -> Cow<'_, str> {
+    if !key.split('/').any(|s| needs_escape(s) || (escape_case && has_case(s))) {
+        return Cow::Borrowed(key);
+    }
+    let segments: Vec<String> = key
+        .split('/')
+        .map(|segment| {
+            if escape_case && has_case(segment) {
+                format!("{}{}", CASED, hex::encode(segment))
+            } else if needs_escape(segment) {
+                format!("{}{}", ESCAPE, segment)
+            } else {
+                segment.to_string()
+            }
+        })
+        .collect();
+    Cow::Owned(segments.join("/"))
+}
+
+/// Name a key's metadata file (plus `.json`) and versions directory have
+pub(super) fn flat_name(key: &str) -> String {
+    flat_name_escaping(key, case_escaping())
+}
+
+fn flat_name_escaping(key: &str, escape_case: bool) -> String {
+    let flat = key.replace('/', SLASH);
+    let hashed = flat.len() > FLAT_NAME_MAX
+        || flat.starts_with(HASHED)
+        || key.contains(SLASH)
+        || (escape_case && has_case(key));
+    if !hashed {
+        return flat;
+    }
+    format!("{}{}", HASHED, hex::encode(Sha256::digest(key.as_bytes())))
+}
+
+/// The key a flat name stands for, or `None` for a hashed one
+pub(super) fn flat_name_key(name: &str) -> Option<String> {
+    (!name.starts_with(HASHED)).then(|| name.replace(SLASH, "/"))
+}
+
+/// The key of the metadata file at `path`, whose name is `stem` plus
+/// `.json`. A hashed name means reading the key from the file.
+pub(super) fn meta_file_key(path: &Path, stem: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Keyed {
+        key: String,
+    }
+    flat_name_key(stem).or_else(|| {
+        let data = fs::read(path).ok()?;
+        serde_json::from_slice::<Keyed>(&data).ok().map(|meta| meta.key)
+    })
+}
+
+/// The key stored under a relative path; the inverse of `key_to_path`
+pub(super) fn path_to_key(path: &str) -> Cow<'_, str> {
+    if !path.contains(ESCAPE) {
+        return Cow::Borrowed(path);
+    }
+    let segments: Vec<Cow<'_, str>> = path
+        .split('/')
+        .map(|segment| {
+            let cased = segment
+                .strip_prefix(CASED)
+                .and_then(|hex| hex::decode(hex).ok())
+                .and_then(|bytes| String::from_utf8(bytes).ok());
+            match cased {
+                Some(original) => Cow::Owned(original),
+                None => Cow::Borrowed(segment.strip_prefix(ESCAPE).unwrap_or(segment)),
+            }
+        })
+        .collect();
+    Cow::Owned(segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keys that look like the escapes, the slash spelling, or each other
+    const KEYS: &[&str] = &[
+        "plain.txt",
+        "a/b",
+        "a__SLASH__b",
+        "a/__SLASH__/b",
+        "__SLASH__",
+        "/",
+        "//",
+        "a//b",
+        "/leading",
+        "trailing/",
+        ".",
+        "..",
+        "./..",
+        ".%",
+        ".%x41",
+        ".%.",
+        "a/.%/b",
+        ".%%",
+        ".%sha256-00",
+        "dir/.%sha256-00",
+        "Readme.md",
+        "readme.md",
+        "README.MD",
+        "Ärger/ärger",
+    ];
+
+    #[test]
+    fn adversarial_keys_round_trip_through_their_paths() {
+        for key in KEYS {
+            let path = key_to_path(key);
+            assert_eq!(path_to_key(&path), *key, "{:?} stored as {:?}", key, path);
+            for segment in path.split('/') {
+                assert!(!segment.is_empty() && segment != "." && segment != "..", "{:?} stored as {:?}", key, path);
+            }
+        }
+        let mut paths: Vec<String> = KEYS.iter().map(|k| key_to_path(k).into_owned()).collect();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), KEYS.len());
+    }
+
+    #[test]
+    fn flat_names_are_unique_and_lead_back_to_their_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let long = "segment/".repeat(40);
+        let keys: Vec<&str> = KEYS.iter().copied().chain([long.as_str()]).collect();
+        let mut names = Vec::new();
+        for key in &keys {
+            let name = flat_name(key);
+            assert!(name.len() <= FLAT_NAME_MAX, "{:?}", key);
+            assert!(!name.contains('/'), "{:?} named {:?}", key, name);
+            // The metadata file of a hashed name carries the key
+            let path = dir.path().join(format!("{}.json", name));
+            fs::write(&path, serde_json::json!({ "key": key }).to_string()).unwrap();
+            assert_eq!(meta_file_key(&path, &name).as_deref(), Some(*key), "{:?} named {:?}", key, name);
+            names.push(name);
+        }
+        assert_eq!(flat_name("a/b"), "a__SLASH__b");
+        assert_ne!(flat_name("a__SLASH__b"), flat_name("a/b"));
+        assert!(flat_name("a__SLASH__b").starts_with(HASHED));
+        assert!(flat_name(&long).starts_with(HASHED));
+        assert_eq!(flat_name_key(&flat_name(&long)), None);
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), keys.len());
+    }
+
+    #[test]
+    fn case_escaping_keeps_keys_that_differ_in_case_apart() {
+        let cased = ["Readme.md", "readme.md", "README.MD", "docs/Readme.md", "Ärger/ärger"];
+        let paths: Vec<String> = cased.iter().map(|k| escaped_path(k, true).into_owned()).collect();
+        let names: Vec<String> = cased.iter().map(|k| flat_name_escaping(k, true)).collect();
+
+        for (key, path) in cased.iter().zip(&paths) {
+            assert!(!has_case(path), "{:?} stored as {:?}", key, path);
+            assert_eq!(path_to_key(path), *key);
+        }
+        let folded: std::collections::HashSet<String> = paths.iter().map(|p| p.to_lowercase()).collect();
+        assert_eq!(folded.len(), cased.len());
+        for (key, name) in cased.iter().zip(&names) {
+            assert!(!has_case(name), "{:?} named {:?}", key, name);
+            assert_eq!(name.starts_with(HASHED), has_case(key), "{:?} named {:?}", key, name);
+        }
+        let folded: std::collections::HashSet<String> = names.iter().map(|n| n.to_lowercase()).collect();
+        assert_eq!(folded.len(), cased.len());
+    }
+}
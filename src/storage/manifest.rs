@@ -0,0 +1,372 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use super::{Audit, PutOptions, StorageEngine};
+use crate::error::AppError;
+use crate::models::{AuditAction, BucketManifest, InboxLimits, LoggingConfig, Manifest, OverwritePolicy};
+
+/// One step towards the state a manifest describes
+#[derive(Debug)]
+pub enum ManifestChange {
+    CreateBucket { bucket: String, region: String },
+    SetRegion { bucket: String, from: String, to: String },
+    SetVersioning { bucket: String, enabled: bool },
+    SetInbox { bucket: String, enabled: bool, limits: Option<InboxLimits> },
+    SetPrecompressed { bucket: String, enabled: bool },
+    SetLogging { bucket: String, logging: LoggingConfig },
+    SetOverwritePolicy { bucket: String, policy: OverwritePolicy },
+    SetMaxObjects { bucket: String, limit: Option<u64> },
+    UploadSeed { bucket: String, key: String, path: PathBuf, size: u64 },
+    DeleteBucket { bucket: String, objects: u64 },
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+impl ManifestChange {
+    /// How the audit log records the change; seed uploads aren't recorded
+    pub fn audit<'a>(&'a self, actor: &'a str) -> Option<Audit<'a>> {
+        let (action, bucket, setting) = match self {
+            ManifestChange::CreateBucket { bucket, .. } => (AuditAction::BucketCreate, bucket, None),
+            ManifestChange::DeleteBucket { bucket, .. } => (AuditAction::BucketDelete, bucket, None),
+            ManifestChange::SetRegion { bucket, .. } => (AuditAction::BucketSettings, bucket, Some("region")),
+            ManifestChange::SetVersioning { bucket, .. } => (AuditAction::BucketSettings, bucket, Some("versioning")),
+            ManifestChange::SetInbox { bucket, .. } => (AuditAction::BucketSettings, bucket, Some("inbox")),
+            ManifestChange::SetPrecompressed { bucket, .. } => {
+                (AuditAction::BucketSettings, bucket, Some("precompressed"))
+            }
+            ManifestChange::SetLogging { bucket, .. } => (AuditAction::BucketSettings, bucket, Some("logging")),
+            ManifestChange::SetOverwritePolicy { bucket, .. } => {
+                (AuditAction::BucketSettings, bucket, Some("overwrite_policy"))
+            }
+            ManifestChange::SetMaxObjects { bucket, .. } => {
+                (AuditAction::BucketSettings, bucket, Some("max_objects"))
+            }
+            ManifestChange::UploadSeed { .. } => return None,
+        };
+        Some(Audit {
+            actor,
+            action,
+            bucket: Some(bucket),
+            target: bucket,
+            changes: setting.into_iter().map(str::to_string).collect(),
+        })
+    }
+}
+
+impl fmt::Display for ManifestChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestChange::CreateBucket { bucket, region } => {
+                write!(f, "+ create bucket {} (region {})", bucket, region)
+            }
+            ManifestChange::SetRegion { bucket, from, to } => {
+                write!(f, "~ {}: region {} → {}", bucket, from, to)
+            }
+            ManifestChange::SetVersioning { bucket, enabled } => {
+                write!(f, "~ {}: versioning {}", bucket, on_off(*enabled))
+            }
+            ManifestChange::SetInbox { bucket, enabled, limits } => {
+                write!(f, "~ {}: inbox {}", bucket, on_off(*enabled))?;
+                if limits.is_some() {
+                    write!(f, " with new limits")?;
+                }
+                Ok(())
+            }
+            ManifestChange::SetPrecompressed { bucket, enabled } => {
+                write!(f, "~ {}: pre-compressed variants {}", bucket, on_off(*enabled))
+            }
+            ManifestChange::SetLogging { bucket, logging } => write!(
+                f,
+                "~ {}: access logs to {}/{}",
+                bucket, logging.target_bucket, logging.target_prefix
+            ),
+            ManifestChange::SetOverwritePolicy { bucket, policy } => {
+                write!(f, "~ {}: overwrite policy {}", bucket, policy.name())
+            }
+            ManifestChange::SetMaxObjects { bucket, limit: Some(limit) } => {
+                write!(f, "~ {}: at most {} objects", bucket, limit)
+            }
+            ManifestChange::SetMaxObjects { bucket, limit: None } => {
+                write!(f, "~ {}: no object limit", bucket)
+            }
+            ManifestChange::UploadSeed { bucket, key, path, size } => write!(
+                f,
+                "+ upload {} → {}/{} ({})",
+                path.display(),
+                bucket,
+                key,
+                crate::size::human_readable_size(*size)
+            ),
+            ManifestChange::DeleteBucket { bucket, objects } => {
+                write!(f, "- delete bucket {} and its {} object(s)", bucket, objects)
+            }
+        }
+    }
+}
+
+/// Files under a seed directory with their paths relative to it, sorted.
+/// Symlinks are skipped.
+fn seed_files(dir: &Path, rel: &str, out: &mut Vec<(PathBuf, String)>) -> std::io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let rel_name = format!("{}{}", rel, name);
+        if file_type.is_dir() {
+            seed_files(&entry.path(), &format!("{}/", rel_name), out)?;
+        } else if file_type.is_file() {
+            out.push((entry.path(), rel_name));
+        }
+    }
+    Ok(())
+}
+
+/// SHA-256 of a local file in the hex form of stored ETags
+pub(super) fn file_etag(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+impl StorageEngine {
+    /// The current buckets and their settings as a manifest. Objects are not
+    /// included.
+    pub fn export_manifest(&self) -> Manifest {
+        let buckets = self
+            .list_buckets()
+            .into_iter()
+            .map(|b| BucketManifest {
+                name: b.name,
+                region: Some(b.region),
+                versioning: Some(b.versioning),
+                inbox: Some(b.inbox),
+                inbox_limits: (b.inbox_limits != InboxLimits::default()).then_some(b.inbox_limits),
+                precompressed: Some(b.precompressed),
+                logging: b.logging,
+                overwrite_policy: Some(b.overwrite_policy),
+                max_objects: Some(b.max_objects.unwrap_or(0)),
+                seed: Vec::new(),
+            })
+            .collect();
+        Manifest { buckets }
+    }
+
+    /// Changes that bring the instance in line with `manifest`, in the order
+    /// they must be applied: new buckets first (they may be logging targets),
+    /// then settings, seed uploads, and with `prune` the removal of buckets
+    /// the manifest doesn't mention. Seed paths are relative to `base_dir`.
+    pub fn plan_manifest(
+        &self,
+        manifest: &Manifest,
+        base_dir: &Path,
+        default_region: &str,
+        prune: bool,
+    ) -> Result<Vec<ManifestChange>, AppError> {
+        let existing: HashMap<String, _> = self
+            .list_buckets()
+            .into_iter()
+            .map(|b| (b.name.clone(), b))
+            .collect();
+
+        let mut named = HashSet::new();
+        for desired in &manifest.buckets {
+            if existing.contains_key(&desired.name) {
+                Self::validate_bucket_syntax(&desired.name)?;
+            } else {
+                self.validate_bucket_name(&desired.name)?;
+            }
+            if let Some(region) = &desired.region {
+                Self::validate_region(region)?;
+            }
+            if !named.insert(desired.name.as_str()) {
+                return Err(AppError::InvalidRequest(format!(
+                    "Bucket '{}' appears more than once in the manifest",
+                    desired.name
+                )));
+            }
+        }
+        for desired in &manifest.buckets {
+            if let Some(logging) = &desired.logging {
+                if !named.contains(logging.target_bucket.as_str())
+                    && !existing.contains_key(&logging.target_bucket)
+                {
+                    return Err(AppError::BucketNotFound(logging.target_bucket.clone()));
+                }
+            }
+        }
+
+        let mut creates = Vec::new();
+        let mut settings = Vec::new();
+        let mut seeds = Vec::new();
+        for desired in &manifest.buckets {
+            let bucket = desired.name.clone();
+            let current = existing.get(&bucket);
+            match current {
+                None => creates.push(ManifestChange::CreateBucket {
+                    bucket: bucket.clone(),
+                    region: desired.region.clone().unwrap_or_else(|| default_region.to_string()),
+                }),
+                Some(current) => {
+                    if let Some(region) = desired.region.as_ref().filter(|r| **r != current.region) {
+                        settings.push(ManifestChange::SetRegion {
+                            bucket: bucket.clone(),
+                            from: current.region.clone(),
+                            to: region.clone(),
+                        });
+                    }
+                }
+            }
+
+            // `version` needs versioning on, so it is set after versioning
+            // and any other policy before it, in case versioning goes off
+            let mut policy_change = desired
+                .overwrite_policy
+                .filter(|policy| current.map_or(*policy != OverwritePolicy::Allow, |c| c.overwrite_policy != *policy))
+                .map(|policy| ManifestChange::SetOverwritePolicy { bucket: bucket.clone(), policy });
+            if desired.overwrite_policy != Some(OverwritePolicy::Version) {
+                settings.extend(policy_change.take());
+            }
+            if let Some(limit) = desired.max_objects {
+                // Before the seeds, which the limit applies to
+                let limit = (limit > 0).then_some(limit);
+                if current.map_or(self.default_max_objects, |c| c.max_objects) != limit {
+                    settings.push(ManifestChange::SetMaxObjects { bucket: bucket.clone(), limit });
+                }
+            }
+            if let Some(enabled) = desired.versioning {
+                if current.map_or(enabled, |c| c.versioning != enabled) {
+                    settings.push(ManifestChange::SetVersioning { bucket: bucket.clone(), enabled });
+                }
+            }
+            settings.extend(policy_change);
+            let inbox_enabled = desired.inbox.or(current.map(|c| c.inbox)).unwrap_or(false);
+            let limits_changed = desired.inbox_limits.as_ref().filter(|limits| {
+                **limits != current.map(|c| c.inbox_limits.clone()).unwrap_or_default()
+            });
+            if limits_changed.is_some() || current.map_or(inbox_enabled, |c| c.inbox != inbox_enabled) {
+                settings.push(ManifestChange::SetInbox {
+                    bucket: bucket.clone(),
+                    enabled: inbox_enabled,
+                    limits: limits_changed.cloned(),
+                });
+            }
+            if let Some(enabled) = desired.precompressed {
+                if current.map_or(enabled, |c| c.precompressed != enabled) {
+                    settings.push(ManifestChange::SetPrecompressed { bucket: bucket.clone(), enabled });
+                }
+            }
+            if let Some(logging) = &desired.logging {
+                if current.and_then(|c| c.logging.as_ref()) != Some(logging) {
+                    settings.push(ManifestChange::SetLogging {
+                        bucket: bucket.clone(),
+                        logging: logging.clone(),
+                    });
+                }
+            }
+
+            for seed in &desired.seed {
+                let path = base_dir.join(&seed.path);
+                let meta = fs::metadata(&path).map_err(|e| {
+                    AppError::InvalidRequest(format!("Seed '{}': {}", path.display(), e))
+                })?;
+                let mut files = Vec::new();
+                if meta.is_dir() {
+                    let prefix = if seed.key.is_empty() || seed.key.ends_with('/') {
+                        seed.key.clone()
+                    } else {
+                        format!("{}/", seed.key)
+                    };
+                    seed_files(&path, &prefix, &mut files)?;
+                } else {
+                    let key = if seed.key.is_empty() || seed.key.ends_with('/') {
+                        let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+                        format!("{}{}", seed.key, name.unwrap_or_default())
+                    } else {
+                        seed.key.clone()
+                    };
+                    files.push((path, key));
+                }
+
+                for (path, key) in files {
+                    if current.is_some() {
+                        if let Ok(stored) = self.get_object_meta(&bucket, &key) {
+                            if stored.etag == file_etag(&path)? {
+                                continue;
+                            }
+                        }
+                    }
+                    let size = fs::metadata(&path)?.len();
+                    seeds.push(ManifestChange::UploadSeed { bucket: bucket.clone(), key, path, size });
+                }
+            }
+        }
+
+        let mut plan = creates;
+        plan.append(&mut settings);
+        plan.append(&mut seeds);
+        if prune {
+            let mut extra: Vec<_> = existing.values().filter(|b| !named.contains(b.name.as_str())).collect();
+            extra.sort_by(|a, b| a.name.cmp(&b.name));
+            plan.extend(extra.into_iter().map(|b| ManifestChange::DeleteBucket {
+                bucket: b.name.clone(),
+                objects: b.object_count,
+            }));
+        }
+        Ok(plan)
+    }
+
+    pub fn apply_manifest_change(&self, change: &ManifestChange) -> Result<(), AppError> {
+        match change {
+            ManifestChange::CreateBucket { bucket, region } => {
+                self.create_bucket(bucket, region)?;
+            }
+            ManifestChange::SetRegion { bucket, to, .. } => {
+                self.set_bucket_region(bucket, to)?;
+            }
+            ManifestChange::SetVersioning { bucket, enabled } => {
+                self.set_bucket_versioning(bucket, *enabled)?;
+            }
+            ManifestChange::SetInbox { bucket, enabled, limits } => {
+                self.set_bucket_inbox(bucket, *enabled, limits.clone())?;
+            }
+            ManifestChange::SetPrecompressed { bucket, enabled } => {
+                self.set_bucket_precompressed(bucket, *enabled)?;
+            }
+            ManifestChange::SetLogging { bucket, logging } => {
+                self.set_bucket_logging(bucket, Some(logging.clone()))?;
+            }
+            ManifestChange::SetOverwritePolicy { bucket, policy } => {
+                self.set_bucket_overwrite_policy(bucket, *policy)?;
+            }
+            ManifestChange::SetMaxObjects { bucket, limit } => {
+                self.set_bucket_max_objects(bucket, *limit)?;
+            }
+            ManifestChange::UploadSeed { bucket, key, path, .. } => {
+                let data = fs::read(path)?;
+                let file_name = path.file_name().and_then(|n| n.to_str());
+                self.put_object(bucket, key, &data, PutOptions { original_filename: file_name, ..Default::default() })?;
+            }
+            ManifestChange::DeleteBucket { bucket, .. } => {
+                let mut keys = Vec::new();
+                self.visit_objects(bucket, "", |meta| {
+                    keys.push(meta.key.clone());
+                    Ok(())
+                })?;
+                self.delete_objects(bucket, &keys)?;
+                self.delete_bucket(bucket, false)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use crate::error::AppError;
+
+/// Longest metadata name shown in an error, in characters
+const SHOWN_NAME_CHARS: usize = 64;
+
+/// Bounds on the user metadata (`x-amz-meta-*`) stored with an object, in
+/// UTF-8 bytes. Metadata already stored keeps loading when the limits
+/// shrink; `freebucket doctor` lists objects over them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataLimits {
+    /// All names and values together; S3 allows 2 KB
+    pub max_total: usize,
+    /// One name, without the `x-amz-meta-` prefix
+    pub max_name: usize,
+    /// One value
+    pub max_value: usize,
+}
+
+impl Default for MetadataLimits {
+    fn default() -> Self {
+        MetadataLimits {
+            max_total: 2048,
+            max_name: 128,
+            max_value: 2048,
+        }
+    }
+}
+
+/// Bytes of user metadata as S3 counts them: every name and value, without
+/// the `x-amz-meta-` prefix
+pub fn metadata_size(metadata: &HashMap<String, String>) -> usize {
+    metadata.iter().map(|(name, value)| name.len() + value.len()).sum()
+}
+
+fn shown_name(name: &str) -> String {
+    if name.chars().count() > SHOWN_NAME_CHARS {
+        format!("{}…", name.chars().take(SHOWN_NAME_CHARS).collect::<String>())
+    } else {
+        name.to_string()
+    }
+}
+
+impl MetadataLimits {
+    /// Refuse metadata over any limit, naming the first one it breaks. Names
+    /// are checked in order, so the same metadata always gets the same error.
+    pub fn check(&self, metadata: &HashMap<String, String>) -> Result<(), AppError> {
+        let mut entries: Vec<_> = metadata.iter().collect();
+        entries.sort();
+        for (name, value) in entries {
+            if name.len() > self.max_name {
+                return Err(AppError::MetadataTooLarge {
+                    what: format!("metadata name '{}'", shown_name(name)),
+                    size: name.len(),
+                    limit: self.max_name,
+                });
+            }
+            if value.len() > self.max_value {
+                return Err(AppError::MetadataTooLarge {
+                    what: format!("value of metadata '{}'", shown_name(name)),
+                    size: value.len(),
+                    limit: self.max_value,
+                });
+            }
+        }
+        let total = metadata_size(metadata);
+        if total > self.max_total {
+            return Err(AppError::MetadataTooLarge {
+                what: "user metadata".to_string(),
+                size: total,
+                limit: self.max_total,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(entries: &[(&str, usize)]) -> HashMap<String, String> {
+        entries.iter().map(|(name, len)| (name.to_string(), "v".repeat(*len))).collect()
+    }
+
+    fn broken(limits: &MetadataLimits, entries: &[(&str, usize)]) -> (String, usize, usize) {
+        match limits.check(&metadata(entries)) {
+            Err(AppError::MetadataTooLarge { what, size, limit }) => (what, size, limit),
+            other => panic!("expected MetadataTooLarge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn limits_are_inclusive_and_errors_carry_the_size() {
+        let limits = MetadataLimits::default();
+        assert!(limits.check(&metadata(&[("a", 1023), ("b", 1023)])).is_ok());
+        assert_eq!(
+            broken(&limits, &[("a", 1023), ("b", 1024)]),
+            ("user metadata".to_string(), 2049, 2048)
+        );
+
+        let long_name = "n".repeat(128);
+        assert!(limits.check(&metadata(&[(&long_name, 1)])).is_ok());
+        let too_long = "n".repeat(129);
+        let (what, size, limit) = broken(&limits, &[(&too_long, 1)]);
+        assert_eq!((size, limit), (129, 128));
+        assert!(what.ends_with("…'"), "names are shortened in the error: {}", what);
+
+        let limits = MetadataLimits {
+            max_value: 10,
+            ..limits
+        };
+        assert!(limits.check(&metadata(&[("note", 10)])).is_ok());
+        assert_eq!(
+            broken(&limits, &[("note", 11)]),
+            ("value of metadata 'note'".to_string(), 11, 10)
+        );
+    }
+
+    #[test]
+    fn the_same_metadata_always_breaks_the_same_limit() {
+        let limits = MetadataLimits {
+            max_total: 2048,
+            max_name: 128,
+            max_value: 4,
+        };
+        let entries: Vec<(String, usize)> = (0..20).map(|i| (format!("k{:02}", i), 5)).collect();
+        let entries: Vec<(&str, usize)> = entries.iter().map(|(n, l)| (n.as_str(), *l)).collect();
+        for _ in 0..10 {
+            assert_eq!(broken(&limits, &entries).0, "value of metadata 'k00'");
+        }
+        assert_eq!(metadata_size(&metadata(&entries)), 20 * 8);
+    }
+}
@@ -0,0 +1,582 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+
+use super::checksum::{self, Checksummer};
+use super::sparse::SparseWriter;
+use super::{ChecksumRequest, EventKind, PutOptions, StorageEngine};
+use crate::error::AppError;
+use crate::models::{
+    ChecksumAlgorithm, ChecksumType, CompletedPart, MultipartUpload, ObjectChecksum, ObjectMeta,
+    ObjectPart, PartInfo,
+};
+
+const MAX_PART_NUMBER: u32 = 10_000;
+
+impl StorageEngine {
+    fn upload_dir(&self, bucket: &str, upload_id: &str) -> PathBuf {
+        self.bucket_path(bucket).join(".uploads").join(upload_id)
+    }
+
+    fn part_path(&self, bucket: &str, upload_id: &str, part_number: u32) -> PathBuf {
+        self.upload_dir(bucket, upload_id)
+            .join(format!("part-{:05}", part_number))
+    }
+
+    /// Record a finished part next to its data so listing and completion
+    /// don't have to re-hash part files
+    fn save_part_info(&self, bucket: &str, upload_id: &str, part: &PartInfo) -> Result<(), AppError> {
+        let path = self
+            .part_path(bucket, upload_id, part.part_number)
+            .with_extension("json");
+        let json = serde_json::to_string_pretty(part).unwrap();
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    fn validate_part_number(part_number: u32) -> Result<(), AppError> {
+        if part_number == 0 || part_number > MAX_PART_NUMBER {
+            return Err(AppError::InvalidPart(format!(
+                "Part number must be between 1 and {}",
+                MAX_PART_NUMBER
+            )));
+        }
+        Ok(())
+    }
+
+    /// Load an upload's manifest, checking that it belongs to the given key
+    fn load_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<MultipartUpload, AppError> {
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+
+        // Upload IDs are UUIDs; reject anything that could escape the uploads dir
+        if upload_id.is_empty() || !upload_id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+            return Err(AppError::NoSuchUpload(upload_id.to_string()));
+        }
+
+        let manifest_path = self.upload_dir(bucket, upload_id).join("upload.json");
+        if !manifest_path.exists() {
+            return Err(AppError::NoSuchUpload(upload_id.to_string()));
+        }
+        let json = fs::read_to_string(&manifest_path)?;
+        let upload: MultipartUpload = serde_json::from_str(&json)
+            .map_err(|e| AppError::StorageError(format!("Corrupt upload manifest: {}", e)))?;
+        if upload.key != self.normalize_key(key) {
+            return Err(AppError::NoSuchUpload(upload_id.to_string()));
+        }
+        Ok(upload)
+    }
+
+    /// Start an upload whose object gets `options` on completion. The
+    /// options' checksum doesn't apply: parts bring their own, combined as
+    /// `checksum_algorithm` and `checksum_type` say.
+    pub fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: PutOptions,
+        checksum_algorithm: Option<ChecksumAlgorithm>,
+        checksum_type: Option<ChecksumType>,
+    ) -> Result<MultipartUpload, AppError> {
+        let PutOptions {
+            content_type,
+            metadata,
+            checksum: _,
+            original_filename,
+            website_redirect_location,
+            source_mtime,
+        } = options;
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+
+        self.check_new_key(key)?;
+        let key = &*self.normalize_key(key);
+        // Checked again on completion; failing now spares uploading the parts
+        self.check_new_object(bucket, key)?;
+        self.metadata_limits.check(&metadata)?;
+
+        // Composite is the default; only CRCs can produce a full-object checksum
+        let checksum_type = match (checksum_algorithm, checksum_type) {
+            (None, None) => None,
+            (None, Some(_)) => {
+                return Err(AppError::InvalidRequest(
+                    "x-amz-checksum-type requires x-amz-checksum-algorithm".to_string(),
+                ))
+            }
+            (Some(algorithm), Some(ChecksumType::FullObject))
+                if !algorithm.supports_full_object_multipart() =>
+            {
+                return Err(AppError::InvalidRequest(format!(
+                    "The FULL_OBJECT checksum type is not supported for {}",
+                    algorithm.name()
+                )))
+            }
+            (Some(_), checksum_type) => Some(checksum_type.unwrap_or(ChecksumType::Composite)),
+        };
+
+        let upload = MultipartUpload {
+            upload_id: self.new_id().to_string(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            content_type: content_type.map(|s| s.to_string()),
+            metadata,
+            initiated: self.now(),
+            checksum_algorithm,
+            checksum_type,
+            original_filename: original_filename.map(str::to_string),
+            website_redirect_location: website_redirect_location.map(str::to_string),
+            source_mtime,
+        };
+
+        let dir = self.upload_dir(bucket, &upload.upload_id);
+        fs::create_dir_all(&dir)?;
+        let json = serde_json::to_string_pretty(&upload).unwrap();
+        fs::write(dir.join("upload.json"), json)?;
+
+        tracing::info!(
+            "Created multipart upload {} for {}/{}",
+            upload.upload_id,
+            bucket,
+            key
+        );
+        Ok(upload)
+    }
+
+    pub fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: &[u8],
+        checksum: Option<&ChecksumRequest>,
+    ) -> Result<PartInfo, AppError> {
+        Self::validate_part_number(part_number)?;
+        let upload = self.load_upload(bucket, key, upload_id)?;
+
+        let algorithm = match (upload.checksum_algorithm, checksum) {
+            (Some(expected), Some(request)) if request.algorithm != expected => {
+                return Err(AppError::InvalidRequest(format!(
+                    "Part checksum uses {} but the upload was created with {}",
+                    request.algorithm.name(),
+                    expected.name()
+                )))
+            }
+            (Some(expected), _) => Some(expected),
+            (None, request) => request.map(|r| r.algorithm),
+        };
+        let part_checksum = algorithm.map(|a| checksum::checksum_of(a, data));
+        if let (Some(request), Some(value)) = (checksum, &part_checksum) {
+            checksum::verify_checksum(request, value)?;
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        let etag = hex::encode(hasher.finalize());
+
+        let mut file = SparseWriter::new(fs::File::create(self.part_path(bucket, upload_id, part_number))?);
+        file.write_all(data)?;
+        file.finish()?;
+
+        let part = PartInfo {
+            part_number,
+            etag,
+            size: data.len() as u64,
+            last_modified: self.now(),
+            checksum: part_checksum,
+        };
+        self.save_part_info(bucket, upload_id, &part)?;
+        Ok(part)
+    }
+
+    /// Copy a byte range of an existing object into a part file (UploadPartCopy).
+    /// `range` is an inclusive `(first, last)` byte pair; `None` copies the whole source.
+    #[allow(clippy::too_many_arguments)]
+    pub fn upload_part_copy(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        src_bucket: &str,
+        src_key: &str,
+        range: Option<(u64, u64)>,
+    ) -> Result<PartInfo, AppError> {
+        Self::validate_part_number(part_number)?;
+        let upload = self.load_upload(bucket, key, upload_id)?;
+
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(src_bucket) {
+                return Err(AppError::BucketNotFound(src_bucket.to_string()));
+            }
+        }
+
+        let src_path = self.object_path(src_bucket, src_key);
+        if !src_path.is_file() {
+            return Err(AppError::ObjectNotFound {
+                bucket: src_bucket.to_string(),
+                key: src_key.to_string(),
+            });
+        }
+
+        let src_size = fs::metadata(&src_path)?.len();
+        let (start, len) = match range {
+            Some((first, last)) => {
+                if first > last || last >= src_size {
+                    return Err(AppError::InvalidRange(format!(
+                        "bytes={}-{} is outside of source object size {}",
+                        first, last, src_size
+                    )));
+                }
+                (first, last - first + 1)
+            }
+            None => (0, src_size),
+        };
+
+        let mut src = fs::File::open(&src_path)?;
+        src.seek(SeekFrom::Start(start))?;
+        let mut reader = src.take(len);
+
+        let mut part = SparseWriter::new(fs::File::create(self.part_path(bucket, upload_id, part_number))?);
+        let mut hasher = Sha256::new();
+        let mut checksummer = upload.checksum_algorithm.map(Checksummer::new);
+        let mut buf = vec![0u8; self.write_buffer_size];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            if let Some(c) = checksummer.as_mut() {
+                c.update(&buf[..n]);
+            }
+            part.write_all(&buf[..n])?;
+        }
+        part.finish()?;
+
+        tracing::info!(
+            "Copied {}/{} bytes={}-{} into part {} of upload {}",
+            src_bucket,
+            src_key,
+            start,
+            start + len.saturating_sub(1),
+            part_number,
+            upload_id
+        );
+
+        let part = PartInfo {
+            part_number,
+            etag: hex::encode(hasher.finalize()),
+            size: len,
+            last_modified: self.now(),
+            checksum: checksummer.map(Checksummer::finalize),
+        };
+        self.save_part_info(bucket, upload_id, &part)?;
+        Ok(part)
+    }
+
+    pub fn list_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<PartInfo>, AppError> {
+        self.load_upload(bucket, key, upload_id)?;
+
+        let mut parts = Vec::new();
+        for entry in fs::read_dir(self.upload_dir(bucket, upload_id))?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !(name.starts_with("part-") && name.ends_with(".json")) {
+                continue;
+            }
+            let json = fs::read_to_string(entry.path())?;
+            let part: PartInfo = serde_json::from_str(&json)
+                .map_err(|e| AppError::StorageError(format!("Corrupt part info: {}", e)))?;
+            parts.push(part);
+        }
+        parts.sort_by_key(|p| p.part_number);
+        Ok(parts)
+    }
+
+    /// Assemble the listed parts into the final object. Uploaded and copied
+    /// parts are stored identically, so they are concatenated the same way.
+    /// `checksum` is the client's expected value for the whole object.
+    pub fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: &[CompletedPart],
+        checksum: Option<&ChecksumRequest>,
+    ) -> Result<ObjectMeta, AppError> {
+        let _op = self.time_op("complete_multipart", bucket, Some(key));
+        let key = &*self.normalize_key(key);
+        let upload = self.load_upload(bucket, key, upload_id)?;
+
+        let available: HashMap<u32, PartInfo> = self
+            .list_parts(bucket, key, upload_id)?
+            .into_iter()
+            .map(|p| (p.part_number, p))
+            .collect();
+
+        // An empty part list means "use every uploaded part in order"
+        let selected: Vec<u32> = if parts.is_empty() {
+            let mut all: Vec<u32> = available.keys().copied().collect();
+            all.sort_unstable();
+            all
+        } else {
+            let mut last = 0;
+            for part in parts {
+                if part.part_number <= last {
+                    return Err(AppError::InvalidPart(
+                        "Parts must be listed in ascending order".to_string(),
+                    ));
+                }
+                last = part.part_number;
+
+                let stored = available.get(&part.part_number).ok_or_else(|| {
+                    AppError::InvalidPart(format!("Part {} was not uploaded", part.part_number))
+                })?;
+                if !part.etag.is_empty() && part.etag.trim_matches('"') != stored.etag {
+                    return Err(AppError::InvalidPart(format!(
+                        "ETag mismatch for part {}",
+                        part.part_number
+                    )));
+                }
+            }
+            parts.iter().map(|p| p.part_number).collect()
+        };
+
+        if selected.is_empty() {
+            return Err(AppError::InvalidPart(
+                "At least one part is required".to_string(),
+            ));
+        }
+
+        // Composite checksums are derived from the part checksums; full-object
+        // ones are computed while the parts are concatenated below
+        let mut composite = None;
+        let mut checksummer = None;
+        if let (Some(algorithm), Some(checksum_type)) = (upload.checksum_algorithm, upload.checksum_type) {
+            match checksum_type {
+                ChecksumType::Composite => {
+                    let part_checksums = selected
+                        .iter()
+                        .map(|n| {
+                            available[n].checksum.clone().ok_or_else(|| {
+                                AppError::InvalidPart(format!("Part {} has no {} checksum", n, algorithm.name()))
+                            })
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    composite = Some(checksum::composite_checksum(algorithm, &part_checksums)?);
+                }
+                ChecksumType::FullObject => checksummer = Some(Checksummer::new(algorithm)),
+            }
+        }
+
+        let _lock = self.lock_key(bucket, key);
+        self.check_overwrite(bucket, key)?;
+        self.check_new_object(bucket, key)?;
+        let tmp = self.temp_file(bucket)?;
+        let mut out = SparseWriter::new(fs::File::create(tmp.path())?);
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let mut buf = vec![0u8; self.write_buffer_size];
+        for part_number in &selected {
+            let mut part = fs::File::open(self.part_path(bucket, upload_id, *part_number))?;
+            loop {
+                let n = part.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                if let Some(c) = checksummer.as_mut() {
+                    c.update(&buf[..n]);
+                }
+                out.write_all(&buf[..n])?;
+                size += n as u64;
+            }
+        }
+        out.finish()?;
+
+        let object_checksum = match (upload.checksum_algorithm, upload.checksum_type) {
+            (Some(algorithm), Some(checksum_type)) => Some(ObjectChecksum {
+                algorithm,
+                checksum_type,
+                value: composite
+                    .or_else(|| checksummer.map(Checksummer::finalize))
+                    .unwrap_or_default(),
+            }),
+            _ => None,
+        };
+        if let (Some(request), Some(stored)) = (checksum, &object_checksum) {
+            checksum::verify_checksum(request, &stored.value)?;
+        }
+
+        let content_type = upload.content_type.clone().unwrap_or_else(|| {
+            mime_guess::from_path(key)
+                .first_or_octet_stream()
+                .to_string()
+        });
+
+        let meta = ObjectMeta {
+            key: key.to_string(),
+            bucket: bucket.to_string(),
+            size,
+            content_type,
+            etag: hex::encode(hasher.finalize()),
+            last_modified: self.now(),
+            metadata: upload.metadata,
+            parts: selected
+                .iter()
+                .map(|n| ObjectPart {
+                    part_number: *n,
+                    size: available[n].size,
+                    etag: available[n].etag.clone(),
+                    checksum: available[n].checksum.clone(),
+                })
+                .collect(),
+            checksum: object_checksum,
+            original_filename: upload.original_filename,
+            website_redirect_location: upload.website_redirect_location,
+            source_mtime: upload.source_mtime,
+            etag_provisional: false,
+            origin: None,
+            allocated_size: None,
+        };
+        if self.is_quarantined(bucket) {
+            let meta = self.quarantine_upload(tmp, meta)?;
+            fs::remove_dir_all(self.upload_dir(bucket, upload_id))?;
+            return Ok(meta);
+        }
+        tmp.commit(&self.object_path(bucket, key))?;
+        self.stamp_source_mtime(&meta);
+
+        self.save_object_meta(&meta)?;
+
+        fs::remove_dir_all(self.upload_dir(bucket, upload_id))?;
+        self.update_bucket_stats(bucket)?;
+        self.object_created(EventKind::CompleteMultipartUpload, &meta);
+
+        tracing::info!(
+            "Completed multipart upload {} → {}/{} ({} parts, {} bytes)",
+            upload_id,
+            bucket,
+            key,
+            selected.len(),
+            size
+        );
+        Ok(meta)
+    }
+
+    pub fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), AppError> {
+        self.load_upload(bucket, key, upload_id)?;
+        fs::remove_dir_all(self.upload_dir(bucket, upload_id))?;
+        tracing::info!("Aborted multipart upload {} for {}/{}", upload_id, bucket, key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::AppError;
+    use crate::models::CompletedPart;
+    use crate::storage::{testing, PutOptions};
+
+    #[test]
+    fn assembles_an_object_from_ranges_of_two_sources() {
+        let (_dir, engine) = testing::engine();
+        engine.create_bucket("chunks", "local").unwrap();
+        let first: Vec<u8> = (0..=255).collect();
+        let second = b"the second source object".to_vec();
+        for (key, data) in [("one", &first), ("two", &second)] {
+            engine.put_object("chunks", key, data, PutOptions::default()).unwrap();
+        }
+
+        let upload = engine
+            .create_multipart_upload("chunks", "archive", PutOptions::default(), None, None)
+            .unwrap();
+        let id = &upload.upload_id;
+        let p1 = engine.upload_part_copy("chunks", "archive", id, 1, "chunks", "one", Some((10, 19))).unwrap();
+        let p2 = engine.upload_part_copy("chunks", "archive", id, 2, "chunks", "two", Some((4, 9))).unwrap();
+        let p3 = engine.upload_part("chunks", "archive", id, 3, b"+uploaded", None).unwrap();
+        let p4 = engine.upload_part_copy("chunks", "archive", id, 4, "chunks", "two", None).unwrap();
+        assert_eq!((p1.size, p2.size, p4.size), (10, 6, second.len() as u64));
+
+        let parts: Vec<CompletedPart> = [p1, p2, p3, p4]
+            .into_iter()
+            .map(|p| CompletedPart { part_number: p.part_number, etag: p.etag })
+            .collect();
+        let meta = engine.complete_multipart_upload("chunks", "archive", id, &parts, None).unwrap();
+
+        let mut expected = first[10..20].to_vec();
+        expected.extend_from_slice(&second[4..10]);
+        expected.extend_from_slice(b"+uploaded");
+        expected.extend_from_slice(&second);
+        assert_eq!(meta.size, expected.len() as u64);
+        assert_eq!(engine.get_object("chunks", "archive").unwrap().1, expected);
+    }
+
+    #[test]
+    fn copy_errors() {
+        let (_dir, engine) = testing::engine();
+        engine.create_bucket("chunks", "local").unwrap();
+        engine.put_object("chunks", "one", b"0123456789", PutOptions::default()).unwrap();
+        let id = engine
+            .create_multipart_upload("chunks", "archive", PutOptions::default(), None, None)
+            .unwrap()
+            .upload_id;
+
+        for range in [(5, 10), (6, 5)] {
+            let err = engine.upload_part_copy("chunks", "archive", &id, 1, "chunks", "one", Some(range)).unwrap_err();
+            assert!(matches!(err, AppError::InvalidRange(_)), "{:?}", err);
+        }
+        let err = engine.upload_part_copy("chunks", "archive", &id, 1, "chunks", "missing", None).unwrap_err();
+        assert_eq!(err.parts().1, "NoSuchKey");
+        let err = engine.upload_part_copy("chunks", "archive", &id, 1, "nowhere", "one", None).unwrap_err();
+        assert_eq!(err.parts().1, "NoSuchBucket");
+    }
+
+    #[test]
+    fn completed_uploads_keep_the_options_given_at_creation() {
+        let (_dir, engine) = testing::engine();
+        engine.create_bucket("site", "local").unwrap();
+        let options = PutOptions {
+            content_type: Some("text/html"),
+            original_filename: Some("Old Page.html"),
+            website_redirect_location: Some("/new-page.html"),
+            ..Default::default()
+        };
+        let id = engine.create_multipart_upload("site", "old-page.html", options, None, None).unwrap().upload_id;
+        let part = engine.upload_part("site", "old-page.html", &id, 1, b"<p>moved</p>", None).unwrap();
+        let parts = [CompletedPart { part_number: 1, etag: part.etag }];
+        engine.complete_multipart_upload("site", "old-page.html", &id, &parts, None).unwrap();
+
+        let meta = engine.get_object_meta("site", "old-page.html").unwrap();
+        assert_eq!(meta.content_type, "text/html");
+        assert_eq!(meta.original_filename.as_deref(), Some("Old Page.html"));
+        assert_eq!(meta.website_redirect_location.as_deref(), Some("/new-page.html"));
+    }
+}
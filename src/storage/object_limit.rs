@@ -0,0 +1,66 @@
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{Bucket, BulkOperation, BulkRequest};
+
+impl StorageEngine {
+    /// Cap how many objects a bucket holds; `None` removes the cap. A bucket
+    /// already past a new cap keeps its objects but takes no new keys until
+    /// deletes bring it under.
+    pub fn set_bucket_max_objects(&self, bucket: &str, max_objects: Option<u64>) -> Result<Bucket, AppError> {
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.max_objects = max_objects;
+        }
+        self.persist_bucket_meta(bucket)?;
+        match max_objects {
+            Some(limit) => tracing::info!("Bucket {} limited to {} objects", bucket, limit),
+            None => tracing::info!("Object limit of bucket {} removed", bucket),
+        }
+        self.get_bucket(bucket)
+    }
+
+    /// Refuse to add `adding` objects to a bucket they would take past its
+    /// limit. Counts are recomputed after every write and delete, so a
+    /// delete makes room straight away.
+    pub(super) fn check_object_limit(&self, bucket: &str, adding: u64) -> Result<(), AppError> {
+        let buckets = self.buckets.read().unwrap();
+        let Some(info) = buckets.get(bucket) else {
+            return Ok(());
+        };
+        match info.max_objects {
+            Some(limit) if info.object_count.saturating_add(adding) > limit => Err(AppError::TooManyObjects {
+                bucket: bucket.to_string(),
+                count: info.object_count,
+                limit,
+                adding,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// `check_object_limit` for a write to one key, which only adds an
+    /// object when the key is new
+    pub(super) fn check_new_object(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        if self.object_path(bucket, key).is_file() {
+            return Ok(());
+        }
+        self.check_object_limit(bucket, 1)
+    }
+
+    /// Check a whole bulk plan against the destination's limit before any
+    /// of it runs, since stats are only recounted once it has finished
+    pub(super) fn check_bulk_object_limit(&self, req: &BulkRequest, plan: &[(String, String)]) -> Result<(), AppError> {
+        // A move within a bucket takes out an object for each it adds
+        if req.operation == BulkOperation::Move && req.source_bucket == req.destination_bucket {
+            return Ok(());
+        }
+        let adding = plan
+            .iter()
+            .filter(|(_, dst)| !self.object_path(&req.destination_bucket, dst).is_file())
+            .count();
+        self.check_object_limit(&req.destination_bucket, adding as u64)
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::StorageEngine;
+use crate::models::SlowOperation;
+
+/// Upper bounds of the latency histogram buckets, in seconds
+pub const OP_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0, 30.0];
+/// Slow operations kept for `/api/admin/slow-ops`
+const SLOW_OP_HISTORY: usize = 100;
+
+/// Latency distribution of one storage operation
+#[derive(Debug, Clone, Default)]
+pub struct OpHistogram {
+    /// Operations at or under each bound of `OP_BUCKETS`, not cumulative
+    pub buckets: [u64; OP_BUCKETS.len()],
+    pub count: u64,
+    pub sum_seconds: f64,
+}
+
+/// Per-operation histograms and the most recent slow operations
+#[derive(Default)]
+pub(super) struct OpTimings {
+    histograms: Mutex<BTreeMap<&'static str, OpHistogram>>,
+    slow: Mutex<VecDeque<SlowOperation>>,
+}
+
+/// Times one storage operation from creation until dropped, inside a
+/// `storage` tracing span. Early returns through `?` are timed too.
+pub(super) struct OpTimer<'a> {
+    engine: &'a StorageEngine,
+    operation: &'static str,
+    bucket: &'a str,
+    key: Option<&'a str>,
+    detail: Option<String>,
+    started: Instant,
+    span: tracing::span::EnteredSpan,
+}
+
+impl OpTimer<'_> {
+    /// Extra arguments, such as a listing's prefix, reported if the
+    /// operation turns out slow
+    pub(super) fn detail(&mut self, detail: impl FnOnce() -> String) {
+        self.detail = Some(detail());
+    }
+}
+
+impl Drop for OpTimer<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        // Fields are only formatted when a subscriber wants the span
+        if !self.span.is_disabled() {
+            self.span.record("duration_ms", elapsed.as_secs_f64() * 1000.0);
+        }
+        self.engine.record_op_time(self, elapsed);
+    }
+}
+
+impl StorageEngine {
+    /// Start timing an operation; the returned guard records it when dropped
+    pub(super) fn time_op<'a>(&'a self, operation: &'static str, bucket: &'a str, key: Option<&'a str>) -> OpTimer<'a> {
+        let span = tracing::debug_span!(
+            "storage",
+            op = operation,
+            bucket,
+            key,
+            duration_ms = tracing::field::Empty
+        )
+        .entered();
+        OpTimer {
+            engine: self,
+            operation,
+            bucket,
+            key,
+            detail: None,
+            started: Instant::now(),
+            span,
+        }
+    }
+
+    fn record_op_time(&self, timer: &OpTimer, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        {
+            let mut histograms = self.op_timings.histograms.lock().unwrap();
+            let histogram = histograms.entry(timer.operation).or_default();
+            if let Some(i) = OP_BUCKETS.iter().position(|&bound| seconds <= bound) {
+                histogram.buckets[i] += 1;
+            }
+            histogram.count += 1;
+            histogram.sum_seconds += seconds;
+        }
+
+        if self.slow_op_threshold.is_zero() || elapsed < self.slow_op_threshold {
+            return;
+        }
+        let duration_ms = elapsed.as_millis() as u64;
+        tracing::warn!(
+            "Slow storage operation: {} on {}{}{} took {} ms",
+            timer.operation,
+            timer.bucket,
+            timer.key.map(|k| format!("/{}", k)).unwrap_or_default(),
+            timer.detail.as_ref().map(|d| format!(" ({})", d)).unwrap_or_default(),
+            duration_ms
+        );
+        let mut slow = self.op_timings.slow.lock().unwrap();
+        if slow.len() == SLOW_OP_HISTORY {
+            slow.pop_front();
+        }
+        slow.push_back(SlowOperation {
+            operation: timer.operation.to_string(),
+            bucket: timer.bucket.to_string(),
+            key: timer.key.map(str::to_string),
+            detail: timer.detail.clone(),
+            duration_ms,
+            finished_at: self.now(),
+        });
+    }
+
+    /// Latency histograms of every operation timed so far, by name
+    pub fn op_histograms(&self) -> Vec<(&'static str, OpHistogram)> {
+        let histograms = self.op_timings.histograms.lock().unwrap();
+        histograms.iter().map(|(op, h)| (*op, h.clone())).collect()
+    }
+
+    /// The last slow operations, newest first
+    pub fn slow_ops(&self) -> Vec<SlowOperation> {
+        self.op_timings.slow.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
@@ -0,0 +1,104 @@
+//! Buckets that cache a remote S3 bucket, their "origin". The requests to
+//! the origin are made by `crate::origin`; this side keeps the settings and
+//! records which local objects are copies of the origin's, so only those
+//! are ever revalidated or dropped on its account. Any local write replaces
+//! the object's metadata and with it that record.
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{Bucket, ObjectMeta, ObjectOrigin, OriginConfig};
+
+impl StorageEngine {
+    /// Set or remove the origin a bucket reads through to
+    pub fn set_bucket_origin(&self, bucket: &str, origin: Option<OriginConfig>) -> Result<Bucket, AppError> {
+        if let Some(config) = &origin {
+            if !config.endpoint.starts_with("http://") && !config.endpoint.starts_with("https://") {
+                return Err(AppError::InvalidRequest(
+                    "The origin endpoint must be an http:// or https:// URL".to_string(),
+                ));
+            }
+            if config.bucket.is_empty() {
+                return Err(AppError::InvalidRequest("The origin needs a bucket".to_string()));
+            }
+            if config.access_key_id.is_some() != config.secret_access_key.is_some() {
+                return Err(AppError::InvalidRequest(
+                    "Set both access_key_id and secret_access_key, or neither".to_string(),
+                ));
+            }
+        }
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.origin = origin;
+        }
+        // Persists the settings along with the stats
+        self.persist_bucket_meta(bucket)?;
+        let info = self.get_bucket(bucket)?;
+        match &info.origin {
+            Some(config) => tracing::info!(
+                "Bucket {} reads through to {}/{}/{}",
+                bucket,
+                config.endpoint.trim_end_matches('/'),
+                config.bucket,
+                config.prefix
+            ),
+            None => tracing::info!("Bucket {} no longer has an origin", bucket),
+        }
+        Ok(info)
+    }
+
+    /// Record that the object `key` holds, written as `local_etag`, is the
+    /// origin's version `origin_etag`. Nothing changes if the object was
+    /// replaced in the meantime.
+    pub fn mark_origin_copy(
+        &self,
+        bucket: &str,
+        key: &str,
+        local_etag: &str,
+        origin_etag: &str,
+    ) -> Result<Option<ObjectMeta>, AppError> {
+        let _lock = self.lock_key(bucket, key);
+        let mut meta = self.get_object_meta(bucket, key)?;
+        if meta.etag != local_etag {
+            return Ok(None);
+        }
+        meta.origin = Some(ObjectOrigin {
+            etag: origin_etag.to_string(),
+            checked_at: self.now(),
+        });
+        self.write_meta_file(&meta)?;
+        Ok(Some(meta))
+    }
+
+    /// Note that the origin still has the cached copy's version
+    pub fn origin_copy_current(&self, bucket: &str, key: &str, origin_etag: &str) -> Result<(), AppError> {
+        let _lock = self.lock_key(bucket, key);
+        let mut meta = self.get_object_meta(bucket, key)?;
+        if let Some(origin) = meta.origin.as_mut().filter(|o| o.etag == origin_etag) {
+            origin.checked_at = self.now();
+            self.write_meta_file(&meta)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a cached copy the origin no longer has. An object written
+    /// here since is kept.
+    pub fn drop_origin_copy(&self, bucket: &str, key: &str, origin_etag: &str) -> Result<(), AppError> {
+        let meta = self.get_object_meta(bucket, key)?;
+        if matches!(&meta.origin, Some(origin) if origin.etag == origin_etag) {
+            tracing::info!("{}/{} is gone from the origin; dropping the cached copy", bucket, key);
+            self.delete_object(bucket, key)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a cached copy is due to be checked against the origin
+    pub fn origin_check_due(&self, meta: &ObjectMeta, config: &OriginConfig) -> bool {
+        let (Some(origin), Some(secs)) = (&meta.origin, config.revalidate_secs) else {
+            return false;
+        };
+        self.now() - origin.checked_at >= chrono::Duration::seconds(secs.min(i64::MAX as u64) as i64)
+    }
+}
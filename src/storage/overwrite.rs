@@ -0,0 +1,49 @@
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{Bucket, OverwritePolicy};
+
+impl StorageEngine {
+    /// Set what writes to an existing key do in a bucket. `version` keeps
+    /// every replaced object, so it needs versioning turned on first.
+    pub fn set_bucket_overwrite_policy(&self, bucket: &str, policy: OverwritePolicy) -> Result<Bucket, AppError> {
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            if policy == OverwritePolicy::Version && !info.versioning {
+                return Err(AppError::InvalidRequest(format!(
+                    "The version overwrite policy needs versioning, which is off for bucket '{}'",
+                    bucket
+                )));
+            }
+            info.overwrite_policy = policy;
+        }
+        // Persists the policy along with the stats
+        self.persist_bucket_meta(bucket)?;
+        tracing::info!("Overwrite policy of bucket {} set to {}", bucket, policy.name());
+        self.get_bucket(bucket)
+    }
+
+    /// Refuse a write that would replace an object in a bucket whose policy
+    /// is `deny`, or one spelled the same but for case on a filesystem that
+    /// would store both in one file. Callers hold the key's lock from this
+    /// check until the new payload is in place, so of two racing creates
+    /// only one succeeds.
+    pub(super) fn check_overwrite(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        self.check_key_case(bucket, key)?;
+        let policy = self
+            .buckets
+            .read()
+            .unwrap()
+            .get(bucket)
+            .map_or(OverwritePolicy::Allow, |b| b.overwrite_policy);
+        if policy == OverwritePolicy::Deny && self.object_path(bucket, key).is_file() {
+            return Err(AppError::ObjectAlreadyExists {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
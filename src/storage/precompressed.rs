@@ -0,0 +1,77 @@
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{Bucket, ObjectMeta};
+
+/// Encodings a pre-compressed sibling object can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Brotli,
+    Gzip,
+}
+
+impl ContentEncoding {
+    /// Server preference when the client rates encodings equally
+    pub const ALL: [ContentEncoding; 2] = [ContentEncoding::Brotli, ContentEncoding::Gzip];
+
+    /// Token used in `Accept-Encoding` and `Content-Encoding`
+    pub fn token(self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    /// Suffix of the sibling key holding this encoding
+    fn suffix(self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => ".br",
+            ContentEncoding::Gzip => ".gz",
+        }
+    }
+}
+
+impl StorageEngine {
+    /// Turn serving of `.br`/`.gz` sibling objects on or off for a bucket
+    pub fn set_bucket_precompressed(&self, bucket: &str, enabled: bool) -> Result<Bucket, AppError> {
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.precompressed = enabled;
+        }
+        // Persists the settings along with the stats
+        self.persist_bucket_meta(bucket)?;
+        tracing::info!(
+            "Pre-compressed variants {} for bucket {}",
+            if enabled { "enabled" } else { "disabled" },
+            bucket
+        );
+        self.get_bucket(bucket)
+    }
+
+    /// The first of `accepted` stored as a sibling of `key`, read under the
+    /// original key's name and content type. The ETag stays the sibling's
+    /// own, so each encoding caches separately.
+    pub fn get_precompressed_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        accepted: &[ContentEncoding],
+    ) -> Result<Option<(ObjectMeta, Vec<u8>, ContentEncoding)>, AppError> {
+        for &encoding in accepted {
+            let variant_key = format!("{}{}", key, encoding.suffix());
+            if !self.object_path(bucket, &variant_key).is_file() {
+                continue;
+            }
+            let (mut meta, data) = self.get_object(bucket, &variant_key)?;
+            meta.key = key.to_string();
+            meta.content_type = match self.get_object_meta(bucket, key) {
+                Ok(original) => original.content_type,
+                Err(_) => mime_guess::from_path(key).first_or_octet_stream().to_string(),
+            };
+            return Ok(Some((meta, data, encoding)));
+        }
+        Ok(None)
+    }
+}
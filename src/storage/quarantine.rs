@@ -0,0 +1,559 @@
+//! Uploads to a bucket with a quarantine wait under
+//! `<bucket>/.quarantine/<id>/` until a scan approves them: `payload` holds
+//! the bytes and `entry.json` the object and where its scan stands. Nothing
+//! there shows up in listings or reads. Approval moves the payload into
+//! place as if it had just been written; rejection deletes it and keeps the
+//! entry with the reason.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+
+use super::{EventKind, StorageEngine, TempFile};
+use crate::error::AppError;
+use crate::models::{Bucket, ObjectMeta, PendingStatus, QuarantineConfig, QuarantineStatus, QuarantinedObject};
+
+const PAYLOAD: &str = "payload";
+const ENTRY: &str = "entry.json";
+
+/// Pending upload IDs by bucket and key, and a wake-up for the scanner
+#[derive(Default)]
+pub(super) struct Quarantine {
+    pending: Mutex<HashMap<(String, String), String>>,
+    arrived: Notify,
+}
+
+fn not_found(bucket: &str, id: &str) -> AppError {
+    AppError::QuarantinedUploadNotFound {
+        bucket: bucket.to_string(),
+        id: id.to_string(),
+    }
+}
+
+fn read_entry_file(dir: &Path) -> Option<QuarantinedObject> {
+    let json = fs::read(dir.join(ENTRY)).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+fn write_entry_file(dir: &Path, entry: &QuarantinedObject) -> Result<(), AppError> {
+    let tmp = dir.join("entry.json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(entry).unwrap())?;
+    fs::rename(&tmp, dir.join(ENTRY))?;
+    Ok(())
+}
+
+impl StorageEngine {
+    fn quarantine_dir(&self, bucket: &str) -> PathBuf {
+        self.bucket_path(bucket).join(".quarantine")
+    }
+
+    fn quarantine_entry_dir(&self, bucket: &str, id: &str) -> Result<PathBuf, AppError> {
+        self.get_bucket(bucket)?;
+        // IDs are UUIDs; anything else could point outside the quarantine
+        if uuid::Uuid::parse_str(id).is_err() {
+            return Err(not_found(bucket, id));
+        }
+        Ok(self.quarantine_dir(bucket).join(id))
+    }
+
+    fn read_quarantined(&self, bucket: &str, id: &str) -> Result<(PathBuf, QuarantinedObject), AppError> {
+        let dir = self.quarantine_entry_dir(bucket, id)?;
+        let entry = read_entry_file(&dir).ok_or_else(|| not_found(bucket, id))?;
+        Ok((dir, entry))
+    }
+
+    /// Replace a bucket's quarantine settings; `None` publishes uploads
+    /// directly again. Uploads already held stay until they are decided.
+    pub fn set_bucket_quarantine(&self, bucket: &str, config: Option<QuarantineConfig>) -> Result<Bucket, AppError> {
+        if let Some(config) = &config {
+            if config.command.is_some() && config.webhook.is_some() {
+                return Err(AppError::InvalidRequest(
+                    "Give either a scan command or a scan webhook, not both".to_string(),
+                ));
+            }
+            let web = |url: &&String| url.starts_with("http://") || url.starts_with("https://");
+            if let Some(url) = config.webhook.as_ref().filter(|url| !web(url)) {
+                return Err(AppError::InvalidRequest(format!(
+                    "Scan webhook URL '{}' must start with http:// or https://",
+                    url
+                )));
+            }
+            if config.timeout_secs == 0 {
+                return Err(AppError::InvalidRequest("timeout_secs must be at least 1".to_string()));
+            }
+        }
+        {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            info.quarantine = config;
+        }
+        self.persist_bucket_meta(bucket)?;
+        let info = self.get_bucket(bucket)?;
+        match &info.quarantine {
+            Some(_) => tracing::info!("Uploads to bucket {} are quarantined until scanned", bucket),
+            None => tracing::info!("Quarantine for bucket {} disabled", bucket),
+        }
+        // Uploads waiting for manual approval may now have a scanner
+        self.quarantine.arrived.notify_one();
+        Ok(info)
+    }
+
+    fn quarantine_config(&self, bucket: &str) -> Option<QuarantineConfig> {
+        self.buckets.read().unwrap().get(bucket)?.quarantine.clone()
+    }
+
+    pub(super) fn is_quarantined(&self, bucket: &str) -> bool {
+        self.buckets
+            .read()
+            .unwrap()
+            .get(bucket)
+            .is_some_and(|b| b.quarantine.is_some())
+    }
+
+    /// Error for a read of a key that holds no object. A key whose upload
+    /// is waiting for its scan answers 423 if its bucket asks for that.
+    pub(super) fn missing_object(&self, bucket: &str, key: &str) -> AppError {
+        let pending = self
+            .quarantine
+            .pending
+            .lock()
+            .unwrap()
+            .contains_key(&(bucket.to_string(), key.to_string()));
+        let locked = pending
+            && self
+                .quarantine_config(bucket)
+                .is_some_and(|c| c.pending_status == PendingStatus::Locked);
+        if locked {
+            AppError::ObjectPending {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            }
+        } else {
+            AppError::ObjectNotFound {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            }
+        }
+    }
+
+    /// Refuse writes that would publish content in a quarantined bucket
+    /// without scanning it
+    pub(super) fn check_unscanned_write(&self, bucket: &str, operation: &str) -> Result<(), AppError> {
+        if self.is_quarantined(bucket) {
+            return Err(AppError::InvalidRequest(format!(
+                "Bucket '{}' scans uploads before publishing them; {} is not supported there",
+                bucket, operation
+            )));
+        }
+        Ok(())
+    }
+
+    /// Hold a fully written upload until its scan. The caller holds the
+    /// key's lock. An older upload of the key that is still waiting is
+    /// rejected, so approving it late can't replace newer content.
+    pub(super) fn quarantine_upload(&self, tmp: TempFile<'_>, meta: ObjectMeta) -> Result<ObjectMeta, AppError> {
+        let id = self.new_id().to_string();
+        let dir = self.quarantine_dir(&meta.bucket).join(&id);
+        tmp.commit(&dir.join(PAYLOAD))?;
+        let entry = QuarantinedObject {
+            id: id.clone(),
+            status: QuarantineStatus::Pending,
+            object: meta.clone(),
+            received_at: meta.last_modified,
+            rejected_at: None,
+            reason: None,
+            failed_scans: 0,
+            last_error: None,
+            retry_at: None,
+        };
+        if let Err(e) = write_entry_file(&dir, &entry) {
+            let _ = fs::remove_dir_all(&dir);
+            return Err(e);
+        }
+
+        let previous = self
+            .quarantine
+            .pending
+            .lock()
+            .unwrap()
+            .insert((meta.bucket.clone(), meta.key.clone()), id.clone());
+        if let Some((old_dir, old)) = previous.and_then(|old| {
+            let dir = self.quarantine_dir(&meta.bucket).join(old);
+            read_entry_file(&dir).map(|entry| (dir, entry))
+        }) {
+            self.reject_entry(&old_dir, old, "Replaced by a newer upload before its scan finished")?;
+        }
+        self.quarantine_event(EventKind::QuarantinePending, &meta);
+        self.quarantine.arrived.notify_one();
+        tracing::info!(
+            "Quarantined upload {}/{} ({} bytes) as {}",
+            meta.bucket,
+            meta.key,
+            meta.size,
+            id
+        );
+        Ok(meta)
+    }
+
+    /// Resolves when an upload arrives in a quarantine or a bucket's scan
+    /// settings change
+    pub async fn quarantine_changed(&self) {
+        self.quarantine.arrived.notified().await
+    }
+
+    /// Uploads held in quarantine, oldest first
+    pub fn list_quarantine(
+        &self,
+        bucket: Option<&str>,
+        status: Option<QuarantineStatus>,
+    ) -> Result<Vec<QuarantinedObject>, AppError> {
+        let names = match bucket {
+            Some(name) => vec![self.get_bucket(name)?.name],
+            None => {
+                let buckets = self.buckets.read().unwrap();
+                buckets.keys().filter(|b| !super::is_system_bucket(b)).cloned().collect()
+            }
+        };
+        let mut entries = Vec::new();
+        for name in names {
+            let Ok(dirs) = fs::read_dir(self.quarantine_dir(&name)) else {
+                continue;
+            };
+            entries.extend(
+                dirs.flatten()
+                    .filter_map(|dir| read_entry_file(&dir.path()))
+                    .filter(|entry| status.is_none() || status == Some(entry.status)),
+            );
+        }
+        entries.sort_by_key(|entry| entry.received_at);
+        Ok(entries)
+    }
+
+    /// Pending uploads with a scanner configured and no failed scan to wait
+    /// out, oldest first
+    pub fn due_scans(&self) -> Vec<(QuarantinedObject, QuarantineConfig)> {
+        let now = self.now();
+        let Ok(pending) = self.list_quarantine(None, Some(QuarantineStatus::Pending)) else {
+            return Vec::new();
+        };
+        pending
+            .into_iter()
+            .filter(|entry| entry.retry_at.unwrap_or(now) <= now)
+            .filter_map(|entry| {
+                let config = self.quarantine_config(&entry.object.bucket)?;
+                (config.command.is_some() || config.webhook.is_some()).then_some((entry, config))
+            })
+            .collect()
+    }
+
+    /// Path of a pending upload's payload, for scanners
+    pub fn quarantined_payload(&self, bucket: &str, id: &str) -> Result<PathBuf, AppError> {
+        let (dir, entry) = self.read_quarantined(bucket, id)?;
+        if entry.status != QuarantineStatus::Pending {
+            return Err(not_found(bucket, id));
+        }
+        Ok(dir.join(PAYLOAD))
+    }
+
+    /// Publish a pending upload as if it had just been written. If the key
+    /// has meanwhile received newer content, or an object the bucket won't
+    /// let the upload replace, the upload is rejected instead.
+    pub fn approve_quarantined(&self, bucket: &str, id: &str) -> Result<ObjectMeta, AppError> {
+        let (_, entry) = self.read_quarantined(bucket, id)?;
+        let _lock = self.lock_key(bucket, &entry.object.key);
+        // Read again: it may have been decided while we waited for the lock
+        let (dir, entry) = self.read_quarantined(bucket, id)?;
+        if entry.status != QuarantineStatus::Pending {
+            return Err(AppError::InvalidRequest(format!(
+                "Upload '{}' was already rejected: {}",
+                id,
+                entry.reason.as_deref().unwrap_or_default()
+            )));
+        }
+        let key = entry.object.key.clone();
+        let newer = self
+            .get_object_meta(bucket, &key)
+            .is_ok_and(|current| current.last_modified > entry.received_at);
+        let allowed = if newer {
+            Err(AppError::PreconditionFailed(format!(
+                "'{}' was written again while the upload waited for its scan",
+                key
+            )))
+        } else {
+            self.check_overwrite(bucket, &key)
+                .and_then(|_| self.check_new_object(bucket, &key))
+        };
+        if let Err(e) = allowed {
+            self.reject_entry(&dir, entry, &e.parts().2)?;
+            return Err(e);
+        }
+
+        let mut meta = entry.object;
+        meta.last_modified = self.now();
+        let dest = self.object_path(bucket, &key);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.rename_file(&dir.join(PAYLOAD), &dest)?;
+        self.save_object_meta(&meta)?;
+        self.forget_pending(bucket, &key, id);
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            tracing::warn!("Published upload {}, but could not remove {}: {}", id, dir.display(), e);
+        }
+        self.update_bucket_stats(bucket)?;
+
+        self.quarantine_event(EventKind::QuarantineApproved, &meta);
+        let kind = if meta.parts.is_empty() {
+            EventKind::Put
+        } else {
+            EventKind::CompleteMultipartUpload
+        };
+        self.object_created(kind, &meta);
+        tracing::info!("Approved upload {}: published {}/{} ({} bytes)", id, bucket, key, meta.size);
+        Ok(meta)
+    }
+
+    /// Reject a pending upload, deleting its payload
+    pub fn reject_quarantined(&self, bucket: &str, id: &str, reason: &str) -> Result<QuarantinedObject, AppError> {
+        let (_, entry) = self.read_quarantined(bucket, id)?;
+        let _lock = self.lock_key(bucket, &entry.object.key);
+        let (dir, entry) = self.read_quarantined(bucket, id)?;
+        if entry.status != QuarantineStatus::Pending {
+            return Err(AppError::InvalidRequest(format!("Upload '{}' was already rejected", id)));
+        }
+        self.reject_entry(&dir, entry, reason)
+    }
+
+    fn reject_entry(&self, dir: &Path, mut entry: QuarantinedObject, reason: &str) -> Result<QuarantinedObject, AppError> {
+        entry.status = QuarantineStatus::Rejected;
+        entry.reason = Some(reason.to_string());
+        entry.rejected_at = Some(self.now());
+        entry.retry_at = None;
+        write_entry_file(dir, &entry)?;
+        match fs::remove_file(dir.join(PAYLOAD)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        self.forget_pending(&entry.object.bucket, &entry.object.key, &entry.id);
+        self.quarantine_event(EventKind::QuarantineRejected, &entry.object);
+        tracing::warn!(
+            "Rejected upload {} of {}/{}: {}",
+            entry.id,
+            entry.object.bucket,
+            entry.object.key,
+            reason
+        );
+        Ok(entry)
+    }
+
+    /// Note a scan that ended without a verdict. The upload stays pending
+    /// and is scanned again from `retry_at`.
+    pub fn record_failed_scan(
+        &self,
+        bucket: &str,
+        id: &str,
+        error: &str,
+        retry_at: DateTime<Utc>,
+    ) -> Result<QuarantinedObject, AppError> {
+        let (_, entry) = self.read_quarantined(bucket, id)?;
+        let _lock = self.lock_key(bucket, &entry.object.key);
+        let (dir, mut entry) = self.read_quarantined(bucket, id)?;
+        if entry.status == QuarantineStatus::Pending {
+            entry.failed_scans += 1;
+            entry.last_error = Some(error.to_string());
+            entry.retry_at = Some(retry_at);
+            write_entry_file(&dir, &entry)?;
+        }
+        Ok(entry)
+    }
+
+    /// Drop the record of a rejected upload
+    pub fn dismiss_quarantined(&self, bucket: &str, id: &str) -> Result<(), AppError> {
+        let (dir, entry) = self.read_quarantined(bucket, id)?;
+        if entry.status == QuarantineStatus::Pending {
+            return Err(AppError::InvalidRequest(format!(
+                "Upload '{}' is still pending; approve or reject it first",
+                id
+            )));
+        }
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    fn forget_pending(&self, bucket: &str, key: &str, id: &str) {
+        let mut pending = self.quarantine.pending.lock().unwrap();
+        let slot = (bucket.to_string(), key.to_string());
+        if pending.get(&slot).map(String::as_str) == Some(id) {
+            pending.remove(&slot);
+        }
+    }
+
+    /// Forget the pending uploads of a deleted bucket
+    pub(super) fn forget_quarantine(&self, bucket: &str) {
+        self.quarantine.pending.lock().unwrap().retain(|(b, _), _| b != bucket);
+    }
+
+    /// Index a bucket's pending uploads at startup. A pending entry without
+    /// a payload was published just before a restart, and a directory
+    /// without an entry belongs to an upload that was cut short; both are
+    /// removed.
+    pub(super) fn load_quarantine(&self, bucket: &str, bucket_dir: &Path) {
+        let Ok(dirs) = fs::read_dir(bucket_dir.join(".quarantine")) else {
+            return;
+        };
+        let mut pending = self.quarantine.pending.lock().unwrap();
+        for dir in dirs.flatten().map(|dir| dir.path()) {
+            match read_entry_file(&dir) {
+                Some(entry) if entry.status == QuarantineStatus::Rejected => {}
+                Some(entry) if dir.join(PAYLOAD).is_file() => {
+                    let slot = (bucket.to_string(), entry.object.key.clone());
+                    // Of two pending uploads of a key, the newer one counts
+                    let newer = match pending.get(&slot) {
+                        Some(other) => read_entry_file(&dir.with_file_name(other))
+                            .filter(|other| other.received_at >= entry.received_at)
+                            .is_none(),
+                        None => true,
+                    };
+                    if newer {
+                        pending.insert(slot, entry.id);
+                    }
+                }
+                _ => {
+                    tracing::info!("Removing leftover quarantine entry {}", dir.display());
+                    let _ = fs::remove_dir_all(&dir);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    /// Manual approval, answering 423 while an upload waits
+    fn manual(pending_status: PendingStatus) -> QuarantineConfig {
+        QuarantineConfig {
+            command: None,
+            webhook: None,
+            timeout_secs: 60,
+            pending_status,
+        }
+    }
+
+    fn upload(storage: &StorageEngine, key: &str, data: &[u8]) -> String {
+        storage
+            .put_object("uploads", key, data, PutOptions::default())
+            .unwrap();
+        let pending = storage.list_quarantine(Some("uploads"), Some(QuarantineStatus::Pending)).unwrap();
+        pending.into_iter().rfind(|entry| entry.object.key == key).unwrap().id
+    }
+
+    #[test]
+    fn uploads_are_hidden_until_approved() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("uploads", "local").unwrap();
+        storage.set_bucket_quarantine("uploads", Some(manual(PendingStatus::Locked))).unwrap();
+        let mut events = storage.subscribe_events();
+
+        let id = upload(&storage, "report.pdf", b"%PDF");
+        assert!(matches!(storage.get_object("uploads", "report.pdf"), Err(AppError::ObjectPending { .. })));
+        assert!(storage.list_objects("uploads", "", None, 100).unwrap().objects.is_empty());
+        assert_eq!(storage.get_bucket("uploads").unwrap().object_count, 0);
+
+        let meta = storage.approve_quarantined("uploads", &id).unwrap();
+        assert_eq!(meta.size, 4);
+        let (_, data) = storage.get_object("uploads", "report.pdf").unwrap();
+        assert_eq!(&data[..], b"%PDF");
+        assert_eq!(storage.get_bucket("uploads").unwrap().object_count, 1);
+        assert!(storage.list_quarantine(Some("uploads"), None).unwrap().is_empty());
+
+        let mut kinds = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            kinds.push(event.kind);
+        }
+        assert_eq!(
+            kinds,
+            [EventKind::QuarantinePending, EventKind::QuarantineApproved, EventKind::Put]
+        );
+    }
+
+    #[test]
+    fn rejected_uploads_keep_their_reason() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("uploads", "local").unwrap();
+        storage.set_bucket_quarantine("uploads", Some(manual(PendingStatus::NotFound))).unwrap();
+
+        let id = upload(&storage, "setup.exe", b"MZ");
+        assert!(matches!(storage.get_object("uploads", "setup.exe"), Err(AppError::ObjectNotFound { .. })));
+        let payload = storage.quarantined_payload("uploads", &id).unwrap();
+
+        storage.reject_quarantined("uploads", &id, "Win.Trojan found").unwrap();
+        assert!(!payload.exists());
+        let rejected = storage.list_quarantine(None, Some(QuarantineStatus::Rejected)).unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].reason.as_deref(), Some("Win.Trojan found"));
+        assert!(rejected[0].rejected_at.is_some());
+        assert!(storage.approve_quarantined("uploads", &id).is_err());
+        assert!(storage.get_object("uploads", "setup.exe").is_err());
+
+        storage.dismiss_quarantined("uploads", &id).unwrap();
+        assert!(storage.list_quarantine(Some("uploads"), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_newer_upload_rejects_the_one_still_waiting() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("uploads", "local").unwrap();
+        storage.set_bucket_quarantine("uploads", Some(manual(PendingStatus::Locked))).unwrap();
+
+        let first = upload(&storage, "notes.txt", b"one");
+        let second = upload(&storage, "notes.txt", b"two");
+        let rejected = storage.list_quarantine(Some("uploads"), Some(QuarantineStatus::Rejected)).unwrap();
+        assert_eq!(rejected.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), [first.as_str()]);
+
+        storage.approve_quarantined("uploads", &second).unwrap();
+        let (_, data) = storage.get_object("uploads", "notes.txt").unwrap();
+        assert_eq!(&data[..], b"two");
+    }
+
+    #[test]
+    fn pending_uploads_survive_a_restart() {
+        let (dir, storage) = engine();
+        storage.create_bucket("uploads", "local").unwrap();
+        storage.set_bucket_quarantine("uploads", Some(manual(PendingStatus::Locked))).unwrap();
+        let id = upload(&storage, "scan-me.bin", b"bytes");
+        // An upload cut short before its entry was written
+        let leftover = storage.quarantine_dir("uploads").join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&leftover).unwrap();
+        fs::write(leftover.join(PAYLOAD), b"half").unwrap();
+        drop(storage);
+
+        let options = crate::config::Config::builtin().storage_options();
+        let storage = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+        assert!(!leftover.exists());
+        assert!(matches!(storage.get_object("uploads", "scan-me.bin"), Err(AppError::ObjectPending { .. })));
+        let due = storage.list_quarantine(Some("uploads"), Some(QuarantineStatus::Pending)).unwrap();
+        assert_eq!(due.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), [id.as_str()]);
+
+        storage.approve_quarantined("uploads", &id).unwrap();
+        let (_, data) = storage.get_object("uploads", "scan-me.bin").unwrap();
+        assert_eq!(&data[..], b"bytes");
+    }
+
+    #[test]
+    fn ids_outside_the_quarantine_are_not_found() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("uploads", "local").unwrap();
+        let err = storage.approve_quarantined("uploads", "../objects").unwrap_err();
+        assert!(matches!(err, AppError::QuarantinedUploadNotFound { .. }));
+    }
+}
@@ -0,0 +1,314 @@
+//! Small objects held in memory after they are read, so a hot object is
+//! served without touching the disk. Entries are keyed by the spelling the
+//! object is stored under and dropped wherever the engine rewrites or
+//! removes an object's metadata, which every payload change ends with.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use super::StorageEngine;
+use crate::models::{ObjectMeta, ReadCacheCleared, ReadCacheStats};
+
+type CacheKey = (String, String);
+
+#[derive(Default)]
+struct Entries {
+    objects: HashMap<CacheKey, (ObjectMeta, Vec<u8>)>,
+    /// Keys from least to most recently used
+    order: VecDeque<CacheKey>,
+    /// Payload bytes held
+    used: u64,
+    /// Bumped by every invalidation, so a read that raced a write doesn't
+    /// cache what it saw
+    generation: u64,
+}
+
+/// LRU cache of whole objects up to `max_object` bytes, bounded to
+/// `capacity` payload bytes in all. A capacity of 0 turns it off.
+pub(super) struct ReadCache {
+    entries: Mutex<Entries>,
+    capacity: u64,
+    max_object: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ReadCache {
+    pub(super) fn new(capacity: u64, max_object: u64) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            capacity,
+            max_object: max_object.min(capacity),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// A cached object, counted as a hit or a miss. Misses include objects
+    /// too large to be cached.
+    pub(super) fn get(&self, bucket: &str, key: &str) -> Option<(ObjectMeta, Vec<u8>)> {
+        let mut entries = self.entries.lock().unwrap();
+        let id = (bucket.to_string(), key.to_string());
+        let Some((meta, data)) = entries.objects.get(&id) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        let found = (meta.clone(), data.clone());
+        touch(&mut entries.order, id);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(found)
+    }
+
+    /// A cached object's metadata, without counting towards the hit rate
+    pub(super) fn meta(&self, bucket: &str, key: &str) -> Option<ObjectMeta> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .objects
+            .get(&(bucket.to_string(), key.to_string()))
+            .map(|(meta, _)| meta.clone())
+    }
+
+    /// Take before reading an object from disk and hand to `insert`
+    pub(super) fn generation(&self) -> u64 {
+        self.entries.lock().unwrap().generation
+    }
+
+    /// Cache an object read from disk, unless it is too large or something
+    /// was invalidated since `generation` was taken
+    pub(super) fn insert(&self, generation: u64, key: &str, meta: &ObjectMeta, data: &[u8]) {
+        let size = data.len() as u64;
+        if size > self.max_object || size != meta.size {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation {
+            return;
+        }
+        let id = (meta.bucket.clone(), key.to_string());
+        if let Some((_, old)) = entries.objects.remove(&id) {
+            entries.used -= old.len() as u64;
+        }
+        while entries.used + size > self.capacity {
+            let Some(oldest) = entries.order.pop_front() else {
+                break;
+            };
+            if let Some((_, evicted)) = entries.objects.remove(&oldest) {
+                entries.used -= evicted.len() as u64;
+            }
+        }
+        entries.used += size;
+        entries.objects.insert(id.clone(), (meta.clone(), data.to_vec()));
+        touch(&mut entries.order, id);
+    }
+
+    /// Drop one object after its metadata or payload changed
+    pub(super) fn forget(&self, bucket: &str, key: &str) {
+        if !self.enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.generation += 1;
+        let id = (bucket.to_string(), key.to_string());
+        if let Some((_, data)) = entries.objects.remove(&id) {
+            entries.used -= data.len() as u64;
+            entries.order.retain(|k| *k != id);
+        }
+    }
+
+    /// Drop every object of a bucket whose tree was replaced or removed
+    pub(super) fn forget_bucket(&self, bucket: &str) {
+        if !self.enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.generation += 1;
+        let Entries { objects, order, used, .. } = &mut *entries;
+        objects.retain(|(b, _), (_, data)| {
+            let keep = b != bucket;
+            if !keep {
+                *used -= data.len() as u64;
+            }
+            keep
+        });
+        order.retain(|(b, _)| b != bucket);
+    }
+
+    /// Drop everything, returning what was held
+    fn clear(&self) -> ReadCacheCleared {
+        let mut entries = self.entries.lock().unwrap();
+        let generation = entries.generation + 1;
+        let cleared = ReadCacheCleared {
+            objects: entries.objects.len(),
+            bytes: entries.used,
+        };
+        *entries = Entries {
+            generation,
+            ..Entries::default()
+        };
+        cleared
+    }
+
+    fn stats(&self) -> ReadCacheStats {
+        let entries = self.entries.lock().unwrap();
+        ReadCacheStats {
+            enabled: self.enabled(),
+            capacity: self.capacity,
+            max_object_size: self.max_object,
+            objects: entries.objects.len(),
+            bytes: entries.used,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl StorageEngine {
+    /// Size and hit counts of the read cache
+    pub fn read_cache_stats(&self) -> ReadCacheStats {
+        self.read_cache.stats()
+    }
+
+    /// Empty the read cache, for when files were changed behind the
+    /// server's back
+    pub fn clear_read_cache(&self) -> ReadCacheCleared {
+        let cleared = self.read_cache.clear();
+        tracing::info!("Cleared the read cache: {} object(s), {} bytes", cleared.objects, cleared.bytes);
+        cleared
+    }
+}
+
+/// Mark `id` as the most recently used
+fn touch(order: &mut VecDeque<CacheKey>, id: CacheKey) {
+    order.retain(|k| *k != id);
+    order.push_back(id);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use axum::http::StatusCode;
+
+    use crate::storage::testing::engine_with;
+    use crate::storage::{PutOptions, StorageEngine};
+    use crate::testing::{body_string, TestServer};
+
+    fn cached(capacity: u64, max_object: u64) -> (tempfile::TempDir, StorageEngine) {
+        let (dir, storage) = engine_with(|options| {
+            options.read_cache_size = capacity;
+            options.read_cache_max_object = max_object;
+        });
+        storage.create_bucket("config", "local").unwrap();
+        (dir, storage)
+    }
+
+    fn put(storage: &StorageEngine, key: &str, data: &[u8]) {
+        storage
+            .put_object("config", key, data, PutOptions::default())
+            .unwrap();
+    }
+
+    /// Move the bucket's payloads and metadata out of the engine's reach
+    fn hide_files(dir: &tempfile::TempDir) {
+        let bucket = dir.path().join("config");
+        fs::rename(bucket.join("objects"), bucket.join("objects.away")).unwrap();
+        fs::rename(bucket.join(".meta"), bucket.join(".meta.away")).unwrap();
+    }
+
+    #[test]
+    fn repeated_reads_of_a_cached_object_skip_the_disk() {
+        let (dir, storage) = cached(1024 * 1024, 64 * 1024);
+        let payload = vec![b'{'; 4096];
+        put(&storage, "app.json", &payload);
+        let (first, _) = storage.get_object("config", "app.json").unwrap();
+
+        hide_files(&dir);
+        for _ in 0..1000 {
+            let (meta, data) = storage.get_object("config", "app.json").unwrap();
+            assert_eq!((meta.etag, meta.last_modified), (first.etag.clone(), first.last_modified));
+            assert_eq!(data, payload);
+        }
+        assert_eq!(storage.get_object_meta("config", "app.json").unwrap().size, 4096);
+        let stats = storage.read_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.objects, stats.bytes), (1000, 1, 1, 4096));
+    }
+
+    #[test]
+    fn writes_drop_the_cached_copy() {
+        let (_dir, storage) = cached(1024, 1024);
+        put(&storage, "app.json", b"v1");
+        storage.get_object("config", "app.json").unwrap();
+
+        put(&storage, "app.json", b"v2");
+        assert_eq!(storage.get_object("config", "app.json").unwrap().1, b"v2");
+
+        storage.copy_object("config", "app.json", "config", "copy.json").unwrap();
+        storage.get_object("config", "copy.json").unwrap();
+        put(&storage, "other.json", b"v3");
+        storage.copy_object("config", "other.json", "config", "copy.json").unwrap();
+        assert_eq!(storage.get_object("config", "copy.json").unwrap().1, b"v3");
+
+        storage.delete_object("config", "app.json").unwrap();
+        assert!(storage.get_object("config", "app.json").is_err());
+        assert_eq!(storage.read_cache_stats().objects, 1);
+    }
+
+    #[test]
+    fn least_recently_used_objects_make_room() {
+        let (dir, storage) = cached(10, 5);
+        put(&storage, "a", b"aaaa");
+        put(&storage, "b", b"bbbb");
+        put(&storage, "c", b"cccc");
+        put(&storage, "big", b"too big");
+        storage.get_object("config", "a").unwrap();
+        storage.get_object("config", "b").unwrap();
+        storage.get_object("config", "a").unwrap();
+        storage.get_object("config", "c").unwrap();
+        storage.get_object("config", "big").unwrap();
+        let stats = storage.read_cache_stats();
+        assert_eq!((stats.objects, stats.bytes), (2, 8));
+
+        // "b" was used least recently, so it went
+        hide_files(&dir);
+        assert!(storage.get_object("config", "a").is_ok());
+        assert!(storage.get_object("config", "c").is_ok());
+        assert!(storage.get_object("config", "b").is_err());
+        assert!(storage.get_object("config", "big").is_err());
+    }
+
+    #[test]
+    fn the_cache_is_off_by_default() {
+        let (_dir, storage) = engine_with(|_| {});
+        storage.create_bucket("config", "local").unwrap();
+        put(&storage, "app.json", b"{}");
+        storage.get_object("config", "app.json").unwrap();
+        let stats = storage.read_cache_stats();
+        assert!(!stats.enabled);
+        assert_eq!((stats.hits, stats.misses, stats.objects), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn hits_are_exported_and_the_cache_can_be_cleared() {
+        let server = TestServer::with_config(|c| c.read_cache_size = 1024 * 1024);
+        server.state.storage.create_bucket("config", "local").unwrap();
+        put(&server.state.storage, "app.json", b"{}");
+        for _ in 0..3 {
+            let res = server.request("GET", "/api/object/config/app.json").await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let metrics = body_string(server.request("GET", "/api/metrics").await).await;
+        assert!(metrics.contains("freebucket_read_cache_hits_total 2\n"), "{}", metrics);
+        assert!(metrics.contains("freebucket_read_cache_misses_total 1\n"), "{}", metrics);
+
+        let res = server.request("POST", "/api/admin/cache/clear").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let cleared: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        assert_eq!((cleared["objects"].as_u64(), cleared["bytes"].as_u64()), (Some(1), Some(2)));
+        assert_eq!(server.state.storage.read_cache_stats().objects, 0);
+    }
+}
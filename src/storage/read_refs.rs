@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Instant;
+
+use super::StorageEngine;
+use crate::error::AppError;
+
+/// What deleting an object does while a GET is still reading it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeletePolicy {
+    /// Wait for the readers to finish, deferring the unlink if they outlast
+    /// the wait
+    Wait,
+    /// Remove the object right away but keep its bytes until the last
+    /// reader is done
+    Defer,
+    /// Refuse the delete with 409 Conflict
+    Fail,
+}
+
+impl DeletePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "wait" => Some(Self::Wait),
+            "defer" => Some(Self::Defer),
+            "fail" => Some(Self::Fail),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Readers {
+    count: usize,
+    /// Payloads moved aside by a delete, removed when the count drops to zero
+    deferred: Vec<PathBuf>,
+}
+
+/// Readers per (bucket, key), so deletes don't pull a file out from under
+/// a GET. The OS would let the read finish on Unix but fail it on Windows;
+/// tracking readers ourselves behaves the same everywhere.
+#[derive(Default)]
+pub(super) struct ReadRefs {
+    objects: Mutex<HashMap<(String, String), Readers>>,
+    released: Condvar,
+}
+
+/// Held for the duration of an object read
+pub(super) struct ReadGuard<'a> {
+    engine: &'a StorageEngine,
+    id: (String, String),
+}
+
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        let refs = &self.engine.read_refs;
+        let mut objects = refs.objects.lock().unwrap();
+        let Some(readers) = objects.get_mut(&self.id) else {
+            return;
+        };
+        readers.count -= 1;
+        if readers.count > 0 {
+            return;
+        }
+        let readers = objects.remove(&self.id).unwrap_or_default();
+        drop(objects);
+        for path in readers.deferred {
+            if let Err(e) = fs::remove_file(&path) {
+                tracing::warn!("Failed to remove deferred delete {}: {}", path.display(), e);
+            }
+            self.engine.active_temp.lock().unwrap().remove(&path);
+        }
+        refs.released.notify_all();
+    }
+}
+
+impl StorageEngine {
+    /// Register a reader of an object until the guard is dropped
+    pub(super) fn begin_read(&self, bucket: &str, key: &str) -> ReadGuard<'_> {
+        let id = (bucket.to_string(), key.to_string());
+        self.read_refs
+            .objects
+            .lock()
+            .unwrap()
+            .entry(id.clone())
+            .or_default()
+            .count += 1;
+        ReadGuard { engine: self, id }
+    }
+
+    /// Unlink an object's payload according to the delete policy. The reader
+    /// table stays locked throughout, so no read can start in between.
+    pub(super) fn remove_payload(&self, bucket: &str, key: &str, obj_path: &Path) -> Result<(), AppError> {
+        let id = (bucket.to_string(), key.to_string());
+        let mut objects = self.read_refs.objects.lock().unwrap();
+        if objects.contains_key(&id) {
+            match self.delete_policy {
+                DeletePolicy::Fail => {
+                    return Err(AppError::ObjectBusy {
+                        bucket: bucket.to_string(),
+                        key: key.to_string(),
+                    });
+                }
+                DeletePolicy::Wait => objects = self.wait_for_readers(objects, &id),
+                DeletePolicy::Defer => {}
+            }
+        }
+
+        match objects.get_mut(&id) {
+            Some(readers) => {
+                // Same filesystem as the object, so this is a rename and open
+                // handles keep working; gc cleans it up after a crash
+                let dir = self.bucket_path(bucket).join(".tmp");
+                fs::create_dir_all(&dir)?;
+                let parked = dir.join(format!(".tmp-{}", uuid::Uuid::new_v4()));
+                self.retry_io(|| fs::rename(obj_path, &parked))?;
+                self.active_temp.lock().unwrap().insert(parked.clone());
+                readers.deferred.push(parked);
+                tracing::info!(
+                    "Deferred removal of {}/{} until {} reader(s) finish",
+                    bucket,
+                    key,
+                    readers.count
+                );
+            }
+            None => fs::remove_file(obj_path)?,
+        }
+        Ok(())
+    }
+
+    fn wait_for_readers<'a>(
+        &self,
+        mut objects: MutexGuard<'a, HashMap<(String, String), Readers>>,
+        id: &(String, String),
+    ) -> MutexGuard<'a, HashMap<(String, String), Readers>> {
+        let deadline = Instant::now() + self.delete_wait;
+        while objects.contains_key(id) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                tracing::warn!(
+                    "{}/{} still being read after {:?}; deferring its removal",
+                    id.0,
+                    id.1,
+                    self.delete_wait
+                );
+                break;
+            }
+            objects = self.read_refs.released.wait_timeout(objects, left).unwrap().0;
+        }
+        objects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::storage::testing::engine_with;
+    use crate::storage::PutOptions;
+
+    fn content() -> Vec<u8> {
+        (0..65_536u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn engine(policy: DeletePolicy, wait: Duration) -> (tempfile::TempDir, StorageEngine) {
+        let (dir, storage) = engine_with(|options| {
+            options.delete_policy = policy;
+            options.delete_wait = wait;
+        });
+        storage.create_bucket("media", "local").unwrap();
+        storage
+            .put_object("media", "big.bin", &content(), PutOptions::default())
+            .unwrap();
+        (dir, storage)
+    }
+
+    /// Read `media/big.bin` the way a GET does, stalling after the first
+    /// bytes until `resume` says to go on
+    fn slow_get(storage: &StorageEngine, started: mpsc::Sender<()>, resume: mpsc::Receiver<()>) -> Vec<u8> {
+        let _reading = storage.begin_read("media", "big.bin");
+        let mut file = fs::File::open(storage.object_path("media", "big.bin")).unwrap();
+        let mut data = vec![0u8; 1024];
+        file.read_exact(&mut data).unwrap();
+        started.send(()).unwrap();
+        resume.recv().unwrap();
+        file.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    fn parked(dir: &tempfile::TempDir) -> usize {
+        fs::read_dir(dir.path().join("media/.tmp")).map_or(0, |entries| entries.count())
+    }
+
+    /// Run a delete while a slow GET holds the object, letting the GET
+    /// finish once `during` has looked at the state. Returns the delete's
+    /// result.
+    fn delete_during_get(
+        storage: &StorageEngine,
+        during: impl FnOnce(&std::thread::ScopedJoinHandle<'_, Result<(), AppError>>),
+    ) -> Result<(), AppError> {
+        let (started_tx, started) = mpsc::channel();
+        let (resume, resume_rx) = mpsc::channel();
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| slow_get(storage, started_tx, resume_rx));
+            started.recv().unwrap();
+            let deleter = scope.spawn(|| storage.delete_object("media", "big.bin"));
+            std::thread::sleep(Duration::from_millis(100));
+            during(&deleter);
+            resume.send(()).unwrap();
+            assert_eq!(reader.join().unwrap(), content(), "the reader gets every byte");
+            deleter.join().unwrap()
+        })
+    }
+
+    #[test]
+    fn wait_holds_the_delete_until_the_read_is_done() {
+        let (dir, storage) = engine(DeletePolicy::Wait, Duration::from_secs(30));
+        let result = delete_during_get(&storage, |deleter| {
+            assert!(!deleter.is_finished(), "the delete waits for the reader");
+        });
+        assert!(result.is_ok());
+        assert!(storage.stat_object("media", "big.bin").is_err());
+        assert_eq!(parked(&dir), 0);
+    }
+
+    #[test]
+    fn wait_defers_the_removal_once_the_wait_runs_out() {
+        let (dir, storage) = engine(DeletePolicy::Wait, Duration::from_millis(20));
+        let result = delete_during_get(&storage, |deleter| {
+            assert!(deleter.is_finished());
+            assert!(storage.stat_object("media", "big.bin").is_err());
+            assert_eq!(parked(&dir), 1, "the bytes are kept for the reader");
+        });
+        assert!(result.is_ok());
+        assert_eq!(parked(&dir), 0);
+        assert!(storage.active_temp.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn defer_removes_the_object_at_once_and_its_bytes_after_the_read() {
+        let (dir, storage) = engine(DeletePolicy::Defer, Duration::from_secs(30));
+        let result = delete_during_get(&storage, |deleter| {
+            assert!(deleter.is_finished());
+            assert!(storage.stat_object("media", "big.bin").is_err());
+            assert_eq!(parked(&dir), 1);
+        });
+        assert!(result.is_ok());
+        assert_eq!(parked(&dir), 0);
+        assert!(storage.active_temp.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn fail_refuses_the_delete_while_the_object_is_read() {
+        let (dir, storage) = engine(DeletePolicy::Fail, Duration::from_secs(30));
+        let result = delete_during_get(&storage, |deleter| assert!(deleter.is_finished()));
+        assert!(matches!(result, Err(AppError::ObjectBusy { .. })), "{:?}", result);
+        assert_eq!(storage.get_object("media", "big.bin").unwrap().1, content());
+        assert_eq!(parked(&dir), 0);
+
+        // Once nobody is reading, the delete goes through
+        storage.delete_object("media", "big.bin").unwrap();
+        assert!(storage.stat_object("media", "big.bin").is_err());
+    }
+}
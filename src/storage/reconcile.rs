@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use super::checksum;
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{ChecksumAlgorithm, ChecksumType, ObjectChecksum, ObjectMeta};
+
+/// When to check bucket metadata against the files actually on disk, which
+/// may have been changed while the server was not running
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReconcileMode {
+    /// Every bucket, before the engine is handed out
+    Startup,
+    /// Each bucket on its first access
+    Lazy,
+    /// Never; for data dirs too large to walk
+    Trust,
+}
+
+impl ReconcileMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "startup" => Some(Self::Startup),
+            "lazy" => Some(Self::Lazy),
+            "trust" => Some(Self::Trust),
+            _ => None,
+        }
+    }
+}
+
+pub struct ReconcileOptions {
+    pub mode: ReconcileMode,
+    /// Metadata is generated for at most this many files per bucket and
+    /// pass, since each one has to be hashed
+    pub max_files: u64,
+    /// Rebuild unreadable metadata on access instead of failing the request
+    pub repair_corrupt_meta: bool,
+}
+
+#[derive(Debug, Default)]
+struct ReconcileReport {
+    meta_created: u64,
+    meta_removed: u64,
+    /// Files left without metadata once `max_files` was reached
+    deferred: u64,
+}
+
+impl StorageEngine {
+    /// Reconcile every loaded bucket now
+    pub(super) fn reconcile_all(&self) {
+        let started = Instant::now();
+        let names: Vec<String> = self.buckets.read().unwrap().keys().cloned().collect();
+        for name in &names {
+            self.reconcile_bucket(name);
+        }
+        tracing::info!(
+            "Reconciled {} bucket(s) with disk in {:?}",
+            names.len(),
+            started.elapsed()
+        );
+    }
+
+    /// Mark every loaded bucket for a pass on its first access
+    pub(super) fn queue_reconcile(&self) {
+        let names: Vec<String> = self.buckets.read().unwrap().keys().cloned().collect();
+        self.unreconciled.lock().unwrap().extend(names);
+    }
+
+    /// Run the deferred pass for a bucket if it has not had one yet. The
+    /// bucket is taken off the list first, so reads made by the pass itself
+    /// (and requests racing it) fall through to the on-the-fly metadata.
+    pub(super) fn ensure_reconciled(&self, bucket: &str) {
+        if self.unreconciled.lock().unwrap().remove(bucket) {
+            self.reconcile_bucket(bucket);
+        }
+    }
+
+    /// Drop metadata whose payload is gone, generate it for payloads that
+    /// have none, and recount the bucket. Failures are logged rather than
+    /// returned so one bad bucket cannot keep the server from starting.
+    fn reconcile_bucket(&self, bucket: &str) {
+        let started = Instant::now();
+        let mut report = ReconcileReport::default();
+        let result = self
+            .reconcile_meta(bucket, &mut report)
+            .and_then(|_| {
+                let objects_root = self.bucket_path(bucket).join("objects");
+                self.reconcile_payloads(bucket, &objects_root, &objects_root, &mut report)
+            })
+            .and_then(|_| self.update_bucket_stats(bucket));
+        if let Err(e) = result {
+            tracing::error!("Reconciling bucket {} failed: {:?}", bucket, e);
+            return;
+        }
+
+        if report.meta_created + report.meta_removed > 0 {
+            tracing::info!(
+                "Reconciled bucket {} in {:?}: {} metadata file(s) generated, {} stale removed",
+                bucket,
+                started.elapsed(),
+                report.meta_created,
+                report.meta_removed
+            );
+        }
+        if report.deferred > 0 {
+            tracing::warn!(
+                "Bucket {} still has {} file(s) without metadata after generating {}; \
+                 they are served with on-the-fly metadata until the next pass",
+                bucket,
+                report.deferred,
+                self.reconcile.max_files
+            );
+        }
+    }
+
+    fn reconcile_meta(&self, bucket: &str, report: &mut ReconcileReport) -> Result<(), AppError> {
+        let Ok(entries) = fs::read_dir(self.bucket_path(bucket).join(".meta")) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(encoded) = name.strip_suffix(".json") else {
+                continue;
+            };
+            let Some(key) = super::key_paths::meta_file_key(&entry.path(), encoded) else {
+                continue;
+            };
+            if self.object_path(bucket, &key).is_file() {
+                continue;
+            }
+            match fs::remove_file(entry.path()) {
+                Ok(()) => {}
+                // A concurrent delete got there first
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+            self.hash_index.write().unwrap().remove(bucket, &key, self.now());
+            report.meta_removed += 1;
+        }
+        Ok(())
+    }
+
+    fn reconcile_payloads(
+        &self,
+        bucket: &str,
+        dir: &Path,
+        root: &Path,
+        report: &mut ReconcileReport,
+    ) -> Result<(), AppError> {
+        let mut stack = vec![dir.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else { continue };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    stack.push(path);
+                    continue;
+                }
+                let key = super::walk::relative_key(root, &path);
+                if self.object_meta_path(bucket, &key).exists() {
+                    continue;
+                }
+                if report.meta_created >= self.reconcile.max_files {
+                    report.deferred += 1;
+                    continue;
+                }
+                // The file may vanish or be replaced mid-pass; skip it and let
+                // the next pass pick it up
+                if let Ok(meta) = self.meta_from_file(bucket, &key, &path) {
+                    self.write_meta_file(&meta)?;
+                    report.meta_created += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Regenerate an object's metadata from its payload, keeping the
+    /// user-supplied fields of the old file that still parse, and swap the
+    /// result in atomically
+    pub fn rebuild_object_meta(&self, bucket: &str, key: &str) -> Result<ObjectMeta, AppError> {
+        {
+            let buckets = self.buckets.read().unwrap();
+            if !buckets.contains_key(bucket) {
+                return Err(AppError::BucketNotFound(bucket.to_string()));
+            }
+        }
+
+        let path = self.object_path(bucket, key);
+        if !path.is_file() {
+            return Err(AppError::ObjectNotFound {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            });
+        }
+        let mut meta = self.meta_from_file(bucket, key, &path)?;
+
+        let meta_path = self.object_meta_path(bucket, key);
+        let old = fs::read(&meta_path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<serde_json::Value>(&json).ok());
+        if let Some(old) = old {
+            if let Some(content_type) = old["content_type"].as_str() {
+                meta.content_type = content_type.to_string();
+            }
+            if let Ok(metadata) = serde_json::from_value(old["metadata"].clone()) {
+                meta.metadata = metadata;
+            }
+            meta.original_filename = old["original_filename"].as_str().map(str::to_string);
+            meta.website_redirect_location = old["website_redirect_location"].as_str().map(str::to_string);
+            meta.source_mtime = serde_json::from_value(old["source_mtime"].clone()).unwrap_or_default();
+            // Part boundaries cannot be recovered, so only a full-object
+            // checksum in the same algorithm can be recomputed
+            let algorithm = serde_json::from_value::<ChecksumAlgorithm>(old["checksum"]["algorithm"].clone());
+            if let Ok(algorithm) = algorithm {
+                meta.checksum = Some(ObjectChecksum {
+                    algorithm,
+                    checksum_type: ChecksumType::FullObject,
+                    value: checksum::checksum_of(algorithm, &fs::read(&path)?),
+                });
+            }
+        }
+
+        if let Some(parent) = meta_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.replace_file(&meta_path, serde_json::to_string_pretty(&meta).unwrap().as_bytes())?;
+        self.hash_index.write().unwrap().insert(&meta);
+        self.read_cache.forget(bucket, &meta.key);
+        self.update_bucket_stats(bucket)?;
+        tracing::info!("Rebuilt metadata for {}/{}", bucket, key);
+        Ok(meta)
+    }
+
+    /// The metadata of an object for `HEAD`, with its size taken from the
+    /// payload file rather than trusted from the metadata
+    pub fn stat_object(&self, bucket: &str, key: &str) -> Result<ObjectMeta, AppError> {
+        let meta = self.get_object_meta(bucket, key)?;
+        let size = match fs::metadata(self.object_path(bucket, key)) {
+            Ok(file) => file.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(self.missing_object(bucket, key)),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(self.check_payload_size(meta, size))
+    }
+
+    /// Compare the recorded size with that of the payload as it was read,
+    /// for a payload changed behind the server's back. The size read wins:
+    /// the stored metadata gets it, and the ETag and checksum, which no
+    /// longer describe the payload either, give way to a provisional ETag
+    /// queued for hashing. The repair is best effort; the corrected
+    /// metadata is returned either way. Callers must not hold the key.
+    pub(super) fn check_payload_size(&self, meta: ObjectMeta, size: u64) -> ObjectMeta {
+        if meta.size == size {
+            return meta;
+        }
+        tracing::warn!(
+            "Metadata of {}/{} records {} bytes but the payload has {}; correcting it",
+            meta.bucket,
+            meta.key,
+            meta.size,
+            size
+        );
+        let mut corrected = meta;
+        corrected.size = size;
+        corrected.etag = self.provisional_etag(size);
+        corrected.etag_provisional = true;
+        corrected.checksum = None;
+        corrected.parts.clear();
+        match self.repair_payload_size(&corrected) {
+            Ok(true) => {}
+            Ok(false) => tracing::debug!(
+                "{}/{} changed again while its size was corrected",
+                corrected.bucket,
+                corrected.key
+            ),
+            Err(e) => tracing::warn!(
+                "Cannot correct the metadata of {}/{}: {}",
+                corrected.bucket,
+                corrected.key,
+                e.parts().2
+            ),
+        }
+        corrected
+    }
+
+    /// Store `corrected` unless the object was written or changed again
+    /// since it was read
+    fn repair_payload_size(&self, corrected: &ObjectMeta) -> Result<bool, AppError> {
+        let (bucket, key) = (corrected.bucket.as_str(), corrected.key.as_str());
+        {
+            let _lock = self.lock_key(bucket, key);
+            let stored = self.get_object_meta(bucket, key)?;
+            if stored.size == corrected.size || fs::metadata(self.object_path(bucket, key))?.len() != corrected.size {
+                return Ok(false);
+            }
+            self.queue_rehash(corrected)?;
+            self.write_meta_file(corrected)?;
+        }
+        self.update_bucket_stats(bucket)?;
+        Ok(true)
+    }
+
+    /// Metadata for a file that was placed in the bucket directly, dated by
+    /// its modification time
+    fn meta_from_file(&self, bucket: &str, key: &str, path: &Path) -> Result<ObjectMeta, AppError> {
+        let file_meta = fs::metadata(path)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            bucket: bucket.to_string(),
+            size: file_meta.len(),
+            content_type: mime_guess::from_path(key)
+                .first_or_octet_stream()
+                .to_string(),
+            etag: hex::encode(hasher.finalize()),
+            last_modified: file_meta
+                .modified()
+                .map(DateTime::<Utc>::from)
+                .unwrap_or_else(|_| self.now()),
+            metadata: HashMap::new(),
+            parts: Vec::new(),
+            checksum: None,
+            original_filename: None,
+            website_redirect_location: None,
+            source_mtime: None,
+            etag_provisional: false,
+            origin: None,
+            allocated_size: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    fn reopen(root: &Path, mode: ReconcileMode, max_files: u64) -> StorageEngine {
+        let mut options = crate::config::Config::builtin().storage_options();
+        options.reconcile.mode = mode;
+        options.reconcile.max_files = max_files;
+        StorageEngine::new(root.to_str().unwrap(), options).unwrap()
+    }
+
+    fn put(storage: &StorageEngine, key: &str, data: &[u8]) {
+        storage
+            .put_object("photos", key, data, PutOptions::default())
+            .unwrap();
+    }
+
+    fn keys(storage: &StorageEngine) -> Vec<String> {
+        let listing = storage.list_objects("photos", "", None, 1000).unwrap();
+        listing.objects.into_iter().map(|o| o.key).collect()
+    }
+
+    /// `a.txt` and `b.txt` stored, then, with the server down, `a.txt`'s
+    /// payload deleted and two files copied in by hand
+    fn changed_while_down() -> tempfile::TempDir {
+        let (dir, storage) = engine();
+        storage.create_bucket("photos", "local").unwrap();
+        put(&storage, "a.txt", b"aaaa");
+        put(&storage, "b.txt", b"bb");
+        drop(storage);
+        let objects = dir.path().join("photos/objects");
+        fs::remove_file(objects.join("a.txt")).unwrap();
+        fs::create_dir_all(objects.join("new")).unwrap();
+        fs::write(objects.join("new/c.json"), b"{}").unwrap();
+        fs::write(objects.join("new/d.txt"), b"dddddd").unwrap();
+        dir
+    }
+
+    #[test]
+    fn startup_matches_metadata_with_the_files() {
+        let dir = changed_while_down();
+        let storage = reopen(dir.path(), ReconcileMode::Startup, 1000);
+
+        let bucket = storage.get_bucket("photos").unwrap();
+        assert_eq!((bucket.object_count, bucket.total_size), (3, 10));
+        assert_eq!(keys(&storage), ["b.txt", "new/c.json", "new/d.txt"]);
+        assert!(!storage.object_meta_path("photos", "a.txt").exists());
+        assert!(matches!(storage.get_object("photos", "a.txt"), Err(AppError::ObjectNotFound { .. })));
+
+        let meta = storage.get_object_meta("photos", "new/c.json").unwrap();
+        assert_eq!(meta.content_type, "application/json");
+        assert_eq!(meta.etag, hex::encode(Sha256::digest(b"{}")));
+        assert!(storage.object_meta_path("photos", "new/c.json").exists());
+    }
+
+    #[test]
+    fn lazy_reconciles_a_bucket_on_first_access() {
+        let dir = changed_while_down();
+        let storage = reopen(dir.path(), ReconcileMode::Lazy, 1000);
+        // Nothing has been checked yet
+        assert_eq!(storage.get_bucket("photos").unwrap().object_count, 2);
+        assert!(storage.object_meta_path("photos", "a.txt").exists());
+
+        assert_eq!(keys(&storage), ["b.txt", "new/c.json", "new/d.txt"]);
+        assert_eq!(storage.get_bucket("photos").unwrap().object_count, 3);
+        assert!(!storage.object_meta_path("photos", "a.txt").exists());
+    }
+
+    #[test]
+    fn trust_leaves_the_metadata_alone() {
+        let dir = changed_while_down();
+        let storage = reopen(dir.path(), ReconcileMode::Trust, 1000);
+        assert_eq!(storage.get_bucket("photos").unwrap().object_count, 2);
+        assert!(storage.object_meta_path("photos", "a.txt").exists());
+        assert!(!storage.object_meta_path("photos", "new/d.txt").exists());
+    }
+
+    #[test]
+    fn generates_at_most_max_files_per_pass() {
+        let dir = changed_while_down();
+        let storage = reopen(dir.path(), ReconcileMode::Startup, 1);
+        let generated = ["new/c.json", "new/d.txt"]
+            .iter()
+            .filter(|key| storage.object_meta_path("photos", key).exists())
+            .count();
+        assert_eq!(generated, 1);
+        // The rest are still served, with metadata made on the fly
+        assert_eq!(keys(&storage), ["b.txt", "new/c.json", "new/d.txt"]);
+        assert_eq!(storage.get_object("photos", "new/d.txt").unwrap().1, b"dddddd");
+
+        // The next pass picks up where this one stopped
+        drop(storage);
+        let storage = reopen(dir.path(), ReconcileMode::Startup, 1);
+        assert!(storage.object_meta_path("photos", "new/c.json").exists());
+        assert!(storage.object_meta_path("photos", "new/d.txt").exists());
+    }
+
+    /// `a.txt` with user metadata, whose metadata file no longer reads as
+    /// an object's; the old fields are returned
+    fn corrupted_meta() -> (tempfile::TempDir, serde_json::Value) {
+        let (dir, storage) = engine();
+        storage.create_bucket("photos", "local").unwrap();
+        let metadata = HashMap::from([("owner".to_string(), "ana".to_string())]);
+        storage
+            .put_object(
+                "photos",
+                "a.txt",
+                b"hello",
+                PutOptions { content_type: Some("text/x-note"), metadata, ..Default::default() },
+            )
+            .unwrap();
+        let meta_path = storage.object_meta_path("photos", "a.txt");
+        drop(storage);
+        let mut old: serde_json::Value = serde_json::from_slice(&fs::read(&meta_path).unwrap()).unwrap();
+        old["size"] = "five".into();
+        fs::write(&meta_path, serde_json::to_vec(&old).unwrap()).unwrap();
+        (dir, old)
+    }
+
+    #[test]
+    fn corrupt_metadata_is_rebuilt_on_access() {
+        let (dir, old) = corrupted_meta();
+        let storage = reopen(dir.path(), ReconcileMode::Startup, 1000);
+
+        let meta = storage.get_object_meta("photos", "a.txt").unwrap();
+        assert_eq!(meta.size, 5);
+        assert_eq!(meta.content_type, "text/x-note");
+        assert_eq!(meta.metadata["owner"], "ana");
+        assert_eq!(meta.etag, old["etag"]);
+        // The file was replaced, not just worked around
+        let stored = fs::read(storage.object_meta_path("photos", "a.txt")).unwrap();
+        assert_eq!(serde_json::from_slice::<ObjectMeta>(&stored).unwrap().size, 5);
+    }
+
+    #[test]
+    fn corrupt_metadata_fails_the_read_when_repair_is_off() {
+        let (dir, _) = corrupted_meta();
+        let mut options = crate::config::Config::builtin().storage_options();
+        options.reconcile.repair_corrupt_meta = false;
+        let storage = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+
+        let err = storage.get_object_meta("photos", "a.txt").unwrap_err();
+        assert!(matches!(&err, AppError::StorageError(msg) if msg.starts_with("Corrupt metadata")), "{:?}", err);
+        // The explicit rebuild still works
+        assert_eq!(storage.rebuild_object_meta("photos", "a.txt").unwrap().metadata["owner"], "ana");
+        assert_eq!(storage.get_object_meta("photos", "a.txt").unwrap().size, 5);
+    }
+
+    #[test]
+    fn unparseable_metadata_is_rebuilt_from_the_payload_alone() {
+        let (dir, storage) = engine();
+        storage.create_bucket("photos", "local").unwrap();
+        put(&storage, "notes/a.md", b"# hi");
+        fs::write(storage.object_meta_path("photos", "notes/a.md"), b"{\"key\": \"notes/a.md\", \"si").unwrap();
+
+        let meta = storage.rebuild_object_meta("photos", "notes/a.md").unwrap();
+        assert_eq!((meta.size, meta.content_type.as_str()), (4, "text/markdown"));
+        assert!(meta.metadata.is_empty());
+        let payload = dir.path().join("photos/objects/notes/a.md");
+        let mtime: DateTime<Utc> = fs::metadata(payload).unwrap().modified().unwrap().into();
+        assert_eq!(meta.last_modified, mtime);
+
+        assert!(matches!(
+            storage.rebuild_object_meta("photos", "missing.txt"),
+            Err(AppError::ObjectNotFound { .. })
+        ));
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn sizes_come_from_the_payload_when_it_changed_behind_our_back() {
+        let (dir, storage) = engine();
+        storage.create_bucket("photos", "local").unwrap();
+        put(&storage, "a.txt", b"hello world");
+        let payload = dir.path().join("photos/objects/a.txt");
+
+        for content in [&b"hello"[..], &b"hello world, and more"[..]] {
+            fs::write(&payload, content).unwrap();
+            let meta = storage.stat_object("photos", "a.txt").unwrap();
+            assert_eq!(meta.size, content.len() as u64);
+            assert!(meta.etag_provisional);
+            let (meta, data) = storage.get_object("photos", "a.txt").unwrap();
+            assert_eq!((meta.size, data.as_slice()), (content.len() as u64, content));
+
+            // The stored record was repaired, and the hasher finishes the job
+            assert_eq!(storage.get_object_meta("photos", "a.txt").unwrap().size, content.len() as u64);
+            assert_eq!(storage.rehash_pending(), 1);
+            let meta = storage.stat_object("photos", "a.txt").unwrap();
+            assert_eq!((meta.etag, meta.etag_provisional), (sha256(content), false));
+            assert_eq!(storage.get_bucket("photos").unwrap().total_size, content.len() as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_of_a_changed_payload_send_its_real_length() {
+        let server = crate::testing::TestServer::new();
+        let storage = &server.state.storage;
+        storage.create_bucket("photos", "local").unwrap();
+        put(storage, "a.txt", b"hello world");
+        let payload = format!("{}/photos/objects/a.txt", server.state.config().data_dir);
+        fs::write(&payload, b"hello").unwrap();
+
+        let res = server.request("HEAD", "/api/object/photos/a.txt").await;
+        assert_eq!(res.headers()["content-length"], "5");
+        let res = server.request("GET", "/api/object/photos/a.txt").await;
+        assert_eq!(res.headers()["content-length"], "5");
+        let etag = res.headers()["etag"].clone();
+        assert_eq!(crate::testing::body_string(res).await, "hello");
+
+        storage.rehash_pending();
+        let res = server.request("GET", "/api/object/photos/a.txt").await;
+        assert_ne!(res.headers()["etag"], etag);
+        assert_eq!(res.headers()["etag"], format!("\"{}\"", sha256(b"hello")).as_str());
+        assert_eq!(res.headers()["x-freebucket-etag-final"], "true");
+    }
+}
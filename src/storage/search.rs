@@ -0,0 +1,63 @@
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::ObjectMeta;
+
+impl StorageEngine {
+    /// Objects under `prefix` whose key or original filename contains
+    /// `query`, ignoring case, in key order. At most `limit` are returned;
+    /// the count is of every match.
+    pub fn search_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        query: &str,
+        limit: usize,
+    ) -> Result<(Vec<ObjectMeta>, usize), AppError> {
+        let query = query.to_lowercase();
+        let matches = |text: &str| text.to_lowercase().contains(&query);
+        let mut found = Vec::new();
+        let mut count = 0;
+        self.visit_objects(bucket, prefix, |meta| {
+            if matches(&meta.key) || meta.original_filename.as_deref().is_some_and(matches) {
+                count += 1;
+                if found.len() < limit {
+                    found.push(meta);
+                }
+            }
+            Ok(())
+        })?;
+        Ok((found, count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    #[test]
+    fn matches_keys_and_original_filenames() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("uploads", "local").unwrap();
+        for (key, original) in [
+            ("3f/9a1c.pdf", Some("Quarterly Report.pdf")),
+            ("71/e0b2.png", Some("holiday.png")),
+            ("reports/2024.csv", None),
+        ] {
+            storage
+                .put_object("uploads", key, b"x", PutOptions { original_filename: original, ..Default::default() })
+                .unwrap();
+        }
+
+        let keys = |prefix: &str, query: &str, limit: usize| {
+            let (found, count) = storage.search_objects("uploads", prefix, query, limit).unwrap();
+            (found.into_iter().map(|m| m.key).collect::<Vec<_>>(), count)
+        };
+        assert_eq!(keys("", "REPORT", 10), (vec!["3f/9a1c.pdf".to_string(), "reports/2024.csv".to_string()], 2));
+        assert_eq!(keys("", "holiday", 10), (vec!["71/e0b2.png".to_string()], 1));
+        assert_eq!(keys("", "report", 1), (vec!["3f/9a1c.pdf".to_string()], 2));
+        assert_eq!(keys("reports/", "report", 10), (vec!["reports/2024.csv".to_string()], 1));
+        assert_eq!(keys("", "nothing", 10), (vec![], 0));
+        assert!(storage.search_objects("missing", "", "x", 10).is_err());
+    }
+}
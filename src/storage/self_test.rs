@@ -0,0 +1,137 @@
+use std::fs;
+use std::time::Instant;
+
+use sha2::{Digest, Sha256};
+
+use super::{PutOptions, StorageEngine};
+use crate::error::AppError;
+use crate::models::{SelfTestReport, SelfTestStep};
+
+/// Holds the self-test's throwaway objects. Bucket names can't contain `_`
+/// and bucket scans skip dot directories, so no user bucket can collide
+/// with it.
+pub(super) const SYSTEM_BUCKET: &str = ".freebucket_system";
+
+/// Whether a bucket is internal and kept out of listings, stats, and events
+pub(super) fn is_system_bucket(name: &str) -> bool {
+    name == SYSTEM_BUCKET
+}
+
+impl StorageEngine {
+    /// Register the system bucket, creating its directory on first use
+    fn ensure_system_bucket(&self) -> Result<(), AppError> {
+        let mut buckets = self.buckets.write().unwrap();
+        if buckets.contains_key(SYSTEM_BUCKET) {
+            return Ok(());
+        }
+        let bucket = self.create_bucket_meta(SYSTEM_BUCKET);
+        let dir = self.bucket_path(SYSTEM_BUCKET);
+        fs::create_dir_all(dir.join("objects"))?;
+        fs::create_dir_all(dir.join(".meta"))?;
+        self.write_bucket_meta(&bucket)?;
+        buckets.insert(SYSTEM_BUCKET.to_string(), bucket);
+        Ok(())
+    }
+
+    /// Put a throwaway object in the system bucket, read it back, list it,
+    /// and delete it, timing each step. Steps after a failure are skipped,
+    /// except that a written object is always deleted again. `light` uses a
+    /// small payload and skips the listing, for frequent readiness checks.
+    pub fn self_test(&self, light: bool) -> SelfTestReport {
+        let started = Instant::now();
+        let key = format!("self-test/{}", self.new_id());
+        let size = if light { 4 * 1024 } else { 1024 * 1024 };
+        // Varies with the key, so a stale file can't pass for this run's
+        let data: Vec<u8> = key.bytes().cycle().take(size).collect();
+        let expected_etag = hex::encode(Sha256::digest(&data));
+
+        let mut steps = Vec::new();
+        let mut step = |name: &str, check: &mut dyn FnMut() -> Result<(), String>| {
+            let step_started = Instant::now();
+            let result = check();
+            steps.push(SelfTestStep {
+                step: name.to_string(),
+                passed: result.is_ok(),
+                duration_ms: step_started.elapsed().as_secs_f64() * 1000.0,
+                error: result.err(),
+            });
+            steps.last().unwrap().passed
+        };
+
+        let mut ok = step("prepare", &mut || self.ensure_system_bucket().map_err(describe));
+        let written = ok
+            && step("write", &mut || {
+                let meta = self
+                    .put_object(
+                        SYSTEM_BUCKET,
+                        &key,
+                        &data,
+                        PutOptions { content_type: Some("application/octet-stream"), ..Default::default() },
+                    )
+                    .map_err(describe)?;
+                if meta.etag != expected_etag {
+                    return Err(format!("Stored ETag {} does not match {}", meta.etag, expected_etag));
+                }
+                Ok(())
+            });
+        ok &= written;
+        ok = ok
+            && step("read", &mut || {
+                let (meta, read) = self.get_object(SYSTEM_BUCKET, &key).map_err(describe)?;
+                if read != data {
+                    return Err(format!("Read {} bytes that differ from the {} written", read.len(), data.len()));
+                }
+                if meta.etag != expected_etag {
+                    return Err(format!("Read ETag {} does not match {}", meta.etag, expected_etag));
+                }
+                Ok(())
+            });
+        if !light {
+            ok = ok
+                && step("list", &mut || {
+                    let listing = self.list_objects(SYSTEM_BUCKET, &key, None, 10).map_err(describe)?;
+                    if !listing.objects.iter().any(|o| o.key == key) {
+                        return Err("Written object is missing from the listing".to_string());
+                    }
+                    Ok(())
+                });
+        }
+        if written {
+            ok &= step("delete", &mut || {
+                self.delete_object(SYSTEM_BUCKET, &key).map_err(describe)?;
+                match self.get_object_meta(SYSTEM_BUCKET, &key) {
+                    Err(AppError::ObjectNotFound { .. }) => Ok(()),
+                    Err(e) => Err(describe(e)),
+                    Ok(_) => Err("Object is still readable after the delete".to_string()),
+                }
+            });
+        }
+
+        if let Some(failed) = steps.iter().find(|s| !s.passed) {
+            tracing::warn!(
+                "Storage self-test failed at {}: {}",
+                failed.step,
+                failed.error.as_deref().unwrap_or_default()
+            );
+        }
+        SelfTestReport {
+            passed: ok,
+            steps,
+            duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+            finished_at: self.now(),
+        }
+    }
+
+    /// Write and remove a file in the data dir, the cheapest sign that
+    /// storage still accepts writes
+    pub fn probe_writable(&self) -> Result<(), AppError> {
+        let probe = self.root.join(".readyz_probe");
+        fs::write(&probe, b"ok")?;
+        fs::remove_file(&probe)?;
+        Ok(())
+    }
+}
+
+fn describe(e: AppError) -> String {
+    e.parts().2
+}
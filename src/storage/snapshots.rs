@@ -0,0 +1,162 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{sparse, StorageEngine};
+use crate::error::AppError;
+use crate::models::Snapshot;
+
+impl StorageEngine {
+    fn snapshots_dir(&self, bucket: &str) -> PathBuf {
+        self.bucket_path(bucket).join(".snapshots")
+    }
+
+    fn snapshot_dir(&self, bucket: &str, id: &str) -> Result<PathBuf, AppError> {
+        // Snapshot IDs are generated by us; reject anything that could escape the snapshots dir
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(AppError::InvalidRequest(format!("Invalid snapshot id '{}'", id)));
+        }
+        Ok(self.snapshots_dir(bucket).join(id))
+    }
+
+    /// Recreate the file tree under `src` at `dst`. Object payloads are hard
+    /// linked (falling back to a copy across filesystems); metadata is always
+    /// copied because it is rewritten in place.
+    pub(super) fn link_tree(src: &Path, dst: &Path, hard_link: bool) -> io::Result<()> {
+        fs::create_dir_all(dst)?;
+        if !src.exists() {
+            return Ok(());
+        }
+        let mut stack = vec![(src.to_path_buf(), dst.to_path_buf())];
+        while let Some((src, dst)) = stack.pop() {
+            for entry in fs::read_dir(&src)?.flatten() {
+                let from = entry.path();
+                let to = dst.join(entry.file_name());
+                if from.is_dir() {
+                    fs::create_dir_all(&to)?;
+                    stack.push((from, to));
+                } else if !hard_link || fs::hard_link(&from, &to).is_err() {
+                    sparse::copy_file(&from, &to)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a point-in-time snapshot of a bucket's live objects
+    pub fn create_snapshot(&self, bucket: &str) -> Result<Snapshot, AppError> {
+        let info = self.get_bucket(bucket)?;
+
+        let id = format!(
+            "{}-{}",
+            self.now().format("%Y%m%dT%H%M%SZ"),
+            &self.new_id().simple().to_string()[..8]
+        );
+        let dir = self.snapshot_dir(bucket, &id)?;
+        let bucket_dir = self.bucket_path(bucket);
+
+        let result = Self::link_tree(&bucket_dir.join("objects"), &dir.join("objects"), self.fs_features.hard_links)
+            .and_then(|_| Self::link_tree(&bucket_dir.join(".meta"), &dir.join(".meta"), false));
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&dir);
+            return Err(e.into());
+        }
+
+        let stats = self.dir_stats(&dir.join("objects"));
+        let snapshot = Snapshot {
+            id,
+            bucket: info.name,
+            created_at: self.now(),
+            object_count: stats.count,
+            total_size: stats.size,
+        };
+        let json = serde_json::to_string_pretty(&snapshot).unwrap();
+        fs::write(dir.join("snapshot.json"), json)?;
+
+        tracing::info!(
+            "Created snapshot {} of bucket {} ({} objects)",
+            snapshot.id,
+            bucket,
+            snapshot.object_count
+        );
+        Ok(snapshot)
+    }
+
+    pub fn list_snapshots(&self, bucket: &str) -> Result<Vec<Snapshot>, AppError> {
+        self.get_bucket(bucket)?;
+
+        let mut snapshots = Vec::new();
+        if let Ok(entries) = fs::read_dir(self.snapshots_dir(bucket)) {
+            for entry in entries.flatten() {
+                let manifest = entry.path().join("snapshot.json");
+                if let Ok(json) = fs::read_to_string(&manifest) {
+                    match serde_json::from_str::<Snapshot>(&json) {
+                        Ok(snapshot) => snapshots.push(snapshot),
+                        Err(e) => tracing::warn!("Skipping corrupt snapshot {:?}: {}", manifest, e),
+                    }
+                }
+            }
+        }
+        snapshots.sort_by_key(|s| s.created_at);
+        Ok(snapshots)
+    }
+
+    fn load_snapshot(&self, bucket: &str, id: &str) -> Result<Snapshot, AppError> {
+        self.get_bucket(bucket)?;
+        let manifest = self.snapshot_dir(bucket, id)?.join("snapshot.json");
+        if !manifest.exists() {
+            return Err(AppError::SnapshotNotFound {
+                bucket: bucket.to_string(),
+                id: id.to_string(),
+            });
+        }
+        let json = fs::read_to_string(&manifest)?;
+        serde_json::from_str(&json)
+            .map_err(|e| AppError::StorageError(format!("Corrupt snapshot manifest: {}", e)))
+    }
+
+    /// Reset a bucket's live contents to a snapshot. Objects written after
+    /// the snapshot are deleted, so callers must pass `force`.
+    pub fn restore_snapshot(&self, bucket: &str, id: &str, force: bool) -> Result<Snapshot, AppError> {
+        let snapshot = self.load_snapshot(bucket, id)?;
+        if !force {
+            return Err(AppError::InvalidRequest(
+                "Restoring a snapshot deletes objects created after it; pass force=true to confirm"
+                    .to_string(),
+            ));
+        }
+
+        let snap_dir = self.snapshot_dir(bucket, id)?;
+        let bucket_dir = self.bucket_path(bucket);
+
+        // Stage the restored tree next to the live one, then swap directories
+        // so a failure part-way never leaves the bucket half restored
+        let tmp = self.temp_file(bucket)?;
+        let staging = tmp.path();
+        Self::link_tree(&snap_dir.join("objects"), &staging.join("objects"), self.fs_features.hard_links)?;
+        Self::link_tree(&snap_dir.join(".meta"), &staging.join(".meta"), false)?;
+
+        for name in ["objects", ".meta"] {
+            let live = bucket_dir.join(name);
+            if live.exists() {
+                fs::rename(&live, staging.join(format!("old-{}", name)))?;
+            }
+            fs::rename(staging.join(name), &live)?;
+        }
+        // Dropping the guard removes the staging dir along with the old trees
+        drop(tmp);
+        self.read_cache.forget_bucket(bucket);
+
+        self.update_bucket_stats(bucket)?;
+        self.reindex_bucket(bucket)?;
+        tracing::info!("Restored bucket {} to snapshot {}", bucket, id);
+        Ok(snapshot)
+    }
+
+    pub fn delete_snapshot(&self, bucket: &str, id: &str) -> Result<(), AppError> {
+        self.load_snapshot(bucket, id)?;
+        fs::remove_dir_all(self.snapshot_dir(bucket, id)?)?;
+        tracing::info!("Deleted snapshot {} of bucket {}", id, bucket);
+        Ok(())
+    }
+}
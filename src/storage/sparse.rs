@@ -0,0 +1,249 @@
+//! Sparse payloads. Disk images and similar files are mostly zeros, so
+//! payloads are written through `SparseWriter`, which seeks over zero
+//! blocks instead of writing them. On filesystems with sparse files the
+//! skipped ranges become holes that take no space, and reads of them return
+//! zeros as usual; elsewhere the filesystem fills them in. Copies between
+//! payloads go through the same writer, so they stay sparse too.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Size of the blocks checked for zeros, which is the smallest hole made.
+/// Blocks are aligned to the start of the file, as filesystem blocks are.
+const BLOCK: usize = 4096;
+
+/// Data blocks gathered before they are written in one go
+const WRITE_BATCH: usize = 1024 * 1024;
+
+/// Bytes a file takes on disk. Where the platform doesn't say, that is its
+/// length.
+pub fn allocated_size(meta: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        meta.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        meta.len()
+    }
+}
+
+/// Writes a new file from the start, leaving holes where whole blocks are
+/// zero. `finish` must be called once everything is written.
+pub(super) struct SparseWriter {
+    file: File,
+    /// The start of a block not yet complete
+    partial: Vec<u8>,
+    /// Data blocks not yet written
+    data: Vec<u8>,
+    /// Zero bytes to seek over before the next write
+    hole: u64,
+    len: u64,
+}
+
+impl SparseWriter {
+    pub(super) fn new(file: File) -> Self {
+        Self {
+            file,
+            partial: Vec::with_capacity(BLOCK),
+            data: Vec::new(),
+            hole: 0,
+            len: 0,
+        }
+    }
+
+    fn push_block(&mut self, block: &[u8]) -> io::Result<()> {
+        if block.iter().all(|b| *b == 0) {
+            self.write_data()?;
+            self.hole += block.len() as u64;
+            return Ok(());
+        }
+        if self.hole > 0 {
+            self.file.seek(SeekFrom::Current(self.hole as i64))?;
+            self.hole = 0;
+        }
+        self.data.extend_from_slice(block);
+        if self.data.len() >= WRITE_BATCH {
+            self.write_data()?;
+        }
+        Ok(())
+    }
+
+    fn write_data(&mut self) -> io::Result<()> {
+        if !self.data.is_empty() {
+            self.file.write_all(&self.data)?;
+            self.data.clear();
+        }
+        Ok(())
+    }
+
+    /// Write what is left and give the file its full length, which a
+    /// trailing hole hasn't set yet
+    pub(super) fn finish(mut self) -> io::Result<File> {
+        if !self.partial.is_empty() {
+            let partial = std::mem::take(&mut self.partial);
+            self.push_block(&partial)?;
+        }
+        self.write_data()?;
+        if self.hole > 0 {
+            self.file.set_len(self.len)?;
+        }
+        Ok(self.file)
+    }
+}
+
+impl Write for SparseWriter {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let written = buf.len();
+        self.len += written as u64;
+        if !self.partial.is_empty() {
+            let take = (BLOCK - self.partial.len()).min(buf.len());
+            self.partial.extend_from_slice(&buf[..take]);
+            buf = &buf[take..];
+            if self.partial.len() < BLOCK {
+                return Ok(written);
+            }
+            let block = std::mem::take(&mut self.partial);
+            self.push_block(&block)?;
+            self.partial = block;
+            self.partial.clear();
+        }
+        let mut blocks = buf.chunks_exact(BLOCK);
+        for block in blocks.by_ref() {
+            self.push_block(block)?;
+        }
+        self.partial.extend_from_slice(blocks.remainder());
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Only whole blocks can be written without knowing what follows
+        self.write_data()
+    }
+}
+
+/// Copy a file, leaving holes where the copy has zero blocks. Returns the
+/// bytes copied, as `fs::copy` does.
+pub(super) fn copy_file(from: &Path, to: &Path) -> io::Result<u64> {
+    let mut source = File::open(from)?;
+    let mut out = SparseWriter::new(File::create(to)?);
+    let mut buf = vec![0u8; WRITE_BATCH];
+    let mut copied = 0;
+    loop {
+        let n = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        out.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    out.finish()?;
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    const MIB: usize = 1024 * 1024;
+
+    /// Whether files in `dir` get holes at all; where they don't, only the
+    /// contents can be checked
+    fn makes_holes(dir: &Path) -> bool {
+        let path = dir.join("hole-probe");
+        let file = File::create(&path).unwrap();
+        file.set_len(MIB as u64).unwrap();
+        let holes = allocated_size(&file.metadata().unwrap()) < MIB as u64;
+        fs::remove_file(&path).unwrap();
+        holes
+    }
+
+    /// `len` bytes that are zero except for one data block in every twenty,
+    /// so 95% zeros
+    fn mostly_zeros(len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        for (i, block) in data.chunks_mut(BLOCK).enumerate().filter(|(i, _)| i % 20 == 0) {
+            block.fill((i % 251) as u8 + 1);
+        }
+        data
+    }
+
+    #[test]
+    fn unaligned_writes_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = mostly_zeros(3 * MIB + 123);
+        // A short data run in the middle of a block, and zeros up to the end
+        data[BLOCK * 7 + 5] = 9;
+        let path = dir.path().join("payload");
+        let mut writer = SparseWriter::new(File::create(&path).unwrap());
+        for piece in data.chunks(1000) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        // Trailing zeros still give the file its full length
+        let zeros = dir.path().join("zeros");
+        let mut writer = SparseWriter::new(File::create(&zeros).unwrap());
+        writer.write_all(&vec![0u8; MIB + 1]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(fs::read(&zeros).unwrap(), vec![0u8; MIB + 1]);
+        if makes_holes(dir.path()) {
+            assert!(allocated_size(&fs::metadata(&zeros).unwrap()) <= BLOCK as u64);
+        }
+
+        let copy = dir.path().join("copy");
+        assert_eq!(copy_file(&path, &copy).unwrap(), data.len() as u64);
+        assert_eq!(fs::read(&copy).unwrap(), data);
+    }
+
+    #[test]
+    fn a_gigabyte_image_of_zeros_takes_a_fraction_of_its_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("disk.img");
+        let mut writer = SparseWriter::new(File::create(&path).unwrap());
+        let chunk = mostly_zeros(MIB);
+        for _ in 0..1024 {
+            writer.write_all(&chunk).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!(meta.len(), 1024 * MIB as u64);
+        if makes_holes(dir.path()) {
+            // One block in twenty holds data; allow for filesystem overhead
+            assert!(allocated_size(&meta) < meta.len() / 10, "{} bytes allocated", allocated_size(&meta));
+        }
+    }
+
+    #[test]
+    fn objects_keep_their_holes_through_reads_and_copies() {
+        let (dir, storage) = engine();
+        storage.create_bucket("images", "local").unwrap();
+        let data = mostly_zeros(16 * MIB);
+        let meta = storage
+            .put_object("images", "vm.img", &data, PutOptions::default())
+            .unwrap();
+        assert_eq!(meta.size, data.len() as u64);
+        assert_eq!(storage.get_object("images", "vm.img").unwrap().1, data);
+
+        storage.copy_object("images", "vm.img", "images", "vm-copy.img").unwrap();
+        assert_eq!(storage.get_object("images", "vm-copy.img").unwrap().1, data);
+
+        let allocated = |key: &str| storage.get_object_meta("images", key).unwrap().allocated_size.unwrap();
+        let bucket = storage.get_bucket("images").unwrap();
+        assert_eq!(bucket.total_size, 2 * data.len() as u64);
+        assert_eq!(bucket.allocated_size, allocated("vm.img") + allocated("vm-copy.img"));
+        if makes_holes(dir.path()) {
+            for key in ["vm.img", "vm-copy.img"] {
+                assert!(allocated(key) < data.len() as u64 / 10, "{}: {} bytes allocated", key, allocated(key));
+            }
+        }
+    }
+}
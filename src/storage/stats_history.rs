@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{StatsPoint, StatsSample};
+
+impl StorageEngine {
+    fn stats_history_path(&self) -> PathBuf {
+        self.root.join(".stats_history.jsonl")
+    }
+
+    fn load_stats_samples(&self) -> Vec<StatsSample> {
+        let Ok(text) = fs::read_to_string(self.stats_history_path()) else {
+            return Vec::new();
+        };
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    /// Append one sample per bucket if `interval` has passed since the last
+    /// one, and drop samples older than `retention`. Counts come from the
+    /// in-memory bucket stats, so sampling never rescans the disk. Returns
+    /// whether a sample was taken.
+    pub fn record_stats_sample(&self, interval: Duration, retention: Duration) -> Result<bool, AppError> {
+        let now = self.now();
+        let samples = self.load_stats_samples();
+        if let Some(last) = samples.iter().map(|s| s.timestamp).max() {
+            if (now - last).to_std().unwrap_or_default() < interval {
+                return Ok(false);
+            }
+        }
+
+        let mut fresh: Vec<StatsSample> = self
+            .buckets
+            .read()
+            .unwrap()
+            .values()
+            .filter(|b| !super::is_system_bucket(&b.name))
+            .map(|b| StatsSample {
+                timestamp: now,
+                bucket: b.name.clone(),
+                object_count: b.object_count,
+                total_size: b.total_size,
+            })
+            .collect();
+        fresh.sort_by(|a, b| a.bucket.cmp(&b.bucket));
+
+        let cutoff = chrono::Duration::from_std(retention)
+            .ok()
+            .and_then(|d| now.checked_sub_signed(d))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+        let path = self.stats_history_path();
+        if samples.iter().any(|s| s.timestamp < cutoff) {
+            // Rewrite without the expired samples, swapping the file in whole
+            let tmp = path.with_extension("jsonl.tmp");
+            let mut out = fs::File::create(&tmp)?;
+            for sample in samples.iter().filter(|s| s.timestamp >= cutoff).chain(&fresh) {
+                writeln!(out, "{}", serde_json::to_string(sample).unwrap())?;
+            }
+            drop(out);
+            self.rename_file(&tmp, &path)?;
+        } else {
+            let mut out = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+            for sample in &fresh {
+                writeln!(out, "{}", serde_json::to_string(sample).unwrap())?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Usage over the last `days`, oldest first. Without a bucket each point
+    /// is the total across all buckets sampled at that time.
+    pub fn stats_history(&self, bucket: Option<&str>, days: u32) -> Result<Vec<StatsPoint>, AppError> {
+        if let Some(name) = bucket {
+            self.get_bucket(name)?;
+        }
+        let cutoff = self.now()
+            .checked_sub_signed(chrono::Duration::days(days as i64))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+        let mut points: BTreeMap<DateTime<Utc>, StatsPoint> = BTreeMap::new();
+        for sample in self.load_stats_samples() {
+            if sample.timestamp < cutoff || matches!(bucket, Some(b) if sample.bucket != b) {
+                continue;
+            }
+            let point = points.entry(sample.timestamp).or_insert(StatsPoint {
+                timestamp: sample.timestamp,
+                object_count: 0,
+                total_size: 0,
+            });
+            point.object_count += sample.object_count;
+            point.total_size += sample.total_size;
+        }
+        Ok(points.into_values().collect())
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::walk::DirStats;
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::Bucket;
+
+/// One change to a bucket's counts: the revision it produced and how far it
+/// moved the object count, total size, and allocated size
+#[derive(Debug, Clone, Copy)]
+pub struct StatsJournalEntry {
+    pub revision: u64,
+    pub count_delta: i64,
+    pub size_delta: i64,
+    pub allocated_delta: i64,
+}
+
+/// Parse a journal file. Reading stops at the first malformed line, which
+/// can only be a write torn by a crash; the flag says whether there was one.
+pub fn read_stats_journal(path: &Path) -> (Vec<StatsJournalEntry>, bool) {
+    let Ok(text) = fs::read_to_string(path) else {
+        return (Vec::new(), false);
+    };
+    let mut entries = Vec::new();
+    for line in text.split_inclusive('\n') {
+        match parse_journal_line(line) {
+            Some(entry) => entries.push(entry),
+            None => return (entries, true),
+        }
+    }
+    (entries, false)
+}
+
+/// A complete `<revision> <count delta> <size delta> <allocated delta>`
+/// line; journals written before allocated sizes were tracked lack the last
+/// field. One without its newline was cut short, even if the numbers
+/// happen to parse.
+fn parse_journal_line(line: &str) -> Option<StatsJournalEntry> {
+    let mut fields = line.strip_suffix('\n')?.split(' ');
+    let entry = StatsJournalEntry {
+        revision: fields.next()?.parse().ok()?,
+        count_delta: fields.next()?.parse().ok()?,
+        size_delta: fields.next()?.parse().ok()?,
+        allocated_delta: match fields.next() {
+            Some(field) => field.parse().ok()?,
+            None => 0,
+        },
+    };
+    fields.next().is_none().then_some(entry)
+}
+
+/// Apply the entries a bucket's metadata doesn't include yet, i.e. those
+/// newer than its revision. Returns how many were applied.
+pub fn replay_stats_journal(bucket: &mut Bucket, entries: &[StatsJournalEntry]) -> usize {
+    let mut applied = 0;
+    for entry in entries {
+        if entry.revision <= bucket.revision {
+            continue;
+        }
+        bucket.object_count = bucket.object_count.saturating_add_signed(entry.count_delta);
+        bucket.total_size = bucket.total_size.saturating_add_signed(entry.size_delta);
+        bucket.allocated_size = bucket.allocated_size.saturating_add_signed(entry.allocated_delta);
+        bucket.revision = entry.revision;
+        applied += 1;
+    }
+    applied
+}
+
+/// Buckets whose journal has entries not yet folded into their metadata
+#[derive(Default)]
+pub(super) struct JournaledBuckets(Mutex<HashSet<String>>);
+
+impl StorageEngine {
+    pub(super) fn stats_journal_path(&self, bucket: &str) -> PathBuf {
+        self.bucket_path(bucket).join(".stats_journal")
+    }
+
+    /// Bring a bucket just loaded from disk up to date with the journal the
+    /// last run left behind
+    pub(super) fn recover_bucket_stats(&self, bucket: &mut Bucket) {
+        let (entries, torn) = read_stats_journal(&self.stats_journal_path(&bucket.name));
+        if torn {
+            tracing::warn!("Ignoring a torn write at the end of the stats journal of bucket {}", bucket.name);
+        }
+        let applied = replay_stats_journal(bucket, &entries);
+        if applied > 0 {
+            tracing::info!("Replayed {} stats journal entries for bucket {}", applied, bucket.name);
+        }
+        // Compacted before anything is appended, which would otherwise
+        // land on the end of a torn line
+        if torn || !entries.is_empty() {
+            self.journaled.0.lock().unwrap().insert(bucket.name.clone());
+        }
+    }
+
+    /// Move a bucket's counts to a fresh recount, appending the change to
+    /// its journal instead of rewriting `.bucket_meta.json`. Called with the
+    /// bucket map locked, so entries land in revision order.
+    pub(super) fn journal_bucket_stats(&self, bucket: &mut Bucket, stats: DirStats) {
+        let entry = StatsJournalEntry {
+            revision: bucket.revision + 1,
+            count_delta: stats.count as i64 - bucket.object_count as i64,
+            size_delta: stats.size as i64 - bucket.total_size as i64,
+            allocated_delta: stats.allocated as i64 - bucket.allocated_size as i64,
+        };
+        bucket.object_count = stats.count;
+        bucket.total_size = stats.size;
+        bucket.allocated_size = stats.allocated;
+        bucket.revision = entry.revision;
+
+        let line = format!(
+            "{} {} {} {}\n",
+            entry.revision, entry.count_delta, entry.size_delta, entry.allocated_delta
+        );
+        let appended = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.stats_journal_path(&bucket.name))
+            .and_then(|mut file| {
+                file.write_all(line.as_bytes())?;
+                // On disk before the write is answered, so a crash loses at
+                // most the entry in flight, which replay sees as torn
+                file.sync_data()
+            });
+        match appended {
+            Ok(()) => {
+                self.journaled.0.lock().unwrap().insert(bucket.name.clone());
+            }
+            Err(e) => {
+                // Fall back to persisting the counts the slow way
+                tracing::warn!("Cannot append to the stats journal of bucket {}: {}", bucket.name, e);
+                let _ = self.write_bucket_meta(bucket);
+            }
+        }
+    }
+
+    /// Write a bucket's metadata, counts included, and drop its journal.
+    /// Settings changes persist through this.
+    pub(super) fn persist_bucket_meta(&self, bucket: &str) -> Result<(), AppError> {
+        let buckets = self.buckets.read().unwrap();
+        let info = buckets
+            .get(bucket)
+            .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+        self.fold_stats_journal(info)?;
+        Ok(())
+    }
+
+    /// The metadata file is swapped in before the journal goes, so a crash
+    /// in between leaves entries the revision check skips on replay
+    fn fold_stats_journal(&self, bucket: &Bucket) -> std::io::Result<()> {
+        self.write_bucket_meta(bucket)?;
+        match fs::remove_file(self.stats_journal_path(&bucket.name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        self.journaled.0.lock().unwrap().remove(&bucket.name);
+        Ok(())
+    }
+
+    /// Fold every pending journal into its bucket's metadata. Runs
+    /// periodically, at startup, and on a clean shutdown. Returns how many
+    /// buckets were compacted.
+    pub fn compact_stats_journals(&self) -> usize {
+        let pending: Vec<String> = self.journaled.0.lock().unwrap().iter().cloned().collect();
+        let mut compacted = 0;
+        for name in pending {
+            // Appends need the write lock, so none land until the journal is gone
+            let buckets = self.buckets.read().unwrap();
+            let Some(bucket) = buckets.get(&name) else {
+                self.journaled.0.lock().unwrap().remove(&name);
+                continue;
+            };
+            match self.fold_stats_journal(bucket) {
+                Ok(()) => compacted += 1,
+                Err(e) => tracing::warn!("Cannot compact the stats journal of bucket {}: {}", name, e),
+            }
+        }
+        compacted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::storage::testing::engine;
+    use crate::storage::PutOptions;
+
+    fn put(storage: &StorageEngine, key: &str) {
+        storage
+            .put_object("docs", key, b"hello", PutOptions::default())
+            .unwrap();
+    }
+
+    fn entry(revision: u64, count_delta: i64, size_delta: i64) -> StatsJournalEntry {
+        StatsJournalEntry {
+            revision,
+            count_delta,
+            size_delta,
+            allocated_delta: 0,
+        }
+    }
+
+    #[test]
+    fn lines_parse_only_when_whole() {
+        let parsed = parse_journal_line("7 -1 -4096 -8192\n").unwrap();
+        assert_eq!(
+            (parsed.revision, parsed.count_delta, parsed.size_delta, parsed.allocated_delta),
+            (7, -1, -4096, -8192)
+        );
+        // Written before allocated sizes were counted
+        assert_eq!(parse_journal_line("7 1 5\n").unwrap().allocated_delta, 0);
+        for line in ["7 1 5 8", "7 1 5 8 9\n", "7 1\n", "x 1 5 8\n", "-7 1 5 8\n", "7  1 5\n", "\n"] {
+            assert!(parse_journal_line(line).is_none(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn replay_stops_at_a_torn_line_and_skips_what_the_metadata_has() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".stats_journal");
+        fs::write(&path, "2 1 5 8\n3 1 7 8\n4 -1 -5 -8\n5 1 9").unwrap();
+        let (entries, torn) = read_stats_journal(&path);
+        assert!(torn);
+        assert_eq!(entries.iter().map(|e| e.revision).collect::<Vec<_>>(), [2, 3, 4]);
+
+        let (_dir, storage) = engine();
+        storage.create_bucket("docs", "local").unwrap();
+        let mut bucket = storage.get_bucket("docs").unwrap();
+        (bucket.object_count, bucket.total_size, bucket.allocated_size, bucket.revision) = (1, 5, 8, 2);
+        assert_eq!(replay_stats_journal(&mut bucket, &entries), 2);
+        assert_eq!((bucket.object_count, bucket.total_size, bucket.allocated_size, bucket.revision), (1, 7, 8, 4));
+        assert_eq!(replay_stats_journal(&mut bucket, &entries), 0, "replaying twice changes nothing");
+
+        // Counts never go below zero, whatever the journal says
+        assert_eq!(replay_stats_journal(&mut bucket, &[entry(5, -3, -100)]), 1);
+        assert_eq!((bucket.object_count, bucket.total_size), (0, 0));
+        assert!(!read_stats_journal(&dir.path().join("missing")).1);
+    }
+
+    #[test]
+    fn writes_are_journaled_and_compacted_into_the_metadata() {
+        let (dir, storage) = engine();
+        storage.create_bucket("docs", "local").unwrap();
+        for key in ["a", "b", "c"] {
+            put(&storage, key);
+        }
+        let journal = storage.stats_journal_path("docs");
+        let (entries, torn) = read_stats_journal(&journal);
+        assert_eq!((entries.len(), torn), (3, false));
+        let saved: Bucket =
+            serde_json::from_slice(&fs::read(dir.path().join("docs/.bucket_meta.json")).unwrap()).unwrap();
+        assert_eq!(saved.object_count, 0, "the metadata file waits for compaction");
+
+        assert_eq!(storage.compact_stats_journals(), 1);
+        assert!(!journal.exists());
+        let saved: Bucket =
+            serde_json::from_slice(&fs::read(dir.path().join("docs/.bucket_meta.json")).unwrap()).unwrap();
+        assert_eq!((saved.object_count, saved.total_size), (3, 15));
+        assert_eq!(storage.compact_stats_journals(), 0, "nothing left to fold");
+    }
+
+    #[test]
+    fn a_restart_replays_the_journal_left_behind() {
+        let (dir, storage) = engine();
+        storage.create_bucket("docs", "local").unwrap();
+        put(&storage, "a");
+        put(&storage, "b");
+        let journal = storage.stats_journal_path("docs");
+        drop(storage);
+        // A crash midway through the next append
+        let mut file = OpenOptions::new().append(true).open(&journal).unwrap();
+        file.write_all(b"99 1 5").unwrap();
+
+        let storage = StorageEngine::new(
+            dir.path().to_str().unwrap(),
+            crate::config::Config::builtin().storage_options(),
+        )
+        .unwrap();
+        let bucket = storage.get_bucket("docs").unwrap();
+        assert_eq!((bucket.object_count, bucket.total_size), (2, 10));
+        // Compacted at startup, so later appends don't land on the torn line
+        put(&storage, "c");
+        let (entries, torn) = read_stats_journal(&journal);
+        assert!(!torn);
+        assert!(entries.iter().all(|e| e.revision != 99), "{:?}", entries);
+        assert_eq!(storage.get_bucket("docs").unwrap().object_count, 3);
+    }
+
+    /// Puts per second with the journal, against rewriting the bucket's
+    /// metadata on every put as was done before it
+    #[test]
+    #[ignore = "benchmark; run with cargo test --release -- --ignored --nocapture"]
+    fn bench_put_throughput() {
+        const PUTS: usize = 2000;
+        let (_dir, storage) = engine();
+        storage.create_bucket("docs", "local").unwrap();
+        let journaled = Instant::now();
+        for i in 0..PUTS {
+            put(&storage, &format!("journaled/{}", i));
+        }
+        let journaled = journaled.elapsed();
+        let rewritten = Instant::now();
+        for i in 0..PUTS {
+            put(&storage, &format!("rewritten/{}", i));
+            storage.persist_bucket_meta("docs").unwrap();
+        }
+        let rewritten = rewritten.elapsed();
+        println!(
+            "{} puts: {:.0}/s journaled, {:.0}/s rewriting the metadata",
+            PUTS,
+            PUTS as f64 / journaled.as_secs_f64(),
+            PUTS as f64 / rewritten.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Recomputing a bucket's object count and size in bounded batches, for
+//! buckets too big to recount in one go after files were changed behind
+//! the server's back. Writes go on as usual while the walk runs; each of
+//! them recounts the bucket itself, so the walk's totals are only swapped
+//! in if no write came in between.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::walk::DirStats;
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{RecomputeStatus, StatsRecompute};
+
+/// Directory entries read per batch
+const BATCH_ENTRIES: usize = 5000;
+
+/// The latest recompute of each bucket, with the flag that cancels it
+#[derive(Default)]
+pub(super) struct Recomputes(Mutex<HashMap<String, (StatsRecompute, Arc<AtomicBool>)>>);
+
+/// A walk in progress. Each `step_stats_recompute` reads one batch.
+pub struct StatsRecount {
+    bucket: String,
+    /// Directories not opened yet, and the one being read
+    dirs: Vec<PathBuf>,
+    current: Option<fs::ReadDir>,
+    stats: DirStats,
+    batches: u64,
+    /// The bucket's revision when the walk started
+    revision: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl StorageEngine {
+    /// Start recomputing a bucket's statistics. Only one recompute per
+    /// bucket runs at a time.
+    pub fn begin_stats_recompute(&self, bucket: &str) -> Result<StatsRecount, AppError> {
+        let info = self.get_bucket(bucket)?;
+        let mut recomputes = self.recomputes.0.lock().unwrap();
+        if let Some((job, _)) = recomputes.get(bucket) {
+            if job.status == RecomputeStatus::Running {
+                return Err(AppError::RecomputeInProgress(bucket.to_string()));
+            }
+        }
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let job = StatsRecompute {
+            bucket: bucket.to_string(),
+            status: RecomputeStatus::Running,
+            recorded_object_count: info.object_count,
+            recorded_total_size: info.total_size,
+            object_count: 0,
+            total_size: 0,
+            batches: 0,
+            directories_left: 1,
+            error: None,
+            started_at: self.now(),
+            finished_at: None,
+        };
+        recomputes.insert(bucket.to_string(), (job, cancelled.clone()));
+        tracing::info!("Recomputing the statistics of bucket {}", bucket);
+        Ok(StatsRecount {
+            bucket: bucket.to_string(),
+            dirs: vec![self.bucket_path(bucket).join("objects")],
+            current: None,
+            stats: DirStats::default(),
+            batches: 0,
+            revision: info.revision,
+            cancelled,
+        })
+    }
+
+    /// Read up to `BATCH_ENTRIES` entries, returning whether there is more
+    /// to read. Stops early once the recompute is cancelled.
+    pub fn step_stats_recompute(&self, recount: &mut StatsRecount) -> bool {
+        let mut read = 0;
+        while read < BATCH_ENTRIES && !recount.cancelled.load(Ordering::Relaxed) {
+            if recount.current.is_none() {
+                let Some(dir) = recount.dirs.pop() else {
+                    break;
+                };
+                // A directory removed by a write since it was found is empty now
+                recount.current = fs::read_dir(dir).ok();
+                continue;
+            }
+            let Some(entry) = recount.current.as_mut().and_then(|entries| entries.next()) else {
+                recount.current = None;
+                continue;
+            };
+            read += 1;
+            let Ok(entry) = entry else { continue };
+            let Ok(file_type) = entry.file_type() else { continue };
+            // Symlinked directories are followed, as the full recount does
+            let path = entry.path();
+            if file_type.is_dir() || (file_type.is_symlink() && path.is_dir()) {
+                recount.dirs.push(path);
+            } else {
+                match entry.metadata() {
+                    Ok(m) => recount.stats.add(&m),
+                    Err(_) => recount.stats.count += 1,
+                }
+            }
+        }
+        recount.batches += 1;
+        let more = !recount.cancelled.load(Ordering::Relaxed) && (recount.current.is_some() || !recount.dirs.is_empty());
+        self.update_recompute(&recount.bucket, |job| {
+            job.object_count = recount.stats.count;
+            job.total_size = recount.stats.size;
+            job.batches = recount.batches;
+            job.directories_left = recount.dirs.len() + usize::from(recount.current.is_some());
+        });
+        more
+    }
+
+    /// End a walk. Unless it was cancelled, its totals replace the
+    /// bucket's in one journaled change, provided no write recounted the
+    /// bucket since the walk started.
+    pub fn finish_stats_recompute(&self, recount: StatsRecount) -> StatsRecompute {
+        let (status, error) = if recount.cancelled.load(Ordering::Relaxed) {
+            (RecomputeStatus::Cancelled, None)
+        } else {
+            let mut buckets = self.buckets.write().unwrap();
+            match buckets.get_mut(&recount.bucket) {
+                None => (
+                    RecomputeStatus::Failed,
+                    Some(AppError::BucketNotFound(recount.bucket.clone()).parts().2),
+                ),
+                Some(bucket) if bucket.revision != recount.revision => (RecomputeStatus::Superseded, None),
+                Some(bucket) => {
+                    self.journal_bucket_stats(bucket, recount.stats);
+                    (RecomputeStatus::Completed, None)
+                }
+            }
+        };
+        let now = self.now();
+        let mut finished = None;
+        self.update_recompute(&recount.bucket, |job| {
+            job.status = status;
+            job.error = error;
+            job.finished_at = Some(now);
+            finished = Some(job.clone());
+        });
+        let job = finished.expect("a recompute is registered until it finishes");
+        tracing::info!(
+            "Statistics recompute of bucket {} ended ({:?}): {} object(s), {} bytes (recorded: {}, {} bytes)",
+            job.bucket,
+            job.status,
+            job.object_count,
+            job.total_size,
+            job.recorded_object_count,
+            job.recorded_total_size
+        );
+        job
+    }
+
+    /// Walk a bucket to the end on the calling thread, calling `progress`
+    /// after every batch
+    pub fn recompute_bucket_stats(
+        &self,
+        bucket: &str,
+        mut progress: impl FnMut(&StatsRecompute),
+    ) -> Result<StatsRecompute, AppError> {
+        let mut recount = self.begin_stats_recompute(bucket)?;
+        while self.step_stats_recompute(&mut recount) {
+            progress(&self.stats_recompute(bucket)?);
+        }
+        Ok(self.finish_stats_recompute(recount))
+    }
+
+    /// The latest recompute of a bucket, running or finished
+    pub fn stats_recompute(&self, bucket: &str) -> Result<StatsRecompute, AppError> {
+        self.recomputes
+            .0
+            .lock()
+            .unwrap()
+            .get(bucket)
+            .map(|(job, _)| job.clone())
+            .ok_or_else(|| AppError::JobNotFound(format!("recompute-stats/{}", bucket)))
+    }
+
+    /// Stop a running recompute after its current batch; the bucket keeps
+    /// its recorded totals
+    pub fn cancel_stats_recompute(&self, bucket: &str) -> Result<StatsRecompute, AppError> {
+        let recomputes = self.recomputes.0.lock().unwrap();
+        let (job, cancelled) = recomputes
+            .get(bucket)
+            .filter(|(job, _)| job.status == RecomputeStatus::Running)
+            .ok_or_else(|| AppError::JobNotFound(format!("recompute-stats/{}", bucket)))?;
+        cancelled.store(true, Ordering::Relaxed);
+        Ok(job.clone())
+    }
+
+    fn update_recompute(&self, bucket: &str, f: impl FnOnce(&mut StatsRecompute)) {
+        if let Some((job, _)) = self.recomputes.0.lock().unwrap().get_mut(bucket) {
+            f(job);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+
+use super::StorageEngine;
+use crate::error::AppError;
+use crate::models::{BucketTraffic, TrafficCounts, TrafficDay, TrafficHour, TrafficKind};
+
+/// Hours of per-hour counts kept in memory, which bounds the longest window
+pub const TRAFFIC_HOURS: usize = 7 * 24;
+
+/// Daily totals by bucket, as stored in `.traffic.json`
+type DailyTotals = HashMap<String, BTreeMap<NaiveDate, TrafficCounts>>;
+
+#[derive(Default)]
+struct Counters {
+    get_requests: AtomicU64,
+    put_requests: AtomicU64,
+    delete_requests: AtomicU64,
+    bytes_served: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl Counters {
+    fn add(&self, kind: TrafficKind, bytes_received: u64, bytes_served: u64) {
+        let requests = match kind {
+            TrafficKind::Get => &self.get_requests,
+            TrafficKind::Put => &self.put_requests,
+            TrafficKind::Delete => &self.delete_requests,
+        };
+        requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes_received, Ordering::Relaxed);
+        self.bytes_served.fetch_add(bytes_served, Ordering::Relaxed);
+    }
+
+    fn add_counts(&self, counts: &TrafficCounts) {
+        self.get_requests.fetch_add(counts.get_requests, Ordering::Relaxed);
+        self.put_requests.fetch_add(counts.put_requests, Ordering::Relaxed);
+        self.delete_requests.fetch_add(counts.delete_requests, Ordering::Relaxed);
+        self.bytes_served.fetch_add(counts.bytes_served, Ordering::Relaxed);
+        self.bytes_received.fetch_add(counts.bytes_received, Ordering::Relaxed);
+    }
+
+    fn read(&self) -> TrafficCounts {
+        TrafficCounts {
+            get_requests: self.get_requests.load(Ordering::Relaxed),
+            put_requests: self.put_requests.load(Ordering::Relaxed),
+            delete_requests: self.delete_requests.load(Ordering::Relaxed),
+            bytes_served: self.bytes_served.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Read and zero the counts
+    fn take(&self) -> TrafficCounts {
+        TrafficCounts {
+            get_requests: self.get_requests.swap(0, Ordering::Relaxed),
+            put_requests: self.put_requests.swap(0, Ordering::Relaxed),
+            delete_requests: self.delete_requests.swap(0, Ordering::Relaxed),
+            bytes_served: self.bytes_served.swap(0, Ordering::Relaxed),
+            bytes_received: self.bytes_received.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// One hour of counts in the ring, tagged with the hour it holds
+#[derive(Default)]
+struct HourSlot {
+    /// Hours since the epoch
+    hour: AtomicI64,
+    counts: Counters,
+}
+
+/// One bucket's counters. Everything is atomic, so requests to the same
+/// bucket never wait on each other.
+struct BucketCounters {
+    /// Indexed by hour modulo `TRAFFIC_HOURS`
+    hours: Vec<HourSlot>,
+    /// Counts not yet added to the daily totals
+    unflushed: Counters,
+}
+
+/// In-memory request and byte counters per bucket. The hourly counts start
+/// over when the server restarts; daily totals are written to
+/// `.traffic.json` by `flush_traffic`, so a crash loses at most one flush
+/// interval of them.
+#[derive(Default)]
+pub(super) struct TrafficTracker {
+    /// Write-locked only to add a bucket seen for the first time
+    buckets: RwLock<HashMap<String, Arc<BucketCounters>>>,
+    /// Totals as of the last flush
+    daily: Mutex<DailyTotals>,
+}
+
+fn hour_index(time: DateTime<Utc>) -> i64 {
+    time.timestamp().div_euclid(60 * 60)
+}
+
+impl StorageEngine {
+    fn traffic_path(&self) -> PathBuf {
+        self.root.join(".traffic.json")
+    }
+
+    /// Load the daily totals written before the last shutdown
+    pub(super) fn load_traffic(&self) {
+        let daily = fs::read(self.traffic_path())
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        *self.traffic.daily.lock().unwrap() = daily;
+    }
+
+    /// Count one request to a bucket. Requests naming a bucket that doesn't
+    /// exist are not counted.
+    pub fn record_traffic(&self, bucket: &str, kind: TrafficKind, bytes_received: u64, bytes_served: u64) {
+        let existing = self.traffic.buckets.read().unwrap().get(bucket).cloned();
+        let counters = match existing {
+            Some(counters) => counters,
+            None => {
+                if !self.buckets.read().unwrap().contains_key(bucket) || super::is_system_bucket(bucket) {
+                    return;
+                }
+                let fresh = BucketCounters {
+                    hours: (0..TRAFFIC_HOURS).map(|_| HourSlot::default()).collect(),
+                    unflushed: Counters::default(),
+                };
+                self.traffic
+                    .buckets
+                    .write()
+                    .unwrap()
+                    .entry(bucket.to_string())
+                    .or_insert_with(|| Arc::new(fresh))
+                    .clone()
+            }
+        };
+
+        let hour = hour_index(self.now());
+        let slot = &counters.hours[hour.rem_euclid(TRAFFIC_HOURS as i64) as usize];
+        let held = slot.hour.load(Ordering::Acquire);
+        // The first request of a new hour claims the slot and clears the
+        // counts it held a week ago. A request racing it may lose its count.
+        if held != hour && slot.hour.compare_exchange(held, hour, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            slot.counts.take();
+        }
+        slot.counts.add(kind, bytes_received, bytes_served);
+        counters.unflushed.add(kind, bytes_received, bytes_served);
+    }
+
+    /// Add the counts gathered since the last flush to today's totals, drop
+    /// days older than `retention_days`, and write `.traffic.json`. Counts
+    /// are dated by the flush, so requests just before midnight can land on
+    /// the next day. Returns how many buckets had new counts.
+    pub fn flush_traffic(&self, retention_days: u64) -> Result<usize, AppError> {
+        let today = self.now().date_naive();
+        let fresh: Vec<(String, TrafficCounts)> = self
+            .traffic
+            .buckets
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, counters)| (name.clone(), counters.unflushed.take()))
+            .filter(|(_, counts)| !counts.is_empty())
+            .collect();
+
+        let mut daily = self.traffic.daily.lock().unwrap();
+        let mut updated = daily.clone();
+        for (bucket, counts) in &fresh {
+            updated.entry(bucket.clone()).or_default().entry(today).or_default().merge(counts);
+        }
+        let cutoff = today
+            .checked_sub_signed(Duration::days(retention_days.min(i32::MAX as u64) as i64))
+            .unwrap_or(NaiveDate::MIN);
+        for days in updated.values_mut() {
+            days.retain(|date, _| *date >= cutoff);
+        }
+        updated.retain(|_, days| !days.is_empty());
+        if fresh.is_empty() && updated == *daily {
+            return Ok(0);
+        }
+
+        let path = self.traffic_path();
+        let written = self.replace_file(&path, &serde_json::to_vec(&updated).unwrap());
+        if let Err(e) = written {
+            // Keep the counts for the next flush to try again
+            let buckets = self.traffic.buckets.read().unwrap();
+            for (bucket, counts) in &fresh {
+                if let Some(counters) = buckets.get(bucket) {
+                    counters.unflushed.add_counts(counts);
+                }
+            }
+            return Err(e.into());
+        }
+        *daily = updated;
+        Ok(fresh.len())
+    }
+
+    /// Hourly counts for the last `hours` hours, oldest first, and daily
+    /// totals for the days the window touches
+    pub fn bucket_traffic(&self, bucket: &str, hours: usize) -> Result<BucketTraffic, AppError> {
+        self.get_bucket(bucket)?;
+        let hours = hours.clamp(1, TRAFFIC_HOURS);
+        let now = self.now();
+        let current = hour_index(now);
+        let counters = self.traffic.buckets.read().unwrap().get(bucket).cloned();
+
+        let mut total = TrafficCounts::default();
+        let points: Vec<TrafficHour> = (current + 1 - hours as i64..=current)
+            .map(|hour| {
+                let counts = counters
+                    .as_ref()
+                    .map(|c| &c.hours[hour.rem_euclid(TRAFFIC_HOURS as i64) as usize])
+                    .filter(|slot| slot.hour.load(Ordering::Acquire) == hour)
+                    .map(|slot| slot.counts.read())
+                    .unwrap_or_default();
+                total.merge(&counts);
+                TrafficHour {
+                    hour: Utc.timestamp_opt(hour * 60 * 60, 0).unwrap(),
+                    counts,
+                }
+            })
+            .collect();
+
+        let first_day = (now - Duration::hours(hours as i64 - 1)).date_naive();
+        let mut days: BTreeMap<NaiveDate, TrafficCounts> = self
+            .traffic
+            .daily
+            .lock()
+            .unwrap()
+            .get(bucket)
+            .map(|days| days.range(first_day..).map(|(d, c)| (*d, *c)).collect())
+            .unwrap_or_default();
+        if let Some(counters) = &counters {
+            days.entry(now.date_naive()).or_default().merge(&counters.unflushed.read());
+        }
+
+        Ok(BucketTraffic {
+            bucket: bucket.to_string(),
+            window_hours: hours,
+            total,
+            hours: points,
+            days: days.into_iter().map(|(date, counts)| TrafficDay { date, counts }).collect(),
+        })
+    }
+
+    /// Drop a deleted bucket's counters and totals
+    pub(super) fn forget_traffic(&self, bucket: &str) {
+        self.traffic.buckets.write().unwrap().remove(bucket);
+        self.traffic.daily.lock().unwrap().remove(bucket);
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{content_type, gc, sparse, EventKind, StorageEngine, TempFile};
+use crate::error::AppError;
+use crate::models::{ObjectMeta, TransactionOperation, TransactionReport, TransactionRequest, TransactionStep};
+
+/// A commit takes milliseconds, so a journal this old that no running
+/// operation owns was left behind by a crash, possibly in another process
+const JOURNAL_MIN_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JournalState {
+    Committing,
+    RollingBack,
+}
+
+/// One key a transaction writes or deletes. Applying and undoing a step are
+/// both safe to repeat, so recovery can redo whichever was interrupted.
+#[derive(Serialize, Deserialize)]
+struct JournalStep {
+    /// Operation number in the request, for error reports
+    op: usize,
+    bucket: String,
+    key: String,
+    /// New payload waiting in the bucket's `.tmp/`; None deletes the key
+    staged: Option<PathBuf>,
+    /// Metadata the key ends up with; None for deletes
+    meta: Option<ObjectMeta>,
+    /// Where the key's previous payload is moved while the commit runs
+    backup: PathBuf,
+    /// The key's metadata before the transaction
+    previous: Option<ObjectMeta>,
+}
+
+/// Written before the first object is touched and removed once the commit
+/// or rollback is complete, so a crash in between can be finished by GC
+#[derive(Serialize, Deserialize)]
+struct Journal {
+    id: String,
+    state: JournalState,
+    steps: Vec<JournalStep>,
+}
+
+/// A write prepared before any locks are taken
+struct Staged<'a> {
+    op: usize,
+    kind: &'static str,
+    bucket: String,
+    key: String,
+    payload: Option<(TempFile<'a>, ObjectMeta)>,
+}
+
+fn failed(op: usize, e: AppError) -> AppError {
+    AppError::TransactionFailed { step: op, reason: e.parts().2 }
+}
+
+fn check_key(key: &str) -> Result<(), AppError> {
+    if key.is_empty() || key.len() > 1024 {
+        return Err(AppError::InvalidObjectKey(
+            "Key must be between 1 and 1024 characters".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl StorageEngine {
+    fn journal_dir(&self) -> PathBuf {
+        self.root.join(".transactions")
+    }
+
+    fn write_journal(&self, path: &Path, journal: &Journal) -> Result<(), AppError> {
+        fs::create_dir_all(self.journal_dir())?;
+        let tmp = path.with_extension("json.tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec_pretty(journal).unwrap())?;
+        file.sync_all()?;
+        self.rename_file(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Apply every operation in `req` or none of them. New payloads are
+    /// staged in temp files first; then, with every key involved locked
+    /// and object reads and listings held off, they are swapped in one by
+    /// one. If any swap fails the ones already done are undone, and the
+    /// error names the operation that failed.
+    pub fn apply_transaction(&self, req: &TransactionRequest) -> Result<TransactionReport, AppError> {
+        if req.operations.is_empty() {
+            return Err(AppError::InvalidRequest("A transaction needs at least one operation".to_string()));
+        }
+        // A malformed request is refused as a whole before anything is staged
+        for (i, operation) in req.operations.iter().enumerate() {
+            if let TransactionOperation::Put { content_type: Some(content_type), .. } = operation {
+                content_type::check_content_type(content_type)
+                    .map_err(|e| AppError::InvalidRequest(format!("Operation {}: {}", i + 1, e.parts().2)))?;
+            }
+        }
+        let mut staged = Vec::new();
+        for (i, operation) in req.operations.iter().enumerate() {
+            self.stage_operation(i + 1, operation, &mut staged)
+                .map_err(|e| failed(i + 1, e))?;
+        }
+        let mut targets = HashSet::new();
+        for step in &staged {
+            if step.payload.is_some() {
+                self.check_unscanned_write(&step.bucket, "writing in a transaction")
+                    .map_err(|e| failed(step.op, e))?;
+            }
+            if !targets.insert((step.bucket.as_str(), step.key.as_str())) {
+                return Err(failed(
+                    step.op,
+                    AppError::InvalidRequest(format!("{}/{} is written more than once", step.bucket, step.key)),
+                ));
+            }
+        }
+
+        let _locks = self.lock_keys(targets);
+        for step in staged.iter().filter(|step| step.payload.is_some()) {
+            self.check_overwrite(&step.bucket, &step.key)
+                .map_err(|e| failed(step.op, e))?;
+        }
+        // Deletes in the transaction make room for its creates. Blamed on the
+        // last create in the bucket, the first that can't fit.
+        let mut growth: HashMap<&str, (i64, usize)> = HashMap::new();
+        for step in &staged {
+            let exists = self.object_path(&step.bucket, &step.key).is_file();
+            let entry = growth.entry(step.bucket.as_str()).or_default();
+            match (&step.payload, exists) {
+                (Some(_), false) => *entry = (entry.0 + 1, step.op),
+                (None, true) => entry.0 -= 1,
+                _ => {}
+            }
+        }
+        for (bucket, (adding, op)) in growth {
+            if adding > 0 {
+                self.check_object_limit(bucket, adding as u64).map_err(|e| failed(op, e))?;
+            }
+        }
+
+        let id = self.new_id().to_string();
+        let mut backups = Vec::new();
+        let mut journal = Journal {
+            id: id.clone(),
+            state: JournalState::Committing,
+            steps: Vec::new(),
+        };
+        for step in &staged {
+            let backup = self.temp_file(&step.bucket).map_err(|e| failed(step.op, e))?;
+            journal.steps.push(JournalStep {
+                op: step.op,
+                bucket: step.bucket.clone(),
+                key: step.key.clone(),
+                staged: step.payload.as_ref().map(|(tmp, _)| tmp.path.clone()),
+                meta: step.payload.as_ref().map(|(_, meta)| meta.clone()),
+                backup: backup.path.clone(),
+                previous: self.get_object_meta(&step.bucket, &step.key).ok(),
+            });
+            backups.push(backup);
+        }
+        let journal_path = self.journal_dir().join(format!("{}.json", id));
+        self.active_temp.lock().unwrap().insert(journal_path.clone());
+        let result = self.commit_journal(&journal_path, &mut journal);
+        // Whatever happened, the journal has served its purpose
+        let _ = fs::remove_file(&journal_path);
+        self.active_temp.lock().unwrap().remove(&journal_path);
+        result?;
+
+        // Bookkeeping that doesn't change what readers see
+        let mut buckets = BTreeSet::new();
+        for step in &journal.steps {
+            let recorded = match &step.meta {
+                Some(meta) => self.record_version(meta),
+                None if step.previous.is_some() => {
+                    self.forget_access(&step.bucket, &step.key);
+                    let objects_root = self.bucket_path(&step.bucket).join("objects");
+                    if let Some(parent) = self.object_path(&step.bucket, &step.key).parent() {
+                        Self::cleanup_empty_dirs(parent, &objects_root);
+                    }
+                    self.record_delete_marker(&step.bucket, &step.key)
+                }
+                None => Ok(()),
+            };
+            if let Err(e) = recorded {
+                tracing::warn!("Transaction {}: cannot record history of {}/{}: {:?}", id, step.bucket, step.key, e);
+            }
+            buckets.insert(step.bucket.as_str());
+        }
+        for bucket in buckets {
+            self.update_bucket_stats(bucket)?;
+        }
+        drop(backups);
+        for step in &journal.steps {
+            match &step.meta {
+                Some(meta) => self.object_created(EventKind::Put, meta),
+                None if step.previous.is_some() => self.object_removed(&step.bucket, &step.key),
+                None => {}
+            }
+        }
+
+        tracing::info!("Committed transaction {} ({} step(s))", id, staged.len());
+        Ok(TransactionReport {
+            id,
+            steps: staged
+                .into_iter()
+                .map(|step| TransactionStep {
+                    op: step.kind.to_string(),
+                    bucket: step.bucket,
+                    key: step.key,
+                    etag: step.payload.map(|(_, meta)| meta.etag),
+                })
+                .collect(),
+        })
+    }
+
+    /// Prepare one operation: validate it and write any new payload to a
+    /// temp file in the target bucket. A put from a staging key also
+    /// schedules the staging key's delete.
+    fn stage_operation<'a>(
+        &'a self,
+        op: usize,
+        operation: &TransactionOperation,
+        staged: &mut Vec<Staged<'a>>,
+    ) -> Result<(), AppError> {
+        match operation {
+            TransactionOperation::Put {
+                bucket,
+                key,
+                body,
+                body_base64,
+                staging_bucket,
+                staging_key,
+                content_type,
+            } => {
+                self.get_bucket(bucket)?;
+                self.check_new_key(key)?;
+                let key = &self.normalize_key(key).into_owned();
+                let (tmp, meta) = match (body, body_base64, staging_bucket, staging_key) {
+                    (Some(body), None, None, None) => self.stage_bytes(bucket, key, body.as_bytes(), content_type)?,
+                    (None, Some(encoded), None, None) => {
+                        let data = base64::engine::general_purpose::STANDARD
+                            .decode(encoded.trim())
+                            .map_err(|e| AppError::InvalidRequest(format!("body_base64 is not valid base64: {}", e)))?;
+                        self.stage_bytes(bucket, key, &data, content_type)?
+                    }
+                    (None, None, Some(source_bucket), Some(source_key)) => {
+                        let (tmp, mut meta) = self.stage_copy(source_bucket, source_key, bucket, key)?;
+                        if let Some(content_type) = content_type {
+                            meta.content_type = content_type.clone();
+                        }
+                        staged.push(Staged {
+                            op,
+                            kind: "delete",
+                            bucket: source_bucket.clone(),
+                            key: source_key.clone(),
+                            payload: None,
+                        });
+                        (tmp, meta)
+                    }
+                    _ => {
+                        return Err(AppError::InvalidRequest(
+                            "A put needs exactly one of body, body_base64, or staging_bucket with staging_key"
+                                .to_string(),
+                        ))
+                    }
+                };
+                staged.push(Staged {
+                    op,
+                    kind: "put",
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    payload: Some((tmp, meta)),
+                });
+            }
+            TransactionOperation::Copy {
+                source_bucket,
+                source_key,
+                bucket,
+                key,
+            } => {
+                self.get_bucket(bucket)?;
+                self.check_new_key(key)?;
+                let key = &self.normalize_key(key).into_owned();
+                let payload = self.stage_copy(source_bucket, source_key, bucket, key)?;
+                staged.push(Staged {
+                    op,
+                    kind: "copy",
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    payload: Some(payload),
+                });
+            }
+            TransactionOperation::Delete { bucket, key } => {
+                self.get_bucket(bucket)?;
+                check_key(key)?;
+                let key = &self.normalize_key(key).into_owned();
+                staged.push(Staged {
+                    op,
+                    kind: "delete",
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    payload: None,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn stage_bytes(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: &[u8],
+        content_type: &Option<String>,
+    ) -> Result<(TempFile<'_>, ObjectMeta), AppError> {
+        let tmp = self.temp_file(bucket)?;
+        fs::write(tmp.path(), data)?;
+        let meta = ObjectMeta {
+            key: key.to_string(),
+            bucket: bucket.to_string(),
+            size: data.len() as u64,
+            content_type: content_type
+                .clone()
+                .unwrap_or_else(|| content_type::detect_content_type(key, data)),
+            etag: hex::encode(Sha256::digest(data)),
+            last_modified: self.now(),
+            metadata: Default::default(),
+            parts: Vec::new(),
+            checksum: None,
+            original_filename: None,
+            website_redirect_location: None,
+            source_mtime: None,
+            etag_provisional: false,
+            origin: None,
+            allocated_size: None,
+        };
+        Ok((tmp, meta))
+    }
+
+    /// Copy an object's payload into a temp file of `dst_bucket`, keeping
+    /// its metadata as `copy_object_files` does
+    fn stage_copy(
+        &self,
+        src_bucket: &str,
+        src_key: &str,
+        dst_bucket: &str,
+        dst_key: &str,
+    ) -> Result<(TempFile<'_>, ObjectMeta), AppError> {
+        self.get_bucket(src_bucket)?;
+        let _reading = self.begin_read(src_bucket, src_key);
+        let src_meta = self.get_object_meta(src_bucket, src_key)?;
+        let tmp = self.temp_file(dst_bucket)?;
+        sparse::copy_file(&self.object_path(src_bucket, src_key), tmp.path())?;
+        let meta = ObjectMeta {
+            key: dst_key.to_string(),
+            bucket: dst_bucket.to_string(),
+            last_modified: self.now(),
+            etag: self.final_etag(&src_meta, tmp.path())?,
+            etag_provisional: false,
+            origin: None,
+            ..src_meta
+        };
+        Ok((tmp, meta))
+    }
+
+    /// Journal the steps, then swap them in with readers held off. On
+    /// failure the journal is marked as rolling back before anything is
+    /// undone, so a crash during the rollback finishes it rather than the
+    /// commit.
+    fn commit_journal(&self, path: &Path, journal: &mut Journal) -> Result<(), AppError> {
+        self.write_journal(path, journal)?;
+        let _gate = self.commit_gate.write().unwrap();
+        for step in &journal.steps {
+            if let Err(e) = self.redo_step(step) {
+                tracing::warn!(
+                    "Transaction {} failed at {}/{}; rolling back: {:?}",
+                    journal.id,
+                    step.bucket,
+                    step.key,
+                    e
+                );
+                journal.state = JournalState::RollingBack;
+                self.write_journal(path, journal)?;
+                for step in journal.steps.iter().rev() {
+                    self.undo_step(step)?;
+                }
+                return Err(failed(step.op, e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Put a step's new payload and metadata in place, moving the old
+    /// payload to the step's backup. Steps already applied are left as
+    /// they are, apart from rewriting their metadata.
+    fn redo_step(&self, step: &JournalStep) -> Result<(), AppError> {
+        let live = self.object_path(&step.bucket, &step.key);
+        match &step.staged {
+            Some(staged) if staged.exists() => {
+                if live.is_file() {
+                    self.rename_file(&live, &step.backup)?;
+                }
+                if let Some(parent) = live.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                self.rename_file(staged, &live)?;
+            }
+            Some(_) => {}
+            None => {
+                if live.is_file() {
+                    self.rename_file(&live, &step.backup)?;
+                }
+            }
+        }
+        match &step.meta {
+            Some(meta) => self.write_meta_file(meta),
+            None => self.drop_meta_file(&step.bucket, &step.key),
+        }
+    }
+
+    /// Put back what a step replaced or deleted, or remove what it created
+    fn undo_step(&self, step: &JournalStep) -> Result<(), AppError> {
+        let live = self.object_path(&step.bucket, &step.key);
+        if step.backup.exists() {
+            if let Some(parent) = live.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            self.rename_file(&step.backup, &live)?;
+            return match &step.previous {
+                Some(meta) => self.write_meta_file(meta),
+                None => self.drop_meta_file(&step.bucket, &step.key),
+            };
+        }
+        let created = step.staged.as_ref().is_some_and(|staged| !staged.exists());
+        if created && step.previous.is_none() && live.is_file() {
+            fs::remove_file(&live)?;
+            self.drop_meta_file(&step.bucket, &step.key)?;
+        }
+        Ok(())
+    }
+
+    fn drop_meta_file(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        let stored = self.stored_key(bucket, key).into_owned();
+        let meta_path = self.object_meta_path(bucket, key);
+        if meta_path.exists() {
+            fs::remove_file(&meta_path)?;
+        }
+        self.hash_index.write().unwrap().remove(bucket, key, self.now());
+        self.read_cache.forget(bucket, &stored);
+        Ok(())
+    }
+
+    /// Finish transactions a crash interrupted: ones that were committing
+    /// are carried through, ones that were rolling back are undone.
+    /// Returns how many were (or, in a dry run, would be) finished.
+    pub(super) fn recover_transactions(&self, dry_run: bool) -> u64 {
+        let Ok(entries) = fs::read_dir(self.journal_dir()) else {
+            return 0;
+        };
+        let mut recovered = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json")
+                || self.is_temp_active(&path)
+                || !gc::older_than(&path, JOURNAL_MIN_AGE)
+            {
+                continue;
+            }
+            let journal = match fs::read(&path).map(|data| serde_json::from_slice::<Journal>(&data)) {
+                Ok(Ok(journal)) => journal,
+                _ => {
+                    tracing::warn!("Skipping unreadable transaction journal {}", path.display());
+                    continue;
+                }
+            };
+            recovered += 1;
+            if dry_run {
+                continue;
+            }
+
+            let result = match journal.state {
+                JournalState::Committing => journal.steps.iter().try_for_each(|step| self.redo_step(step)),
+                JournalState::RollingBack => journal.steps.iter().rev().try_for_each(|step| self.undo_step(step)),
+            };
+            if let Err(e) = result {
+                tracing::warn!("Cannot finish transaction {}: {:?}", journal.id, e);
+                continue;
+            }
+            let buckets: BTreeSet<&str> = journal.steps.iter().map(|step| step.bucket.as_str()).collect();
+            for bucket in buckets {
+                if self.get_bucket(bucket).is_ok() {
+                    let _ = self.update_bucket_stats(bucket);
+                }
+            }
+            let _ = fs::remove_file(&path);
+            tracing::info!(
+                "Finished interrupted transaction {} ({})",
+                journal.id,
+                if journal.state == JournalState::Committing { "committed" } else { "rolled back" }
+            );
+        }
+        recovered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::AppError;
+    use crate::models::TransactionRequest;
+    use crate::storage::testing;
+
+    fn request(json: serde_json::Value) -> TransactionRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn commits_every_operation() {
+        let (_dir, engine) = testing::engine();
+        engine.create_bucket("alpha", "local").unwrap();
+        let report = engine
+            .apply_transaction(&request(serde_json::json!({"operations": [
+                {"op": "put", "bucket": "alpha", "key": "a.txt", "body": "one"},
+                {"op": "put", "bucket": "alpha", "key": "b.txt", "body": "two", "content_type": "text/x-two"},
+            ]})))
+            .unwrap();
+        assert_eq!(report.steps.len(), 2);
+        assert_eq!(engine.get_object("alpha", "a.txt").unwrap().1, b"one");
+        assert_eq!(engine.get_object_meta("alpha", "b.txt").unwrap().content_type, "text/x-two");
+    }
+
+    #[test]
+    fn invalid_content_type_rejects_the_whole_transaction() {
+        let (_dir, engine) = testing::engine();
+        engine.create_bucket("alpha", "local").unwrap();
+        let err = engine
+            .apply_transaction(&request(serde_json::json!({"operations": [
+                {"op": "put", "bucket": "alpha", "key": "a.txt", "body": "one"},
+                {"op": "put", "bucket": "alpha", "key": "b.txt", "body": "two", "content_type": "a\u{1}b"},
+            ]})))
+            .unwrap_err();
+        assert!(matches!(&err, AppError::InvalidRequest(reason) if reason.starts_with("Operation 2")), "{:?}", err);
+        assert!(engine.get_object_meta("alpha", "a.txt").is_err());
+    }
+
+    #[test]
+    fn a_failing_operation_applies_nothing() {
+        let (_dir, engine) = testing::engine();
+        engine.create_bucket("alpha", "local").unwrap();
+        let err = engine
+            .apply_transaction(&request(serde_json::json!({"operations": [
+                {"op": "put", "bucket": "alpha", "key": "a.txt", "body": "one"},
+                {"op": "copy", "source_bucket": "alpha", "source_key": "missing", "bucket": "alpha", "key": "c.txt"},
+            ]})))
+            .unwrap_err();
+        assert!(matches!(err, AppError::TransactionFailed { step: 2, .. }), "{:?}", err);
+        assert!(engine.get_object_meta("alpha", "a.txt").is_err());
+    }
+}
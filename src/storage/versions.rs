@@ -0,0 +1,301 @@
+use std::fs;
+use std::path::PathBuf;
+
+use super::{sparse, ChecksumRequest, PutOptions, StorageEngine};
+use crate::error::AppError;
+use crate::models::{Bucket, ListVersionsResponse, ObjectMeta, ObjectVersion, OverwritePolicy};
+
+impl StorageEngine {
+    /// Version IDs sort chronologically, so the newest version is the largest
+    fn new_version_id(&self) -> String {
+        format!(
+            "{}-{}",
+            self.now().format("%Y%m%dT%H%M%S%6fZ"),
+            &self.new_id().simple().to_string()[..8]
+        )
+    }
+
+    /// Every version of a key lives in `.versions/<key>/`: a `<id>` payload
+    /// (hard linked to the object it was written as) next to `<id>.json`
+    fn versions_dir(&self, bucket: &str, key: &str) -> PathBuf {
+        let flat = super::key_paths::flat_name(&self.stored_key(bucket, key));
+        self.bucket_path(bucket).join(".versions").join(flat)
+    }
+
+    fn version_path(&self, bucket: &str, key: &str, version_id: &str) -> Result<PathBuf, AppError> {
+        // Version IDs are generated by us; reject anything that could escape the versions dir
+        if version_id.is_empty()
+            || !version_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(AppError::VersionNotFound {
+                key: key.to_string(),
+                version_id: version_id.to_string(),
+            });
+        }
+        Ok(self.versions_dir(bucket, key).join(version_id))
+    }
+
+    pub(super) fn is_versioned(&self, bucket: &str) -> bool {
+        self.buckets
+            .read()
+            .unwrap()
+            .get(bucket)
+            .is_some_and(|b| b.versioning)
+    }
+
+    fn write_version(&self, bucket: &str, version: &ObjectVersion) -> Result<(), AppError> {
+        let path = self.version_path(bucket, &version.key, &version.version_id)?;
+        let json = serde_json::to_string_pretty(version).unwrap();
+        fs::write(path.with_extension("json"), json)?;
+        Ok(())
+    }
+
+    /// Give the version recorded with a provisional ETag the hash its
+    /// object just got
+    pub(super) fn finalize_version_etag(&self, meta: &ObjectMeta, provisional: &str) -> Result<(), AppError> {
+        if !self.is_versioned(&meta.bucket) {
+            return Ok(());
+        }
+        let recorded = self.load_versions(&meta.bucket, &meta.key);
+        for mut version in recorded.into_iter().filter(|v| v.etag_provisional && v.etag == provisional) {
+            version.etag = meta.etag.clone();
+            version.etag_provisional = false;
+            self.write_version(&meta.bucket, &version)?;
+        }
+        Ok(())
+    }
+
+    /// Keep the object just written as a new version. Writes replace the live
+    /// file by rename, so the hard link keeps this version's bytes intact.
+    pub(super) fn record_version(&self, meta: &ObjectMeta) -> Result<(), AppError> {
+        if !self.is_versioned(&meta.bucket) {
+            return Ok(());
+        }
+        let version = ObjectVersion::from_meta(meta, self.new_version_id());
+        let path = self.version_path(&meta.bucket, &meta.key, &version.version_id)?;
+        fs::create_dir_all(self.versions_dir(&meta.bucket, &meta.key))?;
+        let live = self.object_path(&meta.bucket, &meta.key);
+        if !self.fs_features.hard_links || fs::hard_link(&live, &path).is_err() {
+            sparse::copy_file(&live, &path)?;
+        }
+        self.write_version(&meta.bucket, &version)
+    }
+
+    pub(super) fn record_delete_marker(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        if !self.is_versioned(bucket) {
+            return Ok(());
+        }
+        fs::create_dir_all(self.versions_dir(bucket, key))?;
+        let marker = ObjectVersion {
+            version_id: self.new_version_id(),
+            key: key.to_string(),
+            size: 0,
+            content_type: String::new(),
+            etag: String::new(),
+            last_modified: self.now(),
+            metadata: Default::default(),
+            parts: Vec::new(),
+            checksum: None,
+            original_filename: None,
+            website_redirect_location: None,
+            source_mtime: None,
+            etag_provisional: false,
+            is_delete_marker: true,
+            is_latest: false,
+        };
+        self.write_version(bucket, &marker)
+    }
+
+    /// All recorded versions of a key, newest first
+    fn load_versions(&self, bucket: &str, key: &str) -> Vec<ObjectVersion> {
+        let mut versions = Vec::new();
+        if let Ok(entries) = fs::read_dir(self.versions_dir(bucket, key)) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                match fs::read_to_string(&path)
+                    .map(|json| serde_json::from_str::<ObjectVersion>(&json))
+                {
+                    Ok(Ok(version)) => versions.push(version),
+                    Ok(Err(e)) => tracing::warn!("Skipping corrupt version {:?}: {}", path, e),
+                    Err(_) => {}
+                }
+            }
+        }
+        versions.sort_by(|a, b| b.version_id.cmp(&a.version_id));
+        if let Some(latest) = versions.first_mut() {
+            latest.is_latest = true;
+        }
+        versions
+    }
+
+    fn load_version(&self, bucket: &str, key: &str, version_id: &str) -> Result<ObjectVersion, AppError> {
+        self.get_bucket(bucket)?;
+        let path = self.version_path(bucket, key, version_id)?.with_extension("json");
+        let json = fs::read_to_string(&path).map_err(|_| AppError::VersionNotFound {
+            key: key.to_string(),
+            version_id: version_id.to_string(),
+        })?;
+        serde_json::from_str(&json)
+            .map_err(|e| AppError::StorageError(format!("Corrupt version manifest: {}", e)))
+    }
+
+    /// Turn versioning on or off. Enabling records the current contents of
+    /// every object without history, so nothing written before is lost on
+    /// the next overwrite. Suspending keeps existing versions.
+    pub fn set_bucket_versioning(&self, bucket: &str, enabled: bool) -> Result<Bucket, AppError> {
+        let was_enabled = {
+            let mut buckets = self.buckets.write().unwrap();
+            let info = buckets
+                .get_mut(bucket)
+                .ok_or_else(|| AppError::BucketNotFound(bucket.to_string()))?;
+            if !enabled && info.overwrite_policy == OverwritePolicy::Version {
+                return Err(AppError::InvalidRequest(format!(
+                    "Bucket '{}' keeps replaced objects as versions; change its overwrite policy before suspending versioning",
+                    bucket
+                )));
+            }
+            std::mem::replace(&mut info.versioning, enabled)
+        };
+        // Persists the flag along with the stats
+        self.persist_bucket_meta(bucket)?;
+
+        if enabled && !was_enabled {
+            self.visit_objects(bucket, "", |meta| {
+                if self.load_versions(bucket, &meta.key).is_empty() {
+                    self.record_version(&meta)?;
+                }
+                Ok(())
+            })?;
+        }
+        tracing::info!(
+            "Versioning {} for bucket {}",
+            if enabled { "enabled" } else { "suspended" },
+            bucket
+        );
+        self.get_bucket(bucket)
+    }
+
+    pub fn list_object_versions(&self, bucket: &str, key: &str) -> Result<ListVersionsResponse, AppError> {
+        self.get_bucket(bucket)?;
+        Ok(ListVersionsResponse {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            versions: self.load_versions(bucket, key),
+        })
+    }
+
+    /// One version's metadata, without reading its payload
+    pub fn get_object_version_meta(&self, bucket: &str, key: &str, version_id: &str) -> Result<ObjectVersion, AppError> {
+        let version = self.load_version(bucket, key, version_id)?;
+        if version.is_delete_marker {
+            return Err(AppError::InvalidRequest(format!(
+                "Version '{}' is a delete marker and has no content",
+                version_id
+            )));
+        }
+        Ok(version)
+    }
+
+    pub fn get_object_version(
+        &self,
+        bucket: &str,
+        key: &str,
+        version_id: &str,
+    ) -> Result<(ObjectVersion, Vec<u8>), AppError> {
+        let version = self.get_object_version_meta(bucket, key, version_id)?;
+        let data = fs::read(self.version_path(bucket, key, version_id)?)?;
+        Ok((version, data))
+    }
+
+    /// Make an older version the latest by writing its content back onto the key
+    pub fn restore_object_version(
+        &self,
+        bucket: &str,
+        key: &str,
+        version_id: &str,
+    ) -> Result<ObjectMeta, AppError> {
+        let (version, data) = self.get_object_version(bucket, key, version_id)?;
+        // The restored copy is a new full-object write, so recompute its checksum
+        let checksum = version.checksum.map(|c| ChecksumRequest {
+            algorithm: c.algorithm,
+            expected: None,
+        });
+        let meta = self.put_object(
+            bucket,
+            key,
+            &data,
+            PutOptions {
+                content_type: Some(&version.content_type),
+                metadata: version.metadata,
+                checksum: checksum.as_ref(),
+                original_filename: version.original_filename.as_deref(),
+                website_redirect_location: version.website_redirect_location.as_deref(),
+                source_mtime: version.source_mtime,
+            },
+        )?;
+        tracing::info!("Restored {}/{} to version {}", bucket, key, version_id);
+        Ok(meta)
+    }
+
+    /// Permanently delete one version. Removing the latest version promotes
+    /// the next newest one to the live object (or removes the live object if
+    /// that is a delete marker or nothing is left).
+    pub fn delete_object_version(&self, bucket: &str, key: &str, version_id: &str) -> Result<(), AppError> {
+        let _lock = self.lock_key(bucket, key);
+        self.load_version(bucket, key, version_id)?;
+        let was_latest = self
+            .load_versions(bucket, key)
+            .first()
+            .is_some_and(|v| v.version_id == version_id);
+
+        let path = self.version_path(bucket, key, version_id)?;
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        fs::remove_file(path.with_extension("json"))?;
+        // Only succeeds once the last version is gone
+        let _ = fs::remove_dir(self.versions_dir(bucket, key));
+
+        if was_latest {
+            self.promote_latest_version(bucket, key)?;
+            self.update_bucket_stats(bucket)?;
+        }
+        tracing::info!("Deleted version {} of {}/{}", version_id, bucket, key);
+        Ok(())
+    }
+
+    fn promote_latest_version(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        let latest = self.load_versions(bucket, key).into_iter().next();
+        match latest {
+            Some(version) if !version.is_delete_marker => {
+                let tmp = self.temp_file(bucket)?;
+                sparse::copy_file(&self.version_path(bucket, key, &version.version_id)?, tmp.path())?;
+                tmp.commit(&self.object_path(bucket, key))?;
+                self.write_meta_file(&ObjectMeta {
+                    key: key.to_string(),
+                    bucket: bucket.to_string(),
+                    size: version.size,
+                    content_type: version.content_type,
+                    etag: version.etag,
+                    last_modified: version.last_modified,
+                    metadata: version.metadata,
+                    parts: version.parts,
+                    checksum: version.checksum,
+                    original_filename: version.original_filename,
+                    website_redirect_location: version.website_redirect_location,
+                    source_mtime: version.source_mtime,
+                    etag_provisional: version.etag_provisional,
+                    origin: None,
+                    allocated_size: None,
+                })
+            }
+            _ => match self.unlink_object(bucket, key) {
+                Err(AppError::ObjectNotFound { .. }) => Ok(()),
+                other => other,
+            },
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Storage roots besides the data dir. A bucket lives wholly on one volume:
+//! its objects, metadata, versions, snapshots, and scratch files all sit
+//! under `<volume root>/<bucket>`, so renames never cross filesystems. The
+//! data dir is the volume named `default`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{sparse, StorageEngine};
+use crate::size::human_readable_size;
+use crate::error::AppError;
+use crate::models::{Bucket, VolumeStats};
+
+/// Name of the data dir when treated as a volume
+pub const DEFAULT_VOLUME: &str = "default";
+
+/// Validate the configured volumes. A missing directory is logged but kept:
+/// it is most likely an unplugged drive, and creating it would put its
+/// buckets on the wrong disk.
+pub(super) fn check_volumes(volumes: Vec<(String, PathBuf)>) -> Result<BTreeMap<String, PathBuf>, AppError> {
+    let mut checked = BTreeMap::new();
+    for (name, path) in volumes {
+        let valid = !name.is_empty()
+            && name != DEFAULT_VOLUME
+            && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !valid {
+            return Err(AppError::StorageError(format!(
+                "Invalid volume name '{}': use lowercase letters, digits, and hyphens, and not '{}'",
+                name, DEFAULT_VOLUME
+            )));
+        }
+        if !path.is_dir() {
+            tracing::error!("Volume {} at {} is missing; its buckets are unavailable", name, path.display());
+        }
+        if checked.insert(name.clone(), path).is_some() {
+            return Err(AppError::StorageError(format!("Volume '{}' is configured twice", name)));
+        }
+    }
+    Ok(checked)
+}
+
+/// `None` for the data dir, however it was spelt
+fn volume_name(volume: Option<&str>) -> Option<&str> {
+    volume.filter(|v| *v != DEFAULT_VOLUME)
+}
+
+impl StorageEngine {
+    /// Root directory of a volume; `None` is the data dir
+    pub(super) fn volume_root(&self, volume: Option<&str>) -> Result<&Path, AppError> {
+        let Some(name) = volume_name(volume) else {
+            return Ok(&self.root);
+        };
+        self.volumes.get(name).map(PathBuf::as_path).ok_or_else(|| {
+            let known: Vec<&str> = std::iter::once(DEFAULT_VOLUME)
+                .chain(self.volumes.keys().map(String::as_str))
+                .collect();
+            AppError::InvalidRequest(format!("Unknown volume '{}'; configured: {}", name, known.join(", ")))
+        })
+    }
+
+    /// Volumes whose directory is present, the data dir first
+    pub(super) fn available_volumes(&self) -> Vec<(Option<String>, PathBuf)> {
+        std::iter::once((None, self.root.clone()))
+            .chain(
+                self.volumes
+                    .iter()
+                    .filter(|(_, path)| path.is_dir())
+                    .map(|(name, path)| (Some(name.clone()), path.clone())),
+            )
+            .collect()
+    }
+
+    /// Root of a volume that is mounted and can take buckets
+    fn available_root(&self, volume: Option<&str>) -> Result<PathBuf, AppError> {
+        let root = self.volume_root(volume)?.to_path_buf();
+        if !root.is_dir() {
+            return Err(AppError::StorageError(format!(
+                "Volume '{}' at {} is not available",
+                volume.unwrap_or(DEFAULT_VOLUME),
+                root.display()
+            )));
+        }
+        Ok(root)
+    }
+
+    /// Where a new bucket goes. Refuses a name with a directory on any other
+    /// volume, so a bucket can never end up split across two.
+    pub(super) fn new_bucket_root(&self, name: &str, volume: Option<&str>) -> Result<PathBuf, AppError> {
+        let root = self.available_root(volume)?;
+        for (other, other_root) in self.available_volumes() {
+            if other_root != root && other_root.join(name).exists() {
+                return Err(already_on_volume(name, other.as_deref()));
+            }
+        }
+        Ok(root)
+    }
+
+    /// Record which root a bucket lives under, for `bucket_path`
+    pub(super) fn set_bucket_root(&self, name: &str, volume: Option<&str>, root: &Path) {
+        let mut roots = self.bucket_roots.write().unwrap();
+        if volume_name(volume).is_some() {
+            roots.insert(name.to_string(), root.to_path_buf());
+        } else {
+            roots.remove(name);
+        }
+    }
+
+    /// Whether a volume holds a bucket of this name
+    pub(super) fn volume_has_bucket(&self, volume: Option<&str>, name: &str) -> bool {
+        self.volume_root(volume)
+            .map(|root| root.join(name).join(".bucket_meta.json").is_file())
+            .unwrap_or(false)
+    }
+
+    /// Usage of each volume from the in-memory bucket counters, and the free
+    /// space of the filesystem under it
+    pub fn volume_stats(&self) -> Vec<VolumeStats> {
+        let buckets = self.buckets.read().unwrap();
+        let volumes = std::iter::once((DEFAULT_VOLUME, &self.root))
+            .chain(self.volumes.iter().map(|(name, path)| (name.as_str(), path)));
+        volumes
+            .map(|(name, path)| {
+                let on_volume: Vec<&Bucket> = buckets
+                    .values()
+                    .filter(|b| volume_name(b.volume.as_deref()) == volume_name(Some(name)))
+                    .filter(|b| !super::is_system_bucket(&b.name))
+                    .collect();
+                let total_size = on_volume.iter().map(|b| b.total_size).sum();
+                let space = disk_space(path);
+                VolumeStats {
+                    name: name.to_string(),
+                    path: path.display().to_string(),
+                    available: path.is_dir(),
+                    bucket_count: on_volume.len() as u64,
+                    object_count: on_volume.iter().map(|b| b.object_count).sum(),
+                    total_size,
+                    total_size_human: human_readable_size(total_size),
+                    free_bytes: space.map(|(free, _)| free),
+                    capacity_bytes: space.map(|(_, capacity)| capacity),
+                }
+            })
+            .collect()
+    }
+
+    /// Move a bucket to another volume. Its directory is copied into a
+    /// hidden staging directory on the target and renamed into place, so the
+    /// bucket is never half there. Before the switch the old copy's metadata
+    /// is pointed at the target; a scan that finds both copies after a crash
+    /// then knows which one to ignore. Every bucket operation waits until the
+    /// move is done.
+    pub fn move_bucket(&self, name: &str, volume: &str) -> Result<Bucket, AppError> {
+        let target = volume_name(Some(volume));
+        let mut buckets = self.buckets.write().unwrap();
+        let info = buckets
+            .get(name)
+            .ok_or_else(|| AppError::BucketNotFound(name.to_string()))?
+            .clone();
+        if volume_name(info.volume.as_deref()) == target {
+            return Ok(info);
+        }
+        let target_root = self.available_root(target)?;
+        let source = self.bucket_path(name);
+        let dest = target_root.join(name);
+        if dest.exists() {
+            return Err(already_on_volume(name, target));
+        }
+
+        let staging = target_root.join(format!(".moving-{}", name));
+        if staging.exists() {
+            // Left by a move that was interrupted before its switchover
+            fs::remove_dir_all(&staging)?;
+        }
+        let moved = Bucket {
+            volume: target.map(str::to_string),
+            ..info
+        };
+        let copied = copy_tree(&source, &staging, &mut HashMap::new()).and_then(|bytes| {
+            fs::write(staging.join(".bucket_meta.json"), serde_json::to_string_pretty(&moved).unwrap())?;
+            Ok(bytes)
+        });
+        let bytes = match copied {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(e.into());
+            }
+        };
+
+        // From here on a restart serves the new copy if it made it into place
+        self.write_bucket_meta(&moved)?;
+        fs::rename(&staging, &dest)?;
+        self.set_bucket_root(name, target, &target_root);
+        buckets.insert(name.to_string(), moved.clone());
+
+        // Hidden first, so a crash while deleting leaves nothing a scan loads
+        let retired = source.with_file_name(format!(".moved-{}-{}", name, uuid::Uuid::new_v4()));
+        fs::rename(&source, &retired)?;
+        if let Err(e) = fs::remove_dir_all(&retired) {
+            tracing::warn!("Moved bucket {}, but could not remove the old copy at {}: {}", name, retired.display(), e);
+        }
+        tracing::info!(
+            "Moved bucket {} to volume {} ({})",
+            name,
+            volume_name(Some(volume)).unwrap_or(DEFAULT_VOLUME),
+            human_readable_size(bytes)
+        );
+        Ok(moved)
+    }
+}
+
+fn already_on_volume(name: &str, volume: Option<&str>) -> AppError {
+    AppError::InvalidRequest(format!(
+        "A directory for bucket '{}' already exists on volume '{}'",
+        name,
+        volume.unwrap_or(DEFAULT_VOLUME)
+    ))
+}
+
+/// Copy a directory tree file by file, streaming each one. Scratch files
+/// are skipped. Files hard-linked to each other, such as snapshot entries,
+/// stay linked in the copy. Returns the bytes copied.
+fn copy_tree(src: &Path, dst: &Path, links: &mut HashMap<(u64, u64), PathBuf>) -> io::Result<u64> {
+    let mut bytes = 0;
+    let mut stack = vec![(src.to_path_buf(), dst.to_path_buf())];
+    while let Some((src, dst)) = stack.pop() {
+        fs::create_dir_all(&dst)?;
+        for entry in fs::read_dir(&src)? {
+            let entry = entry?;
+            let from = entry.path();
+            let to = dst.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if entry.file_name() != ".tmp" {
+                    stack.push((from, to));
+                }
+                continue;
+            }
+            if let Some(id) = file_id(&entry.metadata()?) {
+                // A volume without hard links gets another copy instead
+                if let Some(first) = links.get(&id) {
+                    if fs::hard_link(first, &to).is_ok() {
+                        continue;
+                    }
+                }
+                links.insert(id, to.clone());
+            }
+            bytes += sparse::copy_file(&from, &to)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Identifies a file with more than one link, so its links can be kept
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (meta.nlink() > 1).then(|| (meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Free and total bytes of the filesystem holding `path`
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // The field types differ between platforms
+fn disk_space(path: &Path) -> Option<(u64, u64)> {
+    let stat = nix::sys::statvfs::statvfs(path).ok()?;
+    let block = stat.fragment_size() as u64;
+    Some((stat.blocks_available() as u64 * block, stat.blocks() as u64 * block))
+}
+
+#[cfg(not(unix))]
+fn disk_space(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
@@ -0,0 +1,467 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+
+use super::{sparse, StorageEngine};
+use crate::models::ObjectMeta;
+
+/// Listings shorter than this load their metadata on the calling thread
+const PARALLEL_META_MIN: usize = 256;
+
+/// Files under a directory, with their total size and the bytes they take
+/// on disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct DirStats {
+    pub count: u64,
+    pub size: u64,
+    pub allocated: u64,
+}
+
+impl DirStats {
+    pub(super) fn add(&mut self, meta: &fs::Metadata) {
+        self.count += 1;
+        self.size += meta.len();
+        self.allocated += sparse::allocated_size(meta);
+    }
+}
+
+/// Directories waiting to be read, and how many are queued or still being
+/// read. The walk is over once `pending` drops to zero.
+struct WorkQueue {
+    dirs: Vec<PathBuf>,
+    pending: usize,
+}
+
+/// Read every directory under `root` with up to `workers` threads sharing
+/// a queue of directories. `descend` decides whether a subdirectory is
+/// entered; `visit` maps each file to an item. Items come back in no
+/// particular order, with the first error that left part of the tree
+/// unread. Directories that vanish mid-walk are not errors.
+fn walk_files<T, D, V>(root: &Path, workers: usize, descend: D, visit: V) -> (Vec<T>, Option<io::Error>)
+where
+    T: Send,
+    D: Fn(&Path) -> bool + Sync,
+    V: Fn(&fs::DirEntry) -> Option<T> + Sync,
+{
+    let queue = Mutex::new(WorkQueue {
+        dirs: vec![root.to_path_buf()],
+        pending: 1,
+    });
+    let ready = Condvar::new();
+    let failed: Mutex<Option<io::Error>> = Mutex::new(None);
+    let fail = |e: io::Error| {
+        if e.kind() != io::ErrorKind::NotFound {
+            failed.lock().unwrap().get_or_insert(e);
+        }
+    };
+
+    let worker = || {
+        let mut items = Vec::new();
+        loop {
+            let dir = {
+                let mut queue = queue.lock().unwrap();
+                loop {
+                    if let Some(dir) = queue.dirs.pop() {
+                        break Some(dir);
+                    }
+                    if queue.pending == 0 {
+                        break None;
+                    }
+                    queue = ready.wait(queue).unwrap();
+                }
+            };
+            let Some(dir) = dir else { break };
+
+            let mut subdirs = Vec::new();
+            match fs::read_dir(&dir) {
+                Ok(entries) => {
+                    for entry in entries {
+                        let entry = match entry {
+                            Ok(entry) => entry,
+                            Err(e) => {
+                                fail(e);
+                                continue;
+                            }
+                        };
+                        let file_type = match entry.file_type() {
+                            Ok(file_type) => file_type,
+                            Err(e) => {
+                                fail(e);
+                                continue;
+                            }
+                        };
+                        // Symlinked directories are followed, as `Path::is_dir` does
+                        let is_dir = file_type.is_dir() || (file_type.is_symlink() && entry.path().is_dir());
+                        if is_dir {
+                            let path = entry.path();
+                            if descend(&path) {
+                                subdirs.push(path);
+                            }
+                        } else if let Some(item) = visit(&entry) {
+                            items.push(item);
+                        }
+                    }
+                }
+                Err(e) => fail(e),
+            }
+
+            let mut queue = queue.lock().unwrap();
+            queue.pending = queue.pending - 1 + subdirs.len();
+            queue.dirs.append(&mut subdirs);
+            drop(queue);
+            ready.notify_all();
+        }
+        items
+    };
+
+    let items = if workers <= 1 {
+        worker()
+    } else {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(worker)).collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    };
+    (items, failed.into_inner().unwrap())
+}
+
+/// Every directory under `root`, `root` included, each listed after all of
+/// its subdirectories, with whether it directly holds anything other than
+/// directories. A directory that can't be read counts as holding something.
+/// The walk keeps its own stack, so key depth never reaches the call stack.
+pub(super) fn dirs_deepest_first(root: &Path) -> Vec<(PathBuf, bool)> {
+    let mut dirs = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let mut has_files = false;
+        match fs::read_dir(&dir) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if path.is_dir() {
+                        stack.push(path);
+                    } else {
+                        has_files = true;
+                    }
+                }
+            }
+            Err(_) => has_files = true,
+        }
+        dirs.push((dir, has_files));
+    }
+    // Each directory was pushed before anything below it
+    dirs.reverse();
+    dirs
+}
+
+/// Keys of the files under an objects directory that start with a prefix,
+/// in key order. Only the entries left in the directories being walked are
+/// held, so memory doesn't grow with the bucket, and directories that can't
+/// hold a matching key are never read.
+pub struct SortedKeys {
+    root: PathBuf,
+    prefix: String,
+    /// Entries not yet visited in each directory on the way down, by key
+    stack: Vec<std::vec::IntoIter<(String, PathBuf, bool)>>,
+}
+
+impl SortedKeys {
+    pub(super) fn new(root: &Path, prefix: &str) -> Self {
+        let mut keys = Self {
+            root: root.to_path_buf(),
+            prefix: prefix.to_string(),
+            stack: Vec::new(),
+        };
+        let entries = keys.sorted_entries(root);
+        keys.stack.push(entries);
+        keys
+    }
+
+    /// The entries of `dir` as `(key, path, is_dir)`, in the order their
+    /// keys sort. A directory sorts as its key prefix with the `/`, so
+    /// `a.txt` comes before everything in `a/`, as it does in a listing.
+    fn sorted_entries(&self, dir: &Path) -> std::vec::IntoIter<(String, PathBuf, bool)> {
+        let mut entries: Vec<(String, PathBuf, bool)> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| {
+                        let path = entry.path();
+                        let is_dir = path.is_dir();
+                        let mut key = relative_key(&self.root, &path);
+                        if is_dir {
+                            key.push('/');
+                        }
+                        (key, path, is_dir)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries.into_iter()
+    }
+}
+
+impl Iterator for SortedKeys {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        // A loop rather than recursion, so a deep key can't overflow the
+        // call stack
+        while let Some(entries) = self.stack.last_mut() {
+            let Some((key, path, is_dir)) = entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            if is_dir {
+                if key.starts_with(&self.prefix) || self.prefix.starts_with(&key) {
+                    let entries = self.sorted_entries(&path);
+                    self.stack.push(entries);
+                }
+            } else if key.starts_with(&self.prefix) {
+                return Some(key);
+            }
+        }
+        None
+    }
+}
+
+/// The object key of a file (or, with a `/` appended, the key prefix of a
+/// directory) under the objects directory `root`
+pub(super) fn relative_key(root: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/");
+    super::key_paths::path_to_key(&rel).into_owned()
+}
+
+impl StorageEngine {
+    /// Number of files and total bytes under `dir`. A file whose size
+    /// can't be read counts as empty.
+    pub(super) fn dir_stats(&self, dir: &Path) -> DirStats {
+        let (metas, _) = walk_files(dir, self.walk_workers, |_| true, |entry| Some(entry.metadata()));
+        let mut stats = DirStats::default();
+        for meta in metas {
+            match meta {
+                Ok(m) => stats.add(&m),
+                Err(_) => stats.count += 1,
+            }
+        }
+        stats
+    }
+
+    /// `dir_stats`, failing rather than undercounting when part of the tree
+    /// can't be read. Files deleted mid-walk are left out, not errors.
+    pub(super) fn try_dir_stats(&self, dir: &Path) -> io::Result<DirStats> {
+        let (metas, failed) = walk_files(dir, self.walk_workers, |_| true, |entry| Some(entry.metadata()));
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        let mut stats = DirStats::default();
+        for meta in metas {
+            match meta {
+                Ok(m) => stats.add(&m),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Keys of every file under `root` starting with `prefix`, unsorted.
+    /// Directories that can't hold a matching key are never read.
+    pub(super) fn collect_keys(&self, root: &Path, prefix: &str) -> Vec<String> {
+        let (keys, _) = walk_files(
+            root,
+            self.walk_workers,
+            |dir| {
+                let dir_rel = format!("{}/", relative_key(root, dir));
+                dir_rel.starts_with(prefix) || prefix.starts_with(&dir_rel)
+            },
+            |entry| {
+                let key = relative_key(root, &entry.path());
+                key.starts_with(prefix).then_some(key)
+            },
+        );
+        keys
+    }
+
+    /// Metadata for `keys`, in the same order. Keys whose metadata can't be
+    /// loaded are skipped.
+    pub(super) fn load_object_metas(&self, bucket: &str, keys: &[String]) -> Vec<ObjectMeta> {
+        if self.walk_workers <= 1 || keys.len() < PARALLEL_META_MIN {
+            return keys
+                .iter()
+                .filter_map(|key| self.get_object_meta(bucket, key).ok())
+                .collect();
+        }
+        let chunk_size = keys.len() / self.walk_workers + 1;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = keys
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .filter_map(|key| self.get_object_meta(bucket, key).ok())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+    use crate::storage::testing::{engine, engine_with};
+    use crate::storage::PutOptions;
+
+    /// `d00/d01/…/file.txt` with `depth` segments in all
+    fn deep_key(depth: usize) -> String {
+        let mut segments: Vec<String> = (0..depth - 1).map(|i| format!("d{:03}", i)).collect();
+        segments.push("file.txt".to_string());
+        segments.join("/")
+    }
+
+    fn keys(storage: &StorageEngine, prefix: &str) -> Vec<String> {
+        let listing = storage.list_objects("deep", prefix, None, 1000).unwrap();
+        listing.objects.into_iter().map(|o| o.key).collect()
+    }
+
+    #[test]
+    fn a_150_segment_key_lists_and_deletes_cleanly() {
+        let (dir, storage) = engine_with(|options| options.max_key_depth = 200);
+        storage.create_bucket("deep", "local").unwrap();
+        storage.set_bucket_versioning("deep", true).unwrap();
+        let key = deep_key(150);
+        for data in [&b"one"[..], b"two"] {
+            storage
+                .put_object("deep", &key, data, PutOptions::default())
+                .unwrap();
+        }
+        storage
+            .put_object("deep", "d000/shallow.txt", b"x", PutOptions::default())
+            .unwrap();
+
+        for depth in [1, 75, 149] {
+            let prefix = format!("{}/", key.split('/').take(depth).collect::<Vec<_>>().join("/"));
+            let expected = match depth {
+                1 => vec![key.clone(), "d000/shallow.txt".to_string()],
+                _ => vec![key.clone()],
+            };
+            assert_eq!(keys(&storage, &prefix), expected, "prefix of depth {}", depth);
+        }
+        let listing = storage.list_objects("deep", "d000/", Some("/"), 1000).unwrap();
+        assert_eq!(listing.common_prefixes, ["d000/d001/"]);
+        assert_eq!(storage.list_object_versions("deep", &key).unwrap().versions.len(), 2);
+        // The metadata file's name would be too long for the filesystem
+        let meta_names: Vec<String> = fs::read_dir(dir.path().join("deep/.meta"))
+            .unwrap()
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(meta_names.iter().all(|name| name.len() <= 255), "{:?}", meta_names);
+
+        drop(storage);
+        let mut options = crate::config::Config::builtin().storage_options();
+        options.max_key_depth = 200;
+        let storage = StorageEngine::new(dir.path().to_str().unwrap(), options).unwrap();
+        assert_eq!(storage.get_object("deep", &key).unwrap().1, b"two");
+
+        storage.set_bucket_versioning("deep", false).unwrap();
+        storage.delete_object("deep", &key).unwrap();
+        storage.delete_object("deep", "d000/shallow.txt").unwrap();
+        assert!(keys(&storage, "").is_empty());
+        let objects = dir.path().join("deep/objects");
+        let left: Vec<PathBuf> = dirs_deepest_first(&objects).into_iter().map(|(dir, _)| dir).collect();
+        assert_eq!(left, [objects], "no empty directories are left behind");
+    }
+
+    #[test]
+    fn keys_deeper_than_the_limit_are_refused() {
+        let (_dir, storage) = engine();
+        storage.create_bucket("deep", "local").unwrap();
+        assert!(storage
+            .put_object("deep", &deep_key(128), b"x", PutOptions::default())
+            .is_ok());
+        let err = storage
+            .put_object("deep", &deep_key(150), b"x", PutOptions::default())
+            .unwrap_err();
+        match err {
+            AppError::InvalidObjectKey(reason) => assert!(reason.contains("150 path segments"), "{}", reason),
+            other => panic!("expected InvalidObjectKey, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn walks_keep_deep_trees_off_the_call_stack() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("objects");
+        // As deep as a path may get, far deeper than keys are allowed to go
+        let depth = (3800 - root.as_os_str().len()) / 2;
+        let mut deepest = root.clone();
+        for _ in 0..depth {
+            deepest.push("d");
+        }
+        fs::create_dir_all(&deepest).unwrap();
+        fs::write(deepest.join("f"), b"x").unwrap();
+        fs::write(root.join("top"), b"x").unwrap();
+
+        // A small stack: recursing once per directory would overflow it
+        let walked = std::thread::Builder::new()
+            .stack_size(128 * 1024)
+            .spawn(move || {
+                let sorted: Vec<String> = SortedKeys::new(&root, "d/d/").collect();
+                let dirs = dirs_deepest_first(&root);
+                (sorted, dirs.len(), dirs[0].1, dirs.last().unwrap().1)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        let (sorted, dirs, deepest_has_files, root_has_files) = walked;
+        assert_eq!(sorted, [format!("{}f", "d/".repeat(depth))]);
+        assert_eq!((dirs, deepest_has_files, root_has_files), (depth + 1, true, true));
+    }
+
+    /// Scans of a 100 000-file tree with one thread, as before the walks
+    /// were shared out, against the configured pool, and the first page of
+    /// sorted keys against collecting them all
+    #[test]
+    #[ignore = "benchmark; run with cargo test --release -- --ignored --nocapture"]
+    fn bench_walks() {
+        let (dir, storage) = engine();
+        let root = dir.path().join("tree");
+        for d in 0..100 {
+            let sub = root.join(format!("d{:03}", d));
+            fs::create_dir_all(&sub).unwrap();
+            for f in 0..1000 {
+                fs::write(sub.join(format!("f{:04}", f)), b"x").unwrap();
+            }
+        }
+        let time = |label: &str, run: &dyn Fn() -> usize| {
+            let started = std::time::Instant::now();
+            let found = run();
+            println!("{:<32} {:>8} in {:?}", label, found, started.elapsed());
+        };
+
+        let (_single_dir, single) = engine_with(|options| options.walk_threads = 1);
+        time("dir_stats, 1 thread", &|| single.dir_stats(&root).count as usize);
+        time(&format!("dir_stats, {} threads", storage.walk_workers), &|| {
+            storage.dir_stats(&root).count as usize
+        });
+        time("collect_keys, 1 thread", &|| single.collect_keys(&root, "").len());
+        time(&format!("collect_keys, {} threads", storage.walk_workers), &|| {
+            storage.collect_keys(&root, "").len()
+        });
+        time("first 1000 sorted keys", &|| SortedKeys::new(&root, "").take(1000).count());
+    }
+}
@@ -0,0 +1,107 @@
+use std::path::Path;
+
+/// The listening socket systemd passed in through `LISTEN_FDS`, if any.
+/// Without the `systemd` feature the server always binds its own.
+#[cfg(feature = "systemd")]
+pub fn activated_listener() -> Option<std::net::TcpListener> {
+    let mut fds = listenfd::ListenFd::from_env();
+    if fds.len() == 0 {
+        return None;
+    }
+    if fds.len() > 1 {
+        tracing::warn!("systemd passed {} sockets; only the first is used", fds.len());
+    }
+    match fds.take_tcp_listener(0) {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Cannot use the socket passed by systemd: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "systemd"))]
+pub fn activated_listener() -> Option<std::net::TcpListener> {
+    None
+}
+
+/// Tell systemd the server is accepting connections (for `Type=notify`)
+pub fn notify_ready() {
+    #[cfg(feature = "systemd")]
+    notify(sd_notify::NotifyState::Ready);
+}
+
+/// Tell systemd the server has begun shutting down
+pub fn notify_stopping() {
+    #[cfg(feature = "systemd")]
+    notify(sd_notify::NotifyState::Stopping);
+}
+
+#[cfg(feature = "systemd")]
+fn notify(state: sd_notify::NotifyState) {
+    // A no-op when NOTIFY_SOCKET is unset, i.e. outside systemd
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        tracing::warn!("Cannot notify systemd: {}", e);
+    }
+}
+
+/// Quote an ExecStart argument the way systemd unquotes it
+fn unit_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A `freebucket.socket` and `freebucket.service` pair that starts this
+/// binary on the data dir when something connects to `host:port`
+pub fn unit_files(binary: &Path, data_dir: &Path, host: &str, port: u16) -> String {
+    // IPv6 addresses need brackets in ListenStream
+    let listen = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let exec = [
+        binary.to_string_lossy().as_ref(),
+        "--data-dir",
+        data_dir.to_string_lossy().as_ref(),
+        "serve",
+        "--host",
+        host,
+        "--port",
+        &port.to_string(),
+    ]
+    .iter()
+    .map(|arg| unit_arg(arg))
+    .collect::<Vec<_>>()
+    .join(" ");
+
+    format!(
+        "# freebucket.socket\n\
+         # Install both files in ~/.config/systemd/user/ (or /etc/systemd/system/), then:\n\
+         #   systemctl --user enable --now freebucket.socket\n\
+         [Unit]\n\
+         Description=FreeBucket listening socket\n\
+         \n\
+         [Socket]\n\
+         ListenStream={listen}\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n\
+         \n\
+         # freebucket.service\n\
+         [Unit]\n\
+         Description=FreeBucket local S3-compatible storage\n\
+         Requires=freebucket.socket\n\
+         After=freebucket.socket\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={exec}\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n"
+    )
+}
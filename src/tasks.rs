@@ -0,0 +1,207 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::AppState;
+
+/// How often the background runner wakes up to look for due work
+const TICK: Duration = Duration::from_secs(60 * 60);
+/// How often the rehasher looks for work nothing woke it up for
+const REHASH_POLL: Duration = Duration::from_secs(60);
+
+/// Spawn periodic maintenance work. Storage calls block on disk I/O, so each
+/// job runs on the blocking thread pool. The first tick fires immediately,
+/// which doubles as the startup pass.
+pub fn spawn_background_tasks(state: Arc<AppState>) {
+    spawn_access_log_flusher(state.clone());
+    spawn_access_stats_flusher(state.clone());
+    spawn_traffic_flusher(state.clone());
+    spawn_stats_compactor(state.clone());
+    spawn_readiness_checker(state.clone());
+    spawn_rehasher(state.clone());
+    spawn_alert_checker(state.clone());
+    crate::webhooks::spawn_webhook_dispatcher(state.clone());
+    crate::scanner::spawn_quarantine_scanner(state.clone());
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+
+            let storage_state = state.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                storage_state.storage.run_scheduled_inventories()
+            })
+            .await;
+            match result {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Generated {} scheduled inventory report(s)", n),
+                Err(e) => tracing::error!("Scheduled inventory task panicked: {}", e),
+            }
+
+            let stats_state = state.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let config = stats_state.config();
+                stats_state.storage.record_stats_sample(
+                    Duration::from_secs(config.stats_interval_hours * 60 * 60),
+                    Duration::from_secs(config.stats_retention_days * 24 * 60 * 60),
+                )
+            })
+            .await;
+            match result {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::error!("Recording stats sample failed: {:?}", e),
+                Err(e) => tracing::error!("Stats sampling task panicked: {}", e),
+            }
+
+            let purge_state = state.clone();
+            let result = tokio::task::spawn_blocking(move || purge_state.idempotency.purge_expired()).await;
+            match result {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Removed {} expired idempotency key(s)", n),
+                Err(e) => tracing::error!("Idempotency key purge panicked: {}", e),
+            }
+
+            let gc_state = state.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let opts = gc_state.config().gc_options(false);
+                gc_state.storage.collect_garbage(&opts)
+            })
+            .await;
+            match result {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::error!("Garbage collection failed: {:?}", e),
+                Err(e) => tracing::error!("Garbage collection task panicked: {}", e),
+            }
+        }
+    });
+}
+
+/// Deliver buffered access log records on their own, much shorter, interval
+fn spawn_access_log_flusher(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            let flush_state = state.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                crate::access_log::flush_access_logs(&flush_state)
+            })
+            .await;
+            match result {
+                Ok(0) => {}
+                Ok(n) => tracing::debug!("Delivered {} access log record(s)", n),
+                Err(e) => tracing::error!("Access log flush panicked: {}", e),
+            }
+            // Re-read every time, so a config reload changes the period
+            tokio::time::sleep(Duration::from_secs(state.config().access_log_flush_secs)).await;
+        }
+    });
+}
+
+/// Write download counters to disk, so a crash loses at most one interval
+fn spawn_access_stats_flusher(state: Arc<AppState>) {
+    if !state.config().access_tracking {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            let flush_state = state.clone();
+            let result = tokio::task::spawn_blocking(move || flush_state.storage.flush_access_stats()).await;
+            match result {
+                Ok(0) => {}
+                Ok(n) => tracing::debug!("Wrote access stats for {} bucket(s)", n),
+                Err(e) => tracing::error!("Access stats flush panicked: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(state.config().access_flush_secs)).await;
+        }
+    });
+}
+
+/// Add bucket traffic counts to the persisted daily totals
+fn spawn_traffic_flusher(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(state.config().access_flush_secs)).await;
+            let flush_state = state.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                flush_state.storage.flush_traffic(flush_state.config().stats_retention_days)
+            })
+            .await;
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(n)) => tracing::debug!("Wrote traffic totals for {} bucket(s)", n),
+                Ok(Err(e)) => tracing::warn!("Cannot write traffic totals: {:?}", e),
+                Err(e) => tracing::error!("Traffic flush panicked: {}", e),
+            }
+        }
+    });
+}
+
+/// Fold bucket stats journals into bucket metadata, so replaying them at
+/// startup stays short
+fn spawn_stats_compactor(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            let compact_state = state.clone();
+            let result = tokio::task::spawn_blocking(move || compact_state.storage.compact_stats_journals()).await;
+            match result {
+                Ok(0) => {}
+                Ok(n) => tracing::debug!("Compacted stats journals of {} bucket(s)", n),
+                Err(e) => tracing::error!("Stats journal compaction panicked: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(state.config().stats_compact_secs)).await;
+        }
+    });
+}
+
+/// Hash objects stored under a provisional ETag. The first pass picks up
+/// whatever was queued before a restart; after that it wakes up for every
+/// upload, and now and then for ones queued by a CLI run.
+fn spawn_rehasher(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            let hash_state = state.clone();
+            match tokio::task::spawn_blocking(move || hash_state.storage.rehash_pending()).await {
+                Ok(0) => {}
+                Ok(n) => tracing::debug!("Hashed {} object(s) stored with a provisional ETag", n),
+                Err(e) => tracing::error!("Background hashing panicked: {}", e),
+            }
+            tokio::select! {
+                _ = state.storage.rehash_queued() => {}
+                _ = tokio::time::sleep(REHASH_POLL) => {}
+            }
+        }
+    });
+}
+
+/// Raise and clear bucket usage alerts
+fn spawn_alert_checker(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(state.config().alert_check_secs)).await;
+            let check_state = state.clone();
+            match tokio::task::spawn_blocking(move || check_state.storage.check_alerts()).await {
+                Ok(0) => {}
+                Ok(n) => tracing::debug!("Raised or cleared {} usage alert(s)", n),
+                Err(e) => tracing::error!("Usage alert check panicked: {}", e),
+            }
+        }
+    });
+}
+
+/// Keep a fresh light self-test result for `/readyz`
+fn spawn_readiness_checker(state: Arc<AppState>) {
+    let secs = state.config().readyz_self_test_secs;
+    if secs == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(secs));
+        loop {
+            interval.tick().await;
+            let test_state = state.clone();
+            match tokio::task::spawn_blocking(move || test_state.storage.self_test(true)).await {
+                Ok(report) => *state.readiness.lock().unwrap() = Some(report),
+                Err(e) => tracing::error!("Readiness self-test panicked: {}", e),
+            }
+        }
+    });
+}
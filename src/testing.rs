@@ -0,0 +1,72 @@
+//! A server on a scratch data dir for handler tests. Requests go through
+//! the same routes and middleware as a running server's.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use axum::response::Response;
+use tower::ServiceExt;
+
+use crate::config::{Config, ConfigOverrides};
+use crate::storage::StorageEngine;
+use crate::AppState;
+
+pub struct TestServer {
+    pub state: Arc<AppState>,
+    _dir: tempfile::TempDir,
+}
+
+impl TestServer {
+    /// A server with the built-in settings
+    pub fn new() -> Self {
+        Self::with_config(|_| {})
+    }
+
+    /// A server with its settings adjusted first
+    pub fn with_config(adjust: impl FnOnce(&mut Config)) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::builtin();
+        config.data_dir = dir.path().to_str().unwrap().to_string();
+        adjust(&mut config);
+        let options = config.storage_options();
+        let sources = options.sources.clone();
+        let storage = StorageEngine::new(&config.data_dir, options).unwrap();
+        let (_, log_filter) = tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::new("off"));
+        let state = AppState::new(config, ConfigOverrides::default(), storage, log_filter, None, sources);
+        TestServer {
+            state: Arc::new(state),
+            _dir: dir,
+        }
+    }
+
+    /// Send a request from a loopback client
+    pub async fn send(&self, mut req: Request) -> Response {
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        crate::app(&self.state).oneshot(req).await.unwrap()
+    }
+
+    /// Serve on a free loopback port, for clients that need a real
+    /// connection; returns the endpoint
+    pub async fn listen(&self) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = axum::ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(crate::app(&self.state));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", address)
+    }
+
+    /// Send a request with no headers or body
+    pub async fn request(&self, method: &str, uri: &str) -> Response {
+        self.send(Request::builder().method(method).uri(uri).body(Body::empty()).unwrap())
+            .await
+    }
+}
+
+/// A response's whole body
+pub async fn body_string(res: Response) -> String {
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use axum::body::{Body, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::StreamExt;
+
+use crate::models::TrafficKind;
+use crate::AppState;
+
+/// The bucket a request reads or writes data in. Bucket management routes
+/// (settings, stats, this counter itself) are not traffic.
+fn traffic_bucket(path: &str) -> Option<&str> {
+    let rest = path
+        .strip_prefix("/s3/obj/")
+        .or_else(|| path.strip_prefix("/s3/"))
+        .or_else(|| path.strip_prefix("/api/object/"))
+        .or_else(|| path.strip_prefix("/api/inbox/"))
+        .or_else(|| path.strip_prefix("/dav/"))
+        .or_else(|| {
+            let rest = path.strip_prefix("/api/buckets/")?;
+            let (_, action) = rest.split_once('/')?;
+            (action.starts_with("objects") || action == "upload").then_some(rest)
+        })?;
+    rest.split('/').next().filter(|bucket| !bucket.is_empty())
+}
+
+fn traffic_kind(method: &Method) -> Option<TrafficKind> {
+    match *method {
+        Method::GET | Method::HEAD => Some(TrafficKind::Get),
+        Method::PUT | Method::POST | Method::PATCH => Some(TrafficKind::Put),
+        Method::DELETE => Some(TrafficKind::Delete),
+        _ => None,
+    }
+}
+
+/// One request's counts, recorded once its response body is done with
+struct Tally {
+    state: Arc<AppState>,
+    bucket: String,
+    kind: TrafficKind,
+    received: Arc<AtomicU64>,
+    served: u64,
+}
+
+impl Tally {
+    fn add_served(&mut self, bytes: usize) {
+        self.served += bytes as u64;
+    }
+}
+
+impl Drop for Tally {
+    fn drop(&mut self) {
+        let received = self.received.load(Ordering::Relaxed);
+        self.state.storage.record_traffic(&self.bucket, self.kind, received, self.served);
+    }
+}
+
+/// Count requests and body bytes per bucket. Bodies of known length are
+/// counted from their size; streamed ones are counted as they pass through,
+/// so a download cut short counts only what was sent.
+pub async fn count_traffic(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let Some(kind) = traffic_kind(req.method()) else {
+        return next.run(req).await;
+    };
+    let Some(bucket) = traffic_bucket(req.uri().path()).map(str::to_string) else {
+        return next.run(req).await;
+    };
+
+    let received = Arc::new(AtomicU64::new(0));
+    let req = match req.body().size_hint().exact() {
+        Some(size) => {
+            received.store(size, Ordering::Relaxed);
+            req
+        }
+        None => {
+            let counter = received.clone();
+            req.map(|body| {
+                Body::from_stream(body.into_data_stream().inspect(move |chunk| {
+                    if let Ok(data) = chunk {
+                        counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+                    }
+                }))
+            })
+        }
+    };
+
+    let res = next.run(req).await;
+    let mut tally = Tally {
+        state,
+        bucket,
+        kind,
+        received,
+        served: 0,
+    };
+    match res.body().size_hint().exact() {
+        Some(size) => {
+            tally.served = size;
+            res
+        }
+        None => res.map(|body| {
+            Body::from_stream(body.into_data_stream().map(move |chunk| {
+                if let Ok(data) = &chunk {
+                    tally.add_served(data.len());
+                }
+                chunk
+            }))
+        }),
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::{header, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tower::{Layer, ServiceExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::AppError;
+use crate::AppState;
+
+/// Caps how many uploads are handled at once. Request bodies are buffered
+/// in memory, so this bounds memory use to roughly the permit count times
+/// the upload size limit.
+pub struct UploadLimiter {
+    /// `None` when uploads are unlimited
+    permits: Option<Arc<Semaphore>>,
+    max: usize,
+    wait: Duration,
+}
+
+impl UploadLimiter {
+    pub fn new(max: usize, wait: Duration) -> Self {
+        Self {
+            permits: (max > 0).then(|| Arc::new(Semaphore::new(max))),
+            max,
+            wait,
+        }
+    }
+
+    /// Uploads currently holding a slot
+    pub fn in_flight(&self) -> usize {
+        self.permits
+            .as_ref()
+            .map_or(0, |p| self.max - p.available_permits())
+    }
+
+    /// Configured slot count; 0 means unlimited
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Take a slot, waiting up to the configured time for one to free up.
+    /// `None` when uploads are unlimited.
+    pub async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, AppError> {
+        let Some(permits) = &self.permits else {
+            return Ok(None);
+        };
+        match tokio::time::timeout(self.wait, permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            _ => Err(AppError::SlowDown(format!(
+                "Too many concurrent uploads (limit {}); retry shortly",
+                self.max
+            ))),
+        }
+    }
+}
+
+/// Requests that carry an object body: S3 PUT object and upload part, the
+/// dashboard's multipart form upload, and inbox uploads
+fn is_upload(method: &Method, path: &str) -> bool {
+    match *method {
+        Method::PUT => path.starts_with("/s3/obj/") || path.starts_with("/dav/"),
+        Method::POST => {
+            path.starts_with("/api/inbox/")
+                || (path.starts_with("/api/buckets/") && path.ends_with("/upload"))
+        }
+        _ => false,
+    }
+}
+
+/// Take an upload slot before the body is read, waiting up to the configured
+/// time for one to free up
+pub async fn limit_uploads(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let limiter = &state.upload_limiter;
+    if limiter.permits.is_none() || !is_upload(req.method(), req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    match limiter.acquire().await {
+        Ok(_permit) => next.run(req).await,
+        Err(e) => {
+            tracing::warn!(
+                "Rejected upload to {}: all {} upload slot(s) busy for {:?}",
+                req.uri().path(),
+                limiter.max,
+                limiter.wait
+            );
+            let mut res = e.into_response();
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, limiter.wait.as_secs().max(1).into());
+            res
+        }
+    }
+}
+
+/// Cap request bodies at the configured upload size. The limit is read per
+/// request, so a config reload applies to the next upload.
+pub async fn limit_body_size(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let limit = state.config().max_upload_size;
+    match DefaultBodyLimit::max(limit).layer(next).oneshot(req).await {
+        Ok(res) => res,
+        Err(never) => match never {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, Bytes};
+    use axum::http::StatusCode;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::testing::{body_string, TestServer};
+
+    #[tokio::test]
+    async fn a_slot_frees_up_when_an_upload_ends() {
+        let limiter = UploadLimiter::new(2, Duration::from_millis(20));
+        let first = limiter.acquire().await.unwrap();
+        let _second = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.in_flight(), 2);
+        assert!(matches!(limiter.acquire().await, Err(AppError::SlowDown(_))));
+
+        drop(first);
+        assert_eq!(limiter.in_flight(), 1);
+        assert!(limiter.acquire().await.unwrap().is_some());
+
+        let unlimited = UploadLimiter::new(0, Duration::ZERO);
+        assert!(unlimited.acquire().await.unwrap().is_none());
+        assert_eq!(unlimited.in_flight(), 0);
+    }
+
+    #[test]
+    fn only_requests_with_object_bodies_are_uploads() {
+        assert!(is_upload(&Method::PUT, "/s3/obj/photos/a.jpg"));
+        assert!(is_upload(&Method::PUT, "/dav/photos/a.jpg"));
+        assert!(is_upload(&Method::POST, "/api/buckets/photos/upload"));
+        assert!(is_upload(&Method::POST, "/api/inbox/photos"));
+        assert!(!is_upload(&Method::GET, "/s3/obj/photos/a.jpg"));
+        assert!(!is_upload(&Method::PUT, "/s3/photos"));
+        assert!(!is_upload(&Method::POST, "/api/buckets"));
+    }
+
+    /// An S3 PUT whose body arrives once `release` is sent
+    fn held_upload(key: &str, release: oneshot::Receiver<()>) -> Request {
+        let body = futures::stream::once(async move {
+            let _ = release.await;
+            Ok::<_, std::io::Error>(Bytes::from_static(b"payload"))
+        });
+        Request::put(format!("/s3/photos/{}", key))
+            .body(Body::from_stream(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_third_upload_past_a_limit_of_two_gets_503() {
+        let server = Arc::new(TestServer::with_config(|c| {
+            c.max_concurrent_uploads = 2;
+            c.upload_wait_secs = 0;
+        }));
+        server.state.storage.create_bucket("photos", "local").unwrap();
+
+        let mut releases = Vec::new();
+        let mut uploads = Vec::new();
+        for key in ["a.bin", "b.bin"] {
+            let (release, held) = oneshot::channel();
+            releases.push(release);
+            let server = server.clone();
+            let req = held_upload(key, held);
+            uploads.push(tokio::spawn(async move { server.send(req).await.status() }));
+        }
+        while server.state.upload_limiter.in_flight() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        let res = server
+            .send(Request::put("/s3/photos/c.bin").body(Body::from("payload")).unwrap())
+            .await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[header::RETRY_AFTER], "1");
+        assert!(body_string(res).await.contains("<Code>SlowDown</Code>"));
+
+        let res = server.request("GET", "/api/server-info").await;
+        let info: serde_json::Value = serde_json::from_str(&body_string(res).await).unwrap();
+        assert_eq!(info["uploads_in_flight"], 2);
+        let res = server.request("GET", "/api/metrics").await;
+        assert!(body_string(res).await.contains("\nfreebucket_uploads_in_flight 2\n"));
+        // Reads aren't held up by the full upload slots
+        let res = server.request("GET", "/s3/photos").await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        for release in releases {
+            release.send(()).unwrap();
+        }
+        for upload in uploads {
+            assert_eq!(upload.await.unwrap(), StatusCode::OK);
+        }
+        assert_eq!(server.state.upload_limiter.in_flight(), 0);
+        let res = server
+            .send(Request::put("/s3/photos/c.bin").body(Body::from("payload")).unwrap())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
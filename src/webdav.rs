@@ -0,0 +1,903 @@
+//! WebDAV under `/dav/`, so Finder and Windows Explorer can mount buckets
+//! as network drives. `/dav/` lists the buckets, and folders inside a
+//! bucket are key prefixes, as on the dashboard. An empty folder is kept by
+//! a zero-byte `.keep` object, which listings here leave out.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::any;
+use axum::Router;
+use chrono::{DateTime, Utc};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::error::AppError;
+use crate::handlers::{audited, http_date, quoted_etag};
+use crate::models::{AuditAction, BulkOperation, BulkRequest, ObjectMeta};
+use crate::storage::PutOptions;
+use crate::AppState;
+
+type AppResult<T> = Result<T, AppError>;
+
+/// Marks a folder that has no other objects in it
+const FOLDER_MARKER: &str = ".keep";
+
+/// Methods every WebDAV URL answers to
+const ALLOW: &str = "OPTIONS, PROPFIND, PROPPATCH, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+
+/// Characters left alone in an href path segment
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/dav", any(dav))
+        .route("/dav/", any(dav))
+        .route("/dav/*path", any(dav))
+}
+
+/// Add the WebDAV headers to OPTIONS answers, which the CORS layer gives
+/// before any route sees the request. Windows asks the server root too
+/// before mounting anything below it.
+pub async fn advertise(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    let dav = state.config().webdav
+        && req.method() == Method::OPTIONS
+        && (path == "/" || path == "/dav" || path.starts_with("/dav/"));
+    let mut res = next.run(req).await;
+    if dav {
+        let headers = res.headers_mut();
+        // Class 2 is claimed because Finder mounts read-only without it; see `lock`
+        headers.insert("dav", HeaderValue::from_static("1, 2"));
+        headers.insert("ms-author-via", HeaderValue::from_static("DAV"));
+        headers.insert(header::ALLOW, HeaderValue::from_static(ALLOW));
+    }
+    res
+}
+
+/// A URL under `/dav/`: nothing (the bucket list), a bucket, or a key in one
+struct DavPath {
+    bucket: Option<String>,
+    /// Without leading or trailing slashes; empty for a bucket itself
+    key: String,
+}
+
+impl DavPath {
+    fn parse(path: &str) -> AppResult<Self> {
+        let rest = path.strip_prefix("/dav").unwrap_or(path);
+        let decoded = percent_decode_str(rest.trim_matches('/'))
+            .decode_utf8()
+            .map_err(|_| AppError::InvalidObjectKey("Path is not valid UTF-8".to_string()))?;
+        if decoded.is_empty() {
+            return Ok(DavPath { bucket: None, key: String::new() });
+        }
+        let (bucket, key) = decoded.split_once('/').unwrap_or((&decoded, ""));
+        if key.split('/').any(|segment| matches!(segment, "." | "..")) || key.contains("//") {
+            return Err(AppError::InvalidObjectKey(format!("Invalid path: {}", key)));
+        }
+        Ok(DavPath {
+            bucket: Some(bucket.to_string()),
+            key: key.to_string(),
+        })
+    }
+
+    /// The key's folder, without a trailing slash; empty at the bucket root
+    fn parent_key(&self) -> &str {
+        self.key.rsplit_once('/').map_or("", |(parent, _)| parent)
+    }
+}
+
+/// What a URL names
+enum Resource {
+    Root,
+    Bucket { created_at: DateTime<Utc> },
+    Folder,
+    File(Box<ObjectMeta>),
+}
+
+impl Resource {
+    fn is_collection(&self) -> bool {
+        !matches!(self, Resource::File(_))
+    }
+}
+
+fn resolve(state: &AppState, path: &DavPath) -> AppResult<Option<Resource>> {
+    let Some(bucket) = &path.bucket else {
+        return Ok(Some(Resource::Root));
+    };
+    let info = match state.storage.get_bucket(bucket) {
+        Ok(info) => info,
+        Err(AppError::BucketNotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if path.key.is_empty() {
+        return Ok(Some(Resource::Bucket { created_at: info.created_at }));
+    }
+    // Checked first: asking for the metadata of a folder fails on its directory
+    if folder_exists(state, bucket, &path.key)? {
+        return Ok(Some(Resource::Folder));
+    }
+    match state.storage.get_object_meta(bucket, &path.key) {
+        Ok(meta) => Ok(Some(Resource::File(Box::new(meta)))),
+        Err(AppError::ObjectNotFound { .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether any object, a folder marker included, sits under `key/`
+fn folder_exists(state: &AppState, bucket: &str, key: &str) -> AppResult<bool> {
+    Ok(!state
+        .storage
+        .list_objects(bucket, &format!("{}/", key), None, 1)?
+        .objects
+        .is_empty())
+}
+
+/// Whether the collection a new resource would go into exists
+fn parent_exists(state: &AppState, path: &DavPath) -> AppResult<bool> {
+    match (&path.bucket, path.parent_key()) {
+        (None, _) => Ok(true),
+        (Some(bucket), "") => Ok(state.storage.get_bucket(bucket).is_ok()),
+        (Some(bucket), parent) => folder_exists(state, bucket, parent),
+    }
+}
+
+/// Put a marker in a folder that a delete or move just emptied, so the
+/// folder itself stays, as WebDAV clients expect
+fn keep_folder(state: &AppState, bucket: &str, folder: &str) -> AppResult<()> {
+    if folder.is_empty() || folder_exists(state, bucket, folder)? {
+        return Ok(());
+    }
+    let marker = format!("{}/{}", folder, FOLDER_MARKER);
+    state
+        .storage
+        .put_object(bucket, &marker, b"", PutOptions::default())?;
+    Ok(())
+}
+
+async fn dav(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> AppResult<Response> {
+    let path = DavPath::parse(uri.path())?;
+    match method.as_str() {
+        "PROPFIND" => propfind(&state, &path, &headers, &body),
+        "PROPPATCH" => proppatch(&state, &path, &body),
+        "GET" | "HEAD" => get(&state, &path, method == Method::HEAD),
+        "PUT" => put(&state, &path, &headers, &body),
+        "DELETE" => delete(&state, &path),
+        "MKCOL" => mkcol(&state, &path, &body),
+        "COPY" => transfer(&state, &path, &headers, BulkOperation::Copy),
+        "MOVE" => transfer(&state, &path, &headers, BulkOperation::Move),
+        "LOCK" => Ok(lock(&state, &uri)),
+        "UNLOCK" => Ok(StatusCode::NO_CONTENT.into_response()),
+        _ => Err(AppError::MethodNotAllowed(format!(
+            "{} is not a WebDAV method this server supports; allowed: {}",
+            method, ALLOW
+        ))),
+    }
+}
+
+// ─── PROPFIND / PROPPATCH ─────────────────────────────────────────
+
+/// One `<response>` of a multistatus listing
+struct Entry {
+    href: String,
+    name: String,
+    collection: bool,
+    size: Option<u64>,
+    modified: Option<DateTime<Utc>>,
+    content_type: Option<String>,
+    etag: Option<String>,
+}
+
+fn href(bucket: Option<&str>, key: &str, collection: bool) -> String {
+    let mut href = "/dav/".to_string();
+    for segment in bucket.into_iter().chain(key.split('/').filter(|s| !s.is_empty())) {
+        href.push_str(&utf8_percent_encode(segment, SEGMENT).to_string());
+        href.push('/');
+    }
+    if !collection && href.len() > "/dav/".len() {
+        href.pop();
+    }
+    href
+}
+
+fn file_entry(bucket: &str, meta: ObjectMeta) -> Entry {
+    let name = meta.key.rsplit('/').next().unwrap_or(&meta.key).to_string();
+    Entry {
+        href: href(Some(bucket), &meta.key, false),
+        name,
+        collection: false,
+        size: Some(meta.size),
+        modified: Some(meta.modified()),
+        content_type: Some(meta.content_type),
+        etag: Some(meta.etag),
+    }
+}
+
+fn folder_entry(bucket: Option<&str>, key: &str, modified: Option<DateTime<Utc>>) -> Entry {
+    let name = key.rsplit('/').next().filter(|s| !s.is_empty()).or(bucket).unwrap_or("dav");
+    Entry {
+        href: href(bucket, key, true),
+        name: name.to_string(),
+        collection: true,
+        size: None,
+        modified,
+        content_type: None,
+        etag: None,
+    }
+}
+
+fn propfind(state: &AppState, path: &DavPath, headers: &HeaderMap, body: &[u8]) -> AppResult<Response> {
+    let Some(resource) = resolve(state, path)? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    // Infinite depth is answered as depth 1, which is all clients ask for
+    let depth_zero = headers.get("depth").and_then(|v| v.to_str().ok()) == Some("0");
+    let bucket = path.bucket.as_deref();
+
+    let mut entries = Vec::new();
+    let collection = resource.is_collection();
+    match resource {
+        Resource::Root => entries.push(folder_entry(None, "", None)),
+        Resource::Bucket { created_at } => entries.push(folder_entry(bucket, "", Some(created_at))),
+        Resource::Folder => entries.push(folder_entry(bucket, &path.key, None)),
+        Resource::File(meta) => entries.push(file_entry(bucket.unwrap_or_default(), *meta)),
+    }
+
+    if collection && !depth_zero {
+        match bucket {
+            None => {
+                for info in state.storage.list_buckets() {
+                    entries.push(folder_entry(Some(&info.name), "", Some(info.created_at)));
+                }
+            }
+            Some(bucket) => {
+                let prefix = if path.key.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", path.key)
+                };
+                let listing = state.storage.list_objects(bucket, &prefix, Some("/"), u32::MAX)?;
+                for folder in &listing.common_prefixes {
+                    entries.push(folder_entry(Some(bucket), folder.trim_end_matches('/'), None));
+                }
+                for meta in listing.objects {
+                    if meta.key[prefix.len()..] != *FOLDER_MARKER {
+                        entries.push(file_entry(bucket, meta));
+                    }
+                }
+            }
+        }
+    }
+
+    let wanted = named_props(body);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for entry in &entries {
+        xml.push_str(&entry_response(entry, wanted.as_deref()));
+    }
+    xml.push_str("</D:multistatus>\n");
+    Ok(multistatus(xml))
+}
+
+/// A `<response>` with the entry's live properties, or just the ones asked
+/// for. Those it lacks are reported as 404, as RFC 4918 requires.
+fn entry_response(entry: &Entry, wanted: Option<&[(String, String)]>) -> String {
+    let mut found = String::new();
+    let mut missing = String::new();
+    let names: Vec<(String, String)> = match wanted {
+        Some(names) => names.to_vec(),
+        None => [
+            "displayname",
+            "resourcetype",
+            "getcontentlength",
+            "getlastmodified",
+            "getcontenttype",
+            "getetag",
+            "supportedlock",
+        ]
+        .iter()
+        .map(|name| ("DAV:".to_string(), name.to_string()))
+        .collect(),
+    };
+    for (namespace, name) in &names {
+        match live_property(entry, namespace, name) {
+            Some(value) => found.push_str(&value),
+            None if wanted.is_some() => missing.push_str(&empty_property(namespace, name)),
+            None => {}
+        }
+    }
+
+    let mut xml = format!("<D:response><D:href>{}</D:href>", escape_xml(&entry.href));
+    if !found.is_empty() || missing.is_empty() {
+        xml.push_str(&propstat(&found, "200 OK"));
+    }
+    if !missing.is_empty() {
+        xml.push_str(&propstat(&missing, "404 Not Found"));
+    }
+    xml.push_str("</D:response>\n");
+    xml
+}
+
+fn live_property(entry: &Entry, namespace: &str, name: &str) -> Option<String> {
+    if namespace != "DAV:" {
+        return None;
+    }
+    let value = match name {
+        "displayname" => escape_xml(&entry.name),
+        "resourcetype" if entry.collection => "<D:collection/>".to_string(),
+        "resourcetype" => String::new(),
+        "getcontentlength" => entry.size?.to_string(),
+        "getlastmodified" => http_date(&entry.modified?),
+        "getcontenttype" => escape_xml(entry.content_type.as_deref()?),
+        "getetag" => escape_xml(&quoted_etag(entry.etag.as_deref()?)),
+        "supportedlock" => "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+                            <D:locktype><D:write/></D:locktype></D:lockentry>"
+            .to_string(),
+        "lockdiscovery" => String::new(),
+        _ => return None,
+    };
+    Some(format!("<D:{name}>{value}</D:{name}>"))
+}
+
+fn empty_property(namespace: &str, name: &str) -> String {
+    if namespace == "DAV:" {
+        format!("<D:{}/>", name)
+    } else {
+        format!("<{} xmlns=\"{}\"/>", name, escape_xml(namespace))
+    }
+}
+
+fn propstat(props: &str, status: &str) -> String {
+    format!("<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>", props, status)
+}
+
+/// Property writes are refused, since objects carry no dead properties.
+/// Windows sets timestamps this way and carries on when refused.
+fn proppatch(state: &AppState, path: &DavPath, body: &[u8]) -> AppResult<Response> {
+    let Some(resource) = resolve(state, path)? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let props: String = named_props(body)
+        .unwrap_or_default()
+        .iter()
+        .map(|(namespace, name)| empty_property(namespace, name))
+        .collect();
+    let href = href(path.bucket.as_deref(), &path.key, resource.is_collection());
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n\
+         <D:response><D:href>{}</D:href>{}</D:response>\n</D:multistatus>\n",
+        escape_xml(&href),
+        propstat(&props, "403 Forbidden")
+    );
+    Ok(multistatus(xml))
+}
+
+/// Properties named inside the `<prop>` elements of a PROPFIND or PROPPATCH
+/// body, as (namespace, name). `None` for an empty body, `<allprop/>`, or
+/// `<propname/>`, which are all answered with every property.
+fn named_props(body: &[u8]) -> Option<Vec<(String, String)>> {
+    let text = std::str::from_utf8(body).ok()?;
+    let mut namespaces: HashMap<&str, &str> = HashMap::new();
+    let mut props = Vec::new();
+    let mut in_prop = false;
+    let mut saw_prop = false;
+    // Elements open inside the property being read, e.g. a PROPPATCH value
+    let mut nesting = 0usize;
+
+    for chunk in text.split('<').skip(1) {
+        let tag = &chunk[..chunk.find('>')?];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_start_matches('/').trim_end_matches('/');
+        let mut parts = tag.split_whitespace();
+        let name = parts.next()?;
+        for attribute in parts {
+            let Some((attr, value)) = attribute.split_once('=') else {
+                continue;
+            };
+            let value = value.trim_matches(|c| c == '"' || c == '\'');
+            if attr == "xmlns" {
+                namespaces.insert("", value);
+            } else if let Some(prefix) = attr.strip_prefix("xmlns:") {
+                namespaces.insert(prefix, value);
+            }
+        }
+        let (prefix, local) = name.split_once(':').unwrap_or(("", name));
+
+        if local == "prop" && nesting == 0 {
+            in_prop = !closing && !self_closing;
+            saw_prop |= in_prop;
+            continue;
+        }
+        if !in_prop {
+            continue;
+        }
+        if closing {
+            nesting = nesting.saturating_sub(1);
+            continue;
+        }
+        if nesting == 0 {
+            let namespace = namespaces.get(prefix).copied().unwrap_or_default();
+            props.push((namespace.to_string(), local.to_string()));
+        }
+        if !self_closing {
+            nesting += 1;
+        }
+    }
+    saw_prop.then_some(props)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn multistatus(xml: String) -> Response {
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        xml,
+    )
+        .into_response()
+}
+
+// ─── Content ──────────────────────────────────────────────────────
+
+fn get(state: &AppState, path: &DavPath, head: bool) -> AppResult<Response> {
+    let Some(bucket) = &path.bucket else {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    };
+    let meta = match resolve(state, path)? {
+        Some(Resource::File(meta)) => meta,
+        Some(_) => return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response()),
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+    let headers = [
+        (header::CONTENT_TYPE, meta.content_type.clone()),
+        (header::ETAG, quoted_etag(&meta.etag)),
+        (header::LAST_MODIFIED, http_date(&meta.modified())),
+        (header::CONTENT_LENGTH, meta.size.to_string()),
+    ];
+    if head {
+        return Ok((headers, ()).into_response());
+    }
+    let (_, data) = state.storage.get_object(bucket, &path.key)?;
+    state.storage.record_access(bucket, &path.key);
+    Ok((headers, data).into_response())
+}
+
+fn put(state: &AppState, path: &DavPath, headers: &HeaderMap, body: &[u8]) -> AppResult<Response> {
+    let Some(bucket) = path.bucket.as_deref().filter(|_| !path.key.is_empty()) else {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    };
+    let existed = match resolve(state, path)? {
+        Some(Resource::File(_)) => true,
+        Some(_) => return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response()),
+        None => false,
+    };
+    if !existed && !parent_exists(state, path)? {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    // Clients send octet-stream for everything; let the key decide instead
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.eq_ignore_ascii_case("application/octet-stream"));
+    state
+        .storage
+        .put_object(bucket, &path.key, body, PutOptions { content_type, ..Default::default() })?;
+    Ok(created_or_replaced(existed))
+}
+
+fn created_or_replaced(existed: bool) -> Response {
+    if existed {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::CREATED.into_response()
+    }
+}
+
+// ─── Namespace ────────────────────────────────────────────────────
+
+/// Remove a file or a whole folder. Buckets stay: dragging one to the
+/// trash shouldn't be able to wipe it.
+fn delete(state: &AppState, path: &DavPath) -> AppResult<Response> {
+    let (Some(resource), Some(bucket)) = (resolve(state, path)?, &path.bucket) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    match resource {
+        Resource::Root | Resource::Bucket { .. } => return Ok(StatusCode::FORBIDDEN.into_response()),
+        Resource::File(_) => state.storage.delete_object(bucket, &path.key)?,
+        Resource::Folder => {
+            let result = state.storage.delete_prefix(bucket, &format!("{}/", path.key))?;
+            if !result.errors.is_empty() {
+                let mut xml =
+                    String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+                for error in &result.errors {
+                    let status = if error.code == "OperationAborted" {
+                        "409 Conflict"
+                    } else {
+                        "500 Internal Server Error"
+                    };
+                    xml.push_str(&format!(
+                        "<D:response><D:href>{}</D:href><D:status>HTTP/1.1 {}</D:status>\
+                         <D:responsedescription>{}</D:responsedescription></D:response>\n",
+                        escape_xml(&href(Some(bucket), &error.key, false)),
+                        status,
+                        escape_xml(&error.message)
+                    ));
+                }
+                xml.push_str("</D:multistatus>\n");
+                return Ok(multistatus(xml));
+            }
+        }
+    }
+    keep_folder(state, bucket, path.parent_key())?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Create a folder, or a bucket at the top level
+fn mkcol(state: &AppState, path: &DavPath, body: &[u8]) -> AppResult<Response> {
+    if !body.is_empty() {
+        return Ok(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
+    }
+    let Some(bucket) = &path.bucket else {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    };
+    if path.key.is_empty() {
+        if state.storage.get_bucket(bucket).is_ok() {
+            return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+        }
+        let created = state.storage.create_bucket(bucket, &state.config().default_region);
+        audited(state, AuditAction::BucketCreate, bucket, created)?;
+        return Ok(StatusCode::CREATED.into_response());
+    }
+    if resolve(state, path)?.is_some() {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+    if !parent_exists(state, path)? {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    let marker = format!("{}/{}", path.key, FOLDER_MARKER);
+    state
+        .storage
+        .put_object(bucket, &marker, b"", PutOptions::default())?;
+    Ok(StatusCode::CREATED.into_response())
+}
+
+/// The path of a `Destination` header, which is usually a full URL
+fn destination(headers: &HeaderMap) -> AppResult<DavPath> {
+    let value = headers
+        .get("destination")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| AppError::InvalidRequest("COPY and MOVE need a Destination header".to_string()))?;
+    let path = match value.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+        None => value,
+    };
+    if !path.starts_with("/dav/") {
+        return Err(AppError::InvalidRequest(format!(
+            "Destination {} is outside /dav/",
+            value
+        )));
+    }
+    DavPath::parse(path)
+}
+
+/// COPY or MOVE a file or folder, possibly into another bucket. A folder
+/// goes the way of a bulk transfer of its prefix.
+fn transfer(state: &AppState, path: &DavPath, headers: &HeaderMap, operation: BulkOperation) -> AppResult<Response> {
+    let target = destination(headers)?;
+    let source = match resolve(state, path)? {
+        Some(resource @ (Resource::File(_) | Resource::Folder)) => resource,
+        Some(_) => return Ok(StatusCode::FORBIDDEN.into_response()),
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+    let (Some(src_bucket), Some(dst_bucket)) = (&path.bucket, &target.bucket) else {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    };
+    if target.key.is_empty() || (src_bucket == dst_bucket && path.key == target.key) {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    if !parent_exists(state, &target)? {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+
+    let existing = resolve(state, &target)?;
+    let existed = existing.is_some();
+    if let Some(existing) = existing {
+        let overwrite = headers.get("overwrite").and_then(|v| v.to_str().ok()) != Some("F");
+        if !overwrite {
+            return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+        }
+        match existing {
+            Resource::File(_) => state.storage.delete_object(dst_bucket, &target.key)?,
+            _ => {
+                state.storage.delete_prefix(dst_bucket, &format!("{}/", target.key))?;
+            }
+        }
+    }
+
+    match source {
+        Resource::File(_) => {
+            state
+                .storage
+                .copy_object(src_bucket, &path.key, dst_bucket, &target.key)?;
+            if operation == BulkOperation::Move {
+                state.storage.delete_object(src_bucket, &path.key)?;
+            }
+        }
+        _ => {
+            let req = BulkRequest {
+                operation,
+                source_bucket: src_bucket.clone(),
+                source_prefix: format!("{}/", path.key),
+                destination_bucket: dst_bucket.clone(),
+                destination_prefix: format!("{}/", target.key),
+                keep_source_prefix: false,
+            };
+            let plan = state.storage.plan_bulk_transfer(&req)?;
+            let mut failed = None;
+            for (src_key, dst_key) in &plan {
+                if let Err(e) = state.storage.bulk_transfer_object(&req, src_key, dst_key) {
+                    failed.get_or_insert(e);
+                }
+            }
+            state.storage.finish_bulk_transfer(&req)?;
+            if let Some(e) = failed {
+                return Err(e);
+            }
+        }
+    }
+    if operation == BulkOperation::Move {
+        keep_folder(state, src_bucket, path.parent_key())?;
+    }
+    Ok(created_or_replaced(existed))
+}
+
+// ─── Locking ──────────────────────────────────────────────────────
+
+/// Grant every lock without enforcing it. Finder only mounts a share
+/// writable when it can lock, and Office locks before saving; FreeBucket has
+/// no authentication, so a lock could never keep anyone out anyway.
+fn lock(state: &AppState, uri: &Uri) -> Response {
+    let token = format!("opaquelocktoken:{}", state.storage.new_id());
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+         <D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+         <D:depth>infinity</D:depth><D:timeout>Second-3600</D:timeout>\
+         <D:locktoken><D:href>{token}</D:href></D:locktoken>\
+         <D:lockroot><D:href>{root}</D:href></D:lockroot>\
+         </D:activelock></D:lockdiscovery></D:prop>\n",
+        root = escape_xml(uri.path()),
+    );
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/xml; charset=utf-8".to_string()),
+            (header::HeaderName::from_static("lock-token"), format!("<{}>", token)),
+        ],
+        xml,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+    use crate::testing::{body_string, TestServer};
+
+    fn server() -> TestServer {
+        let server = TestServer::with_config(|c| c.webdav = true);
+        server.state.storage.create_bucket("docs", "local").unwrap();
+        server
+    }
+
+    fn dav_request(method: &str, uri: &str, headers: &[(&str, &str)], body: &'static str) -> Request {
+        let mut req = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(Body::from(body)).unwrap()
+    }
+
+    async fn send(server: &TestServer, method: &str, uri: &str, headers: &[(&str, &str)], body: &'static str) -> Response {
+        server.send(dav_request(method, uri, headers, body)).await
+    }
+
+    /// The hrefs of a multistatus answer, in order
+    fn hrefs(xml: &str) -> Vec<&str> {
+        xml.split("<D:href>")
+            .skip(1)
+            .map(|rest| &rest[..rest.find("</D:href>").unwrap()])
+            .collect()
+    }
+
+    fn keys(server: &TestServer, bucket: &str) -> Vec<String> {
+        let listing = server.state.storage.list_objects(bucket, "", None, 1000).unwrap();
+        listing.objects.into_iter().map(|o| o.key).collect()
+    }
+
+    #[tokio::test]
+    async fn options_advertise_the_dav_classes() {
+        let server = server();
+        for uri in ["/", "/dav/", "/dav/docs/a.txt"] {
+            let res = send(&server, "OPTIONS", uri, &[], "").await;
+            assert_eq!(res.headers()["dav"], "1, 2", "{}", uri);
+            assert_eq!(res.headers()[header::ALLOW], ALLOW);
+        }
+        let res = send(&server, "OPTIONS", "/api/buckets", &[], "").await;
+        assert!(res.headers().get("dav").is_none());
+    }
+
+    #[tokio::test]
+    async fn propfind_lists_one_level_and_hides_folder_markers() {
+        let server = server();
+        for (key, data) in [("report.pdf", &b"%PDF"[..]), ("photos/cat.jpg", b"jpeg"), ("empty/.keep", b"")] {
+            server
+                .state
+                .storage
+                .put_object("docs", key, data, PutOptions::default())
+                .unwrap();
+        }
+
+        let res = send(&server, "PROPFIND", "/dav/docs/", &[("depth", "1")], "").await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let xml = body_string(res).await;
+        assert_eq!(hrefs(&xml), ["/dav/docs/", "/dav/docs/empty/", "/dav/docs/photos/", "/dav/docs/report.pdf"]);
+        assert!(xml.contains("<D:getcontentlength>4</D:getcontentlength>"), "{}", xml);
+        assert!(xml.contains("<D:getcontenttype>application/pdf</D:getcontenttype>"), "{}", xml);
+        assert!(xml.contains("<D:resourcetype><D:collection/></D:resourcetype>"), "{}", xml);
+
+        let res = send(&server, "PROPFIND", "/dav/docs/empty/", &[("depth", "1")], "").await;
+        assert_eq!(hrefs(&body_string(res).await), ["/dav/docs/empty/"]);
+
+        // Depth 0 with named properties: the ones a file lacks are 404
+        let props = r#"<?xml version="1.0"?><D:propfind xmlns:D="DAV:" xmlns:Z="urn:example"><D:prop>
+            <D:displayname/><D:getlastmodified/><Z:colour/></D:prop></D:propfind>"#;
+        let res = send(&server, "PROPFIND", "/dav/docs/photos/cat.jpg", &[("depth", "0")], props).await;
+        let xml = body_string(res).await;
+        assert_eq!(hrefs(&xml), ["/dav/docs/photos/cat.jpg"]);
+        assert!(xml.contains("<D:displayname>cat.jpg</D:displayname><D:getlastmodified>"), "{}", xml);
+        assert!(xml.contains("<colour xmlns=\"urn:example\"/></D:prop><D:status>HTTP/1.1 404 Not Found"), "{}", xml);
+        assert!(!xml.contains("getcontentlength"), "{}", xml);
+
+        let res = send(&server, "PROPFIND", "/dav/", &[("depth", "1")], "").await;
+        assert_eq!(hrefs(&body_string(res).await), ["/dav/", "/dav/docs/"]);
+        let res = send(&server, "PROPFIND", "/dav/docs/missing.txt", &[("depth", "0")], "").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn put_get_and_delete_files() {
+        let server = server();
+        let res = send(&server, "MKCOL", "/dav/docs/notes", &[], "").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let res = send(&server, "PUT", "/dav/docs/notes/todo.md", &[("content-type", "application/octet-stream")], "# todo").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = send(&server, "PUT", "/dav/docs/notes/todo.md", &[], "# done").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = send(&server, "PUT", "/dav/docs/missing/todo.md", &[], "x").await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let res = send(&server, "GET", "/dav/docs/notes/todo.md", &[], "").await;
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/markdown");
+        assert_eq!(body_string(res).await, "# done");
+        let res = send(&server, "HEAD", "/dav/docs/notes/todo.md", &[], "").await;
+        assert_eq!(res.headers()[header::CONTENT_LENGTH], "6");
+        let res = send(&server, "GET", "/dav/docs/notes/", &[], "").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        // The folder outlives its last file
+        let res = send(&server, "DELETE", "/dav/docs/notes/todo.md", &[], "").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(keys(&server, "docs"), ["notes/.keep"]);
+        let res = send(&server, "DELETE", "/dav/docs/notes", &[], "").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(keys(&server, "docs").is_empty());
+
+        let res = send(&server, "DELETE", "/dav/docs/", &[], "").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(server.state.storage.get_bucket("docs").is_ok());
+    }
+
+    #[tokio::test]
+    async fn mkcol_makes_folders_and_buckets() {
+        let server = server();
+        assert_eq!(send(&server, "MKCOL", "/dav/docs/a", &[], "").await.status(), StatusCode::CREATED);
+        assert_eq!(send(&server, "MKCOL", "/dav/docs/a/b", &[], "").await.status(), StatusCode::CREATED);
+        assert_eq!(send(&server, "MKCOL", "/dav/docs/a", &[], "").await.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(send(&server, "MKCOL", "/dav/docs/x/y", &[], "").await.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            send(&server, "MKCOL", "/dav/docs/c", &[], "<body/>").await.status(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(keys(&server, "docs"), ["a/.keep", "a/b/.keep"]);
+
+        assert_eq!(send(&server, "MKCOL", "/dav/archive/", &[], "").await.status(), StatusCode::CREATED);
+        assert!(server.state.storage.get_bucket("archive").is_ok());
+    }
+
+    #[tokio::test]
+    async fn copy_and_move_files_and_folders() {
+        let server = server();
+        server.state.storage.create_bucket("archive", "local").unwrap();
+        for key in ["2024/jan.csv", "2024/feb.csv", "readme.txt"] {
+            server
+                .state
+                .storage
+                .put_object("docs", key, key.as_bytes(), PutOptions::default())
+                .unwrap();
+        }
+
+        let to = |path: &'static str| [("destination", path)];
+        let res = send(&server, "COPY", "/dav/docs/readme.txt", &to("http://localhost:3210/dav/docs/readme-2.txt"), "").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = send(&server, "COPY", "/dav/docs/readme.txt", &[("destination", "/dav/docs/readme-2.txt"), ("overwrite", "F")], "").await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let res = send(&server, "COPY", "/dav/docs/readme.txt", &to("/dav/docs/readme-2.txt"), "").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        let res = send(&server, "MOVE", "/dav/docs/2024", &to("/dav/archive/old"), "").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(keys(&server, "archive"), ["old/feb.csv", "old/jan.csv"]);
+        assert_eq!(keys(&server, "docs"), ["readme-2.txt", "readme.txt"]);
+        let (_, data) = server.state.storage.get_object("archive", "old/jan.csv").unwrap();
+        assert_eq!(data, b"2024/jan.csv");
+
+        let res = send(&server, "MOVE", "/dav/docs/readme.txt", &to("/elsewhere/readme.txt"), "").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = send(&server, "MOVE", "/dav/docs/readme.txt", &to("/dav/docs/none/readme.txt"), "").await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let res = send(&server, "MOVE", "/dav/docs/gone.txt", &to("/dav/docs/b.txt"), "").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn locks_are_granted_and_properties_refused() {
+        let server = server();
+        let res = send(&server, "LOCK", "/dav/docs/a.txt", &[], "").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let token = res.headers()["lock-token"].to_str().unwrap().to_string();
+        assert!(token.starts_with("<opaquelocktoken:"), "{}", token);
+        assert!(body_string(res).await.contains("<D:lockroot><D:href>/dav/docs/a.txt</D:href>"));
+        assert_eq!(send(&server, "UNLOCK", "/dav/docs/a.txt", &[], "").await.status(), StatusCode::NO_CONTENT);
+
+        let patch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:schemas-microsoft-com:"><D:set><D:prop>
+            <Z:Win32LastModifiedTime>Wed, 01 Jan 2025 00:00:00 GMT</Z:Win32LastModifiedTime></D:prop></D:set></D:propertyupdate>"#;
+        let res = send(&server, "PROPPATCH", "/dav/docs/", &[], patch).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let xml = body_string(res).await;
+        assert!(xml.contains("<Win32LastModifiedTime xmlns=\"urn:schemas-microsoft-com:\"/>"), "{}", xml);
+        assert!(xml.contains("HTTP/1.1 403 Forbidden"), "{}", xml);
+
+        let res = send(&server, "SEARCH", "/dav/docs/", &[], "").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn paths_may_not_climb_out_of_a_bucket() {
+        assert!(DavPath::parse("/dav/docs/a/../../etc").is_err());
+        assert!(DavPath::parse("/dav/docs/a//b").is_err());
+        let path = DavPath::parse("/dav/docs/%E6%97%A5%E6%9C%AC/a%20b.txt").unwrap();
+        assert_eq!((path.bucket.as_deref(), path.key.as_str()), (Some("docs"), "日本/a b.txt"));
+        assert_eq!(href(Some("docs"), &path.key, false), "/dav/docs/%E6%97%A5%E6%9C%AC/a%20b.txt");
+    }
+}
@@ -0,0 +1,350 @@
+//! Delivery of object events to the webhooks configured on their bucket,
+//! either as FreeBucket's flat JSON or as S3 event notifications. Usage
+//! alerts have no S3 equivalent and always go out as FreeBucket's JSON.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response as HttpResponse;
+use freebucket::client::{self, ClientError, Response};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::Semaphore;
+
+use crate::models::{Webhook, WebhookFormat};
+use crate::storage::{ObjectEvent, EVENT_SOURCE};
+use crate::AppState;
+
+/// Deliveries in flight at once; events queue up behind them
+const MAX_IN_FLIGHT: usize = 16;
+/// Time allowed for one attempt, from connecting to reading the status
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest answer body `post_for_reply` reads
+const MAX_REPLY_BYTES: usize = 64 * 1024;
+/// Pauses before the second and third attempt
+const RETRY_DELAYS: [Duration; 2] = [Duration::from_secs(1), Duration::from_secs(5)];
+
+/// Principal ID given for the bucket owner and the requester, matching
+/// ListBuckets
+const OWNER: &str = "freebucket-local";
+
+/// Characters S3 leaves alone when it URL-encodes a key in an event
+const EVENT_KEY: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~')
+    .remove(b'*')
+    .remove(b'/')
+    .remove(b' ');
+
+/// Send every object event to its bucket's webhooks and exec hooks until
+/// the server stops. Delivery is best effort: events still queued at
+/// shutdown are lost.
+pub fn spawn_webhook_dispatcher(state: Arc<AppState>) {
+    let mut events = state.storage.subscribe_events();
+    let slots = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let Ok(bucket) = state.storage.get_bucket(&event.bucket) else {
+                continue;
+            };
+            crate::exec_hooks::enqueue(&state, &bucket, &event);
+            for webhook in bucket.webhooks {
+                let body = match webhook.format {
+                    WebhookFormat::S3Event if event.alert.is_none() => s3_event_payload(&event, &bucket.region),
+                    _ => native_payload(&event),
+                }
+                .to_string();
+                let Ok(slot) = slots.clone().acquire_owned().await else {
+                    return;
+                };
+                let name = event.kind.s3_name();
+                tokio::spawn(async move {
+                    deliver(&webhook, name, body).await;
+                    drop(slot);
+                });
+            }
+        }
+    });
+}
+
+/// Run a request with its client's address at hand, so the object events
+/// it causes carry it as their source
+pub async fn event_source(req: Request, next: Next) -> HttpResponse {
+    match crate::access_log::remote_ip(&req) {
+        Some(ip) => EVENT_SOURCE.scope(ip, next.run(req)).await,
+        None => next.run(req).await,
+    }
+}
+
+/// FreeBucket's own format: one flat object per event
+fn native_payload(event: &ObjectEvent) -> serde_json::Value {
+    let mut body = json!({
+        "event": event.kind.s3_name(),
+        "bucket": event.bucket,
+        "key": event.key,
+        "time": event.time.to_rfc3339(),
+        "sequencer": format!("{:016X}", event.sequence),
+    });
+    if let Some(alert) = &event.alert {
+        body["metric"] = json!(alert.metric);
+        body["value"] = json!(alert.value);
+        body["threshold"] = json!(alert.threshold);
+        body["raised_at"] = json!(alert.raised_at.to_rfc3339());
+    }
+    if let Some(meta) = &event.object {
+        body["size"] = json!(meta.size);
+        body["etag"] = json!(meta.etag);
+        body["content_type"] = json!(meta.content_type);
+    }
+    body
+}
+
+/// A notification with the layout of S3's event records (version 2.1), so
+/// consumers of S3 events can read it unchanged
+fn s3_event_payload(event: &ObjectEvent, region: &str) -> serde_json::Value {
+    // S3 form-encodes keys: spaces become `+`, slashes stay
+    let key = utf8_percent_encode(&event.key, EVENT_KEY).to_string().replace(' ', "+");
+    let mut object = json!({
+        "key": key,
+        "sequencer": format!("{:016X}", event.sequence),
+    });
+    if let Some(meta) = &event.object {
+        object["size"] = json!(meta.size);
+        object["eTag"] = json!(meta.etag);
+    }
+    json!({
+        "Records": [{
+            "eventVersion": "2.1",
+            "eventSource": "aws:s3",
+            "awsRegion": region,
+            "eventTime": event.time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            "eventName": event.kind.s3_name(),
+            "userIdentity": { "principalId": OWNER },
+            // Changes the server makes on its own come from itself
+            "requestParameters": { "sourceIPAddress": event.source_ip.as_deref().unwrap_or("127.0.0.1") },
+            "responseElements": {
+                "x-amz-request-id": format!("{:016X}", event.sequence),
+                "x-amz-id-2": format!("{:016X}", event.sequence),
+            },
+            "s3": {
+                "s3SchemaVersion": "1.0",
+                "configurationId": "freebucket-webhook",
+                "bucket": {
+                    "name": event.bucket,
+                    "ownerIdentity": { "principalId": OWNER },
+                    "arn": format!("arn:aws:s3:::{}", event.bucket),
+                },
+                "object": object,
+            },
+        }]
+    })
+}
+
+/// POST one event, retrying twice on errors and non-2xx answers
+async fn deliver(webhook: &Webhook, event_name: &str, body: String) {
+    let signature = webhook
+        .secret
+        .as_ref()
+        .map(|secret| format!("sha256={}", hex::encode(hmac_sha256(secret.as_bytes(), body.as_bytes()))));
+    let mut delays = RETRY_DELAYS.iter();
+    loop {
+        let attempt = post(&webhook.url, event_name, signature.as_deref(), &body);
+        let error = match tokio::time::timeout(ATTEMPT_TIMEOUT, attempt).await {
+            Ok(Ok(status)) if (200..300).contains(&status) => return,
+            Ok(Ok(status)) => format!("answered {}", status),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("no answer within {}s", ATTEMPT_TIMEOUT.as_secs()),
+        };
+        match delays.next() {
+            Some(delay) => tokio::time::sleep(*delay).await,
+            None => {
+                tracing::warn!("Dropping {} event for webhook {}: {}", event_name, webhook.url, error);
+                return;
+            }
+        }
+    }
+}
+
+/// POST a webhook's JSON body. Returns the response status.
+async fn post(url: &str, event_name: &str, signature: Option<&str>, body: &str) -> Result<u16, ClientError> {
+    let mut headers = vec![("X-FreeBucket-Event", event_name)];
+    if let Some(signature) = signature {
+        headers.push(("X-FreeBucket-Signature", signature));
+    }
+    // Only the status matters
+    match send_post(url, &headers, body).await {
+        Ok(response) => Ok(response.status),
+        Err(ClientError::TooLarge { status, .. }) => Ok(status),
+        Err(e) => Err(e),
+    }
+}
+
+/// POST a JSON body and read the whole answer, for callers that need the
+/// reply. Returns the status and the body.
+pub async fn post_for_reply(url: &str, headers: &[(&str, &str)], body: &str) -> Result<(u16, String), ClientError> {
+    let response = send_post(url, headers, body).await?;
+    Ok((response.status, response.text()))
+}
+
+async fn send_post(url: &str, headers: &[(&str, &str)], body: &str) -> Result<Response, ClientError> {
+    let mut all = vec![
+        ("User-Agent".to_string(), format!("FreeBucket/{}", env!("CARGO_PKG_VERSION"))),
+        ("Content-Type".to_string(), "application/json".to_string()),
+    ];
+    all.extend(headers.iter().map(|(name, value)| (name.to_string(), value.to_string())));
+    client::send("POST", url, &all, body.as_bytes(), MAX_REPLY_BYTES).await
+}
+
+/// HMAC-SHA256 (RFC 2104)
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|k| k ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(message).finalize();
+    Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::storage::PutOptions;
+    use crate::testing::TestServer;
+
+    /// The `ObjectCreated:Put` record of S3's event message structure
+    /// documentation
+    const AWS_PUT_RECORD: &str = r#"{"Records":[{"eventVersion":"2.1","eventSource":"aws:s3",
+        "awsRegion":"us-west-2","eventTime":"1970-01-01T00:00:00.000Z","eventName":"ObjectCreated:Put",
+        "userIdentity":{"principalId":"AIDAJDPLRKLG7UEXAMPLE"},"requestParameters":{"sourceIPAddress":"172.16.0.1"},
+        "responseElements":{"x-amz-request-id":"C3D13FE58DE4C810",
+        "x-amz-id-2":"FMyUVURIY8/IgAtTv8xRjskZQpcIZ9KG4V5Wp6S7S/JRWeUWerMUE5JgHvANOjpD"},
+        "s3":{"s3SchemaVersion":"1.0","configurationId":"testConfigRule",
+        "bucket":{"name":"amzn-s3-demo-bucket","ownerIdentity":{"principalId":"A3NL1KOZZKExample"},
+        "arn":"arn:aws:s3:::amzn-s3-demo-bucket"},
+        "object":{"key":"HappyFace.jpg","size":1024,"eTag":"d41d8cd98f00b204e9800998ecf8427e",
+        "sequencer":"0055AED6DCD90281E5"}}}]}"#;
+
+    /// The same record for `ObjectRemoved:Delete`, which has no size or ETag
+    const AWS_DELETE_OBJECT: &str = r#"{"key":"HappyFace.jpg","sequencer":"0055AED6DCD90281E5"}"#;
+
+    /// The field names of a JSON document and the types of their values
+    fn shape(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), shape(v))).collect()),
+            Value::Array(items) => Value::Array(items.iter().map(shape).collect()),
+            Value::String(_) => json!("string"),
+            Value::Number(_) => json!("number"),
+            other => other.clone(),
+        }
+    }
+
+    /// The events a request to `server` causes
+    async fn events_of(server: &TestServer, req: axum::extract::Request) -> Vec<ObjectEvent> {
+        let mut events = server.state.storage.subscribe_events();
+        server.send(req).await;
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn s3_events_are_laid_out_like_aws_records() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("photos", "eu-west-1").unwrap();
+        let put = Request::put("/s3/photos/happy%20face+1.jpg").body(Body::from("smile")).unwrap();
+        let created = events_of(&server, put).await;
+        let delete = Request::delete("/s3/photos/happy%20face+1.jpg")
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+            .body(Body::empty())
+            .unwrap();
+        let removed = events_of(&server, delete).await;
+
+        let sample: Value = serde_json::from_str(AWS_PUT_RECORD).unwrap();
+        let payload = s3_event_payload(&created[0], "eu-west-1");
+        assert_eq!(shape(&payload), shape(&sample));
+        let record = &payload["Records"][0];
+        assert_eq!(record["eventName"], "ObjectCreated:Put");
+        assert_eq!(record["awsRegion"], "eu-west-1");
+        assert_eq!(record["requestParameters"]["sourceIPAddress"], "127.0.0.1");
+        assert_eq!(record["s3"]["object"]["key"], "happy+face%2B1.jpg");
+        assert_eq!(record["s3"]["object"]["size"], 5);
+        assert!(record["eventTime"].as_str().unwrap().ends_with('Z'));
+
+        let payload = s3_event_payload(&removed[0], "eu-west-1");
+        let record = &payload["Records"][0];
+        assert_eq!(record["eventName"], "ObjectRemoved:Delete");
+        assert_eq!(record["requestParameters"]["sourceIPAddress"], "203.0.113.7");
+        let sample: Value = serde_json::from_str(AWS_DELETE_OBJECT).unwrap();
+        assert_eq!(shape(&record["s3"]["object"]), shape(&sample));
+    }
+
+    #[tokio::test]
+    async fn server_made_changes_have_no_source() {
+        let (_dir, storage) = crate::storage::testing::engine();
+        storage.create_bucket("photos", "local").unwrap();
+        let mut events = storage.subscribe_events();
+        storage.put_object("photos", "a.txt", b"a", PutOptions::default()).unwrap();
+        assert_eq!(events.try_recv().unwrap().source_ip, None);
+        let scoped = EVENT_SOURCE.scope("198.51.100.2".to_string(), async {
+            storage.delete_object("photos", "a.txt").unwrap();
+        });
+        scoped.await;
+        assert_eq!(events.try_recv().unwrap().source_ip.as_deref(), Some("198.51.100.2"));
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_posts_and_accepts_https_urls() {
+        use axum::http::HeaderMap;
+
+        let seen = Arc::new(std::sync::Mutex::new(None));
+        let record = seen.clone();
+        let receiver = axum::Router::new().route(
+            "/events",
+            axum::routing::post(move |headers: HeaderMap, body: String| async move {
+                let signature = headers["x-freebucket-signature"].to_str().unwrap().to_string();
+                *record.lock().unwrap() = Some((signature, body));
+                "ok"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let webhook = Webhook {
+            url,
+            format: WebhookFormat::Freebucket,
+            secret: Some("s3cr3t".to_string()),
+        };
+        deliver(&webhook, "ObjectCreated:Put", "{\"key\":\"a\"}".to_string()).await;
+        let (signature, body) = seen.lock().unwrap().clone().unwrap();
+        assert_eq!(body, "{\"key\":\"a\"}");
+        assert_eq!(signature, format!("sha256={}", hex::encode(hmac_sha256(b"s3cr3t", body.as_bytes()))));
+
+        let (_dir, storage) = crate::storage::testing::engine();
+        storage.create_bucket("photos", "local").unwrap();
+        let secure = Webhook {
+            url: "https://hooks.example.com/events".to_string(),
+            ..webhook
+        };
+        assert!(storage.set_bucket_webhooks("photos", vec![secure.clone()]).is_ok());
+        let plain_ftp = Webhook {
+            url: "ftp://hooks.example.com/".to_string(),
+            ..secure
+        };
+        assert!(storage.set_bucket_webhooks("photos", vec![plain_ftp]).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! Static website hosting: `/website/<bucket>/<path>` serves a bucket's
+//! objects the way a browser expects a site to be served. Paths ending in
+//! `/` get the folder's `index.html`, objects stored with
+//! `x-amz-website-redirect-location` answer with a `301` to their target,
+//! and precompressed buckets pick a `.br`/`.gz` variant like object GETs do.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+use crate::error::AppError;
+use crate::handlers::{accepted_encodings, check_preconditions, object_body, object_headers, parse_range};
+use crate::AppState;
+
+/// Served for paths naming a folder
+const INDEX_DOCUMENT: &str = "index.html";
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/website/:bucket", get(site_root))
+        .route("/website/:bucket/", get(site_index))
+        .route("/website/:bucket/*path", get(site_page))
+}
+
+/// The site's root without its slash; relative links in the index only
+/// resolve below the bucket with it
+async fn site_root(State(state): State<Arc<AppState>>, Path(bucket): Path<String>) -> Result<Response, AppError> {
+    state.storage.get_bucket(&bucket)?;
+    Ok(moved_permanently(format!("/website/{}/", bucket)))
+}
+
+async fn site_index(
+    State(state): State<Arc<AppState>>,
+    Path(bucket): Path<String>,
+    req_headers: HeaderMap,
+) -> Result<Response, AppError> {
+    serve(&state, &bucket, "", &req_headers)
+}
+
+async fn site_page(
+    State(state): State<Arc<AppState>>,
+    Path((bucket, path)): Path<(String, String)>,
+    req_headers: HeaderMap,
+) -> Result<Response, AppError> {
+    serve(&state, &bucket, &path, &req_headers)
+}
+
+fn serve(state: &AppState, bucket: &str, path: &str, req_headers: &HeaderMap) -> Result<Response, AppError> {
+    let key = match path.is_empty() || path.ends_with('/') {
+        true => format!("{}{}", path, INDEX_DOCUMENT),
+        false => path.to_string(),
+    };
+    let precompressed = state.storage.get_bucket(bucket)?.precompressed;
+    // The redirect is on the plain object even when a variant would be
+    // served; a bucket may also hold only the variants
+    match state.storage.stat_object(bucket, &key) {
+        Ok(meta) => {
+            if let Some(location) = &meta.website_redirect_location {
+                return Ok(moved_permanently(redirect_target(bucket, location)));
+            }
+        }
+        Err(AppError::ObjectNotFound { .. }) if precompressed => {}
+        Err(e) => return Err(e),
+    }
+
+    let variant = match precompressed {
+        true => state
+            .storage
+            .get_precompressed_object(bucket, &key, &accepted_encodings(req_headers))?,
+        false => None,
+    };
+    let (meta, data, encoding) = match variant {
+        Some((meta, data, encoding)) => (meta, data, Some(encoding)),
+        None => {
+            let (meta, data) = state.storage.get_object(bucket, &key)?;
+            (meta, data, None)
+        }
+    };
+    if let Some(not_modified) = check_preconditions(&meta, req_headers)? {
+        return Ok(not_modified);
+    }
+    let range = req_headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| parse_range(v, data.len() as u64))
+        .transpose()?
+        .flatten();
+    state.storage.record_access(bucket, &key);
+
+    let mut headers = object_headers(&meta, &key, &HashMap::new(), req_headers);
+    if precompressed {
+        headers.insert("vary", "Accept-Encoding".parse().unwrap());
+    }
+    if let Some(encoding) = encoding {
+        headers.insert("content-encoding", encoding.token().parse().unwrap());
+    }
+    Ok(object_body(headers, data, range))
+}
+
+/// Where a stored redirect points: a key (`/docs/new.html`) is a page of
+/// the same site, a URL is followed as it is
+fn redirect_target(bucket: &str, location: &str) -> String {
+    match location.starts_with('/') {
+        true => format!("/website/{}{}", bucket, location),
+        false => location.to_string(),
+    }
+}
+
+fn moved_permanently(location: String) -> Response {
+    (StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, location)]).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::Request;
+
+    use super::*;
+    use crate::storage::PutOptions;
+    use crate::testing::{body_string, TestServer};
+
+    fn put(server: &TestServer, key: &str, data: &[u8], redirect: Option<&str>) {
+        server
+            .state
+            .storage
+            .put_object("site", key, data, PutOptions { website_redirect_location: redirect, ..Default::default() })
+            .unwrap();
+    }
+
+    fn location(res: &Response) -> &str {
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        res.headers()[header::LOCATION].to_str().unwrap()
+    }
+
+    async fn get_encoded(server: &TestServer, uri: &str, accept_encoding: &str) -> Response {
+        let req = Request::builder()
+            .uri(uri)
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .body(Body::empty())
+            .unwrap();
+        server.send(req).await
+    }
+
+    #[tokio::test]
+    async fn follows_stored_redirects_to_a_page_or_a_url() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("site", "local").unwrap();
+        put(&server, "old.html", b"", Some("/docs/"));
+        put(&server, "docs/index.html", b"<h1>Docs</h1>", None);
+        put(&server, "docs/moved.html", b"", Some("/old.html"));
+        put(&server, "blog.html", b"", Some("https://blog.example.com/"));
+
+        // moved.html -> old.html -> docs/ -> docs/index.html
+        let res = server.request("GET", "/website/site/docs/moved.html").await;
+        assert_eq!(location(&res), "/website/site/old.html");
+        let res = server.request("GET", "/website/site/old.html").await;
+        assert_eq!(location(&res), "/website/site/docs/");
+        let res = server.request("GET", "/website/site/docs/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_string(res).await, "<h1>Docs</h1>");
+
+        let res = server.request("GET", "/website/site/blog.html").await;
+        assert_eq!(location(&res), "https://blog.example.com/");
+        let res = server.request("HEAD", "/website/site/blog.html").await;
+        assert_eq!(location(&res), "https://blog.example.com/");
+
+        // The object API still hands out the object, with the target as a header
+        let res = server.request("GET", "/api/object/site/blog.html").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-amz-website-redirect-location"], "https://blog.example.com/");
+    }
+
+    #[tokio::test]
+    async fn serves_the_index_of_the_site_root() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("site", "local").unwrap();
+        put(&server, "index.html", b"<h1>Home</h1>", None);
+
+        let res = server.request("GET", "/website/site").await;
+        assert_eq!(location(&res), "/website/site/");
+        let res = server.request("GET", "/website/site/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/html");
+        assert_eq!(body_string(res).await, "<h1>Home</h1>");
+
+        let res = server.request("GET", "/website/site/missing.html").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = server.request("GET", "/website/nowhere/").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn picks_a_precompressed_variant_by_accept_encoding() {
+        let server = TestServer::new();
+        server.state.storage.create_bucket("site", "local").unwrap();
+        server.state.storage.set_bucket_precompressed("site", true).unwrap();
+        put(&server, "app.js", b"plain", None);
+        put(&server, "app.js.br", b"brotli", None);
+        put(&server, "app.js.gz", b"gzip", None);
+
+        let mut etags = Vec::new();
+        for (accept, encoding, body) in [
+            ("gzip, br", Some("br"), "brotli"),
+            ("gzip", Some("gzip"), "gzip"),
+            ("identity", None, "plain"),
+        ] {
+            let res = get_encoded(&server, "/website/site/app.js", accept).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let headers = res.headers().clone();
+            assert_eq!(headers.get(header::CONTENT_ENCODING).map(|v| v.to_str().unwrap()), encoding);
+            assert_eq!(headers[header::VARY], "Accept-Encoding");
+            assert_eq!(headers[header::CONTENT_TYPE], "text/javascript");
+            assert_eq!(body_string(res).await, body);
+            etags.push(headers[header::ETAG].clone());
+        }
+        etags.dedup();
+        assert_eq!(etags.len(), 3, "each encoding caches under its own ETag");
+
+        // A redirect on the plain object wins over its variants
+        put(&server, "app.js", b"", Some("/v2/app.js"));
+        let res = get_encoded(&server, "/website/site/app.js", "br").await;
+        assert_eq!(location(&res), "/website/site/v2/app.js");
+    }
+}
@@ -0,0 +1,224 @@
+//! S3 interoperability with rclone. `replays_rclone_requests` always runs:
+//! it sends the requests rclone's S3 backend makes, with the query
+//! parameters and headers it uses, and checks the answers against what
+//! rclone parses. With the `rclone-tests` feature, `rclone_round_trip`
+//! also drives a real `rclone` from the PATH against the server.
+
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use freebucket::client::{FreeBucketClient, Response};
+
+/// The `freebucket` binary serving a scratch data dir on a free port,
+/// stopped when dropped
+struct Server {
+    child: Child,
+    endpoint: String,
+    _dir: tempfile::TempDir,
+}
+
+impl Server {
+    async fn start() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let child = Command::new(env!("CARGO_BIN_EXE_freebucket"))
+            .env("FREEBUCKET_HOST", "127.0.0.1")
+            .env("FREEBUCKET_PORT", port.to_string())
+            .env("FREEBUCKET_DATA_DIR", dir.path())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Server {
+            child,
+            endpoint: format!("http://127.0.0.1:{}", port),
+            _dir: dir,
+        };
+        let client = server.client();
+        for _ in 0..100 {
+            if client.get("/api/server-info").await.is_ok_and(|res| res.status == 200) {
+                return server;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the server did not come up on {}", server.endpoint);
+    }
+
+    fn client(&self) -> FreeBucketClient {
+        FreeBucketClient::new(&self.endpoint).unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+async fn send(client: &FreeBucketClient, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Response {
+    let headers: Vec<(String, String)> =
+        headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    let res = client.request_with_headers(method, path, &headers, body).await.unwrap();
+    if method != "HEAD" && res.status >= 400 {
+        assert!(res.text().contains("<Error><Code>"), "{} {}: {}", method, path, res.text());
+    }
+    res
+}
+
+/// The text of the first `<tag>` in an XML body
+fn tag(body: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&format!("</{}>", tag))? + start;
+    Some(body[start..end].to_string())
+}
+
+#[tokio::test]
+async fn replays_rclone_requests() {
+    let server = Server::start().await;
+    let client = server.client();
+    let agent = ("User-Agent", "rclone/v1.68.2");
+
+    // mkdir
+    let res = send(&client, "PUT", "/s3/photos", &[agent], b"").await;
+    assert_eq!(res.status, 200);
+
+    // copy: a HEAD to see whether the file is there, then a PUT with the
+    // modification time in metadata
+    let object = "/s3/photos/notes/a%20b%2Bc.txt";
+    assert_eq!(send(&client, "HEAD", object, &[agent], b"").await.status, 404);
+    let put = [agent, ("Content-Type", "text/plain; charset=utf-8"), ("X-Amz-Meta-Mtime", "1700000000.5")];
+    let res = send(&client, "PUT", object, &put, b"hello").await;
+    assert_eq!(res.status, 200);
+    let etag = res.header("etag").unwrap().to_string();
+    assert_eq!(send(&client, "PUT", "/s3/photos/notes/z.txt", &put, b"zzz").await.status, 200);
+
+    // lsf: one directory level at a time, keys URL-encoded
+    let res = send(
+        &client,
+        "GET",
+        "/s3/photos?delimiter=%2F&encoding-type=url&list-type=2&max-keys=1000&prefix=",
+        &[agent],
+        b"",
+    )
+    .await;
+    let body = res.text();
+    assert_eq!(res.header("content-type"), Some("application/xml"));
+    assert!(body.contains("<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">"), "{}", body);
+    assert!(body.contains("<CommonPrefixes><Prefix>notes/</Prefix></CommonPrefixes>"), "{}", body);
+    assert_eq!(tag(&body, "KeyCount").as_deref(), Some("1"));
+    assert_eq!(tag(&body, "IsTruncated").as_deref(), Some("false"));
+
+    let res = send(
+        &client,
+        "GET",
+        "/s3/photos?delimiter=%2F&encoding-type=url&list-type=2&max-keys=1&prefix=notes%2F",
+        &[agent],
+        b"",
+    )
+    .await;
+    let body = res.text();
+    assert_eq!(tag(&body, "Key").as_deref(), Some("notes/a%20b%2Bc.txt"));
+    assert_eq!(tag(&body, "Size").as_deref(), Some("5"));
+    assert_eq!(tag(&body, "ETag").map(|e| e.replace("&quot;", "\"")), Some(etag.clone()));
+    assert_eq!(tag(&body, "IsTruncated").as_deref(), Some("true"));
+    let token = tag(&body, "NextContinuationToken").unwrap();
+    let res = send(
+        &client,
+        "GET",
+        &format!(
+            "/s3/photos?continuation-token={}&delimiter=%2F&encoding-type=url&list-type=2&max-keys=1&prefix=notes%2F",
+            token
+        ),
+        &[agent],
+        b"",
+    )
+    .await;
+    let body = res.text();
+    assert_eq!(tag(&body, "Key").as_deref(), Some("notes/z.txt"));
+    assert_eq!(tag(&body, "IsTruncated").as_deref(), Some("false"));
+
+    // With `list_version = 1`, pages resume from a marker
+    let res = send(&client, "GET", "/s3/photos?marker=notes%2Fa%20b%2Bc.txt&max-keys=1000&prefix=", &[agent], b"").await;
+    let body = res.text();
+    assert_eq!(tag(&body, "Key").as_deref(), Some("notes/z.txt"));
+    assert_eq!(tag(&body, "Marker").as_deref(), Some("notes/a b+c.txt"));
+
+    // copyto within the remote is a server-side copy
+    let copy = [agent, ("X-Amz-Copy-Source", "photos/notes/a%20b%2Bc.txt"), ("X-Amz-Metadata-Directive", "COPY")];
+    let res = send(&client, "PUT", "/s3/photos/copy.txt", &copy, b"").await;
+    assert_eq!(res.status, 200);
+    let body = res.text();
+    assert!(body.contains("<CopyObjectResult"), "{}", body);
+    assert_eq!(tag(&body, "ETag").map(|e| e.replace("&quot;", "\"")), Some(etag.clone()));
+    let res = send(&client, "HEAD", "/s3/photos/copy.txt", &[agent], b"").await;
+    assert_eq!(res.header("x-amz-meta-mtime"), Some("1700000000.5"));
+
+    // touch sets the modification time by copying the object onto itself
+    let touch = [
+        agent,
+        ("X-Amz-Copy-Source", "photos/copy.txt"),
+        ("X-Amz-Metadata-Directive", "REPLACE"),
+        ("Content-Type", "text/plain; charset=utf-8"),
+        ("X-Amz-Meta-Mtime", "1800000000"),
+    ];
+    assert_eq!(send(&client, "PUT", "/s3/photos/copy.txt", &touch, b"").await.status, 200);
+    let res = send(&client, "HEAD", "/s3/photos/copy.txt", &[agent], b"").await;
+    assert_eq!(res.header("x-amz-meta-mtime"), Some("1800000000"));
+    assert_eq!(res.header("content-length"), Some("5"));
+
+    // A missing object is an S3 error rclone can tell apart
+    let res = send(&client, "GET", "/s3/photos/missing.txt", &[agent], b"").await;
+    assert_eq!(res.status, 404);
+    assert_eq!(tag(&res.text(), "Code").as_deref(), Some("NoSuchKey"));
+    assert_eq!(tag(&res.text(), "Resource").as_deref(), Some("/photos/missing.txt"));
+
+    // purge
+    for key in ["notes/a%20b%2Bc.txt", "notes/z.txt", "copy.txt"] {
+        let res = send(&client, "DELETE", &format!("/s3/photos/{}", key), &[agent], b"").await;
+        assert_eq!(res.status, 204);
+    }
+    assert_eq!(send(&client, "DELETE", "/s3/photos", &[agent], b"").await.status, 204);
+}
+
+#[cfg(feature = "rclone-tests")]
+#[tokio::test]
+async fn rclone_round_trip() {
+    if Command::new("rclone").arg("version").output().is_err() {
+        eprintln!("rclone is not on the PATH; only the replayed requests were checked");
+        return;
+    }
+    let server = Server::start().await;
+    let local = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(local.path().join("sub")).unwrap();
+    std::fs::write(local.path().join("a b+c.txt"), b"hello").unwrap();
+    std::fs::write(local.path().join("sub/deep.txt"), vec![7u8; 100_000]).unwrap();
+    let local = local.path().to_str().unwrap().to_string();
+
+    let rclone = |args: &[&str]| {
+        let output = Command::new("rclone")
+            .args(args)
+            .env("RCLONE_CONFIG_FB_TYPE", "s3")
+            .env("RCLONE_CONFIG_FB_PROVIDER", "Other")
+            .env("RCLONE_CONFIG_FB_ENDPOINT", format!("{}/s3", server.endpoint))
+            .env("RCLONE_CONFIG_FB_FORCE_PATH_STYLE", "true")
+            .env("RCLONE_CONFIG_FB_ACCESS_KEY_ID", "freebucket")
+            .env("RCLONE_CONFIG_FB_SECRET_ACCESS_KEY", "freebucket")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "rclone {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    rclone(&["mkdir", "fb:photos"]);
+    rclone(&["copy", &local, "fb:photos/dir"]);
+    rclone(&["check", &local, "fb:photos/dir"]);
+    let listing = rclone(&["lsf", "-R", "fb:photos"]);
+    assert_eq!(listing, "dir/\ndir/a b+c.txt\ndir/sub/\ndir/sub/deep.txt\n");
+    rclone(&["copyto", "fb:photos/dir/a b+c.txt", "fb:photos/copy.txt"]);
+    rclone(&["touch", "-t", "2030-01-01T00:00:00", "fb:photos/copy.txt"]);
+    assert!(rclone(&["lsl", "fb:photos/copy.txt"]).contains("2030-01-01"));
+    rclone(&["purge", "fb:photos"]);
+    assert!(!rclone(&["lsd", "fb:"]).contains("photos"));
+}